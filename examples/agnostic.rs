@@ -0,0 +1,18 @@
+use xdp_screencast::screencast::ScreenCast;
+
+fn main() {
+    futures_lite::future::block_on(async {
+        let mut screencast = ScreenCast::default();
+        match screencast.screencast().await {
+            Ok(session) => {
+                println!(
+                    "{:?} {:?}",
+                    session.pipewire_fd(),
+                    session.selected_sources()
+                );
+                let _ = session.close().await;
+            }
+            Err(err) => println!("{:?}", err),
+        }
+    });
+}
@@ -0,0 +1,16 @@
+use xdp_screencast::screencast::ScreenCast;
+
+fn main() {
+    let mut screencast = ScreenCast::default();
+    match screencast.screencast_blocking() {
+        Ok(session) => {
+            println!(
+                "{:?} {:?}",
+                session.pipewire_fd(),
+                session.selected_sources()
+            );
+            let _ = session.close_blocking();
+        }
+        Err(err) => println!("{:?}", err),
+    }
+}
@@ -2,7 +2,13 @@ use xdp_screencast::screencast::ScreenCast;
 
 #[tokio::main(flavor = "current_thread")]
 async fn main() {
-  let mut screencast = ScreenCast::default();
-  let result = screencast.screencast().await;
-  println!("{:?} {:?}", result, screencast.get_selected_sources());
-}
\ No newline at end of file
+    let mut screencast = ScreenCast::default();
+    match screencast.screencast().await {
+        Ok(session) => println!(
+            "{:?} {:?}",
+            session.pipewire_fd(),
+            session.selected_sources()
+        ),
+        Err(err) => println!("{:?}", err),
+    }
+}
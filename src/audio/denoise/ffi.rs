@@ -0,0 +1,24 @@
+#![allow(non_camel_case_types, dead_code)]
+
+use std::os::raw::c_int;
+
+#[repr(C)]
+pub struct DenoiseState {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct RNNModel {
+    _private: [u8; 0],
+}
+
+#[link(name = "rnnoise")]
+unsafe extern "C" {
+    pub fn rnnoise_get_frame_size() -> c_int;
+    pub fn rnnoise_create(model: *mut RNNModel) -> *mut DenoiseState;
+    pub fn rnnoise_destroy(state: *mut DenoiseState);
+    /// Denoises a frame of samples at 48 kHz in the range of 16 bit
+    /// integers, returns the probability of voice in it.
+    pub fn rnnoise_process_frame(state: *mut DenoiseState, out: *mut f32, input: *const f32)
+    -> f32;
+}
@@ -0,0 +1,136 @@
+mod ffi;
+
+use super::{AudioBuffer, AudioSource, DENOISE_RATE};
+use crate::error::{Result, ScreencastError};
+use std::time::Duration;
+
+/// Noise suppression with RNNoise, linked from the system, for speech
+/// recorded with the microphone of a laptop.
+///
+/// Each channel is denoised on its own, in frames of 10 ms. The samples
+/// of a frame that is not complete yet are held back for the next buffer,
+/// so buffers come out up to a frame late with their pts moved to match.
+#[derive(Debug)]
+pub struct Denoiser {
+    channels: u32,
+    states: Vec<*mut ffi::DenoiseState>,
+    frame_size: usize,
+    // interleaved samples of the frame not complete yet, its time and
+    // source
+    pending: Vec<f32>,
+    pending_pts: Duration,
+    source: Option<AudioSource>,
+}
+
+// SAFETY: the states have no thread affinity and are only used through
+// `&mut self`.
+unsafe impl Send for Denoiser {}
+
+impl Denoiser {
+    /// Denoises buffers of `channels` at 48 kHz.
+    pub fn new(channels: u32) -> Result<Self> {
+        let frame_size = unsafe { ffi::rnnoise_get_frame_size() }.max(1) as usize;
+        let mut denoiser = Denoiser {
+            channels: channels.max(1),
+            states: Vec::new(),
+            frame_size,
+            pending: Vec::new(),
+            pending_pts: Duration::ZERO,
+            source: None,
+        };
+        for _ in 0..denoiser.channels {
+            let state = unsafe { ffi::rnnoise_create(std::ptr::null_mut()) };
+            if state.is_null() {
+                return Err(ScreencastError::Unsupported(
+                    "failed to create the RNNoise state".to_string(),
+                ));
+            }
+            denoiser.states.push(state);
+        }
+        Ok(denoiser)
+    }
+
+    /// Denoises the frames complete with `buffer`, `None` while there are
+    /// none.
+    pub fn process(&mut self, buffer: &AudioBuffer) -> Result<Option<AudioBuffer>> {
+        if (buffer.sample_rate, buffer.channels) != (DENOISE_RATE, self.channels) {
+            return Err(ScreencastError::Unsupported(format!(
+                "noise suppression of {} Hz and {} channels, RNNoise takes {} Hz",
+                buffer.sample_rate, buffer.channels, DENOISE_RATE
+            )));
+        }
+        let channels = self.channels as usize;
+        let held = (self.pending.len() / channels) as u32;
+        self.pending_pts = buffer
+            .pts
+            .saturating_sub(Duration::from_secs(held as u64) / DENOISE_RATE);
+        self.pending.extend_from_slice(&buffer.samples);
+        self.source.clone_from(&buffer.source);
+        let complete = self.pending.len() / (self.frame_size * channels);
+        if complete == 0 {
+            return Ok(None);
+        }
+        let length = complete * self.frame_size * channels;
+        let rest = self.pending.split_off(length);
+        let samples = std::mem::replace(&mut self.pending, rest);
+        let pts = self.pending_pts;
+        self.pending_pts += Duration::from_secs((length / channels) as u64) / DENOISE_RATE;
+        Ok(Some(self.denoise(samples, pts)))
+    }
+
+    /// Denoises what is held back, filled up with silence to a frame and
+    /// cut back after.
+    pub fn flush(&mut self) -> Option<AudioBuffer> {
+        if self.pending.is_empty() {
+            return None;
+        }
+        let channels = self.channels as usize;
+        let length = self.pending.len();
+        let mut samples = std::mem::take(&mut self.pending);
+        samples.resize(self.frame_size * channels, 0.0);
+        let mut buffer = self.denoise(samples, self.pending_pts);
+        buffer.samples.truncate(length);
+        Some(buffer)
+    }
+
+    fn denoise(&mut self, mut samples: Vec<f32>, pts: Duration) -> AudioBuffer {
+        let channels = self.channels as usize;
+        let mut input = vec![0.0f32; self.frame_size];
+        let mut output = vec![0.0f32; self.frame_size];
+        for frame in samples.chunks_exact_mut(self.frame_size * channels) {
+            for (channel, state) in self.states.iter().enumerate() {
+                // in the range of 16 bit samples
+                for (sample, value) in input
+                    .iter_mut()
+                    .zip(frame.iter().skip(channel).step_by(channels))
+                {
+                    *sample = value * 32_768.0;
+                }
+                unsafe { ffi::rnnoise_process_frame(*state, output.as_mut_ptr(), input.as_ptr()) };
+                for (value, sample) in frame
+                    .iter_mut()
+                    .skip(channel)
+                    .step_by(channels)
+                    .zip(&output)
+                {
+                    *value = (sample / 32_768.0).clamp(-1.0, 1.0);
+                }
+            }
+        }
+        AudioBuffer {
+            samples,
+            sample_rate: DENOISE_RATE,
+            channels: self.channels,
+            pts,
+            source: self.source.clone(),
+        }
+    }
+}
+
+impl Drop for Denoiser {
+    fn drop(&mut self) {
+        for state in self.states.drain(..) {
+            unsafe { ffi::rnnoise_destroy(state) };
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use super::{AudioBuffer, AudioCodec, AudioConfig, AudioPacket, shifted};
+use crate::encode::bitstream::read_or_eof;
+use crate::error::{Result, ScreencastError};
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Samples libopus puts ahead of the audio at 48 kHz, what ffmpeg writes
+/// into `OpusHead`.
+const OPUS_PRE_SKIP: u16 = 312;
+
+/// Samples per AAC frame, ffmpeg's encoder puts one frame ahead.
+const AAC_FRAME: u64 = 1024;
+
+/// Encodes with an `ffmpeg` process, libopus for Opus and ffmpeg's own
+/// encoder for AAC: samples go in on stdin, Ogg pages or ADTS frames come
+/// back on stdout and are split into packets.
+///
+/// The process starts with the first buffer, later buffers must have the
+/// sample rate and channel count of the config. Packets are stamped from
+/// the first buffer on by the samples they hold, so gaps in the input close
+/// up, moved by as far as the times of the buffers drifted from that count
+/// meanwhile, which keeps them on the clock of the buffers.
+#[derive(Debug)]
+pub struct AudioEncoder {
+    config: AudioConfig,
+    program: OsString,
+    running: Option<Running>,
+    // pts of the first buffer and samples encoded since, at the output rate
+    start: Option<Duration>,
+    samples: u64,
+    // frames fed since, at the input rate, and how far the pts of the last
+    // buffer was from them in nanoseconds
+    fed: u64,
+    drift: i64,
+}
+
+#[derive(Debug)]
+struct Running {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    packets: Receiver<Vec<u8>>,
+    reader: JoinHandle<io::Result<()>>,
+    stderr: Arc<Mutex<String>>,
+}
+
+impl AudioEncoder {
+    pub fn new(config: AudioConfig) -> Self {
+        AudioEncoder {
+            config,
+            program: "ffmpeg".into(),
+            running: None,
+            start: None,
+            samples: 0,
+            fed: 0,
+            drift: 0,
+        }
+    }
+
+    /// The ffmpeg binary, looked up in `PATH` by default.
+    pub fn with_program(mut self, program: impl Into<OsString>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// What the decoder needs to know ahead of the packets: the `OpusHead`
+    /// of the Ogg and Matroska mappings, the AudioSpecificConfig of AAC.
+    pub fn codec_private(&self) -> Vec<u8> {
+        let channels = self.config.channels as u8;
+        match self.config.codec {
+            AudioCodec::Opus => {
+                let mut head = b"OpusHead".to_vec();
+                head.extend_from_slice(&[1, channels]);
+                head.extend_from_slice(&OPUS_PRE_SKIP.to_le_bytes());
+                head.extend_from_slice(&self.config.sample_rate.to_le_bytes());
+                // output gain and channel mapping family
+                head.extend_from_slice(&[0, 0, 0]);
+                head
+            }
+            AudioCodec::Aac => {
+                // AAC-LC
+                let object_type = 2u16;
+                let index = frequency_index(self.config.sample_rate) as u16;
+                let config = object_type << 11 | index << 7 | (channels as u16) << 3;
+                config.to_be_bytes().to_vec()
+            }
+        }
+    }
+
+    /// Feeds a buffer to ffmpeg, returns the packets it finished meanwhile.
+    pub fn encode(&mut self, buffer: &AudioBuffer) -> Result<Vec<AudioPacket>> {
+        if (buffer.sample_rate, buffer.channels) != (self.config.sample_rate, self.config.channels)
+        {
+            return Err(ScreencastError::Unsupported(format!(
+                "audio of {} Hz and {} channels for an encoder of {} Hz and {} channels",
+                buffer.sample_rate, buffer.channels, self.config.sample_rate, self.config.channels
+            )));
+        }
+        if self.running.is_none() {
+            self.running = Some(self.spawn()?);
+            self.start = Some(buffer.pts);
+        }
+        let counted = self.fed as i128 * 1_000_000_000 / self.config.sample_rate.max(1) as i128;
+        let start = self.start.unwrap_or_default().as_nanos() as i128;
+        self.drift = (buffer.pts.as_nanos() as i128 - start - counted) as i64;
+        self.fed += buffer.frames() as u64;
+        let running = self.running.as_mut().unwrap();
+        let bytes: Vec<u8> = buffer
+            .samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        if let Err(err) = running.stdin.as_mut().unwrap().write_all(&bytes) {
+            return Err(running.failure(err));
+        }
+        let mut packets = Vec::new();
+        loop {
+            match self.running.as_mut().unwrap().packets.try_recv() {
+                Ok(data) => packets.push(self.packet(data)),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(packets),
+            }
+        }
+    }
+
+    /// Closes ffmpeg's input and waits for the remaining packets.
+    pub fn finish(&mut self) -> Result<Vec<AudioPacket>> {
+        let Some(mut running) = self.running.take() else {
+            return Ok(Vec::new());
+        };
+        drop(running.stdin.take());
+        let mut packets = Vec::new();
+        while let Ok(data) = running.packets.recv() {
+            packets.push(self.packet(data));
+        }
+        let read = running.reader.join().unwrap_or(Ok(()));
+        let status = running.child.wait()?;
+        if !status.success() {
+            let message = running.stderr.lock().unwrap().trim().to_string();
+            return Err(ScreencastError::Encoder(format!(
+                "ffmpeg exited with {}: {}",
+                status, message
+            )));
+        }
+        read?;
+        Ok(packets)
+    }
+
+    fn spawn(&self) -> Result<Running> {
+        let codec = self.config.codec;
+        let mut command = Command::new(&self.program);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-f", "f32le"])
+            .args(["-ar", &self.config.sample_rate.to_string()])
+            .args(["-ac", &self.config.channels.to_string()])
+            .args(["-i", "pipe:0", "-vn"])
+            .args(["-b:a", &self.config.bitrate.to_string()]);
+        match codec {
+            AudioCodec::Opus => command.args([
+                "-c:a",
+                "libopus",
+                "-application",
+                "audio",
+                "-frame_duration",
+                "20",
+                "-ar",
+                "48000",
+                "-f",
+                "ogg",
+                "-page_duration",
+                "20000",
+            ]),
+            AudioCodec::Aac => command.args(["-c:a", "aac", "-f", "adts"]),
+        };
+        command
+            .args(["-flush_packets", "1", "pipe:1"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => {
+                ScreencastError::Unsupported("ffmpeg is not installed".to_string())
+            }
+            _ => ScreencastError::Io(err),
+        })?;
+
+        let stdout = child.stdout.take().unwrap();
+        let (sender, packets) = mpsc::channel();
+        let reader = thread::Builder::new()
+            .name("ffmpeg-audio-reader".to_string())
+            .spawn(move || {
+                let emit = |packet| sender.send(packet).is_ok();
+                match codec {
+                    AudioCodec::Opus => read_ogg(stdout, emit),
+                    AudioCodec::Aac => read_adts(stdout, emit),
+                }
+            })?;
+
+        let mut stderr = child.stderr.take().unwrap();
+        let messages = Arc::new(Mutex::new(String::new()));
+        let collected = messages.clone();
+        thread::Builder::new()
+            .name("ffmpeg-audio-stderr".to_string())
+            .spawn(move || {
+                let mut chunk = [0; 1024];
+                while let Ok(read @ 1..) = stderr.read(&mut chunk) {
+                    let mut messages = collected.lock().unwrap();
+                    messages.push_str(&String::from_utf8_lossy(&chunk[..read]));
+                    if messages.len() > 4096 {
+                        let cut = messages.ceil_char_boundary(messages.len() - 4096);
+                        messages.drain(..cut);
+                    }
+                }
+            })?;
+
+        Ok(Running {
+            stdin: child.stdin.take(),
+            child,
+            packets,
+            reader,
+            stderr: messages,
+        })
+    }
+
+    fn packet(&mut self, data: Vec<u8>) -> AudioPacket {
+        let rate = output_rate(&self.config) as u64;
+        let samples = match self.config.codec {
+            AudioCodec::Opus => opus_samples(&data),
+            AudioCodec::Aac => AAC_FRAME,
+        };
+        let at = |samples: u64| Duration::from_nanos(samples * 1_000_000_000 / rate);
+        let pts = shifted(
+            self.start.unwrap_or_default() + at(self.samples),
+            self.drift,
+        );
+        self.samples += samples;
+        AudioPacket {
+            codec: self.config.codec,
+            data,
+            pts,
+            duration: at(samples),
+        }
+    }
+}
+
+impl Drop for AudioEncoder {
+    fn drop(&mut self) {
+        if let Some(running) = &mut self.running {
+            let _ = running.child.kill();
+            let _ = running.child.wait();
+        }
+    }
+}
+
+impl Running {
+    /// Explains a failed write, ffmpeg usually exited and said why.
+    fn failure(&mut self, err: io::Error) -> ScreencastError {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            return ScreencastError::Io(err);
+        }
+        let _ = self.child.wait();
+        let message = self.stderr.lock().unwrap().trim().to_string();
+        ScreencastError::Encoder(format!("ffmpeg stopped: {}", message))
+    }
+}
+
+pub(super) fn priming(config: &AudioConfig) -> u32 {
+    match config.codec {
+        AudioCodec::Opus => OPUS_PRE_SKIP as u32,
+        AudioCodec::Aac => AAC_FRAME as u32,
+    }
+}
+
+pub(super) fn output_rate(config: &AudioConfig) -> u32 {
+    match config.codec {
+        AudioCodec::Opus => 48_000,
+        AudioCodec::Aac => config.sample_rate,
+    }
+}
+
+/// The sampling frequency index of MPEG-4 audio, the nearest rate below
+/// for those without one.
+fn frequency_index(sample_rate: u32) -> u8 {
+    const RATES: [u32; 13] = [
+        96_000, 88_200, 64_000, 48_000, 44_100, 32_000, 24_000, 22_050, 16_000, 12_000, 11_025,
+        8_000, 7_350,
+    ];
+    RATES
+        .iter()
+        .position(|&rate| rate <= sample_rate)
+        .unwrap_or(RATES.len() - 1) as u8
+}
+
+/// Samples at 48 kHz in an Opus packet, from its TOC byte.
+fn opus_samples(packet: &[u8]) -> u64 {
+    let Some(&toc) = packet.first() else {
+        return 0;
+    };
+    let config = toc >> 3;
+    // in units of 2.5 ms, 120 samples
+    let frame = match config {
+        0..=11 => [4, 8, 16, 24][config as usize % 4],
+        12..=15 => [4, 8][config as usize % 2],
+        _ => [1, 2, 4, 8][config as usize % 4],
+    };
+    let frames = match toc & 0x03 {
+        0 => 1,
+        1 | 2 => 2,
+        _ => packet.get(1).map_or(0, |count| count & 0x3f) as u64,
+    };
+    frame * frames * 120
+}
+
+/// Splits an Ogg stream into its packets, leaving out the Opus headers.
+fn read_ogg(mut reader: impl Read, mut emit: impl FnMut(Vec<u8>) -> bool) -> io::Result<()> {
+    let mut header = [0; 27];
+    let mut packet = Vec::new();
+    while read_or_eof(&mut reader, &mut header)? {
+        if &header[..4] != b"OggS" {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an Ogg stream",
+            ));
+        }
+        let mut lacing = vec![0; header[26] as usize];
+        reader.read_exact(&mut lacing)?;
+        let mut body = vec![0; lacing.iter().map(|&length| length as usize).sum()];
+        reader.read_exact(&mut body)?;
+        let mut offset = 0;
+        // a packet goes on over segments of 255 bytes, also into the next page
+        for length in lacing.into_iter().map(usize::from) {
+            packet.extend_from_slice(&body[offset..offset + length]);
+            offset += length;
+            if length == 255 {
+                continue;
+            }
+            let data = std::mem::take(&mut packet);
+            if data.starts_with(b"OpusHead") || data.starts_with(b"OpusTags") {
+                continue;
+            }
+            if !emit(data) {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Splits an ADTS stream into raw AAC frames.
+fn read_adts(mut reader: impl Read, mut emit: impl FnMut(Vec<u8>) -> bool) -> io::Result<()> {
+    let mut header = [0; 7];
+    while read_or_eof(&mut reader, &mut header)? {
+        if header[0] != 0xff || header[1] & 0xf0 != 0xf0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not an ADTS stream",
+            ));
+        }
+        let length = ((header[3] as usize & 0x03) << 11)
+            | ((header[4] as usize) << 3)
+            | (header[5] as usize >> 5);
+        let mut frame = vec![0; length.saturating_sub(header.len())];
+        reader.read_exact(&mut frame)?;
+        // a CRC follows the header unless protection is absent
+        let crc = if header[1] & 0x01 == 0 { 2 } else { 0 };
+        if !emit(frame.get(crc..).unwrap_or_default().to_vec()) {
+            break;
+        }
+    }
+    Ok(())
+}
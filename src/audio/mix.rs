@@ -0,0 +1,355 @@
+#[cfg(feature = "rnnoise")]
+use super::denoise::Denoiser;
+use super::{AudioBuffer, AudioConfig, AudioInput, DENOISE_RATE, shifted};
+use crate::error::{Result, ScreencastError};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// How far an input may fall behind the others before its part of the mix
+/// is taken as silence.
+const LATENCY: Duration = Duration::from_millis(250);
+
+/// How much of each deviation of the first input moves the clock of the
+/// mix, to follow the drift of the device and not the jitter of the times.
+const DRIFT_SMOOTHING: i128 = 16;
+
+/// Sums the buffers of several inputs into one stream, each scaled by its
+/// gain and clipped to full scale, those asking for it denoised first.
+///
+/// The inputs are lined up by the pts of their buffers, gaps are filled
+/// with silence and overlaps dropped. The mix counts samples, and its times
+/// follow the first input as the clock of that device drifts from the
+/// count, so they stay on the clock of the video; another input drifting
+/// more than 10 ms from the first has a gap filled or an overlap dropped.
+/// Mixed buffers come out as far as
+/// every input got, or at most `LATENCY` behind the input furthest ahead
+/// when another one went quiet, e.g. a microphone that was unplugged.
+/// Buffers without a source count as those of the first input.
+#[derive(Debug)]
+pub struct Mixer {
+    sample_rate: u32,
+    channels: u32,
+    inputs: Vec<Queue>,
+    // the time of the first sample mixed and the frames mixed since
+    origin: Option<Duration>,
+    mixed: u64,
+    // how far the clock of the first input drifted from the frames mixed,
+    // in nanoseconds
+    drift: i64,
+}
+
+#[derive(Debug)]
+struct Queue {
+    input: AudioInput,
+    // interleaved samples from the end of the mix on
+    samples: VecDeque<f32>,
+    #[cfg(feature = "rnnoise")]
+    denoiser: Option<Denoiser>,
+}
+
+impl Mixer {
+    /// Mixes the inputs of `config`, at its rate and channel count.
+    pub fn new(config: &AudioConfig) -> Result<Self> {
+        Mixer::with_inputs(config, config.inputs.clone())
+    }
+
+    pub fn with_inputs(config: &AudioConfig, inputs: Vec<AudioInput>) -> Result<Self> {
+        let inputs = inputs
+            .into_iter()
+            .map(|input| Queue::new(config, input))
+            .collect::<Result<_>>()?;
+        Ok(Mixer {
+            sample_rate: config.sample_rate,
+            channels: config.channels,
+            inputs,
+            origin: None,
+            mixed: 0,
+            drift: 0,
+        })
+    }
+
+    /// Queues a buffer, returns what could be mixed since. Buffers of other
+    /// sources are ignored.
+    pub fn push(&mut self, buffer: &AudioBuffer) -> Result<Vec<AudioBuffer>> {
+        let index = match &buffer.source {
+            Some(source) => self
+                .inputs
+                .iter()
+                .position(|queue| queue.input.source == *source),
+            None if !self.inputs.is_empty() => Some(0),
+            None => None,
+        };
+        let Some(index) = index else {
+            return Ok(Vec::new());
+        };
+        if (buffer.sample_rate, buffer.channels) != (self.sample_rate, self.channels) {
+            return Err(ScreencastError::Unsupported(format!(
+                "audio of {} Hz and {} channels for a mix of {} Hz and {} channels",
+                buffer.sample_rate, buffer.channels, self.sample_rate, self.channels
+            )));
+        }
+        #[cfg(feature = "rnnoise")]
+        if let Some(denoiser) = &mut self.inputs[index].denoiser {
+            return match denoiser.process(buffer)? {
+                Some(denoised) => Ok(self.queue(index, &denoised)),
+                None => Ok(Vec::new()),
+            };
+        }
+        Ok(self.queue(index, buffer))
+    }
+
+    /// Mixes what is left, filling the inputs that are short with silence.
+    pub fn flush(&mut self) -> Vec<AudioBuffer> {
+        #[allow(unused_mut)]
+        let mut mixed = Vec::new();
+        #[cfg(feature = "rnnoise")]
+        for index in 0..self.inputs.len() {
+            let denoised = self.inputs[index]
+                .denoiser
+                .as_mut()
+                .and_then(Denoiser::flush);
+            if let Some(denoised) = denoised {
+                mixed.extend(self.queue(index, &denoised));
+            }
+        }
+        mixed.extend(self.mix(true));
+        mixed
+    }
+
+    /// Lines the buffer up with the others of its input, returns what could
+    /// be mixed since.
+    fn queue(&mut self, index: usize, buffer: &AudioBuffer) -> Vec<AudioBuffer> {
+        let channels = self.channels.max(1) as usize;
+        self.origin.get_or_insert(buffer.pts);
+        // where the queue of the input ends on the clock of the mix
+        let queued = self.inputs[index].samples.len() / channels;
+        let end = shifted(self.position(), self.drift) + self.time(queued as u64);
+        let deviation = buffer.pts.as_nanos() as i128 - end.as_nanos() as i128;
+        let gap = (deviation * self.sample_rate as i128 / 1_000_000_000) as i64;
+        // slight jitter and drift of the timestamps are no gap
+        let tolerance = (self.sample_rate / 100) as i64;
+        let skip = match gap {
+            gap if gap.abs() <= tolerance => {
+                if index == 0 {
+                    self.drift += (deviation / DRIFT_SMOOTHING) as i64;
+                }
+                0
+            }
+            gap if gap > 0 => {
+                let silence = gap as usize * channels;
+                let queue = &mut self.inputs[index].samples;
+                queue.resize(queue.len() + silence, 0.0);
+                0
+            }
+            overlap => (-overlap) as usize * channels,
+        };
+        self.inputs[index]
+            .samples
+            .extend(buffer.samples.iter().skip(skip));
+        self.mix(false)
+    }
+
+    fn mix(&mut self, flush: bool) -> Vec<AudioBuffer> {
+        let channels = self.channels.max(1) as usize;
+        let longest = self
+            .inputs
+            .iter()
+            .map(|queue| queue.samples.len())
+            .max()
+            .unwrap_or(0);
+        let behind = match flush {
+            true => 0,
+            false => self.frames(LATENCY) as usize * channels,
+        };
+        for queue in &mut self.inputs {
+            let wanted = longest.saturating_sub(behind);
+            if queue.samples.len() < wanted {
+                queue.samples.resize(wanted, 0.0);
+            }
+        }
+        let available = self
+            .inputs
+            .iter()
+            .map(|queue| queue.samples.len())
+            .min()
+            .unwrap_or(0);
+        let length = available - available % channels;
+        if length == 0 {
+            return Vec::new();
+        }
+        let mut samples = vec![0.0f32; length];
+        for queue in &mut self.inputs {
+            let gain = queue.input.gain;
+            for (mixed, sample) in samples.iter_mut().zip(queue.samples.drain(..length)) {
+                *mixed += sample * gain;
+            }
+        }
+        for sample in &mut samples {
+            *sample = sample.clamp(-1.0, 1.0);
+        }
+        let pts = shifted(self.position(), self.drift);
+        self.mixed += (length / channels) as u64;
+        vec![AudioBuffer {
+            samples,
+            sample_rate: self.sample_rate,
+            channels: self.channels,
+            pts,
+            source: None,
+        }]
+    }
+
+    /// The time of the next sample to mix, by the frames mixed.
+    fn position(&self) -> Duration {
+        self.origin.unwrap_or_default() + self.time(self.mixed)
+    }
+
+    fn time(&self, frames: u64) -> Duration {
+        Duration::from_nanos(
+            (frames as u128 * 1_000_000_000 / self.sample_rate.max(1) as u128) as u64,
+        )
+    }
+
+    fn frames(&self, time: Duration) -> u64 {
+        (time.as_nanos() * self.sample_rate as u128 / 1_000_000_000) as u64
+    }
+}
+
+impl Queue {
+    fn new(config: &AudioConfig, input: AudioInput) -> Result<Self> {
+        if input.denoise && !cfg!(feature = "rnnoise") {
+            return Err(ScreencastError::Unsupported(
+                "noise suppression without the rnnoise feature".to_string(),
+            ));
+        }
+        if input.denoise && config.sample_rate != DENOISE_RATE {
+            return Err(ScreencastError::Unsupported(format!(
+                "noise suppression at {} Hz, RNNoise takes {} Hz",
+                config.sample_rate, DENOISE_RATE
+            )));
+        }
+        #[cfg(feature = "rnnoise")]
+        let denoiser = match input.denoise {
+            true => Some(Denoiser::new(config.channels)?),
+            false => None,
+        };
+        Ok(Queue {
+            input,
+            samples: VecDeque::new(),
+            #[cfg(feature = "rnnoise")]
+            denoiser,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioCodec, AudioSource};
+
+    const MILLI: Duration = Duration::from_millis(1);
+
+    /// Mono at 1 kHz, a frame a millisecond.
+    fn mixer(inputs: &[(AudioSource, f32)]) -> Mixer {
+        let config = AudioConfig {
+            sample_rate: 1000,
+            channels: 1,
+            ..AudioConfig::new(AudioCodec::Aac)
+        };
+        let inputs = inputs
+            .iter()
+            .map(|(source, gain)| AudioInput {
+                source: source.clone(),
+                gain: *gain,
+                denoise: false,
+            })
+            .collect();
+        Mixer::with_inputs(&config, inputs).unwrap()
+    }
+
+    fn buffer(source: AudioSource, millis: u32, samples: Vec<f32>) -> AudioBuffer {
+        AudioBuffer {
+            samples,
+            sample_rate: 1000,
+            channels: 1,
+            pts: MILLI * millis,
+            source: Some(source),
+        }
+    }
+
+    #[test]
+    fn sums_with_gains() {
+        use AudioSource::{Desktop, Microphone};
+        let mut mixer = mixer(&[(Desktop, 1.0), (Microphone, 0.5)]);
+        let desktop = buffer(Desktop, 0, vec![0.5, 0.9, -0.9, 0.0]);
+        assert!(mixer.push(&desktop).unwrap().is_empty());
+        let microphone = buffer(Microphone, 0, vec![0.4, 0.4, -0.4, 1.0]);
+        let mixed = mixer.push(&microphone).unwrap();
+        assert_eq!(mixed.len(), 1);
+        assert_eq!(mixed[0].pts, Duration::ZERO);
+        assert_eq!(mixed[0].source, None);
+        // clipped to full scale
+        assert_eq!(mixed[0].samples, [0.7, 1.0, -1.0, 0.5]);
+
+        // other sources are no part of the mix
+        let other = buffer(AudioSource::Application("game".into()), 4, vec![1.0; 4]);
+        assert!(mixer.push(&other).unwrap().is_empty());
+        let stereo = AudioBuffer {
+            channels: 2,
+            ..buffer(Desktop, 4, vec![0.0; 4])
+        };
+        assert!(matches!(
+            mixer.push(&stereo),
+            Err(ScreencastError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn gaps_and_overlaps() {
+        let mut mixer = mixer(&[(AudioSource::Desktop, 1.0)]);
+        let mixed = mixer.push(&buffer(AudioSource::Desktop, 0, vec![1.0; 100]));
+        assert_eq!(mixed.unwrap()[0].frames(), 100);
+
+        // 100 ms missing are silence
+        let mixed = mixer
+            .push(&buffer(AudioSource::Desktop, 200, vec![1.0; 100]))
+            .unwrap();
+        assert_eq!(mixed[0].pts, MILLI * 100);
+        assert_eq!(mixed[0].samples, [vec![0.0; 100], vec![1.0; 100]].concat());
+
+        // 50 ms played twice are dropped
+        let mixed = mixer
+            .push(&buffer(AudioSource::Desktop, 250, vec![0.5; 100]))
+            .unwrap();
+        assert_eq!(mixed[0].pts, MILLI * 300);
+        assert_eq!(mixed[0].samples, [0.5; 50]);
+    }
+
+    #[test]
+    fn follows_the_clock_of_the_first_input() {
+        let mut mixer = mixer(&[(AudioSource::Desktop, 1.0)]);
+        mixer
+            .push(&buffer(AudioSource::Desktop, 0, vec![0.0; 100]))
+            .unwrap();
+        // 4 ms late is jitter, a 16th of it moves the clock
+        let mixed = mixer
+            .push(&buffer(AudioSource::Desktop, 104, vec![0.0; 100]))
+            .unwrap();
+        assert_eq!(mixed[0].frames(), 100);
+        assert_eq!(mixed[0].pts, MILLI * 100 + Duration::from_micros(250));
+    }
+
+    #[test]
+    fn quiet_input_falls_behind() {
+        use AudioSource::{Desktop, Microphone};
+        let mut mixer = mixer(&[(Desktop, 1.0), (Microphone, 1.0)]);
+        let mixed = mixer.push(&buffer(Desktop, 0, vec![0.25; 400])).unwrap();
+        // all but LATENCY is mixed with silence for the microphone
+        assert_eq!(mixed[0].pts, Duration::ZERO);
+        assert_eq!(mixed[0].samples, [0.25; 150]);
+
+        let flushed = mixer.flush();
+        assert_eq!(flushed[0].pts, MILLI * 150);
+        assert_eq!(flushed[0].samples, [0.25; 250]);
+        assert!(mixer.flush().is_empty());
+    }
+}
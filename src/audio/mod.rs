@@ -0,0 +1,439 @@
+#[cfg(feature = "rnnoise")]
+mod denoise;
+mod encode;
+mod mix;
+
+#[cfg(feature = "pipewire")]
+pub use crate::pipewire::AudioCapture;
+#[cfg(feature = "rnnoise")]
+pub use denoise::Denoiser;
+pub use encode::AudioEncoder;
+pub use mix::Mixer;
+
+use crate::error::{Result, ScreencastError};
+use crate::recorder::Pauses;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// The rate RNNoise works at.
+const DENOISE_RATE: u32 = 48_000;
+
+/// `time` moved by `nanos`, which may be negative, no earlier than 0.
+fn shifted(time: Duration, nanos: i64) -> Duration {
+    match nanos {
+        0.. => time + Duration::from_nanos(nanos as u64),
+        _ => time.saturating_sub(Duration::from_nanos(nanos.unsigned_abs())),
+    }
+}
+
+/// Audio codecs of the recorded tracks.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AudioCodec {
+    /// Always at 48 kHz, the better choice at any bitrate, not in MP4 files
+    /// some older players open.
+    #[default]
+    Opus,
+    /// AAC-LC, what every player and streaming service takes.
+    Aac,
+}
+
+/// Where captured audio comes from.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum AudioSource {
+    /// What the desktop plays, the monitor of the default output.
+    Desktop,
+    /// The default input, usually a microphone.
+    Microphone,
+    /// What one application plays and nothing else, e.g. a game without
+    /// the notification sounds of the others. Matches the node name, the
+    /// binary or the application name of its output stream, e.g.
+    /// `"firefox"`. Silent until the application plays something.
+    Application(String),
+}
+
+/// A source of a recording and how loud it goes into the mix.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioInput {
+    pub source: AudioSource,
+    /// 1.0 keeps the level of the source.
+    pub gain: f32,
+    /// Suppress the noise around speech, see `Denoiser`.
+    pub denoise: bool,
+}
+
+/// How several inputs end up in a recording.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum AudioMix {
+    /// One track with all inputs mixed.
+    #[default]
+    Mixed,
+    /// A track for each input, in the order of the inputs, to mix later.
+    Separate,
+}
+
+/// How audio is captured and encoded. PipeWire converts the sources to the
+/// rate and channel count asked for.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioConfig {
+    pub codec: AudioCodec,
+    /// Bits per second of each encoded track.
+    pub bitrate: u32,
+    pub sample_rate: u32,
+    pub channels: u32,
+    /// The desktop alone unless changed.
+    pub inputs: Vec<AudioInput>,
+    pub mix: AudioMix,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        AudioConfig::new(AudioCodec::Opus)
+    }
+}
+
+impl AudioConfig {
+    /// 128 kbit/s stereo at 48 kHz.
+    pub fn new(codec: AudioCodec) -> Self {
+        AudioConfig {
+            codec,
+            bitrate: 128_000,
+            sample_rate: 48_000,
+            channels: 2,
+            inputs: vec![AudioInput {
+                source: AudioSource::Desktop,
+                gain: 1.0,
+                denoise: false,
+            }],
+            mix: AudioMix::Mixed,
+        }
+    }
+
+    pub fn with_bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    pub fn with_sample_rate(mut self, sample_rate: u32) -> Self {
+        self.sample_rate = sample_rate.clamp(8_000, 192_000);
+        self
+    }
+
+    pub fn with_channels(mut self, channels: u32) -> Self {
+        self.channels = channels.clamp(1, 2);
+        self
+    }
+
+    /// Records `source` too, or changes its gain when it already is.
+    pub fn with_input(mut self, source: AudioSource, gain: f32) -> Self {
+        let gain = gain.max(0.0);
+        match self.inputs.iter_mut().find(|input| input.source == source) {
+            Some(input) => input.gain = gain,
+            None => self.inputs.push(AudioInput {
+                source,
+                gain,
+                denoise: false,
+            }),
+        }
+        self
+    }
+
+    /// Suppresses the noise of `source` with RNNoise, meant for narration
+    /// over the microphone of a laptop. Needs the `rnnoise` feature and a
+    /// sample rate of 48 kHz, the tracks fail to start otherwise.
+    pub fn with_denoise(mut self, source: AudioSource, denoise: bool) -> Self {
+        if let Some(input) = self.inputs.iter_mut().find(|input| input.source == source) {
+            input.denoise = denoise;
+        }
+        self
+    }
+
+    pub fn without_input(mut self, source: AudioSource) -> Self {
+        self.inputs.retain(|input| input.source != source);
+        self
+    }
+
+    pub fn with_mix(mut self, mix: AudioMix) -> Self {
+        self.mix = mix;
+        self
+    }
+
+    pub fn has_input(&self, source: &AudioSource) -> bool {
+        self.inputs.iter().any(|input| input.source == *source)
+    }
+}
+
+/// Interleaved 32 bit float samples and the time of the first one, on the
+/// clock of the video frames.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub pts: Duration,
+    /// `None` once mixed.
+    pub source: Option<AudioSource>,
+}
+
+impl AudioBuffer {
+    /// Samples per channel.
+    pub fn frames(&self) -> usize {
+        self.samples.len() / self.channels.max(1) as usize
+    }
+
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.frames() as u64) / self.sample_rate.max(1)
+    }
+}
+
+/// One compressed audio frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AudioPacket {
+    pub codec: AudioCodec,
+    pub data: Vec<u8>,
+    pub pts: Duration,
+    pub duration: Duration,
+}
+
+/// Encoded audio on its way into a recording, see `MkvFile::with_audio`
+/// and `Mp4File::with_audio`.
+///
+/// The buffers are encoded on a thread of their own, which stops when the
+/// buffers end or the track is dropped.
+#[derive(Debug)]
+pub struct AudioTrack {
+    config: AudioConfig,
+    codec_private: Vec<u8>,
+    packets: Receiver<Result<AudioPacket>>,
+    // the next packet, taken out of the queue but not due yet, as captured
+    pending: Option<AudioPacket>,
+    pauses: Option<Pauses>,
+}
+
+impl AudioTrack {
+    /// Encodes `buffers` as they come, e.g. those of an `AudioCapture`:
+    /// `AudioTrack::spawn(std::iter::from_fn(move || capture.recv()), config)`.
+    /// The inputs of `config` are mixed into the one track.
+    pub fn spawn<I>(buffers: I, config: AudioConfig) -> Result<Self>
+    where
+        I: IntoIterator<Item = AudioBuffer>,
+        I::IntoIter: Send + 'static,
+    {
+        AudioTrack::spawn_with_encoder(buffers, AudioEncoder::new(config))
+    }
+
+    pub fn spawn_with_encoder<I>(buffers: I, encoder: AudioEncoder) -> Result<Self>
+    where
+        I: IntoIterator<Item = AudioBuffer>,
+        I::IntoIter: Send + 'static,
+    {
+        let mixer = Mixer::new(encoder.config())?;
+        let mut tracks = AudioTrack::spawn_outputs(buffers, vec![(mixer, encoder)])?;
+        Ok(tracks.remove(0))
+    }
+
+    /// The tracks `config.mix` asks for: one mixed, or one for each input
+    /// with the config of the track naming that input alone.
+    pub fn spawn_all<I>(buffers: I, config: AudioConfig) -> Result<Vec<Self>>
+    where
+        I: IntoIterator<Item = AudioBuffer>,
+        I::IntoIter: Send + 'static,
+    {
+        if config.inputs.is_empty() {
+            return Err(ScreencastError::Unsupported(
+                "audio config without inputs".to_string(),
+            ));
+        }
+        let outputs = match config.mix {
+            AudioMix::Mixed => vec![(Mixer::new(&config)?, AudioEncoder::new(config))],
+            AudioMix::Separate => config
+                .inputs
+                .iter()
+                .map(|input| {
+                    let mut single = config.clone();
+                    single.inputs = vec![input.clone()];
+                    Ok((Mixer::new(&single)?, AudioEncoder::new(single)))
+                })
+                .collect::<Result<_>>()?,
+        };
+        AudioTrack::spawn_outputs(buffers, outputs)
+    }
+
+    fn spawn_outputs<I>(buffers: I, outputs: Vec<(Mixer, AudioEncoder)>) -> Result<Vec<Self>>
+    where
+        I: IntoIterator<Item = AudioBuffer>,
+        I::IntoIter: Send + 'static,
+    {
+        let buffers = buffers.into_iter();
+        let mut tracks = Vec::new();
+        let mut outputs: Vec<Output> = outputs
+            .into_iter()
+            .map(|(mixer, encoder)| {
+                let (sender, packets) = mpsc::channel();
+                tracks.push(AudioTrack {
+                    config: encoder.config().clone(),
+                    codec_private: encoder.codec_private(),
+                    packets,
+                    pending: None,
+                    pauses: None,
+                });
+                Output {
+                    mixer,
+                    encoder,
+                    sender: Some(sender),
+                }
+            })
+            .collect();
+        thread::Builder::new()
+            .name("audio-encoder".to_string())
+            .spawn(move || {
+                for buffer in buffers {
+                    for output in &mut outputs {
+                        let mixed = output.mixer.push(&buffer);
+                        output.encode(mixed);
+                    }
+                    if outputs.iter().all(|output| output.sender.is_none()) {
+                        return;
+                    }
+                }
+                for output in &mut outputs {
+                    let mixed = output.mixer.flush();
+                    output.encode(Ok(mixed));
+                    output.finish();
+                }
+            })?;
+        Ok(tracks)
+    }
+
+    /// Leaves out the pauses of a `Recorder` given the same `pauses`, and
+    /// moves the packets after them back, as it does with the frames.
+    pub fn with_pauses(mut self, pauses: Pauses) -> Self {
+        self.pauses = Some(pauses);
+        self
+    }
+
+    /// A track of packets encoded already.
+    #[cfg(test)]
+    pub(crate) fn from_packets(config: AudioConfig, packets: Vec<AudioPacket>) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        packets
+            .into_iter()
+            .for_each(|packet| sender.send(Ok(packet)).unwrap());
+        AudioTrack {
+            config,
+            codec_private: Vec::new(),
+            packets: receiver,
+            pending: None,
+            pauses: None,
+        }
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// The source of a track of `AudioMix::Separate`, `None` for a mix.
+    pub fn source(&self) -> Option<&AudioSource> {
+        match self.config.inputs.as_slice() {
+            [input] if self.config.mix == AudioMix::Separate => Some(&input.source),
+            _ => None,
+        }
+    }
+
+    /// `OpusHead` for Opus, the AudioSpecificConfig for AAC.
+    pub fn codec_private(&self) -> &[u8] {
+        &self.codec_private
+    }
+
+    /// The rate of the encoded samples, 48 kHz for Opus.
+    pub fn output_rate(&self) -> u32 {
+        encode::output_rate(&self.config)
+    }
+
+    /// The time the decoder drops at the start, the samples the encoder
+    /// put ahead of the audio. Packets are stamped with the time of their
+    /// first sample including those.
+    pub fn pre_skip(&self) -> Duration {
+        Duration::from_secs(self.priming() as u64) / self.output_rate()
+    }
+
+    /// `pre_skip` in samples at the output rate.
+    pub(crate) fn priming(&self) -> u32 {
+        encode::priming(&self.config)
+    }
+
+    /// The packets encoded so far that start no later than `until`.
+    pub(crate) fn ready(&mut self, until: Duration) -> Result<Vec<AudioPacket>> {
+        let mut packets = Vec::new();
+        loop {
+            let mut packet = match self.pending.take() {
+                Some(packet) => packet,
+                None => match self.packets.try_recv() {
+                    Ok(packet) => packet?,
+                    Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(packets),
+                },
+            };
+            // a pause may still begin before a packet kept for later
+            let pts = match &self.pauses {
+                Some(pauses) => match pauses.shift(packet.pts) {
+                    Some(pts) => pts,
+                    None => continue,
+                },
+                None => packet.pts,
+            };
+            if pts > until {
+                self.pending = Some(packet);
+                return Ok(packets);
+            }
+            packet.pts = pts;
+            packets.push(packet);
+        }
+    }
+}
+
+/// A track being encoded on the thread of `AudioTrack::spawn_outputs`, done
+/// once its sender is gone.
+struct Output {
+    mixer: Mixer,
+    encoder: AudioEncoder,
+    sender: Option<mpsc::Sender<Result<AudioPacket>>>,
+}
+
+impl Output {
+    fn encode(&mut self, mixed: Result<Vec<AudioBuffer>>) {
+        let Some(sender) = &self.sender else {
+            return;
+        };
+        let encoded = mixed.and_then(|buffers| {
+            let mut packets = Vec::new();
+            for buffer in &buffers {
+                packets.extend(self.encoder.encode(buffer)?);
+            }
+            Ok(packets)
+        });
+        let failed = encoded.is_err();
+        let sent = match encoded {
+            Ok(packets) => packets
+                .into_iter()
+                .all(|packet| sender.send(Ok(packet)).is_ok()),
+            Err(err) => sender.send(Err(err)).is_ok(),
+        };
+        if failed || !sent {
+            self.sender = None;
+        }
+    }
+
+    fn finish(&mut self) {
+        let Some(sender) = self.sender.take() else {
+            return;
+        };
+        match self.encoder.finish() {
+            Ok(packets) => packets.into_iter().for_each(|packet| {
+                let _ = sender.send(Ok(packet));
+            }),
+            Err(err) => {
+                let _ = sender.send(Err(err));
+            }
+        }
+    }
+}
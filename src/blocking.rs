@@ -0,0 +1,47 @@
+use crate::error::Result;
+use crate::runtime::runtime;
+use crate::screencast::{Capabilities, ScreenCast, ScreencastOptions, SelectedSource};
+use crate::session::ActiveSession;
+
+/// Blocking versions of the calls, for programs without an async runtime.
+///
+/// They are not built on `zbus::blocking`: each runs its async counterpart
+/// with `block_on` on a tokio runtime the library starts on first use,
+/// multi-threaded with one worker thread named `xdp-screencast`, which also
+/// drives the D-Bus connection between the calls. Calling them from within
+/// a tokio runtime panics, use the async calls there.
+impl ScreenCast {
+    pub fn capabilities_blocking(&mut self) -> Result<Capabilities> {
+        runtime().block_on(self.capabilities())
+    }
+
+    pub fn screencast_blocking(&mut self) -> Result<ActiveSession> {
+        runtime().block_on(self.screencast())
+    }
+
+    pub fn screencast_with_profile_blocking(&mut self, profile: &str) -> Result<ActiveSession> {
+        runtime().block_on(self.screencast_with_profile(profile))
+    }
+
+    pub fn shutdown_blocking(self) -> Result<()> {
+        runtime().block_on(self.shutdown())
+    }
+}
+
+/// Blocking versions of the calls, on the runtime of those of `ScreenCast`.
+impl ActiveSession {
+    pub fn reselect_sources_blocking(
+        &mut self,
+        options: ScreencastOptions,
+    ) -> Result<&[SelectedSource]> {
+        runtime().block_on(self.reselect_sources(options))
+    }
+
+    pub fn recover_blocking(&mut self) -> Result<()> {
+        runtime().block_on(self.recover())
+    }
+
+    pub fn close_blocking(self) -> Result<()> {
+        runtime().block_on(self.close())
+    }
+}
@@ -0,0 +1,175 @@
+use crate::error::{Result, ScreencastError};
+#[cfg(feature = "pipewire")]
+use crate::pipewire::{CaptureOptions, PipeWireCapture};
+use crate::runtime::compat;
+#[cfg(feature = "pipewire")]
+use crate::screencast::SelectedSource;
+use crate::screencast::{
+    ResponseStream, ZBusRequestProxy, check_response, next_handle_token, request_path,
+    sender_path_segment,
+};
+use std::collections::HashMap;
+use std::os::fd::OwnedFd;
+use std::time::Duration;
+use zbus::export::ordered_stream::OrderedStreamExt;
+use zbus::zvariant::{self, OwnedObjectPath, Value};
+use zbus::{Connection, proxy};
+
+/// Webcam access through `org.freedesktop.portal.Camera`. The portal hands
+/// out a PipeWire remote that only exposes camera nodes, e.g. to feed a
+/// `filters::PictureInPicture` next to a screencast.
+#[derive(Debug, Default)]
+pub struct Camera {
+    /// How long to wait for the user to grant access.
+    pub timeout: Option<Duration>,
+
+    dbus_name: String,
+    connection: Option<Connection>,
+    camera_proxy: Option<ZBusCameraProxy<'static>>,
+}
+
+impl Camera {
+    pub fn new() -> Self {
+        Camera::default()
+    }
+
+    pub fn connection(&self) -> Option<&Connection> {
+        self.connection.as_ref()
+    }
+
+    pub fn camera_proxy(&self) -> Option<&ZBusCameraProxy<'static>> {
+        self.camera_proxy.as_ref()
+    }
+
+    pub async fn connect(&mut self) -> Result<()> {
+        compat(async {
+            if self.connection.is_some() {
+                return Ok(());
+            }
+            let connection = Connection::session().await?;
+            self.dbus_name = connection
+                .unique_name()
+                .ok_or(ScreencastError::InvalidResponse(
+                    "connection: fail to get unique name".to_string(),
+                ))
+                .map(|n| sender_path_segment(n.as_str()))?;
+            self.camera_proxy = Some(ZBusCameraProxy::new(&connection).await?);
+            self.connection = Some(connection);
+            Ok(())
+        })
+        .await
+    }
+
+    /// Whether the portal knows of any camera, without asking the user.
+    pub async fn is_present(&mut self) -> Result<bool> {
+        compat(async {
+            self.connect().await?;
+            Ok(self
+                .camera_proxy
+                .as_ref()
+                .unwrap()
+                .is_camera_present()
+                .await?)
+        })
+        .await
+    }
+
+    /// Asks the user for camera access, the portal remembers the answer for
+    /// the application.
+    pub async fn access(&mut self) -> Result<()> {
+        compat(async {
+            self.connect().await?;
+            let connection = self.connection.as_ref().unwrap();
+            let handle_token = next_handle_token();
+            let expected_path = request_path(&self.dbus_name, &handle_token)?;
+            let mut response_stream = receive_response(connection, expected_path.clone()).await?;
+            let mut payload = HashMap::with_capacity(1);
+            let handle_token_value = Value::new(handle_token.as_str());
+            payload.insert("handle_token", &handle_token_value);
+            let returned_path = self
+                .camera_proxy
+                .as_ref()
+                .unwrap()
+                .access_camera(&payload)
+                .await?;
+            if returned_path != expected_path {
+                response_stream = receive_response(connection, returned_path).await?;
+            }
+            let response = async {
+                response_stream
+                    .next()
+                    .await
+                    .ok_or(ScreencastError::SessionClosed)
+            };
+            let response = match self.timeout {
+                Some(timeout) => tokio::time::timeout(timeout, response)
+                    .await
+                    .unwrap_or(Err(ScreencastError::Timeout))?,
+                None => response.await?,
+            };
+            check_response(response.args()?.response)
+        })
+        .await
+    }
+
+    /// The PipeWire remote with the camera nodes, `access` must have been
+    /// granted first.
+    pub async fn open_pipewire_remote(&mut self) -> Result<OwnedFd> {
+        compat(async {
+            self.connect().await?;
+            let fd = self
+                .camera_proxy
+                .as_ref()
+                .unwrap()
+                .open_pipe_wire_remote(&HashMap::new())
+                .await?;
+            Ok(fd.into())
+        })
+        .await
+    }
+
+    /// Asks for access and captures the default camera. Most webcams only
+    /// deliver YUY2 or NV12, which `options.pixel_formats` has to allow.
+    #[cfg(feature = "pipewire")]
+    pub async fn capture(&mut self, options: &CaptureOptions) -> Result<PipeWireCapture> {
+        self.access().await?;
+        let fd = self.open_pipewire_remote().await?;
+        PipeWireCapture::connect(fd, &[SelectedSource::any()], options)
+    }
+}
+
+async fn receive_response(
+    connection: &Connection,
+    request_path: OwnedObjectPath,
+) -> Result<ResponseStream> {
+    let request_proxy = ZBusRequestProxy::builder(connection)
+        .path(request_path)?
+        .build()
+        .await?;
+    Ok(request_proxy.receive_response().await?)
+}
+
+/// `org.freedesktop.portal.Camera`, see `xml/org.freedesktop.portal.Camera.xml`.
+#[proxy(
+    interface = "org.freedesktop.portal.Camera",
+    default_service = "org.freedesktop.portal.Desktop",
+    default_path = "/org/freedesktop/portal/desktop"
+)]
+pub trait ZBusCamera {
+    /// AccessCamera method
+    fn access_camera(&self, options: &HashMap<&str, &Value<'_>>) -> zbus::Result<OwnedObjectPath>;
+
+    /// OpenPipeWireRemote method
+    fn open_pipe_wire_remote(
+        &self,
+        options: &HashMap<&str, &Value<'_>>,
+    ) -> zbus::Result<zvariant::OwnedFd>;
+
+    /// IsCameraPresent property
+    #[zbus(property)]
+    fn is_camera_present(&self) -> zbus::Result<bool>;
+
+    /// version property
+    #[zbus(property, name = "version")]
+    fn version(&self) -> zbus::Result<u32>;
+}
@@ -0,0 +1,74 @@
+use crate::error::Result;
+use crate::runtime::compat;
+use crate::screencast::ZBusRequestProxy;
+use event_listener::Event;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use zbus::Connection;
+use zbus::zvariant::OwnedObjectPath;
+
+/// Aborts a `ScreenCast::screencast()` call that is waiting on a portal dialog.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    inner: Arc<CancelState>,
+}
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    event: Event,
+    pending: Mutex<Option<(Connection, OwnedObjectPath)>>,
+}
+
+impl CancelHandle {
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    pub async fn cancel(&self) -> Result<()> {
+        compat(async {
+            self.inner.cancelled.store(true, Ordering::Release);
+            self.inner.event.notify(usize::MAX);
+            self.close_pending().await
+        })
+        .await
+    }
+
+    pub(crate) async fn close_pending(&self) -> Result<()> {
+        let pending = self.inner.pending.lock().unwrap().take();
+        if let Some((connection, request_path)) = pending {
+            let request_proxy = ZBusRequestProxy::builder(&connection)
+                .path(request_path)?
+                .build()
+                .await?;
+            request_proxy.close().await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn reset(&self) {
+        self.inner.cancelled.store(false, Ordering::Release);
+        self.inner.pending.lock().unwrap().take();
+    }
+
+    pub(crate) fn set_pending(&self, connection: &Connection, request_path: OwnedObjectPath) {
+        *self.inner.pending.lock().unwrap() = Some((connection.clone(), request_path));
+    }
+
+    pub(crate) fn clear_pending(&self) {
+        self.inner.pending.lock().unwrap().take();
+    }
+
+    pub(crate) async fn cancelled(&self) {
+        loop {
+            if self.is_cancelled() {
+                return;
+            }
+            let listener = self.inner.event.listen();
+            if self.is_cancelled() {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
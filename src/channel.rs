@@ -0,0 +1,193 @@
+use crate::pipewire::BackpressurePolicy;
+use event_listener::{Event, EventListener, Listener};
+use futures_core::Stream;
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// A bounded queue used to hand buffers from the PipeWire loop thread to
+/// either async or blocking consumers, `policy` decides what a full queue does.
+pub(crate) fn bounded<T>(capacity: usize, policy: BackpressurePolicy) -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity,
+        policy,
+        senders: AtomicUsize::new(1),
+        receiver_closed: AtomicBool::new(false),
+        dropped: AtomicU64::new(0),
+        event: Event::new(),
+        space: Event::new(),
+    });
+    (
+        Sender {
+            shared: shared.clone(),
+        },
+        Receiver {
+            shared,
+            listener: None,
+        },
+    )
+}
+
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    policy: BackpressurePolicy,
+    senders: AtomicUsize,
+    receiver_closed: AtomicBool,
+    dropped: AtomicU64,
+    event: Event,
+    space: Event,
+}
+
+impl<T> Shared<T> {
+    fn is_closed(&self) -> bool {
+        self.senders.load(Ordering::Acquire) == 0
+    }
+}
+
+pub(crate) struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Queues the value, dropping a value or blocking when the queue is full.
+    /// Returns false when a value was dropped.
+    pub(crate) fn send(&self, value: T) -> bool {
+        let mut kept = true;
+        loop {
+            let mut queue = self.shared.queue.lock().unwrap();
+            if self.shared.receiver_closed.load(Ordering::Acquire) {
+                return true;
+            }
+            if queue.len() < self.shared.capacity {
+                queue.push_back(value);
+                break;
+            }
+            match self.shared.policy {
+                BackpressurePolicy::DropNewest => {
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                BackpressurePolicy::DropOldest => {
+                    queue.pop_front();
+                    queue.push_back(value);
+                    self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                    kept = false;
+                    break;
+                }
+                BackpressurePolicy::Block => {
+                    let listener = self.shared.space.listen();
+                    drop(queue);
+                    listener.wait();
+                }
+            }
+        }
+        self.shared.event.notify(1);
+        kept
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Sender {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        if self.shared.senders.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.shared.event.notify(usize::MAX);
+        }
+    }
+}
+
+pub(crate) struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    listener: Option<EventListener>,
+}
+
+impl<T> Receiver<T> {
+    pub(crate) fn try_recv(&self) -> Option<T> {
+        let value = self.shared.queue.lock().unwrap().pop_front();
+        if value.is_some() {
+            self.shared.space.notify(1);
+        }
+        value
+    }
+
+    /// Values dropped because the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Wakes a blocked sender and makes further sends no-ops.
+    pub(crate) fn close(&self) {
+        self.shared.receiver_closed.store(true, Ordering::Release);
+        self.shared.space.notify(usize::MAX);
+    }
+
+    /// Blocks the current thread, `None` once every sender is gone.
+    pub(crate) fn recv_blocking(&self) -> Option<T> {
+        loop {
+            if let Some(value) = self.try_recv() {
+                return Some(value);
+            }
+            if self.shared.is_closed() {
+                return None;
+            }
+            let listener = self.shared.event.listen();
+            if let Some(value) = self.try_recv() {
+                return Some(value);
+            }
+            if self.shared.is_closed() {
+                return None;
+            }
+            listener.wait();
+        }
+    }
+}
+
+impl<T> Stream for Receiver<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            if let Some(value) = self.try_recv() {
+                self.listener = None;
+                return Poll::Ready(Some(value));
+            }
+            if self.shared.is_closed() {
+                self.listener = None;
+                return Poll::Ready(None);
+            }
+            match self.listener.as_mut() {
+                None => self.listener = Some(self.shared.event.listen()),
+                Some(listener) => match Pin::new(listener).poll(cx) {
+                    Poll::Ready(()) => self.listener = None,
+                    Poll::Pending => return Poll::Pending,
+                },
+            }
+        }
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl<T> std::fmt::Debug for Receiver<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("len", &self.shared.queue.lock().unwrap().len())
+            .field("dropped", &self.dropped())
+            .finish()
+    }
+}
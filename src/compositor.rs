@@ -0,0 +1,381 @@
+use crate::convert;
+use crate::error::Result;
+use crate::filters::{Scale, ScaleFilter};
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane, Rect};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Where the sources go on the canvas, in the order they are first seen or
+/// set with `Compositor::with_order`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Layout {
+    SideBySide,
+    Stacked,
+    /// `0` columns picks a near square grid.
+    Grid {
+        columns: u32,
+    },
+    /// One rectangle per source, sources without one are not drawn.
+    Custom(Vec<Rect>),
+}
+
+#[derive(Debug)]
+struct Slot {
+    node_id: u32,
+    /// The last frame converted to `Bgrx`.
+    source: Option<Frame<'static>>,
+    /// `source` scaled to fit the slot's rectangle.
+    frame: Option<Frame<'static>>,
+}
+
+/// Blits the latest frame of every source onto one canvas, letterboxed in
+/// its layout rectangle.
+#[derive(Debug)]
+pub struct Compositor {
+    width: u32,
+    height: u32,
+    layout: Layout,
+    pixel_format: PixelFormat,
+    background: [u8; 3],
+    filter: ScaleFilter,
+    slots: Vec<Slot>,
+}
+
+impl Compositor {
+    pub fn new(width: u32, height: u32, layout: Layout) -> Self {
+        Compositor {
+            width,
+            height,
+            layout,
+            pixel_format: PixelFormat::Bgrx,
+            background: [0, 0, 0],
+            filter: ScaleFilter::Bilinear,
+            slots: Vec::new(),
+        }
+    }
+
+    /// Pixel format of the composed frames, `Bgrx` by default.
+    pub fn with_pixel_format(mut self, pixel_format: PixelFormat) -> Self {
+        self.pixel_format = pixel_format;
+        self
+    }
+
+    /// RGB color of the canvas around and between the sources.
+    pub fn with_background(mut self, background: [u8; 3]) -> Self {
+        self.background = background;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ScaleFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Fixes the slot of each source by PipeWire node id.
+    pub fn with_order(mut self, node_ids: &[u32]) -> Self {
+        self.slots = node_ids
+            .iter()
+            .map(|node_id| Slot {
+                node_id: *node_id,
+                source: None,
+                frame: None,
+            })
+            .collect();
+        self
+    }
+
+    pub fn layout(&self) -> &Layout {
+        &self.layout
+    }
+
+    /// Rectangles of the slots on the canvas.
+    pub fn rects(&self) -> Vec<Rect> {
+        let count = self.slots.len().max(1) as u32;
+        let (columns, rows) = match &self.layout {
+            Layout::SideBySide => (count, 1),
+            Layout::Stacked => (1, count),
+            Layout::Grid { columns } => {
+                let columns = match columns {
+                    0 => (count as f32).sqrt().ceil() as u32,
+                    columns => *columns,
+                };
+                (columns, count.div_ceil(columns))
+            }
+            Layout::Custom(rects) => return rects.clone(),
+        };
+        let (cell_width, cell_height) = (self.width / columns, self.height / rows);
+        (0..count)
+            .map(|index| {
+                Rect::new(
+                    ((index % columns) * cell_width) as i32,
+                    ((index / columns) * cell_height) as i32,
+                    cell_width,
+                    cell_height,
+                )
+            })
+            .collect()
+    }
+
+    /// Stores the frame as the latest of its source, frames of new sources
+    /// take the next free slot.
+    pub fn push(&mut self, frame: &Frame<'_>) -> Result<()> {
+        let source = convert::convert(frame, PixelFormat::Bgrx)?;
+        match self
+            .slots
+            .iter()
+            .position(|slot| slot.node_id == frame.node_id())
+        {
+            Some(index) => {
+                self.slots[index].source = Some(source);
+                self.fit_slot(index, self.rects().get(index).copied());
+            }
+            None => {
+                self.slots.push(Slot {
+                    node_id: frame.node_id(),
+                    source: Some(source),
+                    frame: None,
+                });
+                // the rectangles of every slot change with the count
+                let rects = self.rects();
+                for index in 0..self.slots.len() {
+                    self.fit_slot(index, rects.get(index).copied());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn fit_slot(&mut self, index: usize, rect: Option<Rect>) {
+        let slot = &mut self.slots[index];
+        slot.frame = match (&slot.source, rect) {
+            (Some(source), Some(rect)) => {
+                let (width, height) = fit(source.width(), source.height(), rect.width, rect.height);
+                Some(
+                    Scale::new(width, height)
+                        .with_filter(self.filter)
+                        .apply(source.clone()),
+                )
+            }
+            _ => None,
+        };
+    }
+
+    /// Draws the canvas, keeping the timing of the newest source frame.
+    pub fn compose(&self) -> Result<Frame<'static>> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        let stride = width * 4;
+        let [r, g, b] = self.background;
+        let mut data = [b, g, r, 255].repeat(width * height);
+        let mut newest: Option<&Frame<'static>> = None;
+        for (slot, rect) in self.slots.iter().zip(self.rects()) {
+            let Some(frame) = &slot.frame else {
+                continue;
+            };
+            if newest.is_none_or(|newest| frame.pts() > newest.pts()) {
+                newest = Some(frame);
+            }
+            let left = rect.x as i64 + (rect.width as i64 - frame.width() as i64) / 2;
+            let top = rect.y as i64 + (rect.height as i64 - frame.height() as i64) / 2;
+            for row in 0..frame.height() as i64 {
+                let y = top + row;
+                if y < 0 || y >= height as i64 {
+                    continue;
+                }
+                let first = left.max(0);
+                let last = (left + frame.width() as i64).min(width as i64);
+                if first >= last {
+                    continue;
+                }
+                let src_start = row as usize * frame.stride() + (first - left) as usize * 4;
+                let len = (last - first) as usize * 4;
+                let Some(src) = frame.data().get(src_start..src_start + len) else {
+                    continue;
+                };
+                let dst_start = y as usize * stride + first as usize * 4;
+                data[dst_start..dst_start + len].copy_from_slice(src);
+            }
+        }
+        let mut canvas = Frame::new(
+            PixelFormat::Bgrx,
+            self.width,
+            self.height,
+            vec![Plane { offset: 0, stride }],
+            data,
+        );
+        if let Some(newest) = newest {
+            canvas = canvas.with_sequence(newest.sequence());
+            if let Some(pts) = newest.pts() {
+                canvas = canvas.with_pts(pts);
+            }
+        }
+        match self.pixel_format {
+            PixelFormat::Bgrx => Ok(canvas),
+            pixel_format => convert::convert(&canvas, pixel_format),
+        }
+    }
+
+    /// A stream yielding a composed frame for every frame of `frames`, e.g.
+    /// the interleaved sources of a `PipeWireCapture`.
+    pub fn compose_stream<S>(self, frames: S) -> Composited<S>
+    where
+        S: Stream<Item = Frame<'static>> + Unpin,
+    {
+        Composited {
+            compositor: self,
+            frames,
+        }
+    }
+}
+
+/// Largest size with the aspect ratio of `width` x `height` inside the box.
+fn fit(width: u32, height: u32, box_width: u32, box_height: u32) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (box_width, box_height);
+    }
+    let scale = (box_width as f64 / width as f64).min(box_height as f64 / height as f64);
+    (
+        ((width as f64 * scale).round() as u32).clamp(1, box_width.max(1)),
+        ((height as f64 * scale).round() as u32).clamp(1, box_height.max(1)),
+    )
+}
+
+/// See `Compositor::compose_stream`, frames that fail to convert are skipped.
+#[derive(Debug)]
+pub struct Composited<S> {
+    compositor: Compositor,
+    frames: S,
+}
+
+impl<S> Composited<S> {
+    pub fn compositor(&mut self) -> &mut Compositor {
+        &mut self.compositor
+    }
+}
+
+impl<S> Stream for Composited<S>
+where
+    S: Stream<Item = Frame<'static>> + Unpin,
+{
+    type Item = Frame<'static>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame<'static>>> {
+        loop {
+            let Some(frame) = std::task::ready!(Pin::new(&mut self.frames).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            if self.compositor.push(&frame).is_err() {
+                continue;
+            }
+            if let Ok(canvas) = self.compositor.compose() {
+                return Poll::Ready(Some(canvas));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_lite::{StreamExt, future, stream};
+    use std::time::Duration;
+
+    fn solid(node_id: u32, width: u32, height: u32, bgr: [u8; 3]) -> Frame<'static> {
+        let [b, g, r] = bgr;
+        let data = [b, g, r, 255].repeat((width * height) as usize);
+        Frame::packed(PixelFormat::Bgrx, width, height, width as usize * 4, data)
+            .with_node_id(node_id)
+    }
+
+    /// The BGR of each canvas pixel, row by row.
+    fn pixels(frame: &Frame<'_>) -> Vec<[u8; 3]> {
+        frame
+            .data()
+            .chunks(4)
+            .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+            .collect()
+    }
+
+    #[test]
+    fn layouts() {
+        let rects = |layout, count: u32| {
+            let order: Vec<u32> = (0..count).collect();
+            Compositor::new(300, 200, layout).with_order(&order).rects()
+        };
+        assert_eq!(
+            rects(Layout::SideBySide, 3),
+            [
+                Rect::new(0, 0, 100, 200),
+                Rect::new(100, 0, 100, 200),
+                Rect::new(200, 0, 100, 200),
+            ]
+        );
+        assert_eq!(
+            rects(Layout::Stacked, 2),
+            [Rect::new(0, 0, 300, 100), Rect::new(0, 100, 300, 100)]
+        );
+        let grid = rects(Layout::Grid { columns: 0 }, 5);
+        assert_eq!(grid.len(), 5);
+        assert_eq!(grid[2], Rect::new(200, 0, 100, 100));
+        assert_eq!(grid[4], Rect::new(100, 100, 100, 100));
+        assert_eq!(
+            rects(Layout::Grid { columns: 2 }, 3)[2],
+            Rect::new(0, 100, 150, 100)
+        );
+        let custom = vec![Rect::new(5, 5, 10, 10)];
+        assert_eq!(rects(Layout::Custom(custom.clone()), 3), custom);
+        // one slot before any source arrived
+        assert_eq!(rects(Layout::SideBySide, 0), [Rect::new(0, 0, 300, 200)]);
+    }
+
+    #[test]
+    fn letterboxed() {
+        let (red, blue, gray) = ([0, 0, 255], [255, 0, 0], [30, 20, 10]);
+        let mut compositor = Compositor::new(4, 2, Layout::SideBySide)
+            .with_background([10, 20, 30])
+            .with_filter(ScaleFilter::Nearest);
+        compositor
+            .push(&solid(7, 4, 4, red).with_pts(Duration::from_millis(10)))
+            .unwrap();
+        let canvas = compositor.compose().unwrap();
+        assert_eq!(canvas.pts(), Some(Duration::from_millis(10)));
+        #[rustfmt::skip]
+        assert_eq!(pixels(&canvas), [gray, red, red, gray, gray, red, red, gray]);
+
+        // a second source splits the canvas, the frame of a known one is
+        // replaced
+        compositor
+            .push(&solid(3, 2, 1, blue).with_pts(Duration::from_millis(20)))
+            .unwrap();
+        compositor.push(&solid(7, 2, 2, blue)).unwrap();
+        let canvas = compositor.compose().unwrap();
+        assert_eq!(canvas.pts(), Some(Duration::from_millis(20)));
+        #[rustfmt::skip]
+        assert_eq!(pixels(&canvas), [blue, blue, blue, blue, blue, blue, gray, gray]);
+    }
+
+    #[test]
+    fn ordered_sources() {
+        let (red, blue) = ([0, 0, 255], [255, 0, 0]);
+        let mut compositor = Compositor::new(2, 1, Layout::SideBySide)
+            .with_order(&[2, 1])
+            .with_pixel_format(PixelFormat::Rgba);
+        compositor.push(&solid(1, 1, 1, red)).unwrap();
+        let canvas = compositor.compose().unwrap();
+        assert_eq!(canvas.pixel_format(), PixelFormat::Rgba);
+        assert_eq!(canvas.data(), [0, 0, 0, 255, 255, 0, 0, 255]);
+        compositor.push(&solid(2, 1, 1, blue)).unwrap();
+        let canvas = compositor.compose().unwrap();
+        assert_eq!(canvas.data(), [0, 0, 255, 255, 255, 0, 0, 255]);
+    }
+
+    #[test]
+    fn composes_every_frame() {
+        let frames = stream::iter([solid(1, 1, 1, [1, 2, 3]), solid(2, 1, 1, [4, 5, 6])]);
+        let composited = Compositor::new(2, 1, Layout::SideBySide).compose_stream(frames);
+        let canvases: Vec<_> = future::block_on(composited.collect());
+        assert_eq!(canvases.len(), 2);
+        assert_eq!(pixels(&canvases[1]), [[1, 2, 3], [4, 5, 6]]);
+    }
+}
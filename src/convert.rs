@@ -0,0 +1,465 @@
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane};
+
+/// Converts a memory frame into `to`, returning an owned frame that keeps
+/// the timing of the input. YUV uses BT.601 limited range, what most
+/// encoders assume for untagged input.
+pub fn convert(frame: &Frame<'_>, to: PixelFormat) -> Result<Frame<'static>> {
+    if frame.is_dmabuf() {
+        return Err(ScreencastError::Unsupported(
+            "converting dmabuf frames".to_string(),
+        ));
+    }
+    let from = frame.pixel_format();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let (planes, data) = match (from, to) {
+        (PixelFormat::Yuy2, PixelFormat::Yuy2) => {
+            let stride = width.next_multiple_of(2) * 2;
+            let mut out = Vec::with_capacity(stride * height);
+            for y in 0..height {
+                out.extend_from_slice(row(frame, 0, y, stride)?);
+            }
+            (vec![Plane { offset: 0, stride }], out)
+        }
+        (PixelFormat::Yuy2, _) if !to.is_planar() => yuv_to_rgb(frame, to)?,
+        (_, PixelFormat::Yuy2) if !from.is_planar() => rgb_to_yuy2(frame)?,
+        (PixelFormat::Yuy2, _) | (_, PixelFormat::Yuy2) => {
+            return convert(&convert(frame, PixelFormat::Rgbx)?, to);
+        }
+        _ => convert_rgb_yuv(frame, to)?,
+    };
+    Ok(frame.with_image(to, width as u32, height as u32, planes, data))
+}
+
+/// Conversions between packed RGB, NV12 and I420.
+fn convert_rgb_yuv(frame: &Frame<'_>, to: PixelFormat) -> Result<(Vec<Plane>, Vec<u8>)> {
+    let from = frame.pixel_format();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    Ok(match (from.is_planar(), to.is_planar()) {
+        (false, false) => {
+            let stride = width * to.bytes_per_pixel();
+            let mut out = vec![0u8; stride * height];
+            for y in 0..height {
+                let src = row(frame, 0, y, width * from.bytes_per_pixel())?;
+                swizzle_row(src, from, &mut out[y * stride..(y + 1) * stride], to);
+            }
+            (vec![Plane { offset: 0, stride }], out)
+        }
+        (false, true) => rgb_to_yuv(frame, to)?,
+        (true, false) => yuv_to_rgb(frame, to)?,
+        (true, true) => yuv_to_yuv(frame, to)?,
+    })
+}
+
+fn row<'f>(frame: &'f Frame<'_>, plane: usize, y: usize, len: usize) -> Result<&'f [u8]> {
+    let layout = frame
+        .planes()
+        .get(plane)
+        .ok_or_else(|| ScreencastError::Unsupported("missing plane".to_string()))?;
+    let start = layout.offset + y * layout.stride;
+    frame
+        .data()
+        .get(start..start + len)
+        .ok_or_else(|| ScreencastError::Unsupported("frame data is truncated".to_string()))
+}
+
+/// Converts one row of packed pixels, missing alpha becomes opaque.
+fn swizzle_row(src: &[u8], from: PixelFormat, dst: &mut [u8], to: PixelFormat) {
+    let (Some(src_offsets), Some(dst_offsets)) = (from.rgba_offsets(), to.rgba_offsets()) else {
+        return;
+    };
+    let (src_bpp, dst_bpp) = (from.bytes_per_pixel(), to.bytes_per_pixel());
+    if src_bpp == 4 && dst_bpp == 4 {
+        let (shuffle, fill) = shuffle_mask(src_offsets, dst_offsets);
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("ssse3") {
+            // SAFETY: ssse3 support was checked at runtime
+            unsafe { simd::shuffle_row(src, dst, shuffle, fill) };
+            return;
+        }
+        shuffle_row(src, dst, shuffle, fill);
+        return;
+    }
+    for (src, dst) in src.chunks_exact(src_bpp).zip(dst.chunks_exact_mut(dst_bpp)) {
+        for channel in 0..3 {
+            dst[dst_offsets[channel]] = src[src_offsets[channel]];
+        }
+        if dst_bpp == 4 {
+            dst[alpha_slot(to)] = match to.has_alpha() {
+                true => src.get(src_offsets[3]).copied().unwrap_or(255),
+                false => 255,
+            };
+        }
+    }
+}
+
+/// The byte of a 4 byte pixel not taken by a color channel.
+fn alpha_slot(pixel_format: PixelFormat) -> usize {
+    let offsets = pixel_format.rgba_offsets().unwrap_or([0, 1, 2, 3]);
+    (0..4)
+        .find(|slot| !offsets[..3].contains(slot))
+        .unwrap_or(3)
+}
+
+/// For every destination byte the source byte to copy, `0x80` where the
+/// byte is filled with `0xff` instead.
+fn shuffle_mask(src_offsets: [usize; 4], dst_offsets: [usize; 4]) -> ([u8; 4], [u8; 4]) {
+    let mut shuffle = [0x80u8; 4];
+    let mut fill = [0xffu8; 4];
+    for channel in 0..3 {
+        shuffle[dst_offsets[channel]] = src_offsets[channel] as u8;
+        fill[dst_offsets[channel]] = 0;
+    }
+    let src_alpha = (0..4).find(|slot| !src_offsets[..3].contains(slot));
+    let dst_alpha = (0..4).find(|slot| !dst_offsets[..3].contains(slot));
+    if let (Some(src_alpha), Some(dst_alpha)) = (src_alpha, dst_alpha)
+        && src_offsets[3] != usize::MAX
+        && dst_offsets[3] != usize::MAX
+    {
+        shuffle[dst_alpha] = src_alpha as u8;
+        fill[dst_alpha] = 0;
+    }
+    (shuffle, fill)
+}
+
+fn shuffle_row(src: &[u8], dst: &mut [u8], shuffle: [u8; 4], fill: [u8; 4]) {
+    for (src, dst) in src.chunks_exact(4).zip(dst.chunks_exact_mut(4)) {
+        for byte in 0..4 {
+            dst[byte] = match shuffle[byte] {
+                0x80 => 0,
+                index => src[index as usize],
+            } | fill[byte];
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    #[target_feature(enable = "ssse3")]
+    pub(super) unsafe fn shuffle_row(src: &[u8], dst: &mut [u8], shuffle: [u8; 4], fill: [u8; 4]) {
+        let len = src.len().min(dst.len()) / 16 * 16;
+        let mut mask = [0u8; 16];
+        let mut or = [0u8; 16];
+        for pixel in 0..4 {
+            for byte in 0..4 {
+                mask[pixel * 4 + byte] = match shuffle[byte] {
+                    0x80 => 0x80,
+                    index => (pixel * 4) as u8 + index,
+                };
+                or[pixel * 4 + byte] = fill[byte];
+            }
+        }
+        unsafe {
+            let mask = _mm_loadu_si128(mask.as_ptr().cast());
+            let or = _mm_loadu_si128(or.as_ptr().cast());
+            for offset in (0..len).step_by(16) {
+                let pixels = _mm_loadu_si128(src.as_ptr().add(offset).cast());
+                let pixels = _mm_or_si128(_mm_shuffle_epi8(pixels, mask), or);
+                _mm_storeu_si128(dst.as_mut_ptr().add(offset).cast(), pixels);
+            }
+        }
+        super::shuffle_row(&src[len..], &mut dst[len..], shuffle, fill);
+    }
+}
+
+fn rgb_to_yuv(frame: &Frame<'_>, to: PixelFormat) -> Result<(Vec<Plane>, Vec<u8>)> {
+    let from = frame.pixel_format();
+    let offsets = from.rgba_offsets().unwrap_or([0, 1, 2, 3]);
+    let bpp = from.bytes_per_pixel();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let y_stride = width.next_multiple_of(2);
+    let planes = Plane::contiguous(to, height as u32, y_stride);
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let mut out = vec![0u8; planar_len(&planes, chroma_height)];
+    let pixel = |src: &[u8], x: usize| -> (i32, i32, i32) {
+        let base = x * bpp;
+        (
+            src[base + offsets[0]] as i32,
+            src[base + offsets[1]] as i32,
+            src[base + offsets[2]] as i32,
+        )
+    };
+    for y in 0..height {
+        let src = row(frame, 0, y, width * bpp)?;
+        for x in 0..width {
+            let (r, g, b) = pixel(src, x);
+            out[y * y_stride + x] = luma(r, g, b);
+        }
+    }
+    for cy in 0..chroma_height {
+        let top = row(frame, 0, cy * 2, width * bpp)?;
+        let bottom = row(frame, 0, (cy * 2 + 1).min(height - 1), width * bpp)?;
+        for cx in 0..chroma_width {
+            let (x0, x1) = (cx * 2, (cx * 2 + 1).min(width - 1));
+            let samples = [
+                pixel(top, x0),
+                pixel(top, x1),
+                pixel(bottom, x0),
+                pixel(bottom, x1),
+            ];
+            let r = samples.iter().map(|sample| sample.0).sum::<i32>() / 4;
+            let g = samples.iter().map(|sample| sample.1).sum::<i32>() / 4;
+            let b = samples.iter().map(|sample| sample.2).sum::<i32>() / 4;
+            let (u, v) = chroma(r, g, b);
+            match to {
+                PixelFormat::Nv12 => {
+                    let index = planes[1].offset + cy * planes[1].stride + cx * 2;
+                    out[index] = u;
+                    out[index + 1] = v;
+                }
+                _ => {
+                    out[planes[1].offset + cy * planes[1].stride + cx] = u;
+                    out[planes[2].offset + cy * planes[2].stride + cx] = v;
+                }
+            }
+        }
+    }
+    Ok((planes, out))
+}
+
+/// Copies the planes of NV12 or I420 into either, the chroma interleaved
+/// or split up as it goes, with no trip through RGB.
+fn yuv_to_yuv(frame: &Frame<'_>, to: PixelFormat) -> Result<(Vec<Plane>, Vec<u8>)> {
+    let from = frame.pixel_format();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let y_stride = width.next_multiple_of(2);
+    let planes = Plane::contiguous(to, height as u32, y_stride);
+    let chroma_width = width.div_ceil(2);
+    let chroma_height = height.div_ceil(2);
+    let mut out = vec![0u8; planar_len(&planes, chroma_height)];
+    for y in 0..height {
+        out[y * y_stride..y * y_stride + width].copy_from_slice(row(frame, 0, y, width)?);
+    }
+    for cy in 0..chroma_height {
+        let start = |plane: &Plane| plane.offset + cy * plane.stride;
+        match (from, to) {
+            (PixelFormat::Nv12, PixelFormat::Nv12) => {
+                let uv = row(frame, 1, cy, chroma_width * 2)?;
+                out[start(&planes[1])..][..chroma_width * 2].copy_from_slice(uv);
+            }
+            (PixelFormat::Nv12, _) => {
+                let uv = row(frame, 1, cy, chroma_width * 2)?;
+                let (u, v) = (start(&planes[1]), start(&planes[2]));
+                for (cx, pair) in uv.chunks_exact(2).enumerate() {
+                    out[u + cx] = pair[0];
+                    out[v + cx] = pair[1];
+                }
+            }
+            (_, PixelFormat::Nv12) => {
+                let u = row(frame, 1, cy, chroma_width)?;
+                let v = row(frame, 2, cy, chroma_width)?;
+                let uv = &mut out[start(&planes[1])..][..chroma_width * 2];
+                for (pair, (u, v)) in uv.chunks_exact_mut(2).zip(u.iter().zip(v)) {
+                    pair.copy_from_slice(&[*u, *v]);
+                }
+            }
+            _ => {
+                for plane in 1..3 {
+                    let chroma = row(frame, plane, cy, chroma_width)?;
+                    out[start(&planes[plane])..][..chroma_width].copy_from_slice(chroma);
+                }
+            }
+        }
+    }
+    Ok((planes, out))
+}
+
+/// The bytes of the planes of `Plane::contiguous` for NV12 or I420.
+fn planar_len(planes: &[Plane], chroma_height: usize) -> usize {
+    let chroma = &planes[planes.len() - 1];
+    chroma.offset + chroma.stride * chroma_height
+}
+
+fn rgb_to_yuy2(frame: &Frame<'_>) -> Result<(Vec<Plane>, Vec<u8>)> {
+    let from = frame.pixel_format();
+    let offsets = from.rgba_offsets().unwrap_or([0, 1, 2, 3]);
+    let bpp = from.bytes_per_pixel();
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let stride = width.next_multiple_of(2) * 2;
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let src = row(frame, 0, y, width * bpp)?;
+        let pixel = |x: usize| -> (i32, i32, i32) {
+            let base = x.min(width - 1) * bpp;
+            (
+                src[base + offsets[0]] as i32,
+                src[base + offsets[1]] as i32,
+                src[base + offsets[2]] as i32,
+            )
+        };
+        for pair in 0..width.div_ceil(2) {
+            let (left, right) = (pixel(pair * 2), pixel(pair * 2 + 1));
+            let (u, v) = chroma(
+                (left.0 + right.0) / 2,
+                (left.1 + right.1) / 2,
+                (left.2 + right.2) / 2,
+            );
+            let index = y * stride + pair * 4;
+            out[index..index + 4].copy_from_slice(&[
+                luma(left.0, left.1, left.2),
+                u,
+                luma(right.0, right.1, right.2),
+                v,
+            ]);
+        }
+    }
+    Ok((vec![Plane { offset: 0, stride }], out))
+}
+
+fn luma(r: i32, g: i32, b: i32) -> u8 {
+    (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8
+}
+
+fn chroma(r: i32, g: i32, b: i32) -> (u8, u8) {
+    (
+        (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+        (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+    )
+}
+
+fn yuv_to_rgb(frame: &Frame<'_>, to: PixelFormat) -> Result<(Vec<Plane>, Vec<u8>)> {
+    let from = frame.pixel_format();
+    let offsets = to.rgba_offsets().unwrap_or([0, 1, 2, 3]);
+    let bpp = to.bytes_per_pixel();
+    let fill = alpha_slot(to);
+    let (width, height) = (frame.width() as usize, frame.height() as usize);
+    let chroma_width = width.div_ceil(2);
+    let stride = width * bpp;
+    let mut out = vec![0u8; stride * height];
+    for y in 0..height {
+        let (y_row, u_row, v_row) = match from {
+            PixelFormat::Nv12 => {
+                let uv = row(frame, 1, y / 2, chroma_width * 2)?;
+                (row(frame, 0, y, width)?, uv, uv)
+            }
+            PixelFormat::Yuy2 => {
+                let packed = row(frame, 0, y, chroma_width * 4)?;
+                (packed, packed, packed)
+            }
+            _ => (
+                row(frame, 0, y, width)?,
+                row(frame, 1, y / 2, chroma_width)?,
+                row(frame, 2, y / 2, chroma_width)?,
+            ),
+        };
+        for x in 0..width {
+            let (luma, u, v) = match from {
+                PixelFormat::Nv12 => (y_row[x], u_row[x / 2 * 2], v_row[x / 2 * 2 + 1]),
+                PixelFormat::Yuy2 => (y_row[x * 2], u_row[x / 2 * 4 + 1], v_row[x / 2 * 4 + 3]),
+                _ => (y_row[x], u_row[x / 2], v_row[x / 2]),
+            };
+            let c = luma as i32 - 16;
+            let d = u as i32 - 128;
+            let e = v as i32 - 128;
+            let r = ((298 * c + 409 * e + 128) >> 8).clamp(0, 255) as u8;
+            let g = ((298 * c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u8;
+            let b = ((298 * c + 516 * d + 128) >> 8).clamp(0, 255) as u8;
+            let base = y * stride + x * bpp;
+            if bpp == 4 {
+                out[base + fill] = 255;
+            }
+            out[base + offsets[0]] = r;
+            out[base + offsets[1]] = g;
+            out[base + offsets[2]] = b;
+        }
+    }
+    Ok((vec![Plane { offset: 0, stride }], out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bgra(pixels: &[[u8; 3]], width: u32) -> Frame<'static> {
+        let data: Vec<u8> = pixels
+            .iter()
+            .flat_map(|&[r, g, b]| [b, g, r, 255])
+            .collect();
+        let height = pixels.len() as u32 / width;
+        Frame::packed(PixelFormat::Bgra, width, height, width as usize * 4, data)
+    }
+
+    #[test]
+    fn bt601_limited_range() {
+        // black, white, red, green and blue
+        assert_eq!(luma(0, 0, 0), 16);
+        assert_eq!(luma(255, 255, 255), 235);
+        assert_eq!(chroma(0, 0, 0), (128, 128));
+        assert_eq!(chroma(255, 255, 255), (128, 128));
+        assert_eq!((luma(255, 0, 0), chroma(255, 0, 0)), (82, (90, 240)));
+        assert_eq!((luma(0, 255, 0), chroma(0, 255, 0)), (144, (54, 34)));
+        assert_eq!((luma(0, 0, 255), chroma(0, 0, 255)), (41, (240, 110)));
+
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+        let frame = bgra(&colors, 4);
+        let yuy2 = convert(&frame, PixelFormat::Yuy2).unwrap();
+        // the chroma of red and green averaged, yellow
+        assert_eq!(&yuy2.data()[..4], [82, 72, 144, 137]);
+        let rgb = convert(&yuy2, PixelFormat::Rgb).unwrap();
+        assert_eq!(&rgb.data()[..3], [91, 91, 0]);
+
+        let black_white = convert(&bgra(&[[0, 0, 0], [255, 255, 255]], 2), PixelFormat::Rgb);
+        let yuv = convert(&black_white.unwrap(), PixelFormat::I420).unwrap();
+        assert_eq!(yuv.data(), [16, 235, 128, 128]);
+        let rgb = convert(&yuv, PixelFormat::Rgb).unwrap();
+        assert_eq!(rgb.data(), [0, 0, 0, 255, 255, 255]);
+    }
+
+    #[test]
+    fn yuv_to_yuv_copies_planes() {
+        // 3x3 in rows of 4, chroma planes of 2x2
+        let i420: Vec<u8> = (0..4 * 3 + 2 * 2 * 2)
+            .map(|byte| match byte < 12 && byte % 4 == 3 {
+                true => 0,
+                false => byte as u8 * 7,
+            })
+            .collect();
+        let i420 = Frame::packed(PixelFormat::I420, 3, 3, 4, i420);
+        let nv12 = convert(&i420, PixelFormat::Nv12).unwrap();
+        let uv = &nv12.data()[nv12.planes()[1].offset..];
+        assert_eq!(uv, [84, 112, 91, 119, 98, 126, 105, 133]);
+        assert_eq!(nv12.data()[..12], i420.data()[..12]);
+        assert_eq!(
+            convert(&nv12, PixelFormat::Nv12).unwrap().data(),
+            nv12.data()
+        );
+        let back = convert(&nv12, PixelFormat::I420).unwrap();
+        assert_eq!(back.data(), i420.data());
+        assert_eq!(back.planes(), i420.planes());
+        assert_eq!(
+            convert(&back, PixelFormat::I420).unwrap().data(),
+            i420.data()
+        );
+    }
+
+    #[test]
+    fn swizzle_simd_matches_scalar() {
+        #[cfg(target_arch = "x86_64")]
+        if std::arch::is_x86_feature_detected!("ssse3") {
+            let formats = [
+                PixelFormat::Bgra,
+                PixelFormat::Bgrx,
+                PixelFormat::Rgba,
+                PixelFormat::Rgbx,
+            ];
+            // a tail the vector loop leaves to the scalar one
+            let src: Vec<u8> = (0..37 * 4).map(|byte| (byte * 37 + 11) as u8).collect();
+            for from in formats {
+                for to in formats {
+                    let (shuffle, fill) =
+                        shuffle_mask(from.rgba_offsets().unwrap(), to.rgba_offsets().unwrap());
+                    let mut scalar = vec![0u8; src.len()];
+                    shuffle_row(&src, &mut scalar, shuffle, fill);
+                    let mut vector = vec![0u8; src.len()];
+                    // SAFETY: ssse3 support was checked at runtime
+                    unsafe { simd::shuffle_row(&src, &mut vector, shuffle, fill) };
+                    assert_eq!(vector, scalar, "{:?} to {:?}", from, to);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,84 @@
+#![allow(non_camel_case_types, dead_code)]
+
+use std::os::raw::{c_char, c_int, c_void};
+
+pub const RA_ENCODER_STATUS_FAILURE: c_int = -1;
+pub const RA_ENCODER_STATUS_SUCCESS: c_int = 0;
+pub const RA_ENCODER_STATUS_NEED_MORE_DATA: c_int = 1;
+pub const RA_ENCODER_STATUS_ENOUGH_DATA: c_int = 2;
+pub const RA_ENCODER_STATUS_LIMIT_REACHED: c_int = 3;
+pub const RA_ENCODER_STATUS_ENCODED: c_int = 4;
+pub const RA_ENCODER_STATUS_NOT_READY: c_int = -2;
+
+pub const RA_FRAME_TYPE_KEY: c_int = 0;
+
+pub const RA_FRAME_TYPE_OVERRIDE_NO: c_int = 0;
+pub const RA_FRAME_TYPE_OVERRIDE_KEY: c_int = 1;
+
+#[repr(C)]
+pub struct RaConfig {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct RaContext {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct RaFrame {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct RaRational {
+    pub num: u64,
+    pub den: u64,
+}
+
+/// The leading fields of `RaPacket`, only ever read through a pointer.
+#[repr(C)]
+pub struct RaPacket {
+    pub data: *const u8,
+    pub len: usize,
+    pub input_frameno: u64,
+    pub frame_type: c_int,
+}
+
+#[link(name = "rav1e")]
+unsafe extern "C" {
+    pub fn rav1e_config_default() -> *mut RaConfig;
+    pub fn rav1e_config_unref(cfg: *mut RaConfig);
+    pub fn rav1e_config_parse(
+        cfg: *mut RaConfig,
+        key: *const c_char,
+        value: *const c_char,
+    ) -> c_int;
+    pub fn rav1e_config_parse_int(cfg: *mut RaConfig, key: *const c_char, value: c_int) -> c_int;
+    pub fn rav1e_config_set_time_base(cfg: *mut RaConfig, time_base: RaRational);
+
+    pub fn rav1e_context_new(cfg: *const RaConfig) -> *mut RaContext;
+    pub fn rav1e_context_unref(ctx: *mut RaContext);
+
+    pub fn rav1e_frame_new(ctx: *const RaContext) -> *mut RaFrame;
+    pub fn rav1e_frame_unref(frame: *mut RaFrame);
+    pub fn rav1e_frame_fill_plane(
+        frame: *mut RaFrame,
+        plane: c_int,
+        data: *const u8,
+        data_len: usize,
+        stride: isize,
+        bytewidth: c_int,
+    );
+    pub fn rav1e_frame_set_type(frame: *mut RaFrame, frame_type: c_int) -> c_int;
+    pub fn rav1e_frame_set_opaque(
+        frame: *mut RaFrame,
+        opaque: *mut c_void,
+        cb: Option<unsafe extern "C" fn(*mut c_void)>,
+    );
+
+    pub fn rav1e_send_frame(ctx: *mut RaContext, frame: *mut RaFrame) -> c_int;
+    pub fn rav1e_receive_packet(ctx: *mut RaContext, pkt: *mut *mut RaPacket) -> c_int;
+    pub fn rav1e_packet_unref(pkt: *mut RaPacket);
+    pub fn rav1e_status_to_str(status: c_int) -> *const c_char;
+}
@@ -0,0 +1,312 @@
+mod ffi;
+
+use super::{Codec, EncodedPacket, Encoder, EncoderConfig, Preset, RateControl};
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ffi::{CStr, CString};
+use std::ptr;
+use std::time::Duration;
+
+/// AV1 with rav1e, linked from the system.
+///
+/// Speed and lookahead follow `EncoderConfig::preset`, from the fastest
+/// speed and a single frame of lookahead for `Realtime` to speed 4 and 40
+/// frames for `Slow`. `Cqp` and `Crf` both encode with a fixed quantizer.
+///
+/// Frames are converted to I420, a frame of another size restarts the
+/// stream with a keyframe.
+#[derive(Debug)]
+pub struct Av1Encoder {
+    config: EncoderConfig,
+    speed: Option<u8>,
+    lookahead: Option<u32>,
+    context: *mut ffi::RaContext,
+    size: Option<(u32, u32)>,
+    // input frame numbers of the current context and their pts
+    pending: VecDeque<(u64, Duration)>,
+    sent: u64,
+    frames: u64,
+    force_keyframe: bool,
+}
+
+// SAFETY: the context has no thread affinity and is only used through
+// `&mut self`.
+unsafe impl Send for Av1Encoder {}
+
+impl Av1Encoder {
+    pub fn new(config: EncoderConfig) -> Result<Self> {
+        // rav1e codes 4:2:0 here, lossless needs 4:4:4 to be worth it
+        if config.rate_control == RateControl::Lossless {
+            return Err(ScreencastError::Unsupported(
+                "lossless encoding with rav1e".to_string(),
+            ));
+        }
+        if config.codec != Codec::Av1 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} encoding with rav1e",
+                config.codec
+            )));
+        }
+        Ok(Av1Encoder {
+            config,
+            speed: None,
+            lookahead: None,
+            context: ptr::null_mut(),
+            size: None,
+            pending: VecDeque::new(),
+            sent: 0,
+            frames: 0,
+            force_keyframe: false,
+        })
+    }
+
+    /// From 0, the smallest files, to 10, the fastest. Overrides the
+    /// preset.
+    pub fn with_speed(mut self, speed: u8) -> Self {
+        self.speed = Some(speed.min(10));
+        self
+    }
+
+    /// Frames analysed ahead for rate control and keyframe placement, each
+    /// one adds a frame of latency. Overrides the preset.
+    pub fn with_lookahead(mut self, frames: u32) -> Self {
+        self.lookahead = Some(frames.max(1));
+        self
+    }
+
+    fn open(&mut self, width: u32, height: u32) -> Result<()> {
+        unsafe {
+            let config = ffi::rav1e_config_default();
+            let (num, denom) = self.config.framerate;
+            ffi::rav1e_config_set_time_base(
+                config,
+                ffi::RaRational {
+                    num: denom.max(1) as u64,
+                    den: num.max(1) as u64,
+                },
+            );
+            let (speed, lookahead) = match self.config.preset {
+                Preset::Realtime => (10, 1),
+                Preset::Fast => (8, 10),
+                Preset::Medium => (6, 20),
+                Preset::Slow => (4, 40),
+            };
+            let mut options = vec![
+                ("width", width as i32),
+                ("height", height as i32),
+                ("speed", self.speed.unwrap_or(speed) as i32),
+                (
+                    "rdo_lookahead_frames",
+                    self.lookahead.unwrap_or(lookahead) as i32,
+                ),
+            ];
+            if let Some(interval) = self.config.keyframe_interval {
+                let interval = interval.min(i32::MAX as u32) as i32;
+                // rav1e wants the minimum below the maximum
+                options.extend([
+                    ("key_frame_interval", interval),
+                    ("min_key_frame_interval", interval.min(12)),
+                ]);
+            }
+            match self.config.rate_control {
+                RateControl::Cqp(qp) => {
+                    let qp = RateControl::scale(qp, 255) as i32;
+                    options.extend([("quantizer", qp), ("min_quantizer", qp)]);
+                }
+                RateControl::Crf(crf) => {
+                    options.push(("quantizer", RateControl::scale(crf, 255) as i32));
+                }
+                _ => options.push(("bitrate", self.config.bitrate.min(i32::MAX as u32) as i32)),
+            }
+            let mut parsed =
+                ffi::rav1e_config_parse(config, c"low_latency".as_ptr(), c"true".as_ptr());
+            for (key, value) in options {
+                let key = CString::new(key).unwrap();
+                parsed |= ffi::rav1e_config_parse_int(config, key.as_ptr(), value);
+            }
+            let context = match parsed {
+                0 => ffi::rav1e_context_new(config),
+                _ => ptr::null_mut(),
+            };
+            ffi::rav1e_config_unref(config);
+            if context.is_null() {
+                return Err(ScreencastError::Encoder(format!(
+                    "invalid rav1e configuration for {}x{}",
+                    width, height
+                )));
+            }
+            self.context = context;
+        }
+        self.size = Some((width, height));
+        self.pending.clear();
+        self.sent = 0;
+        Ok(())
+    }
+
+    /// Takes every packet rav1e has ready, until it needs more frames or is
+    /// drained.
+    fn receive(&mut self, packets: &mut Vec<EncodedPacket>) -> Result<()> {
+        loop {
+            let mut packet = ptr::null_mut();
+            let status = unsafe { ffi::rav1e_receive_packet(self.context, &mut packet) };
+            match status {
+                ffi::RA_ENCODER_STATUS_SUCCESS => {
+                    let (data, frameno, keyframe) = unsafe {
+                        let read = &*packet;
+                        let data = std::slice::from_raw_parts(read.data, read.len).to_vec();
+                        let read = (
+                            data,
+                            read.input_frameno,
+                            read.frame_type == ffi::RA_FRAME_TYPE_KEY,
+                        );
+                        ffi::rav1e_packet_unref(packet);
+                        read
+                    };
+                    while self
+                        .pending
+                        .front()
+                        .is_some_and(|&(pending, _)| pending < frameno)
+                    {
+                        self.pending.pop_front();
+                    }
+                    let pts = match self.pending.front() {
+                        Some(&(pending, pts)) if pending == frameno => pts,
+                        _ => Duration::ZERO,
+                    };
+                    packets.push(EncodedPacket {
+                        codec: Codec::Av1,
+                        data,
+                        pts,
+                        keyframe,
+                    });
+                }
+                ffi::RA_ENCODER_STATUS_ENCODED => continue,
+                ffi::RA_ENCODER_STATUS_NEED_MORE_DATA | ffi::RA_ENCODER_STATUS_LIMIT_REACHED => {
+                    return Ok(());
+                }
+                status => return Err(status_error(status)),
+            }
+        }
+    }
+
+    /// Flushes and releases the context, collecting its last packets.
+    fn close(&mut self, packets: &mut Vec<EncodedPacket>) -> Result<()> {
+        if self.context.is_null() {
+            return Ok(());
+        }
+        let flushed = unsafe { ffi::rav1e_send_frame(self.context, ptr::null_mut()) };
+        let received = match flushed {
+            ffi::RA_ENCODER_STATUS_SUCCESS => self.receive(packets),
+            status => Err(status_error(status)),
+        };
+        unsafe { ffi::rav1e_context_unref(self.context) };
+        self.context = ptr::null_mut();
+        self.size = None;
+        received
+    }
+
+    fn nominal_pts(&self) -> Duration {
+        let (num, denom) = self.config.framerate;
+        Duration::from_secs_f64(self.frames as f64 * denom.max(1) as f64 / num.max(1) as f64)
+    }
+}
+
+impl Encoder for Av1Encoder {
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "rav1e encoding of DMA-BUF frames".to_string(),
+            ));
+        }
+        let mut packets = Vec::new();
+        let size = (frame.width(), frame.height());
+        if self.size != Some(size) {
+            self.close(&mut packets)?;
+            self.open(size.0, size.1)?;
+        }
+        let pts = frame.pts().unwrap_or_else(|| self.nominal_pts());
+        self.frames += 1;
+
+        let frame = match frame.pixel_format() {
+            PixelFormat::I420 => Cow::Borrowed(frame),
+            _ => Cow::Owned(convert::convert(frame, PixelFormat::I420)?),
+        };
+        let input = unsafe { ffi::rav1e_frame_new(self.context) };
+        if std::mem::take(&mut self.force_keyframe) {
+            unsafe { ffi::rav1e_frame_set_type(input, ffi::RA_FRAME_TYPE_OVERRIDE_KEY) };
+        }
+        for (index, plane) in frame.planes().iter().take(3).enumerate() {
+            let data = frame.plane_data(index).unwrap_or_default();
+            unsafe {
+                ffi::rav1e_frame_fill_plane(
+                    input,
+                    index as i32,
+                    data.as_ptr(),
+                    data.len(),
+                    plane.stride as isize,
+                    1,
+                )
+            };
+        }
+        self.pending.push_back((self.sent, pts));
+        self.sent += 1;
+        let sent = loop {
+            match unsafe { ffi::rav1e_send_frame(self.context, input) } {
+                // the queue is full until packets are taken
+                ffi::RA_ENCODER_STATUS_ENOUGH_DATA => {
+                    let received = packets.len();
+                    if let Err(err) = self.receive(&mut packets) {
+                        break Err(err);
+                    }
+                    if packets.len() == received {
+                        break Err(status_error(ffi::RA_ENCODER_STATUS_ENOUGH_DATA));
+                    }
+                }
+                ffi::RA_ENCODER_STATUS_SUCCESS => break Ok(()),
+                status => break Err(status_error(status)),
+            }
+        };
+        unsafe { ffi::rav1e_frame_unref(input) };
+        sent?;
+        self.receive(&mut packets)?;
+        Ok(packets)
+    }
+
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        let mut packets = Vec::new();
+        self.close(&mut packets)?;
+        Ok(packets)
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.force_keyframe = true;
+        Ok(())
+    }
+}
+
+impl Drop for Av1Encoder {
+    fn drop(&mut self) {
+        if !self.context.is_null() {
+            unsafe { ffi::rav1e_context_unref(self.context) };
+        }
+    }
+}
+
+fn status_error(status: i32) -> ScreencastError {
+    let text = unsafe { ffi::rav1e_status_to_str(status) };
+    let text = match text.is_null() {
+        true => status.to_string(),
+        false => unsafe { CStr::from_ptr(text) }
+            .to_string_lossy()
+            .into_owned(),
+    };
+    ScreencastError::Encoder(format!("rav1e: {}", text))
+}
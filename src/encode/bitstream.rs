@@ -0,0 +1,207 @@
+use super::Codec;
+use std::io::{self, Read};
+
+/// The NAL units of an Annex B buffer, without start codes.
+pub(crate) fn nal_units(data: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let mut rest = match find_start_code(data, 0) {
+        Some(start) => &data[start + 3..],
+        None => &[],
+    };
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let (nal, next) = match find_start_code(rest, 0) {
+            Some(start) => (&rest[..start], &rest[start + 3..]),
+            None => (rest, &[][..]),
+        };
+        rest = next;
+        // trailing zeros belong to the next 4 byte start code
+        let end = nal
+            .iter()
+            .rposition(|&byte| byte != 0)
+            .map_or(0, |last| last + 1);
+        Some(&nal[..end])
+    })
+    .filter(|nal| !nal.is_empty())
+}
+
+/// `nal_type` of an H.264 or HEVC NAL unit.
+pub(crate) fn nal_type(codec: Codec, nal: &[u8]) -> u8 {
+    match codec {
+        Codec::Hevc => nal[0] >> 1 & 0x3f,
+        _ => nal[0] & 0x1f,
+    }
+}
+
+pub(crate) fn is_keyframe(codec: Codec, data: &[u8]) -> bool {
+    match codec {
+        // IDR
+        Codec::H264 => nal_units(data).any(|nal| nal_type(codec, nal) == 5),
+        // BLA, IDR and CRA
+        Codec::Hevc => nal_units(data).any(|nal| (16..=21).contains(&nal_type(codec, nal))),
+        // frame tag, 0 is a key frame
+        Codec::Vp8 => data.first().is_some_and(|&tag| tag & 1 == 0),
+        Codec::Vp9 => vp9_is_keyframe(data),
+        // libaom repeats the sequence header on every key frame
+        Codec::Av1 => obus(data).any(|(obu_type, ..)| obu_type == OBU_SEQUENCE_HEADER),
+    }
+}
+
+fn vp9_is_keyframe(data: &[u8]) -> bool {
+    let Some(&header) = data.first() else {
+        return false;
+    };
+    // frame_marker, profile_low_bit, profile_high_bit
+    let profile = (header >> 5 & 1) | (header >> 3 & 2);
+    let mut bit = if profile == 3 { 5 } else { 4 };
+    let show_existing_frame = header >> (7 - bit) & 1;
+    bit += 1;
+    show_existing_frame == 0 && header >> (7 - bit) & 1 == 0
+}
+
+pub(crate) const OBU_SEQUENCE_HEADER: u8 = 1;
+pub(crate) const OBU_TEMPORAL_DELIMITER: u8 = 2;
+
+/// The OBUs of a temporal unit in low overhead format, as their type, the
+/// whole OBU and its payload.
+pub(crate) fn obus(data: &[u8]) -> impl Iterator<Item = (u8, &[u8], &[u8])> {
+    let mut rest = data;
+    std::iter::from_fn(move || {
+        let (&header, after) = rest.split_first()?;
+        let extension = (header >> 2 & 1) as usize;
+        let mut payload = after.get(extension..)?;
+        let size = if header >> 1 & 1 == 1 {
+            let (size, read) = leb128(payload)?;
+            payload = &payload[read..];
+            size
+        } else {
+            payload.len()
+        };
+        let length = rest.len() - payload.len() + size;
+        let obu = rest.get(..length)?;
+        let payload = payload.get(..size)?;
+        rest = &rest[length..];
+        Some((header >> 3 & 0xf, obu, payload))
+    })
+}
+
+fn leb128(data: &[u8]) -> Option<(usize, usize)> {
+    let mut value = 0usize;
+    for (index, &byte) in data.iter().take(8).enumerate() {
+        value |= ((byte & 0x7f) as usize) << (index * 7);
+        if byte & 0x80 == 0 {
+            return Some((value, index + 1));
+        }
+    }
+    None
+}
+
+/// Splits an encoder's output into packets until the end of the stream:
+/// access units delimited by AUD NAL units for H.264 and HEVC, IVF frames
+/// otherwise. `emit` returns false to stop reading.
+pub(crate) fn read_packets(
+    codec: Codec,
+    reader: impl Read,
+    emit: impl FnMut(Vec<u8>) -> bool,
+) -> io::Result<()> {
+    match codec {
+        Codec::H264 | Codec::Hevc => read_access_units(codec, reader, emit),
+        Codec::Vp8 | Codec::Vp9 | Codec::Av1 => read_ivf(reader, emit),
+    }
+}
+
+fn read_access_units(
+    codec: Codec,
+    mut reader: impl Read,
+    mut emit: impl FnMut(Vec<u8>) -> bool,
+) -> io::Result<()> {
+    let aud = match codec {
+        Codec::Hevc => 35,
+        _ => 9,
+    };
+    let mut buffer = Vec::new();
+    let mut chunk = vec![0; 64 * 1024];
+    let mut scanned = 0;
+    loop {
+        let read = reader.read(&mut chunk)?;
+        if read == 0 {
+            if !buffer.is_empty() {
+                emit(buffer);
+            }
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        while let Some(start) = find_start_code(&buffer, scanned) {
+            let Some(nal) = buffer.get(start + 3..) else {
+                break;
+            };
+            if nal.is_empty() {
+                break;
+            }
+            let begin = if start > 0 && buffer[start - 1] == 0 {
+                start - 1
+            } else {
+                start
+            };
+            if nal_type(codec, nal) == aud && begin > 0 {
+                let rest = buffer.split_off(begin);
+                if !emit(std::mem::replace(&mut buffer, rest)) {
+                    return Ok(());
+                }
+                scanned = 1;
+            } else {
+                scanned = start + 3;
+            }
+        }
+        scanned = scanned.max(buffer.len().saturating_sub(3));
+    }
+}
+
+fn find_start_code(data: &[u8], from: usize) -> Option<usize> {
+    data.get(from..)?
+        .windows(3)
+        .position(|window| window == [0, 0, 1])
+        .map(|position| from + position)
+}
+
+fn read_ivf(mut reader: impl Read, mut emit: impl FnMut(Vec<u8>) -> bool) -> io::Result<()> {
+    let mut header = [0; 32];
+    if !read_or_eof(&mut reader, &mut header)? {
+        return Ok(());
+    }
+    if &header[..4] != b"DKIF" {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not an IVF stream",
+        ));
+    }
+    let header_size = u16::from_le_bytes([header[6], header[7]]) as usize;
+    io::copy(
+        &mut (&mut reader).take(header_size.saturating_sub(32) as u64),
+        &mut io::sink(),
+    )?;
+    let mut frame_header = [0; 12];
+    while read_or_eof(&mut reader, &mut frame_header)? {
+        let size = u32::from_le_bytes(frame_header[..4].try_into().unwrap()) as usize;
+        let mut frame = vec![0; size];
+        reader.read_exact(&mut frame)?;
+        if !emit(frame) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Fills `buffer`, returns false at the end of the stream.
+pub(crate) fn read_or_eof(reader: &mut impl Read, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match reader.read(&mut buffer[filled..])? {
+            0 if filled == 0 => return Ok(false),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => filled += read,
+        }
+    }
+    Ok(true)
+}
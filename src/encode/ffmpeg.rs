@@ -0,0 +1,489 @@
+//! Software encoding through the `ffmpeg` command line tool.
+//!
+//! This is not a binding of libavcodec such as ffmpeg-next: frames are
+//! piped to an `ffmpeg` process, which has to be on `PATH` (or given with
+//! `FfmpegEncoder::with_program`) and built with the encoders asked for.
+//! Every frame is copied once more into the pipe, and failures are reported
+//! with what ffmpeg wrote to stderr. In exchange the crate neither links
+//! nor builds against FFmpeg, whose libraries change their ABI with every
+//! major release.
+
+use super::{Codec, EncodedPacket, Encoder, EncoderConfig, Preset, RateControl, bitstream};
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::collections::VecDeque;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Software encoding with libx264, libx265, libvpx and libaom through an
+/// `ffmpeg` process: raw frames go in on stdin, packets come back on stdout.
+///
+/// Tuned for low latency, without B-frames or lookahead, so every frame
+/// yields one packet. Offers `RateControl::Lossless` for all codecs but
+/// VP8. The process starts with the first frame and keeps its size, later
+/// frames of another pixel format are converted. Keyframes cannot be
+/// forced, the ffmpeg command line has no way to ask for one in the middle
+/// of the stream: set a keyframe interval for viewers joining a stream.
+#[derive(Debug)]
+pub struct FfmpegEncoder {
+    config: EncoderConfig,
+    program: OsString,
+    running: Option<Running>,
+    frames: u64,
+}
+
+#[derive(Debug)]
+struct Running {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    packets: Receiver<Vec<u8>>,
+    reader: JoinHandle<io::Result<()>>,
+    stderr: Arc<Mutex<String>>,
+    pixel_format: PixelFormat,
+    size: (u32, u32),
+    pts: VecDeque<Duration>,
+}
+
+impl FfmpegEncoder {
+    pub fn new(config: EncoderConfig) -> Self {
+        FfmpegEncoder {
+            config,
+            program: "ffmpeg".into(),
+            running: None,
+            frames: 0,
+        }
+    }
+
+    /// The ffmpeg binary, looked up in `PATH` by default.
+    pub fn with_program(mut self, program: impl Into<OsString>) -> Self {
+        self.program = program.into();
+        self
+    }
+
+    fn spawn(&self, pixel_format: PixelFormat, width: u32, height: u32) -> Result<Running> {
+        let codec = self.config.codec;
+        let (num, denom) = self.config.framerate;
+        let output = output_format(&self.config, pixel_format);
+        let mut command = Command::new(&self.program);
+        command
+            .args(["-hide_banner", "-loglevel", "error", "-f", "rawvideo"])
+            .args(["-pix_fmt", input_format(pixel_format)])
+            .args(["-video_size", &format!("{}x{}", width, height)])
+            .args(["-framerate", &format!("{}/{}", num.max(1), denom.max(1))])
+            .args(["-i", "pipe:0", "-an"]);
+        if output.starts_with("yuv42") {
+            // subsampled chroma needs even dimensions
+            command.args(["-vf", "pad=ceil(iw/2)*2:ceil(ih/2)*2"]);
+        }
+        command
+            .args(["-pix_fmt", output])
+            .args(["-c:v", encoder(codec, output)])
+            .args(rate_control_args(&self.config)?)
+            .args(
+                self.config
+                    .keyframe_interval
+                    .map(|interval| ["-g".to_string(), interval.to_string()])
+                    .into_iter()
+                    .flatten(),
+            )
+            .args(codec_args(codec, self.config.preset))
+            .arg("pipe:1")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|err| match err.kind() {
+            io::ErrorKind::NotFound => {
+                ScreencastError::Unsupported("ffmpeg is not installed".to_string())
+            }
+            _ => ScreencastError::Io(err),
+        })?;
+
+        let stdout = child.stdout.take().unwrap();
+        let (sender, packets) = mpsc::channel();
+        let reader = thread::Builder::new()
+            .name("ffmpeg-reader".to_string())
+            .spawn(move || {
+                bitstream::read_packets(codec, stdout, |packet| sender.send(packet).is_ok())
+            })?;
+
+        let mut stderr = child.stderr.take().unwrap();
+        let messages = Arc::new(Mutex::new(String::new()));
+        let collected = messages.clone();
+        thread::Builder::new()
+            .name("ffmpeg-stderr".to_string())
+            .spawn(move || {
+                let mut chunk = [0; 1024];
+                while let Ok(read @ 1..) = stderr.read(&mut chunk) {
+                    let mut messages = collected.lock().unwrap();
+                    messages.push_str(&String::from_utf8_lossy(&chunk[..read]));
+                    // only the latest messages matter
+                    if messages.len() > 4096 {
+                        let cut = messages.ceil_char_boundary(messages.len() - 4096);
+                        messages.drain(..cut);
+                    }
+                }
+            })?;
+
+        Ok(Running {
+            stdin: child.stdin.take(),
+            child,
+            packets,
+            reader,
+            stderr: messages,
+            pixel_format,
+            size: (width, height),
+            pts: VecDeque::new(),
+        })
+    }
+
+    fn nominal_pts(&self) -> Duration {
+        let (num, denom) = self.config.framerate;
+        Duration::from_secs_f64(self.frames as f64 * denom.max(1) as f64 / num.max(1) as f64)
+    }
+}
+
+impl Encoder for FfmpegEncoder {
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "ffmpeg encoding of DMA-BUF frames".to_string(),
+            ));
+        }
+        if self.running.is_none() {
+            let running = self.spawn(frame.pixel_format(), frame.width(), frame.height())?;
+            self.running = Some(running);
+        }
+        let pts = frame.pts().unwrap_or_else(|| self.nominal_pts());
+        self.frames += 1;
+        let running = self.running.as_mut().unwrap();
+        if (frame.width(), frame.height()) != running.size {
+            return Err(ScreencastError::Unsupported(format!(
+                "frame size changed from {}x{} to {}x{} while encoding",
+                running.size.0,
+                running.size.1,
+                frame.width(),
+                frame.height()
+            )));
+        }
+
+        let converted;
+        let frame = if frame.pixel_format() == running.pixel_format {
+            frame
+        } else {
+            converted = convert::convert(frame, running.pixel_format)?;
+            &converted
+        };
+        running.pts.push_back(pts);
+        if let Err(err) = running.write(frame) {
+            return Err(running.failure(err));
+        }
+        Ok(running.ready(self.config.codec))
+    }
+
+    /// Closes ffmpeg's input and waits for the remaining packets.
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        let Some(mut running) = self.running.take() else {
+            return Ok(Vec::new());
+        };
+        drop(running.stdin.take());
+        let mut packets = Vec::new();
+        while let Ok(data) = running.packets.recv() {
+            packets.push(running.packet(self.config.codec, data));
+        }
+        let read = running.reader.join().unwrap_or(Ok(()));
+        let status = running.child.wait()?;
+        if !status.success() {
+            let message = running.stderr.lock().unwrap().trim().to_string();
+            return Err(ScreencastError::Encoder(format!(
+                "ffmpeg exited with {}: {}",
+                status, message
+            )));
+        }
+        read?;
+        Ok(packets)
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        Err(ScreencastError::Unsupported(
+            "forcing keyframes with the ffmpeg encoder".to_string(),
+        ))
+    }
+}
+
+impl Drop for FfmpegEncoder {
+    fn drop(&mut self) {
+        if let Some(running) = &mut self.running {
+            let _ = running.child.kill();
+            let _ = running.child.wait();
+        }
+    }
+}
+
+impl Running {
+    /// Writes the visible rows of every plane, without stride padding.
+    fn write(&mut self, frame: &Frame<'_>) -> io::Result<()> {
+        let stdin = self.stdin.as_mut().unwrap();
+        for index in 0..frame.pixel_format().plane_count() {
+            let plane = frame.planes()[index];
+            let row = row_bytes(frame.pixel_format(), index, frame.width());
+            let data = frame.plane_data(index).unwrap_or_default();
+            let rows = crate::frame::Plane::rows(frame.pixel_format(), index, frame.height());
+            for y in 0..rows {
+                let start = y * plane.stride;
+                let bytes = data.get(start..start + row).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidInput, "frame data is too short")
+                })?;
+                stdin.write_all(bytes)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn ready(&mut self, codec: Codec) -> Vec<EncodedPacket> {
+        let mut packets = Vec::new();
+        loop {
+            match self.packets.try_recv() {
+                Ok(data) => packets.push(self.packet(codec, data)),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return packets,
+            }
+        }
+    }
+
+    fn packet(&mut self, codec: Codec, data: Vec<u8>) -> EncodedPacket {
+        EncodedPacket {
+            codec,
+            keyframe: bitstream::is_keyframe(codec, &data),
+            pts: self.pts.pop_front().unwrap_or_default(),
+            data,
+        }
+    }
+
+    /// Explains a failed write, ffmpeg usually exited and said why.
+    fn failure(&mut self, err: io::Error) -> ScreencastError {
+        if err.kind() != io::ErrorKind::BrokenPipe {
+            return ScreencastError::Io(err);
+        }
+        let _ = self.child.wait();
+        let message = self.stderr.lock().unwrap().trim().to_string();
+        ScreencastError::Encoder(format!("ffmpeg stopped: {}", message))
+    }
+}
+
+pub(crate) fn row_bytes(pixel_format: PixelFormat, index: usize, width: u32) -> usize {
+    let width = width as usize;
+    match (pixel_format, index) {
+        (PixelFormat::Nv12, 1) => width.div_ceil(2) * 2,
+        (PixelFormat::I420, 1..) => width.div_ceil(2),
+        _ => width * pixel_format.bytes_per_pixel(),
+    }
+}
+
+pub(crate) fn input_format(pixel_format: PixelFormat) -> &'static str {
+    match pixel_format {
+        PixelFormat::Bgrx => "bgr0",
+        PixelFormat::Bgra => "bgra",
+        PixelFormat::Rgbx => "rgb0",
+        PixelFormat::Rgba => "rgba",
+        PixelFormat::Xrgb => "0rgb",
+        PixelFormat::Argb => "argb",
+        PixelFormat::Xbgr => "0bgr",
+        PixelFormat::Abgr => "abgr",
+        PixelFormat::Rgb => "rgb24",
+        PixelFormat::Bgr => "bgr24",
+        PixelFormat::Nv12 => "nv12",
+        PixelFormat::I420 => "yuv420p",
+        PixelFormat::Yuy2 => "yuyv422",
+    }
+}
+
+/// What ffmpeg converts frames to before encoding, lossless encoding keeps
+/// RGB as RGB and YUV at its own subsampling.
+fn output_format(config: &EncoderConfig, pixel_format: PixelFormat) -> &'static str {
+    if config.rate_control != RateControl::Lossless {
+        return "yuv420p";
+    }
+    match pixel_format {
+        PixelFormat::Nv12 | PixelFormat::I420 => "yuv420p",
+        PixelFormat::Yuy2 => "yuv422p",
+        _ if config.codec == Codec::H264 => "bgr0",
+        _ => "gbrp",
+    }
+}
+
+fn encoder(codec: Codec, output: &str) -> &'static str {
+    match codec {
+        Codec::H264 if output == "bgr0" => "libx264rgb",
+        Codec::H264 => "libx264",
+        Codec::Hevc => "libx265",
+        Codec::Vp8 => "libvpx",
+        Codec::Vp9 => "libvpx-vp9",
+        Codec::Av1 => "libaom-av1",
+    }
+}
+
+/// libvpx and libaom take quantizers from 0 to 63, VP8 has no constant
+/// quality mode without a bitrate cap.
+fn rate_control_args(config: &EncoderConfig) -> Result<Vec<String>> {
+    let codec = config.codec;
+    let bitrate = config.bitrate.to_string();
+    let quantizer = |quantizer: u8| match codec {
+        Codec::H264 | Codec::Hevc => quantizer.min(51).to_string(),
+        _ => RateControl::scale(quantizer, 63).to_string(),
+    };
+    let args = match (config.rate_control, codec) {
+        (RateControl::Cbr, _) => vec![
+            "-b:v".to_string(),
+            bitrate.clone(),
+            "-minrate".to_string(),
+            bitrate.clone(),
+            "-maxrate".to_string(),
+            bitrate.clone(),
+            "-bufsize".to_string(),
+            bitrate,
+        ],
+        (RateControl::Vbr, _) => {
+            let peak = (config.bitrate as u64 * 2).to_string();
+            vec![
+                "-b:v".to_string(),
+                bitrate,
+                "-maxrate".to_string(),
+                peak.clone(),
+                "-bufsize".to_string(),
+                peak,
+            ]
+        }
+        (RateControl::Cqp(q), Codec::H264) => vec!["-qp".to_string(), quantizer(q)],
+        (RateControl::Cqp(q), Codec::Hevc) => {
+            vec!["-x265-params".to_string(), format!("qp={}", quantizer(q))]
+        }
+        (RateControl::Cqp(q), Codec::Vp8) => vec![
+            "-qmin".to_string(),
+            quantizer(q),
+            "-qmax".to_string(),
+            quantizer(q),
+            "-b:v".to_string(),
+            bitrate,
+        ],
+        (RateControl::Cqp(q), Codec::Vp9 | Codec::Av1) => vec![
+            "-qmin".to_string(),
+            quantizer(q),
+            "-qmax".to_string(),
+            quantizer(q),
+            "-crf".to_string(),
+            quantizer(q),
+            "-b:v".to_string(),
+            "0".to_string(),
+        ],
+        (RateControl::Crf(q), Codec::H264 | Codec::Hevc) => {
+            vec!["-crf".to_string(), quantizer(q)]
+        }
+        (RateControl::Crf(q), Codec::Vp8) => {
+            vec![
+                "-crf".to_string(),
+                quantizer(q),
+                "-b:v".to_string(),
+                bitrate,
+            ]
+        }
+        (RateControl::Crf(q), Codec::Vp9 | Codec::Av1) => vec![
+            "-crf".to_string(),
+            quantizer(q),
+            "-b:v".to_string(),
+            "0".to_string(),
+        ],
+        (RateControl::Lossless, Codec::H264) => vec!["-qp".to_string(), "0".to_string()],
+        (RateControl::Lossless, Codec::Hevc) => {
+            vec!["-x265-params".to_string(), "lossless=1".to_string()]
+        }
+        (RateControl::Lossless, Codec::Vp9 | Codec::Av1) => {
+            vec!["-lossless".to_string(), "1".to_string()]
+        }
+        (RateControl::Lossless, Codec::Vp8) => {
+            return Err(ScreencastError::Unsupported(
+                "lossless VP8 encoding".to_string(),
+            ));
+        }
+    };
+    Ok(args)
+}
+
+/// Speed, low latency tuning and output format, AUDs let the reader split
+/// Annex B streams into access units.
+fn codec_args(codec: Codec, preset: Preset) -> Vec<&'static str> {
+    let (x264_preset, vpx_cpu_used, aom_cpu_used) = match preset {
+        Preset::Realtime => ("veryfast", "8", "8"),
+        Preset::Fast => ("fast", "4", "6"),
+        Preset::Medium => ("medium", "2", "4"),
+        Preset::Slow => ("slow", "0", "2"),
+    };
+    // `-deadline` of libvpx and `-usage` of libaom
+    let mode = match preset {
+        Preset::Realtime => "realtime",
+        _ => "good",
+    };
+    match codec {
+        Codec::H264 => vec![
+            "-preset",
+            x264_preset,
+            "-tune",
+            "zerolatency",
+            "-bsf:v",
+            "h264_metadata=aud=insert",
+            "-f",
+            "h264",
+        ],
+        Codec::Hevc => vec![
+            "-preset",
+            x264_preset,
+            "-tune",
+            "zerolatency",
+            "-bsf:v",
+            "hevc_metadata=aud=insert",
+            "-f",
+            "hevc",
+        ],
+        Codec::Vp8 => vec![
+            "-deadline",
+            mode,
+            "-cpu-used",
+            vpx_cpu_used,
+            "-lag-in-frames",
+            "0",
+            "-f",
+            "ivf",
+        ],
+        Codec::Vp9 => vec![
+            "-deadline",
+            mode,
+            "-cpu-used",
+            vpx_cpu_used,
+            "-lag-in-frames",
+            "0",
+            "-row-mt",
+            "1",
+            "-f",
+            "ivf",
+        ],
+        Codec::Av1 => vec![
+            "-usage",
+            mode,
+            "-cpu-used",
+            aom_cpu_used,
+            "-lag-in-frames",
+            "0",
+            "-f",
+            "ivf",
+        ],
+    }
+}
@@ -0,0 +1,118 @@
+use super::{Codec, EncodedPacket, bitstream};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::gstreamer::{AppSink, AppSrc, GstPipeline};
+use std::time::Duration;
+
+/// Runs an encoder as a GStreamer pipeline: frames are pushed into an
+/// `appsrc` called `src`, packets pulled from an `appsink` called
+/// `packets`. The pipeline starts with the first frame.
+#[derive(Debug)]
+pub(crate) struct GstEncoder {
+    codec: Codec,
+    description: String,
+    running: Option<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    appsrc: AppSrc,
+    appsink: AppSink,
+    pipeline: GstPipeline,
+}
+
+impl GstEncoder {
+    /// `encoder` is linked between the source and a sink taking whole
+    /// packets, e.g. `videoconvert ! vp9enc`.
+    pub(crate) fn new(codec: Codec, encoder: &str) -> Self {
+        let tail = match codec {
+            Codec::H264 => {
+                "h264parse config-interval=-1 ! video/x-h264,stream-format=byte-stream,alignment=au ! "
+            }
+            Codec::Hevc => {
+                "h265parse config-interval=-1 ! video/x-h265,stream-format=byte-stream,alignment=au ! "
+            }
+            Codec::Av1 => "av1parse ! video/x-av1,stream-format=obu-stream,alignment=tu ! ",
+            Codec::Vp8 | Codec::Vp9 => "",
+        };
+        GstEncoder {
+            codec,
+            description: format!(
+                "appsrc name=src format=time is-live=true ! {} ! {}appsink name=packets sync=false",
+                encoder, tail
+            ),
+            running: None,
+        }
+    }
+
+    pub(crate) fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        let codec = self.codec;
+        let running = self.start()?;
+        if let Err(err) = running.appsrc.push(frame) {
+            return Err(running.pipeline.error().unwrap_or(err));
+        }
+        let mut packets = Vec::new();
+        while let Some(packet) = running.pull(codec, Duration::ZERO) {
+            packets.push(packet);
+        }
+        match running.pipeline.error() {
+            Some(err) => Err(err),
+            None => Ok(packets),
+        }
+    }
+
+    /// Sends EOS and collects the packets still in the pipeline.
+    pub(crate) fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        let codec = self.codec;
+        let Some(running) = self.running.take() else {
+            return Ok(Vec::new());
+        };
+        running.appsrc.end_of_stream()?;
+        let mut packets = Vec::new();
+        while !running.appsink.is_eos() {
+            if let Some(err) = running.pipeline.error() {
+                return Err(err);
+            }
+            if let Some(packet) = running.pull(codec, Duration::from_millis(100)) {
+                packets.push(packet);
+            }
+        }
+        running.pipeline.stop()?;
+        Ok(packets)
+    }
+
+    pub(crate) fn force_keyframe(&mut self) -> Result<()> {
+        match &self.running {
+            Some(running) => running.appsrc.force_keyframe(),
+            // the first frame is a keyframe anyway
+            None => Ok(()),
+        }
+    }
+
+    fn start(&mut self) -> Result<&mut Running> {
+        if self.running.is_none() {
+            let pipeline = GstPipeline::launch(&self.description)?;
+            let running = Running {
+                appsrc: pipeline.appsrc("src")?,
+                appsink: pipeline.appsink("packets")?,
+                pipeline,
+            };
+            running.pipeline.play()?;
+            self.running = Some(running);
+        }
+        Ok(self.running.as_mut().unwrap())
+    }
+}
+
+impl Running {
+    fn pull(&self, codec: Codec, timeout: Duration) -> Option<EncodedPacket> {
+        let (data, pts) = self.appsink.try_pull_buffer(timeout)?;
+        let origin = self.appsrc.origin().unwrap_or_default();
+        Some(EncodedPacket {
+            codec,
+            keyframe: bitstream::is_keyframe(codec, &data),
+            pts: origin + pts.unwrap_or_default(),
+            data,
+        })
+    }
+}
@@ -0,0 +1,200 @@
+#[cfg(feature = "rav1e")]
+pub mod av1;
+pub(crate) mod bitstream;
+pub mod ffmpeg;
+#[cfg(feature = "gstreamer")]
+mod gst;
+#[cfg(feature = "openh264")]
+pub mod openh264;
+#[cfg(feature = "gstreamer")]
+pub mod vaapi;
+#[cfg(feature = "gstreamer")]
+pub mod vpx;
+
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use std::time::Duration;
+
+/// Video codecs the encoder backends can produce.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Codec {
+    H264,
+    Hevc,
+    Vp8,
+    Vp9,
+    Av1,
+}
+
+/// How the encoder trades quality for size. Quantizers use the 0 to 51
+/// scale of H.264, lower is better, and are scaled to each codec's range.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum RateControl {
+    /// Holds `EncoderConfig::bitrate` steadily, what streaming needs.
+    #[default]
+    Cbr,
+    /// Averages `EncoderConfig::bitrate`, spending more on busy scenes and
+    /// less on a static desktop.
+    Vbr,
+    /// The same quantizer for every frame.
+    Cqp(u8),
+    /// Constant perceived quality, the bitrate follows the content. The
+    /// usual choice for recordings to disk, 18 to 28 covers most needs.
+    Crf(u8),
+    /// Keeps every pixel, for footage that is edited later and must not
+    /// lose quality with each generation. Files are many times larger.
+    Lossless,
+}
+
+impl RateControl {
+    /// `quantizer`, clamped to 51, on a scale from 0 to `max`.
+    pub(crate) fn scale(quantizer: u8, max: u32) -> u32 {
+        (quantizer.min(51) as u32 * max + 25) / 51
+    }
+}
+
+/// Encoding speed against compression, mapped to the presets, speed levels
+/// or target usages of each backend. Slower presets need more CPU time per
+/// frame, not more latency.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Preset {
+    /// Fast enough for 60 fps capture on a laptop.
+    #[default]
+    Realtime,
+    Fast,
+    Medium,
+    /// The smallest files, for recordings that are archived.
+    Slow,
+}
+
+/// Settings shared by every encoder backend, each maps them onto its own
+/// options. The frame size is taken from the first frame, the default is
+/// H.264.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncoderConfig {
+    pub codec: Codec,
+    pub rate_control: RateControl,
+    /// Target bitrate in bits per second for `Cbr` and `Vbr`.
+    pub bitrate: u32,
+    pub preset: Preset,
+    /// Nominal framerate as `(numerator, denominator)` for rate control,
+    /// packets keep the timestamps of their frames.
+    pub framerate: (u32, u32),
+    /// Frames from one keyframe to the next, `None` leaves it to the
+    /// encoder. Short intervals let viewers join and recover from loss
+    /// sooner but cost bitrate.
+    pub keyframe_interval: Option<u32>,
+}
+
+impl Default for EncoderConfig {
+    fn default() -> Self {
+        EncoderConfig::new(Codec::H264)
+    }
+}
+
+impl EncoderConfig {
+    /// 6 Mbit/s CBR at 60 fps with the realtime preset, enough for a 1080p
+    /// desktop.
+    pub fn new(codec: Codec) -> Self {
+        EncoderConfig {
+            codec,
+            rate_control: RateControl::Cbr,
+            bitrate: 6_000_000,
+            preset: Preset::Realtime,
+            framerate: (60, 1),
+            keyframe_interval: None,
+        }
+    }
+
+    /// Lossless encoding, only the ffmpeg backend offers it. RGB frames
+    /// stay RGB with H.264, other codecs store them as 4:4:4 GBR.
+    pub fn lossless(codec: Codec) -> Self {
+        EncoderConfig::new(codec).with_rate_control(RateControl::Lossless)
+    }
+
+    pub fn with_rate_control(mut self, rate_control: RateControl) -> Self {
+        self.rate_control = rate_control;
+        self
+    }
+
+    pub fn with_bitrate(mut self, bitrate: u32) -> Self {
+        self.bitrate = bitrate;
+        self
+    }
+
+    pub fn with_preset(mut self, preset: Preset) -> Self {
+        self.preset = preset;
+        self
+    }
+
+    pub fn with_framerate(mut self, framerate: (u32, u32)) -> Self {
+        self.framerate = framerate;
+        self
+    }
+
+    pub fn with_keyframe_interval(mut self, frames: u32) -> Self {
+        self.keyframe_interval = Some(frames.max(1));
+        self
+    }
+}
+
+/// One compressed frame: an access unit in Annex B for H.264 and HEVC, a
+/// frame (or VP9 superframe) for VP8 and VP9, a temporal unit for AV1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedPacket {
+    pub codec: Codec,
+    pub data: Vec<u8>,
+    /// The pts of the frame it was encoded from, frames without one are
+    /// placed at the nominal framerate.
+    pub pts: Duration,
+    /// Decoding can start here, the packet carries the parameter sets.
+    pub keyframe: bool,
+}
+
+/// Turns frames into packets. Encoders may buffer, so `encode` returns
+/// whatever became ready and `finish` drains the rest.
+pub trait Encoder: Send {
+    fn config(&self) -> &EncoderConfig;
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>>;
+
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>>;
+
+    /// Makes the next frame a keyframe, for a viewer that just joined or
+    /// lost packets. Fails with `Unsupported` for backends that cannot, see
+    /// `request_keyframe`.
+    fn force_keyframe(&mut self) -> Result<()>;
+}
+
+/// Forces a keyframe where the encoder can, otherwise viewers wait for the
+/// next one of the keyframe interval.
+pub(crate) fn request_keyframe(encoder: &mut dyn Encoder) -> Result<()> {
+    match encoder.force_keyframe() {
+        Err(ScreencastError::Unsupported(_)) => Ok(()),
+        forced => forced,
+    }
+}
+
+/// The encoder used unless one is picked explicitly: OpenH264 for H.264
+/// with the `openh264` feature, which needs nothing at runtime, libvpx for
+/// VP8 and VP9 with the `gstreamer` feature, rav1e for AV1 with the `rav1e`
+/// feature, and an `ffmpeg` process otherwise or for rate control the
+/// others lack.
+pub fn default_encoder(config: EncoderConfig) -> Result<Box<dyn Encoder>> {
+    #[cfg(feature = "openh264")]
+    if config.codec == Codec::H264
+        && matches!(config.rate_control, RateControl::Cbr | RateControl::Vbr)
+    {
+        return Ok(Box::new(openh264::OpenH264Encoder::new(config)?));
+    }
+    #[cfg(feature = "gstreamer")]
+    if matches!(config.codec, Codec::Vp8 | Codec::Vp9)
+        && config.rate_control != RateControl::Lossless
+    {
+        return Ok(Box::new(vpx::VpxEncoder::new(config)?));
+    }
+    #[cfg(feature = "rav1e")]
+    if config.codec == Codec::Av1 && config.rate_control != RateControl::Lossless {
+        return Ok(Box::new(av1::Av1Encoder::new(config)?));
+    }
+    Ok(Box::new(ffmpeg::FfmpegEncoder::new(config)))
+}
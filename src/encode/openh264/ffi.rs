@@ -0,0 +1,96 @@
+#![allow(non_camel_case_types, non_snake_case, dead_code)]
+
+use std::os::raw::{c_float, c_int, c_uchar, c_void};
+
+pub const SCREEN_CONTENT_REAL_TIME: c_int = 1;
+
+pub const RC_QUALITY_MODE: c_int = 0;
+pub const RC_BITRATE_MODE: c_int = 1;
+
+pub const LOW_COMPLEXITY: c_int = 0;
+pub const MEDIUM_COMPLEXITY: c_int = 1;
+pub const HIGH_COMPLEXITY: c_int = 2;
+
+pub const VIDEO_FORMAT_I420: c_int = 23;
+
+pub const VIDEO_FRAME_TYPE_IDR: c_int = 1;
+pub const VIDEO_FRAME_TYPE_SKIP: c_int = 4;
+
+pub const ENCODER_OPTION_DATAFORMAT: c_int = 0;
+pub const ENCODER_OPTION_IDR_INTERVAL: c_int = 1;
+pub const ENCODER_OPTION_COMPLEXITY: c_int = 19;
+
+pub const MAX_LAYER_NUM_OF_FRAME: usize = 128;
+
+/// `ISVCEncoder` of the C API, a pointer to the vtable.
+pub type ISVCEncoder = *const ISVCEncoderVtbl;
+
+#[repr(C)]
+pub struct ISVCEncoderVtbl {
+    pub Initialize: unsafe extern "C" fn(*mut ISVCEncoder, *const SEncParamBase) -> c_int,
+    pub InitializeExt: unsafe extern "C" fn(*mut ISVCEncoder, *const c_void) -> c_int,
+    pub GetDefaultParams: unsafe extern "C" fn(*mut ISVCEncoder, *mut c_void) -> c_int,
+    pub Uninitialize: unsafe extern "C" fn(*mut ISVCEncoder) -> c_int,
+    pub EncodeFrame:
+        unsafe extern "C" fn(*mut ISVCEncoder, *const SSourcePicture, *mut SFrameBSInfo) -> c_int,
+    pub EncodeParameterSets: unsafe extern "C" fn(*mut ISVCEncoder, *mut SFrameBSInfo) -> c_int,
+    pub ForceIntraFrame: unsafe extern "C" fn(*mut ISVCEncoder, bool) -> c_int,
+    pub SetOption: unsafe extern "C" fn(*mut ISVCEncoder, c_int, *mut c_void) -> c_int,
+    pub GetOption: unsafe extern "C" fn(*mut ISVCEncoder, c_int, *mut c_void) -> c_int,
+}
+
+#[repr(C)]
+pub struct SEncParamBase {
+    pub iUsageType: c_int,
+    pub iPicWidth: c_int,
+    pub iPicHeight: c_int,
+    pub iTargetBitrate: c_int,
+    pub iRCMode: c_int,
+    pub fMaxFrameRate: c_float,
+}
+
+#[repr(C)]
+pub struct SSourcePicture {
+    pub iColorFormat: c_int,
+    pub iStride: [c_int; 4],
+    pub pData: [*mut c_uchar; 4],
+    pub iPicWidth: c_int,
+    pub iPicHeight: c_int,
+    pub uiTimeStamp: i64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SLayerBSInfo {
+    pub uiTemporalId: c_uchar,
+    pub uiSpatialId: c_uchar,
+    pub uiQualityId: c_uchar,
+    pub eFrameType: c_int,
+    pub uiLayerType: c_uchar,
+    pub iSubSeqId: c_int,
+    pub iNalCount: c_int,
+    pub pNalLengthInByte: *mut c_int,
+    pub pBsBuf: *mut c_uchar,
+}
+
+#[repr(C)]
+pub struct SFrameBSInfo {
+    pub iLayerNum: c_int,
+    pub sLayerInfo: [SLayerBSInfo; MAX_LAYER_NUM_OF_FRAME],
+    pub eFrameType: c_int,
+    pub iFrameSizeInBytes: c_int,
+    pub uiTimeStamp: i64,
+}
+
+impl SFrameBSInfo {
+    pub fn zeroed() -> Self {
+        // SAFETY: plain pointers and integers, all zero is a valid value
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+#[link(name = "openh264")]
+unsafe extern "C" {
+    pub fn WelsCreateSVCEncoder(encoder: *mut *mut ISVCEncoder) -> c_int;
+    pub fn WelsDestroySVCEncoder(encoder: *mut ISVCEncoder);
+}
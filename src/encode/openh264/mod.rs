@@ -0,0 +1,226 @@
+mod ffi;
+
+use super::{Codec, EncodedPacket, Encoder, EncoderConfig, Preset, RateControl};
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::borrow::Cow;
+use std::ptr;
+use std::time::Duration;
+
+/// H.264 with Cisco's OpenH264, linked from the system. Tuned for screen
+/// content in realtime, every frame is encoded right away and skipped
+/// frames yield no packet. Only `Cbr` and `Vbr` rate control are offered.
+///
+/// Frames are converted to I420 and a trailing odd row or column is cut.
+/// A frame of another size restarts the stream with a keyframe.
+#[derive(Debug)]
+pub struct OpenH264Encoder {
+    config: EncoderConfig,
+    encoder: *mut ffi::ISVCEncoder,
+    size: Option<(u32, u32)>,
+    frames: u64,
+}
+
+// SAFETY: the encoder has no thread affinity and is only used through
+// `&mut self`.
+unsafe impl Send for OpenH264Encoder {}
+
+impl OpenH264Encoder {
+    pub fn new(config: EncoderConfig) -> Result<Self> {
+        if !matches!(config.rate_control, RateControl::Cbr | RateControl::Vbr) {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} rate control with OpenH264",
+                config.rate_control
+            )));
+        }
+        if config.codec != Codec::H264 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} encoding with OpenH264",
+                config.codec
+            )));
+        }
+        let mut encoder = ptr::null_mut();
+        if unsafe { ffi::WelsCreateSVCEncoder(&mut encoder) } != 0 || encoder.is_null() {
+            return Err(ScreencastError::Encoder(
+                "failed to create an OpenH264 encoder".to_string(),
+            ));
+        }
+        Ok(OpenH264Encoder {
+            config,
+            encoder,
+            size: None,
+            frames: 0,
+        })
+    }
+
+    fn vtable(&self) -> &ffi::ISVCEncoderVtbl {
+        // SAFETY: `encoder` points at the vtable pointer until dropped
+        unsafe { &**self.encoder }
+    }
+
+    fn initialize(&mut self, width: u32, height: u32) -> Result<()> {
+        if self.size.take().is_some() {
+            unsafe { (self.vtable().Uninitialize)(self.encoder) };
+        }
+        let (num, denom) = self.config.framerate;
+        let params = ffi::SEncParamBase {
+            iUsageType: ffi::SCREEN_CONTENT_REAL_TIME,
+            iPicWidth: width as i32,
+            iPicHeight: height as i32,
+            iTargetBitrate: self.config.bitrate.min(i32::MAX as u32) as i32,
+            iRCMode: match self.config.rate_control {
+                RateControl::Vbr => ffi::RC_QUALITY_MODE,
+                _ => ffi::RC_BITRATE_MODE,
+            },
+            fMaxFrameRate: num.max(1) as f32 / denom.max(1) as f32,
+        };
+        let initialized = unsafe { (self.vtable().Initialize)(self.encoder, &params) };
+        if initialized != 0 {
+            return Err(ScreencastError::Encoder(format!(
+                "failed to initialize OpenH264 for {}x{} ({})",
+                width, height, initialized
+            )));
+        }
+        let mut format = ffi::VIDEO_FORMAT_I420;
+        let mut complexity = match self.config.preset {
+            Preset::Realtime => ffi::LOW_COMPLEXITY,
+            Preset::Fast => ffi::MEDIUM_COMPLEXITY,
+            Preset::Medium | Preset::Slow => ffi::HIGH_COMPLEXITY,
+        };
+        unsafe {
+            (self.vtable().SetOption)(
+                self.encoder,
+                ffi::ENCODER_OPTION_DATAFORMAT,
+                (&mut format as *mut i32).cast(),
+            );
+            (self.vtable().SetOption)(
+                self.encoder,
+                ffi::ENCODER_OPTION_COMPLEXITY,
+                (&mut complexity as *mut i32).cast(),
+            );
+            if let Some(interval) = self.config.keyframe_interval {
+                let mut interval = interval.min(i32::MAX as u32) as i32;
+                (self.vtable().SetOption)(
+                    self.encoder,
+                    ffi::ENCODER_OPTION_IDR_INTERVAL,
+                    (&mut interval as *mut i32).cast(),
+                );
+            }
+        }
+        self.size = Some((width, height));
+        Ok(())
+    }
+
+    fn nominal_pts(&self) -> Duration {
+        let (num, denom) = self.config.framerate;
+        Duration::from_secs_f64(self.frames as f64 * denom.max(1) as f64 / num.max(1) as f64)
+    }
+}
+
+impl Encoder for OpenH264Encoder {
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "OpenH264 encoding of DMA-BUF frames".to_string(),
+            ));
+        }
+        let (width, height) = (frame.width() & !1, frame.height() & !1);
+        if width == 0 || height == 0 {
+            return Err(ScreencastError::Unsupported(format!(
+                "encoding {}x{} frames",
+                frame.width(),
+                frame.height()
+            )));
+        }
+        if self.size != Some((width, height)) {
+            self.initialize(width, height)?;
+        }
+        let pts = frame.pts().unwrap_or_else(|| self.nominal_pts());
+        self.frames += 1;
+
+        let frame = match frame.pixel_format() {
+            PixelFormat::I420 => Cow::Borrowed(frame),
+            _ => Cow::Owned(convert::convert(frame, PixelFormat::I420)?),
+        };
+        let mut picture = ffi::SSourcePicture {
+            iColorFormat: ffi::VIDEO_FORMAT_I420,
+            iStride: [0; 4],
+            pData: [ptr::null_mut(); 4],
+            iPicWidth: width as i32,
+            iPicHeight: height as i32,
+            uiTimeStamp: pts.as_millis() as i64,
+        };
+        for (index, plane) in frame.planes().iter().take(3).enumerate() {
+            let data = frame.plane_data(index).unwrap_or_default();
+            picture.iStride[index] = plane.stride as i32;
+            // read only, the API just lacks const
+            picture.pData[index] = data.as_ptr().cast_mut();
+        }
+
+        let mut info = ffi::SFrameBSInfo::zeroed();
+        let encoded = unsafe { (self.vtable().EncodeFrame)(self.encoder, &picture, &mut info) };
+        if encoded != 0 {
+            return Err(ScreencastError::Encoder(format!(
+                "OpenH264 failed to encode a frame ({})",
+                encoded
+            )));
+        }
+        if info.eFrameType == ffi::VIDEO_FRAME_TYPE_SKIP {
+            return Ok(Vec::new());
+        }
+        let mut data = Vec::with_capacity(info.iFrameSizeInBytes.max(0) as usize);
+        for layer in &info.sLayerInfo[..info.iLayerNum.clamp(0, 128) as usize] {
+            if layer.iNalCount <= 0 {
+                continue;
+            }
+            let nals = unsafe {
+                std::slice::from_raw_parts(layer.pNalLengthInByte, layer.iNalCount as usize)
+            };
+            let size = nals.iter().map(|&size| size as usize).sum();
+            data.extend_from_slice(unsafe { std::slice::from_raw_parts(layer.pBsBuf, size) });
+        }
+        Ok(vec![EncodedPacket {
+            codec: Codec::H264,
+            data,
+            pts,
+            keyframe: info.eFrameType == ffi::VIDEO_FRAME_TYPE_IDR,
+        }])
+    }
+
+    /// Frames are never held back, nothing is left to drain.
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        Ok(Vec::new())
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        // before the first frame there is nothing to force
+        if self.size.is_none() {
+            return Ok(());
+        }
+        let forced = unsafe { (self.vtable().ForceIntraFrame)(self.encoder, true) };
+        match forced {
+            0 => Ok(()),
+            forced => Err(ScreencastError::Encoder(format!(
+                "OpenH264 failed to force a keyframe ({})",
+                forced
+            ))),
+        }
+    }
+}
+
+impl Drop for OpenH264Encoder {
+    fn drop(&mut self) {
+        unsafe {
+            if self.size.is_some() {
+                (self.vtable().Uninitialize)(self.encoder);
+            }
+            ffi::WelsDestroySVCEncoder(self.encoder);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use super::gst::GstEncoder;
+use super::{Codec, EncodedPacket, Encoder, EncoderConfig, Preset, RateControl};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+
+/// Hardware encoding of H.264 and HEVC through the VA elements of
+/// GStreamer's `va` plugin.
+///
+/// DMA-BUF frames are imported into VA surfaces and converted to NV12 on
+/// the GPU, so frames never touch the CPU. Memory frames are uploaded.
+/// Negotiate DMA-BUF modifiers the driver can import, see
+/// `CaptureOptions::dmabuf_modifiers`.
+///
+/// `Crf` falls back to a constant quantizer, the drivers' quality modes
+/// differ too much between vendors.
+#[derive(Debug)]
+pub struct VaapiEncoder {
+    config: EncoderConfig,
+    encoder: GstEncoder,
+}
+
+impl VaapiEncoder {
+    pub fn new(config: EncoderConfig) -> Result<Self> {
+        if config.rate_control == RateControl::Lossless {
+            return Err(ScreencastError::Unsupported(
+                "lossless encoding with VA-API".to_string(),
+            ));
+        }
+        match config.codec {
+            Codec::H264 | Codec::Hevc => Ok(VaapiEncoder {
+                encoder: GstEncoder::new(config.codec, &description(&config, false)),
+                config,
+            }),
+            codec => Err(ScreencastError::Unsupported(format!(
+                "{:?} encoding with VA-API",
+                codec
+            ))),
+        }
+    }
+
+    /// Uses the fixed function encoder (`vah264lpenc`) found on recent
+    /// Intel GPUs, faster but with fewer tuning options.
+    pub fn with_low_power(mut self, low_power: bool) -> Self {
+        self.encoder = GstEncoder::new(self.config.codec, &description(&self.config, low_power));
+        self
+    }
+}
+
+impl Encoder for VaapiEncoder {
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        self.encoder.encode(frame)
+    }
+
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        self.encoder.finish()
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.encoder.force_keyframe()
+    }
+}
+
+fn description(config: &EncoderConfig, low_power: bool) -> String {
+    let encoder = match config.codec {
+        Codec::Hevc => "vah265",
+        _ => "vah264",
+    };
+    let power = if low_power { "lp" } else { "" };
+    let rate_control = match config.rate_control {
+        RateControl::Cqp(qp) | RateControl::Crf(qp) => {
+            let qp = qp.min(51);
+            format!("rate-control=cqp qpi={} qpp={} qpb={}", qp, qp, qp)
+        }
+        RateControl::Vbr => format!(
+            "rate-control=vbr bitrate={}",
+            (config.bitrate / 1000).max(1)
+        ),
+        _ => format!(
+            "rate-control=cbr bitrate={}",
+            (config.bitrate / 1000).max(1)
+        ),
+    };
+    let target_usage = match config.preset {
+        Preset::Realtime => 7,
+        Preset::Fast => 6,
+        Preset::Medium => 4,
+        Preset::Slow => 1,
+    };
+    let mut description = format!(
+        "vapostproc ! video/x-raw(memory:VAMemory),format=NV12 ! {}{}enc {} target-usage={}",
+        encoder, power, rate_control, target_usage
+    );
+    if let Some(interval) = config.keyframe_interval {
+        description.push_str(&format!(" key-int-max={}", interval));
+    }
+    description
+}
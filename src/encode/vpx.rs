@@ -0,0 +1,122 @@
+use super::gst::GstEncoder;
+use super::{Codec, EncodedPacket, Encoder, EncoderConfig, Preset, RateControl};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+
+/// VP8 and VP9 with libvpx through GStreamer's `vp8enc` and `vp9enc`,
+/// without lookahead, so every frame yields one packet. Presets other than
+/// `Realtime` switch libvpx to its good quality deadline. VP9 is tuned for screen content by default, which
+/// keeps text sharp and makes static desktops nearly free.
+///
+/// Takes memory frames, they are converted to I420 on the way in.
+#[derive(Debug)]
+pub struct VpxEncoder {
+    config: EncoderConfig,
+    screen_content: bool,
+    encoder: GstEncoder,
+}
+
+impl VpxEncoder {
+    pub fn new(config: EncoderConfig) -> Result<Self> {
+        if config.rate_control == RateControl::Lossless {
+            return Err(ScreencastError::Unsupported(
+                "lossless encoding with libvpx".to_string(),
+            ));
+        }
+        match config.codec {
+            Codec::Vp8 | Codec::Vp9 => Ok(VpxEncoder {
+                encoder: GstEncoder::new(config.codec, &description(&config, true)),
+                config,
+                screen_content: true,
+            }),
+            codec => Err(ScreencastError::Unsupported(format!(
+                "{:?} encoding with libvpx",
+                codec
+            ))),
+        }
+    }
+
+    /// Switches VP9 between screen content and camera tuning, VP8 has no
+    /// such mode.
+    pub fn with_screen_content(mut self, screen_content: bool) -> Self {
+        self.screen_content = screen_content;
+        self.encoder = GstEncoder::new(
+            self.config.codec,
+            &description(&self.config, screen_content),
+        );
+        self
+    }
+
+    pub fn is_screen_content(&self) -> bool {
+        self.screen_content && self.config.codec == Codec::Vp9
+    }
+}
+
+impl Encoder for VpxEncoder {
+    fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "libvpx encoding of DMA-BUF frames".to_string(),
+            ));
+        }
+        self.encoder.encode(frame)
+    }
+
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        self.encoder.finish()
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        self.encoder.force_keyframe()
+    }
+}
+
+fn description(config: &EncoderConfig, screen_content: bool) -> String {
+    let threads = std::thread::available_parallelism().map_or(4, |threads| threads.get().min(8));
+    let (deadline, cpu_used) = match config.preset {
+        Preset::Realtime => (1, 8),
+        Preset::Fast => (1_000_000, 4),
+        Preset::Medium => (1_000_000, 2),
+        Preset::Slow => (1_000_000, 0),
+    };
+    let rate_control = match config.rate_control {
+        RateControl::Vbr => format!("end-usage=vbr target-bitrate={}", config.bitrate),
+        RateControl::Cqp(qp) => {
+            let qp = RateControl::scale(qp, 63);
+            format!("end-usage=q min-quantizer={} max-quantizer={}", qp, qp)
+        }
+        // the bitrate caps busy scenes
+        RateControl::Crf(crf) => format!(
+            "end-usage=cq cq-level={} target-bitrate={}",
+            RateControl::scale(crf, 63),
+            config.bitrate
+        ),
+        _ => format!("end-usage=cbr target-bitrate={}", config.bitrate),
+    };
+    let mut encoder = format!(
+        "videoconvert ! video/x-raw,format=I420 ! {} deadline={} cpu-used={} {} \
+         lag-in-frames=0 threads={}",
+        match config.codec {
+            Codec::Vp8 => "vp8enc",
+            _ => "vp9enc",
+        },
+        deadline,
+        cpu_used,
+        rate_control,
+        threads
+    );
+    if let Some(interval) = config.keyframe_interval {
+        encoder.push_str(&format!(" keyframe-max-dist={}", interval));
+    }
+    if config.codec == Codec::Vp9 {
+        encoder.push_str(" row-mt=true");
+        if screen_content {
+            encoder.push_str(" tune-content=screen");
+        }
+    }
+    encoder
+}
@@ -0,0 +1,95 @@
+use std::fmt::{Display, Formatter};
+use zbus::zvariant;
+
+pub type Result<T> = std::result::Result<T, ScreencastError>;
+
+#[derive(Debug)]
+pub enum ScreencastError {
+    UserCancelled,
+    Interrupted,
+    Timeout,
+    Unsupported(String),
+    PortalUnavailable,
+    SessionClosed,
+    SinkClosed,
+    InvalidResponse(String),
+    PipeWire(String),
+    GStreamer(String),
+    Encoder(String),
+    Streaming(String),
+    DBus(zbus::Error),
+    Io(std::io::Error),
+}
+
+impl Display for ScreencastError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScreencastError::UserCancelled => write!(f, "screencast cancelled by user"),
+            ScreencastError::Interrupted => write!(f, "screencast request was interrupted"),
+            ScreencastError::Timeout => write!(f, "timed out waiting for portal response"),
+            ScreencastError::Unsupported(message) => write!(f, "unsupported: {}", message),
+            ScreencastError::PortalUnavailable => write!(f, "screencast portal is unavailable"),
+            ScreencastError::SessionClosed => write!(f, "screencast session is closed"),
+            ScreencastError::SinkClosed => write!(f, "frame sink has stopped"),
+            ScreencastError::InvalidResponse(message) => {
+                write!(f, "invalid portal response: {}", message)
+            }
+            ScreencastError::PipeWire(message) => write!(f, "pipewire error: {}", message),
+            ScreencastError::GStreamer(message) => write!(f, "gstreamer error: {}", message),
+            ScreencastError::Encoder(message) => write!(f, "encoder error: {}", message),
+            ScreencastError::Streaming(message) => write!(f, "streaming error: {}", message),
+            ScreencastError::DBus(err) => write!(f, "dbus error: {}", err),
+            ScreencastError::Io(err) => write!(f, "io error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ScreencastError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ScreencastError::DBus(err) => Some(err),
+            ScreencastError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<zbus::Error> for ScreencastError {
+    fn from(err: zbus::Error) -> Self {
+        match err {
+            zbus::Error::MethodError(ref name, _, _)
+                if name.as_str() == "org.freedesktop.DBus.Error.ServiceUnknown"
+                    || name.as_str() == "org.freedesktop.DBus.Error.UnknownInterface" =>
+            {
+                ScreencastError::PortalUnavailable
+            }
+            zbus::Error::FDO(ref err)
+                if matches!(
+                    **err,
+                    zbus::fdo::Error::ServiceUnknown(_) | zbus::fdo::Error::UnknownInterface(_)
+                ) =>
+            {
+                ScreencastError::PortalUnavailable
+            }
+            err => ScreencastError::DBus(err),
+        }
+    }
+}
+
+impl From<zbus::fdo::Error> for ScreencastError {
+    fn from(err: zbus::fdo::Error) -> Self {
+        ScreencastError::from(zbus::Error::from(err))
+    }
+}
+
+impl From<zvariant::Error> for ScreencastError {
+    fn from(err: zvariant::Error) -> Self {
+        ScreencastError::InvalidResponse(err.to_string())
+    }
+}
+
+impl From<std::io::Error> for ScreencastError {
+    fn from(err: std::io::Error) -> Self {
+        ScreencastError::Io(err)
+    }
+}
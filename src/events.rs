@@ -0,0 +1,90 @@
+use crate::format::StreamInfo;
+use crate::screencast::SelectedSource;
+use async_broadcast::{InactiveReceiver, Receiver, Sender};
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use zbus::zvariant::OwnedObjectPath;
+
+const EVENT_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone)]
+pub enum ScreencastEvent {
+    SessionCreated {
+        session_handle: OwnedObjectPath,
+    },
+    SourcesSelected,
+    Started {
+        streams: Vec<SelectedSource>,
+    },
+    SessionClosed {
+        reason: CloseReason,
+    },
+    PortalRestarted,
+    /// A stream fixed its format, sent again whenever it is renegotiated.
+    FormatChanged {
+        info: StreamInfo,
+    },
+    /// The source changed resolution, frames keep flowing at the new size.
+    Resized {
+        node_id: u32,
+        width: u32,
+        height: u32,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CloseReason {
+    Application,
+    Compositor,
+    Cancelled,
+    Failed,
+}
+
+#[derive(Debug)]
+pub struct EventStream {
+    receiver: Receiver<ScreencastEvent>,
+}
+
+impl Stream for EventStream {
+    type Item = ScreencastEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct EventSender {
+    sender: Sender<ScreencastEvent>,
+    receiver: InactiveReceiver<ScreencastEvent>,
+}
+
+impl Default for EventSender {
+    fn default() -> Self {
+        let (mut sender, receiver) = async_broadcast::broadcast(EVENT_CAPACITY);
+        sender.set_overflow(true);
+        EventSender {
+            sender,
+            receiver: receiver.deactivate(),
+        }
+    }
+}
+
+impl EventSender {
+    pub(crate) fn subscribe(&self) -> EventStream {
+        EventStream {
+            receiver: self.receiver.activate_cloned(),
+        }
+    }
+
+    pub(crate) fn has_subscribers(&self) -> bool {
+        self.sender.receiver_count() > 0
+    }
+
+    pub(crate) fn send(&self, event: ScreencastEvent) {
+        if self.has_subscribers() {
+            let _ = self.sender.try_broadcast(event);
+        }
+    }
+}
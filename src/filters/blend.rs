@@ -0,0 +1,62 @@
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+
+/// A packed RGB image drawn onto frames.
+pub(super) struct Image<'d> {
+    pub(super) pixel_format: PixelFormat,
+    pub(super) width: u32,
+    pub(super) height: u32,
+    pub(super) stride: usize,
+    pub(super) data: &'d [u8],
+}
+
+/// Alpha blends `image` with its top left corner at `(left, top)`, scaled by
+/// `opacity` from 0 to 255. YUV and DMA-BUF frames are left untouched.
+pub(super) fn blend(frame: &mut Frame<'_>, image: &Image<'_>, left: i64, top: i64, opacity: u32) {
+    let (Some(dst_offsets), Some(src_offsets)) = (
+        frame.pixel_format().rgba_offsets(),
+        image.pixel_format.rgba_offsets(),
+    ) else {
+        return;
+    };
+    if frame.is_dmabuf() || opacity == 0 {
+        return;
+    }
+    let dst_bpp = frame.pixel_format().bytes_per_pixel();
+    let src_bpp = image.pixel_format.bytes_per_pixel();
+    let (width, height, stride) = (frame.width() as i64, frame.height() as i64, frame.stride());
+    let data = frame.data_mut();
+    for row in 0..image.height as i64 {
+        let y = top + row;
+        if y < 0 || y >= height {
+            continue;
+        }
+        for column in 0..image.width as i64 {
+            let x = left + column;
+            if x < 0 || x >= width {
+                continue;
+            }
+            let src_index = row as usize * image.stride + column as usize * src_bpp;
+            let dst_index = y as usize * stride + x as usize * dst_bpp;
+            let (Some(src), Some(dst)) = (
+                image.data.get(src_index..src_index + src_bpp),
+                data.get_mut(dst_index..dst_index + dst_bpp),
+            ) else {
+                continue;
+            };
+            let alpha = src.get(src_offsets[3]).copied().unwrap_or(255) as u32 * opacity / 255;
+            if alpha == 0 {
+                continue;
+            }
+            for channel in 0..3 {
+                let src_value = src[src_offsets[channel]] as u32;
+                let dst_value = &mut dst[dst_offsets[channel]];
+                *dst_value =
+                    ((src_value * alpha + *dst_value as u32 * (255 - alpha) + 127) / 255) as u8;
+            }
+            if let Some(dst_alpha) = dst.get_mut(dst_offsets[3]) {
+                *dst_alpha = (alpha + *dst_alpha as u32 * (255 - alpha) / 255) as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::blend::{Image, blend};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Reported click positions, `None` for the cursor position.
+type Pending = Vec<Option<(i32, i32)>>;
+
+/// Feeds clicks into a `ClickHighlight` from any thread, e.g. from a libei
+/// or RemoteDesktop input stream.
+#[derive(Debug, Clone, Default)]
+pub struct ClickHandle {
+    pending: Arc<Mutex<Pending>>,
+}
+
+impl ClickHandle {
+    /// A click at the cursor position of the next frame.
+    pub fn click(&self) {
+        self.pending.lock().unwrap().push(None);
+    }
+
+    /// A click at a position in frame coordinates, for sessions without
+    /// cursor metadata.
+    pub fn click_at(&self, x: i32, y: i32) {
+        self.pending.lock().unwrap().push(Some((x, y)));
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Click {
+    position: (i32, i32),
+    start: Duration,
+}
+
+/// Draws an expanding, fading ring where clicks happened, as seen in
+/// tutorial recordings. A click starts on the first frame after it was
+/// reported and is animated by the frame timestamps. YUV and DMA-BUF
+/// frames are passed through.
+#[derive(Debug, Clone)]
+pub struct ClickHighlight {
+    handle: ClickHandle,
+    active: Vec<Click>,
+    duration: Duration,
+    radius: (u32, u32),
+    thickness: u32,
+    color: [u8; 4],
+}
+
+impl Default for ClickHighlight {
+    fn default() -> Self {
+        ClickHighlight {
+            handle: ClickHandle::default(),
+            active: Vec::new(),
+            duration: Duration::from_millis(400),
+            radius: (8, 40),
+            thickness: 4,
+            color: [255, 200, 0, 220],
+        }
+    }
+}
+
+impl ClickHighlight {
+    pub fn new() -> Self {
+        ClickHighlight::default()
+    }
+
+    pub fn handle(&self) -> ClickHandle {
+        self.handle.clone()
+    }
+
+    /// How long the ring grows and fades.
+    pub fn with_duration(mut self, duration: Duration) -> Self {
+        self.duration = duration;
+        self
+    }
+
+    /// Ring radius at the start and the end of the animation, in pixels.
+    pub fn with_radius(mut self, start: u32, end: u32) -> Self {
+        self.radius = (start, end);
+        self
+    }
+
+    pub fn with_thickness(mut self, thickness: u32) -> Self {
+        self.thickness = thickness.max(1);
+        self
+    }
+
+    /// RGBA, the alpha is the opacity at the start of the animation.
+    pub fn with_color(mut self, color: [u8; 4]) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn apply<'a>(&mut self, mut frame: Frame<'a>) -> Frame<'a> {
+        let now = frame.pts().unwrap_or_default();
+        let cursor = frame.cursor().map(|cursor| cursor.position);
+        for position in self.handle.pending.lock().unwrap().drain(..) {
+            if let Some(position) = position.or(cursor) {
+                self.active.push(Click {
+                    position,
+                    start: now,
+                });
+            }
+        }
+        self.active
+            .retain(|click| now.saturating_sub(click.start) < self.duration);
+        if frame.is_dmabuf() || frame.pixel_format().is_yuv() {
+            return frame;
+        }
+        for click in &self.active {
+            let progress = now.saturating_sub(click.start).as_secs_f32()
+                / self.duration.as_secs_f32().max(f32::EPSILON);
+            let (start, end) = (self.radius.0 as f32, self.radius.1 as f32);
+            let radius = start + (end - start) * progress;
+            let opacity = ((1.0 - progress) * 255.0) as u32;
+            let (size, rgba) = ring(radius, self.thickness as f32, self.color);
+            let image = Image {
+                pixel_format: PixelFormat::Rgba,
+                width: size as u32,
+                height: size as u32,
+                stride: size * 4,
+                data: &rgba,
+            };
+            let half = (size / 2) as i64;
+            let (x, y) = (click.position.0 as i64, click.position.1 as i64);
+            blend(&mut frame, &image, x - half, y - half, opacity);
+        }
+        frame
+    }
+}
+
+/// An antialiased ring centered in a square RGBA image of odd size.
+fn ring(radius: f32, thickness: f32, color: [u8; 4]) -> (usize, Vec<u8>) {
+    let half = (radius + thickness).ceil() as usize + 1;
+    let size = half * 2 + 1;
+    let mut rgba = vec![0u8; size * size * 4];
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = (x as f32 - half as f32, y as f32 - half as f32);
+            let distance = ((dx * dx + dy * dy).sqrt() - radius).abs();
+            let coverage = (thickness / 2.0 + 0.5 - distance).clamp(0.0, 1.0);
+            if coverage > 0.0 {
+                let index = (y * size + x) * 4;
+                rgba[index..index + 3].copy_from_slice(&color[..3]);
+                rgba[index + 3] = (color[3] as f32 * coverage) as u8;
+            }
+        }
+    }
+    (size, rgba)
+}
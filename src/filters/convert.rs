@@ -0,0 +1,29 @@
+use crate::convert;
+use crate::error::Result;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+
+/// Converts memory frames to a fixed pixel format, e.g. ahead of filters
+/// that only draw on packed RGB. Frames already in that format and DMA-BUF
+/// frames are passed through.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Convert {
+    pixel_format: PixelFormat,
+}
+
+impl Convert {
+    pub fn new(pixel_format: PixelFormat) -> Self {
+        Convert { pixel_format }
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    pub fn apply<'a>(&self, frame: Frame<'a>) -> Result<Frame<'a>> {
+        if frame.is_dmabuf() || frame.pixel_format() == self.pixel_format {
+            return Ok(frame);
+        }
+        convert::convert(&frame, self.pixel_format)
+    }
+}
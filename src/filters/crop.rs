@@ -0,0 +1,157 @@
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane, Rect};
+
+/// Cuts a fixed region out of memory frames, e.g. to record one application
+/// area of a monitor. The rectangle is in stream coordinates and clipped to
+/// the frame, YUV frames round it to even offsets. DMA-BUF frames are
+/// passed through.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Crop {
+    rect: Rect,
+}
+
+impl Crop {
+    pub fn new(rect: Rect) -> Self {
+        Crop { rect }
+    }
+
+    pub fn rect(&self) -> Rect {
+        self.rect
+    }
+
+    pub fn apply<'a>(&self, frame: Frame<'a>) -> Frame<'a> {
+        if frame.is_dmabuf() {
+            return frame;
+        }
+        let pixel_format = frame.pixel_format();
+        let (frame_width, frame_height) = (frame.width() as i64, frame.height() as i64);
+        let mut left = (self.rect.x as i64).clamp(0, frame_width);
+        let mut top = (self.rect.y as i64).clamp(0, frame_height);
+        let right = (self.rect.x as i64 + self.rect.width as i64).clamp(left, frame_width);
+        let bottom = (self.rect.y as i64 + self.rect.height as i64).clamp(top, frame_height);
+        if pixel_format.is_yuv() {
+            left -= left % 2;
+        }
+        if pixel_format.is_planar() {
+            top -= top % 2;
+        }
+        let (x, y) = (left as usize, top as usize);
+        let (width, height) = ((right - left) as usize, (bottom - top) as usize);
+        if width == 0 || height == 0 || (width as i64, height as i64) == (frame_width, frame_height)
+        {
+            return frame;
+        }
+        let stride = match pixel_format {
+            PixelFormat::Nv12 | PixelFormat::I420 => width.next_multiple_of(2),
+            // YUY2 keeps whole Y0 U Y1 V pairs
+            PixelFormat::Yuy2 => width.next_multiple_of(2) * 2,
+            _ => width * pixel_format.bytes_per_pixel(),
+        };
+        let planes = Plane::contiguous(pixel_format, height as u32, stride);
+        let mut out = Vec::new();
+        for (index, plane) in planes.iter().enumerate() {
+            let Some(src_plane) = frame.planes().get(index) else {
+                return frame;
+            };
+            // byte offset and length of the cropped part of each row
+            let (row_offset, row_len, first_row) = match (pixel_format, index) {
+                (PixelFormat::Nv12, 1) => (x, width.div_ceil(2) * 2, y / 2),
+                (PixelFormat::I420, 1..) => (x / 2, width.div_ceil(2), y / 2),
+                (PixelFormat::Yuy2, _) => (x * 2, stride, y),
+                _ => {
+                    let bpp = pixel_format.bytes_per_pixel();
+                    (x * bpp, width * bpp, y)
+                }
+            };
+            out.resize(plane.offset, 0);
+            for row in 0..Plane::rows(pixel_format, index, height as u32) {
+                let start = src_plane.offset + (first_row + row) * src_plane.stride + row_offset;
+                let end = out.len() + plane.stride;
+                match frame.data().get(start..start + row_len) {
+                    Some(src) => out.extend_from_slice(src),
+                    None => return frame,
+                }
+                out.resize(end, 0);
+            }
+        }
+        let cropped = frame
+            .with_image(pixel_format, width as u32, height as u32, planes, out)
+            .with_transform(frame.transform());
+        match frame.cursor() {
+            Some(cursor) => {
+                let mut cursor = cursor.clone();
+                cursor.position.0 -= x as i32;
+                cursor.position.1 -= y as i32;
+                cropped.with_cursor(cursor)
+            }
+            None => cropped,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Cursor;
+
+    /// A 4x4 RGB frame, each pixel holding its index.
+    fn frame() -> Frame<'static> {
+        let data = (0..16u8).flat_map(|index| [index; 3]).collect::<Vec<_>>();
+        Frame::packed(PixelFormat::Rgb, 4, 4, 12, data)
+    }
+
+    fn pixels(frame: &Frame<'_>) -> Vec<u8> {
+        frame.data().iter().step_by(3).copied().collect()
+    }
+
+    #[test]
+    fn region() {
+        let cursor = Cursor {
+            position: (3, 3),
+            hotspot: (0, 0),
+            bitmap: None,
+        };
+        let frame = frame().with_cursor(cursor);
+        let cropped = Crop::new(Rect::new(1, 2, 2, 2)).apply(frame);
+        assert_eq!(
+            (cropped.width(), cropped.height(), cropped.stride()),
+            (2, 2, 6)
+        );
+        assert_eq!(pixels(&cropped), [9, 10, 13, 14]);
+        assert_eq!(cropped.cursor().unwrap().position, (2, 1));
+    }
+
+    #[test]
+    fn clipped_to_the_frame() {
+        let cropped = Crop::new(Rect::new(-2, 3, 4, 10)).apply(frame());
+        assert_eq!((cropped.width(), cropped.height()), (2, 1));
+        assert_eq!(pixels(&cropped), [12, 13]);
+
+        // nothing or all of the frame left is the frame as it was
+        let outside = Crop::new(Rect::new(8, 8, 2, 2)).apply(frame());
+        assert_eq!((outside.width(), outside.height()), (4, 4));
+        let all = Crop::new(Rect::new(-1, -1, 10, 10)).apply(frame());
+        assert_eq!(pixels(&all), (0..16).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn yuv_at_even_offsets() {
+        // Y holds its index, U 100 + index, V 200 + index
+        let data = [
+            (0..16).collect::<Vec<u8>>(),
+            (100..104).collect(),
+            (200..204).collect(),
+        ]
+        .concat();
+        let frame = Frame::packed(PixelFormat::I420, 4, 4, 4, data);
+        let cropped = Crop::new(Rect::new(3, 3, 1, 1)).apply(frame);
+        assert_eq!((cropped.width(), cropped.height()), (2, 2));
+        assert_eq!(cropped.data(), [10, 11, 14, 15, 103, 203]);
+
+        let data = (0..16).collect::<Vec<u8>>();
+        let frame = Frame::packed(PixelFormat::Yuy2, 4, 2, 8, data);
+        let cropped = Crop::new(Rect::new(1, 1, 2, 1)).apply(frame);
+        assert_eq!((cropped.width(), cropped.height()), (3, 1));
+        assert_eq!(cropped.data(), [8, 9, 10, 11, 12, 13, 14, 15]);
+    }
+}
@@ -0,0 +1,35 @@
+use super::blend::{Image, blend};
+use crate::frame::{Cursor, Frame};
+
+/// Blends the metadata cursor into the frame on the CPU, so sessions using
+/// `CursorMode::Metadata` still produce video with a visible pointer.
+///
+/// Frames without a cursor bitmap, YUV frames and DMA-BUF frames are
+/// passed through untouched.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CursorOverlay;
+
+impl CursorOverlay {
+    pub fn apply<'a>(&self, mut frame: Frame<'a>) -> Frame<'a> {
+        if let Some(cursor) = frame.cursor().cloned() {
+            blend_cursor(&mut frame, &cursor);
+        }
+        frame
+    }
+}
+
+fn blend_cursor(frame: &mut Frame<'_>, cursor: &Cursor) {
+    let Some(bitmap) = &cursor.bitmap else {
+        return;
+    };
+    let image = Image {
+        pixel_format: bitmap.pixel_format,
+        width: bitmap.width,
+        height: bitmap.height,
+        stride: bitmap.stride,
+        data: &bitmap.data,
+    };
+    let left = cursor.position.0 as i64 - cursor.hotspot.0 as i64;
+    let top = cursor.position.1 as i64 - cursor.hotspot.1 as i64;
+    blend(frame, &image, left, top, 255);
+}
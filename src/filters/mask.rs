@@ -0,0 +1,257 @@
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Rect};
+
+/// How masked regions are hidden.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MaskStyle {
+    #[default]
+    Black,
+    /// Box blur of the given radius in pixels, applied three times.
+    Blur { radius: u32 },
+}
+
+/// Hides regions of memory frames, e.g. a password manager or the
+/// notification corner, before they reach a sink. Rectangles are in frame
+/// coordinates and clipped to the frame. DMA-BUF frames are passed through.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Mask {
+    regions: Vec<Rect>,
+    style: MaskStyle,
+}
+
+/// A rectangle clipped to a plane, in pixels of that plane.
+#[derive(Debug, Copy, Clone)]
+struct Area {
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl Mask {
+    pub fn new(regions: Vec<Rect>) -> Self {
+        Mask {
+            regions,
+            style: MaskStyle::default(),
+        }
+    }
+
+    pub fn with_style(mut self, style: MaskStyle) -> Self {
+        self.style = style;
+        self
+    }
+
+    pub fn regions(&self) -> &[Rect] {
+        &self.regions
+    }
+
+    pub fn apply<'a>(&self, mut frame: Frame<'a>) -> Frame<'a> {
+        if frame.is_dmabuf() || self.regions.is_empty() {
+            return frame;
+        }
+        let pixel_format = frame.pixel_format();
+        let (width, height) = (frame.width(), frame.height());
+        let planes = frame.planes().to_vec();
+        let data = frame.data_mut();
+        for rect in &self.regions {
+            for (index, plane) in planes.iter().enumerate() {
+                // chroma planes of NV12 and I420 are subsampled by two, YUY2
+                // is handled in Y0 U Y1 V pairs
+                let (scale, channels) = match (pixel_format, index) {
+                    (PixelFormat::Nv12, 1) => ((2, 2), 2),
+                    (PixelFormat::I420, 1..) => ((2, 2), 1),
+                    (PixelFormat::Yuy2, _) => ((2, 1), 4),
+                    _ => ((1, 1), pixel_format.bytes_per_pixel()),
+                };
+                let Some(area) = clip(rect, width, height, scale) else {
+                    continue;
+                };
+                let Some(plane_data) = data.get_mut(plane.offset..) else {
+                    continue;
+                };
+                if plane.stride * (area.y + area.height - 1) + (area.x + area.width) * channels
+                    > plane_data.len()
+                {
+                    continue;
+                }
+                match self.style {
+                    MaskStyle::Black => {
+                        let black = black(pixel_format, index);
+                        for y in area.y..area.y + area.height {
+                            let start = y * plane.stride + area.x * channels;
+                            for pixel in plane_data[start..start + area.width * channels]
+                                .chunks_exact_mut(channels)
+                            {
+                                pixel.copy_from_slice(&black[..channels]);
+                            }
+                        }
+                    }
+                    MaskStyle::Blur { radius } => {
+                        let radius = (radius as usize / scale.0).max(1);
+                        for _ in 0..3 {
+                            blur(plane_data, plane.stride, channels, area, radius);
+                        }
+                    }
+                }
+            }
+        }
+        frame
+    }
+}
+
+fn clip(rect: &Rect, width: u32, height: u32, (scale_x, scale_y): (usize, usize)) -> Option<Area> {
+    let left = (rect.x as i64).clamp(0, width as i64) as usize;
+    let top = (rect.y as i64).clamp(0, height as i64) as usize;
+    let right = (rect.x as i64 + rect.width as i64).clamp(0, width as i64) as usize;
+    let bottom = (rect.y as i64 + rect.height as i64).clamp(0, height as i64) as usize;
+    let area = Area {
+        x: left / scale_x,
+        y: top / scale_y,
+        width: right.div_ceil(scale_x).saturating_sub(left / scale_x),
+        height: bottom.div_ceil(scale_y).saturating_sub(top / scale_y),
+    };
+    (area.width > 0 && area.height > 0).then_some(area)
+}
+
+/// Black for the plane, keeping opaque alpha on formats that have it.
+fn black(pixel_format: PixelFormat, index: usize) -> [u8; 4] {
+    match (pixel_format, index) {
+        (PixelFormat::Nv12 | PixelFormat::I420, 0) => [16; 4],
+        (PixelFormat::Nv12 | PixelFormat::I420, _) => [128; 4],
+        (PixelFormat::Yuy2, _) => [16, 128, 16, 128],
+        _ => {
+            let mut black = [0; 4];
+            if let Some(offsets) = pixel_format.rgba_offsets()
+                && offsets[3] != usize::MAX
+            {
+                black[offsets[3]] = 255;
+            }
+            black
+        }
+    }
+}
+
+/// One horizontal and one vertical box blur pass over the area, edges are
+/// clamped to the area so nothing from outside leaks in.
+fn blur(data: &mut [u8], stride: usize, channels: usize, area: Area, radius: usize) {
+    let mut line = Vec::new();
+    for y in area.y..area.y + area.height {
+        let start = y * stride + area.x * channels;
+        line.clear();
+        line.extend_from_slice(&data[start..start + area.width * channels]);
+        box_line(
+            &line,
+            &mut data[start..],
+            area.width,
+            channels,
+            channels,
+            radius,
+        );
+    }
+    for x in 0..area.width {
+        line.clear();
+        for y in area.y..area.y + area.height {
+            let start = y * stride + (area.x + x) * channels;
+            line.extend_from_slice(&data[start..start + channels]);
+        }
+        let start = area.y * stride + (area.x + x) * channels;
+        box_line(
+            &line,
+            &mut data[start..],
+            area.height,
+            channels,
+            stride,
+            radius,
+        );
+    }
+}
+
+/// Blurs `len` pixels of `src` into `dst`, whose pixels are `step` bytes apart.
+fn box_line(src: &[u8], dst: &mut [u8], len: usize, channels: usize, step: usize, radius: usize) {
+    let window = (2 * radius + 1) as u32;
+    for channel in 0..channels {
+        let at = |index: isize| src[index.clamp(0, len as isize - 1) as usize * channels + channel];
+        let mut sum: u32 = (-(radius as isize)..=radius as isize)
+            .map(|index| at(index) as u32)
+            .sum();
+        for index in 0..len {
+            dst[index * step + channel] = (sum / window) as u8;
+            let index = index as isize;
+            sum = sum + at(index + radius as isize + 1) as u32 - at(index - radius as isize) as u32;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gray(frame: &Frame<'_>) -> Vec<u8> {
+        frame.data().iter().step_by(3).copied().collect()
+    }
+
+    fn rgb(values: &[u8], width: u32) -> Frame<'static> {
+        let data = values
+            .iter()
+            .flat_map(|value| [*value; 3])
+            .collect::<Vec<_>>();
+        let height = values.len() as u32 / width;
+        Frame::packed(PixelFormat::Rgb, width, height, width as usize * 3, data)
+    }
+
+    #[test]
+    fn black() {
+        let frame = Frame::packed(PixelFormat::Bgra, 3, 2, 12, vec![9; 24]);
+        let masked = Mask::new(vec![Rect::new(1, 1, 5, 5)]).apply(frame);
+        assert_eq!(&masked.data()[..16], [9; 16]);
+        assert_eq!(&masked.data()[16..], [0, 0, 0, 255, 0, 0, 0, 255]);
+
+        let frame = Frame::packed(PixelFormat::Rgbx, 1, 1, 4, vec![9; 4]);
+        let masked = Mask::new(vec![Rect::new(0, 0, 1, 1)]).apply(frame);
+        assert_eq!(masked.data(), [0; 4]);
+
+        let frame = Frame::packed(PixelFormat::I420, 4, 4, 4, vec![200; 24]);
+        let masked = Mask::new(vec![Rect::new(1, 1, 2, 2)]).apply(frame);
+        #[rustfmt::skip]
+        assert_eq!(
+            &masked.data()[..16],
+            [
+                200, 200, 200, 200,
+                200, 16, 16, 200,
+                200, 16, 16, 200,
+                200, 200, 200, 200,
+            ]
+        );
+        // the chroma of every pixel touched
+        assert_eq!(&masked.data()[16..], [128; 8]);
+    }
+
+    #[test]
+    fn outside_of_the_frame() {
+        let frame = rgb(&[1, 2, 3, 4], 2);
+        let masked = Mask::new(vec![Rect::new(2, 0, 4, 4), Rect::new(-3, -3, 2, 2)]).apply(frame);
+        assert_eq!(gray(&masked), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn blur() {
+        let blur = MaskStyle::Blur { radius: 1 };
+        let frame = rgb(&[0, 0, 90, 0, 0, 0], 6);
+        let masked = Mask::new(vec![Rect::new(1, 0, 4, 1)])
+            .with_style(blur)
+            .apply(frame);
+        let values = gray(&masked);
+        assert_eq!((values[0], values[5]), (0, 0));
+        assert!(
+            values[1..5].iter().all(|value| *value > 0 && *value < 90),
+            "{values:?}"
+        );
+
+        // nothing from outside the area leaks in
+        let frame = rgb(&[255, 0, 0, 0, 255, 255, 0, 0, 0, 255], 5);
+        let masked = Mask::new(vec![Rect::new(1, 0, 3, 2)])
+            .with_style(blur)
+            .apply(frame);
+        assert_eq!(gray(&masked), [255, 0, 0, 0, 255, 255, 0, 0, 0, 255]);
+    }
+}
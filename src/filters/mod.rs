@@ -0,0 +1,29 @@
+mod blend;
+mod click;
+mod convert;
+mod crop;
+mod cursor;
+mod font;
+mod mask;
+mod motion;
+mod overlay;
+mod pip;
+mod pipeline;
+mod scale;
+mod text;
+mod upright;
+mod zoom;
+
+pub use click::{ClickHandle, ClickHighlight};
+pub use convert::Convert;
+pub use crop::Crop;
+pub use cursor::CursorOverlay;
+pub use mask::{Mask, MaskStyle};
+pub use motion::{Motion, MotionDetect};
+pub use overlay::{Anchor, Overlay};
+pub use pip::{PictureInPicture, PipHandle};
+pub use pipeline::{Filtered, FrameFilter, FromFn, Pipeline, from_fn};
+pub use scale::{Scale, ScaleFilter};
+pub use text::{TextField, TextOverlay};
+pub use upright::Upright;
+pub use zoom::ZoomFollow;
@@ -0,0 +1,181 @@
+use crate::frame::{Frame, Rect};
+use std::time::Duration;
+
+const CELL: usize = 16;
+
+/// Where a frame differs from the previous one.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Motion {
+    /// Bounding box of the changed cells.
+    pub region: Rect,
+    /// Share of the frame that changed, from 0 to 1.
+    pub changed: f32,
+}
+
+/// Compares the average luma of 16x16 cells against the previous frame, to
+/// react on changes or to only let frames through while something moves.
+///
+/// Frames whose damage metadata is empty are treated as unchanged without
+/// looking at the pixels. DMA-BUF frames always count as changed.
+#[derive(Debug, Clone)]
+pub struct MotionDetect {
+    threshold: u8,
+    min_changed: f32,
+    hold: Duration,
+    last_motion: Option<Duration>,
+    previous: Option<Grid>,
+}
+
+#[derive(Debug, Clone)]
+struct Grid {
+    size: (u32, u32),
+    columns: usize,
+    cells: Vec<u8>,
+}
+
+impl Default for MotionDetect {
+    fn default() -> Self {
+        MotionDetect {
+            threshold: 8,
+            min_changed: 0.0,
+            hold: Duration::ZERO,
+            last_motion: None,
+            previous: None,
+        }
+    }
+}
+
+impl MotionDetect {
+    pub fn new() -> Self {
+        MotionDetect::default()
+    }
+
+    /// Luma difference below which a cell counts as unchanged, filtering
+    /// out dithering and compression noise.
+    pub fn with_threshold(mut self, threshold: u8) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Share of the frame, from 0 to 1, that has to change to count as
+    /// motion.
+    pub fn with_min_changed(mut self, min_changed: f32) -> Self {
+        self.min_changed = min_changed.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Keeps `apply` passing frames for this long after the last motion.
+    pub fn with_hold(mut self, hold: Duration) -> Self {
+        self.hold = hold;
+        self
+    }
+
+    /// Compares the frame with the previous one and remembers it.
+    pub fn detect(&mut self, frame: &Frame<'_>) -> Option<Motion> {
+        let full = Rect::new(0, 0, frame.width(), frame.height());
+        if frame.is_dmabuf() {
+            return Some(Motion {
+                region: full,
+                changed: 1.0,
+            });
+        }
+        if frame.damage().is_some_and(|damage| damage.is_empty()) && self.previous.is_some() {
+            return None;
+        }
+        let grid = Grid::new(frame)?;
+        let previous = self.previous.replace(grid);
+        let grid = self.previous.as_ref()?;
+        let Some(previous) = previous.filter(|previous| previous.size == grid.size) else {
+            return Some(Motion {
+                region: full,
+                changed: 1.0,
+            });
+        };
+        let mut changed = 0;
+        let (mut left, mut top, mut right, mut bottom) = (usize::MAX, usize::MAX, 0, 0);
+        for (index, (cell, previous)) in grid.cells.iter().zip(&previous.cells).enumerate() {
+            if cell.abs_diff(*previous) <= self.threshold {
+                continue;
+            }
+            let (x, y) = (index % grid.columns, index / grid.columns);
+            changed += 1;
+            left = left.min(x);
+            top = top.min(y);
+            right = right.max(x + 1);
+            bottom = bottom.max(y + 1);
+        }
+        let changed = changed as f32 / grid.cells.len() as f32;
+        if changed == 0.0 || changed < self.min_changed {
+            return None;
+        }
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        let (x, y) = (left * CELL, top * CELL);
+        Some(Motion {
+            region: Rect::new(
+                x as i32,
+                y as i32,
+                ((right * CELL).min(width) - x) as u32,
+                ((bottom * CELL).min(height) - y) as u32,
+            ),
+            changed,
+        })
+    }
+
+    /// Passes the frame while there is motion or within the hold time after
+    /// it, returns `None` otherwise.
+    pub fn apply<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        let now = frame.pts().unwrap_or_default();
+        if self.detect(&frame).is_some() {
+            self.last_motion = Some(now);
+            return Some(frame);
+        }
+        match self.last_motion {
+            Some(last) if now.saturating_sub(last) < self.hold => Some(frame),
+            _ => None,
+        }
+    }
+}
+
+impl Grid {
+    fn new(frame: &Frame<'_>) -> Option<Grid> {
+        let pixel_format = frame.pixel_format();
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        let columns = width.div_ceil(CELL);
+        let rows = height.div_ceil(CELL);
+        let mut sums = vec![0u32; columns * rows];
+        let mut counts = vec![0u32; columns * rows];
+        let plane = frame.planes().first()?;
+        let offsets = pixel_format.rgba_offsets().unwrap_or([0; 4]);
+        let bpp = pixel_format.bytes_per_pixel();
+        // every other pixel and row is plenty for cell averages
+        for y in (0..height).step_by(2) {
+            let start = plane.offset + y * plane.stride;
+            let row = frame.data().get(start..start + width * bpp)?;
+            for x in (0..width).step_by(2) {
+                let pixel = &row[x * bpp..(x + 1) * bpp];
+                let value = match pixel_format.is_yuv() {
+                    true => pixel[0] as u32,
+                    false => {
+                        (pixel[offsets[0]] as u32 * 77
+                            + pixel[offsets[1]] as u32 * 150
+                            + pixel[offsets[2]] as u32 * 29)
+                            >> 8
+                    }
+                };
+                let cell = (y / CELL) * columns + x / CELL;
+                sums[cell] += value;
+                counts[cell] += 1;
+            }
+        }
+        let cells = sums
+            .iter()
+            .zip(&counts)
+            .map(|(sum, count)| (sum / (*count).max(1)) as u8)
+            .collect();
+        Some(Grid {
+            size: (frame.width(), frame.height()),
+            columns,
+            cells,
+        })
+    }
+}
@@ -0,0 +1,201 @@
+use super::blend::{Image, blend};
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::image;
+use std::sync::Arc;
+
+/// The frame corner an overlay is placed relative to.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum Anchor {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+    Center,
+}
+
+impl Anchor {
+    /// Top left corner of a `size` box placed in the frame.
+    pub(super) fn position(
+        &self,
+        frame: &Frame<'_>,
+        (width, height): (u32, u32),
+        (margin_x, margin_y): (i32, i32),
+    ) -> (i64, i64) {
+        let right = frame.width() as i64 - width as i64 - margin_x as i64;
+        let bottom = frame.height() as i64 - height as i64 - margin_y as i64;
+        let (margin_x, margin_y) = (margin_x as i64, margin_y as i64);
+        match self {
+            Anchor::TopLeft => (margin_x, margin_y),
+            Anchor::TopRight => (right, margin_y),
+            Anchor::BottomLeft => (margin_x, bottom),
+            Anchor::BottomRight => (right, bottom),
+            Anchor::Center => (
+                (frame.width() as i64 - width as i64) / 2,
+                (frame.height() as i64 - height as i64) / 2,
+            ),
+        }
+    }
+}
+
+/// Composites a fixed image, e.g. a logo or a "recording" badge, onto packed
+/// RGB frames. YUV and DMA-BUF frames are passed through.
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    width: u32,
+    height: u32,
+    rgba: Arc<[u8]>,
+    anchor: Anchor,
+    margin: (i32, i32),
+    opacity: f32,
+}
+
+impl Overlay {
+    /// `rgba` holds tightly packed rows with straight alpha.
+    pub fn from_rgba(width: u32, height: u32, rgba: Vec<u8>) -> Result<Self> {
+        if rgba.len() < width as usize * height as usize * 4 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{} bytes for a {}x{} overlay",
+                rgba.len(),
+                width,
+                height
+            )));
+        }
+        Ok(Overlay {
+            width,
+            height,
+            rgba: rgba.into(),
+            anchor: Anchor::default(),
+            margin: (16, 16),
+            opacity: 1.0,
+        })
+    }
+
+    pub fn from_png(png: &[u8]) -> Result<Self> {
+        let (width, height, rgba) = image::decode_png(png)?;
+        Overlay::from_rgba(width, height, rgba)
+    }
+
+    pub fn with_anchor(mut self, anchor: Anchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    /// Distance from the anchored edges, ignored for `Anchor::Center`.
+    pub fn with_margin(mut self, x: i32, y: i32) -> Self {
+        self.margin = (x, y);
+        self
+    }
+
+    /// From 0, invisible, to 1, the image's own alpha.
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn apply<'a>(&self, mut frame: Frame<'a>) -> Frame<'a> {
+        let (left, top) = self.anchor.position(&frame, self.size(), self.margin);
+        let image = Image {
+            pixel_format: PixelFormat::Rgba,
+            width: self.width,
+            height: self.height,
+            stride: self.width as usize * 4,
+            data: &self.rgba,
+        };
+        let opacity = (self.opacity * 255.0).round() as u32;
+        blend(&mut frame, &image, left, top, opacity);
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageFormat;
+
+    fn black() -> Frame<'static> {
+        Frame::packed(PixelFormat::Bgrx, 4, 4, 16, vec![0; 64])
+    }
+
+    /// The pixels of a BGRX frame that are not black.
+    fn drawn(frame: &Frame<'_>) -> Vec<((usize, usize), [u8; 3])> {
+        frame
+            .data()
+            .chunks(4)
+            .enumerate()
+            .filter(|(_, pixel)| pixel[..3] != [0; 3])
+            .map(|(index, pixel)| ((index % 4, index / 4), [pixel[0], pixel[1], pixel[2]]))
+            .collect()
+    }
+
+    #[test]
+    fn anchors() {
+        let red = Overlay::from_rgba(1, 1, vec![255, 0, 0, 255]).unwrap();
+        let placed = |anchor, margin: (i32, i32)| {
+            let overlay = red
+                .clone()
+                .with_anchor(anchor)
+                .with_margin(margin.0, margin.1);
+            drawn(&overlay.apply(black()))
+        };
+        assert_eq!(placed(Anchor::TopLeft, (0, 0)), [((0, 0), [0, 0, 255])]);
+        assert_eq!(placed(Anchor::TopRight, (1, 0)), [((2, 0), [0, 0, 255])]);
+        assert_eq!(placed(Anchor::BottomLeft, (0, 2)), [((0, 1), [0, 0, 255])]);
+        assert_eq!(placed(Anchor::BottomRight, (16, 16)), []);
+        assert_eq!(placed(Anchor::BottomRight, (0, 0)), [((3, 3), [0, 0, 255])]);
+
+        let square = Overlay::from_rgba(2, 2, vec![255; 16]).unwrap();
+        let centered = drawn(&square.with_anchor(Anchor::Center).apply(black()));
+        let positions: Vec<_> = centered.iter().map(|(position, _)| *position).collect();
+        assert_eq!(positions, [(1, 1), (2, 1), (1, 2), (2, 2)]);
+    }
+
+    #[test]
+    fn opacity() {
+        let white = Overlay::from_rgba(1, 1, vec![255; 4])
+            .unwrap()
+            .with_anchor(Anchor::TopLeft)
+            .with_margin(0, 0);
+        let half = white.clone().with_opacity(0.5).apply(black());
+        assert_eq!(drawn(&half), [((0, 0), [128; 3])]);
+        assert_eq!(drawn(&white.clone().with_opacity(0.0).apply(black())), []);
+
+        // straight alpha of the image on top of the opacity
+        let translucent = Overlay::from_rgba(1, 1, vec![255, 255, 255, 51])
+            .unwrap()
+            .with_anchor(Anchor::TopLeft)
+            .with_margin(0, 0);
+        assert_eq!(drawn(&translucent.apply(black())), [((0, 0), [51; 3])]);
+
+        let frame = Frame::packed(PixelFormat::Rgba, 1, 1, 4, vec![0; 4]);
+        let blended = white.with_opacity(0.2).apply(frame);
+        assert_eq!(blended.data(), [51, 51, 51, 51]);
+    }
+
+    #[test]
+    fn yuv_untouched() {
+        let white = Overlay::from_rgba(1, 1, vec![255; 4]).unwrap();
+        let frame = Frame::packed(PixelFormat::I420, 2, 2, 2, vec![16; 6]);
+        assert_eq!(white.apply(frame).data(), [16; 6]);
+    }
+
+    #[test]
+    fn sources() {
+        assert!(matches!(
+            Overlay::from_rgba(2, 2, vec![0; 15]),
+            Err(ScreencastError::Unsupported(_))
+        ));
+
+        let frame = Frame::packed(PixelFormat::Rgba, 3, 2, 12, vec![200; 24]);
+        let png = image::encode(&frame, ImageFormat::Png).unwrap();
+        let overlay = Overlay::from_png(&png).unwrap();
+        assert_eq!(overlay.size(), (3, 2));
+        assert!(Overlay::from_png(&png[..png.len() / 2]).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use super::blend::{Image, blend};
+use super::{Anchor, Scale, ScaleFilter};
+use crate::convert;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::sync::{Arc, Mutex};
+
+/// Feeds frames of a second source, e.g. a `Camera` capture, into a
+/// `PictureInPicture` from any thread. Only the latest frame is kept.
+#[derive(Debug, Clone, Default)]
+pub struct PipHandle {
+    latest: Arc<Mutex<Option<Frame<'static>>>>,
+}
+
+impl PipHandle {
+    pub fn push(&self, frame: Frame<'_>) {
+        *self.latest.lock().unwrap() = Some(frame.into_owned());
+    }
+
+    fn take(&self) -> Option<Frame<'static>> {
+        self.latest.lock().unwrap().take()
+    }
+}
+
+/// Composites the latest frame of a second source as a small window over
+/// packed RGB frames, typically a webcam over a screen recording. The window
+/// keeps the source's aspect ratio and can be moved and resized while
+/// recording. YUV and DMA-BUF frames are passed through.
+#[derive(Debug, Clone)]
+pub struct PictureInPicture {
+    handle: PipHandle,
+    source: Option<Frame<'static>>,
+    scaled: Option<Frame<'static>>,
+    width: u32,
+    anchor: Anchor,
+    margin: (i32, i32),
+    position: Option<(i32, i32)>,
+    opacity: f32,
+    mirror: bool,
+    filter: ScaleFilter,
+}
+
+impl Default for PictureInPicture {
+    fn default() -> Self {
+        PictureInPicture {
+            handle: PipHandle::default(),
+            source: None,
+            scaled: None,
+            width: 320,
+            anchor: Anchor::BottomRight,
+            margin: (16, 16),
+            position: None,
+            opacity: 1.0,
+            mirror: false,
+            filter: ScaleFilter::Bilinear,
+        }
+    }
+}
+
+impl PictureInPicture {
+    pub fn new() -> Self {
+        PictureInPicture::default()
+    }
+
+    pub fn handle(&self) -> PipHandle {
+        self.handle.clone()
+    }
+
+    /// Width of the window in pixels, the height follows the source.
+    pub fn with_width(mut self, width: u32) -> Self {
+        self.set_width(width);
+        self
+    }
+
+    pub fn with_anchor(mut self, anchor: Anchor) -> Self {
+        self.set_anchor(anchor);
+        self
+    }
+
+    /// Distance from the anchored edges, ignored for `Anchor::Center`.
+    pub fn with_margin(mut self, x: i32, y: i32) -> Self {
+        self.margin = (x, y);
+        self
+    }
+
+    pub fn with_opacity(mut self, opacity: f32) -> Self {
+        self.opacity = opacity.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Flips the source horizontally, how people expect to see themselves.
+    pub fn with_mirror(mut self, mirror: bool) -> Self {
+        self.mirror = mirror;
+        self.scaled = None;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ScaleFilter) -> Self {
+        self.filter = filter;
+        self.scaled = None;
+        self
+    }
+
+    pub fn set_width(&mut self, width: u32) {
+        self.width = width.max(1);
+        self.scaled = None;
+    }
+
+    /// Places the window relative to a frame corner again, undoing
+    /// `set_position`.
+    pub fn set_anchor(&mut self, anchor: Anchor) {
+        self.anchor = anchor;
+        self.position = None;
+    }
+
+    /// Places the top left corner of the window at a position in frame
+    /// coordinates, e.g. while it is dragged.
+    pub fn set_position(&mut self, x: i32, y: i32) {
+        self.position = Some((x, y));
+    }
+
+    /// Size of the window, `None` until the first source frame arrived.
+    pub fn size(&self) -> Option<(u32, u32)> {
+        self.source
+            .as_ref()
+            .map(|source| (self.width, scaled_height(source, self.width)))
+    }
+
+    pub fn apply<'a>(&mut self, mut frame: Frame<'a>) -> Frame<'a> {
+        if let Some(source) = self.handle.take()
+            && let Ok(source) = convert::convert(&source, PixelFormat::Rgba)
+        {
+            self.source = Some(source);
+            self.scaled = None;
+        }
+        if self.scaled.is_none()
+            && let Some(source) = &self.source
+        {
+            let height = scaled_height(source, self.width);
+            let mut scaled = Scale::new(self.width, height)
+                .with_filter(self.filter)
+                .apply(source.clone());
+            if self.mirror {
+                let (stride, len) = (scaled.stride(), scaled.width() as usize * 4);
+                for row in scaled.data_mut().chunks_exact_mut(stride) {
+                    mirror_row(&mut row[..len]);
+                }
+            }
+            self.scaled = Some(scaled);
+        }
+        let Some(scaled) = &self.scaled else {
+            return frame;
+        };
+        let size = (scaled.width(), scaled.height());
+        let (left, top) = match self.position {
+            Some((x, y)) => (x as i64, y as i64),
+            None => self.anchor.position(&frame, size, self.margin),
+        };
+        let image = Image {
+            pixel_format: PixelFormat::Rgba,
+            width: size.0,
+            height: size.1,
+            stride: scaled.stride(),
+            data: scaled.data(),
+        };
+        let opacity = (self.opacity * 255.0).round() as u32;
+        blend(&mut frame, &image, left, top, opacity);
+        frame
+    }
+}
+
+fn scaled_height(source: &Frame<'_>, width: u32) -> u32 {
+    let height = width as u64 * source.height() as u64 / source.width().max(1) as u64;
+    (height as u32).max(1)
+}
+
+fn mirror_row(row: &mut [u8]) {
+    let pixels = row.len() / 4;
+    for x in 0..pixels / 2 {
+        let (left, right) = row.split_at_mut((pixels - 1 - x) * 4);
+        left[x * 4..x * 4 + 4].swap_with_slice(&mut right[..4]);
+    }
+}
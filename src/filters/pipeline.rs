@@ -0,0 +1,207 @@
+use super::{
+    ClickHighlight, Convert, Crop, CursorOverlay, Mask, MotionDetect, Overlay, PictureInPicture,
+    Scale, TextOverlay, Upright, ZoomFollow,
+};
+use crate::compositor::Compositor;
+use crate::frame::Frame;
+use futures_core::Stream;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// One processing stage between capture and sink. Returning `None` drops the
+/// frame, e.g. for rate limiting or motion gating.
+pub trait FrameFilter {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>>;
+}
+
+/// Filters running in the order they were added, a frame dropped by one
+/// stage never reaches the next.
+#[derive(Default)]
+pub struct Pipeline {
+    filters: Vec<Box<dyn FrameFilter + Send>>,
+}
+
+impl std::fmt::Debug for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Pipeline")
+            .field("filters", &self.filters.len())
+            .finish()
+    }
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Pipeline::default()
+    }
+
+    pub fn with(mut self, filter: impl FrameFilter + Send + 'static) -> Self {
+        self.push(filter);
+        self
+    }
+
+    pub fn push(&mut self, filter: impl FrameFilter + Send + 'static) {
+        self.filters.push(Box::new(filter));
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    /// Runs every frame of `frames` through the pipeline.
+    pub fn run<S>(self, frames: S) -> Filtered<S>
+    where
+        S: Stream<Item = Frame<'static>> + Unpin,
+    {
+        Filtered {
+            pipeline: self,
+            frames,
+        }
+    }
+}
+
+impl FrameFilter for Pipeline {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        self.filters
+            .iter_mut()
+            .try_fold(frame, |frame, filter| filter.process(frame))
+    }
+}
+
+/// See `Pipeline::run`.
+#[derive(Debug)]
+pub struct Filtered<S> {
+    pipeline: Pipeline,
+    frames: S,
+}
+
+impl<S> Filtered<S> {
+    pub fn pipeline(&mut self) -> &mut Pipeline {
+        &mut self.pipeline
+    }
+}
+
+impl<S> Stream for Filtered<S>
+where
+    S: Stream<Item = Frame<'static>> + Unpin,
+{
+    type Item = Frame<'static>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame<'static>>> {
+        loop {
+            let Some(frame) = std::task::ready!(Pin::new(&mut self.frames).poll_next(cx)) else {
+                return Poll::Ready(None);
+            };
+            if let Some(frame) = self.pipeline.process(frame) {
+                return Poll::Ready(Some(frame));
+            }
+        }
+    }
+}
+
+/// A filter from a closure, see `from_fn`.
+#[derive(Debug, Clone)]
+pub struct FromFn<F> {
+    f: F,
+}
+
+/// Turns a closure into a `FrameFilter`.
+pub fn from_fn<F>(f: F) -> FromFn<F>
+where
+    F: for<'a> FnMut(Frame<'a>) -> Option<Frame<'a>>,
+{
+    FromFn { f }
+}
+
+impl<F> FrameFilter for FromFn<F>
+where
+    F: for<'a> FnMut(Frame<'a>) -> Option<Frame<'a>>,
+{
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        (self.f)(frame)
+    }
+}
+
+impl FrameFilter for Convert {
+    /// Frames that fail to convert are dropped.
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        self.apply(frame).ok()
+    }
+}
+
+impl FrameFilter for Crop {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for CursorOverlay {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for Mask {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for MotionDetect {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        self.apply(frame)
+    }
+}
+
+impl FrameFilter for Overlay {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for PictureInPicture {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for Scale {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for TextOverlay {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for ClickHighlight {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for Upright {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for ZoomFollow {
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        Some(self.apply(frame))
+    }
+}
+
+impl FrameFilter for Compositor {
+    /// Adds the frame to its slot and yields the whole canvas.
+    fn process<'a>(&mut self, frame: Frame<'a>) -> Option<Frame<'a>> {
+        self.push(&frame).ok()?;
+        self.compose().ok()
+    }
+}
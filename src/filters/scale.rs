@@ -0,0 +1,283 @@
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane};
+use std::f32::consts::PI;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum ScaleFilter {
+    Nearest,
+    #[default]
+    Bilinear,
+    /// Lanczos with a 3 lobe window, sharpest but the slowest.
+    Lanczos3,
+}
+
+impl ScaleFilter {
+    fn support(&self) -> f32 {
+        match self {
+            ScaleFilter::Nearest | ScaleFilter::Bilinear => 1.0,
+            ScaleFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    fn kernel(&self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ScaleFilter::Nearest | ScaleFilter::Bilinear => (1.0 - x).max(0.0),
+            ScaleFilter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ScaleFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    match x {
+        0.0 => 1.0,
+        x => (PI * x).sin() / (PI * x),
+    }
+}
+
+/// Resizes memory frames to a fixed resolution, e.g. a 4K monitor down to
+/// 1080p before encoding. DMA-BUF frames are passed through.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Scale {
+    width: u32,
+    height: u32,
+    filter: ScaleFilter,
+}
+
+impl Scale {
+    pub fn new(width: u32, height: u32) -> Self {
+        Scale {
+            width,
+            height,
+            filter: ScaleFilter::default(),
+        }
+    }
+
+    pub fn with_filter(mut self, filter: ScaleFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn apply<'a>(&self, frame: Frame<'a>) -> Frame<'a> {
+        if frame.is_dmabuf()
+            || self.width == 0
+            || self.height == 0
+            || (frame.width(), frame.height()) == (self.width, self.height)
+        {
+            return frame;
+        }
+        let pixel_format = frame.pixel_format();
+        let (dst_width, dst_height) = (self.width as usize, self.height as usize);
+        let stride = match pixel_format {
+            PixelFormat::Nv12 | PixelFormat::I420 => dst_width.next_multiple_of(2),
+            PixelFormat::Yuy2 => dst_width.next_multiple_of(2) * 2,
+            _ => dst_width * pixel_format.bytes_per_pixel(),
+        };
+        let planes = Plane::contiguous(pixel_format, self.height, stride);
+        let mut out = Vec::new();
+        for (index, plane) in planes.iter().enumerate() {
+            let Some(src_plane) = frame.planes().get(index) else {
+                return frame;
+            };
+            let (channels, src_size, dst_size) = match (pixel_format, index) {
+                (PixelFormat::Nv12, 1) => (2, chroma(&frame), chroma_size(dst_width, dst_height)),
+                (PixelFormat::I420, 1..) => (1, chroma(&frame), chroma_size(dst_width, dst_height)),
+                // Y0 U Y1 V pairs are resampled as one four channel pixel
+                (PixelFormat::Yuy2, _) => (
+                    4,
+                    (frame.width().div_ceil(2) as usize, frame.height() as usize),
+                    (dst_width.div_ceil(2), dst_height),
+                ),
+                _ => (
+                    pixel_format.bytes_per_pixel(),
+                    (frame.width() as usize, frame.height() as usize),
+                    (dst_width, dst_height),
+                ),
+            };
+            let src = &frame.data()[src_plane.offset.min(frame.data().len())..];
+            out.resize(plane.offset, 0);
+            let scaled = resample(
+                src,
+                src_plane.stride,
+                src_size,
+                channels,
+                dst_size,
+                plane.stride,
+                self.filter,
+            );
+            out.extend_from_slice(&scaled);
+        }
+        frame.with_image(pixel_format, self.width, self.height, planes, out)
+    }
+}
+
+fn chroma(frame: &Frame<'_>) -> (usize, usize) {
+    chroma_size(frame.width() as usize, frame.height() as usize)
+}
+
+fn chroma_size(width: usize, height: usize) -> (usize, usize) {
+    (width.div_ceil(2), height.div_ceil(2))
+}
+
+/// Contributing source indices and normalized weights per destination index.
+fn contributions(src_len: usize, dst_len: usize, filter: ScaleFilter) -> Vec<(usize, Vec<f32>)> {
+    let ratio = src_len as f32 / dst_len as f32;
+    let stretch = ratio.max(1.0);
+    let support = filter.support() * stretch;
+    (0..dst_len)
+        .map(|index| {
+            let center = (index as f32 + 0.5) * ratio;
+            if filter == ScaleFilter::Nearest {
+                return ((center as usize).min(src_len - 1), vec![1.0]);
+            }
+            let start = ((center - support).floor().max(0.0) as usize).min(src_len - 1);
+            let end = ((center + support).ceil() as usize).clamp(start + 1, src_len);
+            let mut weights: Vec<f32> = (start..end)
+                .map(|source| filter.kernel((source as f32 + 0.5 - center) / stretch))
+                .collect();
+            let sum: f32 = weights.iter().sum();
+            if sum.abs() > f32::EPSILON {
+                weights.iter_mut().for_each(|weight| *weight /= sum);
+            } else {
+                let nearest = (center as usize).clamp(start, end - 1) - start;
+                weights.iter_mut().for_each(|weight| *weight = 0.0);
+                weights[nearest] = 1.0;
+            }
+            (start, weights)
+        })
+        .collect()
+}
+
+/// Separable resampling of an interleaved plane, horizontal pass first.
+fn resample(
+    src: &[u8],
+    src_stride: usize,
+    (src_width, src_height): (usize, usize),
+    channels: usize,
+    (dst_width, dst_height): (usize, usize),
+    dst_stride: usize,
+    filter: ScaleFilter,
+) -> Vec<u8> {
+    let mut out = vec![0u8; dst_stride * dst_height];
+    if src_width == 0 || src_height == 0 {
+        return out;
+    }
+    let columns = contributions(src_width, dst_width, filter);
+    let rows = contributions(src_height, dst_height, filter);
+    let row_len = dst_width * channels;
+    let mut horizontal = vec![0f32; row_len * src_height];
+    for y in 0..src_height {
+        let Some(src_row) = src.get(y * src_stride..y * src_stride + src_width * channels) else {
+            break;
+        };
+        let dst_row = &mut horizontal[y * row_len..(y + 1) * row_len];
+        for (x, (start, weights)) in columns.iter().enumerate() {
+            for channel in 0..channels {
+                dst_row[x * channels + channel] = weights
+                    .iter()
+                    .enumerate()
+                    .map(|(offset, weight)| {
+                        src_row[(start + offset) * channels + channel] as f32 * weight
+                    })
+                    .sum();
+            }
+        }
+    }
+    for (y, (start, weights)) in rows.iter().enumerate() {
+        let dst_row = &mut out[y * dst_stride..y * dst_stride + row_len];
+        for (index, value) in dst_row.iter_mut().enumerate() {
+            let sum: f32 = weights
+                .iter()
+                .enumerate()
+                .map(|(offset, weight)| horizontal[(start + offset) * row_len + index] * weight)
+                .sum();
+            *value = sum.round().clamp(0.0, 255.0) as u8;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(width: u32, height: u32, pixel: impl Fn(u32, u32) -> u8) -> Frame<'static> {
+        let mut data = Vec::new();
+        for y in 0..height {
+            for x in 0..width {
+                data.extend([pixel(x, y); 3]);
+            }
+        }
+        Frame::packed(PixelFormat::Rgb, width, height, width as usize * 3, data)
+    }
+
+    fn gray(frame: &Frame<'_>) -> Vec<u8> {
+        frame.data().iter().step_by(3).copied().collect()
+    }
+
+    #[test]
+    fn nearest() {
+        let frame = rgb(4, 2, |x, y| (x + 10 * y) as u8);
+        let nearest = Scale::new(2, 1).with_filter(ScaleFilter::Nearest);
+        let scaled = nearest.apply(frame.clone());
+        assert_eq!(
+            (scaled.width(), scaled.height(), scaled.stride()),
+            (2, 1, 6)
+        );
+        assert_eq!(gray(&scaled), [11, 13]);
+
+        let scaled = Scale::new(8, 1)
+            .with_filter(ScaleFilter::Nearest)
+            .apply(frame);
+        assert_eq!(gray(&scaled), [10, 10, 11, 11, 12, 12, 13, 13]);
+    }
+
+    #[test]
+    fn bilinear() {
+        let frame = rgb(2, 1, |x, _| x as u8 * 200);
+        let scaled = Scale::new(4, 1).apply(frame);
+        assert_eq!(gray(&scaled), [0, 50, 150, 200]);
+
+        // downscaling widens the kernel to what a pixel covers
+        let frame = rgb(4, 1, |x, _| [0, 100, 100, 200][x as usize]);
+        let scaled = Scale::new(2, 1).apply(frame);
+        assert_eq!(gray(&scaled), [57, 143]);
+    }
+
+    #[test]
+    fn flat_colors_stay_flat() {
+        let frame = Frame::packed(PixelFormat::Rgba, 5, 3, 20, [10, 20, 30, 255].repeat(15));
+        for filter in [ScaleFilter::Bilinear, ScaleFilter::Lanczos3] {
+            for (width, height) in [(2, 2), (11, 7)] {
+                let scaled = Scale::new(width, height)
+                    .with_filter(filter)
+                    .apply(frame.clone());
+                assert_eq!((scaled.width(), scaled.height()), (width, height));
+                let pixels = (width * height) as usize;
+                assert_eq!(
+                    scaled.data(),
+                    [10, 20, 30, 255].repeat(pixels),
+                    "{filter:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn yuv_planes() {
+        let data = [[100; 16].as_slice(), &[50; 4], &[200; 4]].concat();
+        let frame = Frame::packed(PixelFormat::I420, 4, 4, 4, data);
+        let scaled = Scale::new(2, 2).apply(frame);
+        assert_eq!(scaled.planes(), Plane::contiguous(PixelFormat::I420, 2, 2));
+        assert_eq!(scaled.data(), [100, 100, 100, 100, 50, 200]);
+    }
+
+    #[test]
+    fn passes_through() {
+        let data = [0u8; 12];
+        let frame = Frame::packed(PixelFormat::Rgb, 2, 2, 6, data.as_slice());
+        assert!(Scale::new(2, 2).apply(frame.clone()).is_borrowed());
+        assert!(Scale::new(0, 1).apply(frame).is_borrowed());
+    }
+}
@@ -0,0 +1,192 @@
+use super::Anchor;
+use super::blend::{Image, blend};
+use super::font;
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A piece of text drawn by `TextOverlay`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TextField {
+    /// `YYYY-MM-DD HH:MM:SS` when the frame is processed.
+    WallClock,
+    /// `HH:MM:SS.mmm` since the first frame, from the frame timestamps.
+    Elapsed,
+    /// Frames per second over the last second of timestamps.
+    Fps,
+    Sequence,
+    Text(String),
+}
+
+/// Burns timing information into packed RGB frames, e.g. to debug capture
+/// performance or for surveillance style recordings. YUV and DMA-BUF
+/// frames are passed through.
+#[derive(Debug, Clone)]
+pub struct TextOverlay {
+    fields: Vec<TextField>,
+    anchor: Anchor,
+    margin: (i32, i32),
+    scale: u32,
+    color: [u8; 4],
+    background: [u8; 4],
+    utc_offset: i32,
+    first_pts: Option<Duration>,
+    recent: VecDeque<Duration>,
+}
+
+impl Default for TextOverlay {
+    fn default() -> Self {
+        TextOverlay::new(vec![
+            TextField::WallClock,
+            TextField::Elapsed,
+            TextField::Fps,
+        ])
+    }
+}
+
+impl TextOverlay {
+    pub fn new(fields: Vec<TextField>) -> Self {
+        TextOverlay {
+            fields,
+            anchor: Anchor::TopLeft,
+            margin: (16, 16),
+            scale: 2,
+            color: [255, 255, 255, 255],
+            background: [0, 0, 0, 160],
+            utc_offset: 0,
+            first_pts: None,
+            recent: VecDeque::new(),
+        }
+    }
+
+    pub fn with_anchor(mut self, anchor: Anchor) -> Self {
+        self.anchor = anchor;
+        self
+    }
+
+    pub fn with_margin(mut self, x: i32, y: i32) -> Self {
+        self.margin = (x, y);
+        self
+    }
+
+    /// Size of a glyph pixel, glyphs are 5x7.
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// RGBA text and background colors, the background is drawn behind the
+    /// whole line.
+    pub fn with_colors(mut self, color: [u8; 4], background: [u8; 4]) -> Self {
+        self.color = color;
+        self.background = background;
+        self
+    }
+
+    /// The wall clock is UTC shifted by this many seconds.
+    pub fn with_utc_offset(mut self, seconds: i32) -> Self {
+        self.utc_offset = seconds;
+        self
+    }
+
+    pub fn apply<'a>(&mut self, mut frame: Frame<'a>) -> Frame<'a> {
+        let text = self.text(&frame);
+        if frame.is_dmabuf() || frame.pixel_format().is_yuv() {
+            return frame;
+        }
+        let (width, height, rgba) =
+            font::render(&text, self.scale as usize, self.color, self.background);
+        let image = Image {
+            pixel_format: PixelFormat::Rgba,
+            width: width as u32,
+            height: height as u32,
+            stride: width * 4,
+            data: &rgba,
+        };
+        let (left, top) = self
+            .anchor
+            .position(&frame, (width as u32, height as u32), self.margin);
+        blend(&mut frame, &image, left, top, 255);
+        frame
+    }
+
+    fn text(&mut self, frame: &Frame<'_>) -> String {
+        let pts = frame.pts();
+        if let Some(pts) = pts {
+            self.first_pts.get_or_insert(pts);
+            self.recent.push_back(pts);
+            while let Some(oldest) = self.recent.front()
+                && pts.saturating_sub(*oldest) > Duration::from_secs(1)
+            {
+                self.recent.pop_front();
+            }
+        }
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|field| match field {
+                TextField::WallClock => wall_clock(self.utc_offset),
+                TextField::Elapsed => {
+                    let elapsed = pts
+                        .zip(self.first_pts)
+                        .map_or(Duration::ZERO, |(pts, first)| pts - first);
+                    let millis = elapsed.as_millis();
+                    format!(
+                        "{:02}:{:02}:{:02}.{:03}",
+                        millis / 3_600_000,
+                        millis / 60_000 % 60,
+                        millis / 1000 % 60,
+                        millis % 1000
+                    )
+                }
+                TextField::Fps => {
+                    let span = match (self.recent.front(), self.recent.back()) {
+                        (Some(oldest), Some(newest)) => (*newest - *oldest).as_secs_f64(),
+                        _ => 0.0,
+                    };
+                    let fps = match span > 0.0 {
+                        true => (self.recent.len() - 1) as f64 / span,
+                        false => 0.0,
+                    };
+                    format!("{:.1} FPS", fps)
+                }
+                TextField::Sequence => format!("#{}", frame.sequence()),
+                TextField::Text(text) => text.clone(),
+            })
+            .collect();
+        fields.join("  ")
+    }
+}
+
+fn wall_clock(utc_offset: i32) -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+        + utc_offset as i64;
+    let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+    // civil date from days since the epoch, after Howard Hinnant
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
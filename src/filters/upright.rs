@@ -0,0 +1,58 @@
+use crate::frame::{Frame, Plane, Transform};
+
+/// Applies `Frame::transform` on the CPU so rotated or flipped outputs come
+/// out upright.
+///
+/// Only packed RGB frames are rotated, YUV and DMA-BUF frames are passed
+/// through with their transform left for the consumer.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct Upright;
+
+impl Upright {
+    pub fn apply<'a>(&self, frame: Frame<'a>) -> Frame<'a> {
+        let transform = frame.transform();
+        if transform == Transform::Normal || frame.is_dmabuf() || frame.pixel_format().is_yuv() {
+            return frame;
+        }
+        let bpp = frame.pixel_format().bytes_per_pixel();
+        let (width, height) = (frame.width() as usize, frame.height() as usize);
+        let (out_width, out_height) = match transform.is_transposed() {
+            true => (height, width),
+            false => (width, height),
+        };
+        let stride = frame.stride();
+        let src = frame.data();
+        let out_stride = out_width * bpp;
+        let mut out = vec![0u8; out_stride * out_height];
+        for oy in 0..out_height {
+            for ox in 0..out_width {
+                let (sx, sy) = match transform.degrees() {
+                    90 => (width - 1 - oy, ox),
+                    180 => (width - 1 - ox, height - 1 - oy),
+                    270 => (oy, height - 1 - ox),
+                    _ => (ox, oy),
+                };
+                let sx = match transform.is_flipped() {
+                    true => width - 1 - sx,
+                    false => sx,
+                };
+                let src_index = sy * stride + sx * bpp;
+                let out_index = oy * out_stride + ox * bpp;
+                if let Some(pixel) = src.get(src_index..src_index + bpp) {
+                    out[out_index..out_index + bpp].copy_from_slice(pixel);
+                }
+            }
+        }
+        let pixel_format = frame.pixel_format();
+        frame.with_image(
+            pixel_format,
+            out_width as u32,
+            out_height as u32,
+            vec![Plane {
+                offset: 0,
+                stride: out_stride,
+            }],
+            out,
+        )
+    }
+}
@@ -0,0 +1,110 @@
+use super::{Crop, Scale, ScaleFilter};
+use crate::frame::{Frame, Rect};
+use std::time::Duration;
+
+/// A virtual camera following the metadata cursor: crops a window around
+/// it, panning and zooming smoothly, and scales it back to the frame size.
+///
+/// Frames without cursor metadata keep the last position. DMA-BUF frames are
+/// passed through.
+#[derive(Debug, Clone)]
+pub struct ZoomFollow {
+    zoom: f32,
+    target_zoom: f32,
+    smoothing: Duration,
+    filter: ScaleFilter,
+    center: Option<(f32, f32)>,
+    last_pts: Option<Duration>,
+}
+
+impl Default for ZoomFollow {
+    fn default() -> Self {
+        ZoomFollow {
+            zoom: 2.0,
+            target_zoom: 2.0,
+            smoothing: Duration::from_millis(250),
+            filter: ScaleFilter::Bilinear,
+            center: None,
+            last_pts: None,
+        }
+    }
+}
+
+impl ZoomFollow {
+    pub fn new() -> Self {
+        ZoomFollow::default()
+    }
+
+    /// Magnification, 1 shows the whole frame.
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom.max(1.0);
+        self.target_zoom = self.zoom;
+        self
+    }
+
+    /// Time constant of the camera movement, zero jumps straight to the
+    /// cursor.
+    pub fn with_smoothing(mut self, smoothing: Duration) -> Self {
+        self.smoothing = smoothing;
+        self
+    }
+
+    pub fn with_filter(mut self, filter: ScaleFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Animates to a new magnification, e.g. 1 to zoom out while idle.
+    pub fn set_zoom(&mut self, zoom: f32) {
+        self.target_zoom = zoom.max(1.0);
+    }
+
+    pub fn zoom(&self) -> f32 {
+        self.zoom
+    }
+
+    pub fn apply<'a>(&mut self, frame: Frame<'a>) -> Frame<'a> {
+        if frame.is_dmabuf() {
+            return frame;
+        }
+        let (width, height) = (frame.width() as f32, frame.height() as f32);
+        let pts = frame.pts().unwrap_or_default();
+        let elapsed = self
+            .last_pts
+            .map_or(Duration::ZERO, |last| pts.saturating_sub(last));
+        self.last_pts = Some(pts);
+        // exponential approach, independent of the framerate
+        let step = match self.smoothing.is_zero() {
+            true => 1.0,
+            false => 1.0 - (-elapsed.as_secs_f32() / self.smoothing.as_secs_f32()).exp(),
+        };
+        let target = frame
+            .cursor()
+            .map(|cursor| (cursor.position.0 as f32, cursor.position.1 as f32))
+            .or(self.center)
+            .unwrap_or((width / 2.0, height / 2.0));
+        let center = match self.center {
+            Some((x, y)) => (x + (target.0 - x) * step, y + (target.1 - y) * step),
+            None => target,
+        };
+        self.center = Some(center);
+        self.zoom += (self.target_zoom - self.zoom) * step;
+        if self.zoom <= 1.0 + f32::EPSILON {
+            return frame;
+        }
+        let (view_width, view_height) = (width / self.zoom, height / self.zoom);
+        let left = (center.0 - view_width / 2.0).clamp(0.0, width - view_width);
+        let top = (center.1 - view_height / 2.0).clamp(0.0, height - view_height);
+        let rect = Rect::new(
+            left.round() as i32,
+            top.round() as i32,
+            view_width.round().max(1.0) as u32,
+            view_height.round().max(1.0) as u32,
+        );
+        let (frame_width, frame_height) = (frame.width(), frame.height());
+        let cropped = Crop::new(rect).apply(frame);
+        Scale::new(frame_width, frame_height)
+            .with_filter(self.filter)
+            .apply(cropped)
+    }
+}
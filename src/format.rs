@@ -0,0 +1,111 @@
+/// Pixel layouts a screencast stream can be negotiated to, packed RGB formats
+/// are named after their byte order in memory.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    Bgrx,
+    Bgra,
+    Rgbx,
+    Rgba,
+    Xrgb,
+    Argb,
+    Xbgr,
+    Abgr,
+    Rgb,
+    Bgr,
+    /// Full resolution Y plane followed by an interleaved half resolution UV
+    /// plane.
+    Nv12,
+    /// Y, U and V planes, chroma at half resolution.
+    I420,
+    /// Packed Y0 U Y1 V, chroma at half horizontal resolution, what most
+    /// webcams deliver.
+    Yuy2,
+}
+
+impl PixelFormat {
+    pub const ALL: [PixelFormat; 13] = [
+        PixelFormat::Bgrx,
+        PixelFormat::Bgra,
+        PixelFormat::Rgbx,
+        PixelFormat::Rgba,
+        PixelFormat::Xrgb,
+        PixelFormat::Argb,
+        PixelFormat::Xbgr,
+        PixelFormat::Abgr,
+        PixelFormat::Rgb,
+        PixelFormat::Bgr,
+        PixelFormat::Nv12,
+        PixelFormat::I420,
+        PixelFormat::Yuy2,
+    ];
+
+    /// Bytes per pixel of the first plane.
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelFormat::Rgb | PixelFormat::Bgr => 3,
+            PixelFormat::Yuy2 => 2,
+            PixelFormat::Nv12 | PixelFormat::I420 => 1,
+            _ => 4,
+        }
+    }
+
+    pub fn is_planar(&self) -> bool {
+        matches!(self, PixelFormat::Nv12 | PixelFormat::I420)
+    }
+
+    pub fn is_yuv(&self) -> bool {
+        self.is_planar() || *self == PixelFormat::Yuy2
+    }
+
+    /// Byte offsets of red, green, blue and alpha within a packed RGB pixel.
+    pub(crate) fn rgba_offsets(&self) -> Option<[usize; 4]> {
+        let (r, g, b, a) = match self {
+            PixelFormat::Rgbx => (0, 1, 2, None),
+            PixelFormat::Rgba => (0, 1, 2, Some(3)),
+            PixelFormat::Bgrx => (2, 1, 0, None),
+            PixelFormat::Bgra => (2, 1, 0, Some(3)),
+            PixelFormat::Xrgb => (1, 2, 3, None),
+            PixelFormat::Argb => (1, 2, 3, Some(0)),
+            PixelFormat::Xbgr => (3, 2, 1, None),
+            PixelFormat::Abgr => (3, 2, 1, Some(0)),
+            PixelFormat::Rgb => (0, 1, 2, None),
+            PixelFormat::Bgr => (2, 1, 0, None),
+            PixelFormat::Nv12 | PixelFormat::I420 | PixelFormat::Yuy2 => return None,
+        };
+        Some([r, g, b, a.unwrap_or(usize::MAX)])
+    }
+
+    pub fn plane_count(&self) -> usize {
+        match self {
+            PixelFormat::Nv12 => 2,
+            PixelFormat::I420 => 3,
+            _ => 1,
+        }
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        matches!(
+            self,
+            PixelFormat::Bgra | PixelFormat::Rgba | PixelFormat::Argb | PixelFormat::Abgr
+        )
+    }
+}
+
+/// The format a stream settled on during negotiation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct VideoFormat {
+    pub pixel_format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    /// `(0, 1)` when the compositor only delivers frames on damage.
+    pub framerate: (u32, u32),
+    /// Set when the stream negotiated DMA-BUF buffers.
+    pub modifier: Option<u64>,
+}
+
+/// The format negotiated for one source's stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StreamInfo {
+    pub node_id: u32,
+    pub format: VideoFormat,
+}
@@ -0,0 +1,498 @@
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use std::borrow::Cow;
+use std::fmt::{Debug, Formatter};
+use std::os::fd::OwnedFd;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Linear layout, importable by any consumer.
+pub const DRM_FORMAT_MOD_LINEAR: u64 = 0;
+/// The driver picks the layout implicitly.
+pub const DRM_FORMAT_MOD_INVALID: u64 = 0x00ff_ffff_ffff_ffff;
+
+/// Where a plane starts in the frame data and how many bytes a row takes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Plane {
+    pub offset: usize,
+    pub stride: usize,
+}
+
+impl Plane {
+    /// Planes laid out back to back, `stride` is the stride of the first
+    /// plane and chroma planes of I420 use half of it.
+    pub fn contiguous(pixel_format: PixelFormat, height: u32, stride: usize) -> Vec<Plane> {
+        let height = height as usize;
+        let chroma_height = height.div_ceil(2);
+        match pixel_format {
+            PixelFormat::Nv12 => vec![
+                Plane { offset: 0, stride },
+                Plane {
+                    offset: stride * height,
+                    stride,
+                },
+            ],
+            PixelFormat::I420 => {
+                let chroma_stride = stride.div_ceil(2);
+                vec![
+                    Plane { offset: 0, stride },
+                    Plane {
+                        offset: stride * height,
+                        stride: chroma_stride,
+                    },
+                    Plane {
+                        offset: stride * height + chroma_stride * chroma_height,
+                        stride: chroma_stride,
+                    },
+                ]
+            }
+            _ => vec![Plane { offset: 0, stride }],
+        }
+    }
+
+    /// Rows in this plane of a frame `height` pixels high.
+    pub fn rows(pixel_format: PixelFormat, index: usize, height: u32) -> usize {
+        match (pixel_format.is_planar(), index) {
+            (true, 1..) => height.div_ceil(2) as usize,
+            _ => height as usize,
+        }
+    }
+}
+
+/// One plane of a DMA-BUF, the fd is a duplicate owned by the frame.
+#[derive(Debug)]
+pub struct DmaBufPlane {
+    pub fd: OwnedFd,
+    pub offset: u32,
+    pub stride: u32,
+}
+
+/// GPU memory handed over by the compositor without a CPU copy.
+#[derive(Debug)]
+pub struct DmaBuf {
+    pub modifier: u64,
+    pub planes: Vec<DmaBufPlane>,
+}
+
+/// A rectangle in frame coordinates.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: u32, height: u32) -> Self {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+}
+
+/// How the buffer content has to be transformed to appear upright: an optional
+/// horizontal flip followed by a counter-clockwise rotation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub enum Transform {
+    #[default]
+    Normal,
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    Flipped,
+    Flipped90,
+    Flipped180,
+    Flipped270,
+}
+
+impl Transform {
+    pub fn is_flipped(&self) -> bool {
+        matches!(
+            self,
+            Transform::Flipped
+                | Transform::Flipped90
+                | Transform::Flipped180
+                | Transform::Flipped270
+        )
+    }
+
+    /// Counter-clockwise rotation in degrees.
+    pub fn degrees(&self) -> u32 {
+        match self {
+            Transform::Normal | Transform::Flipped => 0,
+            Transform::Rotate90 | Transform::Flipped90 => 90,
+            Transform::Rotate180 | Transform::Flipped180 => 180,
+            Transform::Rotate270 | Transform::Flipped270 => 270,
+        }
+    }
+
+    /// Whether width and height swap.
+    pub fn is_transposed(&self) -> bool {
+        matches!(self.degrees(), 90 | 270)
+    }
+}
+
+/// Pointer image sent by the compositor in `CursorMode::Metadata`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct CursorBitmap {
+    pub pixel_format: PixelFormat,
+    pub width: u32,
+    pub height: u32,
+    pub stride: usize,
+    pub data: Vec<u8>,
+}
+
+impl Debug for CursorBitmap {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CursorBitmap")
+            .field("pixel_format", &self.pixel_format)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("stride", &self.stride)
+            .finish()
+    }
+}
+
+/// Pointer position relative to the frame, the bitmap is the last one the
+/// compositor sent since it only resends it when the image changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor {
+    pub position: (i32, i32),
+    pub hotspot: (i32, i32),
+    pub bitmap: Option<Arc<CursorBitmap>>,
+}
+
+#[derive(Debug, Clone)]
+enum FrameData<'a> {
+    Memory(Cow<'a, [u8]>),
+    DmaBuf(Arc<DmaBuf>),
+}
+
+/// A video frame, either borrowing the PipeWire buffer it was read from,
+/// owning a copy of it, or referencing a DMA-BUF.
+#[derive(Clone)]
+pub struct Frame<'a> {
+    pixel_format: PixelFormat,
+    width: u32,
+    height: u32,
+    planes: Vec<Plane>,
+    data: FrameData<'a>,
+    pts: Option<Duration>,
+    sequence: u64,
+    node_id: u32,
+    cursor: Option<Cursor>,
+    crop: Option<Rect>,
+    transform: Transform,
+    damage: Option<Vec<Rect>>,
+}
+
+impl<'a> Frame<'a> {
+    pub fn new(
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+        planes: Vec<Plane>,
+        data: impl Into<Cow<'a, [u8]>>,
+    ) -> Self {
+        Frame {
+            pixel_format,
+            width,
+            height,
+            planes,
+            data: FrameData::Memory(data.into()),
+            pts: None,
+            sequence: 0,
+            node_id: 0,
+            cursor: None,
+            crop: None,
+            transform: Transform::Normal,
+            damage: None,
+        }
+    }
+
+    /// A frame with its planes back to back, see `Plane::contiguous`.
+    pub fn packed(
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+        stride: usize,
+        data: impl Into<Cow<'a, [u8]>>,
+    ) -> Self {
+        Frame::new(
+            pixel_format,
+            width,
+            height,
+            Plane::contiguous(pixel_format, height, stride),
+            data,
+        )
+    }
+
+    /// The planes mirror the offsets and strides of the DMA-BUF planes.
+    pub fn from_dmabuf(pixel_format: PixelFormat, width: u32, height: u32, dmabuf: DmaBuf) -> Self {
+        let planes = dmabuf
+            .planes
+            .iter()
+            .map(|plane| Plane {
+                offset: plane.offset as usize,
+                stride: plane.stride as usize,
+            })
+            .collect();
+        Frame {
+            pixel_format,
+            width,
+            height,
+            planes,
+            data: FrameData::DmaBuf(Arc::new(dmabuf)),
+            pts: None,
+            sequence: 0,
+            node_id: 0,
+            cursor: None,
+            crop: None,
+            transform: Transform::Normal,
+            damage: None,
+        }
+    }
+
+    pub fn with_pts(mut self, pts: Duration) -> Self {
+        self.pts = Some(pts);
+        self
+    }
+
+    pub fn with_sequence(mut self, sequence: u64) -> Self {
+        self.sequence = sequence;
+        self
+    }
+
+    pub fn with_node_id(mut self, node_id: u32) -> Self {
+        self.node_id = node_id;
+        self
+    }
+
+    pub fn with_cursor(mut self, cursor: Cursor) -> Self {
+        self.cursor = Some(cursor);
+        self
+    }
+
+    pub fn with_crop(mut self, crop: Rect) -> Self {
+        self.crop = Some(crop);
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_damage(mut self, damage: Vec<Rect>) -> Self {
+        self.damage = Some(damage);
+        self
+    }
+
+    /// An owned frame with new image data, keeping the timing and source but
+    /// not the geometry dependent metadata.
+    pub(crate) fn with_image(
+        &self,
+        pixel_format: PixelFormat,
+        width: u32,
+        height: u32,
+        planes: Vec<Plane>,
+        data: Vec<u8>,
+    ) -> Frame<'static> {
+        Frame {
+            pixel_format,
+            width,
+            height,
+            planes,
+            data: FrameData::Memory(Cow::Owned(data)),
+            pts: self.pts,
+            sequence: self.sequence,
+            node_id: self.node_id,
+            cursor: None,
+            crop: None,
+            transform: Transform::Normal,
+            damage: None,
+        }
+    }
+
+    pub fn pixel_format(&self) -> PixelFormat {
+        self.pixel_format
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn planes(&self) -> &[Plane] {
+        &self.planes
+    }
+
+    /// Row stride of the first plane.
+    pub fn stride(&self) -> usize {
+        self.planes.first().map_or(0, |plane| plane.stride)
+    }
+
+    /// The mapped bytes, empty for DMA-BUF frames.
+    pub fn data(&self) -> &[u8] {
+        match &self.data {
+            FrameData::Memory(data) => data,
+            FrameData::DmaBuf(_) => &[],
+        }
+    }
+
+    /// Copies the data first if it is still borrowed.
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        match &mut self.data {
+            FrameData::Memory(data) => data.to_mut(),
+            FrameData::DmaBuf(_) => &mut [],
+        }
+    }
+
+    pub fn dmabuf(&self) -> Option<&DmaBuf> {
+        match &self.data {
+            FrameData::DmaBuf(dmabuf) => Some(dmabuf),
+            FrameData::Memory(_) => None,
+        }
+    }
+
+    pub fn is_dmabuf(&self) -> bool {
+        matches!(self.data, FrameData::DmaBuf(_))
+    }
+
+    pub fn plane_data(&self, index: usize) -> Option<&[u8]> {
+        let data = self.data();
+        let plane = self.planes.get(index)?;
+        let rows = Plane::rows(self.pixel_format, index, self.height);
+        let end = (plane.offset + plane.stride * rows).min(data.len());
+        data.get(plane.offset..end)
+    }
+
+    /// Presentation time on `CLOCK_MONOTONIC`, strictly increasing per
+    /// stream. Captured frames always carry one, see `timebase::Timebase`
+    /// for muxer timestamps.
+    pub fn pts(&self) -> Option<Duration> {
+        self.pts
+    }
+
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// The PipeWire node of the source this frame was captured from.
+    pub fn node_id(&self) -> u32 {
+        self.node_id
+    }
+
+    /// Only present when the session uses `CursorMode::Metadata`.
+    pub fn cursor(&self) -> Option<&Cursor> {
+        self.cursor.as_ref()
+    }
+
+    /// The valid region when the compositor pads the buffer, e.g. for
+    /// window captures smaller than the negotiated size.
+    pub fn crop(&self) -> Option<Rect> {
+        self.crop
+    }
+
+    /// Set for rotated or flipped outputs, see `filters::Upright`.
+    pub fn transform(&self) -> Transform {
+        self.transform
+    }
+
+    /// Tightly packed RGBA rows, the layout most texture uploads take and
+    /// what makes an `image` crate buffer without a dependency on it here:
+    /// `RgbaImage::from_raw(frame.width(), frame.height(), frame.to_rgba()?)`.
+    /// DMA-BUF frames are not mapped.
+    pub fn to_rgba(&self) -> Result<Vec<u8>> {
+        match convert::convert(self, PixelFormat::Rgba)?.data {
+            FrameData::Memory(data) => Ok(data.into_owned()),
+            FrameData::DmaBuf(_) => Err(ScreencastError::Unsupported(
+                "RGBA of DMA-BUF frames".to_string(),
+            )),
+        }
+    }
+
+    /// Regions that changed since the previous frame, when the compositor
+    /// reports them. Empty means nothing changed.
+    pub fn damage(&self) -> Option<&[Rect]> {
+        self.damage.as_deref()
+    }
+
+    pub fn is_borrowed(&self) -> bool {
+        matches!(self.data, FrameData::Memory(Cow::Borrowed(_)))
+    }
+
+    pub fn into_owned(self) -> Frame<'static> {
+        Frame {
+            pixel_format: self.pixel_format,
+            width: self.width,
+            height: self.height,
+            planes: self.planes,
+            data: match self.data {
+                FrameData::Memory(data) => FrameData::Memory(Cow::Owned(data.into_owned())),
+                FrameData::DmaBuf(dmabuf) => FrameData::DmaBuf(dmabuf),
+            },
+            pts: self.pts,
+            sequence: self.sequence,
+            node_id: self.node_id,
+            cursor: self.cursor,
+            crop: self.crop,
+            transform: self.transform,
+            damage: self.damage,
+        }
+    }
+}
+
+impl Debug for Frame<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Frame")
+            .field("pixel_format", &self.pixel_format)
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("planes", &self.planes)
+            .field("len", &self.data().len())
+            .field("dmabuf", &self.dmabuf())
+            .field("pts", &self.pts)
+            .field("sequence", &self.sequence)
+            .field("node_id", &self.node_id)
+            .field("cursor", &self.cursor)
+            .field("crop", &self.crop)
+            .field("transform", &self.transform)
+            .field("damage", &self.damage)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_rgba() {
+        let bgra = Frame::packed(PixelFormat::Bgra, 2, 1, 8, vec![1, 2, 3, 255, 4, 5, 6, 255]);
+        assert_eq!(bgra.to_rgba().unwrap(), [3, 2, 1, 255, 6, 5, 4, 255]);
+
+        let fd = std::fs::File::open("/dev/null").unwrap().into();
+        let dmabuf = DmaBuf {
+            modifier: 0,
+            planes: vec![DmaBufPlane {
+                fd,
+                offset: 0,
+                stride: 8,
+            }],
+        };
+        let frame = Frame::from_dmabuf(PixelFormat::Bgra, 2, 1, dmabuf);
+        assert!(matches!(
+            frame.to_rgba(),
+            Err(ScreencastError::Unsupported(_))
+        ));
+    }
+}
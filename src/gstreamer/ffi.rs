@@ -0,0 +1,240 @@
+#![allow(non_camel_case_types, non_upper_case_globals, dead_code)]
+
+use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+pub const GST_STATE_NULL: c_int = 1;
+pub const GST_STATE_PAUSED: c_int = 3;
+pub const GST_STATE_PLAYING: c_int = 4;
+
+pub const GST_STATE_CHANGE_FAILURE: c_int = 0;
+
+pub const GST_MESSAGE_EOS: c_int = 1 << 0;
+pub const GST_MESSAGE_ERROR: c_int = 1 << 1;
+
+pub const GST_MAP_READ: c_int = 1 << 0;
+
+pub const GST_CLOCK_TIME_NONE: u64 = u64::MAX;
+
+pub const GST_FLOW_OK: c_int = 0;
+
+pub const GST_VIDEO_FORMAT_I420: c_int = 2;
+pub const GST_VIDEO_FORMAT_YUY2: c_int = 4;
+pub const GST_VIDEO_FORMAT_RGBX: c_int = 7;
+pub const GST_VIDEO_FORMAT_BGRX: c_int = 8;
+pub const GST_VIDEO_FORMAT_XRGB: c_int = 9;
+pub const GST_VIDEO_FORMAT_XBGR: c_int = 10;
+pub const GST_VIDEO_FORMAT_RGBA: c_int = 11;
+pub const GST_VIDEO_FORMAT_BGRA: c_int = 12;
+pub const GST_VIDEO_FORMAT_ARGB: c_int = 13;
+pub const GST_VIDEO_FORMAT_ABGR: c_int = 14;
+pub const GST_VIDEO_FORMAT_RGB: c_int = 15;
+pub const GST_VIDEO_FORMAT_BGR: c_int = 16;
+pub const GST_VIDEO_FORMAT_NV12: c_int = 23;
+
+pub type gboolean = c_int;
+
+#[repr(C)]
+pub struct GstElement {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstBus {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstSample {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstCaps {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstStructure {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstEvent {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstAllocator {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstMemory {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct GstMiniObject {
+    pub type_: usize,
+    pub refcount: c_int,
+    pub lockstate: c_int,
+    pub flags: c_uint,
+    pub copy: *mut c_void,
+    pub dispose: *mut c_void,
+    pub free: *mut c_void,
+    pub priv_uint: c_uint,
+    pub priv_pointer: *mut c_void,
+}
+
+#[repr(C)]
+pub struct GstBuffer {
+    pub mini_object: GstMiniObject,
+    pub pool: *mut c_void,
+    pub pts: u64,
+    pub dts: u64,
+    pub duration: u64,
+    pub offset: u64,
+    pub offset_end: u64,
+}
+
+#[repr(C)]
+pub struct GstMessage {
+    pub mini_object: GstMiniObject,
+    pub type_: c_int,
+}
+
+#[repr(C)]
+pub struct GstMapInfo {
+    pub memory: *mut c_void,
+    pub flags: c_int,
+    pub data: *mut u8,
+    pub size: usize,
+    pub maxsize: usize,
+    pub user_data: [*mut c_void; 4],
+    pub _gst_reserved: [*mut c_void; 4],
+}
+
+impl GstMapInfo {
+    pub fn zeroed() -> Self {
+        // SAFETY: plain pointers and integers, all zero is a valid value
+        unsafe { std::mem::zeroed() }
+    }
+}
+
+#[repr(C)]
+pub struct GError {
+    pub domain: u32,
+    pub code: c_int,
+    pub message: *mut c_char,
+}
+
+#[link(name = "glib-2.0")]
+unsafe extern "C" {
+    pub fn g_free(mem: *mut c_void);
+    pub fn g_error_free(error: *mut GError);
+}
+
+#[link(name = "gobject-2.0")]
+unsafe extern "C" {
+    pub fn g_object_ref(object: *mut c_void) -> *mut c_void;
+    pub fn g_object_unref(object: *mut c_void);
+    pub fn g_object_set(object: *mut c_void, first_property_name: *const c_char, ...);
+    pub fn g_object_get(object: *mut c_void, first_property_name: *const c_char, ...);
+    pub fn g_signal_emit_by_name(instance: *mut c_void, detailed_signal: *const c_char, ...);
+}
+
+#[link(name = "gstreamer-1.0")]
+unsafe extern "C" {
+    pub fn gst_init_check(
+        argc: *mut c_int,
+        argv: *mut *mut *mut c_char,
+        error: *mut *mut GError,
+    ) -> gboolean;
+
+    pub fn gst_parse_launch(
+        pipeline_description: *const c_char,
+        error: *mut *mut GError,
+    ) -> *mut GstElement;
+
+    pub fn gst_element_set_state(element: *mut GstElement, state: c_int) -> c_int;
+    pub fn gst_element_get_base_time(element: *mut GstElement) -> u64;
+    pub fn gst_element_get_bus(element: *mut GstElement) -> *mut GstBus;
+    pub fn gst_element_send_event(element: *mut GstElement, event: *mut GstEvent) -> gboolean;
+    pub fn gst_bin_get_by_name(bin: *mut GstElement, name: *const c_char) -> *mut GstElement;
+
+    pub fn gst_event_new_eos() -> *mut GstEvent;
+
+    pub fn gst_bus_timed_pop_filtered(
+        bus: *mut GstBus,
+        timeout: u64,
+        types: c_int,
+    ) -> *mut GstMessage;
+    pub fn gst_message_parse_error(
+        message: *mut GstMessage,
+        error: *mut *mut GError,
+        debug: *mut *mut c_char,
+    );
+
+    pub fn gst_mini_object_unref(object: *mut GstMiniObject);
+
+    pub fn gst_sample_get_buffer(sample: *mut GstSample) -> *mut GstBuffer;
+    pub fn gst_sample_get_caps(sample: *mut GstSample) -> *mut GstCaps;
+    pub fn gst_caps_from_string(string: *const c_char) -> *mut GstCaps;
+    pub fn gst_caps_get_structure(caps: *const GstCaps, index: c_uint) -> *mut GstStructure;
+    pub fn gst_structure_get_int(
+        structure: *const GstStructure,
+        fieldname: *const c_char,
+        value: *mut c_int,
+    ) -> gboolean;
+    pub fn gst_structure_get_string(
+        structure: *const GstStructure,
+        fieldname: *const c_char,
+    ) -> *const c_char;
+
+    pub fn gst_buffer_new() -> *mut GstBuffer;
+    pub fn gst_buffer_new_allocate(
+        allocator: *mut GstAllocator,
+        size: usize,
+        params: *mut c_void,
+    ) -> *mut GstBuffer;
+    pub fn gst_buffer_fill(
+        buffer: *mut GstBuffer,
+        offset: usize,
+        src: *const c_void,
+        size: usize,
+    ) -> usize;
+    pub fn gst_buffer_append_memory(buffer: *mut GstBuffer, memory: *mut GstMemory);
+    pub fn gst_buffer_map(buffer: *mut GstBuffer, info: *mut GstMapInfo, flags: c_int) -> gboolean;
+    pub fn gst_buffer_unmap(buffer: *mut GstBuffer, info: *mut GstMapInfo);
+}
+
+#[link(name = "gstallocators-1.0")]
+unsafe extern "C" {
+    pub fn gst_dmabuf_allocator_new() -> *mut GstAllocator;
+    pub fn gst_dmabuf_allocator_alloc(
+        allocator: *mut GstAllocator,
+        fd: c_int,
+        size: usize,
+    ) -> *mut GstMemory;
+}
+
+#[link(name = "gstvideo-1.0")]
+unsafe extern "C" {
+    pub fn gst_buffer_add_video_meta_full(
+        buffer: *mut GstBuffer,
+        flags: c_int,
+        format: c_int,
+        width: c_uint,
+        height: c_uint,
+        n_planes: c_uint,
+        offset: *const usize,
+        stride: *const c_int,
+    ) -> *mut c_void;
+    pub fn gst_video_event_new_downstream_force_key_unit(
+        timestamp: u64,
+        stream_time: u64,
+        running_time: u64,
+        all_headers: gboolean,
+        count: c_uint,
+    ) -> *mut GstEvent;
+}
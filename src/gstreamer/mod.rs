@@ -0,0 +1,777 @@
+mod ffi;
+
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::{DRM_FORMAT_MOD_INVALID, DRM_FORMAT_MOD_LINEAR, DmaBuf, Frame, Plane};
+use futures_core::Stream;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::os::fd::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::fs::MetadataExt;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+/// A `pipewiresrc` element reading `node_id` from the remote behind `fd`,
+/// for building launch descriptions.
+pub fn pipewire_source(fd: RawFd, node_id: u32) -> String {
+    crate::launch::pipewire_source(fd, node_id)
+}
+
+/// Appended to a source to deliver packed frames through `GstPipeline::frames`.
+pub const APPSINK: &str = "videoconvert ! video/x-raw,format=BGRx ! appsink name=frames max-buffers=4 drop=true sync=false";
+
+fn init() -> Result<()> {
+    static INIT: OnceLock<std::result::Result<(), String>> = OnceLock::new();
+    INIT.get_or_init(|| unsafe {
+        let mut error = ptr::null_mut();
+        match ffi::gst_init_check(ptr::null_mut(), ptr::null_mut(), &mut error) {
+            0 => Err(take_error(error)),
+            _ => Ok(()),
+        }
+    })
+    .clone()
+    .map_err(ScreencastError::GStreamer)
+}
+
+/// A GStreamer pipeline built from a launch description, set to `NULL` and
+/// released on drop.
+///
+/// `as_ptr` hands the `GstPipeline*` to gstreamer-rs, e.g. through
+/// `gst::Pipeline::from_glib_none`, for anything not covered here.
+pub struct GstPipeline {
+    pipeline: *mut ffi::GstElement,
+    bus: *mut ffi::GstBus,
+    // the PipeWire remote `pipewiresrc` connects to, kept open as long as
+    // the pipeline
+    fd: Option<OwnedFd>,
+}
+
+// SAFETY: GStreamer objects are reference counted and thread safe.
+unsafe impl Send for GstPipeline {}
+unsafe impl Sync for GstPipeline {}
+
+impl std::fmt::Debug for GstPipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GstPipeline")
+            .field("pipeline", &self.pipeline)
+            .field("fd", &self.fd)
+            .finish()
+    }
+}
+
+impl GstPipeline {
+    /// Parses `description` as `gst-launch-1.0` would, the pipeline starts
+    /// out in the `NULL` state.
+    pub fn launch(description: &str) -> Result<Self> {
+        init()?;
+        let description = CString::new(description).map_err(|_| {
+            ScreencastError::GStreamer("description contains a NUL byte".to_string())
+        })?;
+        unsafe {
+            let mut error = ptr::null_mut();
+            let pipeline = ffi::gst_parse_launch(description.as_ptr(), &mut error);
+            if !error.is_null() {
+                if !pipeline.is_null() {
+                    ffi::g_object_unref(pipeline.cast());
+                }
+                return Err(ScreencastError::GStreamer(take_error(error)));
+            }
+            if pipeline.is_null() {
+                return Err(ScreencastError::GStreamer(
+                    "failed to parse pipeline".to_string(),
+                ));
+            }
+            Ok(GstPipeline {
+                pipeline,
+                bus: ffi::gst_element_get_bus(pipeline),
+                fd: None,
+            })
+        }
+    }
+
+    /// Launches `source ! tail` where `source` reads `node_id` over `fd`.
+    pub fn launch_pipewire(fd: OwnedFd, node_id: u32, tail: &str) -> Result<Self> {
+        let description = format!("{} ! {}", pipewire_source(fd.as_raw_fd(), node_id), tail);
+        let mut pipeline = GstPipeline::launch(&description)?;
+        pipeline.fd = Some(fd);
+        Ok(pipeline)
+    }
+
+    pub fn play(&self) -> Result<()> {
+        self.set_state(ffi::GST_STATE_PLAYING)
+    }
+
+    pub fn pause(&self) -> Result<()> {
+        self.set_state(ffi::GST_STATE_PAUSED)
+    }
+
+    /// Tears the pipeline down to `NULL` without draining it, see `finish`.
+    pub fn stop(&self) -> Result<()> {
+        self.set_state(ffi::GST_STATE_NULL)
+    }
+
+    fn set_state(&self, state: i32) -> Result<()> {
+        match unsafe { ffi::gst_element_set_state(self.pipeline, state) } {
+            ffi::GST_STATE_CHANGE_FAILURE => Err(self
+                .error()
+                .unwrap_or_else(|| ScreencastError::GStreamer("state change failed".to_string()))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sends EOS and waits until it reached the sinks, so muxers write their
+    /// headers and indexes, then stops the pipeline.
+    pub fn finish(&self, timeout: Option<Duration>) -> Result<()> {
+        unsafe { ffi::gst_element_send_event(self.pipeline, ffi::gst_event_new_eos()) };
+        let timeout = timeout.map_or(ffi::GST_CLOCK_TIME_NONE, clock_time);
+        let message = unsafe {
+            ffi::gst_bus_timed_pop_filtered(
+                self.bus,
+                timeout,
+                ffi::GST_MESSAGE_EOS | ffi::GST_MESSAGE_ERROR,
+            )
+        };
+        let result = match unsafe { message.as_ref() } {
+            None => Err(ScreencastError::Timeout),
+            Some(found) if found.type_ == ffi::GST_MESSAGE_ERROR => {
+                Err(unsafe { parse_error(message) })
+            }
+            Some(_) => Ok(()),
+        };
+        if !message.is_null() {
+            unsafe { ffi::gst_mini_object_unref(message.cast()) };
+        }
+        self.stop()?;
+        result
+    }
+
+    /// Takes the oldest error posted on the bus, if any.
+    pub fn error(&self) -> Option<ScreencastError> {
+        unsafe {
+            let message = ffi::gst_bus_timed_pop_filtered(self.bus, 0, ffi::GST_MESSAGE_ERROR);
+            if message.is_null() {
+                return None;
+            }
+            let error = parse_error(message);
+            ffi::gst_mini_object_unref(message.cast());
+            Some(error)
+        }
+    }
+
+    /// The `appsink` element called `name`, see `APPSINK`.
+    pub fn appsink(&self, name: &str) -> Result<AppSink> {
+        Ok(AppSink {
+            element: self.element(name)?,
+            pipeline: unsafe { ffi::g_object_ref(self.pipeline.cast()).cast() },
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// The `appsrc` element called `name`, for pipelines fed with frames.
+    pub fn appsrc(&self, name: &str) -> Result<AppSrc> {
+        Ok(AppSrc {
+            element: self.element(name)?,
+            allocator: ptr::null_mut(),
+            caps: None,
+            origin: None,
+        })
+    }
+
+    fn element(&self, name: &str) -> Result<*mut ffi::GstElement> {
+        let c_name = CString::new(name)
+            .map_err(|_| ScreencastError::GStreamer("name contains a NUL byte".to_string()))?;
+        let element = unsafe { ffi::gst_bin_get_by_name(self.pipeline, c_name.as_ptr()) };
+        if element.is_null() {
+            return Err(ScreencastError::GStreamer(format!(
+                "no element named {}",
+                name
+            )));
+        }
+        Ok(element)
+    }
+
+    /// Plays the pipeline and streams the frames of the `appsink` called
+    /// `name` until EOS or until the stream is dropped.
+    pub fn frames(self, name: &str) -> Result<GstFrames> {
+        let appsink = self.appsink(name)?;
+        self.play()?;
+        let (mut sender, receiver) = async_broadcast::broadcast(4);
+        sender.set_overflow(true);
+        std::thread::Builder::new()
+            .name("xdp-screencast-appsink".to_string())
+            .spawn(move || {
+                while let Some(frame) = appsink.pull() {
+                    if sender.try_broadcast(frame).is_err() {
+                        break;
+                    }
+                }
+            })?;
+        Ok(GstFrames {
+            receiver,
+            pipeline: self,
+        })
+    }
+
+    /// The `GstPipeline*`, still owned by this value.
+    pub fn as_ptr(&self) -> *mut c_void {
+        self.pipeline.cast()
+    }
+}
+
+impl Drop for GstPipeline {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::gst_element_set_state(self.pipeline, ffi::GST_STATE_NULL);
+            if !self.bus.is_null() {
+                ffi::g_object_unref(self.bus.cast());
+            }
+            ffi::g_object_unref(self.pipeline.cast());
+        }
+    }
+}
+
+/// An `appsink` element of a `GstPipeline`. Pulling blocks until a sample
+/// arrives and returns `None` at EOS or once the pipeline stopped.
+pub struct AppSink {
+    element: *mut ffi::GstElement,
+    pipeline: *mut ffi::GstElement,
+    sequence: AtomicU64,
+}
+
+// SAFETY: appsink's pull signals are thread safe.
+unsafe impl Send for AppSink {}
+
+impl std::fmt::Debug for AppSink {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppSink")
+            .field("element", &self.element)
+            .finish()
+    }
+}
+
+impl AppSink {
+    pub fn pull(&self) -> Option<Frame<'static>> {
+        unsafe { self.read_sample(self.pull_sample(None)) }
+    }
+
+    /// Like `pull` but gives up after `timeout`.
+    pub fn try_pull(&self, timeout: Duration) -> Option<Frame<'static>> {
+        unsafe { self.read_sample(self.pull_sample(Some(timeout))) }
+    }
+
+    /// The bytes and pts of the next buffer, for encoded streams.
+    pub(crate) fn try_pull_buffer(&self, timeout: Duration) -> Option<(Vec<u8>, Option<Duration>)> {
+        unsafe {
+            let sample = self.pull_sample(Some(timeout));
+            if sample.is_null() {
+                return None;
+            }
+            let buffer = ffi::gst_sample_get_buffer(sample);
+            let mut map = ffi::GstMapInfo::zeroed();
+            let read = match !buffer.is_null()
+                && ffi::gst_buffer_map(buffer, &mut map, ffi::GST_MAP_READ) != 0
+            {
+                true => {
+                    let data = std::slice::from_raw_parts(map.data, map.size).to_vec();
+                    ffi::gst_buffer_unmap(buffer, &mut map);
+                    let pts = (*buffer).pts;
+                    Some((
+                        data,
+                        (pts != ffi::GST_CLOCK_TIME_NONE).then(|| Duration::from_nanos(pts)),
+                    ))
+                }
+                false => None,
+            };
+            ffi::gst_mini_object_unref(sample.cast());
+            read
+        }
+    }
+
+    /// Whether the last sample before EOS was pulled.
+    pub fn is_eos(&self) -> bool {
+        let mut eos: ffi::gboolean = 0;
+        unsafe {
+            ffi::g_object_get(
+                self.element.cast(),
+                c"eos".as_ptr(),
+                &mut eos as *mut ffi::gboolean,
+                ptr::null::<c_char>(),
+            )
+        };
+        eos != 0
+    }
+
+    unsafe fn pull_sample(&self, timeout: Option<Duration>) -> *mut ffi::GstSample {
+        let mut sample: *mut ffi::GstSample = ptr::null_mut();
+        unsafe {
+            match timeout {
+                None => ffi::g_signal_emit_by_name(
+                    self.element.cast(),
+                    c"pull-sample".as_ptr(),
+                    &mut sample as *mut *mut ffi::GstSample,
+                ),
+                Some(timeout) => ffi::g_signal_emit_by_name(
+                    self.element.cast(),
+                    c"try-pull-sample".as_ptr(),
+                    clock_time(timeout),
+                    &mut sample as *mut *mut ffi::GstSample,
+                ),
+            }
+        }
+        sample
+    }
+
+    /// Copies the sample into a frame and releases it.
+    unsafe fn read_sample(&self, sample: *mut ffi::GstSample) -> Option<Frame<'static>> {
+        if sample.is_null() {
+            return None;
+        }
+        let frame = unsafe { read_frame(sample, ffi::gst_element_get_base_time(self.pipeline)) };
+        unsafe { ffi::gst_mini_object_unref(sample.cast()) };
+        frame.map(|frame| frame.with_sequence(self.sequence.fetch_add(1, Ordering::Relaxed)))
+    }
+}
+
+impl Drop for AppSink {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::g_object_unref(self.element.cast());
+            ffi::g_object_unref(self.pipeline.cast());
+        }
+    }
+}
+
+/// An `appsrc` element of a `GstPipeline`. The caps follow the pushed
+/// frames and timestamps count from the first frame, so the description
+/// only needs `appsrc name=... format=time`. DMA-BUF frames are imported
+/// without a copy.
+pub struct AppSrc {
+    element: *mut ffi::GstElement,
+    allocator: *mut ffi::GstAllocator,
+    caps: Option<String>,
+    origin: Option<Duration>,
+}
+
+// SAFETY: appsrc's signals are thread safe.
+unsafe impl Send for AppSrc {}
+
+impl std::fmt::Debug for AppSrc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AppSrc")
+            .field("element", &self.element)
+            .field("caps", &self.caps)
+            .finish()
+    }
+}
+
+impl AppSrc {
+    pub fn push(&mut self, frame: &Frame<'_>) -> Result<()> {
+        let caps = caps(frame)?;
+        if self.caps.as_ref() != Some(&caps) {
+            let c_caps = CString::new(caps.as_str()).unwrap();
+            unsafe {
+                let parsed = ffi::gst_caps_from_string(c_caps.as_ptr());
+                if parsed.is_null() {
+                    return Err(ScreencastError::GStreamer(format!("invalid caps {}", caps)));
+                }
+                ffi::g_object_set(
+                    self.element.cast(),
+                    c"caps".as_ptr(),
+                    parsed,
+                    ptr::null::<c_char>(),
+                );
+                ffi::gst_mini_object_unref(parsed.cast());
+            }
+            self.caps = Some(caps);
+        }
+
+        let buffer = match frame.dmabuf() {
+            Some(dmabuf) => self.import(dmabuf)?,
+            None => unsafe {
+                let data = frame.data();
+                let buffer =
+                    ffi::gst_buffer_new_allocate(ptr::null_mut(), data.len(), ptr::null_mut());
+                if buffer.is_null() {
+                    return Err(ScreencastError::GStreamer(
+                        "failed to allocate a buffer".to_string(),
+                    ));
+                }
+                ffi::gst_buffer_fill(buffer, 0, data.as_ptr().cast(), data.len());
+                buffer
+            },
+        };
+        let mut offsets = [0usize; 4];
+        let mut strides = [0; 4];
+        for (index, plane) in frame.planes().iter().take(4).enumerate() {
+            offsets[index] = plane.offset;
+            strides[index] = plane.stride as c_int;
+        }
+        let pts = frame
+            .pts()
+            .map(|pts| pts.saturating_sub(*self.origin.get_or_insert(pts)));
+        let mut flow = ffi::GST_FLOW_OK;
+        unsafe {
+            (*buffer).pts = pts.map_or(ffi::GST_CLOCK_TIME_NONE, clock_time);
+            ffi::gst_buffer_add_video_meta_full(
+                buffer,
+                0,
+                video_format(frame.pixel_format()),
+                frame.width(),
+                frame.height(),
+                frame.planes().len().min(4) as u32,
+                offsets.as_ptr(),
+                strides.as_ptr(),
+            );
+            ffi::g_signal_emit_by_name(
+                self.element.cast(),
+                c"push-buffer".as_ptr(),
+                buffer,
+                &mut flow as *mut c_int,
+            );
+            ffi::gst_mini_object_unref(buffer.cast());
+        }
+        match flow {
+            ffi::GST_FLOW_OK => Ok(()),
+            flow => Err(ScreencastError::GStreamer(format!(
+                "pushing a buffer returned flow {}",
+                flow
+            ))),
+        }
+    }
+
+    /// Signals that no more frames follow.
+    pub fn end_of_stream(&self) -> Result<()> {
+        let mut flow = ffi::GST_FLOW_OK;
+        unsafe {
+            ffi::g_signal_emit_by_name(
+                self.element.cast(),
+                c"end-of-stream".as_ptr(),
+                &mut flow as *mut c_int,
+            )
+        };
+        match flow {
+            ffi::GST_FLOW_OK => Ok(()),
+            flow => Err(ScreencastError::GStreamer(format!(
+                "end of stream returned flow {}",
+                flow
+            ))),
+        }
+    }
+
+    /// Asks the encoder downstream to make the next frame a keyframe that
+    /// repeats the stream headers.
+    pub fn force_keyframe(&self) -> Result<()> {
+        let sent = unsafe {
+            let event = ffi::gst_video_event_new_downstream_force_key_unit(
+                ffi::GST_CLOCK_TIME_NONE,
+                ffi::GST_CLOCK_TIME_NONE,
+                ffi::GST_CLOCK_TIME_NONE,
+                1,
+                0,
+            );
+            ffi::gst_element_send_event(self.element, event)
+        };
+        match sent {
+            0 => Err(ScreencastError::GStreamer(
+                "the pipeline refused a keyframe request".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The pts of the first frame, buffer timestamps count from here.
+    pub fn origin(&self) -> Option<Duration> {
+        self.origin
+    }
+
+    /// Wraps the DMA-BUF in a buffer, all planes must live in one buffer
+    /// object.
+    fn import(&mut self, dmabuf: &DmaBuf) -> Result<*mut ffi::GstBuffer> {
+        let first = dmabuf
+            .planes
+            .first()
+            .ok_or_else(|| ScreencastError::GStreamer("DMA-BUF without planes".to_string()))?;
+        let mut file = File::from(first.fd.try_clone()?);
+        let inode = file.metadata()?.ino();
+        for plane in &dmabuf.planes[1..] {
+            if File::from(plane.fd.try_clone()?).metadata()?.ino() != inode {
+                return Err(ScreencastError::Unsupported(
+                    "DMA-BUF planes in separate buffer objects".to_string(),
+                ));
+            }
+        }
+        let size = file.seek(SeekFrom::End(0))? as usize;
+        unsafe {
+            if self.allocator.is_null() {
+                self.allocator = ffi::gst_dmabuf_allocator_new();
+            }
+            let fd = first.fd.try_clone()?.into_raw_fd();
+            let memory = ffi::gst_dmabuf_allocator_alloc(self.allocator, fd, size);
+            if memory.is_null() {
+                drop(OwnedFd::from_raw_fd(fd));
+                return Err(ScreencastError::GStreamer(
+                    "failed to import the DMA-BUF".to_string(),
+                ));
+            }
+            let buffer = ffi::gst_buffer_new();
+            ffi::gst_buffer_append_memory(buffer, memory);
+            Ok(buffer)
+        }
+    }
+}
+
+impl Drop for AppSrc {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.allocator.is_null() {
+                ffi::g_object_unref(self.allocator.cast());
+            }
+            ffi::g_object_unref(self.element.cast());
+        }
+    }
+}
+
+/// See `GstPipeline::frames`. Frames are dropped, oldest first, when the
+/// stream is polled slower than the pipeline produces them.
+#[derive(Debug)]
+pub struct GstFrames {
+    receiver: async_broadcast::Receiver<Frame<'static>>,
+    pipeline: GstPipeline,
+}
+
+impl GstFrames {
+    pub fn pipeline(&self) -> &GstPipeline {
+        &self.pipeline
+    }
+}
+
+impl Stream for GstFrames {
+    type Item = Frame<'static>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame<'static>>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+unsafe fn read_frame(sample: *mut ffi::GstSample, base_time: u64) -> Option<Frame<'static>> {
+    unsafe {
+        let caps = ffi::gst_sample_get_caps(sample);
+        let buffer = ffi::gst_sample_get_buffer(sample);
+        if caps.is_null() || buffer.is_null() {
+            return None;
+        }
+        let structure = ffi::gst_caps_get_structure(caps, 0);
+        let (mut width, mut height) = (0, 0);
+        if ffi::gst_structure_get_int(structure, c"width".as_ptr(), &mut width) == 0
+            || ffi::gst_structure_get_int(structure, c"height".as_ptr(), &mut height) == 0
+        {
+            return None;
+        }
+        let format = ffi::gst_structure_get_string(structure, c"format".as_ptr());
+        let pixel_format = pixel_format(CStr::from_ptr(format.as_ref()?).to_bytes())?;
+        let (width, height) = (width as u32, height as u32);
+        let mut map = ffi::GstMapInfo::zeroed();
+        if ffi::gst_buffer_map(buffer, &mut map, ffi::GST_MAP_READ) == 0 {
+            return None;
+        }
+        let data = std::slice::from_raw_parts(map.data, map.size).to_vec();
+        ffi::gst_buffer_unmap(buffer, &mut map);
+        let mut frame = Frame::new(
+            pixel_format,
+            width,
+            height,
+            planes(pixel_format, width, height),
+            data,
+        );
+        let pts = (*buffer).pts;
+        if pts != ffi::GST_CLOCK_TIME_NONE && base_time != ffi::GST_CLOCK_TIME_NONE {
+            frame = frame.with_pts(Duration::from_nanos(base_time + pts));
+        }
+        Some(frame)
+    }
+}
+
+/// Caps describing `frame`, DMA-BUFs with an explicit modifier use the
+/// `DMA_DRM` format of GStreamer 1.24.
+fn caps(frame: &Frame<'_>) -> Result<String> {
+    let size = format!(
+        "width={},height={},framerate=0/1",
+        frame.width(),
+        frame.height()
+    );
+    let Some(dmabuf) = frame.dmabuf() else {
+        return Ok(format!(
+            "video/x-raw,format={},{}",
+            format_name(frame.pixel_format()),
+            size
+        ));
+    };
+    Ok(match dmabuf.modifier {
+        DRM_FORMAT_MOD_LINEAR | DRM_FORMAT_MOD_INVALID => format!(
+            "video/x-raw(memory:DMABuf),format={},{}",
+            format_name(frame.pixel_format()),
+            size
+        ),
+        modifier => format!(
+            "video/x-raw(memory:DMABuf),format=DMA_DRM,drm-format={}:{:#018x},{}",
+            drm_fourcc(frame.pixel_format()),
+            modifier,
+            size
+        ),
+    })
+}
+
+fn format_name(pixel_format: PixelFormat) -> &'static str {
+    match pixel_format {
+        PixelFormat::Bgrx => "BGRx",
+        PixelFormat::Bgra => "BGRA",
+        PixelFormat::Rgbx => "RGBx",
+        PixelFormat::Rgba => "RGBA",
+        PixelFormat::Xrgb => "xRGB",
+        PixelFormat::Argb => "ARGB",
+        PixelFormat::Xbgr => "xBGR",
+        PixelFormat::Abgr => "ABGR",
+        PixelFormat::Rgb => "RGB",
+        PixelFormat::Bgr => "BGR",
+        PixelFormat::Nv12 => "NV12",
+        PixelFormat::I420 => "I420",
+        PixelFormat::Yuy2 => "YUY2",
+    }
+}
+
+fn drm_fourcc(pixel_format: PixelFormat) -> &'static str {
+    match pixel_format {
+        PixelFormat::Bgrx => "XR24",
+        PixelFormat::Bgra => "AR24",
+        PixelFormat::Rgbx => "XB24",
+        PixelFormat::Rgba => "AB24",
+        PixelFormat::Xrgb => "BX24",
+        PixelFormat::Argb => "BA24",
+        PixelFormat::Xbgr => "RX24",
+        PixelFormat::Abgr => "RA24",
+        PixelFormat::Rgb => "BG24",
+        PixelFormat::Bgr => "RG24",
+        PixelFormat::Nv12 => "NV12",
+        PixelFormat::I420 => "YU12",
+        PixelFormat::Yuy2 => "YUYV",
+    }
+}
+
+fn video_format(pixel_format: PixelFormat) -> c_int {
+    match pixel_format {
+        PixelFormat::Bgrx => ffi::GST_VIDEO_FORMAT_BGRX,
+        PixelFormat::Bgra => ffi::GST_VIDEO_FORMAT_BGRA,
+        PixelFormat::Rgbx => ffi::GST_VIDEO_FORMAT_RGBX,
+        PixelFormat::Rgba => ffi::GST_VIDEO_FORMAT_RGBA,
+        PixelFormat::Xrgb => ffi::GST_VIDEO_FORMAT_XRGB,
+        PixelFormat::Argb => ffi::GST_VIDEO_FORMAT_ARGB,
+        PixelFormat::Xbgr => ffi::GST_VIDEO_FORMAT_XBGR,
+        PixelFormat::Abgr => ffi::GST_VIDEO_FORMAT_ABGR,
+        PixelFormat::Rgb => ffi::GST_VIDEO_FORMAT_RGB,
+        PixelFormat::Bgr => ffi::GST_VIDEO_FORMAT_BGR,
+        PixelFormat::Nv12 => ffi::GST_VIDEO_FORMAT_NV12,
+        PixelFormat::I420 => ffi::GST_VIDEO_FORMAT_I420,
+        PixelFormat::Yuy2 => ffi::GST_VIDEO_FORMAT_YUY2,
+    }
+}
+
+fn clock_time(duration: Duration) -> u64 {
+    duration.as_nanos().min(u64::MAX as u128 - 1) as u64
+}
+
+fn pixel_format(format: &[u8]) -> Option<PixelFormat> {
+    Some(match format {
+        b"BGRx" => PixelFormat::Bgrx,
+        b"BGRA" => PixelFormat::Bgra,
+        b"RGBx" => PixelFormat::Rgbx,
+        b"RGBA" => PixelFormat::Rgba,
+        b"xRGB" => PixelFormat::Xrgb,
+        b"ARGB" => PixelFormat::Argb,
+        b"xBGR" => PixelFormat::Xbgr,
+        b"ABGR" => PixelFormat::Abgr,
+        b"RGB" => PixelFormat::Rgb,
+        b"BGR" => PixelFormat::Bgr,
+        b"NV12" => PixelFormat::Nv12,
+        b"I420" => PixelFormat::I420,
+        b"YUY2" => PixelFormat::Yuy2,
+        _ => return None,
+    })
+}
+
+/// GStreamer's default layout, rows padded to 4 bytes.
+fn planes(pixel_format: PixelFormat, width: u32, height: u32) -> Vec<Plane> {
+    let (width, height) = (width as usize, height as usize);
+    let luma_height = height.next_multiple_of(2);
+    match pixel_format {
+        PixelFormat::Nv12 => {
+            let stride = width.next_multiple_of(4);
+            vec![
+                Plane { offset: 0, stride },
+                Plane {
+                    offset: stride * luma_height,
+                    stride,
+                },
+            ]
+        }
+        PixelFormat::I420 => {
+            let stride = width.next_multiple_of(4);
+            let chroma_stride = width.next_multiple_of(2).div_ceil(2).next_multiple_of(4);
+            let chroma_offset = stride * luma_height;
+            vec![
+                Plane { offset: 0, stride },
+                Plane {
+                    offset: chroma_offset,
+                    stride: chroma_stride,
+                },
+                Plane {
+                    offset: chroma_offset + chroma_stride * luma_height / 2,
+                    stride: chroma_stride,
+                },
+            ]
+        }
+        PixelFormat::Yuy2 => vec![Plane {
+            offset: 0,
+            stride: (width.next_multiple_of(2) * 2).next_multiple_of(4),
+        }],
+        _ => vec![Plane {
+            offset: 0,
+            stride: (width * pixel_format.bytes_per_pixel()).next_multiple_of(4),
+        }],
+    }
+}
+
+unsafe fn parse_error(message: *mut ffi::GstMessage) -> ScreencastError {
+    let mut error = ptr::null_mut();
+    let mut debug = ptr::null_mut();
+    unsafe {
+        ffi::gst_message_parse_error(message, &mut error, &mut debug);
+        let mut text = take_error(error);
+        if !debug.is_null() {
+            text = format!("{} ({})", text, CStr::from_ptr(debug).to_string_lossy());
+            ffi::g_free(debug.cast());
+        }
+        ScreencastError::GStreamer(text)
+    }
+}
+
+unsafe fn take_error(error: *mut ffi::GError) -> String {
+    if error.is_null() {
+        return "unknown error".to_string();
+    }
+    unsafe {
+        let message = match (*error).message.is_null() {
+            true => "unknown error".to_string(),
+            false => CStr::from_ptr((*error).message)
+                .to_string_lossy()
+                .into_owned(),
+        };
+        ffi::g_error_free(error);
+        message
+    }
+}
@@ -0,0 +1,605 @@
+use crate::error::{Result, ScreencastError};
+use std::f32::consts::PI;
+
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+const LUMA_QUANT: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+
+const CHROMA_QUANT: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+// The example Huffman tables of ITU T.81 Annex K.3, as code counts per
+// length followed by the symbols.
+const LUMA_DC_BITS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const CHROMA_DC_BITS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+
+const LUMA_AC_BITS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+const CHROMA_AC_BITS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Baseline JFIF with 4:2:0 chroma from tightly packed RGBA input.
+pub(super) fn encode(rgba: &[u8], width: u32, height: u32, quality: u8) -> Result<Vec<u8>> {
+    if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(ScreencastError::Unsupported(format!(
+            "jpeg of {}x{}",
+            width, height
+        )));
+    }
+    let (width, height) = (width as usize, height as usize);
+    let luma_quant = scale_quant(&LUMA_QUANT, quality);
+    let chroma_quant = scale_quant(&CHROMA_QUANT, quality);
+    let luma_dc = Huffman::new(&LUMA_DC_BITS, &DC_VALUES);
+    let luma_ac = Huffman::new(&LUMA_AC_BITS, &LUMA_AC_VALUES);
+    let chroma_dc = Huffman::new(&CHROMA_DC_BITS, &DC_VALUES);
+    let chroma_ac = Huffman::new(&CHROMA_AC_BITS, &CHROMA_AC_VALUES);
+
+    let mut out = vec![0xff, 0xd8];
+    segment(
+        &mut out,
+        0xe0,
+        &[b'J', b'F', b'I', b'F', 0, 1, 1, 0, 0, 1, 0, 1, 0, 0],
+    );
+    for (id, quant) in [&luma_quant, &chroma_quant].into_iter().enumerate() {
+        let mut data = vec![id as u8];
+        data.extend(ZIGZAG.iter().map(|index| quant[*index] as u8));
+        segment(&mut out, 0xdb, &data);
+    }
+    let mut frame_header = vec![8];
+    frame_header.extend_from_slice(&(height as u16).to_be_bytes());
+    frame_header.extend_from_slice(&(width as u16).to_be_bytes());
+    frame_header.extend_from_slice(&[3, 1, 0x22, 0, 2, 0x11, 1, 3, 0x11, 1]);
+    segment(&mut out, 0xc0, &frame_header);
+    for (class_id, bits, values) in [
+        (0x00, &LUMA_DC_BITS, &DC_VALUES[..]),
+        (0x10, &LUMA_AC_BITS, &LUMA_AC_VALUES[..]),
+        (0x01, &CHROMA_DC_BITS, &DC_VALUES[..]),
+        (0x11, &CHROMA_AC_BITS, &CHROMA_AC_VALUES[..]),
+    ] {
+        let mut data = vec![class_id];
+        data.extend_from_slice(bits);
+        data.extend_from_slice(values);
+        segment(&mut out, 0xc4, &data);
+    }
+    segment(&mut out, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0]);
+
+    let dct = Dct::new();
+    let mut bits = BitWriter {
+        out,
+        buffer: 0,
+        count: 0,
+    };
+    let mut previous_dc = [0i32; 3];
+    let pixel = |x: usize, y: usize| {
+        let index = (y.min(height - 1) * width + x.min(width - 1)) * 4;
+        let (r, g, b) = (
+            rgba[index] as f32,
+            rgba[index + 1] as f32,
+            rgba[index + 2] as f32,
+        );
+        (
+            0.299 * r + 0.587 * g + 0.114 * b,
+            -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+            0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+        )
+    };
+    let mut luma = [[0f32; 64]; 4];
+    let mut cb = [0f32; 64];
+    let mut cr = [0f32; 64];
+    for mcu_y in (0..height).step_by(16) {
+        for mcu_x in (0..width).step_by(16) {
+            cb.fill(0.0);
+            cr.fill(0.0);
+            for y in 0..16 {
+                for x in 0..16 {
+                    let (l, u, v) = pixel(mcu_x + x, mcu_y + y);
+                    luma[(y / 8) * 2 + x / 8][(y % 8) * 8 + x % 8] = l - 128.0;
+                    let chroma = (y / 2) * 8 + x / 2;
+                    cb[chroma] += (u - 128.0) / 4.0;
+                    cr[chroma] += (v - 128.0) / 4.0;
+                }
+            }
+            for block in &luma {
+                let coefficients = dct.quantize(block, &luma_quant);
+                encode_block(
+                    &mut bits,
+                    &coefficients,
+                    &mut previous_dc[0],
+                    &luma_dc,
+                    &luma_ac,
+                );
+            }
+            let coefficients = dct.quantize(&cb, &chroma_quant);
+            encode_block(
+                &mut bits,
+                &coefficients,
+                &mut previous_dc[1],
+                &chroma_dc,
+                &chroma_ac,
+            );
+            let coefficients = dct.quantize(&cr, &chroma_quant);
+            encode_block(
+                &mut bits,
+                &coefficients,
+                &mut previous_dc[2],
+                &chroma_dc,
+                &chroma_ac,
+            );
+        }
+    }
+    let mut out = bits.finish();
+    out.extend_from_slice(&[0xff, 0xd9]);
+    Ok(out)
+}
+
+/// The libjpeg quality scaling of the example tables.
+fn scale_quant(base: &[u16; 64], quality: u8) -> [u16; 64] {
+    let quality = quality.clamp(1, 100) as u32;
+    let scale = match quality {
+        0..50 => 5000 / quality,
+        _ => 200 - quality * 2,
+    };
+    base.map(|value| ((value as u32 * scale + 50) / 100).clamp(1, 255) as u16)
+}
+
+fn segment(out: &mut Vec<u8>, marker: u8, data: &[u8]) {
+    out.extend_from_slice(&[0xff, marker]);
+    out.extend_from_slice(&(data.len() as u16 + 2).to_be_bytes());
+    out.extend_from_slice(data);
+}
+
+fn encode_block(
+    bits: &mut BitWriter,
+    coefficients: &[i32; 64],
+    previous_dc: &mut i32,
+    dc: &Huffman,
+    ac: &Huffman,
+) {
+    let diff = coefficients[0] - *previous_dc;
+    *previous_dc = coefficients[0];
+    let size = magnitude(diff);
+    dc.write(bits, size as u8);
+    bits.write_value(diff, size);
+
+    let mut zeros = 0;
+    for coefficient in &coefficients[1..] {
+        if *coefficient == 0 {
+            zeros += 1;
+            continue;
+        }
+        while zeros > 15 {
+            ac.write(bits, 0xf0);
+            zeros -= 16;
+        }
+        let size = magnitude(*coefficient);
+        ac.write(bits, ((zeros << 4) | size) as u8);
+        bits.write_value(*coefficient, size);
+        zeros = 0;
+    }
+    if zeros > 0 {
+        ac.write(bits, 0x00);
+    }
+}
+
+fn magnitude(value: i32) -> u32 {
+    32 - value.unsigned_abs().leading_zeros()
+}
+
+/// Separable float DCT-II with the cosines precomputed.
+struct Dct {
+    cosines: [[f32; 8]; 8],
+}
+
+impl Dct {
+    fn new() -> Self {
+        let mut cosines = [[0f32; 8]; 8];
+        for (u, row) in cosines.iter_mut().enumerate() {
+            let scale = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+            for (x, value) in row.iter_mut().enumerate() {
+                *value = 0.5 * scale * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos();
+            }
+        }
+        Dct { cosines }
+    }
+
+    /// Transforms a level shifted block and returns it quantized in zigzag order.
+    fn quantize(&self, block: &[f32; 64], quant: &[u16; 64]) -> [i32; 64] {
+        let mut rows = [0f32; 64];
+        for y in 0..8 {
+            for u in 0..8 {
+                rows[y * 8 + u] = (0..8).map(|x| block[y * 8 + x] * self.cosines[u][x]).sum();
+            }
+        }
+        let mut coefficients = [0i32; 64];
+        for (zigzag, index) in ZIGZAG.iter().enumerate() {
+            let (v, u) = (index / 8, index % 8);
+            let value: f32 = (0..8).map(|y| rows[y * 8 + u] * self.cosines[v][y]).sum();
+            coefficients[zigzag] = (value / quant[*index] as f32).round() as i32;
+        }
+        coefficients
+    }
+}
+
+struct Huffman {
+    codes: [(u16, u8); 256],
+}
+
+impl Huffman {
+    fn new(bits: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0u16, 0u8); 256];
+        let mut code = 0u16;
+        let mut values = values.iter();
+        for (len, count) in bits.iter().enumerate() {
+            for _ in 0..*count {
+                if let Some(value) = values.next() {
+                    codes[*value as usize] = (code, len as u8 + 1);
+                }
+                code += 1;
+            }
+            code <<= 1;
+        }
+        Huffman { codes }
+    }
+
+    fn write(&self, bits: &mut BitWriter, symbol: u8) {
+        let (code, len) = self.codes[symbol as usize];
+        bits.write(code as u32, len as u32);
+    }
+}
+
+/// Entropy coded data, most significant bit first with 0xff stuffing.
+struct BitWriter {
+    out: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, len: u32) {
+        for bit in (0..len).rev() {
+            self.buffer = (self.buffer << 1) | ((value >> bit) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                self.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Negative values are stored as their one's complement.
+    fn write_value(&mut self, value: i32, size: u32) {
+        let value = if value < 0 { value - 1 } else { value };
+        self.write(value as u32 & ((1 << size) - 1), size);
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.out.push(byte);
+        if byte == 0xff {
+            self.out.push(0);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            let padding = 8 - self.count;
+            self.write((1 << padding) - 1, padding);
+        }
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    struct Component {
+        h: usize,
+        v: usize,
+        quant: usize,
+        dc: usize,
+        ac: usize,
+        samples: Vec<f32>,
+        stride: usize,
+    }
+
+    struct Bits {
+        data: Vec<u8>,
+        position: usize,
+    }
+
+    impl Bits {
+        /// Past the end the padding of ones.
+        fn bit(&mut self) -> u32 {
+            let bit = match self.data.get(self.position / 8) {
+                Some(byte) => (byte >> (7 - self.position % 8)) & 1,
+                None => 1,
+            };
+            self.position += 1;
+            bit as u32
+        }
+
+        fn read(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |value, _| value << 1 | self.bit())
+        }
+
+        fn extend(&mut self, size: u32) -> i32 {
+            let value = self.read(size) as i32;
+            match size {
+                0 => 0,
+                _ if value < 1 << (size - 1) => value - (1 << size) + 1,
+                _ => value,
+            }
+        }
+
+        fn decode(&mut self, table: &HashMap<(u32, u32), u8>) -> u8 {
+            let mut code = 0;
+            for len in 1..=16 {
+                code = code << 1 | self.bit();
+                if let Some(value) = table.get(&(len, code)) {
+                    return *value;
+                }
+            }
+            panic!("no Huffman code at bit {}", self.position);
+        }
+    }
+
+    /// A baseline decoder for a single scan without restarts, chroma
+    /// upsampled by repeating it. The RGB of each pixel and the bits of the
+    /// scan left over.
+    fn decode(jpeg: &[u8]) -> (usize, usize, Vec<[f32; 3]>, usize) {
+        assert_eq!(jpeg[..2], [0xff, 0xd8]);
+        assert_eq!(jpeg[jpeg.len() - 2..], [0xff, 0xd9]);
+        let mut quants = [[0u16; 64]; 4];
+        let mut tables: [Vec<HashMap<(u32, u32), u8>>; 2] = Default::default();
+        let (mut width, mut height) = (0, 0);
+        let mut components = Vec::new();
+        let mut position = 2;
+        loop {
+            assert_eq!(jpeg[position], 0xff);
+            let marker = jpeg[position + 1];
+            let len = u16::from_be_bytes([jpeg[position + 2], jpeg[position + 3]]) as usize;
+            let data = &jpeg[position + 4..position + 2 + len];
+            position += 2 + len;
+            match marker {
+                0xdb => {
+                    for table in data.chunks_exact(65) {
+                        quants[table[0] as usize] = std::array::from_fn(|k| table[1 + k] as u16);
+                    }
+                }
+                0xc4 => {
+                    let (class_id, counts) = (data[0], &data[1..17]);
+                    let mut values = data[17..].iter();
+                    let mut table = HashMap::new();
+                    let mut code = 0;
+                    for (len, count) in counts.iter().enumerate() {
+                        for _ in 0..*count {
+                            table.insert((len as u32 + 1, code), *values.next().unwrap());
+                            code += 1;
+                        }
+                        code <<= 1;
+                    }
+                    let class = &mut tables[(class_id >> 4) as usize];
+                    class.resize((class_id & 0x0f) as usize + 1, HashMap::new());
+                    class[(class_id & 0x0f) as usize] = table;
+                }
+                0xc0 => {
+                    assert_eq!(data[0], 8);
+                    height = u16::from_be_bytes([data[1], data[2]]) as usize;
+                    width = u16::from_be_bytes([data[3], data[4]]) as usize;
+                    for component in data[6..].chunks_exact(3) {
+                        components.push(Component {
+                            h: (component[1] >> 4) as usize,
+                            v: (component[1] & 0x0f) as usize,
+                            quant: component[2] as usize,
+                            dc: 0,
+                            ac: 0,
+                            samples: Vec::new(),
+                            stride: 0,
+                        });
+                    }
+                }
+                0xda => {
+                    assert_eq!(data[0] as usize, components.len());
+                    for (component, selector) in components.iter_mut().zip(data[1..].chunks(2)) {
+                        component.dc = (selector[1] >> 4) as usize;
+                        component.ac = (selector[1] & 0x0f) as usize;
+                    }
+                    assert_eq!(data[data.len() - 3..], [0, 63, 0]);
+                    break;
+                }
+                _ => {}
+            }
+        }
+
+        // the entropy coded data, unstuffed
+        let mut data = Vec::new();
+        let mut scan = jpeg[position..jpeg.len() - 2].iter();
+        while let Some(&byte) = scan.next() {
+            data.push(byte);
+            if byte == 0xff {
+                assert_eq!(scan.next(), Some(&0));
+            }
+        }
+        let mut bits = Bits { data, position: 0 };
+
+        let h_max = components.iter().map(|c| c.h).max().unwrap();
+        let v_max = components.iter().map(|c| c.v).max().unwrap();
+        let mcus_x = width.div_ceil(8 * h_max);
+        let mcus_y = height.div_ceil(8 * v_max);
+        for component in &mut components {
+            component.stride = mcus_x * component.h * 8;
+            component.samples = vec![0.0; component.stride * mcus_y * component.v * 8];
+        }
+        let mut predictions = vec![0i32; components.len()];
+        for mcu in 0..mcus_x * mcus_y {
+            for (component, prediction) in components.iter_mut().zip(&mut predictions) {
+                for block in 0..component.h * component.v {
+                    let quant = &quants[component.quant];
+                    let mut coefficients = [0f32; 64];
+                    let size = bits.decode(&tables[0][component.dc]);
+                    *prediction += bits.extend(size as u32);
+                    coefficients[0] = (*prediction * quant[0] as i32) as f32;
+                    let mut k = 1;
+                    while k < 64 {
+                        let run_size = bits.decode(&tables[1][component.ac]);
+                        let (run, size) = ((run_size >> 4) as usize, (run_size & 0x0f) as u32);
+                        if size == 0 {
+                            if run != 15 {
+                                break;
+                            }
+                            k += 16;
+                            continue;
+                        }
+                        k += run;
+                        coefficients[ZIGZAG[k]] = (bits.extend(size) * quant[k] as i32) as f32;
+                        k += 1;
+                    }
+                    let left = (mcu % mcus_x * component.h + block % component.h) * 8;
+                    let top = (mcu / mcus_x * component.v + block / component.h) * 8;
+                    for y in 0..8 {
+                        for x in 0..8 {
+                            let mut sum = 0.0;
+                            for v in 0..8 {
+                                for u in 0..8 {
+                                    let cu = if u == 0 { 0.5f32.sqrt() } else { 1.0 };
+                                    let cv = if v == 0 { 0.5f32.sqrt() } else { 1.0 };
+                                    sum += cu
+                                        * cv
+                                        * coefficients[v * 8 + u]
+                                        * ((2 * x + 1) as f32 * u as f32 * PI / 16.0).cos()
+                                        * ((2 * y + 1) as f32 * v as f32 * PI / 16.0).cos();
+                                }
+                            }
+                            component.samples[(top + y) * component.stride + left + x] =
+                                sum / 4.0 + 128.0;
+                        }
+                    }
+                }
+            }
+        }
+        let left_over = (bits.data.len() * 8).saturating_sub(bits.position);
+
+        let sample = |component: &Component, x: usize, y: usize| {
+            let (x, y) = (x * component.h / h_max, y * component.v / v_max);
+            component.samples[y * component.stride + x]
+        };
+        let pixels = (0..width * height)
+            .map(|index| {
+                let (x, y) = (index % width, index / width);
+                let luma = sample(&components[0], x, y);
+                let cb = sample(&components[1], x, y) - 128.0;
+                let cr = sample(&components[2], x, y) - 128.0;
+                [
+                    luma + 1.402 * cr,
+                    luma - 0.344_136 * cb - 0.714_136 * cr,
+                    luma + 1.772 * cb,
+                ]
+            })
+            .collect();
+        (width, height, pixels, left_over)
+    }
+
+    /// The mean and the largest difference of the channels.
+    fn errors(rgba: &[u8], pixels: &[[f32; 3]]) -> (f32, f32) {
+        let differences: Vec<f32> = rgba
+            .chunks_exact(4)
+            .zip(pixels)
+            .flat_map(|(expected, decoded)| {
+                (0..3).map(move |channel| (expected[channel] as f32 - decoded[channel]).abs())
+            })
+            .collect();
+        let mean = differences.iter().sum::<f32>() / differences.len() as f32;
+        (mean, differences.into_iter().fold(0.0, f32::max))
+    }
+
+    #[test]
+    fn decodes_to_the_input() {
+        // smooth, and not a multiple of the 16 pixels of an MCU
+        let (width, height) = (53, 27);
+        let rgba: Vec<u8> = (0..width * height)
+            .flat_map(|index| {
+                let (x, y) = (index % width, index / width);
+                [(x * 4) as u8, (y * 8) as u8, (200 - x - y) as u8, 255]
+            })
+            .collect();
+        let jpeg = encode(&rgba, width as u32, height as u32, 90).unwrap();
+        let (decoded_width, decoded_height, pixels, left_over) = decode(&jpeg);
+        assert_eq!((decoded_width, decoded_height), (width, height));
+        assert!(left_over < 8);
+        let (mean, max) = errors(&rgba, &pixels);
+        assert!(mean < 4.0 && max < 16.0, "mean {} max {}", mean, max);
+
+        let flat = [90u8, 160, 30, 255].repeat(16 * 16);
+        let (_, _, pixels, _) = decode(&encode(&flat, 16, 16, 100).unwrap());
+        let (mean, max) = errors(&flat, &pixels);
+        assert!(mean < 1.0 && max < 2.0, "mean {} max {}", mean, max);
+    }
+
+    #[test]
+    fn stuffs_ff_bytes() {
+        // noise codes to long runs of ones
+        let mut state = 1u32;
+        let noise: Vec<u8> = (0..64 * 48 * 4)
+            .map(|_| {
+                state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (state >> 24) as u8
+            })
+            .collect();
+        let jpeg = encode(&noise, 64, 48, 100).unwrap();
+        assert!(jpeg.windows(2).any(|pair| pair == [0xff, 0x00]));
+        let (_, _, pixels, left_over) = decode(&jpeg);
+        assert!(left_over < 8);
+        assert_eq!(pixels.len(), 64 * 48);
+    }
+
+    #[test]
+    fn quality_scaling() {
+        assert_eq!(scale_quant(&LUMA_QUANT, 50), LUMA_QUANT);
+        assert!(
+            scale_quant(&LUMA_QUANT, 100)
+                .iter()
+                .all(|&value| value == 1)
+        );
+        assert_eq!(scale_quant(&LUMA_QUANT, 1)[0], 255);
+        assert!(encode(&[0; 4], 0, 1, 75).is_err());
+    }
+}
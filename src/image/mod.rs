@@ -0,0 +1,63 @@
+mod jpeg;
+mod png;
+mod vp8l;
+mod zlib;
+
+use crate::error::Result;
+use crate::frame::Frame;
+
+/// Still image encodings, e.g. for `ActiveSession::snapshot_image`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ImageFormat {
+    /// Lossless, keeps the alpha channel of formats that have one.
+    Png,
+    /// Baseline JPEG with 4:2:0 chroma, `quality` from 1 to 100.
+    Jpeg { quality: u8 },
+}
+
+/// Decodes a PNG into its width, height and tightly packed RGBA pixels.
+pub(crate) fn decode_png(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    png::decode(bytes)
+}
+
+/// The signature and header of an 8-bit RGB PNG, chunks follow with
+/// `png_chunk`.
+pub(crate) fn png_rgb_header(width: u32, height: u32) -> Vec<u8> {
+    png::rgb_header(width, height)
+}
+
+pub(crate) fn png_chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png::chunk(out, kind, data)
+}
+
+/// Tightly packed RGB rows as the data of an IDAT or fdAT chunk.
+pub(crate) fn png_rgb_data(rgb: &[u8], width: u32) -> Vec<u8> {
+    png::rgb_data(rgb, width)
+}
+
+/// The CRC-32 of PNG chunks, the one of zlib and Ethernet.
+#[cfg(feature = "webrtc")]
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    png::crc32(data)
+}
+
+/// A lossless WebP bitstream, the payload of a VP8L chunk, of tightly
+/// packed RGBA at most 16384 pixels wide and high.
+pub(crate) fn encode_vp8l(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    vp8l::encode(rgba, width, height)
+}
+
+/// Encodes a memory frame as a standalone image file.
+pub fn encode(frame: &Frame<'_>, format: ImageFormat) -> Result<Vec<u8>> {
+    let rgba = frame.to_rgba()?;
+    let (width, height) = (frame.width(), frame.height());
+    match format {
+        ImageFormat::Png => Ok(png::encode(
+            &rgba,
+            width,
+            height,
+            frame.pixel_format().has_alpha(),
+        )),
+        ImageFormat::Jpeg { quality } => jpeg::encode(&rgba, width, height, quality),
+    }
+}
@@ -0,0 +1,381 @@
+use super::zlib;
+use crate::error::{Result, ScreencastError};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// 8-bit RGB or RGBA from tightly packed RGBA input.
+pub(super) fn encode(rgba: &[u8], width: u32, height: u32, alpha: bool) -> Vec<u8> {
+    let channels = if alpha { 4 } else { 3 };
+    let row_len = width as usize * channels;
+    let mut raw = Vec::with_capacity(row_len * height as usize);
+    for row in rgba.chunks_exact(width as usize * 4).take(height as usize) {
+        match alpha {
+            true => raw.extend_from_slice(row),
+            false => row
+                .chunks_exact(4)
+                .for_each(|pixel| raw.extend_from_slice(&pixel[..3])),
+        }
+    }
+    let filtered = filter(&raw, row_len, channels);
+
+    let mut out = SIGNATURE.to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, if alpha { 6 } else { 2 }, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+    chunk(&mut out, b"IDAT", &zlib::compress(&filtered));
+    chunk(&mut out, b"IEND", &[]);
+    out
+}
+
+/// The signature and header of an 8-bit RGB image, the start of an
+/// animated PNG.
+pub(super) fn rgb_header(width: u32, height: u32) -> Vec<u8> {
+    let mut out = SIGNATURE.to_vec();
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    chunk(&mut out, b"IHDR", &header);
+    out
+}
+
+/// Filtered and compressed rows of 8-bit RGB, the data of IDAT or fdAT.
+pub(super) fn rgb_data(rgb: &[u8], width: u32) -> Vec<u8> {
+    zlib::compress(&filter(rgb, width as usize * 3, 3))
+}
+
+pub(super) fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = out.len();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(data);
+    let crc = crc32(&out[start..]);
+    out.extend_from_slice(&crc.to_be_bytes());
+}
+
+pub(super) fn crc32(data: &[u8]) -> u32 {
+    static TABLE: std::sync::OnceLock<[u32; 256]> = std::sync::OnceLock::new();
+    let table = TABLE.get_or_init(|| {
+        let mut table = [0u32; 256];
+        for (index, entry) in table.iter_mut().enumerate() {
+            let mut crc = index as u32;
+            for _ in 0..8 {
+                crc = match crc & 1 {
+                    1 => 0xedb8_8320 ^ (crc >> 1),
+                    _ => crc >> 1,
+                };
+            }
+            *entry = crc;
+        }
+        table
+    });
+    !data.iter().fold(!0u32, |crc, byte| {
+        table[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+/// Picks the filter with the smallest sum of absolute differences per row.
+fn filter(raw: &[u8], row_len: usize, bpp: usize) -> Vec<u8> {
+    let rows = raw.len().checked_div(row_len).unwrap_or(0);
+    let mut out = Vec::with_capacity(raw.len() + rows);
+    let zero = vec![0u8; row_len];
+    let mut candidate = vec![0u8; row_len];
+    let mut best = vec![0u8; row_len];
+    for y in 0..rows {
+        let row = &raw[y * row_len..(y + 1) * row_len];
+        let up = match y {
+            0 => &zero[..],
+            _ => &raw[(y - 1) * row_len..y * row_len],
+        };
+        let mut best_kind = 0;
+        let mut best_score = u64::MAX;
+        for kind in 0..5u8 {
+            for x in 0..row_len {
+                let a = if x >= bpp { row[x - bpp] } else { 0 };
+                let b = up[x];
+                let c = if x >= bpp { up[x - bpp] } else { 0 };
+                let predicted = match kind {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    _ => paeth(a, b, c),
+                };
+                candidate[x] = row[x].wrapping_sub(predicted);
+            }
+            let score = candidate
+                .iter()
+                .map(|byte| (*byte as i8).unsigned_abs() as u64)
+                .sum();
+            if score < best_score {
+                best_score = score;
+                best_kind = kind;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        out.push(best_kind);
+        out.extend_from_slice(&best);
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Decodes a non-interlaced PNG into tightly packed RGBA, 16-bit samples
+/// are cut to their high byte.
+pub(crate) fn decode(bytes: &[u8]) -> Result<(u32, u32, Vec<u8>)> {
+    let invalid = || ScreencastError::Unsupported("malformed png".to_string());
+    if !bytes.starts_with(&SIGNATURE) {
+        return Err(invalid());
+    }
+    let mut position = SIGNATURE.len();
+    let mut header = None;
+    let mut palette: &[u8] = &[];
+    let mut transparency: &[u8] = &[];
+    let mut compressed = Vec::new();
+    while let Some(len) = bytes.get(position..position + 4) {
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize;
+        let kind = bytes.get(position + 4..position + 8).ok_or_else(invalid)?;
+        let data = bytes
+            .get(position + 8..position + 8 + len)
+            .ok_or_else(invalid)?;
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"PLTE" => palette = data,
+            b"tRNS" => transparency = data,
+            b"IDAT" => compressed.extend_from_slice(data),
+            b"IEND" => break,
+            _ => {}
+        }
+        position += 12 + len;
+    }
+    let header = header.ok_or_else(invalid)?;
+    let width = u32::from_be_bytes(header[0..4].try_into().unwrap());
+    let height = u32::from_be_bytes(header[4..8].try_into().unwrap());
+    let (depth, color_type, interlace) = (header[8] as usize, header[9], header[12]);
+    if interlace != 0 {
+        return Err(ScreencastError::Unsupported("interlaced png".to_string()));
+    }
+    let channels = match color_type {
+        0 | 3 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid()),
+    };
+    if !matches!(depth, 1 | 2 | 4 | 8 | 16) || (depth > 8 && color_type == 3) {
+        return Err(invalid());
+    }
+    let raw = zlib::decompress(&compressed).ok_or_else(invalid)?;
+    let (width_px, height_px) = (width as usize, height as usize);
+    let bits_per_pixel = channels * depth;
+    let row_len = (width_px * bits_per_pixel).div_ceil(8);
+    let pixels =
+        unfilter(&raw, row_len, bits_per_pixel.div_ceil(8), height_px).ok_or_else(invalid)?;
+
+    let sample = |row: &[u8], index: usize| {
+        let value = raw_sample(row, index, depth);
+        match depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ if color_type == 3 => value as u8,
+            _ => (value as u32 * 255 / ((1 << depth) - 1)) as u8,
+        }
+    };
+    // the raw sample, before scaling, to match the tRNS color key
+    let key = |index: usize| {
+        transparency
+            .get(index * 2..index * 2 + 2)
+            .map(|value| u16::from_be_bytes([value[0], value[1]]))
+    };
+    let mut out = Vec::with_capacity(width_px * height_px * 4);
+    for row in pixels.chunks_exact(row_len) {
+        for x in 0..width_px {
+            let base = x * channels;
+            let pixel = match color_type {
+                0 => {
+                    let gray = sample(row, base);
+                    let opaque = key(0).is_none_or(|key| key != raw_sample(row, base, depth));
+                    [gray, gray, gray, if opaque { 255 } else { 0 }]
+                }
+                2 => {
+                    let opaque = match (key(0), key(1), key(2)) {
+                        (Some(r), Some(g), Some(b)) => {
+                            (r, g, b)
+                                != (
+                                    raw_sample(row, base, depth),
+                                    raw_sample(row, base + 1, depth),
+                                    raw_sample(row, base + 2, depth),
+                                )
+                        }
+                        _ => true,
+                    };
+                    [
+                        sample(row, base),
+                        sample(row, base + 1),
+                        sample(row, base + 2),
+                        if opaque { 255 } else { 0 },
+                    ]
+                }
+                3 => {
+                    let index = sample(row, base) as usize;
+                    let color = palette.get(index * 3..index * 3 + 3).ok_or_else(invalid)?;
+                    let alpha = transparency.get(index).copied().unwrap_or(255);
+                    [color[0], color[1], color[2], alpha]
+                }
+                4 => {
+                    let gray = sample(row, base);
+                    [gray, gray, gray, sample(row, base + 1)]
+                }
+                _ => [
+                    sample(row, base),
+                    sample(row, base + 1),
+                    sample(row, base + 2),
+                    sample(row, base + 3),
+                ],
+            };
+            out.extend_from_slice(&pixel);
+        }
+    }
+    Ok((width, height, out))
+}
+
+fn raw_sample(row: &[u8], index: usize, depth: usize) -> u16 {
+    match depth {
+        16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+        8 => row[index] as u16,
+        _ => {
+            let bit = index * depth;
+            ((row[bit / 8] >> (8 - depth - bit % 8)) & ((1 << depth) - 1)) as u16
+        }
+    }
+}
+
+fn unfilter(raw: &[u8], row_len: usize, bpp: usize, rows: usize) -> Option<Vec<u8>> {
+    let mut out = vec![0u8; row_len * rows];
+    for y in 0..rows {
+        let start = y * (row_len + 1);
+        let kind = *raw.get(start)?;
+        let line = raw.get(start + 1..start + 1 + row_len)?;
+        let (previous, current) = out.split_at_mut(y * row_len);
+        let up = previous.get(previous.len().saturating_sub(row_len)..);
+        let current = &mut current[..row_len];
+        for x in 0..row_len {
+            let a = if x >= bpp { current[x - bpp] } else { 0 };
+            let b = match (y, up) {
+                (1.., Some(up)) => up[x],
+                _ => 0,
+            };
+            let c = match (y, up) {
+                (1.., Some(up)) if x >= bpp => up[x - bpp],
+                _ => 0,
+            };
+            let predicted = match kind {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth(a, b, c),
+                _ => return None,
+            };
+            current[x] = line[x].wrapping_add(predicted);
+        }
+    }
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A gradient with a noisy alpha, something for every filter.
+    fn picture(width: u32, height: u32) -> Vec<u8> {
+        (0..width * height)
+            .flat_map(|index| {
+                let (x, y) = (index % width, index / width);
+                [
+                    (x * 255 / width) as u8,
+                    (y * 255 / height) as u8,
+                    ((x ^ y) * 3) as u8,
+                    (index * 37 % 256) as u8,
+                ]
+            })
+            .collect()
+    }
+
+    /// The kinds of the chunks, each checked against its CRC.
+    fn chunks(png: &[u8]) -> Vec<[u8; 4]> {
+        assert!(png.starts_with(&SIGNATURE));
+        let mut kinds = Vec::new();
+        let mut rest = &png[SIGNATURE.len()..];
+        while !rest.is_empty() {
+            let len = u32::from_be_bytes(rest[..4].try_into().unwrap()) as usize;
+            let (covered, crc) = rest[4..].split_at(4 + len);
+            assert_eq!(crc32(covered).to_be_bytes(), crc[..4]);
+            kinds.push(covered[..4].try_into().unwrap());
+            rest = &crc[4..];
+        }
+        kinds
+    }
+
+    #[test]
+    fn crc32_of_known_input() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+    }
+
+    #[test]
+    fn round_trip() {
+        for (width, height) in [(1, 1), (37, 21), (64, 3)] {
+            let rgba = picture(width, height);
+            let png = encode(&rgba, width, height, true);
+            assert_eq!(chunks(&png), [*b"IHDR", *b"IDAT", *b"IEND"]);
+            assert_eq!(png[24..26], [8, 6]);
+            assert_eq!(decode(&png).unwrap(), (width, height, rgba.clone()));
+
+            let png = encode(&rgba, width, height, false);
+            assert_eq!(png[24..26], [8, 2]);
+            let opaque: Vec<u8> = rgba
+                .chunks_exact(4)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 255])
+                .collect();
+            assert_eq!(decode(&png).unwrap(), (width, height, opaque));
+        }
+    }
+    #[test]
+    fn animation_frames() {
+        let rgba = picture(5, 4);
+        let rgb: Vec<u8> = rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| pixel[..3].to_vec())
+            .collect();
+        let mut png = rgb_header(5, 4);
+        chunk(&mut png, b"IDAT", &rgb_data(&rgb, 5));
+        chunk(&mut png, b"IEND", &[]);
+        assert_eq!(chunks(&png), [*b"IHDR", *b"IDAT", *b"IEND"]);
+        let (_, _, decoded) = decode(&png).unwrap();
+        assert!(
+            decoded
+                .chunks_exact(4)
+                .map(|pixel| &pixel[..3])
+                .eq(rgb.chunks_exact(3))
+        );
+    }
+}
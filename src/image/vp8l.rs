@@ -0,0 +1,758 @@
+use super::zlib::BitWriter;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 4096;
+// the largest distance 40 prefix codes reach, less the 120 short codes
+const WINDOW: usize = (1 << 20) - 120;
+const HASH_BITS: u32 = 16;
+// blocks of 16 by 16 pixels share a predictor
+const PREDICTOR_BITS: u32 = 4;
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    17, 18, 0, 1, 2, 3, 4, 5, 16, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Debug, Copy, Clone)]
+enum Token {
+    Literal(u32),
+    Copy { length: usize, distance: usize },
+}
+
+/// VP8L with the subtract green and predictor transforms, one set of
+/// prefix codes and greedy backward references, found by hashing, in the
+/// row above and to the left.
+pub(super) fn encode(rgba: &[u8], width: u32, height: u32) -> Vec<u8> {
+    let pixels: Vec<u32> = rgba
+        .chunks_exact(4)
+        .take(width as usize * height as usize)
+        .map(|pixel| {
+            let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+            u32::from_be_bytes([a, r.wrapping_sub(g), g, b.wrapping_sub(g)])
+        })
+        .collect();
+    let alpha = pixels.iter().any(|pixel| pixel >> 24 != 0xff);
+    let (modes, residuals) = predict(&pixels, width as usize, height as usize);
+
+    let mut bits = BitWriter::default();
+    bits.write(0x2f, 8);
+    bits.write(width - 1, 14);
+    bits.write(height - 1, 14);
+    bits.write(alpha as u32, 1);
+    bits.write(0, 3);
+    // subtract green, the predictor with its modes, then no more transforms
+    bits.write(1, 1);
+    bits.write(2, 2);
+    bits.write(1, 1);
+    bits.write(0, 2);
+    bits.write(PREDICTOR_BITS - 2, 3);
+    write_image(
+        &mut bits,
+        &modes,
+        (width as usize).div_ceil(1 << PREDICTOR_BITS),
+        false,
+    );
+    bits.write(0, 1);
+    write_image(&mut bits, &residuals, width as usize, true);
+    bits.finish()
+}
+
+/// An entropy coded image, the main one has a bit for meta prefix codes.
+fn write_image(bits: &mut BitWriter, pixels: &[u32], width: usize, main: bool) {
+    let tokens = backward_references(pixels, width);
+    // green with the length prefixes, red, blue, alpha and distance
+    let mut counts = [
+        vec![0u32; 256 + 24],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 256],
+        vec![0; 40],
+    ];
+    for token in &tokens {
+        match *token {
+            Token::Literal(argb) => {
+                let [a, r, g, b] = argb.to_be_bytes();
+                counts[0][g as usize] += 1;
+                counts[1][r as usize] += 1;
+                counts[2][b as usize] += 1;
+                counts[3][a as usize] += 1;
+            }
+            Token::Copy { length, distance } => {
+                counts[0][256 + prefix(length).0 as usize] += 1;
+                counts[4][prefix(distance_code(distance, width)).0 as usize] += 1;
+            }
+        }
+    }
+
+    // no colour cache, no meta prefix codes
+    bits.write(0, 1);
+    if main {
+        bits.write(0, 1);
+    }
+    let codes = counts.map(|counts| {
+        let code = PrefixCode::new(&counts, 15);
+        code.write_lengths(bits);
+        code
+    });
+    for token in tokens {
+        match token {
+            Token::Literal(argb) => {
+                let [a, r, g, b] = argb.to_be_bytes();
+                codes[0].write(bits, g as usize);
+                codes[1].write(bits, r as usize);
+                codes[2].write(bits, b as usize);
+                codes[3].write(bits, a as usize);
+            }
+            Token::Copy { length, distance } => {
+                let (symbol, extra, len) = prefix(length);
+                codes[0].write(bits, 256 + symbol as usize);
+                bits.write(extra, len);
+                let (symbol, extra, len) = prefix(distance_code(distance, width));
+                codes[4].write(bits, symbol as usize);
+                bits.write(extra, len);
+            }
+        }
+    }
+}
+
+/// The predictor of each block, in the green of the first image, picked by
+/// the smallest residuals, and the residuals of all pixels.
+fn predict(pixels: &[u32], width: usize, height: usize) -> (Vec<u32>, Vec<u32>) {
+    let size = 1 << PREDICTOR_BITS;
+    let mut modes = Vec::new();
+    let mut residuals = vec![0; pixels.len()];
+    for top in (0..height).step_by(size) {
+        for left in (0..width).step_by(size) {
+            let block = || {
+                (top..(top + size).min(height))
+                    .flat_map(move |y| (left..(left + size).min(width)).map(move |x| (x, y)))
+            };
+            let cost = |mode: u8| -> u32 {
+                block()
+                    .map(|(x, y)| {
+                        let residual =
+                            sub(pixels[y * width + x], predictor(pixels, width, x, y, mode));
+                        residual
+                            .to_be_bytes()
+                            .iter()
+                            .map(|&value| (value as i8).unsigned_abs() as u32)
+                            .sum::<u32>()
+                    })
+                    .sum()
+            };
+            let mode = (0..14).min_by_key(|&mode| cost(mode)).unwrap_or(0);
+            modes.push((mode as u32) << 8);
+            for (x, y) in block() {
+                let index = y * width + x;
+                residuals[index] = sub(pixels[index], predictor(pixels, width, x, y, mode));
+            }
+        }
+    }
+    (modes, residuals)
+}
+
+fn predictor(pixels: &[u32], width: usize, x: usize, y: usize, mode: u8) -> u32 {
+    let index = y * width + x;
+    match (x, y) {
+        (0, 0) => return 0xff00_0000,
+        (_, 0) => return pixels[index - 1],
+        (0, _) => return pixels[index - width],
+        _ => {}
+    }
+    let left = pixels[index - 1];
+    let top = pixels[index - width];
+    let top_left = pixels[index - width - 1];
+    // in the last column the first pixel of the row
+    let top_right = pixels[index - width + 1];
+    match mode {
+        0 => 0xff00_0000,
+        1 => left,
+        2 => top,
+        3 => top_right,
+        4 => top_left,
+        5 => average(average(left, top_right), top),
+        6 => average(left, top_left),
+        7 => average(left, top),
+        8 => average(top_left, top),
+        9 => average(top, top_right),
+        10 => average(average(left, top_left), average(top, top_right)),
+        11 => select(left, top, top_left),
+        12 => channels([left, top, top_left], |[a, b, c]| a + b - c),
+        _ => channels([average(left, top), top_left, 0], |[a, b, _]| {
+            a + (a - b) / 2
+        }),
+    }
+}
+
+/// Applies `f` to each channel of the pixels, clamping the result.
+fn channels(pixels: [u32; 3], f: impl Fn([i32; 3]) -> i32) -> u32 {
+    let bytes = pixels.map(u32::to_be_bytes);
+    u32::from_be_bytes(std::array::from_fn(|channel| {
+        f(bytes.map(|pixel| pixel[channel] as i32)).clamp(0, 255) as u8
+    }))
+}
+
+fn average(a: u32, b: u32) -> u32 {
+    channels([a, b, 0], |[a, b, _]| (a + b) / 2)
+}
+
+/// The left or top pixel, whichever is closer to their gradient.
+fn select(left: u32, top: u32, top_left: u32) -> u32 {
+    let distance = |pixel: u32| -> i32 {
+        let [l, t, tl, p] = [left, top, top_left, pixel].map(u32::to_be_bytes);
+        (0..4)
+            .map(|channel| {
+                let estimate = l[channel] as i32 + t[channel] as i32 - tl[channel] as i32;
+                (estimate - p[channel] as i32).abs()
+            })
+            .sum()
+    };
+    match distance(left) < distance(top) {
+        true => left,
+        false => top,
+    }
+}
+
+fn sub(pixel: u32, prediction: u32) -> u32 {
+    let [a, b] = [pixel, prediction].map(u32::to_be_bytes);
+    u32::from_be_bytes(std::array::from_fn(|channel| {
+        a[channel].wrapping_sub(b[channel])
+    }))
+}
+
+fn backward_references(pixels: &[u32], width: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |position: usize| {
+        let value = (pixels[position] as u64) << 32 | pixels[position + 1] as u64;
+        (value.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> (64 - HASH_BITS)) as usize
+    };
+    let match_length = |candidate: usize, position: usize| {
+        let limit = MAX_MATCH.min(pixels.len() - position);
+        (0..limit)
+            .position(|at| pixels[candidate + at] != pixels[position + at])
+            .unwrap_or(limit)
+    };
+    let mut position = 0;
+    while position < pixels.len() {
+        let mut best = (0, 0);
+        if position + 1 < pixels.len() {
+            let key = hash(position);
+            let candidates = [
+                head[key],
+                position.wrapping_sub(width),
+                position.wrapping_sub(1),
+            ];
+            head[key] = position;
+            for candidate in candidates {
+                if candidate < position && position - candidate <= WINDOW {
+                    let length = match_length(candidate, position);
+                    if length > best.0 {
+                        best = (length, position - candidate);
+                    }
+                }
+            }
+        }
+        let (length, distance) = best;
+        if length >= MIN_MATCH {
+            tokens.push(Token::Copy { length, distance });
+            for skipped in position + 1..(position + length).min(pixels.len() - 1) {
+                head[hash(skipped)] = skipped;
+            }
+            position += length;
+        } else {
+            tokens.push(Token::Literal(pixels[position]));
+            position += 1;
+        }
+    }
+    tokens
+}
+
+/// The pixel above and the one to the left have short codes, the other
+/// distances follow the 120 codes of the neighbourhood.
+fn distance_code(distance: usize, width: usize) -> usize {
+    match distance {
+        distance if distance == width => 1,
+        1 => 2,
+        distance => distance + 120,
+    }
+}
+
+/// The prefix symbol of a length or distance, its extra bits and their
+/// count.
+fn prefix(value: usize) -> (u32, u32, u32) {
+    let value = value as u32 - 1;
+    if value < 4 {
+        return (value, 0, 0);
+    }
+    let highest = 31 - value.leading_zeros();
+    let second = (value >> (highest - 1)) & 1;
+    let extra = highest - 1;
+    (2 * highest + second, value & ((1 << extra) - 1), extra)
+}
+
+#[derive(Debug)]
+struct PrefixCode {
+    lengths: Vec<u8>,
+    codes: Vec<u32>,
+    // a code of one symbol takes no bits
+    single: bool,
+}
+
+impl PrefixCode {
+    fn new(counts: &[u32], limit: u8) -> Self {
+        let lengths = code_lengths(counts, limit);
+        let used = lengths.iter().filter(|&&length| length > 0).count();
+        PrefixCode {
+            codes: canonical_codes(&lengths),
+            lengths,
+            single: used <= 1,
+        }
+    }
+
+    fn write(&self, bits: &mut BitWriter, symbol: usize) {
+        if !self.single {
+            bits.write_code(self.codes[symbol], self.lengths[symbol] as u32);
+        }
+    }
+
+    /// The code lengths, themselves compressed with a code for lengths
+    /// and runs, or the simple form for a code of up to two symbols.
+    fn write_lengths(&self, bits: &mut BitWriter) {
+        let used: Vec<usize> = (0..self.lengths.len())
+            .filter(|&symbol| self.lengths[symbol] > 0)
+            .collect();
+        if used.len() <= 2 && used.iter().all(|&symbol| symbol < 256) {
+            let symbols = match used.is_empty() {
+                true => vec![0],
+                false => used,
+            };
+            bits.write(1, 1);
+            bits.write(symbols.len() as u32 - 1, 1);
+            match symbols[0] {
+                symbol @ 0..=1 => {
+                    bits.write(0, 1);
+                    bits.write(symbol as u32, 1);
+                }
+                symbol => {
+                    bits.write(1, 1);
+                    bits.write(symbol as u32, 8);
+                }
+            }
+            if let Some(&symbol) = symbols.get(1) {
+                bits.write(symbol as u32, 8);
+            }
+            return;
+        }
+        bits.write(0, 1);
+
+        let runs = runs(&self.lengths);
+        let mut counts = [0u32; 19];
+        runs.iter()
+            .for_each(|&(symbol, _)| counts[symbol as usize] += 1);
+        let code = PrefixCode::new(&counts, 7);
+        let count = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| code.lengths[symbol] > 0)
+            .map_or(0, |at| at + 1)
+            .max(4);
+        bits.write(count as u32 - 4, 4);
+        for &symbol in &CODE_LENGTH_ORDER[..count] {
+            bits.write(code.lengths[symbol] as u32, 3);
+        }
+        // every symbol has a length
+        bits.write(0, 1);
+        for (symbol, extra) in runs {
+            code.write(bits, symbol as usize);
+            match symbol {
+                16 => bits.write(extra as u32, 2),
+                17 => bits.write(extra as u32, 3),
+                18 => bits.write(extra as u32, 7),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Code lengths as symbols of the length code with their extra bits: 16
+/// repeats the previous length 3 to 6 times, 17 and 18 are runs of 3 to 10
+/// and 11 to 138 zeros.
+fn runs(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut previous = 8;
+    let mut position = 0;
+    while position < lengths.len() {
+        let length = lengths[position];
+        let run = lengths[position..]
+            .iter()
+            .take_while(|&&other| other == length)
+            .count();
+        match length {
+            0 if run >= 11 => {
+                let run = run.min(138);
+                runs.push((18, (run - 11) as u8));
+                position += run;
+            }
+            0 if run >= 3 => {
+                runs.push((17, (run - 3) as u8));
+                position += run;
+            }
+            length if length == previous && run >= 3 => {
+                let run = run.min(6);
+                runs.push((16, (run - 3) as u8));
+                position += run;
+            }
+            length => {
+                runs.push((length, 0));
+                position += 1;
+                if length > 0 {
+                    previous = length;
+                }
+            }
+        }
+    }
+    runs
+}
+
+/// Huffman code lengths of at most `limit` bits, the counts are halved
+/// until the tree is shallow enough.
+fn code_lengths(counts: &[u32], limit: u8) -> Vec<u8> {
+    let mut counts = counts.to_vec();
+    loop {
+        let mut lengths = vec![0u8; counts.len()];
+        let mut heap: BinaryHeap<_> = counts
+            .iter()
+            .enumerate()
+            .filter(|(_, count)| **count > 0)
+            .map(|(symbol, count)| Reverse((*count as u64, symbol)))
+            .collect();
+        if heap.len() == 1 {
+            let Reverse((_, symbol)) = heap.pop().unwrap_or(Reverse((0, 0)));
+            lengths[symbol] = 1;
+            return lengths;
+        }
+        // leaves are the symbols, inner nodes follow
+        let mut parents = vec![usize::MAX; counts.len()];
+        while heap.len() > 1 {
+            let (Some(Reverse((a, first))), Some(Reverse((b, second)))) = (heap.pop(), heap.pop())
+            else {
+                break;
+            };
+            let node = parents.len();
+            parents.push(usize::MAX);
+            parents[first] = node;
+            parents[second] = node;
+            heap.push(Reverse((a + b, node)));
+        }
+        let mut deepest = 0;
+        for (symbol, length) in lengths.iter_mut().enumerate() {
+            if counts[symbol] == 0 {
+                continue;
+            }
+            let mut node = symbol;
+            while parents[node] != usize::MAX {
+                node = parents[node];
+                *length += 1;
+            }
+            deepest = deepest.max(*length);
+        }
+        if deepest <= limit {
+            return lengths;
+        }
+        counts
+            .iter_mut()
+            .filter(|count| **count > 0)
+            .for_each(|count| *count = count.div_ceil(2));
+    }
+}
+
+fn canonical_codes(lengths: &[u8]) -> Vec<u32> {
+    let mut per_length = [0u32; 16];
+    lengths
+        .iter()
+        .for_each(|&length| per_length[length as usize] += 1);
+    per_length[0] = 0;
+    let mut next = [0u32; 16];
+    let mut code = 0;
+    for length in 1..16 {
+        code = (code + per_length[length - 1]) << 1;
+        next[length] = code;
+    }
+    lengths
+        .iter()
+        .map(|&length| match length {
+            0 => 0,
+            length => {
+                let code = next[length as usize];
+                next[length as usize] += 1;
+                code
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Bits<'d> {
+        data: &'d [u8],
+        position: usize,
+    }
+
+    impl Bits<'_> {
+        fn read(&mut self, len: u32) -> u32 {
+            (0..len).fold(0, |value, bit| {
+                let byte = self.data[self.position / 8];
+                self.position += 1;
+                value | (((byte >> ((self.position - 1) % 8)) & 1) as u32) << bit
+            })
+        }
+    }
+
+    /// A canonical prefix code, `None` for a code of one symbol taking no
+    /// bits.
+    struct Code {
+        symbols: Vec<(u32, u32, usize)>,
+        single: Option<usize>,
+    }
+
+    impl Code {
+        fn new(lengths: &[u8]) -> Self {
+            let used: Vec<usize> = (0..lengths.len()).filter(|&s| lengths[s] > 0).collect();
+            let codes = canonical_codes(lengths);
+            Code {
+                symbols: used
+                    .iter()
+                    .map(|&symbol| (lengths[symbol] as u32, codes[symbol], symbol))
+                    .collect(),
+                single: (used.len() == 1).then(|| used[0]),
+            }
+        }
+
+        fn read(bits: &mut Bits<'_>, alphabet: usize) -> Self {
+            if bits.read(1) == 1 {
+                let count = bits.read(1) + 1;
+                let first = match bits.read(1) {
+                    0 => bits.read(1),
+                    _ => bits.read(8),
+                };
+                let mut lengths = vec![0u8; alphabet];
+                lengths[first as usize] = 1;
+                if count == 2 {
+                    lengths[bits.read(8) as usize] = 1;
+                }
+                return Code::new(&lengths);
+            }
+            let count = bits.read(4) as usize + 4;
+            let mut length_lengths = [0u8; 19];
+            for &symbol in &CODE_LENGTH_ORDER[..count] {
+                length_lengths[symbol] = bits.read(3) as u8;
+            }
+            let length_code = Code::new(&length_lengths);
+            let mut remaining = match bits.read(1) {
+                0 => alphabet,
+                _ => {
+                    let len = 2 + 2 * bits.read(3);
+                    2 + bits.read(len) as usize
+                }
+            };
+            let mut lengths = Vec::with_capacity(alphabet);
+            let mut previous = 8;
+            while lengths.len() < alphabet && remaining > 0 {
+                remaining -= 1;
+                match length_code.decode(bits) {
+                    length @ 0..16 => {
+                        lengths.push(length as u8);
+                        if length > 0 {
+                            previous = length as u8;
+                        }
+                    }
+                    16 => {
+                        let repeat = 3 + bits.read(2) as usize;
+                        lengths.extend(std::iter::repeat_n(previous, repeat));
+                    }
+                    17 => lengths.extend(std::iter::repeat_n(0, 3 + bits.read(3) as usize)),
+                    _ => lengths.extend(std::iter::repeat_n(0, 11 + bits.read(7) as usize)),
+                }
+            }
+            lengths.resize(alphabet, 0);
+            Code::new(&lengths)
+        }
+
+        fn decode(&self, bits: &mut Bits<'_>) -> usize {
+            if let Some(symbol) = self.single {
+                return symbol;
+            }
+            let mut code = 0;
+            for len in 1..=15 {
+                code = code << 1 | bits.read(1);
+                let found = self
+                    .symbols
+                    .iter()
+                    .find(|(l, c, _)| (*l, *c) == (len, code));
+                if let Some((_, _, symbol)) = found {
+                    return *symbol;
+                }
+            }
+            panic!("no prefix code at bit {}", bits.position);
+        }
+    }
+
+    fn read_prefixed(bits: &mut Bits<'_>, symbol: usize) -> usize {
+        if symbol < 4 {
+            return symbol + 1;
+        }
+        let extra = (symbol as u32 - 2) >> 1;
+        let offset = (2 + (symbol & 1)) << extra;
+        offset + bits.read(extra) as usize + 1
+    }
+
+    /// An entropy coded image without a colour cache or meta prefix codes,
+    /// the only kind `encode` writes.
+    fn read_image(bits: &mut Bits<'_>, width: usize, height: usize, main: bool) -> Vec<u32> {
+        assert_eq!(bits.read(1), 0, "colour cache");
+        if main {
+            assert_eq!(bits.read(1), 0, "meta prefix codes");
+        }
+        let codes = [256 + 24, 256, 256, 256, 40].map(|alphabet| Code::read(bits, alphabet));
+        let mut pixels = Vec::with_capacity(width * height);
+        while pixels.len() < width * height {
+            let green = codes[0].decode(bits);
+            if green < 256 {
+                let red = codes[1].decode(bits);
+                let blue = codes[2].decode(bits);
+                let alpha = codes[3].decode(bits);
+                pixels.push(u32::from_be_bytes(
+                    [alpha, red, green, blue].map(|c| c as u8),
+                ));
+                continue;
+            }
+            let length = read_prefixed(bits, green - 256);
+            let symbol = codes[4].decode(bits);
+            let distance = match read_prefixed(bits, symbol) {
+                // the first two of the neighbourhood, what `encode` uses
+                1 => width,
+                2 => 1,
+                code => {
+                    assert!(code > 120, "distance code {}", code);
+                    code - 120
+                }
+            };
+            let start = pixels.len() - distance;
+            for at in 0..length {
+                pixels.push(pixels[start + at]);
+            }
+        }
+        pixels
+    }
+
+    /// Decodes the VP8L `encode` writes back to RGBA, undoing its
+    /// transforms.
+    fn decode(data: &[u8]) -> (u32, u32, bool, Vec<u8>) {
+        let mut bits = Bits { data, position: 0 };
+        assert_eq!(bits.read(8), 0x2f);
+        let width = bits.read(14) as usize + 1;
+        let height = bits.read(14) as usize + 1;
+        let alpha = bits.read(1) == 1;
+        assert_eq!(bits.read(3), 0);
+        let mut transforms = Vec::new();
+        while bits.read(1) == 1 {
+            match bits.read(2) {
+                0 => {
+                    let size = bits.read(3) + 2;
+                    let blocks_wide = width.div_ceil(1 << size);
+                    let blocks_high = height.div_ceil(1 << size);
+                    let modes = read_image(&mut bits, blocks_wide, blocks_high, false);
+                    transforms.push(Some((size, blocks_wide, modes)));
+                }
+                2 => transforms.push(None),
+                other => panic!("transform {}", other),
+            }
+        }
+        let mut pixels = read_image(&mut bits, width, height, true);
+        for transform in transforms.into_iter().rev() {
+            match transform {
+                Some((size, blocks_wide, modes)) => {
+                    for index in 0..pixels.len() {
+                        let (x, y) = (index % width, index / width);
+                        let block = (y >> size) * blocks_wide + (x >> size);
+                        let mode = (modes[block] >> 8 & 0x0f) as u8;
+                        let prediction = predictor(&pixels, width, x, y, mode);
+                        let [a, b] = [pixels[index], prediction].map(u32::to_be_bytes);
+                        pixels[index] = u32::from_be_bytes(std::array::from_fn(|channel| {
+                            a[channel].wrapping_add(b[channel])
+                        }));
+                    }
+                }
+                None => {
+                    for pixel in &mut pixels {
+                        let [a, r, g, b] = pixel.to_be_bytes();
+                        *pixel = u32::from_be_bytes([a, r.wrapping_add(g), g, b.wrapping_add(g)]);
+                    }
+                }
+            }
+        }
+        let rgba = pixels
+            .into_iter()
+            .flat_map(|pixel| {
+                let [a, r, g, b] = pixel.to_be_bytes();
+                [r, g, b, a]
+            })
+            .collect();
+        (width as u32, height as u32, alpha, rgba)
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut state = 5u32;
+        let mut noise = || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 24) as u8
+        };
+        // a gradient with repeats, flat areas and a bit of noise
+        let (width, height) = (75u32, 41u32);
+        let picture: Vec<u8> = (0..width * height)
+            .flat_map(|index| {
+                let (x, y) = (index % width, index / width);
+                match (x / 10 + y / 10) % 3 {
+                    0 => [(x * 3) as u8, (y * 5) as u8, 90, 255],
+                    1 => [40, 40, 40, 255],
+                    _ => [noise(), noise() & 0xf0, (x ^ y) as u8, 255],
+                }
+            })
+            .collect();
+        let translucent: Vec<u8> = picture
+            .chunks_exact(4)
+            .enumerate()
+            .flat_map(|(index, pixel)| [pixel[0], pixel[1], pixel[2], (index % 251) as u8])
+            .collect();
+        let cases = [
+            (1, 1, vec![10, 20, 30, 255], false),
+            (3, 2, [1u8, 2, 3, 4].repeat(6), true),
+            (width, height, picture, false),
+            (width, height, translucent, true),
+            (16, 16, [200, 100, 0, 255].repeat(256), false),
+        ];
+        for (width, height, rgba, alpha) in cases {
+            let encoded = encode(&rgba, width, height);
+            assert_eq!(decode(&encoded), (width, height, alpha, rgba));
+        }
+    }
+
+    #[test]
+    fn prefix_codes() {
+        // values 1 to 4 have no extra bits, then two codes per power of two
+        assert_eq!(prefix(1), (0, 0, 0));
+        assert_eq!(prefix(4), (3, 0, 0));
+        assert_eq!(prefix(5), (4, 0, 1));
+        assert_eq!(prefix(7), (5, 0, 1));
+        assert_eq!(prefix(4096), (23, 1023, 10));
+        assert_eq!(distance_code(1, 75), 2);
+        assert_eq!(distance_code(75, 75), 1);
+        assert_eq!(distance_code(76, 75), 196);
+        let lengths = code_lengths(&[5, 1, 0, 1, 9], 15);
+        assert_eq!(lengths, [2, 3, 0, 3, 1]);
+        assert_eq!(canonical_codes(&lengths), [0b10, 0b110, 0, 0b111, 0b0]);
+    }
+}
@@ -0,0 +1,406 @@
+const WINDOW: usize = 32768;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// A zlib stream of a single fixed Huffman block.
+pub(super) fn compress(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // zlib header for deflate with a 32K window
+    bits.out.extend_from_slice(&[0x78, 0x01]);
+    deflate(data, &mut bits);
+    let mut out = bits.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for byte in chunk {
+            a += *byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    (b << 16) | a
+}
+
+/// A single fixed Huffman block with greedy LZ77 matching.
+fn deflate(data: &[u8], bits: &mut BitWriter) {
+    bits.write(1, 1);
+    bits.write(1, 2);
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let hash = |position: usize| {
+        let value = u32::from_le_bytes([data[position], data[position + 1], data[position + 2], 0]);
+        (value.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    };
+    let mut position = 0;
+    while position < data.len() {
+        let mut length = 0;
+        let mut distance = 0;
+        if position + MIN_MATCH <= data.len() {
+            let key = hash(position);
+            let candidate = head[key];
+            head[key] = position;
+            if candidate != usize::MAX && position - candidate <= WINDOW {
+                let limit = MAX_MATCH.min(data.len() - position);
+                while length < limit && data[candidate + length] == data[position + length] {
+                    length += 1;
+                }
+                distance = position - candidate;
+            }
+        }
+        if length >= MIN_MATCH {
+            write_match(bits, length, distance);
+            for skipped in position + 1..(position + length).min(data.len() - MIN_MATCH + 1) {
+                head[hash(skipped)] = skipped;
+            }
+            position += length;
+        } else {
+            write_literal(bits, data[position] as u16);
+            position += 1;
+        }
+    }
+    write_literal(bits, 256);
+}
+
+fn write_literal(bits: &mut BitWriter, symbol: u16) {
+    let (code, len) = match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    };
+    bits.write_code(code as u32, len);
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let index = LENGTH_BASE
+        .iter()
+        .rposition(|base| *base as usize <= length)
+        .unwrap_or(0);
+    write_literal(bits, 257 + index as u16);
+    bits.write(
+        (length - LENGTH_BASE[index] as usize) as u32,
+        LENGTH_EXTRA[index] as u32,
+    );
+    let index = DISTANCE_BASE
+        .iter()
+        .rposition(|base| *base as usize <= distance)
+        .unwrap_or(0);
+    bits.write_code(index as u32, 5);
+    bits.write(
+        (distance - DISTANCE_BASE[index] as usize) as u32,
+        DISTANCE_EXTRA[index] as u32,
+    );
+}
+
+/// Deflate bit order, least significant bit first.
+#[derive(Default)]
+pub(super) struct BitWriter {
+    out: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    pub(super) fn write(&mut self, value: u32, len: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += len;
+        while self.count >= 8 {
+            self.out.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Huffman codes are stored most significant bit first.
+    pub(super) fn write_code(&mut self, code: u32, len: u32) {
+        self.write(code.reverse_bits() >> (32 - len), len);
+    }
+
+    pub(super) fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.out.push(self.buffer as u8);
+        }
+        self.out
+    }
+}
+
+/// Inflates a zlib stream, `None` when it is malformed.
+pub(super) fn decompress(data: &[u8]) -> Option<Vec<u8>> {
+    let (&cmf, &flg) = (data.first()?, data.get(1)?);
+    if cmf & 0x0f != 8 || !(((cmf as u16) << 8) | flg as u16).is_multiple_of(31) || flg & 0x20 != 0
+    {
+        return None;
+    }
+    let mut bits = BitReader {
+        data: &data[2..],
+        position: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                bits.position = bits.position.next_multiple_of(8);
+                let start = bits.position / 8;
+                let header = bits.data.get(start..start + 4)?;
+                let len = u16::from_le_bytes([header[0], header[1]]) as usize;
+                out.extend_from_slice(bits.data.get(start + 4..start + 4 + len)?);
+                bits.position = (start + 4 + len) * 8;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances)?;
+            }
+            _ => return None,
+        }
+        if last {
+            return Some(out);
+        }
+    }
+}
+
+fn read_dynamic(bits: &mut BitReader<'_>) -> Option<(Huffman, Huffman)> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_count = bits.read(4)? as usize + 4;
+    let mut code_lengths = [0u8; 19];
+    for index in ORDER.iter().take(code_count) {
+        code_lengths[*index] = bits.read(3)? as u8;
+    }
+    let codes = Huffman::new(&code_lengths)?;
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        match codes.decode(bits)? {
+            symbol @ 0..=15 => lengths.push(symbol as u8),
+            16 => {
+                let previous = *lengths.last()?;
+                let repeat = 3 + bits.read(2)? as usize;
+                lengths.extend(std::iter::repeat_n(previous, repeat));
+            }
+            17 => {
+                let repeat = 3 + bits.read(3)? as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            }
+            _ => {
+                let repeat = 11 + bits.read(7)? as usize;
+                lengths.extend(std::iter::repeat_n(0, repeat));
+            }
+        }
+    }
+    if lengths.len() != literal_count + distance_count {
+        return None;
+    }
+    Some((
+        Huffman::new(&lengths[..literal_count])?,
+        Huffman::new(&lengths[literal_count..])?,
+    ))
+}
+
+fn inflate_block(
+    bits: &mut BitReader<'_>,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Option<()> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Some(()),
+            _ => {
+                let index = symbol - 257;
+                let length = *LENGTH_BASE.get(index)? as usize
+                    + bits.read(LENGTH_EXTRA[index] as u32)? as usize;
+                let index = distances.decode(bits)? as usize;
+                let distance = *DISTANCE_BASE.get(index)? as usize
+                    + bits.read(DISTANCE_EXTRA[index] as u32)? as usize;
+                let start = out.len().checked_sub(distance)?;
+                for offset in 0..length {
+                    out.push(out[start + offset]);
+                }
+            }
+        }
+    }
+}
+
+/// Canonical Huffman code, decoded a bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Option<Self> {
+        let mut counts = [0u16; 16];
+        for len in lengths {
+            *counts.get_mut(*len as usize)? += 1;
+        }
+        counts[0] = 0;
+        let mut offsets = [0u16; 16];
+        for len in 1..15 {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, len) in lengths.iter().enumerate() {
+            if *len != 0 {
+                symbols[offsets[*len as usize] as usize] = symbol as u16;
+                offsets[*len as usize] += 1;
+            }
+        }
+        Some(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut BitReader<'_>) -> Option<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.read(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return self.symbols.get((index + code - first) as usize).copied();
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+}
+
+struct BitReader<'d> {
+    data: &'d [u8],
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, len: u32) -> Option<u32> {
+        let mut value = 0;
+        for bit in 0..len {
+            let byte = *self.data.get(self.position / 8)?;
+            value |= (((byte >> (self.position % 8)) & 1) as u32) << bit;
+            self.position += 1;
+        }
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    /// Letters by a fixed generator, the input of the zlib streams below.
+    fn letters() -> Vec<u8> {
+        let mut state = 1u32;
+        (0..400)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345) & 0x7fff_ffff;
+                b"eeeettaaoinshrdl"[(state >> 16) as usize % 16]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adler32_of_known_input() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        // past the 5552 bytes the sums are reduced after
+        assert_eq!(adler32(&[0xff; 6000]), 0xa497_59ea);
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut noise = vec![0u8; 70_000];
+        let mut state = 7u32;
+        noise.fill_with(|| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        });
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"abcabcabcabcabcabcabc".repeat(1000),
+            vec![0; 100_000],
+            noise,
+            letters(),
+        ];
+        for input in inputs {
+            let compressed = compress(&input);
+            assert_eq!(u16::from_be_bytes([compressed[0], compressed[1]]) % 31, 0);
+            let trailer = &compressed[compressed.len() - 4..];
+            assert_eq!(trailer, adler32(&input).to_be_bytes());
+            assert_eq!(decompress(&compressed), Some(input));
+        }
+    }
+
+    // streams of Python's zlib.compress at levels 9 and 0
+    #[test]
+    fn inflates_zlib_output() {
+        let dynamic = hex(concat!(
+            "78da1d90010ac0300803df1a30a0200aeaff59ba31d6b53567228cbbcb3d4cba134c9a3ed013d51e",
+            "052e8fed7709bb2d463b0b9611993a62cf94a10ee6319620b9c93e5e38a7b6432783ac13fd6dccd9",
+            "542bedd52706d577ed70eed092810718896e66396669e84389232ef6ffc9104ecef840d769167b92",
+            "85d5ea4268c72981b2215e59f300bdce7b89200ba762c1d4446a87bc955dd15bb79ac895228a35af",
+            "5c1acb81c2cf1e7d857a1e55f5d88de48dc05a4e1392ac646c9fd33755dcb07cc0378cdc920b4d61",
+            "47623544a14776ecf80132a3a51e"
+        ));
+        assert_eq!(decompress(&dynamic), Some(letters()));
+
+        let text = [
+            &b"The quick brown fox jumps over the lazy dog. ".repeat(4)[..],
+            b"0123456789",
+        ]
+        .concat();
+        let fixed = hex(concat!(
+            "78da0bc94855282ccd4cce56482aca2fcf5348cbaf50c82acd2d2856c82f4b2d5228014ae72456",
+            "552aa4e4a7eb29840c0ec5068646c626a666e61696008ba442aa"
+        ));
+        assert_eq!(decompress(&fixed), Some(text.clone()));
+        let mut stored = hex("780101be0041ff");
+        stored.extend_from_slice(&text);
+        stored.extend_from_slice(&hex("8ba442aa"));
+        assert_eq!(decompress(&stored), Some(text));
+
+        assert_eq!(decompress(&dynamic[..dynamic.len() / 2]), None);
+        assert_eq!(decompress(&[0x78, 0x02]), None);
+    }
+}
@@ -0,0 +1,92 @@
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::raw::c_int;
+
+const F_GETFD: c_int = 1;
+const F_SETFD: c_int = 2;
+const FD_CLOEXEC: c_int = 1;
+
+unsafe extern "C" {
+    fn fcntl(fd: c_int, cmd: c_int, ...) -> c_int;
+}
+
+/// A `pipewiresrc` element reading `node_id` from the remote behind `fd`.
+pub(crate) fn pipewire_source(fd: RawFd, node_id: u32) -> String {
+    format!(
+        "pipewiresrc fd={} path={} do-timestamp=true keepalive-time=1000",
+        fd, node_id
+    )
+}
+
+/// `gst-launch-1.0` and ffmpeg command lines for each selected stream, to
+/// run with `sh -c` from this process.
+///
+/// They read from a duplicate of the PipeWire remote which, unlike the
+/// session's own, is inherited by child processes. It stays open as long
+/// as the `LaunchCommands`, keep them until the tools are started.
+#[derive(Debug)]
+pub struct LaunchCommands {
+    fd: OwnedFd,
+    node_ids: Vec<u32>,
+}
+
+impl LaunchCommands {
+    pub(crate) fn new(fd: OwnedFd, node_ids: Vec<u32>) -> io::Result<Self> {
+        unsafe {
+            let flags = fcntl(fd.as_raw_fd(), F_GETFD);
+            if flags < 0 || fcntl(fd.as_raw_fd(), F_SETFD, flags & !FD_CLOEXEC) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(LaunchCommands { fd, node_ids })
+    }
+
+    /// The remote the command lines refer to by number.
+    pub fn fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    pub fn node_ids(&self) -> &[u32] {
+        &self.node_ids
+    }
+
+    /// `gst-launch-1.0 -e pipewiresrc … ! tail` per stream, e.g. with
+    /// `"videoconvert ! x264enc ! mp4mux ! filesink location=out.mp4"`.
+    /// `-e` finishes the file when the pipeline is interrupted.
+    pub fn gst_launch(&self, tail: &str) -> Vec<String> {
+        self.node_ids
+            .iter()
+            .map(|&node_id| self.gst_launch_line("-e", node_id, tail))
+            .collect()
+    }
+
+    /// ffmpeg reading the stream as yuv4mpeg2 from `gst-launch-1.0`, which
+    /// does the PipeWire side, followed by `args` per stream, e.g.
+    /// `"-c:v libx264 out.mp4"`. Further inputs such as `-f pulse -i
+    /// default` for audio go into `args` as well.
+    pub fn ffmpeg(&self, args: &str) -> Vec<String> {
+        self.node_ids
+            .iter()
+            .map(|&node_id| {
+                let source = self.gst_launch_line(
+                    "-e -q",
+                    node_id,
+                    "videoconvert ! video/x-raw,format=I420 ! y4menc ! fdsink fd=1",
+                );
+                format!(
+                    "{} | ffmpeg -hide_banner -f yuv4mpegpipe -i pipe:0 {}",
+                    source, args
+                )
+            })
+            .collect()
+    }
+
+    fn gst_launch_line(&self, options: &str, node_id: u32, tail: &str) -> String {
+        format!(
+            "gst-launch-1.0 {} {} ! {}",
+            options,
+            pipewire_source(self.fd.as_raw_fd(), node_id),
+            tail
+        )
+    }
+}
@@ -1 +1,30 @@
+pub mod audio;
+#[cfg(feature = "blocking")]
+mod blocking;
+pub mod camera;
+pub mod cancel;
+#[cfg(feature = "pipewire")]
+mod channel;
+pub mod compositor;
+pub mod convert;
+pub mod encode;
+pub mod error;
+pub mod events;
+pub mod filters;
+pub mod format;
+pub mod frame;
+#[cfg(feature = "gstreamer")]
+pub mod gstreamer;
+pub mod image;
+pub mod launch;
+#[cfg(feature = "pipewire")]
+pub mod pipewire;
+pub mod recorder;
+mod runtime;
 pub mod screencast;
+pub mod session;
+pub mod sink;
+pub mod timebase;
+pub mod tokens;
+
+pub use error::{Result, ScreencastError};
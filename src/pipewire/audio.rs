@@ -0,0 +1,510 @@
+use super::BackpressurePolicy;
+use super::clock::graph_time;
+use super::ffi;
+use super::params;
+use super::pod::Value;
+use super::remote::{self, LoopLock, pipewire_error};
+use super::stats::monotonic_now;
+use crate::audio::{AudioBuffer, AudioConfig, AudioSource};
+use crate::channel::{self, Receiver, Sender};
+use crate::error::{Result, ScreencastError};
+use futures_core::Stream;
+use std::ffi::{CStr, CString};
+use std::os::fd::{IntoRawFd, OwnedFd};
+use std::os::raw::{c_char, c_void};
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+// about a second of buffers at the usual quantum of 1024 samples, for each
+// input
+const QUEUE_DEPTH: usize = 64;
+
+static AUDIO_EVENTS: ffi::pw_stream_events = ffi::pw_stream_events {
+    version: ffi::PW_VERSION_STREAM_EVENTS,
+    destroy: None,
+    state_changed: None,
+    control_info: None,
+    io_changed: None,
+    param_changed: Some(on_param_changed),
+    add_buffer: None,
+    remove_buffer: None,
+    process: Some(on_process),
+    drained: None,
+    command: None,
+    trigger_done: None,
+};
+
+static REGISTRY_EVENTS: ffi::pw_registry_events = ffi::pw_registry_events {
+    version: ffi::PW_VERSION_REGISTRY_EVENTS,
+    global: Some(on_global),
+    global_remove: Some(on_global_remove),
+};
+
+/// Records the inputs of the config, what the desktop plays, the microphone
+/// or single applications, as buffers of interleaved float samples on the
+/// clock of the video frames, each tagged with its source.
+/// `AudioTrack::spawn` mixes them.
+///
+/// PipeWire converts to the rate and channel count of the config and
+/// follows the default output and input when they change. An application
+/// is recorded once one of its output streams shows up, the first one when
+/// it has several, and again when it comes back after closing it. The
+/// screen cast portal hands out a remote that usually only shows the shared
+/// screens, in which case `connect` waits in vain and `connect_default`,
+/// which goes to the user's PipeWire daemon, is the way to get the audio.
+/// The oldest buffers are dropped when the consumer falls behind by about a
+/// second.
+#[derive(Debug)]
+pub struct AudioCapture {
+    // dropped first so the loop thread never waits on a full queue
+    receiver: Receiver<AudioBuffer>,
+    connection: Connection,
+    config: AudioConfig,
+}
+
+struct Connection {
+    thread_loop: *mut ffi::pw_thread_loop,
+    context: *mut ffi::pw_context,
+    core: *mut ffi::pw_core,
+    // to find the streams of applications, null without those
+    registry: *mut ffi::pw_registry,
+    // boxed so the pointers handed to the stream listeners stay put
+    #[allow(clippy::vec_box)]
+    streams: Vec<Box<AudioStream>>,
+}
+
+// SAFETY: the raw handles are only used with the thread loop locked.
+unsafe impl Send for Connection {}
+unsafe impl Sync for Connection {}
+
+impl std::fmt::Debug for Connection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Connection")
+            .field("streams", &self.streams.len())
+            .finish()
+    }
+}
+
+/// State shared with the stream and registry callbacks, only touched on the
+/// loop thread or with the thread loop locked.
+struct AudioStream {
+    core: *mut ffi::pw_core,
+    config: AudioConfig,
+    source: AudioSource,
+    // null while an application plays nothing
+    stream: *mut ffi::pw_stream,
+    listener: ffi::spa_hook,
+    registry_listener: ffi::spa_hook,
+    // the output streams of the application by global id, and the one
+    // recorded
+    candidates: Vec<(u32, CString)>,
+    node: Option<u32>,
+    // rate and channels as negotiated
+    format: Option<(u32, u32)>,
+    output: Sender<AudioBuffer>,
+}
+
+impl AudioCapture {
+    /// Captures over the remote behind `fd`, e.g.
+    /// `ActiveSession::try_clone_pipewire_fd`.
+    pub fn connect(fd: OwnedFd, config: &AudioConfig) -> Result<Self> {
+        AudioCapture::connect_to(Some(fd), config)
+    }
+
+    /// Captures over a connection of its own to the PipeWire daemon of the
+    /// user.
+    pub fn connect_default(config: &AudioConfig) -> Result<Self> {
+        AudioCapture::connect_to(None, config)
+    }
+
+    fn connect_to(fd: Option<OwnedFd>, config: &AudioConfig) -> Result<Self> {
+        if config.inputs.is_empty() {
+            return Err(ScreencastError::Unsupported(
+                "audio config without inputs".to_string(),
+            ));
+        }
+        remote::init();
+        let depth = QUEUE_DEPTH * config.inputs.len();
+        let (sender, receiver) = channel::bounded(depth, BackpressurePolicy::DropOldest);
+        let mut connection = Connection {
+            thread_loop: ptr::null_mut(),
+            context: ptr::null_mut(),
+            core: ptr::null_mut(),
+            registry: ptr::null_mut(),
+            streams: Vec::new(),
+        };
+        unsafe {
+            connection.thread_loop =
+                ffi::pw_thread_loop_new(c"xdp-screencast-audio".as_ptr(), ptr::null());
+            if connection.thread_loop.is_null() {
+                return Err(pipewire_error("failed to create thread loop"));
+            }
+            let pw_loop = ffi::pw_thread_loop_get_loop(connection.thread_loop);
+            connection.context = ffi::pw_context_new(pw_loop, ptr::null_mut(), 0);
+            if connection.context.is_null() {
+                return Err(pipewire_error("failed to create context"));
+            }
+            if ffi::pw_thread_loop_start(connection.thread_loop) < 0 {
+                return Err(pipewire_error("failed to start thread loop"));
+            }
+            let _lock = LoopLock::new(connection.thread_loop);
+            connection.core = match fd {
+                Some(fd) => ffi::pw_context_connect_fd(
+                    connection.context,
+                    fd.into_raw_fd(),
+                    ptr::null_mut(),
+                    0,
+                ),
+                None => ffi::pw_context_connect(connection.context, ptr::null_mut(), 0),
+            };
+            if connection.core.is_null() {
+                return Err(pipewire_error("failed to connect to remote"));
+            }
+            let applications = config
+                .inputs
+                .iter()
+                .any(|input| matches!(input.source, AudioSource::Application(_)));
+            if applications {
+                connection.registry = ffi::pw_core_get_registry(connection.core);
+                if connection.registry.is_null() {
+                    return Err(pipewire_error("failed to get the registry"));
+                }
+            }
+            for input in &config.inputs {
+                let stream = AudioStream::connect(
+                    connection.core,
+                    connection.registry,
+                    config,
+                    input.source.clone(),
+                    sender.clone(),
+                )?;
+                connection.streams.push(stream);
+            }
+        }
+        Ok(AudioCapture {
+            receiver,
+            connection,
+            config: config.clone(),
+        })
+    }
+
+    pub fn config(&self) -> &AudioConfig {
+        &self.config
+    }
+
+    /// The sample rate and channel count PipeWire settled on for `source`,
+    /// `None` until its stream is linked to a device.
+    pub fn negotiated(&self, source: &AudioSource) -> Option<(u32, u32)> {
+        let _lock = unsafe { LoopLock::new(self.connection.thread_loop) };
+        self.connection
+            .streams
+            .iter()
+            .find(|stream| stream.source == *source)?
+            .format
+    }
+
+    /// Buffers dropped for a consumer that fell behind.
+    pub fn dropped_buffers(&self) -> u64 {
+        self.receiver.dropped()
+    }
+
+    /// Blocks until the next buffer arrives.
+    pub fn recv(&self) -> Option<AudioBuffer> {
+        self.receiver.recv_blocking()
+    }
+
+    pub fn try_recv(&self) -> Option<AudioBuffer> {
+        self.receiver.try_recv()
+    }
+}
+
+impl Stream for AudioCapture {
+    type Item = AudioBuffer;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<AudioBuffer>> {
+        Pin::new(&mut self.receiver).poll_next(cx)
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if self.thread_loop.is_null() {
+            return;
+        }
+        unsafe {
+            {
+                let _lock = LoopLock::new(self.thread_loop);
+                for stream in &mut self.streams {
+                    stream.destroy();
+                }
+                if !self.registry.is_null() {
+                    ffi::pw_proxy_destroy(self.registry.cast());
+                }
+                if !self.core.is_null() {
+                    ffi::pw_core_disconnect(self.core);
+                }
+            }
+            ffi::pw_thread_loop_stop(self.thread_loop);
+            if !self.context.is_null() {
+                ffi::pw_context_destroy(self.context);
+            }
+            ffi::pw_thread_loop_destroy(self.thread_loop);
+        }
+    }
+}
+
+impl AudioStream {
+    /// # Safety
+    /// The thread loop owning `core` must be locked, `registry` is needed
+    /// for an application.
+    unsafe fn connect(
+        core: *mut ffi::pw_core,
+        registry: *mut ffi::pw_registry,
+        config: &AudioConfig,
+        source: AudioSource,
+        output: Sender<AudioBuffer>,
+    ) -> Result<Box<AudioStream>> {
+        let mut data = Box::new(AudioStream {
+            core,
+            config: config.clone(),
+            source,
+            stream: ptr::null_mut(),
+            listener: ffi::spa_hook::zeroed(),
+            registry_listener: ffi::spa_hook::zeroed(),
+            candidates: Vec::new(),
+            node: None,
+            format: None,
+            output,
+        });
+        unsafe {
+            match data.source {
+                // opened for the first output stream the registry reports
+                AudioSource::Application(_) => {
+                    let data_ptr: *mut AudioStream = &mut *data;
+                    ffi::pw_registry_add_listener(
+                        registry,
+                        &mut (*data_ptr).registry_listener,
+                        &REGISTRY_EVENTS,
+                        data_ptr.cast(),
+                    );
+                }
+                AudioSource::Desktop | AudioSource::Microphone => data.open(None)?,
+            }
+        }
+        Ok(data)
+    }
+
+    /// Creates and connects the stream, to the output stream `target` of
+    /// an application or the default device.
+    ///
+    /// # Safety
+    /// The thread loop must be locked.
+    unsafe fn open(&mut self, target: Option<&CStr>) -> Result<()> {
+        unsafe {
+            let props = ffi::pw_properties_new(ptr::null());
+            ffi::pw_properties_set(props, c"media.type".as_ptr(), c"Audio".as_ptr());
+            ffi::pw_properties_set(props, c"media.category".as_ptr(), c"Capture".as_ptr());
+            let name = match &self.source {
+                AudioSource::Desktop => {
+                    // the monitor of the default output rather than the
+                    // default input
+                    ffi::pw_properties_set(
+                        props,
+                        c"stream.capture.sink".as_ptr(),
+                        c"true".as_ptr(),
+                    );
+                    c"xdp-screencast-audio"
+                }
+                AudioSource::Microphone => c"xdp-screencast-microphone",
+                AudioSource::Application(_) => c"xdp-screencast-application",
+            };
+            if let Some(target) = target {
+                ffi::pw_properties_set(props, c"target.object".as_ptr(), target.as_ptr());
+                // never the microphone in place of an application gone
+                ffi::pw_properties_set(props, c"node.dont-reconnect".as_ptr(), c"true".as_ptr());
+                ffi::pw_properties_set(props, c"node.dont-fallback".as_ptr(), c"true".as_ptr());
+            }
+            let stream = ffi::pw_stream_new(self.core, name.as_ptr(), props);
+            if stream.is_null() {
+                return Err(pipewire_error("failed to create audio stream"));
+            }
+            self.stream = stream;
+            let data_ptr: *mut AudioStream = self;
+            ffi::pw_stream_add_listener(stream, &mut self.listener, &AUDIO_EVENTS, data_ptr.cast());
+            let format = params::audio_format(&self.config);
+            let mut pointers = [format.as_ptr()];
+            let connected = ffi::pw_stream_connect(
+                stream,
+                ffi::SPA_DIRECTION_INPUT,
+                ffi::PW_ID_ANY,
+                ffi::PW_STREAM_FLAG_AUTOCONNECT | ffi::PW_STREAM_FLAG_MAP_BUFFERS,
+                pointers.as_mut_ptr(),
+                pointers.len() as u32,
+            );
+            if connected < 0 {
+                self.close();
+                return Err(ScreencastError::PipeWire(format!(
+                    "failed to connect audio stream: {}",
+                    std::io::Error::from_raw_os_error(-connected)
+                )));
+            }
+            Ok(())
+        }
+    }
+
+    /// # Safety
+    /// The thread loop must be locked.
+    unsafe fn close(&mut self) {
+        if self.stream.is_null() {
+            return;
+        }
+        unsafe {
+            ffi::spa_hook_remove(&mut self.listener);
+            ffi::pw_stream_destroy(self.stream);
+        }
+        self.stream = ptr::null_mut();
+        self.format = None;
+    }
+
+    /// # Safety
+    /// The thread loop must be locked.
+    unsafe fn destroy(&mut self) {
+        unsafe {
+            ffi::spa_hook_remove(&mut self.registry_listener);
+            self.close();
+        }
+    }
+
+    /// Records the first output stream left of the application, if any.
+    ///
+    /// # Safety
+    /// The thread loop must be locked.
+    unsafe fn follow(&mut self) {
+        while self.node.is_none() && !self.candidates.is_empty() {
+            let (id, target) = self.candidates.remove(0);
+            if unsafe { self.open(Some(&target)) }.is_ok() {
+                self.node = Some(id);
+            }
+        }
+    }
+
+    /// # Safety
+    /// `buffer` must be dequeued.
+    unsafe fn read_buffer(&mut self, buffer: &ffi::pw_buffer) -> Option<AudioBuffer> {
+        let (sample_rate, channels) = self.format?;
+        let spa_buffer = unsafe { buffer.buffer.as_ref()? };
+        if spa_buffer.n_datas == 0 {
+            return None;
+        }
+        let data = unsafe { &*spa_buffer.datas };
+        let chunk = unsafe { data.chunk.as_ref()? };
+        if data.data.is_null() || chunk.flags & ffi::SPA_CHUNK_FLAG_CORRUPTED != 0 {
+            return None;
+        }
+        let memory =
+            unsafe { std::slice::from_raw_parts(data.data.cast::<u8>(), data.maxsize as usize) };
+        let offset = (chunk.offset as usize).min(memory.len());
+        let size = (chunk.size as usize).min(memory.len() - offset);
+        let samples: Vec<f32> = memory[offset..offset + size]
+            .chunks_exact(4)
+            .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        let mut buffer = AudioBuffer {
+            samples,
+            sample_rate,
+            channels,
+            pts: Duration::ZERO,
+            source: Some(self.source.clone()),
+        };
+        // audio buffers carry no timestamp: the last sample left the device
+        // the delay of the stream before the cycle started, on the clock of
+        // the video frames. The times follow the device clock, the mixer
+        // and the muxers keep up with its drift.
+        let end = match unsafe { graph_time(self.stream, sample_rate) } {
+            Some(time) => time.now.saturating_sub(time.delay),
+            None => monotonic_now(),
+        };
+        buffer.pts = end.saturating_sub(buffer.duration());
+        Some(buffer)
+    }
+}
+
+unsafe extern "C" fn on_param_changed(data: *mut c_void, id: u32, param: *const ffi::spa_pod) {
+    let data = unsafe { &mut *data.cast::<AudioStream>() };
+    if id != ffi::SPA_PARAM_Format || param.is_null() {
+        return;
+    }
+    data.format = unsafe { Value::from_raw(param) }
+        .as_ref()
+        .and_then(params::parse_audio_format);
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+    let data = unsafe { &mut *data.cast::<AudioStream>() };
+    loop {
+        let buffer = unsafe { ffi::pw_stream_dequeue_buffer(data.stream) };
+        if buffer.is_null() {
+            break;
+        }
+        if let Some(audio) = unsafe { data.read_buffer(&*buffer) } {
+            data.output.send(audio);
+        }
+        unsafe { ffi::pw_stream_queue_buffer(data.stream, buffer) };
+    }
+}
+
+unsafe extern "C" fn on_global(
+    data: *mut c_void,
+    id: u32,
+    _permissions: u32,
+    type_: *const c_char,
+    _version: u32,
+    props: *const ffi::spa_dict,
+) {
+    let data = unsafe { &mut *data.cast::<AudioStream>() };
+    let AudioSource::Application(application) = &data.source else {
+        return;
+    };
+    if type_.is_null() || unsafe { CStr::from_ptr(type_) } != ffi::PW_TYPE_INTERFACE_Node {
+        return;
+    }
+    let lookup = |key: &CStr| unsafe { ffi::spa_dict_lookup(props, key) };
+    if lookup(c"media.class") != Some(c"Stream/Output/Audio") {
+        return;
+    }
+    let matches = [
+        c"node.name",
+        c"application.process.binary",
+        c"application.name",
+    ]
+    .into_iter()
+    .filter_map(lookup)
+    .any(|value| {
+        value
+            .to_str()
+            .is_ok_and(|value| value.eq_ignore_ascii_case(application))
+    });
+    // the serial is unique where names repeat, older daemons lack it
+    let target = lookup(c"object.serial").or_else(|| lookup(c"node.name"));
+    if let (true, Some(target)) = (matches, target) {
+        data.candidates.push((id, target.to_owned()));
+        unsafe { data.follow() };
+    }
+}
+
+unsafe extern "C" fn on_global_remove(data: *mut c_void, id: u32) {
+    let data = unsafe { &mut *data.cast::<AudioStream>() };
+    data.candidates.retain(|(candidate, _)| *candidate != id);
+    if data.node == Some(id) {
+        data.node = None;
+        unsafe {
+            data.close();
+            data.follow();
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use super::ffi;
+use std::time::Duration;
+
+/// Where a stream stands on the clock of the graph, CLOCK_MONOTONIC, which
+/// compositors stamp their frames on too.
+#[derive(Debug, Copy, Clone)]
+pub(crate) struct GraphTime {
+    /// The start of the current cycle, when the driver took or gave the
+    /// samples of its quantum.
+    pub now: Duration,
+    /// How long a sample takes between the device and the stream, filters
+    /// and the resampler of the stream included.
+    pub delay: Duration,
+}
+
+/// The time PipeWire reports for `stream`, `None` before it runs.
+/// `sample_rate` is that of an audio stream, 0 for video.
+///
+/// # Safety
+/// `stream` must be live and only touched on the loop thread.
+pub(crate) unsafe fn graph_time(
+    stream: *mut ffi::pw_stream,
+    sample_rate: u32,
+) -> Option<GraphTime> {
+    if stream.is_null() {
+        return None;
+    }
+    let mut time = ffi::pw_time::default();
+    let result = unsafe { ffi::pw_stream_get_time_n(stream, &mut time, size_of::<ffi::pw_time>()) };
+    if result < 0 || time.now <= 0 {
+        return None;
+    }
+    let nanos = |ticks: u128, num: u128, denom: u128| match denom {
+        0 => 0,
+        denom => (ticks * num * 1_000_000_000 / denom) as u64,
+    };
+    // the delay counts in the rate of the graph, buffered frames in that of
+    // the stream
+    let delay = nanos(
+        time.delay.max(0) as u128,
+        time.rate.num as u128,
+        time.rate.denom as u128,
+    ) + nanos(time.buffered as u128, 1, sample_rate as u128);
+    Some(GraphTime {
+        now: Duration::from_nanos(time.now as u64),
+        delay: Duration::from_nanos(delay),
+    })
+}
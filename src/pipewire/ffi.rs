@@ -0,0 +1,443 @@
+#![allow(non_camel_case_types, non_upper_case_globals, dead_code)]
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_void};
+
+pub const PW_ID_ANY: u32 = 0xffffffff;
+
+pub const SPA_DIRECTION_INPUT: u32 = 0;
+
+pub const PW_STREAM_FLAG_AUTOCONNECT: u32 = 1 << 0;
+pub const PW_STREAM_FLAG_MAP_BUFFERS: u32 = 1 << 2;
+
+pub const PW_STREAM_STATE_ERROR: c_int = -1;
+pub const PW_STREAM_STATE_UNCONNECTED: c_int = 0;
+pub const PW_STREAM_STATE_STREAMING: c_int = 3;
+
+pub const PW_VERSION_STREAM_EVENTS: u32 = 2;
+pub const PW_VERSION_REGISTRY: u32 = 3;
+pub const PW_VERSION_REGISTRY_EVENTS: u32 = 0;
+
+pub const PW_TYPE_INTERFACE_Node: &std::ffi::CStr = c"PipeWire:Interface:Node";
+
+pub const SPA_PARAM_EnumFormat: u32 = 3;
+pub const SPA_PARAM_Format: u32 = 4;
+pub const SPA_PARAM_Buffers: u32 = 5;
+pub const SPA_PARAM_Meta: u32 = 6;
+
+pub const SPA_META_Header: u32 = 1;
+pub const SPA_META_VideoCrop: u32 = 2;
+pub const SPA_META_VideoDamage: u32 = 3;
+pub const SPA_META_Cursor: u32 = 5;
+pub const SPA_META_VideoTransform: u32 = 8;
+
+pub const SPA_DATA_MemPtr: u32 = 1;
+pub const SPA_DATA_MemFd: u32 = 2;
+pub const SPA_DATA_DmaBuf: u32 = 3;
+
+pub const SPA_CHUNK_FLAG_CORRUPTED: i32 = 1 << 0;
+
+pub enum pw_thread_loop {}
+pub enum pw_loop {}
+pub enum pw_context {}
+pub enum pw_core {}
+pub enum pw_stream {}
+pub enum pw_properties {}
+pub enum pw_registry {}
+pub enum pw_proxy {}
+pub enum pw_stream_control {}
+pub enum spa_command {}
+
+#[repr(C)]
+pub struct spa_pod {
+    pub size: u32,
+    pub type_: u32,
+}
+
+#[repr(C)]
+pub struct spa_list {
+    pub next: *mut spa_list,
+    pub prev: *mut spa_list,
+}
+
+#[repr(C)]
+pub struct spa_dict_item {
+    pub key: *const c_char,
+    pub value: *const c_char,
+}
+
+#[repr(C)]
+pub struct spa_dict {
+    pub flags: u32,
+    pub n_items: u32,
+    pub items: *const spa_dict_item,
+}
+
+/// `spa_dict_lookup()` is an inline function in the spa headers.
+///
+/// # Safety
+/// `dict` must be valid for the duration of the call.
+pub unsafe fn spa_dict_lookup<'a>(dict: *const spa_dict, key: &CStr) -> Option<&'a CStr> {
+    let dict = unsafe { dict.as_ref()? };
+    if dict.items.is_null() {
+        return None;
+    }
+    let items = unsafe { std::slice::from_raw_parts(dict.items, dict.n_items as usize) };
+    items
+        .iter()
+        .filter(|item| !item.key.is_null() && !item.value.is_null())
+        .find(|item| unsafe { CStr::from_ptr(item.key) } == key)
+        .map(|item| unsafe { CStr::from_ptr(item.value) })
+}
+
+#[repr(C)]
+pub struct spa_callbacks {
+    pub funcs: *const c_void,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct spa_hook {
+    pub link: spa_list,
+    pub cb: spa_callbacks,
+    pub removed: Option<unsafe extern "C" fn(hook: *mut spa_hook)>,
+    pub priv_: *mut c_void,
+}
+
+impl spa_hook {
+    pub fn zeroed() -> Self {
+        spa_hook {
+            link: spa_list {
+                next: std::ptr::null_mut(),
+                prev: std::ptr::null_mut(),
+            },
+            cb: spa_callbacks {
+                funcs: std::ptr::null(),
+                data: std::ptr::null_mut(),
+            },
+            removed: None,
+            priv_: std::ptr::null_mut(),
+        }
+    }
+}
+
+/// `spa_hook_remove()` is an inline function in the spa headers.
+///
+/// # Safety
+/// `hook` must have been registered with a listener list.
+pub unsafe fn spa_hook_remove(hook: *mut spa_hook) {
+    unsafe {
+        let link = &mut (*hook).link;
+        if !link.prev.is_null() && !link.next.is_null() {
+            (*link.prev).next = link.next;
+            (*link.next).prev = link.prev;
+            link.next = std::ptr::null_mut();
+            link.prev = std::ptr::null_mut();
+        }
+        if let Some(removed) = (*hook).removed {
+            removed(hook);
+        }
+    }
+}
+
+/// The header of the objects behind `pw_core` and `pw_registry`, whose
+/// methods are inline functions calling through it.
+#[repr(C)]
+struct spa_interface {
+    type_: *const c_char,
+    version: u32,
+    cb: spa_callbacks,
+}
+
+#[repr(C)]
+struct pw_core_methods {
+    version: u32,
+    add_listener: *const c_void,
+    hello: *const c_void,
+    sync: *const c_void,
+    pong: *const c_void,
+    error: *const c_void,
+    get_registry: Option<
+        unsafe extern "C" fn(
+            object: *mut c_void,
+            version: u32,
+            user_data_size: usize,
+        ) -> *mut pw_registry,
+    >,
+}
+
+#[repr(C)]
+struct pw_registry_methods {
+    version: u32,
+    add_listener: Option<
+        unsafe extern "C" fn(
+            object: *mut c_void,
+            listener: *mut spa_hook,
+            events: *const pw_registry_events,
+            data: *mut c_void,
+        ) -> c_int,
+    >,
+}
+
+#[repr(C)]
+pub struct pw_registry_events {
+    pub version: u32,
+    pub global: Option<
+        unsafe extern "C" fn(
+            data: *mut c_void,
+            id: u32,
+            permissions: u32,
+            type_: *const c_char,
+            version: u32,
+            props: *const spa_dict,
+        ),
+    >,
+    pub global_remove: Option<unsafe extern "C" fn(data: *mut c_void, id: u32)>,
+}
+
+/// `pw_core_get_registry()` is an inline function in the pipewire headers.
+///
+/// # Safety
+/// `core` must be connected and its thread loop locked.
+pub unsafe fn pw_core_get_registry(core: *mut pw_core) -> *mut pw_registry {
+    unsafe {
+        let interface = &*core.cast::<spa_interface>();
+        let methods = &*interface.cb.funcs.cast::<pw_core_methods>();
+        match methods.get_registry {
+            Some(get_registry) => get_registry(interface.cb.data, PW_VERSION_REGISTRY, 0),
+            None => std::ptr::null_mut(),
+        }
+    }
+}
+
+/// `pw_registry_add_listener()` is an inline function in the pipewire
+/// headers.
+///
+/// # Safety
+/// `registry` must be valid and its thread loop locked, `listener` and
+/// `data` must outlive the registration.
+pub unsafe fn pw_registry_add_listener(
+    registry: *mut pw_registry,
+    listener: *mut spa_hook,
+    events: *const pw_registry_events,
+    data: *mut c_void,
+) -> c_int {
+    unsafe {
+        let interface = &*registry.cast::<spa_interface>();
+        let methods = &*interface.cb.funcs.cast::<pw_registry_methods>();
+        match methods.add_listener {
+            Some(add_listener) => add_listener(interface.cb.data, listener, events, data),
+            None => -libc::ENOTSUP,
+        }
+    }
+}
+
+#[repr(C)]
+pub struct spa_chunk {
+    pub offset: u32,
+    pub size: u32,
+    pub stride: i32,
+    pub flags: i32,
+}
+
+#[repr(C)]
+pub struct spa_data {
+    pub type_: u32,
+    pub flags: u32,
+    pub fd: i64,
+    pub mapoffset: u32,
+    pub maxsize: u32,
+    pub data: *mut c_void,
+    pub chunk: *mut spa_chunk,
+}
+
+#[repr(C)]
+pub struct spa_meta {
+    pub type_: u32,
+    pub size: u32,
+    pub data: *mut c_void,
+}
+
+#[repr(C)]
+pub struct spa_meta_header {
+    pub flags: u32,
+    pub offset: u32,
+    pub pts: i64,
+    pub dts_offset: i64,
+    pub seq: u64,
+}
+
+#[repr(C)]
+pub struct spa_point {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[repr(C)]
+pub struct spa_rectangle {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[repr(C)]
+pub struct spa_region {
+    pub position: spa_point,
+    pub size: spa_rectangle,
+}
+
+#[repr(C)]
+pub struct spa_meta_region {
+    pub region: spa_region,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct spa_fraction {
+    pub num: u32,
+    pub denom: u32,
+}
+
+/// The first fields of `struct pw_time`, those of PipeWire 0.3.50, which
+/// `pw_stream_get_time_n` is told the size of.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default)]
+pub struct pw_time {
+    pub now: i64,
+    pub rate: spa_fraction,
+    pub ticks: u64,
+    pub delay: i64,
+    pub queued: u64,
+    pub buffered: u64,
+    pub queued_buffers: u32,
+    pub avail_buffers: u32,
+}
+
+#[repr(C)]
+pub struct spa_meta_videotransform {
+    pub transform: u32,
+}
+
+#[repr(C)]
+pub struct spa_meta_cursor {
+    pub id: u32,
+    pub flags: u32,
+    pub position: spa_point,
+    pub hotspot: spa_point,
+    pub bitmap_offset: u32,
+}
+
+#[repr(C)]
+pub struct spa_meta_bitmap {
+    pub format: u32,
+    pub size: spa_rectangle,
+    pub stride: i32,
+    pub offset: u32,
+}
+
+#[repr(C)]
+pub struct spa_buffer {
+    pub n_metas: u32,
+    pub n_datas: u32,
+    pub metas: *mut spa_meta,
+    pub datas: *mut spa_data,
+}
+
+#[repr(C)]
+pub struct pw_buffer {
+    pub buffer: *mut spa_buffer,
+    pub user_data: *mut c_void,
+    pub size: u64,
+    pub requested: u64,
+}
+
+#[repr(C)]
+pub struct pw_stream_events {
+    pub version: u32,
+    pub destroy: Option<unsafe extern "C" fn(data: *mut c_void)>,
+    pub state_changed: Option<
+        unsafe extern "C" fn(data: *mut c_void, old: c_int, state: c_int, error: *const c_char),
+    >,
+    pub control_info:
+        Option<unsafe extern "C" fn(data: *mut c_void, id: u32, control: *const pw_stream_control)>,
+    pub io_changed:
+        Option<unsafe extern "C" fn(data: *mut c_void, id: u32, area: *mut c_void, size: u32)>,
+    pub param_changed:
+        Option<unsafe extern "C" fn(data: *mut c_void, id: u32, param: *const spa_pod)>,
+    pub add_buffer: Option<unsafe extern "C" fn(data: *mut c_void, buffer: *mut pw_buffer)>,
+    pub remove_buffer: Option<unsafe extern "C" fn(data: *mut c_void, buffer: *mut pw_buffer)>,
+    pub process: Option<unsafe extern "C" fn(data: *mut c_void)>,
+    pub drained: Option<unsafe extern "C" fn(data: *mut c_void)>,
+    pub command: Option<unsafe extern "C" fn(data: *mut c_void, command: *const spa_command)>,
+    pub trigger_done: Option<unsafe extern "C" fn(data: *mut c_void)>,
+}
+
+#[link(name = "pipewire-0.3")]
+unsafe extern "C" {
+    pub fn pw_init(argc: *mut c_int, argv: *mut *mut *mut c_char);
+
+    pub fn pw_thread_loop_new(name: *const c_char, props: *const spa_dict) -> *mut pw_thread_loop;
+    pub fn pw_thread_loop_destroy(thread_loop: *mut pw_thread_loop);
+    pub fn pw_thread_loop_get_loop(thread_loop: *mut pw_thread_loop) -> *mut pw_loop;
+    pub fn pw_thread_loop_start(thread_loop: *mut pw_thread_loop) -> c_int;
+    pub fn pw_thread_loop_stop(thread_loop: *mut pw_thread_loop);
+    pub fn pw_thread_loop_lock(thread_loop: *mut pw_thread_loop);
+    pub fn pw_thread_loop_unlock(thread_loop: *mut pw_thread_loop);
+
+    pub fn pw_context_new(
+        main_loop: *mut pw_loop,
+        props: *mut pw_properties,
+        user_data_size: usize,
+    ) -> *mut pw_context;
+    pub fn pw_context_destroy(context: *mut pw_context);
+    pub fn pw_context_connect_fd(
+        context: *mut pw_context,
+        fd: c_int,
+        props: *mut pw_properties,
+        user_data_size: usize,
+    ) -> *mut pw_core;
+    pub fn pw_context_connect(
+        context: *mut pw_context,
+        props: *mut pw_properties,
+        user_data_size: usize,
+    ) -> *mut pw_core;
+    pub fn pw_core_disconnect(core: *mut pw_core) -> c_int;
+
+    pub fn pw_proxy_destroy(proxy: *mut pw_proxy);
+
+    pub fn pw_properties_new(key: *const c_char, ...) -> *mut pw_properties;
+    pub fn pw_properties_set(
+        properties: *mut pw_properties,
+        key: *const c_char,
+        value: *const c_char,
+    ) -> c_int;
+
+    pub fn pw_stream_new(
+        core: *mut pw_core,
+        name: *const c_char,
+        props: *mut pw_properties,
+    ) -> *mut pw_stream;
+    pub fn pw_stream_destroy(stream: *mut pw_stream);
+    pub fn pw_stream_add_listener(
+        stream: *mut pw_stream,
+        listener: *mut spa_hook,
+        events: *const pw_stream_events,
+        data: *mut c_void,
+    );
+    pub fn pw_stream_connect(
+        stream: *mut pw_stream,
+        direction: u32,
+        target_id: u32,
+        flags: u32,
+        params: *mut *const spa_pod,
+        n_params: u32,
+    ) -> c_int;
+    pub fn pw_stream_disconnect(stream: *mut pw_stream) -> c_int;
+    pub fn pw_stream_update_params(
+        stream: *mut pw_stream,
+        params: *mut *const spa_pod,
+        n_params: u32,
+    ) -> c_int;
+    pub fn pw_stream_dequeue_buffer(stream: *mut pw_stream) -> *mut pw_buffer;
+    pub fn pw_stream_queue_buffer(stream: *mut pw_stream, buffer: *mut pw_buffer) -> c_int;
+    pub fn pw_stream_get_time_n(stream: *mut pw_stream, time: *mut pw_time, size: usize) -> c_int;
+}
@@ -0,0 +1,61 @@
+use std::io;
+use std::os::fd::{AsRawFd, BorrowedFd};
+use std::ptr::NonNull;
+
+/// A read-only shared mapping of a memfd buffer, unmapped on drop.
+pub(crate) struct Mmap {
+    ptr: NonNull<libc::c_void>,
+    map_len: usize,
+    data_offset: usize,
+    len: usize,
+}
+
+// SAFETY: the mapping is read-only and owned by this value.
+unsafe impl Send for Mmap {}
+
+impl Mmap {
+    /// Maps `len` bytes at `offset`, which doesn't have to be page aligned.
+    pub(crate) fn map(fd: BorrowedFd<'_>, offset: u64, len: usize) -> io::Result<Mmap> {
+        if len == 0 {
+            return Err(io::Error::from(io::ErrorKind::InvalidInput));
+        }
+        let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) } as u64;
+        let aligned_offset = offset - offset % page_size;
+        let data_offset = (offset - aligned_offset) as usize;
+        let map_len = len + data_offset;
+        let ptr = unsafe {
+            libc::mmap(
+                std::ptr::null_mut(),
+                map_len,
+                libc::PROT_READ,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                aligned_offset as libc::off_t,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mmap {
+            ptr: NonNull::new(ptr).ok_or_else(|| io::Error::from(io::ErrorKind::Other))?,
+            map_len,
+            data_offset,
+            len,
+        })
+    }
+
+    pub(crate) fn as_slice(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(
+                self.ptr.as_ptr().cast::<u8>().add(self.data_offset),
+                self.len,
+            )
+        }
+    }
+}
+
+impl Drop for Mmap {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr(), self.map_len) };
+    }
+}
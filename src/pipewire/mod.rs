@@ -0,0 +1,378 @@
+mod audio;
+mod clock;
+mod ffi;
+mod mmap;
+mod params;
+mod pod;
+mod remote;
+mod sched;
+mod stats;
+mod stream;
+
+use crate::channel::{self, Receiver};
+use crate::error::Result;
+use crate::events::{EventSender, EventStream};
+use crate::format::{PixelFormat, StreamInfo};
+use crate::frame::Frame;
+use crate::screencast::SelectedSource;
+pub use audio::AudioCapture;
+use futures_core::Stream;
+use remote::Remote;
+pub(crate) use stats::StatsRecorder;
+use std::os::fd::OwnedFd;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use stream::Output;
+
+/// How the streams are negotiated, encoded into the `EnumFormat` params.
+#[derive(Debug, Clone)]
+pub struct CaptureOptions {
+    /// Accepted pixel formats, most preferred first.
+    pub pixel_formats: Vec<PixelFormat>,
+    pub min_size: (u32, u32),
+    pub max_size: (u32, u32),
+    /// Framerates as `(numerator, denominator)`, `(0, 1)` allows damage-driven
+    /// streams without a fixed rate.
+    pub min_framerate: (u32, u32),
+    /// Advertised as the stream's max framerate and also enforced here by
+    /// dropping frames, as compositors may deliver at the refresh rate anyway.
+    pub max_framerate: (u32, u32),
+    /// DRM modifiers the consumer can import, DMA-BUF buffers are offered
+    /// when this is not empty.
+    pub dmabuf_modifiers: Vec<u64>,
+    /// Upper bound of PipeWire buffers per stream, more buffers let the
+    /// compositor keep rendering while frames are being read.
+    pub buffers: u32,
+    /// Frames kept queued for the consumer.
+    pub queue_depth: usize,
+    /// What happens once `queue_depth` frames are waiting.
+    pub backpressure: BackpressurePolicy,
+    /// `SCHED_FIFO` priority for the PipeWire loop thread, requested from
+    /// rtkit or the realtime portal when the process lacks the privilege.
+    pub realtime_priority: Option<u32>,
+    /// CPUs the PipeWire loop thread may run on, any when empty.
+    pub cpu_affinity: Vec<usize>,
+}
+
+/// How the capture thread reacts to a consumer slower than the compositor.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy {
+    /// Replace the oldest queued frame, keeping latency low.
+    #[default]
+    DropOldest,
+    /// Discard the new frame.
+    DropNewest,
+    /// Stall the PipeWire loop until the consumer catches up, the compositor
+    /// then runs out of buffers and skips frames on its side.
+    Block,
+}
+
+impl Default for CaptureOptions {
+    fn default() -> Self {
+        CaptureOptions {
+            pixel_formats: PixelFormat::ALL
+                .into_iter()
+                .filter(|pixel_format| !pixel_format.is_yuv())
+                .collect(),
+            min_size: (1, 1),
+            max_size: (16384, 16384),
+            min_framerate: (0, 1),
+            max_framerate: (360, 1),
+            dmabuf_modifiers: Vec::new(),
+            buffers: 8,
+            queue_depth: 4,
+            backpressure: BackpressurePolicy::default(),
+            realtime_priority: None,
+            cpu_affinity: Vec::new(),
+        }
+    }
+}
+
+/// Counters since the capture started, latency is measured from the
+/// frame's pts until the consumer takes it and covers the last 512 frames.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub struct CaptureStats {
+    /// Frames read from PipeWire buffers.
+    pub frames_received: u64,
+    /// Frames discarded by `max_framerate` or the backpressure policy.
+    pub frames_dropped: u64,
+    pub frames_delivered: u64,
+    pub bytes_received: u64,
+    /// Frames delivered during the last second.
+    pub fps: f64,
+    pub latency_avg: Duration,
+    pub latency_p50: Duration,
+    pub latency_p95: Duration,
+    pub latency_p99: Duration,
+}
+
+/// Consumes the PipeWire streams of a started session on a dedicated thread
+/// loop, see `CaptureOptions::backpressure` for a consumer falling behind.
+#[derive(Debug)]
+pub struct PipeWireCapture {
+    // dropped first so a sender blocked on a full queue releases the loop
+    receiver: Receiver<Frame<'static>>,
+    remote: Remote,
+    events: EventSender,
+    stats: Arc<StatsRecorder>,
+}
+
+impl PipeWireCapture {
+    /// Connects to the remote behind `fd` and creates a stream per source,
+    /// frames of all sources are delivered interleaved.
+    pub fn connect(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+    ) -> Result<Self> {
+        PipeWireCapture::connect_with_events(
+            fd,
+            sources,
+            options,
+            EventSender::default(),
+            Arc::default(),
+        )
+    }
+
+    pub(crate) fn connect_with_events(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+        events: EventSender,
+        stats: Arc<StatsRecorder>,
+    ) -> Result<Self> {
+        let (sender, receiver) = channel::bounded(options.queue_depth.max(1), options.backpressure);
+        let remote = Remote::connect(fd, sources, options, &events, &stats, |_| {
+            Output::Queue(sender.clone())
+        })?;
+        Ok(PipeWireCapture {
+            receiver,
+            remote,
+            events,
+            stats,
+        })
+    }
+
+    /// Connects like `connect` but gives every source its own stream.
+    pub fn connect_per_source(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+    ) -> Result<Vec<FrameStream>> {
+        PipeWireCapture::connect_per_source_with_events(
+            fd,
+            sources,
+            options,
+            EventSender::default(),
+            Arc::default(),
+        )
+    }
+
+    pub(crate) fn connect_per_source_with_events(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+        events: EventSender,
+        stats: Arc<StatsRecorder>,
+    ) -> Result<Vec<FrameStream>> {
+        let mut receivers = Vec::with_capacity(sources.len());
+        let remote = Remote::connect(fd, sources, options, &events, &stats, |_| {
+            let (sender, receiver) =
+                channel::bounded(options.queue_depth.max(1), options.backpressure);
+            receivers.push(receiver);
+            Output::Queue(sender)
+        })?;
+        let remote = Arc::new(remote);
+        Ok(sources
+            .iter()
+            .zip(receivers)
+            .map(|(source, receiver)| FrameStream {
+                receiver,
+                source: source.clone(),
+                remote: remote.clone(),
+                events: events.clone(),
+                stats: stats.clone(),
+            })
+            .collect())
+    }
+
+    /// Connects like `connect` but hands every frame to `callback` instead of
+    /// queueing it, see `CallbackCapture`.
+    pub fn connect_callback(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+        callback: impl FnMut(Frame<'_>) + Send + 'static,
+    ) -> Result<CallbackCapture> {
+        PipeWireCapture::connect_callback_with_events(
+            fd,
+            sources,
+            options,
+            Box::new(callback),
+            EventSender::default(),
+            Arc::default(),
+        )
+    }
+
+    pub(crate) fn connect_callback_with_events(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+        callback: Box<stream::FrameCallback>,
+        events: EventSender,
+        stats: Arc<StatsRecorder>,
+    ) -> Result<CallbackCapture> {
+        let callback = Arc::new(Mutex::new(callback));
+        let remote = Remote::connect(fd, sources, options, &events, &stats, |_| {
+            Output::Callback(callback.clone())
+        })?;
+        Ok(CallbackCapture {
+            remote,
+            events,
+            stats,
+        })
+    }
+
+    /// The formats of the streams that finished negotiation.
+    pub fn stream_info(&self) -> Vec<StreamInfo> {
+        self.remote.stream_info()
+    }
+
+    /// Subscribes to `ScreencastEvent::FormatChanged`, sessions created
+    /// through `ActiveSession::capture` also report them on the session's
+    /// event stream.
+    pub fn events(&self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    /// Frames discarded by the backpressure policy, across all streams.
+    pub fn dropped_frames(&self) -> u64 {
+        self.receiver.dropped()
+    }
+
+    /// Counters of this capture, or of every capture of the session when
+    /// created through `ActiveSession::capture`.
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+
+    /// Blocks until the next frame arrives.
+    pub fn recv(&self) -> Option<Frame<'static>> {
+        delivered(&self.stats, self.receiver.recv_blocking())
+    }
+
+    pub fn try_recv(&self) -> Option<Frame<'static>> {
+        delivered(&self.stats, self.receiver.try_recv())
+    }
+}
+
+impl Stream for PipeWireCapture {
+    type Item = Frame<'static>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame<'static>>> {
+        Pin::new(&mut self.receiver)
+            .poll_next(cx)
+            .map(|frame| delivered(&self.stats, frame))
+    }
+}
+
+/// A capture delivering frames straight from the PipeWire loop thread,
+/// skipping the queue and the copy out of the PipeWire buffer.
+///
+/// The callback runs while the buffer is held, so it must return quickly and
+/// never block: a slow callback stalls every stream of the capture and the
+/// compositor runs out of buffers. Frames borrow the buffer, call
+/// `Frame::into_owned` to keep one, and the callback must not drop the
+/// capture itself. `max_framerate` still applies, the backpressure policy
+/// and `queue_depth` do not.
+pub struct CallbackCapture {
+    remote: Remote,
+    events: EventSender,
+    stats: Arc<StatsRecorder>,
+}
+
+impl std::fmt::Debug for CallbackCapture {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CallbackCapture")
+            .field("remote", &self.remote)
+            .finish()
+    }
+}
+
+impl CallbackCapture {
+    pub fn stream_info(&self) -> Vec<StreamInfo> {
+        self.remote.stream_info()
+    }
+
+    pub fn events(&self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+}
+
+/// Frames of a single source, the connection stays up until every stream
+/// of the same `connect_per_source` call is dropped.
+#[derive(Debug)]
+pub struct FrameStream {
+    receiver: Receiver<Frame<'static>>,
+    source: SelectedSource,
+    remote: Arc<Remote>,
+    events: EventSender,
+    stats: Arc<StatsRecorder>,
+}
+
+impl FrameStream {
+    pub fn source(&self) -> &SelectedSource {
+        &self.source
+    }
+
+    pub fn stream_info(&self) -> Option<StreamInfo> {
+        self.remote
+            .stream_info()
+            .into_iter()
+            .find(|info| info.node_id == self.source.node_id())
+    }
+
+    pub fn events(&self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.receiver.dropped()
+    }
+
+    /// Counters shared by all streams of the same connection.
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+
+    pub fn recv(&self) -> Option<Frame<'static>> {
+        delivered(&self.stats, self.receiver.recv_blocking())
+    }
+
+    pub fn try_recv(&self) -> Option<Frame<'static>> {
+        delivered(&self.stats, self.receiver.try_recv())
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = Frame<'static>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Frame<'static>>> {
+        Pin::new(&mut self.receiver)
+            .poll_next(cx)
+            .map(|frame| delivered(&self.stats, frame))
+    }
+}
+
+fn delivered(stats: &StatsRecorder, frame: Option<Frame<'static>>) -> Option<Frame<'static>> {
+    if let Some(frame) = &frame {
+        stats.delivered(frame);
+    }
+    frame
+}
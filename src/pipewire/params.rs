@@ -0,0 +1,291 @@
+#![allow(non_upper_case_globals)]
+
+use super::CaptureOptions;
+use super::ffi;
+use super::pod::{
+    Object, PodBuffer, SPA_POD_PROP_FLAG_DONT_FIXATE, SPA_POD_PROP_FLAG_MANDATORY, Value,
+};
+use crate::audio::AudioConfig;
+use crate::format::{PixelFormat, VideoFormat};
+use crate::frame::Transform;
+
+const SPA_TYPE_OBJECT_Format: u32 = 0x40003;
+const SPA_TYPE_OBJECT_ParamBuffers: u32 = 0x40004;
+const SPA_TYPE_OBJECT_ParamMeta: u32 = 0x40005;
+
+const SPA_FORMAT_mediaType: u32 = 1;
+const SPA_FORMAT_mediaSubtype: u32 = 2;
+const SPA_FORMAT_AUDIO_format: u32 = 0x10001;
+const SPA_FORMAT_AUDIO_rate: u32 = 0x10003;
+const SPA_FORMAT_AUDIO_channels: u32 = 0x10004;
+const SPA_FORMAT_AUDIO_position: u32 = 0x10005;
+const SPA_FORMAT_VIDEO_format: u32 = 0x20001;
+const SPA_FORMAT_VIDEO_modifier: u32 = 0x20002;
+const SPA_FORMAT_VIDEO_size: u32 = 0x20003;
+const SPA_FORMAT_VIDEO_framerate: u32 = 0x20004;
+const SPA_FORMAT_VIDEO_maxFramerate: u32 = 0x20005;
+
+const SPA_PARAM_BUFFERS_buffers: u32 = 1;
+const SPA_PARAM_BUFFERS_dataType: u32 = 6;
+
+const SPA_PARAM_META_type: u32 = 1;
+const SPA_PARAM_META_size: u32 = 2;
+
+const MAX_CURSOR_SIZE: usize = 256;
+const MAX_DAMAGE_REGIONS: usize = 16;
+
+const SPA_MEDIA_TYPE_audio: u32 = 1;
+const SPA_MEDIA_TYPE_video: u32 = 2;
+const SPA_MEDIA_SUBTYPE_raw: u32 = 1;
+
+const SPA_AUDIO_FORMAT_F32_LE: u32 = 0x11b;
+const SPA_AUDIO_CHANNEL_MONO: u32 = 2;
+const SPA_AUDIO_CHANNEL_FL: u32 = 3;
+const SPA_AUDIO_CHANNEL_FR: u32 = 4;
+
+pub(crate) fn spa_video_format(pixel_format: PixelFormat) -> u32 {
+    match pixel_format {
+        PixelFormat::Rgbx => 7,
+        PixelFormat::Bgrx => 8,
+        PixelFormat::Xrgb => 9,
+        PixelFormat::Xbgr => 10,
+        PixelFormat::Rgba => 11,
+        PixelFormat::Bgra => 12,
+        PixelFormat::Argb => 13,
+        PixelFormat::Abgr => 14,
+        PixelFormat::Rgb => 15,
+        PixelFormat::Bgr => 16,
+        PixelFormat::Nv12 => 23,
+        PixelFormat::I420 => 2,
+        PixelFormat::Yuy2 => 4,
+    }
+}
+
+pub(crate) fn pixel_format(spa_format: u32) -> Option<PixelFormat> {
+    PixelFormat::ALL
+        .into_iter()
+        .find(|pixel_format| spa_video_format(*pixel_format) == spa_format)
+}
+
+/// The `EnumFormat` params offered when connecting a stream, DMA-BUF first
+/// so shared memory is only picked when the compositor can't do it.
+pub(crate) fn enum_formats(options: &CaptureOptions) -> Vec<PodBuffer> {
+    let mut params = Vec::new();
+    if !options.dmabuf_modifiers.is_empty() {
+        let modifiers = options
+            .dmabuf_modifiers
+            .iter()
+            .map(|modifier| Value::Long(*modifier as i64))
+            .collect();
+        let format = enum_format(options).property_with_flags(
+            SPA_FORMAT_VIDEO_modifier,
+            SPA_POD_PROP_FLAG_MANDATORY | SPA_POD_PROP_FLAG_DONT_FIXATE,
+            Value::enumeration(modifiers),
+        );
+        params.push(Value::Object(format).to_pod());
+    }
+    params.push(Value::Object(enum_format(options)).to_pod());
+    params
+}
+
+fn enum_format(options: &CaptureOptions) -> Object {
+    let formats = options
+        .pixel_formats
+        .iter()
+        .map(|pixel_format| Value::Id(spa_video_format(*pixel_format)))
+        .collect();
+    let (min_width, min_height) = options.min_size;
+    let (max_width, max_height) = options.max_size;
+    let (min_num, min_denom) = options.min_framerate;
+    let (max_num, max_denom) = options.max_framerate;
+    let framerates = Value::range(
+        Value::Fraction {
+            num: max_num,
+            denom: max_denom,
+        },
+        Value::Fraction {
+            num: min_num,
+            denom: min_denom,
+        },
+        Value::Fraction {
+            num: max_num,
+            denom: max_denom,
+        },
+    );
+    Object::new(SPA_TYPE_OBJECT_Format, ffi::SPA_PARAM_EnumFormat)
+        .property(SPA_FORMAT_mediaType, Value::Id(SPA_MEDIA_TYPE_video))
+        .property(SPA_FORMAT_mediaSubtype, Value::Id(SPA_MEDIA_SUBTYPE_raw))
+        .property(SPA_FORMAT_VIDEO_format, Value::enumeration(formats))
+        .property(
+            SPA_FORMAT_VIDEO_size,
+            Value::range(
+                Value::Rectangle {
+                    width: max_width,
+                    height: max_height,
+                },
+                Value::Rectangle {
+                    width: min_width,
+                    height: min_height,
+                },
+                Value::Rectangle {
+                    width: max_width,
+                    height: max_height,
+                },
+            ),
+        )
+        .property(SPA_FORMAT_VIDEO_framerate, framerates.clone())
+        .property(SPA_FORMAT_VIDEO_maxFramerate, framerates)
+}
+
+/// Reads the negotiated `Format` param, `None` until the compositor fixed
+/// the DMA-BUF modifier.
+pub(crate) fn parse_video_format(param: &Value) -> Option<VideoFormat> {
+    let object = param.as_object()?;
+    if object.get(SPA_FORMAT_mediaType)?.as_id()? != SPA_MEDIA_TYPE_video
+        || object.get(SPA_FORMAT_mediaSubtype)?.as_id()? != SPA_MEDIA_SUBTYPE_raw
+    {
+        return None;
+    }
+    let pixel_format = pixel_format(object.get(SPA_FORMAT_VIDEO_format)?.as_id()?)?;
+    let (width, height) = object.get(SPA_FORMAT_VIDEO_size)?.as_rectangle()?;
+    let framerate = object
+        .get(SPA_FORMAT_VIDEO_framerate)
+        .and_then(Value::as_fraction)
+        .unwrap_or((0, 1));
+    let modifier = match object.get(SPA_FORMAT_VIDEO_modifier) {
+        Some(modifier) if modifier.is_choice() => return None,
+        Some(modifier) => Some(modifier.as_long()? as u64),
+        None => None,
+    };
+    Some(VideoFormat {
+        pixel_format,
+        width,
+        height,
+        framerate,
+        modifier,
+    })
+}
+
+/// Params sent once the format is fixed, requesting the buffer type and the
+/// metadata read per buffer.
+pub(crate) fn buffer_params(format: &VideoFormat, options: &CaptureOptions) -> Vec<PodBuffer> {
+    let data_types = match format.modifier {
+        Some(_) => 1 << ffi::SPA_DATA_DmaBuf,
+        None => (1 << ffi::SPA_DATA_MemPtr) | (1 << ffi::SPA_DATA_MemFd),
+    };
+    let max_buffers = options.buffers.max(1) as i32;
+    let buffers = Object::new(SPA_TYPE_OBJECT_ParamBuffers, ffi::SPA_PARAM_Buffers)
+        .property(
+            SPA_PARAM_BUFFERS_buffers,
+            Value::range(
+                Value::Int(max_buffers),
+                Value::Int(1),
+                Value::Int(max_buffers),
+            ),
+        )
+        .property(
+            SPA_PARAM_BUFFERS_dataType,
+            Value::flags(Value::Int(data_types)),
+        );
+    let header = Object::new(SPA_TYPE_OBJECT_ParamMeta, ffi::SPA_PARAM_Meta)
+        .property(SPA_PARAM_META_type, Value::Id(ffi::SPA_META_Header))
+        .property(
+            SPA_PARAM_META_size,
+            Value::Int(size_of::<ffi::spa_meta_header>() as i32),
+        );
+    let crop = Object::new(SPA_TYPE_OBJECT_ParamMeta, ffi::SPA_PARAM_Meta)
+        .property(SPA_PARAM_META_type, Value::Id(ffi::SPA_META_VideoCrop))
+        .property(
+            SPA_PARAM_META_size,
+            Value::Int(size_of::<ffi::spa_meta_region>() as i32),
+        );
+    let region_size = size_of::<ffi::spa_meta_region>();
+    let damage = Object::new(SPA_TYPE_OBJECT_ParamMeta, ffi::SPA_PARAM_Meta)
+        .property(SPA_PARAM_META_type, Value::Id(ffi::SPA_META_VideoDamage))
+        .property(
+            SPA_PARAM_META_size,
+            Value::range(
+                Value::Int((region_size * MAX_DAMAGE_REGIONS) as i32),
+                Value::Int(region_size as i32),
+                Value::Int((region_size * MAX_DAMAGE_REGIONS) as i32),
+            ),
+        );
+    let transform = Object::new(SPA_TYPE_OBJECT_ParamMeta, ffi::SPA_PARAM_Meta)
+        .property(SPA_PARAM_META_type, Value::Id(ffi::SPA_META_VideoTransform))
+        .property(
+            SPA_PARAM_META_size,
+            Value::Int(size_of::<ffi::spa_meta_videotransform>() as i32),
+        );
+    let cursor = Object::new(SPA_TYPE_OBJECT_ParamMeta, ffi::SPA_PARAM_Meta)
+        .property(SPA_PARAM_META_type, Value::Id(ffi::SPA_META_Cursor))
+        .property(
+            SPA_PARAM_META_size,
+            Value::range(
+                Value::Int(cursor_meta_size(64) as i32),
+                Value::Int(cursor_meta_size(1) as i32),
+                Value::Int(cursor_meta_size(MAX_CURSOR_SIZE) as i32),
+            ),
+        );
+    vec![
+        Value::Object(buffers).to_pod(),
+        Value::Object(header).to_pod(),
+        Value::Object(crop).to_pod(),
+        Value::Object(damage).to_pod(),
+        Value::Object(transform).to_pod(),
+        Value::Object(cursor).to_pod(),
+    ]
+}
+
+/// Room for the cursor meta followed by a square 32-bit bitmap.
+fn cursor_meta_size(size: usize) -> usize {
+    size_of::<ffi::spa_meta_cursor>() + size_of::<ffi::spa_meta_bitmap>() + size * size * 4
+}
+
+pub(crate) fn transform(spa_transform: u32) -> Transform {
+    match spa_transform {
+        1 => Transform::Rotate90,
+        2 => Transform::Rotate180,
+        3 => Transform::Rotate270,
+        4 => Transform::Flipped,
+        5 => Transform::Flipped90,
+        6 => Transform::Flipped180,
+        7 => Transform::Flipped270,
+        _ => Transform::Normal,
+    }
+}
+
+/// The one `EnumFormat` of an audio stream, interleaved 32 bit float at the
+/// rate and channel count of `config`, which PipeWire converts to.
+pub(crate) fn audio_format(config: &AudioConfig) -> PodBuffer {
+    let position = match config.channels {
+        1 => vec![Value::Id(SPA_AUDIO_CHANNEL_MONO)],
+        _ => vec![
+            Value::Id(SPA_AUDIO_CHANNEL_FL),
+            Value::Id(SPA_AUDIO_CHANNEL_FR),
+        ],
+    };
+    let format = Object::new(SPA_TYPE_OBJECT_Format, ffi::SPA_PARAM_EnumFormat)
+        .property(SPA_FORMAT_mediaType, Value::Id(SPA_MEDIA_TYPE_audio))
+        .property(SPA_FORMAT_mediaSubtype, Value::Id(SPA_MEDIA_SUBTYPE_raw))
+        .property(SPA_FORMAT_AUDIO_format, Value::Id(SPA_AUDIO_FORMAT_F32_LE))
+        .property(SPA_FORMAT_AUDIO_rate, Value::Int(config.sample_rate as i32))
+        .property(
+            SPA_FORMAT_AUDIO_channels,
+            Value::Int(config.channels as i32),
+        )
+        .property(SPA_FORMAT_AUDIO_position, Value::Array(position));
+    Value::Object(format).to_pod()
+}
+
+/// The rate and channel count of the negotiated audio `Format` param.
+pub(crate) fn parse_audio_format(param: &Value) -> Option<(u32, u32)> {
+    let object = param.as_object()?;
+    if object.get(SPA_FORMAT_mediaType)?.as_id()? != SPA_MEDIA_TYPE_audio
+        || object.get(SPA_FORMAT_AUDIO_format)?.as_id()? != SPA_AUDIO_FORMAT_F32_LE
+    {
+        return None;
+    }
+    let rate = object.get(SPA_FORMAT_AUDIO_rate)?.as_int()?;
+    let channels = object.get(SPA_FORMAT_AUDIO_channels)?.as_int()?;
+    Some((rate.max(1) as u32, channels.max(1) as u32))
+}
@@ -0,0 +1,411 @@
+#![allow(non_upper_case_globals)]
+
+use super::ffi::spa_pod;
+
+pub(crate) const SPA_TYPE_None: u32 = 1;
+pub(crate) const SPA_TYPE_Bool: u32 = 2;
+pub(crate) const SPA_TYPE_Id: u32 = 3;
+pub(crate) const SPA_TYPE_Int: u32 = 4;
+pub(crate) const SPA_TYPE_Long: u32 = 5;
+pub(crate) const SPA_TYPE_Float: u32 = 6;
+pub(crate) const SPA_TYPE_Double: u32 = 7;
+pub(crate) const SPA_TYPE_String: u32 = 8;
+pub(crate) const SPA_TYPE_Bytes: u32 = 9;
+pub(crate) const SPA_TYPE_Rectangle: u32 = 10;
+pub(crate) const SPA_TYPE_Fraction: u32 = 11;
+pub(crate) const SPA_TYPE_Array: u32 = 13;
+pub(crate) const SPA_TYPE_Struct: u32 = 14;
+pub(crate) const SPA_TYPE_Object: u32 = 15;
+pub(crate) const SPA_TYPE_Choice: u32 = 19;
+pub(crate) const SPA_TYPE_Fd: u32 = 18;
+
+pub(crate) const SPA_CHOICE_Range: u32 = 1;
+pub(crate) const SPA_CHOICE_Enum: u32 = 3;
+pub(crate) const SPA_CHOICE_Flags: u32 = 4;
+
+pub(crate) const SPA_POD_PROP_FLAG_MANDATORY: u32 = 1 << 3;
+pub(crate) const SPA_POD_PROP_FLAG_DONT_FIXATE: u32 = 1 << 4;
+
+/// A decoded or to-be-encoded SPA POD value.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Value {
+    None,
+    Bool(bool),
+    Id(u32),
+    Int(i32),
+    Long(i64),
+    Float(f32),
+    Double(f64),
+    String(String),
+    Bytes(Vec<u8>),
+    Rectangle { width: u32, height: u32 },
+    Fraction { num: u32, denom: u32 },
+    Array(Vec<Value>),
+    Struct(Vec<Value>),
+    Object(Object),
+    Choice(Choice),
+    Fd(i64),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Object {
+    pub type_: u32,
+    pub id: u32,
+    pub properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Property {
+    pub key: u32,
+    pub flags: u32,
+    pub value: Value,
+}
+
+/// For `Range` the values are `[default, min, max]`, for `Enum` and `Flags`
+/// they are `[default, alternatives...]`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Choice {
+    pub kind: u32,
+    pub flags: u32,
+    pub values: Vec<Value>,
+}
+
+impl Object {
+    pub(crate) fn new(type_: u32, id: u32) -> Self {
+        Object {
+            type_,
+            id,
+            properties: Vec::new(),
+        }
+    }
+
+    pub(crate) fn property(mut self, key: u32, value: Value) -> Self {
+        self.properties.push(Property {
+            key,
+            flags: 0,
+            value,
+        });
+        self
+    }
+
+    pub(crate) fn property_with_flags(mut self, key: u32, flags: u32, value: Value) -> Self {
+        self.properties.push(Property { key, flags, value });
+        self
+    }
+
+    pub(crate) fn get(&self, key: u32) -> Option<&Value> {
+        self.properties
+            .iter()
+            .find(|property| property.key == key)
+            .map(|property| &property.value)
+    }
+}
+
+impl Value {
+    pub(crate) fn range(default: Value, min: Value, max: Value) -> Self {
+        Value::Choice(Choice {
+            kind: SPA_CHOICE_Range,
+            flags: 0,
+            values: vec![default, min, max],
+        })
+    }
+
+    pub(crate) fn enumeration(values: Vec<Value>) -> Self {
+        let mut values = values;
+        if let Some(default) = values.first().cloned() {
+            values.insert(0, default);
+        }
+        Value::Choice(Choice {
+            kind: SPA_CHOICE_Enum,
+            flags: 0,
+            values,
+        })
+    }
+
+    pub(crate) fn flags(value: Value) -> Self {
+        Value::Choice(Choice {
+            kind: SPA_CHOICE_Flags,
+            flags: 0,
+            values: vec![value],
+        })
+    }
+
+    /// The fixated value, the default of a choice.
+    pub(crate) fn fixated(&self) -> &Value {
+        match self {
+            Value::Choice(choice) => choice.values.first().unwrap_or(&Value::None),
+            value => value,
+        }
+    }
+
+    pub(crate) fn as_id(&self) -> Option<u32> {
+        match self.fixated() {
+            Value::Id(id) => Some(*id),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_int(&self) -> Option<i32> {
+        match self.fixated() {
+            Value::Int(int) => Some(*int),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_long(&self) -> Option<i64> {
+        match self.fixated() {
+            Value::Long(long) => Some(*long),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn is_choice(&self) -> bool {
+        matches!(self, Value::Choice(choice) if choice.values.len() > 1)
+    }
+
+    pub(crate) fn as_rectangle(&self) -> Option<(u32, u32)> {
+        match self.fixated() {
+            Value::Rectangle { width, height } => Some((*width, *height)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_fraction(&self) -> Option<(u32, u32)> {
+        match self.fixated() {
+            Value::Fraction { num, denom } => Some((*num, *denom)),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_object(&self) -> Option<&Object> {
+        match self {
+            Value::Object(object) => Some(object),
+            _ => None,
+        }
+    }
+
+    fn type_id(&self) -> u32 {
+        match self {
+            Value::None => SPA_TYPE_None,
+            Value::Bool(_) => SPA_TYPE_Bool,
+            Value::Id(_) => SPA_TYPE_Id,
+            Value::Int(_) => SPA_TYPE_Int,
+            Value::Long(_) => SPA_TYPE_Long,
+            Value::Float(_) => SPA_TYPE_Float,
+            Value::Double(_) => SPA_TYPE_Double,
+            Value::String(_) => SPA_TYPE_String,
+            Value::Bytes(_) => SPA_TYPE_Bytes,
+            Value::Rectangle { .. } => SPA_TYPE_Rectangle,
+            Value::Fraction { .. } => SPA_TYPE_Fraction,
+            Value::Array(_) => SPA_TYPE_Array,
+            Value::Struct(_) => SPA_TYPE_Struct,
+            Value::Object(_) => SPA_TYPE_Object,
+            Value::Choice(_) => SPA_TYPE_Choice,
+            Value::Fd(_) => SPA_TYPE_Fd,
+        }
+    }
+
+    fn write_body(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::None => {}
+            Value::Bool(value) => out.extend_from_slice(&(*value as i32).to_ne_bytes()),
+            Value::Id(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Value::Int(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Value::Long(value) | Value::Fd(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Value::Float(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Value::Double(value) => out.extend_from_slice(&value.to_ne_bytes()),
+            Value::String(value) => {
+                out.extend_from_slice(value.as_bytes());
+                out.push(0);
+            }
+            Value::Bytes(value) => out.extend_from_slice(value),
+            Value::Rectangle { width, height } => {
+                out.extend_from_slice(&width.to_ne_bytes());
+                out.extend_from_slice(&height.to_ne_bytes());
+            }
+            Value::Fraction { num, denom } => {
+                out.extend_from_slice(&num.to_ne_bytes());
+                out.extend_from_slice(&denom.to_ne_bytes());
+            }
+            Value::Array(values) => write_elements(values, out),
+            Value::Struct(values) => {
+                for value in values {
+                    value.write(out);
+                }
+            }
+            Value::Object(object) => {
+                out.extend_from_slice(&object.type_.to_ne_bytes());
+                out.extend_from_slice(&object.id.to_ne_bytes());
+                for property in &object.properties {
+                    out.extend_from_slice(&property.key.to_ne_bytes());
+                    out.extend_from_slice(&property.flags.to_ne_bytes());
+                    property.value.write(out);
+                }
+            }
+            Value::Choice(choice) => {
+                out.extend_from_slice(&choice.kind.to_ne_bytes());
+                out.extend_from_slice(&choice.flags.to_ne_bytes());
+                write_elements(&choice.values, out);
+            }
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let header = out.len();
+        out.extend_from_slice(&0u32.to_ne_bytes());
+        out.extend_from_slice(&self.type_id().to_ne_bytes());
+        self.write_body(out);
+        let size = (out.len() - header - 8) as u32;
+        out[header..header + 4].copy_from_slice(&size.to_ne_bytes());
+        out.resize(out.len().next_multiple_of(8), 0);
+    }
+
+    /// Encodes the value into an 8-byte aligned buffer.
+    pub(crate) fn to_pod(&self) -> PodBuffer {
+        let mut bytes = Vec::new();
+        self.write(&mut bytes);
+        PodBuffer::from_bytes(&bytes)
+    }
+
+    /// Decodes a value and returns it with the padded number of bytes read.
+    pub(crate) fn parse(bytes: &[u8]) -> Option<(Value, usize)> {
+        let size = read_u32(bytes, 0)? as usize;
+        let type_id = read_u32(bytes, 4)?;
+        let body = bytes.get(8..8 + size)?;
+        let value = Value::parse_body(type_id, body)?;
+        Some((value, (8 + size).next_multiple_of(8)))
+    }
+
+    /// # Safety
+    /// `pod` must point to a valid pod of `size + 8` readable bytes.
+    pub(crate) unsafe fn from_raw(pod: *const spa_pod) -> Option<Value> {
+        if pod.is_null() {
+            return None;
+        }
+        let bytes = unsafe {
+            let size = (*pod).size as usize;
+            std::slice::from_raw_parts(pod.cast::<u8>(), size + 8)
+        };
+        Value::parse(bytes).map(|(value, _)| value)
+    }
+
+    fn parse_body(type_id: u32, body: &[u8]) -> Option<Value> {
+        let value = match type_id {
+            SPA_TYPE_None => Value::None,
+            SPA_TYPE_Bool => Value::Bool(read_u32(body, 0)? != 0),
+            SPA_TYPE_Id => Value::Id(read_u32(body, 0)?),
+            SPA_TYPE_Int => Value::Int(read_u32(body, 0)? as i32),
+            SPA_TYPE_Long => Value::Long(read_u64(body, 0)? as i64),
+            SPA_TYPE_Fd => Value::Fd(read_u64(body, 0)? as i64),
+            SPA_TYPE_Float => Value::Float(f32::from_bits(read_u32(body, 0)?)),
+            SPA_TYPE_Double => Value::Double(f64::from_bits(read_u64(body, 0)?)),
+            SPA_TYPE_String => {
+                let end = body
+                    .iter()
+                    .position(|byte| *byte == 0)
+                    .unwrap_or(body.len());
+                Value::String(String::from_utf8_lossy(&body[..end]).into_owned())
+            }
+            SPA_TYPE_Bytes => Value::Bytes(body.to_vec()),
+            SPA_TYPE_Rectangle => Value::Rectangle {
+                width: read_u32(body, 0)?,
+                height: read_u32(body, 4)?,
+            },
+            SPA_TYPE_Fraction => Value::Fraction {
+                num: read_u32(body, 0)?,
+                denom: read_u32(body, 4)?,
+            },
+            SPA_TYPE_Array => Value::Array(parse_elements(body)?),
+            SPA_TYPE_Struct => {
+                let mut values = Vec::new();
+                let mut offset = 0;
+                while offset + 8 <= body.len() {
+                    let (value, read) = Value::parse(&body[offset..])?;
+                    values.push(value);
+                    offset += read;
+                }
+                Value::Struct(values)
+            }
+            SPA_TYPE_Object => {
+                let mut object = Object::new(read_u32(body, 0)?, read_u32(body, 4)?);
+                let mut offset = 8;
+                while offset + 16 <= body.len() {
+                    let key = read_u32(body, offset)?;
+                    let flags = read_u32(body, offset + 4)?;
+                    let (value, read) = Value::parse(&body[offset + 8..])?;
+                    object.properties.push(Property { key, flags, value });
+                    offset += 8 + read;
+                }
+                Value::Object(object)
+            }
+            SPA_TYPE_Choice => Value::Choice(Choice {
+                kind: read_u32(body, 0)?,
+                flags: read_u32(body, 4)?,
+                values: parse_elements(body.get(8..)?)?,
+            }),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+fn write_elements(values: &[Value], out: &mut Vec<u8>) {
+    let mut bodies = Vec::new();
+    for value in values {
+        value.write_body(&mut bodies);
+    }
+    let child_size = match values.len() {
+        0 => 0,
+        len => bodies.len() / len,
+    };
+    let child_type = values.first().map_or(SPA_TYPE_None, Value::type_id);
+    out.extend_from_slice(&(child_size as u32).to_ne_bytes());
+    out.extend_from_slice(&child_type.to_ne_bytes());
+    out.extend_from_slice(&bodies);
+}
+
+fn parse_elements(body: &[u8]) -> Option<Vec<Value>> {
+    let child_size = read_u32(body, 0)? as usize;
+    let child_type = read_u32(body, 4)?;
+    if child_size == 0 {
+        return Some(Vec::new());
+    }
+    body[8..]
+        .chunks_exact(child_size)
+        .map(|chunk| Value::parse_body(child_type, chunk))
+        .collect()
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 4)?;
+    Some(u32::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    let bytes = bytes.get(offset..offset + 8)?;
+    Some(u64::from_ne_bytes(bytes.try_into().ok()?))
+}
+
+/// Pod bytes kept in `u64` storage so the header is suitably aligned.
+#[derive(Debug, Clone)]
+pub(crate) struct PodBuffer {
+    words: Vec<u64>,
+}
+
+impl PodBuffer {
+    fn from_bytes(bytes: &[u8]) -> Self {
+        let words = bytes
+            .chunks(8)
+            .map(|chunk| {
+                let mut word = [0u8; 8];
+                word[..chunk.len()].copy_from_slice(chunk);
+                u64::from_ne_bytes(word)
+            })
+            .collect();
+        PodBuffer { words }
+    }
+
+    pub(crate) fn as_ptr(&self) -> *const spa_pod {
+        self.words.as_ptr().cast()
+    }
+}
@@ -0,0 +1,149 @@
+use super::CaptureOptions;
+use super::StatsRecorder;
+use super::ffi;
+use super::stream::{Output, StreamData};
+use crate::error::{Result, ScreencastError};
+use crate::events::EventSender;
+use crate::format::StreamInfo;
+use crate::screencast::SelectedSource;
+use std::os::fd::{IntoRawFd, OwnedFd};
+use std::ptr;
+use std::sync::{Arc, Once};
+
+/// The thread loop, context and core connected to the portal's remote, with
+/// one stream per source.
+pub(crate) struct Remote {
+    thread_loop: *mut ffi::pw_thread_loop,
+    context: *mut ffi::pw_context,
+    core: *mut ffi::pw_core,
+    // boxed so the pointers handed to the stream listeners stay put
+    #[allow(clippy::vec_box)]
+    streams: Vec<Box<StreamData>>,
+}
+
+// SAFETY: the raw handles are only used with the thread loop locked.
+unsafe impl Send for Remote {}
+unsafe impl Sync for Remote {}
+
+impl std::fmt::Debug for Remote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Remote")
+            .field("streams", &self.streams.len())
+            .finish()
+    }
+}
+
+impl Remote {
+    /// `output` is asked once per source, in order, for where its frames go.
+    pub(crate) fn connect(
+        fd: OwnedFd,
+        sources: &[SelectedSource],
+        options: &CaptureOptions,
+        events: &EventSender,
+        stats: &Arc<StatsRecorder>,
+        mut output: impl FnMut(&SelectedSource) -> Output,
+    ) -> Result<Self> {
+        if options.pixel_formats.is_empty() {
+            return Err(ScreencastError::Unsupported(
+                "no pixel formats to negotiate".to_string(),
+            ));
+        }
+        init();
+        let mut remote = Remote {
+            thread_loop: ptr::null_mut(),
+            context: ptr::null_mut(),
+            core: ptr::null_mut(),
+            streams: Vec::new(),
+        };
+        unsafe {
+            remote.thread_loop = ffi::pw_thread_loop_new(c"xdp-screencast".as_ptr(), ptr::null());
+            if remote.thread_loop.is_null() {
+                return Err(pipewire_error("failed to create thread loop"));
+            }
+            let pw_loop = ffi::pw_thread_loop_get_loop(remote.thread_loop);
+            remote.context = ffi::pw_context_new(pw_loop, ptr::null_mut(), 0);
+            if remote.context.is_null() {
+                return Err(pipewire_error("failed to create context"));
+            }
+            if ffi::pw_thread_loop_start(remote.thread_loop) < 0 {
+                return Err(pipewire_error("failed to start thread loop"));
+            }
+            let _lock = LoopLock::new(remote.thread_loop);
+            remote.core =
+                ffi::pw_context_connect_fd(remote.context, fd.into_raw_fd(), ptr::null_mut(), 0);
+            if remote.core.is_null() {
+                return Err(pipewire_error("failed to connect to remote"));
+            }
+            for source in sources {
+                let stream = StreamData::connect(
+                    remote.core,
+                    source.node_id(),
+                    options,
+                    output(source),
+                    events.clone(),
+                    stats.clone(),
+                )?;
+                remote.streams.push(stream);
+            }
+        }
+        Ok(remote)
+    }
+
+    /// The formats of the streams that finished negotiation.
+    pub(crate) fn stream_info(&self) -> Vec<StreamInfo> {
+        let _lock = unsafe { LoopLock::new(self.thread_loop) };
+        self.streams
+            .iter()
+            .filter_map(|stream| unsafe { stream.info() })
+            .collect()
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        if self.thread_loop.is_null() {
+            return;
+        }
+        unsafe {
+            {
+                let _lock = LoopLock::new(self.thread_loop);
+                for stream in &mut self.streams {
+                    stream.destroy();
+                }
+                if !self.core.is_null() {
+                    ffi::pw_core_disconnect(self.core);
+                }
+            }
+            ffi::pw_thread_loop_stop(self.thread_loop);
+            if !self.context.is_null() {
+                ffi::pw_context_destroy(self.context);
+            }
+            ffi::pw_thread_loop_destroy(self.thread_loop);
+        }
+    }
+}
+
+/// Initializes libpipewire once per process.
+pub(super) fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| unsafe { ffi::pw_init(ptr::null_mut(), ptr::null_mut()) });
+}
+
+pub(super) struct LoopLock(*mut ffi::pw_thread_loop);
+
+impl LoopLock {
+    pub(super) unsafe fn new(thread_loop: *mut ffi::pw_thread_loop) -> Self {
+        unsafe { ffi::pw_thread_loop_lock(thread_loop) };
+        LoopLock(thread_loop)
+    }
+}
+
+impl Drop for LoopLock {
+    fn drop(&mut self) {
+        unsafe { ffi::pw_thread_loop_unlock(self.0) };
+    }
+}
+
+pub(super) fn pipewire_error(message: &str) -> ScreencastError {
+    ScreencastError::PipeWire(message.to_string())
+}
@@ -0,0 +1,94 @@
+use super::CaptureOptions;
+use crate::runtime;
+use std::cell::Cell;
+use std::io;
+use zbus::Connection;
+
+const SCHED_RESET_ON_FORK: i32 = 0x4000_0000;
+/// rtkit refuses threads without a CPU time limit, 200ms is what PipeWire uses.
+const RTTIME_LIMIT_USEC: u64 = 200_000;
+
+/// Applies the scheduling options to the calling loop thread once, best
+/// effort as failures can only be noticed in the thread's own callbacks.
+pub(crate) fn apply(options: &CaptureOptions) {
+    thread_local! {
+        static APPLIED: Cell<bool> = const { Cell::new(false) };
+    }
+    if APPLIED.replace(true) {
+        return;
+    }
+    if !options.cpu_affinity.is_empty() {
+        let _ = set_affinity(&options.cpu_affinity);
+    }
+    if let Some(priority) = options.realtime_priority
+        && set_fifo(priority).is_err()
+    {
+        let tid = unsafe { libc::syscall(libc::SYS_gettid) } as u64;
+        runtime::handle().spawn(async move {
+            if make_realtime_rtkit(tid, priority).await.is_err() {
+                let _ = make_realtime_portal(tid, priority).await;
+            }
+        });
+    }
+}
+
+fn set_affinity(cpus: &[usize]) -> io::Result<()> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        for cpu in cpus {
+            libc::CPU_SET(*cpu, &mut set);
+        }
+        match libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+fn set_fifo(priority: u32) -> io::Result<()> {
+    unsafe {
+        let max = libc::sched_get_priority_max(libc::SCHED_FIFO).max(1);
+        let param = libc::sched_param {
+            sched_priority: (priority as i32).clamp(1, max),
+        };
+        match libc::sched_setscheduler(0, libc::SCHED_FIFO | SCHED_RESET_ON_FORK, &param) {
+            0 => Ok(()),
+            _ => Err(io::Error::last_os_error()),
+        }
+    }
+}
+
+/// Unprivileged processes go through rtkit on the system bus.
+async fn make_realtime_rtkit(tid: u64, priority: u32) -> zbus::Result<()> {
+    let limit = libc::rlimit {
+        rlim_cur: RTTIME_LIMIT_USEC,
+        rlim_max: RTTIME_LIMIT_USEC,
+    };
+    unsafe { libc::setrlimit(libc::RLIMIT_RTTIME, &limit) };
+    let connection = Connection::system().await?;
+    connection
+        .call_method(
+            Some("org.freedesktop.RealtimeKit1"),
+            "/org/freedesktop/RealtimeKit1",
+            Some("org.freedesktop.RealtimeKit1"),
+            "MakeThreadRealtime",
+            &(tid, priority),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Sandboxed processes can't reach rtkit and use the portal that forwards to it.
+async fn make_realtime_portal(tid: u64, priority: u32) -> zbus::Result<()> {
+    let connection = Connection::session().await?;
+    connection
+        .call_method(
+            Some("org.freedesktop.portal.Desktop"),
+            "/org/freedesktop/portal/desktop",
+            Some("org.freedesktop.portal.Realtime"),
+            "MakeThreadRealtimeWithPID",
+            &(std::process::id() as u64, tid, priority),
+        )
+        .await?;
+    Ok(())
+}
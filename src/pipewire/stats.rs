@@ -0,0 +1,98 @@
+use super::CaptureStats;
+use crate::frame::Frame;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const LATENCY_SAMPLES: usize = 512;
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Counters shared by the stream callbacks and the consumer side of every
+/// capture of a session.
+#[derive(Debug, Default)]
+pub(crate) struct StatsRecorder {
+    state: Mutex<State>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    frames_received: u64,
+    frames_dropped: u64,
+    frames_delivered: u64,
+    bytes: u64,
+    latencies: VecDeque<Duration>,
+    deliveries: VecDeque<Duration>,
+}
+
+impl StatsRecorder {
+    /// A frame read from a PipeWire buffer.
+    pub(crate) fn received(&self, frame: &Frame<'_>) {
+        let mut state = self.state.lock().unwrap();
+        state.frames_received += 1;
+        state.bytes += frame.data().len() as u64;
+    }
+
+    /// A frame discarded by the framerate limit or the backpressure policy.
+    pub(crate) fn dropped(&self) {
+        self.state.lock().unwrap().frames_dropped += 1;
+    }
+
+    /// A frame handed to the consumer.
+    pub(crate) fn delivered(&self, frame: &Frame<'_>) {
+        let now = monotonic_now();
+        let mut state = self.state.lock().unwrap();
+        state.frames_delivered += 1;
+        if let Some(pts) = frame.pts() {
+            if state.latencies.len() == LATENCY_SAMPLES {
+                state.latencies.pop_front();
+            }
+            state.latencies.push_back(now.saturating_sub(pts));
+        }
+        state.deliveries.push_back(now);
+        while let Some(first) = state.deliveries.front()
+            && now.saturating_sub(*first) > FPS_WINDOW
+        {
+            state.deliveries.pop_front();
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> CaptureStats {
+        let now = monotonic_now();
+        let state = self.state.lock().unwrap();
+        let mut latencies: Vec<Duration> = state.latencies.iter().copied().collect();
+        latencies.sort_unstable();
+        let percentile = |percent: usize| match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies[(len * percent / 100).min(len - 1)],
+        };
+        let latency_avg = match latencies.len() {
+            0 => Duration::ZERO,
+            len => latencies.iter().sum::<Duration>() / len as u32,
+        };
+        let recent = state
+            .deliveries
+            .iter()
+            .filter(|delivery| now.saturating_sub(**delivery) <= FPS_WINDOW)
+            .count();
+        CaptureStats {
+            frames_received: state.frames_received,
+            frames_dropped: state.frames_dropped,
+            frames_delivered: state.frames_delivered,
+            bytes_received: state.bytes,
+            fps: recent as f64 / FPS_WINDOW.as_secs_f64(),
+            latency_avg,
+            latency_p50: percentile(50),
+            latency_p95: percentile(95),
+            latency_p99: percentile(99),
+        }
+    }
+}
+
+pub(crate) fn monotonic_now() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
@@ -0,0 +1,560 @@
+use super::CaptureOptions;
+use super::StatsRecorder;
+use super::clock::graph_time;
+use super::ffi;
+use super::mmap::Mmap;
+use super::params;
+use super::pod::Value;
+use super::sched;
+use super::stats::monotonic_now;
+use crate::channel::Sender;
+use crate::error::{Result, ScreencastError};
+use crate::events::{EventSender, ScreencastEvent};
+use crate::format::{StreamInfo, VideoFormat};
+use crate::frame::{Cursor, CursorBitmap, DmaBuf, DmaBufPlane, Frame, Plane, Rect};
+use std::os::fd::{BorrowedFd, RawFd};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+static STREAM_EVENTS: ffi::pw_stream_events = ffi::pw_stream_events {
+    version: ffi::PW_VERSION_STREAM_EVENTS,
+    destroy: None,
+    state_changed: None,
+    control_info: None,
+    io_changed: None,
+    param_changed: Some(on_param_changed),
+    add_buffer: Some(on_add_buffer),
+    remove_buffer: Some(on_remove_buffer),
+    process: Some(on_process),
+    drained: None,
+    command: None,
+    trigger_done: None,
+};
+
+/// Called on the PipeWire loop thread with frames borrowing the buffer.
+pub(crate) type FrameCallback = dyn FnMut(Frame<'_>) + Send;
+
+/// Where a stream's frames go.
+pub(crate) enum Output {
+    Queue(Sender<Frame<'static>>),
+    // shared by the streams of one remote, which all run on the loop thread
+    Callback(Arc<Mutex<Box<FrameCallback>>>),
+}
+
+/// State shared with the stream callbacks, only touched on the loop thread or
+/// with the thread loop locked.
+pub(crate) struct StreamData {
+    stream: *mut ffi::pw_stream,
+    listener: ffi::spa_hook,
+    node_id: u32,
+    format: Option<VideoFormat>,
+    sequence: u64,
+    cursor_bitmap: Option<Arc<CursorBitmap>>,
+    options: CaptureOptions,
+    limiter: FrameLimiter,
+    last_pts: Option<Duration>,
+    output: Output,
+    events: EventSender,
+    stats: Arc<StatsRecorder>,
+}
+
+impl StreamData {
+    /// # Safety
+    /// The thread loop owning `core` must be locked.
+    pub(crate) unsafe fn connect(
+        core: *mut ffi::pw_core,
+        node_id: u32,
+        options: &CaptureOptions,
+        output: Output,
+        events: EventSender,
+        stats: Arc<StatsRecorder>,
+    ) -> Result<Box<StreamData>> {
+        unsafe {
+            let props = ffi::pw_properties_new(ptr::null());
+            ffi::pw_properties_set(props, c"media.type".as_ptr(), c"Video".as_ptr());
+            ffi::pw_properties_set(props, c"media.category".as_ptr(), c"Capture".as_ptr());
+            ffi::pw_properties_set(props, c"media.role".as_ptr(), c"Screen".as_ptr());
+            let stream = ffi::pw_stream_new(core, c"xdp-screencast".as_ptr(), props);
+            if stream.is_null() {
+                return Err(ScreencastError::PipeWire(format!(
+                    "failed to create stream for node {}",
+                    node_id
+                )));
+            }
+            let mut data = Box::new(StreamData {
+                stream,
+                listener: ffi::spa_hook::zeroed(),
+                node_id,
+                format: None,
+                sequence: 0,
+                cursor_bitmap: None,
+                options: options.clone(),
+                limiter: FrameLimiter::new(options.max_framerate),
+                last_pts: None,
+                output,
+                events,
+                stats,
+            });
+            let data_ptr: *mut StreamData = &mut *data;
+            ffi::pw_stream_add_listener(
+                stream,
+                &mut (*data_ptr).listener,
+                &STREAM_EVENTS,
+                data_ptr.cast(),
+            );
+            let params = params::enum_formats(options);
+            let mut pointers: Vec<_> = params.iter().map(|param| param.as_ptr()).collect();
+            let connected = ffi::pw_stream_connect(
+                stream,
+                ffi::SPA_DIRECTION_INPUT,
+                node_id,
+                ffi::PW_STREAM_FLAG_AUTOCONNECT,
+                pointers.as_mut_ptr(),
+                pointers.len() as u32,
+            );
+            if connected < 0 {
+                data.destroy();
+                return Err(ScreencastError::PipeWire(format!(
+                    "failed to connect stream for node {}: {}",
+                    node_id,
+                    std::io::Error::from_raw_os_error(-connected)
+                )));
+            }
+            Ok(data)
+        }
+    }
+
+    /// # Safety
+    /// The thread loop must be locked.
+    pub(crate) unsafe fn info(&self) -> Option<StreamInfo> {
+        Some(StreamInfo {
+            node_id: self.node_id,
+            format: self.format?,
+        })
+    }
+
+    /// # Safety
+    /// The thread loop must be locked.
+    pub(crate) unsafe fn destroy(&mut self) {
+        unsafe {
+            ffi::spa_hook_remove(&mut self.listener);
+            ffi::pw_stream_destroy(self.stream);
+        }
+        self.stream = ptr::null_mut();
+    }
+
+    /// # Safety
+    /// `buffer` must be dequeued, the frame borrows its memory until it is
+    /// queued again.
+    unsafe fn read_frame<'b>(&mut self, buffer: &'b ffi::pw_buffer) -> Option<Frame<'b>> {
+        let format = self.format?;
+        let spa_buffer = unsafe { buffer.buffer.as_ref()? };
+        if spa_buffer.n_datas == 0 {
+            return None;
+        }
+        let datas =
+            unsafe { std::slice::from_raw_parts(spa_buffer.datas, spa_buffer.n_datas as usize) };
+        let mut frame = match datas[0].type_ {
+            ffi::SPA_DATA_DmaBuf => {
+                let dmabuf = unsafe { read_dmabuf(datas, format.modifier?)? };
+                Frame::from_dmabuf(format.pixel_format, format.width, format.height, dmabuf)
+            }
+            _ if datas.len() > 1 => unsafe { read_planes(buffer, datas, &format)? },
+            _ => {
+                let (memory, stride) = unsafe { plane_memory(buffer, datas, 0, &format)? };
+                // buffers still queued from before a resize are smaller than
+                // the new format, and a format without rows has no frames
+                let row = format.width as usize * format.pixel_format.bytes_per_pixel();
+                let last_row = (format.height as usize).checked_sub(1)?;
+                if memory.len() < stride * last_row + row {
+                    return None;
+                }
+                Frame::packed(
+                    format.pixel_format,
+                    format.width,
+                    format.height,
+                    stride,
+                    memory,
+                )
+            }
+        };
+        self.sequence += 1;
+        frame = frame
+            .with_sequence(self.sequence)
+            .with_node_id(self.node_id);
+        // compositors stamp the header on CLOCK_MONOTONIC, the clock PipeWire
+        // runs on, so frames without one take the start of the graph cycle,
+        // like the audio buffers, or the time they arrived
+        let pts = match unsafe {
+            find_meta::<ffi::spa_meta_header>(spa_buffer, ffi::SPA_META_Header)
+        } {
+            Some(header) if header.pts >= 0 => Duration::from_nanos(header.pts as u64),
+            _ => unsafe { graph_time(self.stream, 0) }.map_or_else(monotonic_now, |time| time.now),
+        };
+        let pts = match self.last_pts {
+            Some(last) if pts <= last => last + Duration::from_nanos(1),
+            _ => pts,
+        };
+        self.last_pts = Some(pts);
+        frame = frame.with_pts(pts);
+        if let Some(crop) =
+            unsafe { find_meta::<ffi::spa_meta_region>(spa_buffer, ffi::SPA_META_VideoCrop) }
+            && let region = &crop.region
+            && region.size.width > 0
+            && region.size.height > 0
+            && (
+                region.position.x,
+                region.position.y,
+                region.size.width,
+                region.size.height,
+            ) != (0, 0, format.width, format.height)
+        {
+            frame = frame.with_crop(Rect::new(
+                region.position.x,
+                region.position.y,
+                region.size.width,
+                region.size.height,
+            ));
+        }
+        if let Some(transform) = unsafe {
+            find_meta::<ffi::spa_meta_videotransform>(spa_buffer, ffi::SPA_META_VideoTransform)
+        } {
+            frame = frame.with_transform(params::transform(transform.transform));
+        }
+        if let Some(cursor) = unsafe { self.read_cursor(spa_buffer) } {
+            frame = frame.with_cursor(cursor);
+        }
+        if let Some(damage) = unsafe { read_damage(spa_buffer) } {
+            frame = frame.with_damage(damage);
+        }
+        Some(frame)
+    }
+}
+
+impl StreamData {
+    /// # Safety
+    /// `spa_buffer` must be dequeued.
+    unsafe fn read_cursor(&mut self, spa_buffer: &ffi::spa_buffer) -> Option<Cursor> {
+        let (meta, meta_size) = unsafe { find_meta_raw(spa_buffer, ffi::SPA_META_Cursor)? };
+        if meta_size < size_of::<ffi::spa_meta_cursor>() {
+            return None;
+        }
+        let cursor = unsafe { &*meta.cast::<ffi::spa_meta_cursor>() };
+        if cursor.id == 0 {
+            return None;
+        }
+        let bitmap_offset = cursor.bitmap_offset as usize;
+        if bitmap_offset >= size_of::<ffi::spa_meta_cursor>()
+            && bitmap_offset + size_of::<ffi::spa_meta_bitmap>() <= meta_size
+        {
+            let bitmap = unsafe { &*meta.add(bitmap_offset).cast::<ffi::spa_meta_bitmap>() };
+            let bitmap_start = bitmap_offset + bitmap.offset as usize;
+            let stride = bitmap.stride.max(0) as usize;
+            let len = stride * bitmap.size.height as usize;
+            if let Some(pixel_format) = params::pixel_format(bitmap.format)
+                && bitmap.size.width > 0
+                && bitmap_start + len <= meta_size
+            {
+                let data = unsafe { std::slice::from_raw_parts(meta.add(bitmap_start), len) };
+                self.cursor_bitmap = Some(Arc::new(CursorBitmap {
+                    pixel_format,
+                    width: bitmap.size.width,
+                    height: bitmap.size.height,
+                    stride,
+                    data: data.to_vec(),
+                }));
+            }
+        }
+        Some(Cursor {
+            position: (cursor.position.x, cursor.position.y),
+            hotspot: (cursor.hotspot.x, cursor.hotspot.y),
+            bitmap: self.cursor_bitmap.clone(),
+        })
+    }
+}
+
+/// Drops frames arriving faster than the configured max framerate.
+struct FrameLimiter {
+    interval: Option<Duration>,
+    next: Option<Duration>,
+}
+
+impl FrameLimiter {
+    fn new((num, denom): (u32, u32)) -> Self {
+        let interval = match num {
+            0 => None,
+            num => Some(Duration::from_secs(denom as u64) / num),
+        };
+        FrameLimiter {
+            interval,
+            next: None,
+        }
+    }
+
+    fn accept(&mut self, time: Duration) -> bool {
+        let Some(interval) = self.interval else {
+            return true;
+        };
+        // a quarter interval of slack absorbs compositor jitter
+        if let Some(next) = self.next
+            && time + interval / 4 < next
+        {
+            return false;
+        }
+        self.next = Some(match self.next {
+            Some(next) if time < next + interval => next + interval,
+            _ => time + interval,
+        });
+        true
+    }
+}
+
+/// The chunk of plane `index` and its stride.
+///
+/// # Safety
+/// `datas` must belong to `buffer`.
+unsafe fn plane_memory<'b>(
+    buffer: &'b ffi::pw_buffer,
+    datas: &'b [ffi::spa_data],
+    index: usize,
+    format: &VideoFormat,
+) -> Option<(&'b [u8], usize)> {
+    let data = datas.get(index)?;
+    let chunk = unsafe { data.chunk.as_ref()? };
+    if chunk.flags & ffi::SPA_CHUNK_FLAG_CORRUPTED != 0 {
+        return None;
+    }
+    let memory = match data.type_ {
+        ffi::SPA_DATA_MemPtr if !data.data.is_null() => unsafe {
+            std::slice::from_raw_parts(data.data.cast::<u8>(), data.maxsize as usize)
+        },
+        ffi::SPA_DATA_MemFd => {
+            let mappings = unsafe { buffer.user_data.cast::<Vec<Option<Mmap>>>().as_ref()? };
+            mappings.get(index)?.as_ref()?.as_slice()
+        }
+        _ => return None,
+    };
+    let offset = (chunk.offset as usize).min(memory.len());
+    let size = (chunk.size as usize).min(memory.len() - offset);
+    if size == 0 {
+        return None;
+    }
+    let stride = match chunk.stride {
+        stride if stride > 0 => stride as usize,
+        _ if index == 0 => format.width as usize * format.pixel_format.bytes_per_pixel(),
+        _ => format.width as usize,
+    };
+    Some((&memory[offset..offset + size], stride))
+}
+
+/// Copies planes delivered in separate datas into one contiguous frame.
+///
+/// # Safety
+/// `datas` must belong to `buffer`.
+unsafe fn read_planes(
+    buffer: &ffi::pw_buffer,
+    datas: &[ffi::spa_data],
+    format: &VideoFormat,
+) -> Option<Frame<'static>> {
+    let mut planes = Vec::with_capacity(datas.len());
+    let mut bytes = Vec::new();
+    for index in 0..format.pixel_format.plane_count().min(datas.len()) {
+        let (memory, stride) = unsafe { plane_memory(buffer, datas, index, format)? };
+        let rows = Plane::rows(format.pixel_format, index, format.height);
+        let len = (stride * rows).min(memory.len());
+        planes.push(Plane {
+            offset: bytes.len(),
+            stride,
+        });
+        bytes.extend_from_slice(&memory[..len]);
+    }
+    Some(Frame::new(
+        format.pixel_format,
+        format.width,
+        format.height,
+        planes,
+        bytes,
+    ))
+}
+
+/// Duplicates the plane fds so the frame can outlive the buffer, the contents
+/// are only stable until the compositor renders into the buffer again.
+unsafe fn read_dmabuf(datas: &[ffi::spa_data], modifier: u64) -> Option<DmaBuf> {
+    let mut planes = Vec::with_capacity(datas.len());
+    for data in datas {
+        let chunk = unsafe { data.chunk.as_ref()? };
+        if data.fd < 0 || chunk.flags & ffi::SPA_CHUNK_FLAG_CORRUPTED != 0 {
+            return None;
+        }
+        let fd = unsafe { BorrowedFd::borrow_raw(data.fd as RawFd) };
+        planes.push(DmaBufPlane {
+            fd: fd.try_clone_to_owned().ok()?,
+            offset: chunk.offset,
+            stride: chunk.stride.max(0) as u32,
+        });
+    }
+    Some(DmaBuf { modifier, planes })
+}
+
+/// The data and size of a meta, for metas with trailing payloads.
+///
+/// # Safety
+/// `spa_buffer` must be dequeued.
+unsafe fn find_meta_raw(spa_buffer: &ffi::spa_buffer, type_: u32) -> Option<(*const u8, usize)> {
+    if spa_buffer.metas.is_null() {
+        return None;
+    }
+    let metas =
+        unsafe { std::slice::from_raw_parts(spa_buffer.metas, spa_buffer.n_metas as usize) };
+    metas
+        .iter()
+        .find(|meta| meta.type_ == type_ && !meta.data.is_null())
+        .map(|meta| (meta.data.cast::<u8>().cast_const(), meta.size as usize))
+}
+
+/// The regions of the damage meta up to the first empty one.
+///
+/// # Safety
+/// The metas of `spa_buffer` must be valid.
+unsafe fn read_damage(spa_buffer: &ffi::spa_buffer) -> Option<Vec<Rect>> {
+    let (meta, meta_size) = unsafe { find_meta_raw(spa_buffer, ffi::SPA_META_VideoDamage)? };
+    let regions = unsafe {
+        std::slice::from_raw_parts(
+            meta.cast::<ffi::spa_meta_region>(),
+            meta_size / size_of::<ffi::spa_meta_region>(),
+        )
+    };
+    Some(
+        regions
+            .iter()
+            .map(|meta| &meta.region)
+            .take_while(|region| region.size.width > 0 && region.size.height > 0)
+            .map(|region| {
+                Rect::new(
+                    region.position.x,
+                    region.position.y,
+                    region.size.width,
+                    region.size.height,
+                )
+            })
+            .collect(),
+    )
+}
+
+/// # Safety
+/// `T` must be the layout of the meta registered as `type_`.
+unsafe fn find_meta<T>(spa_buffer: &ffi::spa_buffer, type_: u32) -> Option<&T> {
+    if spa_buffer.metas.is_null() {
+        return None;
+    }
+    let metas =
+        unsafe { std::slice::from_raw_parts(spa_buffer.metas, spa_buffer.n_metas as usize) };
+    metas
+        .iter()
+        .find(|meta| meta.type_ == type_ && meta.size as usize >= size_of::<T>())
+        .and_then(|meta| unsafe { meta.data.cast::<T>().as_ref() })
+}
+
+unsafe extern "C" fn on_param_changed(data: *mut c_void, id: u32, param: *const ffi::spa_pod) {
+    let data = unsafe { &mut *data.cast::<StreamData>() };
+    // the first callback running on the loop thread
+    sched::apply(&data.options);
+    if id != ffi::SPA_PARAM_Format || param.is_null() {
+        return;
+    }
+    let format = unsafe { Value::from_raw(param) }
+        .as_ref()
+        .and_then(params::parse_video_format);
+    let previous = data.format;
+    data.format = format;
+    let Some(format) = format else {
+        return;
+    };
+    if previous != Some(format) {
+        data.events.send(ScreencastEvent::FormatChanged {
+            info: StreamInfo {
+                node_id: data.node_id,
+                format,
+            },
+        });
+    }
+    if let Some(previous) = previous
+        && (previous.width, previous.height) != (format.width, format.height)
+    {
+        data.events.send(ScreencastEvent::Resized {
+            node_id: data.node_id,
+            width: format.width,
+            height: format.height,
+        });
+    }
+    let params = params::buffer_params(&format, &data.options);
+    let mut pointers: Vec<_> = params.iter().map(|param| param.as_ptr()).collect();
+    unsafe {
+        ffi::pw_stream_update_params(data.stream, pointers.as_mut_ptr(), pointers.len() as u32)
+    };
+}
+
+unsafe extern "C" fn on_add_buffer(_data: *mut c_void, buffer: *mut ffi::pw_buffer) {
+    let buffer = unsafe { &mut *buffer };
+    let Some(spa_buffer) = (unsafe { buffer.buffer.as_ref() }) else {
+        return;
+    };
+    if spa_buffer.n_datas == 0 {
+        return;
+    }
+    let datas =
+        unsafe { std::slice::from_raw_parts(spa_buffer.datas, spa_buffer.n_datas as usize) };
+    if datas.iter().all(|data| data.type_ != ffi::SPA_DATA_MemFd) {
+        return;
+    }
+    let mappings: Vec<Option<Mmap>> = datas
+        .iter()
+        .map(|data| {
+            if data.type_ != ffi::SPA_DATA_MemFd || data.fd < 0 {
+                return None;
+            }
+            let fd = unsafe { BorrowedFd::borrow_raw(data.fd as RawFd) };
+            Mmap::map(fd, data.mapoffset as u64, data.maxsize as usize).ok()
+        })
+        .collect();
+    buffer.user_data = Box::into_raw(Box::new(mappings)).cast();
+}
+
+unsafe extern "C" fn on_remove_buffer(_data: *mut c_void, buffer: *mut ffi::pw_buffer) {
+    let buffer = unsafe { &mut *buffer };
+    if !buffer.user_data.is_null() {
+        drop(unsafe { Box::from_raw(buffer.user_data.cast::<Vec<Option<Mmap>>>()) });
+        buffer.user_data = ptr::null_mut();
+    }
+}
+
+unsafe extern "C" fn on_process(data: *mut c_void) {
+    let data = unsafe { &mut *data.cast::<StreamData>() };
+    loop {
+        let buffer = unsafe { ffi::pw_stream_dequeue_buffer(data.stream) };
+        if buffer.is_null() {
+            break;
+        }
+        if let Some(frame) = unsafe { data.read_frame(&*buffer) } {
+            data.stats.received(&frame);
+            if !data.limiter.accept(frame.pts().unwrap_or_default()) {
+                data.stats.dropped();
+            } else {
+                match &data.output {
+                    Output::Queue(sender) => {
+                        if !sender.send(frame.into_owned()) {
+                            data.stats.dropped();
+                        }
+                    }
+                    Output::Callback(callback) => {
+                        data.stats.delivered(&frame);
+                        (callback.lock().unwrap())(frame);
+                    }
+                }
+            }
+        }
+        unsafe { ffi::pw_stream_queue_buffer(data.stream, buffer) };
+    }
+}
@@ -0,0 +1,604 @@
+use crate::audio::{AudioMix, AudioSource, AudioTrack};
+use crate::encode::EncoderConfig;
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime;
+use crate::sink::{self, MkvFile, Mp4File, SinkHandle};
+use event_listener::Event;
+use futures_core::Stream;
+use futures_lite::{StreamExt, future};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Records a stream of frames into sinks on a task of its own, like
+/// `sink::forward`, and can be paused.
+///
+/// Frames that arrive while paused are dropped and the later ones moved back
+/// in time, so the recording goes on from where it was paused instead of
+/// showing a frozen frame for the length of the pause. Dropping the
+/// recorder stops it as `stop` does, without waiting for the sinks.
+///
+/// Audio tracks given the `Pauses` of the recorder, see `with_pauses`, leave
+/// out the same time and stay in sync with the video.
+#[derive(Debug)]
+pub struct Recorder {
+    control: Arc<Control>,
+    markers: Markers,
+    task: JoinHandle<Result<()>>,
+}
+
+#[derive(Debug, Default)]
+struct Control {
+    paused: AtomicBool,
+    // pauses so far, also those no frame came during
+    pause_count: AtomicU64,
+    stopped: AtomicBool,
+    event: Event,
+    // from the first frame written to the last one
+    position: Mutex<Duration>,
+    // end of the last frame written, on the clock of the capture
+    end: Mutex<Option<Duration>>,
+    pauses: Mutex<Pauses>,
+}
+
+/// A moment of a recording picked out with `Recorder::add_marker`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Marker {
+    /// From the start of the recording, pauses left out.
+    pub time: Duration,
+    pub label: String,
+}
+
+/// What a recording is, stored in its container by `MkvFile` and `Mp4File`,
+/// see their `with_metadata`: in the segment info and tags of Matroska and
+/// the iTunes style metadata of MP4, where `tags` become freeform items.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+    /// The application that recorded, rather than this library.
+    pub application: Option<String>,
+    pub tags: Vec<(String, String)>,
+}
+
+/// The markers of a recording, shared by the recorder that sets them and
+/// the sinks that write them: chapters in `MkvFile` and `Mp4File`, see
+/// their `with_markers`, or a file of their own with `save`.
+#[derive(Debug, Clone, Default)]
+pub struct Markers {
+    inner: Arc<Mutex<Vec<Marker>>>,
+}
+
+/// The pauses of a recording, shared by the recorder that makes them and
+/// the audio tracks that leave them out, see `AudioTrack::with_pauses`.
+///
+/// A pause goes from the end of the last frame before it to the first frame
+/// after, on the clock of the capture: audio within is dropped, and audio
+/// after moved back by the length of the pauses before, like the frames.
+#[derive(Debug, Clone, Default)]
+pub struct Pauses {
+    inner: Arc<Mutex<PauseSpans>>,
+}
+
+#[derive(Debug, Default)]
+struct PauseSpans {
+    ended: Vec<Pause>,
+    // start of the pause going on
+    open: Option<Duration>,
+}
+
+#[derive(Debug)]
+struct Pause {
+    start: Duration,
+    end: Duration,
+    // of this pause and all before it
+    offset: Duration,
+}
+
+/// How the video and audio of a recording are split up, see `create`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutputLayout {
+    /// One file with the video and the audio mixed into one track.
+    #[default]
+    Single,
+    /// One file with the video and a track for each audio input, so an
+    /// editor can adjust the narration on its own.
+    Tracks,
+    /// The video alone and each audio input in a Matroska file of its own
+    /// next to it, named after the input: `recording.mkv` and
+    /// `recording.microphone.mka`. The files start and end together.
+    Files,
+}
+
+/// Moves the timestamps of the frames after a pause back by its length.
+#[derive(Debug)]
+struct Timeline {
+    offset: Duration,
+    // pts as written of the first frame
+    first: Option<Duration>,
+    // pts as captured of the last frame written
+    last: Option<Duration>,
+    interval: Duration,
+    paused: bool,
+}
+
+impl Recorder {
+    /// Starts writing `frames` to `sinks`. The recording ends with `stop`,
+    /// once the frames end or a sink fails. `OutputLayout::create` makes the
+    /// sinks of a recording with audio.
+    pub fn start<S>(frames: S, sinks: Vec<SinkHandle>) -> Self
+    where
+        S: Stream<Item = Frame<'static>> + Send + Unpin + 'static,
+    {
+        let control = Arc::new(Control::default());
+        let task = runtime::handle().spawn(record(frames, sinks, control.clone()));
+        Recorder {
+            control,
+            markers: Markers::default(),
+            task,
+        }
+    }
+
+    /// Sets the markers to those given to the sinks.
+    pub fn with_markers(mut self, markers: Markers) -> Self {
+        self.markers = markers;
+        self
+    }
+
+    pub fn markers(&self) -> &Markers {
+        &self.markers
+    }
+
+    /// Records the pauses into `pauses`, those given to the audio tracks.
+    pub fn with_pauses(self, pauses: Pauses) -> Self {
+        *self.control.pauses.lock().unwrap() = pauses;
+        self
+    }
+
+    /// Marks the moment of the last frame written, returns its time in the
+    /// recording.
+    pub fn add_marker(&self, label: impl Into<String>) -> Duration {
+        let time = *self.control.position.lock().unwrap();
+        self.markers.add(Marker {
+            time,
+            label: label.into(),
+        });
+        time
+    }
+
+    /// Drops the frames from now on until `resume`. The pause starts at
+    /// the end of the last frame written, also when the screen stays still
+    /// and no frame comes until `resume`.
+    pub fn pause(&self) {
+        if self.control.paused.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        if let Some(end) = *self.control.end.lock().unwrap() {
+            self.control.pauses.lock().unwrap().begin(end);
+        }
+        self.control.pause_count.fetch_add(1, Ordering::AcqRel);
+    }
+
+    pub fn resume(&self) {
+        self.control.paused.store(false, Ordering::Release);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.paused.load(Ordering::Acquire)
+    }
+
+    /// Whether the recording ended, also without `stop`.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Ends the recording, lets the sinks write their queued frames and
+    /// finish, and returns the first error of any of them.
+    pub async fn stop(mut self) -> Result<()> {
+        self.control.stop();
+        (&mut self.task)
+            .await
+            .map_err(|_| ScreencastError::SinkClosed)?
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        self.control.stop();
+    }
+}
+
+impl Control {
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+        self.event.notify(usize::MAX);
+    }
+
+    async fn stopped(&self) {
+        loop {
+            if self.stopped.load(Ordering::Acquire) {
+                return;
+            }
+            let listener = self.event.listen();
+            if self.stopped.load(Ordering::Acquire) {
+                return;
+            }
+            listener.await;
+        }
+    }
+}
+
+async fn record<S>(mut frames: S, sinks: Vec<SinkHandle>, control: Arc<Control>) -> Result<()>
+where
+    S: Stream<Item = Frame<'static>> + Unpin,
+{
+    let mut timeline = Timeline {
+        offset: Duration::ZERO,
+        first: None,
+        last: None,
+        interval: Duration::from_millis(33),
+        paused: false,
+    };
+    let mut pause_count = 0;
+    loop {
+        let next = future::or(frames.next(), async {
+            control.stopped().await;
+            None
+        });
+        let Some(frame) = next.await else {
+            break;
+        };
+        let pauses = control.pauses.lock().unwrap().clone();
+        // a pause since the last frame, maybe over before this one came
+        let count = control.pause_count.load(Ordering::Acquire);
+        if count != pause_count {
+            timeline.pause(&pauses);
+            pause_count = count;
+        }
+        if control.paused.load(Ordering::Acquire) {
+            timeline.pause(&pauses);
+            continue;
+        }
+        let frame = timeline.shift(frame, &pauses);
+        if let (Some(first), Some(pts)) = (timeline.first, frame.pts()) {
+            *control.position.lock().unwrap() = pts.saturating_sub(first);
+        }
+        *control.end.lock().unwrap() = timeline.end();
+        if sink::send_all(&sinks, frame).await.is_err() {
+            break;
+        }
+    }
+    sink::finish_all(sinks).await
+}
+
+impl Timeline {
+    fn pause(&mut self, pauses: &Pauses) {
+        if let (false, Some(end)) = (self.paused, self.end()) {
+            pauses.begin(end);
+        }
+        self.paused = true;
+    }
+
+    /// The end of the last frame written, as captured.
+    fn end(&self) -> Option<Duration> {
+        self.last.map(|last| last + self.interval)
+    }
+
+    fn shift(&mut self, frame: Frame<'static>, pauses: &Pauses) -> Frame<'static> {
+        let Some(pts) = frame.pts() else {
+            return frame;
+        };
+        if let Some(last) = self.last {
+            match self.paused {
+                // the first frame after the pause follows the last one before
+                true => {
+                    self.offset += pts.saturating_sub(last).saturating_sub(self.interval);
+                    pauses.end(pts, self.offset);
+                }
+                false if pts > last => {
+                    self.interval = (pts - last).min(Duration::from_secs(1));
+                }
+                false => {}
+            }
+        }
+        self.paused = false;
+        self.last = Some(pts);
+        let pts = pts.saturating_sub(self.offset);
+        self.first.get_or_insert(pts);
+        frame.with_pts(pts)
+    }
+}
+
+impl Pauses {
+    fn begin(&self, start: Duration) {
+        self.inner.lock().unwrap().open.get_or_insert(start);
+    }
+
+    fn end(&self, end: Duration, offset: Duration) {
+        let mut spans = self.inner.lock().unwrap();
+        if let Some(start) = spans.open.take() {
+            spans.ended.push(Pause { start, end, offset });
+        }
+    }
+
+    /// The time in the recording of `pts` as captured, `None` within a
+    /// pause.
+    pub(crate) fn shift(&self, pts: Duration) -> Option<Duration> {
+        let spans = self.inner.lock().unwrap();
+        if spans.open.is_some_and(|start| pts >= start) {
+            return None;
+        }
+        let before = spans.ended.iter().rev().find(|pause| pause.start <= pts);
+        match before {
+            Some(pause) if pts < pause.end => None,
+            Some(pause) => Some(pts.saturating_sub(pause.offset)),
+            None => Some(pts),
+        }
+    }
+}
+
+impl OutputLayout {
+    /// How the inputs are mixed for the layout, for `AudioTrack::spawn_all`.
+    pub fn mix(self) -> AudioMix {
+        match self {
+            OutputLayout::Single => AudioMix::Mixed,
+            OutputLayout::Tracks | OutputLayout::Files => AudioMix::Separate,
+        }
+    }
+
+    /// The sinks writing the video of `config` and the `audio` tracks to
+    /// `path` and the files next to it, for `Recorder::start`: MP4 for a
+    /// path ending in `.mp4`, WebM for `.webm` and Matroska otherwise.
+    pub async fn create(
+        self,
+        path: impl AsRef<Path>,
+        config: EncoderConfig,
+        audio: Vec<AudioTrack>,
+    ) -> Result<Vec<SinkHandle>> {
+        let path = path.as_ref();
+        let (video_audio, separate) = match self {
+            OutputLayout::Single | OutputLayout::Tracks => (audio, Vec::new()),
+            OutputLayout::Files => (Vec::new(), audio),
+        };
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let video = match extension {
+            Some("mp4") => {
+                let file = Mp4File::create(path, config).await?;
+                SinkHandle::spawn(video_audio.into_iter().fold(file, Mp4File::with_audio))
+            }
+            Some("webm") => {
+                let file = MkvFile::create_webm(path, config).await?;
+                SinkHandle::spawn(add_audio(file, video_audio)?)
+            }
+            _ => {
+                let file = MkvFile::create(path, config).await?;
+                SinkHandle::spawn(add_audio(file, video_audio)?)
+            }
+        };
+        let mut sinks = vec![video];
+        for (index, audio) in separate.into_iter().enumerate() {
+            let file = MkvFile::create_audio(audio_path(path, &audio, index), audio).await?;
+            sinks.push(SinkHandle::spawn(file));
+        }
+        Ok(sinks)
+    }
+}
+
+fn add_audio(file: MkvFile, audio: Vec<AudioTrack>) -> Result<MkvFile> {
+    audio.into_iter().try_fold(file, MkvFile::with_audio)
+}
+
+/// `recording.mkv` to `recording.microphone.mka`.
+fn audio_path(path: &Path, audio: &AudioTrack, index: usize) -> PathBuf {
+    let name = match audio.source() {
+        Some(AudioSource::Desktop) => "desktop".to_string(),
+        Some(AudioSource::Microphone) => "microphone".to_string(),
+        Some(AudioSource::Application(name)) => name.replace(['/', '\\'], "-"),
+        None if index == 0 => "audio".to_string(),
+        None => format!("audio{}", index + 1),
+    };
+    path.with_extension(format!("{}.mka", name))
+}
+
+impl Metadata {
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn with_author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
+    pub fn with_application(mut self, application: impl Into<String>) -> Self {
+        self.application = Some(application.into());
+        self
+    }
+
+    pub fn with_tag(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.tags.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl Markers {
+    pub fn add(&self, marker: Marker) {
+        let mut markers = self.inner.lock().unwrap();
+        // kept in order of time
+        let index = markers.partition_point(|other| other.time <= marker.time);
+        markers.insert(index, marker);
+    }
+
+    pub fn list(&self) -> Vec<Marker> {
+        self.inner.lock().unwrap().clone()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inner.lock().unwrap().is_empty()
+    }
+
+    /// Writes the markers as ffmpeg metadata, one chapter from each to the
+    /// next, which `ffmpeg -i recording.mp4 -i markers.txt -map_metadata 1
+    /// -codec copy` adds to a file. `duration` ends the last one.
+    pub async fn save(&self, path: impl AsRef<Path>, duration: Duration) -> Result<()> {
+        let markers = self.list();
+        let mut out = String::from(";FFMETADATA1\n");
+        let ends = markers.iter().skip(1).map(|marker| marker.time);
+        for (marker, end) in markers.iter().zip(ends.chain([duration])) {
+            let escaped: String = marker
+                .label
+                .chars()
+                .flat_map(|c| match c {
+                    '=' | ';' | '#' | '\\' | '\n' => vec!['\\', c],
+                    c => vec![c],
+                })
+                .collect();
+            let _ = write!(
+                out,
+                "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+                marker.time.as_millis(),
+                end.max(marker.time).as_millis(),
+                escaped
+            );
+        }
+        runtime::compat(tokio::fs::write(path.as_ref(), out)).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioCodec, AudioConfig, AudioPacket};
+    use crate::format::PixelFormat;
+
+    fn frame(millis: u64) -> Frame<'static> {
+        Frame::packed(PixelFormat::Bgra, 1, 1, 4, vec![0; 4])
+            .with_pts(Duration::from_millis(millis))
+    }
+
+    fn packet(millis: u64) -> AudioPacket {
+        AudioPacket {
+            codec: AudioCodec::Opus,
+            data: vec![0],
+            pts: Duration::from_millis(millis),
+            duration: Duration::from_millis(20),
+        }
+    }
+
+    #[test]
+    fn pause_shifts_audio_with_video() {
+        let pauses = Pauses::default();
+        let mut timeline = Timeline {
+            offset: Duration::ZERO,
+            first: None,
+            last: None,
+            interval: Duration::from_millis(33),
+            paused: false,
+        };
+        let captured = (0..10).map(|index| packet(index * 20)).chain([
+            packet(400),
+            packet(980),
+            packet(1000),
+            packet(1020),
+        ]);
+        let mut audio = AudioTrack::from_packets(AudioConfig::default(), captured.collect())
+            .with_pauses(pauses.clone());
+
+        let mut video = Vec::new();
+        for millis in [0, 33, 66, 99] {
+            video.push(timeline.shift(frame(millis), &pauses).pts().unwrap());
+        }
+        // the frames at 132, 500 and 900 ms come while paused and are
+        // dropped, the pause lasts from 132 ms to the frame at 1000 ms
+        for _ in 0..3 {
+            timeline.pause(&pauses);
+        }
+        video.push(timeline.shift(frame(1000), &pauses).pts().unwrap());
+        video.push(timeline.shift(frame(1033), &pauses).pts().unwrap());
+
+        let millis = |times: &[Duration]| times.iter().map(|t| t.as_millis()).collect::<Vec<_>>();
+        assert_eq!(millis(&video), [0, 33, 66, 99, 132, 165]);
+        let written: Vec<_> = audio
+            .ready(Duration::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|packet| packet.pts)
+            .collect();
+        // the audio within the pause is gone, the audio at the first frame
+        // after it plays with that frame
+        assert_eq!(millis(&written), [0, 20, 40, 60, 80, 100, 120, 132, 152]);
+    }
+
+    #[derive(Debug)]
+    struct Collect(Arc<Mutex<Vec<Duration>>>);
+
+    impl sink::FrameSink for Collect {
+        async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+            self.0.lock().unwrap().extend(frame.pts());
+            Ok(())
+        }
+
+        async fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_without_frames() {
+        let (sender, frames) = async_broadcast::broadcast(8);
+        let written = Arc::new(Mutex::new(Vec::new()));
+        let pauses = Pauses::default();
+        let recorder = Recorder::start(frames, vec![SinkHandle::spawn(Collect(written.clone()))])
+            .with_pauses(pauses.clone());
+        let wait_for = |count: usize| {
+            let written = written.clone();
+            async move {
+                while written.lock().unwrap().len() < count {
+                    tokio::task::yield_now().await;
+                }
+            }
+        };
+        for millis in [0, 33] {
+            sender.broadcast(frame(millis)).await.unwrap();
+        }
+        wait_for(2).await;
+
+        // a still screen, no frame comes while paused
+        recorder.pause();
+        assert_eq!(pauses.shift(Duration::from_millis(500)), None);
+        recorder.resume();
+        for millis in [1000, 1033] {
+            sender.broadcast(frame(millis)).await.unwrap();
+        }
+        wait_for(4).await;
+        recorder.stop().await.unwrap();
+
+        let millis: Vec<u128> = written
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|t| t.as_millis())
+            .collect();
+        assert_eq!(millis, [0, 33, 66, 99]);
+        // the audio from the end of the frame at 33 ms to the one at 1000 ms
+        // is left out
+        assert_eq!(
+            pauses.shift(Duration::from_millis(60)),
+            Some(Duration::from_millis(60))
+        );
+        assert_eq!(pauses.shift(Duration::from_millis(700)), None);
+        assert_eq!(
+            pauses.shift(Duration::from_millis(1020)),
+            Some(Duration::from_millis(86))
+        );
+    }
+}
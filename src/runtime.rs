@@ -0,0 +1,46 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use std::task::{Context, Poll};
+use tokio::runtime::{Handle, Runtime};
+
+/// Runtime driving the dbus connections when the caller isn't on tokio.
+pub(crate) fn runtime() -> &'static Runtime {
+    static RUNTIME: OnceLock<Runtime> = OnceLock::new();
+    RUNTIME.get_or_init(|| {
+        tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("xdp-screencast")
+            .enable_all()
+            .build()
+            .expect("fail to build xdp-screencast runtime")
+    })
+}
+
+pub(crate) fn handle() -> Handle {
+    Handle::try_current().unwrap_or_else(|_| runtime().handle().clone())
+}
+
+/// Polls `future` inside a tokio context so it can be awaited from any executor.
+pub(crate) fn compat<F: Future>(future: F) -> Compat<F> {
+    Compat {
+        handle: handle(),
+        future,
+    }
+}
+
+pub(crate) struct Compat<F> {
+    handle: Handle,
+    future: F,
+}
+
+impl<F: Future> Future for Compat<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // SAFETY: `future` is never moved out of the pinned `Compat`
+        let this = unsafe { self.get_unchecked_mut() };
+        let _guard = this.handle.enter();
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
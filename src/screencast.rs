@@ -1,123 +1,529 @@
+use crate::cancel::CancelHandle;
+use crate::error::{Result, ScreencastError};
+use crate::events::{CloseReason, EventSender, EventStream, ScreencastEvent};
+use crate::runtime::compat;
+use crate::session::ActiveSession;
+use crate::tokens::TokenStore;
 use bitflags::bitflags;
 use std::collections::HashMap;
-use std::os::fd::{AsRawFd, RawFd};
+use std::fmt::{Display, Formatter};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
 use zbus::export::ordered_stream::OrderedStreamExt;
 use zbus::zvariant::{ObjectPath, OwnedFd, OwnedObjectPath, Value};
-use zbus::{proxy, zvariant, Connection, Error, Result};
+use zbus::{Connection, proxy, zvariant};
 
 #[derive(Debug, Default)]
-pub struct ScreenCast<'a> {
+pub struct ScreenCast {
     pub cursor_mode: CursorMode,
     pub source_type: SourceType,
     pub persist_mode: PersistMode,
     pub multiple_source: bool,
+    pub parent_window: Option<WindowIdentifier>,
+    pub timeouts: Timeouts,
+    pub capability_policy: CapabilityPolicy,
 
     dbus_name: String,
+    restore_token: Option<String>,
+    version: Option<u32>,
+    ignored_options: Vec<IgnoredOption>,
+    downgrades: Vec<Downgrade>,
     selected_sources: Vec<SelectedSource>,
     connection: Option<Connection>,
-    screencast_proxy: Option<ZBusScreencastProxy<'a>>,
-    response_stream: Option<ResponseStream>,
+    screencast_proxy: Option<ZBusScreencastProxy<'static>>,
+    session_proxy: Option<ZBusSessionProxy<'static>>,
     session: OwnedObjectPath,
-    counter: usize,
+    cancel: CancelHandle,
+    events: EventSender,
 }
 
-impl<'a> ScreenCast<'a> {
-    pub fn get_selected_sources(&self) -> &[SelectedSource] {
-        &self.selected_sources
+impl ScreenCast {
+    pub fn with_options(options: ScreencastOptions) -> Self {
+        let mut screencast = ScreenCast::default();
+        screencast.set_options(options);
+        screencast
+    }
+
+    pub fn options(&self) -> ScreencastOptions {
+        ScreencastOptions {
+            cursor_mode: self.cursor_mode,
+            source_types: self.source_type,
+            persist_mode: self.persist_mode,
+            multiple: self.multiple_source,
+            restore_token: self.restore_token.clone(),
+        }
+    }
+
+    pub fn set_options(&mut self, options: ScreencastOptions) {
+        self.cursor_mode = options.cursor_mode;
+        self.source_type = options.source_types;
+        self.persist_mode = options.persist_mode;
+        self.multiple_source = options.multiple;
+        self.restore_token = options.restore_token;
+    }
+
+    pub fn restore_token(&self) -> Option<&str> {
+        self.restore_token.as_deref()
+    }
+
+    pub fn set_restore_token(&mut self, restore_token: Option<String>) {
+        self.restore_token = restore_token;
+    }
+
+    pub fn ignored_options(&self) -> &[IgnoredOption] {
+        &self.ignored_options
+    }
+
+    pub fn downgrades(&self) -> &[Downgrade] {
+        &self.downgrades
     }
 
-    pub async fn screencast(&mut self) -> Result<RawFd> {
-        if self.connection.is_none() {
+    /// The session bus connection, `None` until `connect()` or the first portal call.
+    pub fn connection(&self) -> Option<&Connection> {
+        self.connection.as_ref()
+    }
+
+    /// The raw ScreenCast portal proxy, for calls this crate doesn't model.
+    pub fn screencast_proxy(&self) -> Option<&ZBusScreencastProxy<'static>> {
+        self.screencast_proxy.as_ref()
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    pub fn events(&self) -> EventStream {
+        self.events.subscribe()
+    }
+
+    pub async fn connect(&mut self) -> Result<()> {
+        compat(async {
+            if self.connection.is_some() {
+                return Ok(());
+            }
             let connection = Connection::session().await?;
 
             let dbus_name = connection
                 .unique_name()
-                .ok_or(Error::Names(zbus::names::Error::InvalidName("None")))
-                .map(|n| n.to_string().replace(":", ""))
-                .map(|n| n.replace(".", "_"))?;
+                .ok_or(ScreencastError::InvalidResponse(
+                    "connection: fail to get unique name".to_string(),
+                ))
+                .map(|n| sender_path_segment(n.as_str()))?;
             self.dbus_name = dbus_name;
 
             let screencast_proxy = ZBusScreencastProxy::new(&connection).await?;
             self.screencast_proxy = Some(screencast_proxy);
 
             self.connection = Some(connection);
+            Ok(())
+        })
+        .await
+    }
+
+    pub async fn version(&mut self) -> Result<u32> {
+        compat(async {
+            if let Some(version) = self.version {
+                return Ok(version);
+            }
+            self.connect().await?;
+            let version = self.screencast_proxy.as_ref().unwrap().version().await?;
+            self.version = Some(version);
+            Ok(version)
+        })
+        .await
+    }
+
+    pub async fn available_cursor_modes(&mut self) -> Result<CursorModes> {
+        compat(async {
+            if self.version().await? < IgnoredOption::CursorMode.min_version() {
+                return Ok(CursorModes::empty());
+            }
+            let bits = self
+                .screencast_proxy
+                .as_ref()
+                .unwrap()
+                .available_cursor_modes()
+                .await?;
+            Ok(CursorModes::from_bits_truncate(bits))
+        })
+        .await
+    }
+
+    pub async fn available_source_types(&mut self) -> Result<SourceType> {
+        compat(async {
+            self.connect().await?;
+            let bits = self
+                .screencast_proxy
+                .as_ref()
+                .unwrap()
+                .available_source_types()
+                .await?;
+            Ok(SourceType::from_bits_truncate(bits))
+        })
+        .await
+    }
+
+    pub async fn capabilities(&mut self) -> Result<Capabilities> {
+        Ok(Capabilities {
+            version: self.version().await?,
+            cursor_modes: self.available_cursor_modes().await?,
+            source_types: self.available_source_types().await?,
+        })
+    }
+
+    pub async fn screencast(&mut self) -> Result<ActiveSession> {
+        compat(async {
+            self.cancel.reset();
+            self.connect().await?;
+
+            let fd = match self.negotiate().await {
+                Ok(fd) => fd,
+                Err(err) => {
+                    if let Some(session_proxy) = self.session_proxy.take() {
+                        let _ = session_proxy.close().await;
+                        let reason = match err {
+                            ScreencastError::UserCancelled => CloseReason::Cancelled,
+                            _ => CloseReason::Failed,
+                        };
+                        self.events.send(ScreencastEvent::SessionClosed { reason });
+                    }
+                    return Err(err);
+                }
+            };
+
+            Ok(ActiveSession::new(self.detach(), fd))
+        })
+        .await
+    }
+
+    pub async fn screencast_with_profile(&mut self, profile: &str) -> Result<ActiveSession> {
+        let store = TokenStore::new()?;
+        if let PersistMode::DoNotPersist = self.persist_mode {
+            self.persist_mode = PersistMode::UntilRevoked;
         }
-        self.counter += 1;
+        if self.restore_token.is_none() {
+            self.restore_token = store.load(profile)?;
+        }
+        let session = self.screencast().await?;
+        if let Some(restore_token) = self.restore_token.as_deref() {
+            store.save(profile, restore_token)?;
+        }
+        Ok(session)
+    }
 
-        let request_proxy = ZBusRequestProxy::builder(self.connection.as_ref().unwrap())
-            .path(format!(
-                "/org/freedesktop/portal/desktop/request/{}/{}",
-                self.dbus_name, self.counter
-            ))?
-            .build()
-            .await?;
-        let response_stream = request_proxy.receive_response().await?;
-        self.response_stream = Some(response_stream);
+    pub async fn shutdown(mut self) -> Result<()> {
+        compat(async { self.close().await }).await
+    }
+
+    pub(crate) async fn close(&mut self) -> Result<()> {
+        let request_closed = self.cancel.close_pending().await;
+        let (session_proxy, connection) = self.take_connection();
+        let session_closed = match session_proxy {
+            Some(session_proxy) => session_proxy.close().await,
+            None => Ok(()),
+        };
+        if let Some(connection) = connection {
+            connection.close().await?;
+        }
+        request_closed?;
+        Ok(session_closed?)
+    }
+
+    pub(crate) fn session_handle(&self) -> &OwnedObjectPath {
+        &self.session
+    }
 
+    pub(crate) fn selected_sources(&self) -> &[SelectedSource] {
+        &self.selected_sources
+    }
+
+    pub(crate) fn session_proxy(&self) -> Option<&ZBusSessionProxy<'static>> {
+        self.session_proxy.as_ref()
+    }
+
+    pub(crate) fn event_sender(&self) -> &EventSender {
+        &self.events
+    }
+
+    pub(crate) fn take_connection(
+        &mut self,
+    ) -> (Option<ZBusSessionProxy<'static>>, Option<Connection>) {
+        self.screencast_proxy = None;
+        (self.session_proxy.take(), self.connection.take())
+    }
+
+    /// Negotiates a session of its own for the new selection, the portal
+    /// takes no `SelectSources` once a session started. The old session is
+    /// closed once the new one started, and kept as it was when that fails.
+    pub(crate) async fn reselect(&mut self, options: ScreencastOptions) -> Result<OwnedFd> {
+        let Some(old_session_proxy) = self.session_proxy.take() else {
+            return Err(ScreencastError::SessionClosed);
+        };
+        let old_options = self.options();
+        let old_session = std::mem::take(&mut self.session);
+        let old_sources = std::mem::take(&mut self.selected_sources);
+        self.cancel.reset();
+        self.set_options(options);
+        match self.negotiate().await {
+            Ok(fd) => {
+                let _ = old_session_proxy.close().await;
+                Ok(fd)
+            }
+            Err(err) => {
+                if let Some(session_proxy) = self.session_proxy.take() {
+                    let _ = session_proxy.close().await;
+                }
+                self.set_options(old_options);
+                self.session = old_session;
+                self.selected_sources = old_sources;
+                self.session_proxy = Some(old_session_proxy);
+                Err(err)
+            }
+        }
+    }
+
+    pub(crate) async fn renegotiate(&mut self) -> Result<OwnedFd> {
+        let connection = self
+            .connection
+            .clone()
+            .ok_or(ScreencastError::SessionClosed)?;
+        // the previous session died with the old portal instance, and the
+        // cached version may not match the new one
+        self.session_proxy = None;
+        self.version = None;
+        self.screencast_proxy = Some(ZBusScreencastProxy::new(&connection).await?);
+        self.cancel.reset();
+        self.negotiate().await
+    }
+
+    fn detach(&mut self) -> ScreenCast {
+        ScreenCast {
+            cursor_mode: self.cursor_mode,
+            source_type: self.source_type,
+            persist_mode: self.persist_mode,
+            multiple_source: self.multiple_source,
+            parent_window: self.parent_window.clone(),
+            timeouts: self.timeouts,
+            dbus_name: std::mem::take(&mut self.dbus_name),
+            restore_token: self.restore_token.clone(),
+            version: self.version,
+            ignored_options: self.ignored_options.clone(),
+            capability_policy: self.capability_policy,
+            downgrades: self.downgrades.clone(),
+            selected_sources: std::mem::take(&mut self.selected_sources),
+            connection: self.connection.take(),
+            screencast_proxy: self.screencast_proxy.take(),
+            session_proxy: self.session_proxy.take(),
+            session: std::mem::take(&mut self.session),
+            cancel: CancelHandle::default(),
+            events: self.events.clone(),
+        }
+    }
+
+    async fn negotiate(&mut self) -> Result<OwnedFd> {
         self.create_session().await?;
+        self.events.send(ScreencastEvent::SessionCreated {
+            session_handle: self.session.clone(),
+        });
         self.prepare_select().await?;
+        self.events.send(ScreencastEvent::SourcesSelected);
         self.start_select().await?;
+        self.events.send(ScreencastEvent::Started {
+            streams: self.selected_sources.clone(),
+        });
         self.open_remote().await
     }
 
-    pub async fn shutdown(self) -> Result<()> {
-        if let Some(connection) = self.connection {
-            return connection.close().await;
+    async fn subscribe_response(&mut self) -> Result<(String, ResponseStream)> {
+        let handle_token = next_handle_token();
+        let request_path = request_path(&self.dbus_name, &handle_token)?;
+        let response_stream = self.receive_response(request_path.clone()).await?;
+        self.cancel
+            .set_pending(self.connection.as_ref().unwrap(), request_path);
+        Ok((handle_token, response_stream))
+    }
+
+    async fn receive_response(&self, request_path: OwnedObjectPath) -> Result<ResponseStream> {
+        let request_proxy = ZBusRequestProxy::builder(self.connection.as_ref().unwrap())
+            .path(request_path)?
+            .build()
+            .await?;
+        Ok(request_proxy.receive_response().await?)
+    }
+
+    async fn wait_response(
+        &self,
+        handle_token: &str,
+        returned_path: OwnedObjectPath,
+        mut response_stream: ResponseStream,
+        timeout: Option<Duration>,
+    ) -> Result<Response> {
+        // portals older than 0.9 return request handles that don't follow the
+        // documented SENDER/TOKEN layout, listen on the returned one instead
+        if returned_path != request_path(&self.dbus_name, handle_token)? {
+            self.cancel
+                .set_pending(self.connection.as_ref().unwrap(), returned_path.clone());
+            response_stream = self.receive_response(returned_path).await?;
         }
-        Ok(())
+        let response = futures_lite::future::or(
+            async {
+                response_stream
+                    .next()
+                    .await
+                    .ok_or(ScreencastError::SessionClosed)
+            },
+            async {
+                self.cancel.cancelled().await;
+                Err(ScreencastError::UserCancelled)
+            },
+        );
+        let response = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, response)
+                .await
+                .unwrap_or(Err(ScreencastError::Timeout)),
+            None => response.await,
+        };
+        if let Err(ScreencastError::Timeout) = response {
+            let _ = self.cancel.close_pending().await;
+        }
+        self.cancel.clear_pending();
+        response
     }
 
     async fn create_session(&mut self) -> Result<()> {
+        let (handle_token, response_stream) = self.subscribe_response().await?;
         let mut payload = HashMap::with_capacity(4);
-        let session_token_value = Value::new(self.counter.to_string());
+        let session_token_value = Value::new(handle_token.as_str());
         payload.insert("session_handle_token", &session_token_value);
-        let handle_token_value = Value::new(self.counter.to_string());
+        let handle_token_value = Value::new(handle_token.as_str());
         payload.insert("handle_token", &handle_token_value);
-        let _ = self
+        let request_path = self
             .screencast_proxy
             .as_ref()
             .unwrap()
             .create_session(&payload)
             .await?;
 
-        let response =
-            self.response_stream
-                .as_mut()
-                .unwrap()
-                .next()
-                .await
-                .ok_or(Error::Failure(
-                    "create session: fail to receive response".to_string(),
-                ))?;
+        let response = self
+            .wait_response(
+                &handle_token,
+                request_path,
+                response_stream,
+                self.timeouts.create_session,
+            )
+            .await?;
         let mut response = response.args()?;
+        check_response(response.response)?;
 
-        let session_handle = match response.response {
-            0 => response.results.remove("session_handle"),
-            _ => None,
-        };
-
-        let session = session_handle
-            .ok_or(Error::Failure(
+        let session = response
+            .results
+            .remove("session_handle")
+            .ok_or(ScreencastError::InvalidResponse(
                 "create session: fail to get session_handle".to_string(),
             ))
-            .and_then(|v| String::try_from(v).map_err(Error::Variant))
-            .and_then(|v| OwnedObjectPath::try_from(v).map_err(Error::Variant))?;
+            .and_then(|v| String::try_from(v).map_err(ScreencastError::from))
+            .and_then(|v| OwnedObjectPath::try_from(v).map_err(ScreencastError::from))?;
+        let session_proxy = ZBusSessionProxy::builder(self.connection.as_ref().unwrap())
+            .path(session.clone())?
+            .build()
+            .await?;
+        self.session_proxy = Some(session_proxy);
         self.session = session;
         Ok(())
     }
 
+    async fn validate_modes(
+        &mut self,
+        mut source_type: SourceType,
+    ) -> Result<(CursorMode, SourceType)> {
+        self.downgrades.clear();
+        let capabilities = self.capabilities().await?;
+        let mut cursor_mode = self.cursor_mode;
+        if capabilities.version >= IgnoredOption::CursorMode.min_version()
+            && !capabilities.supports_cursor_mode(cursor_mode)
+        {
+            let fallback = [CursorMode::Embedded, CursorMode::Hidden]
+                .into_iter()
+                .find(|mode| capabilities.supports_cursor_mode(*mode));
+            match (self.capability_policy, fallback) {
+                (CapabilityPolicy::Downgrade, Some(fallback)) => {
+                    self.downgrades.push(Downgrade::CursorMode {
+                        requested: cursor_mode,
+                        used: fallback,
+                    });
+                    cursor_mode = fallback;
+                }
+                _ => {
+                    return Err(ScreencastError::Unsupported(format!(
+                        "cursor mode {:?} is not supported, available: {:?}",
+                        cursor_mode, capabilities.cursor_modes
+                    )));
+                }
+            }
+        }
+        if !capabilities.supports_source_type(source_type) {
+            let supported = source_type & capabilities.source_types;
+            match self.capability_policy {
+                CapabilityPolicy::Downgrade if !supported.is_empty() => {
+                    self.downgrades.push(Downgrade::SourceType {
+                        requested: source_type,
+                        used: supported,
+                    });
+                    source_type = supported;
+                }
+                _ => {
+                    return Err(ScreencastError::Unsupported(format!(
+                        "source type {:?} is not supported, available: {:?}",
+                        source_type, capabilities.source_types
+                    )));
+                }
+            }
+        }
+        Ok((cursor_mode, source_type))
+    }
+
     async fn prepare_select(&mut self) -> Result<()> {
+        let version = self.version().await?;
+        self.ignored_options.clear();
+        // older portals don't list VIRTUAL among the available types, strip
+        // it before it fails the capability check
+        let mut source_type = self.source_type;
+        if source_type.contains(SourceType::VIRTUAL)
+            && version < IgnoredOption::VirtualSource.min_version()
+        {
+            source_type.remove(SourceType::VIRTUAL);
+            self.ignored_options.push(IgnoredOption::VirtualSource);
+        }
+        let (cursor_mode, source_type) = self.validate_modes(source_type).await?;
+        let (handle_token, response_stream) = self.subscribe_response().await?;
         let mut payload = HashMap::with_capacity(8);
-        let handle_token_value = Value::new(self.counter.to_string());
+        let handle_token_value = Value::new(handle_token.as_str());
         payload.insert("handle_token", &handle_token_value);
         let multiple_value = Value::Bool(self.multiple_source);
         payload.insert("multiple", &multiple_value);
-        let types_value = Value::U32(self.source_type.bits());
+        let types_value = Value::U32(source_type.bits());
         payload.insert("types", &types_value);
         let persist_value = Value::U32(self.persist_mode.to_u32());
-        payload.insert("persist_mode", &persist_value);
-        let cursor_value = Value::U32(self.cursor_mode.to_u32());
-        payload.insert("cursor_mode", &cursor_value);
+        if version >= IgnoredOption::PersistMode.min_version() {
+            payload.insert("persist_mode", &persist_value);
+        } else if !matches!(self.persist_mode, PersistMode::DoNotPersist) {
+            self.ignored_options.push(IgnoredOption::PersistMode);
+        }
+        let cursor_value = Value::U32(cursor_mode.to_u32());
+        if version >= IgnoredOption::CursorMode.min_version() {
+            payload.insert("cursor_mode", &cursor_value);
+        } else if !matches!(cursor_mode, CursorMode::Hidden) {
+            self.ignored_options.push(IgnoredOption::CursorMode);
+        }
+        let restore_token_value = self.restore_token.as_deref().map(Value::new);
+        if let Some(restore_token_value) = restore_token_value.as_ref() {
+            if version >= IgnoredOption::RestoreToken.min_version() {
+                payload.insert("restore_token", restore_token_value);
+            } else {
+                self.ignored_options.push(IgnoredOption::RestoreToken);
+            }
+        }
         let request_path = self
             .screencast_proxy
             .as_ref()
@@ -125,83 +531,147 @@ impl<'a> ScreenCast<'a> {
             .select_sources(&self.session, &payload)
             .await?;
 
-        let response =
-            self.response_stream
-                .as_mut()
-                .unwrap()
-                .next()
-                .await
-                .ok_or(Error::Failure(
-                    "select source: fail to receive response".to_string(),
-                ))?;
+        let response = self
+            .wait_response(
+                &handle_token,
+                request_path,
+                response_stream,
+                self.timeouts.select_sources,
+            )
+            .await?;
         let response = response.args()?;
-        if response.response != 0 {
-            return Err(Error::Failure(
-                "select source: fail to get select source".to_string(),
-            ));
-        }
-        Ok(())
+        check_response(response.response)
     }
 
     async fn start_select(&mut self) -> Result<()> {
+        let (handle_token, response_stream) = self.subscribe_response().await?;
+        let parent_window = self
+            .parent_window
+            .as_ref()
+            .map(|w| w.to_string())
+            .unwrap_or_default();
         let mut payload = HashMap::with_capacity(1);
-        let handle_token_value = Value::new(self.counter.to_string());
+        let handle_token_value = Value::new(handle_token.as_str());
         payload.insert("handle_token", &handle_token_value);
-        let _ = self
+        let request_path = self
             .screencast_proxy
             .as_ref()
             .unwrap()
-            .start(&self.session, "", &payload)
+            .start(&self.session, &parent_window, &payload)
             .await?;
 
-        let response =
-            self.response_stream
-                .as_mut()
-                .unwrap()
-                .next()
-                .await
-                .ok_or(Error::Failure(
-                    "start select: fail to receive response".to_string(),
-                ))?;
+        let response = self
+            .wait_response(
+                &handle_token,
+                request_path,
+                response_stream,
+                self.timeouts.start,
+            )
+            .await?;
         let mut response = response.args()?;
-        let sources = match response.response {
-            0 => response.results.remove("streams"),
-            _ => None,
-        };
-        self.selected_sources = sources
-            .ok_or(Error::Failure(
+        check_response(response.response)?;
+        if let Some(restore_token) = response.results.remove("restore_token") {
+            self.restore_token = Some(String::try_from(restore_token)?);
+        }
+        self.selected_sources = response
+            .results
+            .remove("streams")
+            .ok_or(ScreencastError::InvalidResponse(
                 "start select: fail to get streams".to_string(),
             ))
-            .and_then(|v| Vec::try_from(v).map_err(Error::Variant))?;
+            .and_then(|v| Vec::try_from(v).map_err(ScreencastError::from))?;
         Ok(())
     }
 
-    async fn open_remote(&mut self) -> Result<RawFd> {
-        self.counter += 1;
+    async fn open_remote(&mut self) -> Result<OwnedFd> {
         let payload = HashMap::new();
         self.screencast_proxy
             .as_ref()
             .unwrap()
             .open_pipe_wire_remote(&self.session, &payload)
             .await
-            .map(|fd| fd.as_raw_fd())
+            .map_err(ScreencastError::from)
     }
 }
 
-#[derive(Debug)]
+pub(crate) fn next_handle_token() -> String {
+    static TOKEN_PREFIX: OnceLock<u32> = OnceLock::new();
+    static TOKEN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let prefix = TOKEN_PREFIX.get_or_init(|| fastrand::u32(..));
+    let counter = TOKEN_COUNTER.fetch_add(1, Ordering::Relaxed);
+    format!("xdp_screencast_{:08x}_{}", prefix, counter)
+}
+
+pub(crate) fn sender_path_segment(unique_name: &str) -> String {
+    unique_name.trim_start_matches(':').replace('.', "_")
+}
+
+pub(crate) fn request_path(sender: &str, handle_token: &str) -> Result<OwnedObjectPath> {
+    let path = format!(
+        "/org/freedesktop/portal/desktop/request/{}/{}",
+        sender, handle_token
+    );
+    Ok(OwnedObjectPath::try_from(path)?)
+}
+
+pub(crate) fn check_response(response: u32) -> Result<()> {
+    match response {
+        0 => Ok(()),
+        1 => Err(ScreencastError::UserCancelled),
+        2 => Err(ScreencastError::Interrupted),
+        _ => Err(ScreencastError::InvalidResponse(format!(
+            "portal request ended with response code {}",
+            response
+        ))),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SelectedSource {
-    pub node: u32,
-    pub width: Option<i32>,
-    pub height: Option<i32>,
-    pub type_: u32,
+    node: u32,
+    width: Option<i32>,
+    height: Option<i32>,
+    position: Option<(i32, i32)>,
+    mapping_id: Option<String>,
+    type_: u32,
 }
 
 impl SelectedSource {
+    pub fn node_id(&self) -> u32 {
+        self.node
+    }
+
+    pub fn size(&self) -> Option<(i32, i32)> {
+        self.width.zip(self.height)
+    }
+
+    pub fn position(&self) -> Option<(i32, i32)> {
+        self.position
+    }
+
+    pub fn mapping_id(&self) -> Option<&str> {
+        self.mapping_id.as_deref()
+    }
+
+    pub fn source_type(&self) -> SourceType {
+        SourceType::from_bits_truncate(self.type_)
+    }
+
+    /// Lets the session manager pick the node, for remotes exposing a single
+    /// kind of node such as the camera portal's.
+    #[cfg(feature = "pipewire")]
+    pub(crate) fn any() -> Self {
+        SelectedSource::new(u32::MAX, 0)
+    }
+
     fn new(id: u32, type_: u32) -> Self {
         SelectedSource {
             node: id,
             width: None,
             height: None,
+            position: None,
+            mapping_id: None,
             type_,
         }
     }
@@ -211,32 +681,45 @@ impl TryFrom<Value<'_>> for SelectedSource {
     type Error = zvariant::Error;
 
     fn try_from(value: Value<'_>) -> std::result::Result<Self, Self::Error> {
-        if let Value::Structure(source) = value {
-            if let [Value::U32(id), Value::Dict(source)] = source.fields() {
-                let source_type_key = Value::new("source_type");
-                let source_type: u32 =
-                    source
-                        .get(&source_type_key)?
-                        .ok_or(zvariant::Error::Message(
-                            "fail to get source_type".to_string(),
-                        ))?;
-                let mut result = SelectedSource::new(*id, source_type);
-                let source_size_key = Value::new("size");
-                if let Some(Value::Structure(size)) = source.get(&source_size_key)? {
-                    if let (Some(Value::I32(width)), Some(Value::I32(height))) =
-                        (size.fields().first(), size.fields().get(1))
-                    {
-                        result.width = Some(*width);
-                        result.height = Some(*height);
-                    }
-                }
-                return Ok(result);
+        if let Value::Structure(source) = value
+            && let [Value::U32(id), Value::Dict(source)] = source.fields()
+        {
+            let source_type_key = Value::new("source_type");
+            let source_type: u32 =
+                source
+                    .get(&source_type_key)?
+                    .ok_or(zvariant::Error::Message(
+                        "fail to get source_type".to_string(),
+                    ))?;
+            let mut result = SelectedSource::new(*id, source_type);
+            let source_size_key = Value::new("size");
+            if let Some(Value::Structure(size)) = source.get(&source_size_key)?
+                && let (Some(Value::I32(width)), Some(Value::I32(height))) =
+                    (size.fields().first(), size.fields().get(1))
+            {
+                result.width = Some(*width);
+                result.height = Some(*height);
+            }
+            let source_position_key = Value::new("position");
+            if let Some(Value::Structure(position)) = source.get(&source_position_key)?
+                && let (Some(Value::I32(x)), Some(Value::I32(y))) =
+                    (position.fields().first(), position.fields().get(1))
+            {
+                result.position = Some((*x, *y));
+            }
+            let mapping_id_key = Value::new("mapping_id");
+            if let Some(Value::Str(mapping_id)) = source.get(&mapping_id_key)? {
+                result.mapping_id = Some(mapping_id.to_string());
             }
+            return Ok(result);
         }
         Err(zvariant::Error::IncorrectType)
     }
 }
 
+pub(crate) const PORTAL_SERVICE: &str = "org.freedesktop.portal.Desktop";
+
+/// `org.freedesktop.portal.ScreenCast`, see `xml/org.freedesktop.portal.ScreenCast.xml`.
 #[proxy(
     interface = "org.freedesktop.portal.ScreenCast",
     default_service = "org.freedesktop.portal.Desktop",
@@ -244,14 +727,14 @@ impl TryFrom<Value<'_>> for SelectedSource {
 )]
 pub trait ZBusScreencast {
     /// CreateSession method
-    fn create_session(&self, options: &HashMap<&str, &Value<'_>>) -> Result<OwnedObjectPath>;
+    fn create_session(&self, options: &HashMap<&str, &Value<'_>>) -> zbus::Result<OwnedObjectPath>;
 
     /// SelectSources method
     fn select_sources(
         &self,
         session_handle: &ObjectPath<'_>,
         options: &HashMap<&str, &Value<'_>>,
-    ) -> Result<OwnedObjectPath>;
+    ) -> zbus::Result<OwnedObjectPath>;
 
     /// Start method
     fn start(
@@ -259,28 +742,29 @@ pub trait ZBusScreencast {
         session_handle: &ObjectPath<'_>,
         parent_window: &str,
         options: &HashMap<&str, &Value<'_>>,
-    ) -> Result<OwnedObjectPath>;
+    ) -> zbus::Result<OwnedObjectPath>;
 
     /// OpenPipeWireRemote method
     fn open_pipe_wire_remote(
         &self,
         session_handle: &ObjectPath<'_>,
         options: &HashMap<&str, &Value<'_>>,
-    ) -> Result<OwnedFd>;
+    ) -> zbus::Result<OwnedFd>;
 
     /// AvailableCursorModes property
     #[zbus(property)]
-    fn available_cursor_modes(&self) -> Result<u32>;
+    fn available_cursor_modes(&self) -> zbus::Result<u32>;
 
     /// AvailableSourceTypes property
     #[zbus(property)]
-    fn available_source_types(&self) -> Result<u32>;
+    fn available_source_types(&self) -> zbus::Result<u32>;
 
     /// version property
     #[zbus(property, name = "version")]
-    fn version(&self) -> Result<u32>;
+    fn version(&self) -> zbus::Result<u32>;
 }
 
+/// `org.freedesktop.portal.Request`, see `xml/org.freedesktop.portal.Request.xml`.
 #[proxy(
     interface = "org.freedesktop.portal.Request",
     default_service = "org.freedesktop.portal.Desktop",
@@ -288,26 +772,84 @@ pub trait ZBusScreencast {
 )]
 pub trait ZBusRequest {
     /// Close method
-    fn close(&self) -> Result<()>;
+    fn close(&self) -> zbus::Result<()>;
 
     /// Response signal
     #[zbus(signal)]
-    fn response(&self, response: u32, results: HashMap<&str, Value<'_>>) -> Result<()>;
+    fn response(&self, response: u32, results: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
 }
 
-#[derive(Debug, Copy, Clone)]
+/// `org.freedesktop.portal.Session`, see `xml/org.freedesktop.portal.Session.xml`.
+#[proxy(
+    interface = "org.freedesktop.portal.Session",
+    default_service = "org.freedesktop.portal.Desktop"
+)]
+pub trait ZBusSession {
+    /// Close method
+    fn close(&self) -> zbus::Result<()>;
+
+    /// Closed signal
+    #[zbus(signal)]
+    fn closed(&self, details: HashMap<&str, Value<'_>>) -> zbus::Result<()>;
+
+    /// version property
+    #[zbus(property, name = "version")]
+    fn version(&self) -> zbus::Result<u32>;
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ScreencastOptions {
+    pub cursor_mode: CursorMode,
+    pub source_types: SourceType,
+    pub persist_mode: PersistMode,
+    pub multiple: bool,
+    pub restore_token: Option<String>,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Timeouts {
+    pub create_session: Option<Duration>,
+    pub select_sources: Option<Duration>,
+    pub start: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowIdentifier {
+    Wayland(String),
+    X11(u64),
+}
+
+impl WindowIdentifier {
+    pub fn wayland(exported_handle: impl Into<String>) -> Self {
+        WindowIdentifier::Wayland(exported_handle.into())
+    }
+
+    pub fn x11(xid: u64) -> Self {
+        WindowIdentifier::X11(xid)
+    }
+}
+
+impl Display for WindowIdentifier {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WindowIdentifier::Wayland(handle) => write!(f, "wayland:{}", handle),
+            WindowIdentifier::X11(xid) => write!(f, "x11:{:x}", xid),
+        }
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum PersistMode {
+    #[default]
     DoNotPersist,
     AsApplication,
     UntilRevoked,
 }
 
-impl Default for PersistMode {
-    fn default() -> Self {
-        PersistMode::DoNotPersist
-    }
-}
-
 impl PersistMode {
     pub fn to_u32(&self) -> u32 {
         match self {
@@ -320,6 +862,7 @@ impl PersistMode {
 
 bitflags! {
   #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+  #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
   pub struct SourceType: u32 {
     const MONITOR = 1;
     const WINDOW = 2;
@@ -333,19 +876,98 @@ impl Default for SourceType {
     }
 }
 
-#[derive(Debug, Copy, Clone)]
+bitflags! {
+  #[derive(Debug, Copy, Clone, PartialEq, Eq)]
+  pub struct CursorModes: u32 {
+    const HIDDEN = 1;
+    const EMBEDDED = 2;
+    const METADATA = 4;
+  }
+}
+
+impl CursorModes {
+    pub fn supports(&self, cursor_mode: CursorMode) -> bool {
+        self.contains(CursorModes::from(cursor_mode))
+    }
+}
+
+impl From<CursorMode> for CursorModes {
+    fn from(cursor_mode: CursorMode) -> Self {
+        CursorModes::from_bits_truncate(cursor_mode.to_u32())
+    }
+}
+
+impl SourceType {
+    pub fn supports(&self, source_type: SourceType) -> bool {
+        self.contains(source_type)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub enum CapabilityPolicy {
+    #[default]
+    Reject,
+    Downgrade,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Downgrade {
+    CursorMode {
+        requested: CursorMode,
+        used: CursorMode,
+    },
+    SourceType {
+        requested: SourceType,
+        used: SourceType,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum IgnoredOption {
+    CursorMode,
+    VirtualSource,
+    PersistMode,
+    RestoreToken,
+}
+
+impl IgnoredOption {
+    pub fn min_version(&self) -> u32 {
+        match self {
+            IgnoredOption::CursorMode => 2,
+            IgnoredOption::VirtualSource => 3,
+            IgnoredOption::PersistMode => 4,
+            IgnoredOption::RestoreToken => 4,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    pub version: u32,
+    pub cursor_modes: CursorModes,
+    pub source_types: SourceType,
+}
+
+impl Capabilities {
+    pub fn supports_cursor_mode(&self, cursor_mode: CursorMode) -> bool {
+        self.cursor_modes.supports(cursor_mode)
+    }
+
+    pub fn supports_source_type(&self, source_type: SourceType) -> bool {
+        self.source_types.supports(source_type)
+    }
+}
+
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum CursorMode {
+    #[default]
     Hidden,
     Embedded,
     Metadata,
 }
 
-impl Default for CursorMode {
-    fn default() -> Self {
-        CursorMode::Hidden
-    }
-}
-
 impl CursorMode {
     pub fn to_u32(&self) -> u32 {
         match self {
@@ -355,3 +977,8 @@ impl CursorMode {
         }
     }
 }
+
+const _: () = {
+    const fn assert_send<T: Send + 'static>() {}
+    assert_send::<ScreenCast>();
+};
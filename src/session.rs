@@ -0,0 +1,360 @@
+#[cfg(feature = "pipewire")]
+use crate::audio::AudioConfig;
+use crate::cancel::CancelHandle;
+use crate::error::{Result, ScreencastError};
+use crate::events::{CloseReason, EventSender, ScreencastEvent};
+#[cfg(feature = "pipewire")]
+use crate::filters::{Crop, CursorOverlay, Upright};
+#[cfg(feature = "pipewire")]
+use crate::frame::Frame;
+#[cfg(feature = "gstreamer")]
+use crate::gstreamer::{self, GstFrames, GstPipeline};
+#[cfg(feature = "pipewire")]
+use crate::image::{self, ImageFormat};
+use crate::launch::LaunchCommands;
+#[cfg(feature = "pipewire")]
+use crate::pipewire::{
+    AudioCapture, CallbackCapture, CaptureOptions, CaptureStats, FrameStream, PipeWireCapture,
+    StatsRecorder,
+};
+use crate::runtime::{self, compat};
+use crate::screencast::{
+    ClosedStream, PORTAL_SERVICE, ScreenCast, ScreencastOptions, SelectedSource,
+    ZBusScreencastProxy, ZBusSessionProxy,
+};
+#[cfg(feature = "pipewire")]
+use futures_core::Stream;
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+#[cfg(feature = "pipewire")]
+use std::pin::Pin;
+#[cfg(feature = "pipewire")]
+use std::sync::Arc;
+use tokio::task::JoinHandle;
+use zbus::Connection;
+use zbus::export::ordered_stream::OrderedStreamExt;
+use zbus::fdo::DBusProxy;
+use zbus::zvariant::{self, ObjectPath};
+
+/// A started screencast session, closed on `close()` or when dropped.
+#[derive(Debug)]
+pub struct ActiveSession {
+    screencast: ScreenCast,
+    fd: OwnedFd,
+    // of the session closing and the portal restarting
+    watchers: Vec<JoinHandle<()>>,
+    #[cfg(feature = "pipewire")]
+    stats: Arc<StatsRecorder>,
+}
+
+impl ActiveSession {
+    pub(crate) fn new(screencast: ScreenCast, fd: zvariant::OwnedFd) -> Self {
+        let mut session = ActiveSession {
+            screencast,
+            fd: fd.into(),
+            watchers: Vec::new(),
+            #[cfg(feature = "pipewire")]
+            stats: Arc::default(),
+        };
+        session.spawn_watchers();
+        session
+    }
+
+    /// Negotiates a new session after `ScreencastEvent::PortalRestarted`, the
+    /// picker is skipped when the portal returned a restore token.
+    pub async fn recover(&mut self) -> Result<()> {
+        compat(async {
+            let fd = self.screencast.renegotiate().await?;
+            self.fd = fd.into();
+            self.spawn_watchers();
+            Ok(())
+        })
+        .await
+    }
+
+    /// Watches for the session closing and the portal restarting, also
+    /// without subscribers yet: those of `events()` may come later, and
+    /// `send` costs nothing until they do.
+    fn spawn_watchers(&mut self) {
+        // those of the session before a recovery or reselection
+        for watcher in self.watchers.drain(..) {
+            watcher.abort();
+        }
+        let events = self.screencast.event_sender();
+        let handle = runtime::handle();
+        if let Some(session_proxy) = self.screencast.session_proxy() {
+            let session_proxy = session_proxy.clone();
+            let events = events.clone();
+            self.watchers.push(handle.spawn(async move {
+                if let Ok(mut closed_stream) = session_proxy.receive_closed().await
+                    && closed_stream.next().await.is_some()
+                {
+                    events.send(ScreencastEvent::SessionClosed {
+                        reason: CloseReason::Compositor,
+                    });
+                }
+            }));
+        }
+        if let Some(connection) = self.screencast.connection() {
+            let connection = connection.clone();
+            let events = events.clone();
+            self.watchers.push(handle.spawn(async move {
+                let _ = watch_portal_owner(connection, events).await;
+            }));
+        }
+    }
+
+    pub fn session_handle(&self) -> ObjectPath<'_> {
+        self.screencast.session_handle().as_ref()
+    }
+
+    pub fn pipewire_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+
+    pub fn try_clone_pipewire_fd(&self) -> std::io::Result<OwnedFd> {
+        self.fd.try_clone()
+    }
+
+    /// `gst-launch-1.0` and ffmpeg command lines for the selected sources,
+    /// for capturing with external tools.
+    pub fn launch_commands(&self) -> Result<LaunchCommands> {
+        let node_ids = self
+            .selected_sources()
+            .iter()
+            .map(SelectedSource::node_id)
+            .collect();
+        Ok(LaunchCommands::new(
+            self.try_clone_pipewire_fd()?,
+            node_ids,
+        )?)
+    }
+
+    pub fn selected_sources(&self) -> &[SelectedSource] {
+        self.screencast.selected_sources()
+    }
+
+    /// Starts consuming the selected sources over a clone of the PipeWire fd.
+    #[cfg(feature = "pipewire")]
+    pub fn capture(&self, options: &CaptureOptions) -> Result<PipeWireCapture> {
+        PipeWireCapture::connect_with_events(
+            self.try_clone_pipewire_fd()?,
+            self.selected_sources(),
+            options,
+            self.screencast.event_sender().clone(),
+            self.stats.clone(),
+        )
+    }
+
+    /// Hands the frames of every selected source to `callback` on the
+    /// PipeWire loop thread, see `CallbackCapture` for what it may do.
+    #[cfg(feature = "pipewire")]
+    pub fn capture_with(
+        &self,
+        options: &CaptureOptions,
+        callback: impl FnMut(Frame<'_>) + Send + 'static,
+    ) -> Result<CallbackCapture> {
+        PipeWireCapture::connect_callback_with_events(
+            self.try_clone_pipewire_fd()?,
+            self.selected_sources(),
+            options,
+            Box::new(callback),
+            self.screencast.event_sender().clone(),
+            self.stats.clone(),
+        )
+    }
+
+    /// Captures the audio inputs of `config` over a clone of the PipeWire
+    /// fd, see `AudioCapture` for when the portal's remote has none.
+    #[cfg(feature = "pipewire")]
+    pub fn audio_capture(&self, config: &AudioConfig) -> Result<AudioCapture> {
+        AudioCapture::connect(self.try_clone_pipewire_fd()?, config)
+    }
+
+    /// One independent frame stream per selected source.
+    #[cfg(feature = "pipewire")]
+    pub fn source_streams(&self, options: &CaptureOptions) -> Result<Vec<FrameStream>> {
+        PipeWireCapture::connect_per_source_with_events(
+            self.try_clone_pipewire_fd()?,
+            self.selected_sources(),
+            options,
+            self.screencast.event_sender().clone(),
+            self.stats.clone(),
+        )
+    }
+
+    /// Frames of every selected source as an async stream, the PipeWire
+    /// loop keeps running until the stream is dropped.
+    #[cfg(feature = "pipewire")]
+    pub fn frames(
+        &self,
+        options: &CaptureOptions,
+    ) -> Result<impl Stream<Item = Frame<'static>> + Send + Unpin + 'static> {
+        self.capture(options)
+    }
+
+    /// Counters across every capture started from this session.
+    #[cfg(feature = "pipewire")]
+    pub fn stats(&self) -> CaptureStats {
+        self.stats.snapshot()
+    }
+
+    /// Grabs the next frame of the first selected source, with the cursor
+    /// drawn in, cut to the valid region and upright.
+    #[cfg(feature = "pipewire")]
+    pub async fn snapshot(&self) -> Result<Frame<'static>> {
+        let sources = &self.selected_sources()[..self.selected_sources().len().min(1)];
+        let options = CaptureOptions {
+            queue_depth: 1,
+            ..CaptureOptions::default()
+        };
+        let mut capture =
+            PipeWireCapture::connect(self.try_clone_pipewire_fd()?, sources, &options)?;
+        let frame = std::future::poll_fn(|cx| Pin::new(&mut capture).poll_next(cx))
+            .await
+            .ok_or(ScreencastError::SessionClosed)?;
+        let frame = CursorOverlay.apply(frame);
+        let frame = match frame.crop() {
+            Some(rect) => Crop::new(rect).apply(frame),
+            None => frame,
+        };
+        Ok(Upright.apply(frame))
+    }
+
+    /// Like `snapshot`, encoded as a PNG or JPEG file.
+    #[cfg(feature = "pipewire")]
+    pub async fn snapshot_image(&self, format: ImageFormat) -> Result<Vec<u8>> {
+        image::encode(&self.snapshot().await?, format)
+    }
+
+    /// A GStreamer pipeline reading the first selected source through
+    /// `pipewiresrc`, followed by `tail`, e.g.
+    /// `"videoconvert ! x264enc ! mp4mux ! filesink location=out.mp4"`.
+    #[cfg(feature = "gstreamer")]
+    pub fn gstreamer_pipeline(&self, tail: &str) -> Result<GstPipeline> {
+        let source = self
+            .selected_sources()
+            .first()
+            .ok_or_else(|| ScreencastError::Unsupported("no selected sources".to_string()))?;
+        GstPipeline::launch_pipewire(self.try_clone_pipewire_fd()?, source.node_id(), tail)
+    }
+
+    /// Frames of the first selected source, negotiated and converted to
+    /// BGRx by GStreamer.
+    #[cfg(feature = "gstreamer")]
+    pub fn gstreamer_frames(&self) -> Result<GstFrames> {
+        self.gstreamer_pipeline(gstreamer::APPSINK)?
+            .frames("frames")
+    }
+
+    pub fn connection(&self) -> Option<&Connection> {
+        self.screencast.connection()
+    }
+
+    pub fn screencast_proxy(&self) -> Option<&ZBusScreencastProxy<'static>> {
+        self.screencast.screencast_proxy()
+    }
+
+    pub fn session_proxy(&self) -> Option<&ZBusSessionProxy<'static>> {
+        self.screencast.session_proxy()
+    }
+
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.screencast.cancel_handle()
+    }
+
+    /// Picks the sources anew in a session that replaces this one: the
+    /// portal takes no new selection once a session started. A
+    /// `restore_token` in `options` restores that selection without the
+    /// dialog, none shows it. The PipeWire remote is swapped for the new
+    /// session's, captures connected to the old one end and are connected
+    /// again. When the new session fails this one goes on as it was.
+    pub async fn reselect_sources(
+        &mut self,
+        options: ScreencastOptions,
+    ) -> Result<&[SelectedSource]> {
+        compat(async {
+            let fd = self.screencast.reselect(options).await?;
+            self.fd = fd.into();
+            self.spawn_watchers();
+            Ok(self.screencast.selected_sources())
+        })
+        .await
+    }
+
+    pub async fn receive_closed(&self) -> Result<ClosedStream> {
+        compat(async {
+            match self.screencast.session_proxy() {
+                Some(session_proxy) => Ok(session_proxy.receive_closed().await?),
+                None => Err(ScreencastError::SessionClosed),
+            }
+        })
+        .await
+    }
+
+    pub async fn close(mut self) -> Result<()> {
+        compat(async {
+            self.screencast
+                .event_sender()
+                .send(ScreencastEvent::SessionClosed {
+                    reason: CloseReason::Application,
+                });
+            self.screencast.close().await
+        })
+        .await
+    }
+}
+
+impl AsFd for ActiveSession {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.pipewire_fd()
+    }
+}
+
+impl Drop for ActiveSession {
+    fn drop(&mut self) {
+        for watcher in &self.watchers {
+            watcher.abort();
+        }
+        let (session_proxy, connection) = self.screencast.take_connection();
+        if session_proxy.is_none() && connection.is_none() {
+            return;
+        }
+        self.screencast
+            .event_sender()
+            .send(ScreencastEvent::SessionClosed {
+                reason: CloseReason::Application,
+            });
+        runtime::handle().spawn(async move {
+            let _ = close_session(session_proxy, connection).await;
+        });
+    }
+}
+
+async fn watch_portal_owner(connection: Connection, events: EventSender) -> Result<()> {
+    let dbus_proxy = DBusProxy::new(&connection).await?;
+    let mut owner_stream = dbus_proxy
+        .receive_name_owner_changed_with_args(&[(0, PORTAL_SERVICE)])
+        .await?;
+    while let Some(owner_changed) = owner_stream.next().await {
+        let args = owner_changed.args()?;
+        if args.new_owner().is_some() {
+            events.send(ScreencastEvent::PortalRestarted);
+            break;
+        }
+    }
+    Ok(())
+}
+
+async fn close_session(
+    session_proxy: Option<ZBusSessionProxy<'static>>,
+    connection: Option<Connection>,
+) -> Result<()> {
+    let closed = match session_proxy {
+        Some(session_proxy) => session_proxy.close().await,
+        None => Ok(()),
+    };
+    if let Some(connection) = connection {
+        connection.close().await?;
+    }
+    Ok(closed?)
+}
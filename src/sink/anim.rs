@@ -0,0 +1,183 @@
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use std::time::{Duration, Instant};
+
+/// Thins a capture out to the frame rate of an animation and ends it after
+/// a maximum duration, shared by the animated image sinks.
+#[derive(Debug)]
+pub(crate) struct Decimate {
+    interval: Duration,
+    limit: Option<Duration>,
+    first: Option<Duration>,
+    last: Duration,
+    // stands in for the timestamps of frames that have none
+    clock: Option<Instant>,
+    next: Duration,
+}
+
+impl Decimate {
+    pub(crate) fn new(fps: u32) -> Self {
+        Decimate {
+            interval: Duration::from_secs(1) / fps.max(1),
+            limit: None,
+            first: None,
+            last: Duration::ZERO,
+            clock: None,
+            next: Duration::ZERO,
+        }
+    }
+
+    pub(crate) fn with_framerate(self, fps: u32) -> Self {
+        Decimate {
+            interval: Duration::from_secs(1) / fps.max(1),
+            ..self
+        }
+    }
+
+    pub(crate) fn with_limit(self, limit: Duration) -> Self {
+        Decimate {
+            limit: Some(limit),
+            ..self
+        }
+    }
+
+    /// When the last frame ends, one interval after the last one that
+    /// came in time, kept or not, or at the limit.
+    pub(crate) fn end(&self) -> Duration {
+        (self.last + self.interval).min(self.limit.unwrap_or(Duration::MAX))
+    }
+
+    /// The time of a kept frame since the first one, `None` for a frame
+    /// that comes too soon after the last kept one or past the limit.
+    pub(crate) fn accept(&mut self, pts: Option<Duration>) -> Option<Duration> {
+        let pts = pts.unwrap_or_else(|| self.clock.get_or_insert_with(Instant::now).elapsed());
+        let time = pts.saturating_sub(*self.first.get_or_insert(pts));
+        if self.limit.is_some_and(|limit| time >= limit) {
+            return None;
+        }
+        self.last = self.last.max(time);
+        if time < self.next {
+            return None;
+        }
+        while self.next <= time {
+            self.next += self.interval;
+        }
+        Some(time)
+    }
+}
+
+/// What an animation shows, the frames are drawn onto it to find out what
+/// changed. It takes the size of the first frame, larger frames are cut
+/// and smaller ones leave the rest of the picture as it was.
+#[derive(Debug)]
+pub(crate) struct Canvas {
+    format: &'static str,
+    max: u32,
+    width: usize,
+    height: usize,
+    // opaque RGBA
+    pixels: Vec<u8>,
+}
+
+impl Canvas {
+    /// A canvas of at most `max` pixels wide and high, for an animation
+    /// in `format`.
+    pub(crate) fn new(format: &'static str, max: u32) -> Self {
+        Canvas {
+            format,
+            max,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+        }
+    }
+
+    pub(crate) fn width(&self) -> usize {
+        self.width
+    }
+
+    pub(crate) fn height(&self) -> usize {
+        self.height
+    }
+
+    pub(crate) fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Draws a memory frame and returns the picture before, which is empty
+    /// for the first frame.
+    pub(crate) fn draw(&mut self, frame: &Frame<'_>) -> Result<Vec<u8>> {
+        if self.pixels.is_empty() {
+            let (width, height) = (frame.width(), frame.height());
+            if width == 0 || height == 0 || width > self.max || height > self.max {
+                return Err(ScreencastError::Unsupported(format!(
+                    "{} of {}x{}",
+                    self.format, width, height
+                )));
+            }
+            (self.width, self.height) = (width as usize, height as usize);
+        }
+        let rgba = frame.to_rgba()?;
+        let previous = match self.pixels.is_empty() {
+            true => {
+                self.pixels = vec![0; self.width * self.height * 4];
+                Vec::new()
+            }
+            false => self.pixels.clone(),
+        };
+        let frame_row = frame.width() as usize * 4;
+        let row = (frame.width() as usize).min(self.width) * 4;
+        for y in 0..(frame.height() as usize).min(self.height) {
+            let pixels = &mut self.pixels[y * self.width * 4..][..row];
+            pixels.copy_from_slice(&rgba[y * frame_row..][..row]);
+            pixels.chunks_exact_mut(4).for_each(|pixel| pixel[3] = 0xff);
+        }
+        Ok(previous)
+    }
+
+    /// The pixels of `left..right` and `top..bottom`, tightly packed with
+    /// `channels` of 3 for RGB or 4 for RGBA.
+    pub(crate) fn region(&self, (left, top, right, bottom): Bounds, channels: usize) -> Vec<u8> {
+        let mut out = Vec::with_capacity((right - left) * (bottom - top) * channels);
+        for y in top..bottom {
+            for pixel in self.pixels[(y * self.width + left) * 4..(y * self.width + right) * 4]
+                .chunks_exact(4)
+            {
+                out.extend_from_slice(&pixel[..channels]);
+            }
+        }
+        out
+    }
+}
+
+/// Left, top, right and bottom, the latter two exclusive.
+pub(crate) type Bounds = (usize, usize, usize, usize);
+
+/// The bounds of the pixels that changed, `None` if none did.
+pub(crate) fn changed(
+    width: usize,
+    height: usize,
+    unchanged: impl Fn(usize) -> bool,
+) -> Option<Bounds> {
+    let mut bounds: Option<Bounds> = None;
+    for y in 0..height {
+        let row = (0..width).filter(|&x| !unchanged(y * width + x));
+        let (Some(first), Some(last)) = (row.clone().next(), row.last()) else {
+            continue;
+        };
+        bounds = Some(match bounds {
+            Some((left, top, right, _)) => (left.min(first), top, right.max(last + 1), y + 1),
+            None => (first, y, last + 1, y + 1),
+        });
+    }
+    bounds
+}
+
+/// An encoded frame waiting for the next one, which tells its delay.
+#[derive(Debug)]
+pub(crate) struct Pending {
+    pub(crate) time: Duration,
+    pub(crate) data: Vec<u8>,
+    // offset of the delay in `data`
+    pub(crate) delay: usize,
+}
@@ -0,0 +1,165 @@
+use super::FrameSink;
+use super::anim::{Canvas, Decimate, Pending, changed};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::image;
+use crate::runtime::compat;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+
+/// Offset of the animation control chunk, after the signature and header.
+const ACTL: u64 = 33;
+
+/// Records an animated PNG that loops forever, lossless and in true colour,
+/// played by browsers as any other image.
+///
+/// The capture is thinned out to 10 frames per second by default, and only
+/// the rectangle that changed since the last frame is stored. Frames
+/// without changes extend the previous one. The animation takes the size of
+/// the first frame.
+#[derive(Debug)]
+pub struct Apng {
+    file: BufWriter<File>,
+    decimate: Decimate,
+    canvas: Canvas,
+    pending: Option<Pending>,
+    // sequence number of the next frame control or frame data chunk
+    sequence: u32,
+    frames: u32,
+}
+
+impl Apng {
+    /// Creates or truncates `path`.
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = compat(File::create(path.as_ref())).await?;
+        Ok(Apng {
+            file: BufWriter::new(file),
+            decimate: Decimate::new(10),
+            canvas: Canvas::new("APNG", i32::MAX as u32),
+            pending: None,
+            sequence: 0,
+            frames: 0,
+        })
+    }
+
+    /// Frames per second kept of the capture.
+    pub fn with_framerate(mut self, fps: u32) -> Self {
+        self.decimate = self.decimate.with_framerate(fps.clamp(1, 1000));
+        self
+    }
+
+    /// Ends the animation after `duration`, later frames are dropped.
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.decimate = self.decimate.with_limit(duration);
+        self
+    }
+
+    /// Writes the pending frame, lasting until `until`.
+    async fn flush_pending(&mut self, until: Duration) -> Result<()> {
+        let Some(mut pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let delay = until
+            .saturating_sub(pending.time)
+            .as_millis()
+            .clamp(1, u16::MAX as u128) as u16;
+        // the delay is in milliseconds, with its CRC at the end of the chunk
+        let delay_at = pending.delay;
+        pending.data[delay_at..delay_at + 2].copy_from_slice(&delay.to_be_bytes());
+        let fctl = delay_at - 28;
+        let mut chunk = Vec::with_capacity(38);
+        image::png_chunk(&mut chunk, b"fcTL", &pending.data[fctl + 8..fctl + 34]);
+        pending.data[fctl..fctl + 38].copy_from_slice(&chunk);
+        self.file.write_all(&pending.data).await?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// The frame as PNG chunks of what changed and the offset of its delay,
+    /// `None` without changes.
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Option<(Vec<u8>, usize)>> {
+        let previous = self.canvas.draw(frame)?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let current = self.canvas.pixels();
+        let first = previous.is_empty();
+        let unchanged =
+            |index: usize| !first && current[index * 4..][..4] == previous[index * 4..][..4];
+        let Some(bounds) = changed(width, height, unchanged) else {
+            return Ok(None);
+        };
+        let (left, top, right, bottom) = bounds;
+
+        let mut out = Vec::new();
+        if first {
+            out = image::png_rgb_header(width as u32, height as u32);
+            // the frame count is filled in by `finish`, loops forever
+            image::png_chunk(&mut out, b"acTL", &[0; 8]);
+        }
+        let delay = out.len() + 8 + 20;
+        let mut control = Vec::with_capacity(26);
+        control.extend_from_slice(&self.sequence.to_be_bytes());
+        for value in [right - left, bottom - top, left, top] {
+            control.extend_from_slice(&(value as u32).to_be_bytes());
+        }
+        // delay of 0 / 1000 until known, no disposal, replaces the region
+        control.extend_from_slice(&[0, 0, 0x03, 0xe8, 0, 0]);
+        image::png_chunk(&mut out, b"fcTL", &control);
+        self.sequence += 1;
+
+        let data = image::png_rgb_data(&self.canvas.region(bounds, 3), (right - left) as u32);
+        match first {
+            true => image::png_chunk(&mut out, b"IDAT", &data),
+            false => {
+                let mut chunk = Vec::with_capacity(data.len() + 4);
+                chunk.extend_from_slice(&self.sequence.to_be_bytes());
+                chunk.extend_from_slice(&data);
+                image::png_chunk(&mut out, b"fdAT", &chunk);
+                self.sequence += 1;
+            }
+        }
+        Ok(Some((out, delay)))
+    }
+}
+
+impl FrameSink for Apng {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        if frame.is_dmabuf() {
+            return Ok(());
+        }
+        let Some(time) = self.decimate.accept(frame.pts()) else {
+            return Ok(());
+        };
+        let Some((data, delay)) = self.encode(&frame)? else {
+            return Ok(());
+        };
+        compat(async {
+            self.flush_pending(time).await?;
+            self.pending = Some(Pending { time, data, delay });
+            Ok(())
+        })
+        .await
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            self.flush_pending(self.decimate.end()).await?;
+            if self.frames > 0 {
+                let mut end = Vec::with_capacity(12);
+                image::png_chunk(&mut end, b"IEND", &[]);
+                self.file.write_all(&end).await?;
+                let mut control = Vec::with_capacity(8);
+                control.extend_from_slice(&self.frames.to_be_bytes());
+                control.extend_from_slice(&0u32.to_be_bytes());
+                let mut chunk = Vec::with_capacity(20);
+                image::png_chunk(&mut chunk, b"acTL", &control);
+                self.file.seek(SeekFrom::Start(ACTL)).await?;
+                self.file.write_all(&chunk).await?;
+            }
+            self.file.flush().await?;
+            Ok(())
+        })
+        .await
+    }
+}
@@ -0,0 +1,552 @@
+use crate::encode::bitstream::{self, OBU_SEQUENCE_HEADER, OBU_TEMPORAL_DELIMITER};
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime;
+
+/// Stands in for the encoder of a container written from packets encoded
+/// elsewhere.
+#[derive(Debug)]
+pub(super) struct Encoded(pub EncoderConfig);
+
+impl Encoder for Encoded {
+    fn config(&self) -> &EncoderConfig {
+        &self.0
+    }
+
+    fn encode(&mut self, _frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+        Ok(Vec::new())
+    }
+
+    fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        Ok(Vec::new())
+    }
+
+    fn force_keyframe(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The encoder of a sink, run on the blocking threads of the runtime: a
+/// slow encode or a full pipe to `ffmpeg` holds up neither the sink's task
+/// nor the dbus connection and the other sinks on the same runtime.
+pub(super) struct BlockingEncoder {
+    config: EncoderConfig,
+    // away while it encodes, gone after it panicked
+    encoder: Option<Box<dyn Encoder>>,
+}
+
+impl std::fmt::Debug for BlockingEncoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockingEncoder")
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl BlockingEncoder {
+    pub(super) fn new(encoder: Box<dyn Encoder>) -> Self {
+        BlockingEncoder {
+            config: encoder.config().clone(),
+            encoder: Some(encoder),
+        }
+    }
+
+    pub(super) fn config(&self) -> &EncoderConfig {
+        &self.config
+    }
+
+    pub(super) async fn encode(&mut self, frame: Frame<'static>) -> Result<Vec<EncodedPacket>> {
+        self.run(move |encoder| encoder.encode(&frame)).await
+    }
+
+    pub(super) async fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+        self.run(|encoder| encoder.finish()).await
+    }
+
+    /// See `encode::request_keyframe`.
+    pub(super) async fn request_keyframe(&mut self) -> Result<()> {
+        self.run(|encoder| encode::request_keyframe(encoder)).await
+    }
+
+    async fn run<T: Send + 'static>(
+        &mut self,
+        call: impl FnOnce(&mut dyn Encoder) -> Result<T> + Send + 'static,
+    ) -> Result<T> {
+        let mut encoder = self
+            .encoder
+            .take()
+            .ok_or_else(|| ScreencastError::Encoder("the encoder panicked".to_string()))?;
+        let (encoder, result) = runtime::handle()
+            .spawn_blocking(move || {
+                let result = call(encoder.as_mut());
+                (encoder, result)
+            })
+            .await
+            .map_err(|_| ScreencastError::Encoder("the encoder panicked".to_string()))?;
+        self.encoder = Some(encoder);
+        result
+    }
+}
+
+/// The decoder configuration record containers store for a stream: the
+/// body of `avcC`, `hvcC`, `vpcC` (after its version and flags) or `av1C`,
+/// the first, second and last also Matroska's `CodecPrivate`. Built from
+/// the first keyframe, `None` for VP8, which has none, or when the keyframe
+/// lacks the headers.
+pub(crate) fn decoder_config(codec: Codec, keyframe: &[u8]) -> Option<Vec<u8>> {
+    match codec {
+        Codec::H264 => avc_config(keyframe),
+        Codec::Hevc => hevc_config(keyframe),
+        Codec::Vp8 => None,
+        Codec::Vp9 => vp9_config(keyframe),
+        Codec::Av1 => av1_config(keyframe),
+    }
+}
+
+/// A packet as a container sample: NAL units with 4 byte big endian
+/// lengths instead of start codes for H.264 and HEVC, without temporal
+/// delimiters for AV1. Access unit delimiters are dropped.
+pub(crate) fn sample_data(codec: Codec, data: &[u8]) -> Vec<u8> {
+    match codec {
+        Codec::H264 | Codec::Hevc => {
+            let aud = if codec == Codec::Hevc { 35 } else { 9 };
+            let mut sample = Vec::with_capacity(data.len());
+            for nal in bitstream::nal_units(data) {
+                if bitstream::nal_type(codec, nal) != aud {
+                    sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    sample.extend_from_slice(nal);
+                }
+            }
+            sample
+        }
+        Codec::Av1 => bitstream::obus(data)
+            .filter(|&(obu_type, ..)| obu_type != OBU_TEMPORAL_DELIMITER)
+            .flat_map(|(_, obu, _)| obu.iter().copied())
+            .collect(),
+        Codec::Vp8 | Codec::Vp9 => data.to_vec(),
+    }
+}
+
+fn find_nal(codec: Codec, data: &[u8], nal_type: u8) -> Option<&[u8]> {
+    bitstream::nal_units(data).find(|nal| bitstream::nal_type(codec, nal) == nal_type)
+}
+
+fn avc_config(keyframe: &[u8]) -> Option<Vec<u8>> {
+    let sps = find_nal(Codec::H264, keyframe, 7)?;
+    let pps = find_nal(Codec::H264, keyframe, 8)?;
+    let rbsp = unescape(&sps[1..]);
+    let mut reader = BitReader::new(&rbsp);
+    let profile = reader.bits(8)? as u8;
+    reader.bits(16)?;
+    reader.ue()?;
+    let (mut chroma_format, mut luma_depth, mut chroma_depth) = (1, 0, 0);
+    if matches!(
+        profile,
+        100 | 110 | 122 | 244 | 44 | 83 | 86 | 118 | 128 | 138 | 139 | 134 | 135
+    ) {
+        chroma_format = reader.ue()?;
+        if chroma_format == 3 {
+            reader.bits(1)?;
+        }
+        luma_depth = reader.ue()?;
+        chroma_depth = reader.ue()?;
+    }
+
+    let mut config = vec![1, sps[1], sps[2], sps[3], 0xfc | 3, 0xe0 | 1];
+    config.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    config.extend_from_slice(sps);
+    config.push(1);
+    config.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    config.extend_from_slice(pps);
+    // the extension every profile above High needs
+    if !matches!(profile, 66 | 77 | 88) {
+        config.extend_from_slice(&[
+            0xfc | chroma_format as u8,
+            0xf8 | luma_depth as u8,
+            0xf8 | chroma_depth as u8,
+            0,
+        ]);
+    }
+    Some(config)
+}
+
+fn hevc_config(keyframe: &[u8]) -> Option<Vec<u8>> {
+    let vps = find_nal(Codec::Hevc, keyframe, 32)?;
+    let sps = find_nal(Codec::Hevc, keyframe, 33)?;
+    let pps = find_nal(Codec::Hevc, keyframe, 34)?;
+    let rbsp = unescape(sps.get(2..)?);
+    // vps id, max sub layers, temporal id nesting, then the general
+    // profile, tier and level copied as they are
+    let general = rbsp.get(..13)?;
+    let sub_layers = (general[0] >> 1 & 7) as usize;
+    let mut reader = BitReader::new(&rbsp);
+    reader.bits(104)?;
+    let mut present = Vec::with_capacity(sub_layers);
+    for _ in 0..sub_layers {
+        present.push((reader.bits(1)? == 1, reader.bits(1)? == 1));
+    }
+    if sub_layers > 0 {
+        reader.bits(2 * (8 - sub_layers as u32))?;
+    }
+    for (profile, level) in present {
+        if profile {
+            reader.bits(88)?;
+        }
+        if level {
+            reader.bits(8)?;
+        }
+    }
+    reader.ue()?;
+    let chroma_format = reader.ue()?;
+    if chroma_format == 3 {
+        reader.bits(1)?;
+    }
+    reader.ue()?;
+    reader.ue()?;
+    if reader.bits(1)? == 1 {
+        for _ in 0..4 {
+            reader.ue()?;
+        }
+    }
+    let luma_depth = reader.ue()?;
+    let chroma_depth = reader.ue()?;
+
+    let mut config = vec![1];
+    config.extend_from_slice(&general[1..13]);
+    config.extend_from_slice(&[
+        0xf0,
+        0,
+        0xfc,
+        0xfc | chroma_format as u8,
+        0xf8 | luma_depth as u8,
+        0xf8 | chroma_depth as u8,
+        0,
+        0,
+        // no constant framerate, the temporal layers and nesting of the
+        // SPS, 4 byte lengths
+        ((sub_layers as u8 + 1) << 3) | (general[0] & 1) << 2 | 3,
+        3,
+    ]);
+    for nal in [vps, sps, pps] {
+        config.push(0x80 | bitstream::nal_type(Codec::Hevc, nal));
+        config.extend_from_slice(&1u16.to_be_bytes());
+        config.extend_from_slice(&(nal.len() as u16).to_be_bytes());
+        config.extend_from_slice(nal);
+    }
+    Some(config)
+}
+
+fn vp9_config(keyframe: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(keyframe);
+    reader.bits(2)?;
+    let profile = reader.bits(1)? | reader.bits(1)? << 1;
+    if profile == 3 {
+        reader.bits(1)?;
+    }
+    // show_existing_frame and frame_type
+    if reader.bits(2)? != 0 {
+        return None;
+    }
+    reader.bits(2)?;
+    if reader.bits(24)? != 0x498342 {
+        return None;
+    }
+    let depth = match profile {
+        2 | 3 if reader.bits(1)? == 1 => 12,
+        2 | 3 => 10,
+        _ => 8,
+    };
+    let srgb = reader.bits(3)? == 7;
+    let full_range = if srgb { 1 } else { reader.bits(1)? };
+    let subsampling = match (profile, srgb) {
+        (1 | 3, false) => (reader.bits(1)?, reader.bits(1)?),
+        (1 | 3, true) => (0, 0),
+        _ => (1, 1),
+    };
+    if profile == 1 || profile == 3 {
+        reader.bits(1)?;
+    }
+    let chroma = match subsampling {
+        (1, 1) => 0,
+        (1, 0) => 2,
+        _ => 3,
+    };
+    let width = reader.bits(16)? + 1;
+    let height = reader.bits(16)? + 1;
+    Some(vec![
+        profile as u8,
+        vp9_level(width as u64 * height as u64),
+        (depth << 4 | chroma << 1 | full_range) as u8,
+        // unspecified primaries, transfer and matrix
+        2,
+        2,
+        2,
+        0,
+        0,
+    ])
+}
+
+/// The lowest VP9 level that allows pictures of `samples` luma samples.
+fn vp9_level(samples: u64) -> u8 {
+    const LEVELS: [(u64, u8); 9] = [
+        (36_864, 10),
+        (73_728, 11),
+        (122_880, 20),
+        (245_760, 21),
+        (552_960, 30),
+        (983_040, 31),
+        (2_228_224, 40),
+        (8_912_896, 50),
+        (35_651_584, 60),
+    ];
+    LEVELS
+        .iter()
+        .find(|&&(max, _)| samples <= max)
+        .map_or(62, |&(_, level)| level)
+}
+
+fn av1_config(keyframe: &[u8]) -> Option<Vec<u8>> {
+    let (_, obu, payload) =
+        bitstream::obus(keyframe).find(|&(obu_type, ..)| obu_type == OBU_SEQUENCE_HEADER)?;
+    let mut reader = BitReader::new(payload);
+    let profile = reader.bits(3)?;
+    reader.bits(1)?;
+    let reduced = reader.bits(1)? == 1;
+    let (level, tier) = if reduced {
+        (reader.bits(5)?, 0)
+    } else {
+        let mut buffer_delay_length = 0;
+        let mut decoder_model = false;
+        if reader.bits(1)? == 1 {
+            reader.bits(64)?;
+            if reader.bits(1)? == 1 {
+                // uvlc codes like ue
+                reader.ue()?;
+            }
+            decoder_model = reader.bits(1)? == 1;
+            if decoder_model {
+                buffer_delay_length = reader.bits(5)? + 1;
+                reader.bits(32 + 5 + 5)?;
+            }
+        }
+        let display_delay = reader.bits(1)? == 1;
+        let operating_points = reader.bits(5)? + 1;
+        let mut first = (0, 0);
+        for index in 0..operating_points {
+            reader.bits(12)?;
+            let level = reader.bits(5)?;
+            let tier = if level > 7 { reader.bits(1)? } else { 0 };
+            if decoder_model && reader.bits(1)? == 1 {
+                reader.bits(2 * buffer_delay_length + 1)?;
+            }
+            if display_delay && reader.bits(1)? == 1 {
+                reader.bits(4)?;
+            }
+            if index == 0 {
+                first = (level, tier);
+            }
+        }
+        first
+    };
+    let width_bits = reader.bits(4)? + 1;
+    let height_bits = reader.bits(4)? + 1;
+    reader.bits(width_bits + height_bits)?;
+    if !reduced && reader.bits(1)? == 1 {
+        reader.bits(4 + 3)?;
+    }
+    reader.bits(3)?;
+    if !reduced {
+        reader.bits(4)?;
+        let order_hint = reader.bits(1)? == 1;
+        if order_hint {
+            reader.bits(2)?;
+        }
+        let screen_content = match reader.bits(1)? {
+            1 => 2,
+            _ => reader.bits(1)?,
+        };
+        if screen_content > 0 && reader.bits(1)? == 0 {
+            reader.bits(1)?;
+        }
+        if order_hint {
+            reader.bits(3)?;
+        }
+    }
+    reader.bits(3)?;
+
+    // color_config
+    let high_bitdepth = reader.bits(1)?;
+    let twelve_bit = match profile == 2 && high_bitdepth == 1 {
+        true => reader.bits(1)?,
+        false => 0,
+    };
+    let monochrome = match profile {
+        1 => 0,
+        _ => reader.bits(1)?,
+    };
+    let (mut primaries, mut transfer, mut matrix) = (2, 2, 2);
+    if reader.bits(1)? == 1 {
+        primaries = reader.bits(8)?;
+        transfer = reader.bits(8)?;
+        matrix = reader.bits(8)?;
+    }
+    let (subsampling_x, subsampling_y) = if monochrome == 1 {
+        (1, 1)
+    } else if primaries == 1 && transfer == 13 && matrix == 0 {
+        (0, 0)
+    } else {
+        // color_range
+        reader.bits(1)?;
+        match profile {
+            0 => (1, 1),
+            1 => (0, 0),
+            _ if twelve_bit == 1 => {
+                let x = reader.bits(1)?;
+                (x, if x == 1 { reader.bits(1)? } else { 0 })
+            }
+            _ => (1, 0),
+        }
+    };
+    let sample_position = match (subsampling_x, subsampling_y, monochrome) {
+        (1, 1, 0) => reader.bits(2)?,
+        _ => 0,
+    };
+
+    let mut config = vec![
+        0x81,
+        (profile << 5 | level) as u8,
+        (tier << 7
+            | high_bitdepth << 6
+            | twelve_bit << 5
+            | monochrome << 4
+            | subsampling_x << 3
+            | subsampling_y << 2
+            | sample_position) as u8,
+        0,
+    ];
+    config.extend_from_slice(obu);
+    Some(config)
+}
+
+/// Removes the emulation prevention bytes of a NAL unit.
+fn unescape(nal: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(nal.len());
+    let mut zeros = 0;
+    for &byte in nal {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(byte);
+    }
+    rbsp
+}
+
+/// Reads big endian bit fields, `None` past the end.
+struct BitReader<'a> {
+    data: &'a [u8],
+    bit: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        BitReader { data, bit: 0 }
+    }
+
+    /// Up to 32 bits are returned, longer reads only skip.
+    fn bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..count {
+            let byte = self.data.get(self.bit / 8)?;
+            value = value << 1 | (byte >> (7 - self.bit % 8) & 1) as u32;
+            self.bit += 1;
+        }
+        Some(value)
+    }
+
+    /// Exp-Golomb coded, as in H.264 and HEVC.
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.bits(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1 << zeros) - 1 + self.bits(zeros)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::PixelFormat;
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, ThreadId};
+    use std::time::Duration;
+
+    /// Keeps the threads it is called on, and panics on the frame at 1 s.
+    struct Threads(EncoderConfig, Arc<Mutex<Vec<ThreadId>>>);
+
+    impl Encoder for Threads {
+        fn config(&self) -> &EncoderConfig {
+            &self.0
+        }
+
+        fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+            assert_ne!(frame.pts(), Some(Duration::from_secs(1)));
+            self.1.lock().unwrap().push(thread::current().id());
+            Ok(vec![EncodedPacket {
+                codec: self.0.codec,
+                data: vec![1],
+                pts: frame.pts().unwrap_or_default(),
+                keyframe: true,
+            }])
+        }
+
+        fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+            self.1.lock().unwrap().push(thread::current().id());
+            Ok(Vec::new())
+        }
+
+        fn force_keyframe(&mut self) -> Result<()> {
+            self.1.lock().unwrap().push(thread::current().id());
+            Ok(())
+        }
+    }
+
+    fn frame(millis: u64) -> Frame<'static> {
+        Frame::packed(PixelFormat::Bgra, 1, 1, 4, vec![0; 4])
+            .with_pts(Duration::from_millis(millis))
+    }
+
+    #[tokio::test]
+    async fn encodes_off_the_task() {
+        let threads = Arc::new(Mutex::new(Vec::new()));
+        let config = EncoderConfig::new(Codec::H264);
+        let mut encoder = BlockingEncoder::new(Box::new(Threads(config, threads.clone())));
+        for millis in [0, 33] {
+            let packets = encoder.encode(frame(millis)).await.unwrap();
+            assert_eq!(packets[0].pts, Duration::from_millis(millis));
+        }
+        encoder.request_keyframe().await.unwrap();
+        assert!(encoder.finish().await.unwrap().is_empty());
+        assert_eq!(encoder.config().codec, Codec::H264);
+        // all on the blocking threads rather than the one of the task
+        let threads = threads.lock().unwrap().clone();
+        assert_eq!(threads.len(), 4);
+        assert!(threads.iter().all(|&id| id != thread::current().id()));
+
+        // a panic fails this call and those after
+        assert!(matches!(
+            encoder.encode(frame(1000)).await,
+            Err(ScreencastError::Encoder(_))
+        ));
+        assert!(matches!(
+            encoder.finish().await,
+            Err(ScreencastError::Encoder(_))
+        ));
+    }
+}
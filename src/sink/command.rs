@@ -0,0 +1,298 @@
+use super::{FrameSink, Y4m};
+use crate::convert;
+use crate::encode::ffmpeg;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane};
+use crate::runtime::{self, compat};
+use std::ffi::OsString;
+use std::io;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::process::{Child, ChildStdin};
+use tokio::task::JoinHandle;
+
+/// How the frames go down the pipe of a `Command`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum PipeFormat {
+    /// yuv4mpeg2 in I420, a stream that describes itself.
+    #[default]
+    Y4m,
+    /// The frames as they come, in the pixel format of the first one and
+    /// without row padding.
+    RawVideo,
+}
+
+/// Runs a program with the frames on its stdin, e.g. ffmpeg with the
+/// arguments of an existing workflow:
+/// `Command::ffmpeg(["-c:v", "libx264", "-preset", "veryfast", "out.mp4"])`.
+///
+/// The program starts with the first frame. Placeholders in its arguments
+/// are replaced then: `{width}`, `{height}`, `{framerate}` as `num/denom`
+/// and `{pix_fmt}`, ffmpeg's name of the pixel format. `finish` closes
+/// its stdin and waits for it to exit, a failure carries the last of what
+/// it wrote to stderr. A program that stops reading stops the sink, one
+/// still running when the sink is dropped is killed.
+#[derive(Debug)]
+pub struct Command {
+    program: OsString,
+    args: Vec<OsString>,
+    ffmpeg: bool,
+    format: PipeFormat,
+    framerate: (u32, u32),
+    running: Option<Running>,
+}
+
+#[derive(Debug)]
+struct Running {
+    child: Child,
+    input: Input,
+    stderr: Arc<Mutex<String>>,
+    reader: JoinHandle<()>,
+}
+
+#[derive(Debug)]
+enum Input {
+    Y4m(Y4m),
+    Raw {
+        stdin: BufWriter<ChildStdin>,
+        pixel_format: PixelFormat,
+        size: (u32, u32),
+    },
+}
+
+impl Command {
+    pub fn new<I>(program: impl Into<OsString>, args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        Command {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+            ffmpeg: false,
+            format: PipeFormat::Y4m,
+            framerate: (30, 1),
+            running: None,
+        }
+    }
+
+    /// `ffmpeg` from `PATH` reading the frames as its first input, `args`
+    /// add the outputs and anything else.
+    pub fn ffmpeg<I>(args: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<OsString>,
+    {
+        Command {
+            ffmpeg: true,
+            ..Command::new("ffmpeg", args)
+        }
+    }
+
+    pub fn with_format(mut self, format: PipeFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// The frame rate the program is told about, as numerator and
+    /// denominator, 30 per second by default.
+    pub fn with_framerate(mut self, framerate: (u32, u32)) -> Self {
+        self.framerate = (framerate.0.max(1), framerate.1.max(1));
+        self
+    }
+
+    fn spawn(&self, frame: &Frame<'_>) -> Result<Running> {
+        let mut args: Vec<OsString> = Vec::new();
+        if self.ffmpeg {
+            args.extend(["-hide_banner", "-loglevel", "error"].map(Into::into));
+            args.extend(
+                match self.format {
+                    PipeFormat::Y4m => &["-f", "yuv4mpegpipe"][..],
+                    PipeFormat::RawVideo => &[
+                        "-f",
+                        "rawvideo",
+                        "-pix_fmt",
+                        "{pix_fmt}",
+                        "-video_size",
+                        "{width}x{height}",
+                        "-framerate",
+                        "{framerate}",
+                    ],
+                }
+                .iter()
+                .map(Into::into),
+            );
+            args.extend(["-i", "pipe:0"].map(Into::into));
+        }
+        let pixel_format = match self.format {
+            PipeFormat::Y4m => PixelFormat::I420,
+            PipeFormat::RawVideo => frame.pixel_format(),
+        };
+        let placeholders = [
+            ("{width}", frame.width().to_string()),
+            ("{height}", frame.height().to_string()),
+            (
+                "{framerate}",
+                format!("{}/{}", self.framerate.0, self.framerate.1),
+            ),
+            ("{pix_fmt}", ffmpeg::input_format(pixel_format).to_string()),
+        ];
+        let args = args.iter().chain(&self.args).map(|arg| match arg.to_str() {
+            Some(arg) => placeholders
+                .iter()
+                .fold(arg.to_string(), |arg, (name, value)| {
+                    arg.replace(name, value)
+                })
+                .into(),
+            None => arg.clone(),
+        });
+
+        let mut child = tokio::process::Command::new(&self.program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::NotFound => ScreencastError::Unsupported(format!(
+                    "{} is not installed",
+                    self.program.to_string_lossy()
+                )),
+                _ => ScreencastError::Io(err),
+            })?;
+
+        let mut stderr = child.stderr.take().unwrap();
+        let messages = Arc::new(Mutex::new(String::new()));
+        let collected = messages.clone();
+        let reader = runtime::handle().spawn(async move {
+            let mut chunk = [0; 1024];
+            while let Ok(read @ 1..) = stderr.read(&mut chunk).await {
+                let mut messages = collected.lock().unwrap();
+                messages.push_str(&String::from_utf8_lossy(&chunk[..read]));
+                // only the latest messages matter
+                if messages.len() > 4096 {
+                    let cut = messages.ceil_char_boundary(messages.len() - 4096);
+                    messages.drain(..cut);
+                }
+            }
+        });
+
+        let stdin = child.stdin.take().unwrap();
+        let input = match self.format {
+            PipeFormat::Y4m => Input::Y4m(Y4m::from_writer(stdin).with_framerate(self.framerate)),
+            PipeFormat::RawVideo => Input::Raw {
+                stdin: BufWriter::new(stdin),
+                pixel_format,
+                size: (frame.width(), frame.height()),
+            },
+        };
+        Ok(Running {
+            child,
+            input,
+            stderr: messages,
+            reader,
+        })
+    }
+
+    /// Closes the program's input and waits for it to exit.
+    async fn wait(&mut self) -> Result<()> {
+        let Some(mut running) = self.running.take() else {
+            return Ok(());
+        };
+        let closed = match &mut running.input {
+            Input::Y4m(y4m) => y4m.finish().await,
+            Input::Raw { stdin, .. } => stdin.shutdown().await.map_err(Into::into),
+        };
+        drop(running.input);
+        let status = running.child.wait().await?;
+        let _ = running.reader.await;
+        if !status.success() {
+            let message = running.stderr.lock().unwrap().trim().to_string();
+            return Err(ScreencastError::Io(io::Error::other(format!(
+                "{} exited with {}: {}",
+                self.program.to_string_lossy(),
+                status,
+                message
+            ))));
+        }
+        // a program that exits early may not read everything
+        match closed {
+            Err(ScreencastError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+            closed => closed,
+        }
+    }
+}
+
+impl FrameSink for Command {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        compat(async {
+            if self.running.is_none() {
+                self.running = Some(self.spawn(&frame)?);
+            }
+            let Some(running) = &mut self.running else {
+                return Ok(());
+            };
+            let written = match &mut running.input {
+                Input::Y4m(y4m) => y4m.write_frame(frame).await,
+                Input::Raw {
+                    stdin,
+                    pixel_format,
+                    size,
+                } => {
+                    let data = raw(&frame, *pixel_format, *size)?;
+                    stdin.write_all(&data).await.map_err(Into::into)
+                }
+            };
+            match written {
+                Err(ScreencastError::Io(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+                    self.wait().await?;
+                    Err(ScreencastError::SinkClosed)
+                }
+                written => written,
+            }
+        })
+        .await
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        compat(self.wait()).await
+    }
+}
+
+/// The visible rows of every plane, without stride padding.
+fn raw(frame: &Frame<'_>, pixel_format: PixelFormat, size: (u32, u32)) -> Result<Vec<u8>> {
+    if (frame.width(), frame.height()) != size {
+        return Err(ScreencastError::Unsupported(format!(
+            "raw video frames of {}x{} after {}x{}",
+            frame.width(),
+            frame.height(),
+            size.0,
+            size.1
+        )));
+    }
+    let converted;
+    let frame = match frame.pixel_format() == pixel_format {
+        true => frame,
+        false => {
+            converted = convert::convert(frame, pixel_format)?;
+            &converted
+        }
+    };
+    let mut out = Vec::new();
+    for (index, plane) in frame.planes().iter().enumerate() {
+        let row = ffmpeg::row_bytes(pixel_format, index, size.0);
+        let data = frame.plane_data(index).unwrap_or_default();
+        for y in 0..Plane::rows(pixel_format, index, size.1) {
+            let bytes = data
+                .get(y * plane.stride..y * plane.stride + row)
+                .ok_or_else(|| {
+                    ScreencastError::Unsupported("frame data is too short".to_string())
+                })?;
+            out.extend_from_slice(bytes);
+        }
+    }
+    Ok(out)
+}
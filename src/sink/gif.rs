@@ -0,0 +1,500 @@
+use super::FrameSink;
+use super::anim::{Canvas, Decimate, Pending, changed};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::runtime::compat;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+
+/// Colours of a local colour table, one more index is left for transparency.
+const MAX_COLORS: usize = 255;
+
+/// Records an animated GIF that loops forever, e.g. a short demo of a UI
+/// for an issue tracker.
+///
+/// The capture is thinned out to 10 frames per second by default, every
+/// frame gets its own palette of up to 255 colours and is dithered unless
+/// `with_dither(false)`. Only the rectangle that changed since the last
+/// frame is stored, with the unchanged pixels in it transparent, and
+/// frames without changes only extend the previous one. Scale the frames
+/// down before, a GIF of a full monitor is large.
+/// The GIF takes the size of the first frame.
+#[derive(Debug)]
+pub struct Gif {
+    file: BufWriter<File>,
+    decimate: Decimate,
+    dither: bool,
+    max_size: Option<u64>,
+    canvas: Canvas,
+    pending: Option<Pending>,
+    position: u64,
+    full: bool,
+}
+
+impl Gif {
+    /// Creates or truncates `path`.
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = compat(File::create(path.as_ref())).await?;
+        Ok(Gif {
+            file: BufWriter::new(file),
+            decimate: Decimate::new(10),
+            dither: true,
+            max_size: None,
+            canvas: Canvas::new("GIF", u16::MAX as u32),
+            pending: None,
+            position: 0,
+            full: false,
+        })
+    }
+
+    /// Frames per second kept of the capture, at most 50, the finest delay
+    /// players honour.
+    pub fn with_framerate(mut self, fps: u32) -> Self {
+        self.decimate = self.decimate.with_framerate(fps.clamp(1, 50));
+        self
+    }
+
+    /// Ends the animation after `duration`, later frames are dropped.
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.decimate = self.decimate.with_limit(duration);
+        self
+    }
+
+    /// Ends the animation before the file grows past `bytes`, the frame
+    /// that does not fit and all later ones are dropped.
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Floyd-Steinberg dithering against banding in gradients and photos,
+    /// on by default. Flat UI colours compress better without.
+    pub fn with_dither(mut self, dither: bool) -> Self {
+        self.dither = dither;
+        self
+    }
+
+    /// Writes the pending frame, lasting until `until`.
+    async fn flush_pending(&mut self, until: Duration) -> Result<()> {
+        let Some(mut pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let delay = (centis(until) - centis(pending.time)).clamp(2, u16::MAX as u64) as u16;
+        pending.data[pending.delay..pending.delay + 2].copy_from_slice(&delay.to_le_bytes());
+        // room for the trailer
+        let end = self.position + pending.data.len() as u64 + 1;
+        if self.max_size.is_some_and(|max| end > max) {
+            self.full = true;
+            return Ok(());
+        }
+        self.file.write_all(&pending.data).await?;
+        self.position += pending.data.len() as u64;
+        Ok(())
+    }
+
+    /// The frame as a GIF image of what changed and the offset of its delay,
+    /// `None` without changes.
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Option<(Vec<u8>, usize)>> {
+        let previous = self.canvas.draw(frame)?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let current = self.canvas.pixels();
+        let first = previous.is_empty();
+
+        let mut out = Vec::new();
+        if first {
+            out.extend_from_slice(b"GIF89a");
+            out.extend_from_slice(&(width as u16).to_le_bytes());
+            out.extend_from_slice(&(height as u16).to_le_bytes());
+            // no global colour table, background 0, square pixels
+            out.extend_from_slice(&[0, 0, 0]);
+            // loops forever
+            out.extend_from_slice(&[0x21, 0xff, 11]);
+            out.extend_from_slice(b"NETSCAPE2.0");
+            out.extend_from_slice(&[3, 1, 0, 0, 0]);
+        }
+
+        let rgb =
+            |data: &[u8], index: usize| [data[index * 4], data[index * 4 + 1], data[index * 4 + 2]];
+        let unchanged =
+            |index: usize| !first && current[index * 4..][..4] == previous[index * 4..][..4];
+        let Some((left, top, right, bottom)) = changed(width, height, unchanged) else {
+            return Ok(None);
+        };
+        let (box_width, box_height) = (right - left, bottom - top);
+        let pixels = (top..bottom).flat_map(|y| (left..right).map(move |x| y * width + x));
+
+        let palette = median_cut(
+            pixels
+                .clone()
+                .filter(|&index| !unchanged(index))
+                .map(|index| rgb(current, index)),
+        );
+        let transparent = palette.len();
+        let bits = (usize::BITS - transparent.leading_zeros()).max(1);
+
+        // dithering errors of this and the next row, in sixteenths
+        let mut errors = vec![[0i32; 3]; box_width + 2];
+        let mut next_errors = vec![[0i32; 3]; box_width + 2];
+        let mut nearest = vec![u16::MAX; 1 << 15];
+        let mut indices = Vec::with_capacity(box_width * box_height);
+        for (at, index) in pixels.enumerate() {
+            let x = at % box_width;
+            if x == 0 && at > 0 {
+                std::mem::swap(&mut errors, &mut next_errors);
+                next_errors.fill([0; 3]);
+            }
+            if unchanged(index) {
+                indices.push(transparent as u8);
+                continue;
+            }
+            let mut color = rgb(current, index).map(i32::from);
+            if self.dither {
+                for (channel, error) in color.iter_mut().zip(errors[x + 1]) {
+                    *channel = (*channel + error / 16).clamp(0, 255);
+                }
+            }
+            let key = key(color.map(|channel| channel as u8));
+            if nearest[key] == u16::MAX {
+                nearest[key] = closest(&palette, color) as u16;
+            }
+            let chosen = nearest[key] as usize;
+            indices.push(chosen as u8);
+            if self.dither {
+                for channel in 0..3 {
+                    let error = color[channel] - palette[chosen][channel] as i32;
+                    errors[x + 2][channel] += error * 7;
+                    next_errors[x][channel] += error * 3;
+                    next_errors[x + 1][channel] += error * 5;
+                    next_errors[x + 2][channel] += error;
+                }
+            }
+        }
+
+        let delay = out.len() + 4;
+        let has_transparency = !first && indices.contains(&(transparent as u8));
+        // graphic control extension, left in place by the next frame
+        out.extend_from_slice(&[0x21, 0xf9, 4, 1 << 2 | has_transparency as u8, 0, 0]);
+        out.extend_from_slice(&[transparent as u8, 0]);
+        out.push(0x2c);
+        for value in [left, top, box_width, box_height] {
+            out.extend_from_slice(&(value as u16).to_le_bytes());
+        }
+        out.push(0x80 | (bits - 1) as u8);
+        for index in 0..1 << bits {
+            out.extend_from_slice(&palette.get(index).copied().unwrap_or_default());
+        }
+        let min_code_size = bits.max(2) as u8;
+        out.push(min_code_size);
+        for block in lzw(&indices, min_code_size).chunks(255) {
+            out.push(block.len() as u8);
+            out.extend_from_slice(block);
+        }
+        out.push(0);
+
+        Ok(Some((out, delay)))
+    }
+}
+
+impl FrameSink for Gif {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        if self.full || frame.is_dmabuf() {
+            return Ok(());
+        }
+        let Some(time) = self.decimate.accept(frame.pts()) else {
+            return Ok(());
+        };
+        let Some((data, delay)) = self.encode(&frame)? else {
+            return Ok(());
+        };
+        compat(async {
+            self.flush_pending(time).await?;
+            if !self.full {
+                self.pending = Some(Pending { time, data, delay });
+            }
+            Ok(())
+        })
+        .await
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            self.flush_pending(self.decimate.end()).await?;
+            if self.position > 0 {
+                self.file.write_all(&[0x3b]).await?;
+            }
+            self.file.flush().await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+fn centis(time: Duration) -> u64 {
+    (time.as_millis() as u64 + 5) / 10
+}
+
+/// The bin of a colour with 5 bits per channel.
+fn key(color: [u8; 3]) -> usize {
+    (color[0] as usize >> 3) << 10 | (color[1] as usize >> 3) << 5 | color[2] as usize >> 3
+}
+
+/// Colours that occur in the same bin of 5 bits per channel.
+#[derive(Debug, Copy, Clone, Default)]
+struct Bin {
+    key: u16,
+    count: u64,
+    sum: [u64; 3],
+}
+
+impl Bin {
+    fn channel(&self, channel: usize) -> u16 {
+        self.key >> (10 - channel * 5) & 0x1f
+    }
+}
+
+/// A palette of up to `MAX_COLORS` by median cut, splitting the box of
+/// bins with the widest weighted range until there are enough.
+fn median_cut(colors: impl Iterator<Item = [u8; 3]>) -> Vec<[u8; 3]> {
+    let mut histogram = vec![Bin::default(); 1 << 15];
+    for color in colors {
+        let bin = &mut histogram[key(color)];
+        bin.count += 1;
+        for (sum, value) in bin.sum.iter_mut().zip(color) {
+            *sum += value as u64;
+        }
+    }
+    let mut bins: Vec<Bin> = histogram
+        .into_iter()
+        .enumerate()
+        .filter(|(_, bin)| bin.count > 0)
+        .map(|(key, bin)| Bin {
+            key: key as u16,
+            ..bin
+        })
+        .collect();
+
+    let mut boxes = Vec::with_capacity(MAX_COLORS);
+    boxes.push(0..bins.len());
+    while boxes.len() < MAX_COLORS {
+        // the widest channel of each box, weighted by its pixels
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, range)| range.len() > 1)
+            .map(|(at, range)| {
+                let slice = &bins[range.clone()];
+                let count: u64 = slice.iter().map(|bin| bin.count).sum();
+                let (channel, extent) = (0..3)
+                    .map(|channel| {
+                        let values = slice.iter().map(|bin| bin.channel(channel));
+                        let extent = values.clone().max().unwrap_or(0) - values.min().unwrap_or(0);
+                        (channel, extent)
+                    })
+                    .max_by_key(|&(_, extent)| extent)
+                    .unwrap_or((0, 0));
+                (at, channel, extent as u64 * count)
+            })
+            .max_by_key(|&(_, _, score)| score);
+        let Some((at, channel, _)) = widest else {
+            break;
+        };
+        let range = boxes[at].clone();
+        let slice = &mut bins[range.clone()];
+        slice.sort_unstable_by_key(|bin| bin.channel(channel));
+        let half = slice.iter().map(|bin| bin.count).sum::<u64>() / 2;
+        let mut seen = 0;
+        let split = slice
+            .iter()
+            .position(|bin| {
+                seen += bin.count;
+                seen > half
+            })
+            .unwrap_or(0)
+            .clamp(1, slice.len() - 1);
+        boxes[at] = range.start..range.start + split;
+        boxes.push(range.start + split..range.end);
+    }
+
+    boxes
+        .into_iter()
+        .map(|range| {
+            let slice = &bins[range];
+            let count = slice.iter().map(|bin| bin.count).sum::<u64>().max(1);
+            std::array::from_fn(|channel| {
+                let sum: u64 = slice.iter().map(|bin| bin.sum[channel]).sum();
+                ((sum + count / 2) / count) as u8
+            })
+        })
+        .collect()
+}
+
+fn closest(palette: &[[u8; 3]], color: [i32; 3]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| {
+            (0..3)
+                .map(|channel| (color[channel] - entry[channel] as i32).pow(2))
+                .sum::<i32>()
+        })
+        .map_or(0, |(index, _)| index)
+}
+
+/// GIF flavoured LZW: codes grow from `min_code_size + 1` to 12 bits, least
+/// significant bit first, and the table starts over once full.
+fn lzw(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut out = Vec::with_capacity(indices.len() / 2);
+    let (mut bits, mut pending) = (0u32, 0u32);
+    let mut width = min_code_size as u32 + 1;
+    let mut next = end + 1;
+    // the code of a prefix code followed by an index, 0 for none
+    let mut table = vec![0u16; 4096 << 8];
+    let mut used = Vec::with_capacity(4096);
+
+    let mut emit = |code: u16, next: u16, width: &mut u32| {
+        while next as u32 > 1 << *width && *width < 12 {
+            *width += 1;
+        }
+        pending |= (code as u32) << bits;
+        bits += *width;
+        while bits >= 8 {
+            out.push(pending as u8);
+            pending >>= 8;
+            bits -= 8;
+        }
+    };
+
+    emit(clear, next, &mut width);
+    let Some((&first, rest)) = indices.split_first() else {
+        emit(end, next, &mut width);
+        return flush(out, pending, bits);
+    };
+    let mut prefix = first as u16;
+    for &index in rest {
+        let key = (prefix as usize) << 8 | index as usize;
+        if table[key] != 0 {
+            prefix = table[key];
+            continue;
+        }
+        emit(prefix, next, &mut width);
+        if next < 4096 {
+            table[key] = next;
+            used.push(key);
+            next += 1;
+        } else {
+            emit(clear, next, &mut width);
+            used.drain(..).for_each(|key| table[key] = 0);
+            next = end + 1;
+            width = min_code_size as u32 + 1;
+        }
+        prefix = index as u16;
+    }
+    emit(prefix, next, &mut width);
+    emit(end, next, &mut width);
+    flush(out, pending, bits)
+}
+
+fn flush(mut out: Vec<u8>, pending: u32, bits: u32) -> Vec<u8> {
+    if bits > 0 {
+        out.push(pending as u8);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// What a GIF decoder makes of the codes: the indices, the widths the
+    /// codes were read with and how often the table was cleared.
+    fn decode(data: &[u8], min_code_size: u8) -> (Vec<u8>, Vec<u32>, usize) {
+        let clear = 1usize << min_code_size;
+        let end = clear + 1;
+        let (mut out, mut widths, mut clears) = (Vec::new(), Vec::new(), 0);
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut width = min_code_size as u32 + 1;
+        let (mut position, mut bits) = (0usize, 0u32);
+        loop {
+            let mut code = 0usize;
+            for bit in 0..width {
+                let byte = data[position / 8];
+                code |= ((byte >> (position % 8)) as usize & 1) << bit;
+                position += 1;
+            }
+            bits += width;
+            widths.push(width);
+            if code == clear {
+                table = (0..clear + 2).map(|index| vec![index as u8]).collect();
+                width = min_code_size as u32 + 1;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == end {
+                break;
+            }
+            let entry = match table.get(code) {
+                Some(entry) => entry.clone(),
+                None => {
+                    assert_eq!(code, table.len());
+                    let previous = previous.as_ref().unwrap();
+                    [previous.as_slice(), &previous[..1]].concat()
+                }
+            };
+            out.extend_from_slice(&entry);
+            if let Some(previous) = previous
+                && table.len() < 4096
+            {
+                table.push([previous.as_slice(), &entry[..1]].concat());
+            }
+            if table.len() == 1 << width && width < 12 {
+                width += 1;
+            }
+            previous = Some(entry);
+        }
+        assert_eq!(data.len(), bits.div_ceil(8) as usize);
+        (out, widths, clears)
+    }
+
+    #[test]
+    fn lzw_code_sizes_grow() {
+        let indices = [0, 1, 0, 1, 0, 1, 0, 1, 2, 3, 2, 3];
+        let data = lzw(&indices, 2);
+        let (decoded, widths, clears) = decode(&data, 2);
+        assert_eq!(decoded, indices);
+        assert_eq!(clears, 1);
+        assert_eq!(widths, [3, 3, 3, 3, 4, 4, 4, 4, 4, 4]);
+
+        let (decoded, widths, _) = decode(&lzw(&[], 2), 2);
+        assert!(decoded.is_empty());
+        assert_eq!(widths, [3, 3]);
+    }
+
+    #[test]
+    fn lzw_clears_a_full_table() {
+        let mut state = 0x2545_f491u32;
+        let indices: Vec<u8> = (0..60_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 200) as u8
+            })
+            .collect();
+        let data = lzw(&indices, 8);
+        let (decoded, widths, clears) = decode(&data, 8);
+        assert_eq!(decoded, indices);
+        assert!(clears > 2, "{clears} clear codes");
+        assert_eq!(widths.iter().max(), Some(&12));
+        // after a clear the codes are back to 9 bits
+        let after = widths
+            .windows(2)
+            .filter(|pair| pair[0] == 12 && pair[1] == 9);
+        assert_eq!(after.count(), clears - 1);
+    }
+}
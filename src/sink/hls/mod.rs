@@ -0,0 +1,433 @@
+mod ts;
+
+use super::codec::BlockingEncoder;
+use super::http::{self, Request};
+use super::{FrameSink, Mp4File};
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime::{self, compat};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinHandle;
+use ts::TsMuxer;
+
+const PLAYLIST: &str = "index.m3u8";
+const INIT_SEGMENT: &str = "init.mp4";
+/// A connection to the server idle for this long is closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long the server waits after a failed accept, e.g. out of file
+/// descriptors, instead of failing again right away.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// The segments of an `Hls` stream.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum HlsFormat {
+    /// Fragmented MP4 after an init segment, of every codec but VP8 and
+    /// with AAC or Opus.
+    #[default]
+    Fmp4,
+    /// MPEG-TS, H.264 or HEVC and AAC, what older players take too.
+    Ts,
+}
+
+/// Streams to any number of viewers with standard players, from Safari
+/// to VLC and hls.js: the frames go into segments in a directory, listed
+/// by the playlist `index.m3u8` there. Serve the directory, or let `serve`
+/// do it.
+///
+/// A segment ends at the first keyframe past `segment_duration`. The
+/// playlist lists the last `playlist_length` of them, those before are
+/// deleted once they have been out of it as long, so players still on
+/// them can finish. A length of 0 keeps all, as an event playlist that can
+/// be watched from the start. `finish` ends the playlist.
+pub struct Hls {
+    directory: PathBuf,
+    encoder: BlockingEncoder,
+    audio: Option<AudioTrack>,
+    format: HlsFormat,
+    segment_duration: Duration,
+    playlist_length: usize,
+    size: Option<(u32, u32)>,
+    // from the first keyframe on
+    muxer: Option<Muxer>,
+    // whether the header of fMP4 went to the init segment
+    initialized: bool,
+    // the start of the segment being written, and the last pts in it
+    start: Option<Duration>,
+    last_pts: Duration,
+    index: u32,
+    // those on disk, the playlist lists the last of them
+    segments: VecDeque<Entry>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for Hls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hls")
+            .field("directory", &self.directory)
+            .field("config", self.encoder.config())
+            .field("format", &self.format)
+            .field("segments", &self.index)
+            .finish()
+    }
+}
+
+enum Muxer {
+    Fmp4(Box<Mp4File>),
+    Ts(TsMuxer),
+}
+
+#[derive(Debug)]
+struct Entry {
+    index: u32,
+    name: String,
+    duration: Duration,
+}
+
+impl Hls {
+    /// Writes into `directory`, created when missing, encoding with
+    /// `encode::default_encoder`. Without an interval in the config a
+    /// keyframe is made every two seconds, the default segment duration.
+    pub async fn create(directory: impl AsRef<Path>, config: EncoderConfig) -> Result<Self> {
+        let config = match config.keyframe_interval {
+            Some(_) => config,
+            None => {
+                let (num, denom) = config.framerate;
+                let interval = 2 * num.max(1) / denom.max(1);
+                config.with_keyframe_interval(interval)
+            }
+        };
+        Hls::create_with_encoder(directory, encode::default_encoder(config)?).await
+    }
+
+    pub async fn create_with_encoder(
+        directory: impl AsRef<Path>,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        let codec = encoder.config().codec;
+        if codec == Codec::Vp8 {
+            return Err(ScreencastError::Unsupported("VP8 in HLS".to_string()));
+        }
+        let directory = directory.as_ref().to_path_buf();
+        compat(tokio::fs::create_dir_all(&directory)).await?;
+        Ok(Hls {
+            directory,
+            encoder: BlockingEncoder::new(encoder),
+            audio: None,
+            format: HlsFormat::Fmp4,
+            segment_duration: Duration::from_secs(2),
+            playlist_length: 6,
+            size: None,
+            muxer: None,
+            initialized: false,
+            start: None,
+            last_pts: Duration::ZERO,
+            index: 0,
+            segments: VecDeque::new(),
+            server: None,
+        })
+    }
+
+    /// MPEG-TS takes H.264 or HEVC and AAC only.
+    pub fn with_format(mut self, format: HlsFormat) -> Result<Self> {
+        if format == HlsFormat::Ts {
+            let audio = self.audio.as_ref().map(|audio| audio.config().codec);
+            ts::check(self.encoder.config().codec, audio)?;
+        }
+        self.format = format;
+        Ok(self)
+    }
+
+    /// Adds an audio track, the audio up to each frame; AAC for MPEG-TS.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Result<Self> {
+        if self.format == HlsFormat::Ts && audio.config().codec != AudioCodec::Aac {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} in MPEG-TS",
+                audio.config().codec
+            )));
+        }
+        self.audio = Some(audio);
+        Ok(self)
+    }
+
+    pub fn with_segment_duration(mut self, duration: Duration) -> Self {
+        self.segment_duration = duration.max(Duration::from_secs(1));
+        self
+    }
+
+    /// Segments in the playlist, 0 for all of them.
+    pub fn with_playlist_length(mut self, length: usize) -> Self {
+        self.playlist_length = length;
+        self
+    }
+
+    pub fn playlist_path(&self) -> PathBuf {
+        self.directory.join(PLAYLIST)
+    }
+
+    /// Serves the directory over HTTP on `address`, e.g. `"0.0.0.0:8080"`,
+    /// until the sink is dropped: the playlist is at `/index.m3u8`, open
+    /// to pages of any origin. Returns the address bound.
+    pub async fn serve(&mut self, address: impl ToSocketAddrs) -> Result<SocketAddr> {
+        let listener = compat(TcpListener::bind(address)).await?;
+        let bound = listener.local_addr()?;
+        let task = runtime::handle().spawn(serve(listener, self.directory.clone()));
+        if let Some(server) = self.server.replace(task) {
+            server.abort();
+        }
+        Ok(bound)
+    }
+
+    async fn write_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        for packet in packets {
+            let start = match self.start {
+                Some(start) => start,
+                // segments start with a keyframe
+                None if packet.keyframe => *self.start.insert(packet.pts),
+                None => continue,
+            };
+            if packet.keyframe && packet.pts.saturating_sub(start) >= self.segment_duration {
+                let data = match self.muxer.as_mut() {
+                    Some(Muxer::Fmp4(mp4)) => {
+                        mp4.cut(packet.pts).await?;
+                        mp4.take_output()
+                    }
+                    Some(Muxer::Ts(ts)) => ts.cut(packet.pts)?,
+                    None => Vec::new(),
+                };
+                self.write_segment(data, packet.pts).await?;
+            }
+            self.last_pts = packet.pts;
+            self.mux(packet).await?;
+        }
+        Ok(())
+    }
+
+    async fn mux(&mut self, packet: EncodedPacket) -> Result<()> {
+        if self.muxer.is_none() {
+            let config = self.encoder.config().clone();
+            let audio = self.audio.take();
+            self.muxer = Some(match self.format {
+                HlsFormat::Fmp4 => {
+                    let mp4 = Mp4File::in_memory(config, self.segment_duration)?;
+                    Muxer::Fmp4(Box::new(match audio {
+                        Some(audio) => mp4.with_audio(audio),
+                        None => mp4,
+                    }))
+                }
+                HlsFormat::Ts => Muxer::Ts(TsMuxer::new(config.codec, audio)?),
+            });
+        }
+        match self.muxer.as_mut() {
+            Some(Muxer::Fmp4(mp4)) => {
+                let size = self.size.unwrap_or_default();
+                mp4.write_encoded(size, vec![packet]).await?;
+                // the header comes out with the first keyframe, the samples
+                // stay until the fragment is cut
+                if !self.initialized {
+                    let init = mp4.take_output();
+                    tokio::fs::write(self.directory.join(INIT_SEGMENT), init).await?;
+                    self.initialized = true;
+                }
+            }
+            Some(Muxer::Ts(ts)) => ts.write(&packet)?,
+            None => {}
+        }
+        Ok(())
+    }
+
+    /// Writes the segment that ends at `end`, and the playlist with it.
+    async fn write_segment(&mut self, data: Vec<u8>, end: Duration) -> Result<()> {
+        let Some(start) = self.start.replace(end) else {
+            return Ok(());
+        };
+        let extension = match self.format {
+            HlsFormat::Fmp4 => "m4s",
+            HlsFormat::Ts => "ts",
+        };
+        let name = format!("segment{:05}.{}", self.index, extension);
+        tokio::fs::write(self.directory.join(&name), data).await?;
+        self.segments.push_back(Entry {
+            index: self.index,
+            name,
+            duration: end.saturating_sub(start),
+        });
+        self.index += 1;
+        if self.playlist_length > 0 {
+            while self.segments.len() > 2 * self.playlist_length {
+                let Some(entry) = self.segments.pop_front() else {
+                    break;
+                };
+                // gone already is fine
+                let _ = tokio::fs::remove_file(self.directory.join(&entry.name)).await;
+            }
+        }
+        self.write_playlist(false).await
+    }
+
+    /// Replaces the playlist at once, players never see half of it.
+    async fn write_playlist(&mut self, ended: bool) -> Result<()> {
+        let listed = match self.playlist_length {
+            0 => self.segments.len(),
+            length => length.min(self.segments.len()),
+        };
+        let window = self.segments.range(self.segments.len() - listed..);
+        let longest = window
+            .clone()
+            .map(|entry| entry.duration)
+            .max()
+            .unwrap_or_default()
+            .max(self.segment_duration);
+        let version = match self.format {
+            HlsFormat::Fmp4 => 7,
+            HlsFormat::Ts => 3,
+        };
+        let mut playlist = format!(
+            "#EXTM3U\n#EXT-X-VERSION:{}\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:{}\n\
+             #EXT-X-INDEPENDENT-SEGMENTS\n",
+            version,
+            longest.as_secs_f64().round() as u64,
+            window.clone().next().map_or(0, |entry| entry.index)
+        );
+        if self.playlist_length == 0 {
+            playlist.push_str("#EXT-X-PLAYLIST-TYPE:EVENT\n");
+        }
+        if self.format == HlsFormat::Fmp4 {
+            playlist.push_str(&format!("#EXT-X-MAP:URI=\"{}\"\n", INIT_SEGMENT));
+        }
+        for entry in window {
+            playlist.push_str(&format!(
+                "#EXTINF:{:.3},\n{}\n",
+                entry.duration.as_secs_f64(),
+                entry.name
+            ));
+        }
+        if ended {
+            playlist.push_str("#EXT-X-ENDLIST\n");
+        }
+        let path = self.playlist_path();
+        let partial = self.directory.join(format!("{}.tmp", PLAYLIST));
+        tokio::fs::write(&partial, playlist).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    /// Writes the last segment, up to the end of the last frame.
+    async fn write_last_segment(&mut self) -> Result<()> {
+        if self.start.is_none() {
+            return Ok(());
+        }
+        let (num, denom) = self.encoder.config().framerate;
+        let frame = Duration::from_secs(denom.max(1) as u64) / num.max(1);
+        let end = self.last_pts + frame;
+        let data = match self.muxer.as_mut() {
+            Some(Muxer::Fmp4(mp4)) => {
+                mp4.finish().await?;
+                mp4.take_output()
+            }
+            Some(Muxer::Ts(ts)) => ts.cut(end)?,
+            None => return Ok(()),
+        };
+        self.write_segment(data, end).await
+    }
+}
+
+impl Drop for Hls {
+    fn drop(&mut self) {
+        if let Some(server) = &self.server {
+            server.abort();
+        }
+    }
+}
+
+impl FrameSink for Hls {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        self.size.get_or_insert((frame.width(), frame.height()));
+        let packets = self.encoder.encode(frame).await?;
+        compat(self.write_packets(packets)).await
+    }
+
+    /// Drains the encoder, writes the last segment and ends the playlist.
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            let written = match self.encoder.finish().await {
+                Ok(packets) => self.write_packets(packets).await,
+                Err(err) => Err(err),
+            };
+            self.write_last_segment().await?;
+            self.write_playlist(true).await?;
+            written
+        })
+        .await
+    }
+}
+
+async fn serve(listener: TcpListener, directory: PathBuf) {
+    loop {
+        match listener.accept().await {
+            Ok((tcp, _)) => {
+                runtime::handle().spawn(serve_files(tcp, directory.clone()));
+            }
+            Err(_) => tokio::time::sleep(ACCEPT_BACKOFF).await,
+        }
+    }
+}
+
+/// Answers the requests of a connection with the playlist and segments.
+async fn serve_files(mut tcp: TcpStream, directory: PathBuf) {
+    let mut buffer = Vec::new();
+    loop {
+        let request = tokio::time::timeout(IDLE_TIMEOUT, http::read_request(&mut tcp, &mut buffer));
+        let Ok(Some(request)) = request.await else {
+            return;
+        };
+        let (head, body) = respond(&request, &directory).await;
+        let sent = match request.method.as_str() {
+            "HEAD" => tcp.write_all(&head).await,
+            _ => match tcp.write_all(&head).await {
+                Ok(()) => tcp.write_all(&body).await,
+                Err(err) => Err(err),
+            },
+        };
+        if sent.is_err() || !request.keep_alive() {
+            return;
+        }
+    }
+}
+
+async fn respond(request: &Request, directory: &Path) -> (Vec<u8>, Vec<u8>) {
+    if request.method != "GET" && request.method != "HEAD" {
+        let head = http::response("405 Method Not Allowed", &[("Allow", "GET, HEAD")], Some(0));
+        return (head, Vec::new());
+    }
+    // the files of the stream only, nothing above the directory
+    let name = request.path.trim_start_matches('/');
+    let kind = match name.rsplit_once('.') {
+        _ if name.contains(['/', '\\']) || name.starts_with('.') => None,
+        Some((_, "m3u8")) => Some(("application/vnd.apple.mpegurl", "no-cache")),
+        Some((_, "m4s" | "mp4")) => Some(("video/mp4", "max-age=60")),
+        Some((_, "ts")) => Some(("video/mp2t", "max-age=60")),
+        _ => None,
+    };
+    let file = match kind {
+        Some(_) => tokio::fs::read(directory.join(name)).await.ok(),
+        None => None,
+    };
+    match (kind, file) {
+        (Some((content_type, cache)), Some(body)) => {
+            let headers = [
+                ("Content-Type", content_type),
+                ("Cache-Control", cache),
+                ("Access-Control-Allow-Origin", "*"),
+            ];
+            (http::response("200 OK", &headers, Some(body.len())), body)
+        }
+        _ => (http::response("404 Not Found", &[], Some(0)), Vec::new()),
+    }
+}
@@ -0,0 +1,480 @@
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::{Codec, EncodedPacket, bitstream};
+use crate::error::{Result, ScreencastError};
+use std::time::Duration;
+
+const PACKET_SIZE: usize = 188;
+const PMT_PID: u16 = 0x1000;
+const VIDEO_PID: u16 = 0x100;
+const AUDIO_PID: u16 = 0x101;
+/// Timestamps are a second ahead of the clock reference, the time decoders
+/// get to buffer.
+const PTS_OFFSET: u64 = 90_000;
+
+/// Multiplexes H.264 or HEVC and AAC into an MPEG transport stream, the
+/// segments of HLS as players have taken them all along: each one starts
+/// with the program tables, the video with access unit delimiters and the
+/// audio in ADTS frames.
+#[derive(Debug)]
+pub(super) struct TsMuxer {
+    codec: Codec,
+    audio: Option<AudioTrack>,
+    // of the PAT, PMT, video and audio
+    continuity: [u8; 4],
+    start: Option<Duration>,
+    output: Vec<u8>,
+}
+
+impl TsMuxer {
+    pub(super) fn new(codec: Codec, audio: Option<AudioTrack>) -> Result<Self> {
+        check(codec, audio.as_ref().map(|audio| audio.config().codec))?;
+        Ok(TsMuxer {
+            codec,
+            audio,
+            continuity: [0; 4],
+            start: None,
+            output: Vec::new(),
+        })
+    }
+
+    /// Appends a packet, with the audio up to it.
+    pub(super) fn write(&mut self, packet: &EncodedPacket) -> Result<()> {
+        let start = *self.start.get_or_insert(packet.pts);
+        self.write_audio(packet.pts)?;
+        if self.output.is_empty() {
+            self.write_tables();
+        }
+        let time = ticks(packet.pts.saturating_sub(start));
+        let mut data = Vec::with_capacity(packet.data.len() + 7);
+        let delimited = bitstream::nal_units(&packet.data)
+            .next()
+            .is_some_and(|nal| bitstream::nal_type(self.codec, nal) == aud_type(self.codec));
+        if !delimited {
+            match self.codec {
+                Codec::Hevc => data.extend([0, 0, 0, 1, 0x46, 0x01, 0x50]),
+                _ => data.extend([0, 0, 0, 1, 0x09, 0xf0]),
+            }
+        }
+        data.extend_from_slice(&packet.data);
+        // unbounded, video PES packets may be longer than the field allows
+        let pes = pes(0xe0, time + PTS_OFFSET, &data, false);
+        self.write_payload(2, &pes, Some(time), packet.keyframe);
+        Ok(())
+    }
+
+    /// Ends the segment before the packet at `next`: the audio up to it
+    /// joins, and what was written comes out.
+    pub(super) fn cut(&mut self, next: Duration) -> Result<Vec<u8>> {
+        self.write_audio(next)?;
+        Ok(std::mem::take(&mut self.output))
+    }
+
+    fn write_audio(&mut self, until: Duration) -> Result<()> {
+        let (Some(audio), Some(start)) = (&mut self.audio, self.start) else {
+            return Ok(());
+        };
+        let packets = audio.ready(until)?;
+        let config = audio.codec_private().to_vec();
+        for packet in packets {
+            let Some(time) = packet.pts.checked_sub(start) else {
+                continue;
+            };
+            if self.output.is_empty() {
+                self.write_tables();
+            }
+            let mut frame = adts_header(&config, packet.data.len()).to_vec();
+            frame.extend_from_slice(&packet.data);
+            let pes = pes(0xc0, ticks(time) + PTS_OFFSET, &frame, true);
+            self.write_payload(3, &pes, None, false);
+        }
+        Ok(())
+    }
+
+    /// The program association and program map tables, one program with
+    /// the video and the audio.
+    fn write_tables(&mut self) {
+        let mut pat = Vec::new();
+        pat.extend(1u16.to_be_bytes());
+        pat.extend((0xe000 | PMT_PID).to_be_bytes());
+        let pat = section(0x00, 1, &pat);
+
+        let mut pmt = Vec::new();
+        // the clock reference is on the video
+        pmt.extend((0xe000 | VIDEO_PID).to_be_bytes());
+        pmt.extend(0xf000u16.to_be_bytes());
+        let video_type = match self.codec {
+            Codec::Hevc => 0x24,
+            _ => 0x1b,
+        };
+        pmt.push(video_type);
+        pmt.extend((0xe000 | VIDEO_PID).to_be_bytes());
+        pmt.extend(0xf000u16.to_be_bytes());
+        if self.audio.is_some() {
+            // ADTS
+            pmt.push(0x0f);
+            pmt.extend((0xe000 | AUDIO_PID).to_be_bytes());
+            pmt.extend(0xf000u16.to_be_bytes());
+        }
+        let pmt = section(0x02, 1, &pmt);
+
+        for (stream, table) in [(0, pat), (1, pmt)] {
+            // a pointer field ahead, the rest of the packet stuffed
+            let mut payload = vec![0];
+            payload.extend(table);
+            payload.resize(PACKET_SIZE - 4, 0xff);
+            self.write_payload(stream, &payload, None, false);
+        }
+    }
+
+    /// Splits a table or PES packet over transport packets, the first one
+    /// with the clock reference and the random access flag when given.
+    fn write_payload(&mut self, stream: usize, payload: &[u8], pcr: Option<u64>, random: bool) {
+        let pid = [0, PMT_PID, VIDEO_PID, AUDIO_PID][stream];
+        let mut rest = payload;
+        let mut first = true;
+        while first || !rest.is_empty() {
+            // the flags and fields of the adaptation field, after its length
+            let mut adaptation: Option<Vec<u8>> = None;
+            if first && (pcr.is_some() || random) {
+                let mut field = vec![(random as u8) << 6 | (pcr.is_some() as u8) << 4];
+                if let Some(pcr) = pcr {
+                    field.extend(((pcr >> 1) as u32).to_be_bytes());
+                    field.extend([((pcr & 1) as u8) << 7 | 0x7e, 0]);
+                }
+                adaptation = Some(field);
+            }
+            let taken = adaptation.as_ref().map_or(0, |field| field.len() + 1);
+            let room = PACKET_SIZE - 4 - taken;
+            if rest.len() < room {
+                let stuffing = room - rest.len();
+                match &mut adaptation {
+                    Some(field) => field.extend(std::iter::repeat_n(0xff, stuffing)),
+                    // the length byte alone takes one
+                    None if stuffing == 1 => adaptation = Some(Vec::new()),
+                    None => {
+                        let mut field = vec![0];
+                        field.extend(std::iter::repeat_n(0xff, stuffing - 2));
+                        adaptation = Some(field);
+                    }
+                }
+            }
+            let (chunk, next) = rest.split_at(rest.len().min(room));
+            let control = match adaptation {
+                Some(_) => 0x30,
+                None => 0x10,
+            };
+            self.output.push(0x47);
+            self.output
+                .extend(((first as u16) << 14 | pid).to_be_bytes());
+            self.output.push(control | self.continuity[stream]);
+            self.continuity[stream] = (self.continuity[stream] + 1) & 0x0f;
+            if let Some(field) = adaptation {
+                self.output.push(field.len() as u8);
+                self.output.extend(field);
+            }
+            self.output.extend_from_slice(chunk);
+            rest = next;
+            first = false;
+        }
+    }
+}
+
+/// Fails for what transport streams of HLS do not carry.
+pub(super) fn check(codec: Codec, audio: Option<AudioCodec>) -> Result<()> {
+    if !matches!(codec, Codec::H264 | Codec::Hevc) {
+        return Err(ScreencastError::Unsupported(format!(
+            "{:?} in MPEG-TS",
+            codec
+        )));
+    }
+    if let Some(audio) = audio
+        && audio != AudioCodec::Aac
+    {
+        return Err(ScreencastError::Unsupported(format!(
+            "{:?} in MPEG-TS",
+            audio
+        )));
+    }
+    Ok(())
+}
+
+fn aud_type(codec: Codec) -> u8 {
+    match codec {
+        Codec::Hevc => 35,
+        _ => 9,
+    }
+}
+
+fn ticks(time: Duration) -> u64 {
+    (time.as_nanos() * 90_000 / 1_000_000_000) as u64
+}
+
+/// A PES packet with a presentation timestamp, its length left open when
+/// not `bounded`.
+fn pes(stream_id: u8, pts: u64, data: &[u8], bounded: bool) -> Vec<u8> {
+    let mut pes = Vec::with_capacity(14 + data.len());
+    pes.extend([0, 0, 1, stream_id]);
+    let length = 8 + data.len();
+    let length = match bounded && length <= u16::MAX as usize {
+        true => length as u16,
+        false => 0,
+    };
+    pes.extend(length.to_be_bytes());
+    // the PTS alone, in 5 bytes
+    pes.extend([0x80, 0x80, 5]);
+    let pts = pts & 0x1_ffff_ffff;
+    pes.push(0x21 | ((pts >> 29) as u8 & 0x0e));
+    pes.extend(((pts >> 14) as u16 | 1).to_be_bytes());
+    pes.extend(((pts << 1) as u16 | 1).to_be_bytes());
+    pes.extend_from_slice(data);
+    pes
+}
+
+/// A PSI section of `table_id` with its header and CRC around `body`.
+fn section(table_id: u8, id: u16, body: &[u8]) -> Vec<u8> {
+    let mut section = vec![table_id];
+    // syntax indicator, the length counts from after it to the CRC's end
+    section.extend((0xb000 | (5 + body.len() + 4) as u16).to_be_bytes());
+    section.extend(id.to_be_bytes());
+    // version 0, current, section 0 of 0
+    section.extend([0xc1, 0, 0]);
+    section.extend_from_slice(body);
+    let crc = crc32(&section);
+    section.extend(crc.to_be_bytes());
+    section
+}
+
+/// The CRC of MPEG-2 tables, unreflected unlike that of PNG.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = u32::MAX;
+    for &byte in data {
+        crc ^= (byte as u32) << 24;
+        for _ in 0..8 {
+            crc = match crc & 0x8000_0000 {
+                0 => crc << 1,
+                _ => crc << 1 ^ 0x04c1_1db7,
+            };
+        }
+    }
+    crc
+}
+
+/// The header of an ADTS frame of `length` bytes of AAC, from the
+/// AudioSpecificConfig.
+fn adts_header(config: &[u8], length: usize) -> [u8; 7] {
+    let first = config.first().copied().unwrap_or(0x10);
+    let second = config.get(1).copied().unwrap_or(0);
+    let profile = (first >> 3).saturating_sub(1) & 0x03;
+    let frequency = (first & 0x07) << 1 | second >> 7;
+    let channels = second >> 3 & 0x0f;
+    let length = length + 7;
+    [
+        0xff,
+        // MPEG-4, no CRC
+        0xf1,
+        profile << 6 | frequency << 2 | channels >> 2,
+        (channels & 0x03) << 6 | (length >> 11) as u8 & 0x03,
+        (length >> 3) as u8,
+        ((length & 0x07) as u8) << 5 | 0x1f,
+        0xfc,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::{AudioConfig, AudioPacket};
+
+    fn video(millis: u64, keyframe: bool) -> EncodedPacket {
+        let nals: &[&[u8]] = match keyframe {
+            true => &[
+                &[0x67, 0x42, 0xc0, 0x1e, 0x8c, 0x8d, 0x40],
+                &[0x68, 0xce, 0x3c, 0x80],
+                &[0x65, 0x88, 0x84, 0x21, 0xa0],
+            ],
+            false => &[&[0x41, 0x9a, 0x02, 0x04]],
+        };
+        EncodedPacket {
+            codec: Codec::H264,
+            // large enough for the PES packet to span transport packets
+            data: nals
+                .iter()
+                .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+                .chain(std::iter::repeat_n(millis as u8, 300))
+                .collect(),
+            pts: Duration::from_millis(millis),
+            keyframe,
+        }
+    }
+
+    fn audio(millis: u64) -> AudioPacket {
+        AudioPacket {
+            codec: AudioCodec::Aac,
+            data: vec![millis as u8; 24],
+            pts: Duration::from_millis(millis),
+            duration: Duration::from_millis(21),
+        }
+    }
+
+    /// A table, or a PES packet with its clock reference and random access
+    /// flag, reassembled from the transport packets of its PID.
+    #[derive(Debug)]
+    struct Unit {
+        pid: u16,
+        pcr: Option<u64>,
+        random: bool,
+        payload: Vec<u8>,
+    }
+
+    /// The units of a segment, each packet checked for its size, sync byte
+    /// and continuity against the `continuity` counters of the PIDs.
+    fn units(segment: &[u8], continuity: &mut [(u16, u8)]) -> Vec<Unit> {
+        assert_eq!(segment.len() % PACKET_SIZE, 0);
+        let mut units: Vec<Unit> = Vec::new();
+        for packet in segment.chunks(PACKET_SIZE) {
+            assert_eq!(packet[0], 0x47);
+            let start = packet[1] & 0x40 != 0;
+            let pid = u16::from_be_bytes([packet[1], packet[2]]) & 0x1fff;
+            let control = packet[3] >> 4;
+            let counter = continuity
+                .iter_mut()
+                .find(|(other, _)| *other == pid)
+                .unwrap_or_else(|| panic!("unexpected PID {pid:x}"));
+            assert_eq!(packet[3] & 0x0f, counter.1, "continuity of PID {pid:x}");
+            counter.1 = (counter.1 + 1) & 0x0f;
+
+            let mut payload = &packet[4..];
+            let (mut pcr, mut random) = (None, false);
+            if control & 0x02 != 0 {
+                let length = payload[0] as usize;
+                let field = &payload[1..1 + length];
+                if let Some(&flags) = field.first() {
+                    random = flags & 0x40 != 0;
+                    if flags & 0x10 != 0 {
+                        let base = u32::from_be_bytes(field[1..5].try_into().unwrap());
+                        pcr = Some((base as u64) << 1 | (field[5] >> 7) as u64);
+                    }
+                }
+                payload = &payload[1 + length..];
+            }
+            assert!(control & 0x01 != 0, "packet without payload");
+            if start {
+                units.push(Unit {
+                    pid,
+                    pcr,
+                    random,
+                    payload: payload.to_vec(),
+                });
+            } else {
+                let unit = units.last_mut().expect("continuation without a start");
+                assert_eq!(unit.pid, pid);
+                assert!(pcr.is_none() && !random);
+                unit.payload.extend_from_slice(payload);
+            }
+        }
+        units
+    }
+
+    /// The body of a section after its pointer field, its CRC checked.
+    fn section_body(unit: &Unit, table_id: u8) -> &[u8] {
+        assert_eq!(unit.payload[0], 0);
+        let section = &unit.payload[1..];
+        assert_eq!(section[0], table_id);
+        let length = (u16::from_be_bytes([section[1], section[2]]) & 0x0fff) as usize;
+        let section = &section[..3 + length];
+        // the CRC over a section with its CRC leaves nothing
+        assert_eq!(crc32(section), 0);
+        assert!(
+            unit.payload[1 + section.len()..]
+                .iter()
+                .all(|&byte| byte == 0xff)
+        );
+        &section[8..section.len() - 4]
+    }
+
+    /// The stream id, PTS and data of a PES packet.
+    fn pes_fields(unit: &Unit) -> (u8, u64, &[u8]) {
+        let pes = &unit.payload;
+        assert_eq!(pes[..3], [0, 0, 1]);
+        assert_eq!(pes[6..9], [0x80, 0x80, 5]);
+        let pts = &pes[9..14];
+        assert_eq!(pts[0] & 0xf1, 0x21);
+        let pts = ((pts[0] >> 1 & 0x07) as u64) << 30
+            | ((pts[1] as u64) << 7 | (pts[2] >> 1) as u64) << 15
+            | ((pts[3] as u64) << 7 | (pts[4] >> 1) as u64);
+        let length = u16::from_be_bytes([pes[4], pes[5]]) as usize;
+        if length > 0 {
+            assert_eq!(length, pes.len() - 6);
+        }
+        (pes[3], pts, &pes[14..])
+    }
+
+    #[test]
+    fn segment_layout() {
+        let packets = [1000, 1021, 1042, 1063, 1084].map(audio).to_vec();
+        let track = AudioTrack::from_packets(AudioConfig::new(AudioCodec::Aac), packets);
+        let mut muxer = TsMuxer::new(Codec::H264, Some(track)).unwrap();
+        let frames = [video(1000, true), video(1033, false), video(1066, false)];
+        for frame in &frames {
+            muxer.write(frame).unwrap();
+        }
+        let first = muxer.cut(Duration::from_millis(1100)).unwrap();
+        let next = video(1100, true);
+        muxer.write(&next).unwrap();
+        let second = muxer.cut(Duration::from_millis(1133)).unwrap();
+
+        let mut continuity = [(0, 0), (PMT_PID, 0), (VIDEO_PID, 0), (AUDIO_PID, 0)];
+        let first = units(&first, &mut continuity);
+        // the counters go on in the next segment
+        let second = units(&second, &mut continuity);
+        let pids: Vec<u16> = first.iter().map(|unit| unit.pid).collect();
+        let (a, v) = (AUDIO_PID, VIDEO_PID);
+        // the audio up to each frame ahead of it
+        assert_eq!(pids, [0, PMT_PID, a, v, a, v, a, a, v, a]);
+        let pids: Vec<u16> = second.iter().map(|unit| unit.pid).collect();
+        assert_eq!(pids, [0, PMT_PID, v]);
+
+        for units in [&first, &second] {
+            // program 1 on the PMT's PID
+            let pat = section_body(&units[0], 0x00);
+            assert_eq!(pat, [0, 1, 0xf0, 0x00]);
+            // the clock on the video, then H.264 and ADTS AAC
+            let pmt = section_body(&units[1], 0x02);
+            assert_eq!(
+                pmt,
+                [
+                    0xe1, 0x00, 0xf0, 0x00, 0x1b, 0xe1, 0x00, 0xf0, 0x00, 0x0f, 0xe1, 0x01, 0xf0,
+                    0x00
+                ]
+            );
+        }
+
+        let videos = first.iter().chain(&second).filter(|unit| unit.pid == v);
+        for (unit, frame) in videos.zip(frames.iter().chain([&next])) {
+            let (stream_id, pts, data) = pes_fields(unit);
+            assert_eq!(stream_id, 0xe0);
+            let time = (frame.pts.as_millis() as u64 - 1000) * 90;
+            assert_eq!(pts, time + PTS_OFFSET);
+            assert_eq!(unit.pcr, Some(time));
+            assert_eq!(unit.random, frame.keyframe);
+            // an access unit delimiter ahead of each frame
+            assert_eq!(data, [&[0, 0, 0, 1, 0x09, 0xf0][..], &frame.data].concat());
+        }
+
+        let audios = first.iter().filter(|unit| unit.pid == a);
+        for (unit, millis) in audios.zip([1000u64, 1021, 1042, 1063, 1084]) {
+            let (stream_id, pts, data) = pes_fields(unit);
+            assert_eq!(stream_id, 0xc0);
+            assert_eq!(pts, (millis - 1000) * 90 + PTS_OFFSET);
+            assert!(unit.pcr.is_none() && !unit.random);
+            // an ADTS header of the frame's whole length, then the packet
+            let (header, frame) = data.split_at(7);
+            assert_eq!(header[..2], [0xff, 0xf1]);
+            let length = ((header[3] & 0x03) as usize) << 11
+                | (header[4] as usize) << 3
+                | (header[5] >> 5) as usize;
+            assert_eq!(length, data.len());
+            assert_eq!(frame, audio(millis).data);
+        }
+    }
+}
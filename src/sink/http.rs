@@ -0,0 +1,89 @@
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Heads larger than this close the connection.
+const MAX_HEAD: usize = 16 * 1024;
+
+/// The head of a request of a viewer, what the sinks serving over HTTP
+/// look at. Bodies are not read.
+#[derive(Debug)]
+pub(super) struct Request {
+    pub method: String,
+    /// Without the query.
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Whether the connection stays open for another request.
+    pub(super) fn keep_alive(&self) -> bool {
+        let close = self
+            .header("Connection")
+            .is_some_and(|connection| connection.eq_ignore_ascii_case("close"));
+        !close && self.header("Upgrade").is_none()
+    }
+}
+
+/// Reads the next request of a connection, `None` at its end or when it
+/// sent something else. `buffer` keeps what came after it.
+pub(super) async fn read_request(
+    reader: &mut (impl AsyncRead + Unpin),
+    buffer: &mut Vec<u8>,
+) -> Option<Request> {
+    let mut received = [0u8; 4096];
+    let end = loop {
+        if let Some(end) = buffer.windows(4).position(|window| window == b"\r\n\r\n") {
+            break end;
+        }
+        if buffer.len() > MAX_HEAD {
+            return None;
+        }
+        match reader.read(&mut received).await {
+            Ok(0) | Err(_) => return None,
+            Ok(length) => buffer.extend_from_slice(&received[..length]),
+        }
+    };
+    let head = String::from_utf8_lossy(&buffer[..end]).into_owned();
+    buffer.drain(..end + 4);
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    if !request_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+    let path = target
+        .split(['?', '#'])
+        .next()
+        .unwrap_or_default()
+        .to_string();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Some(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+/// The head of a response, with a `Content-Length` when the length of the
+/// body is known.
+pub(super) fn response(status: &str, headers: &[(&str, &str)], length: Option<usize>) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {}\r\nServer: xdp-screencast\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    if let Some(length) = length {
+        head.push_str(&format!("Content-Length: {}\r\n", length));
+    }
+    head.push_str("\r\n");
+    head.into_bytes()
+}
@@ -0,0 +1,257 @@
+use super::anim::Decimate;
+use super::http::{self, Request};
+use super::{FrameSink, rtp};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::image::{self, ImageFormat};
+use crate::runtime::{self, compat};
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs};
+use tokio::task::JoinHandle;
+
+const BOUNDARY: &str = "frame";
+/// Connections without a request for this long are closed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Viewers that take longer for a frame are dropped.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Pictures queued for each viewer, a slow one skips the older ones.
+const QUEUE: usize = 2;
+/// How long the server waits after a failed accept, e.g. out of file
+/// descriptors, instead of failing again right away.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Serves the capture as a Motion JPEG stream over HTTP, a
+/// `multipart/x-mixed-replace` response that browsers show in an `<img>`,
+/// e.g. to preview a capture or put it on a dashboard.
+///
+/// Frames are thinned out to 10 per second by default and only encoded
+/// while someone watches, every viewer gets the same pictures. Scale the
+/// frames down before for many viewers or a slow network, every picture
+/// is sent whole.
+///
+/// The stream is at any path, and there is no authentication: bind to a
+/// loopback address to keep it to this host.
+pub struct MjpegServer {
+    address: SocketAddr,
+    quality: u8,
+    decimate: Decimate,
+    sender: Sender<Arc<Vec<u8>>>,
+    // the last picture, for viewers joining while the capture is still
+    latest: Arc<Mutex<Option<Arc<Vec<u8>>>>>,
+    task: JoinHandle<()>,
+}
+
+impl std::fmt::Debug for MjpegServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MjpegServer")
+            .field("address", &self.address)
+            .field("quality", &self.quality)
+            .field("viewers", &self.viewers())
+            .finish()
+    }
+}
+
+impl MjpegServer {
+    /// Listens on `address`, e.g. `"127.0.0.1:8080"`.
+    pub async fn bind(address: impl ToSocketAddrs) -> Result<Self> {
+        compat(async {
+            let listener = TcpListener::bind(address).await?;
+            let address = listener.local_addr()?;
+            let (mut sender, receiver) = async_broadcast::broadcast(QUEUE);
+            sender.set_overflow(true);
+            let latest = Arc::new(Mutex::new(None));
+            let task =
+                runtime::handle().spawn(accept(listener, receiver.deactivate(), latest.clone()));
+            Ok(MjpegServer {
+                address,
+                quality: 75,
+                decimate: Decimate::new(10),
+                sender,
+                latest,
+                task,
+            })
+        })
+        .await
+    }
+
+    /// Pictures per second sent, at most 60.
+    pub fn with_framerate(mut self, fps: u32) -> Self {
+        self.decimate = Decimate::new(fps.clamp(1, 60));
+        self
+    }
+
+    /// JPEG quality from 1 to 100, 75 by default.
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality.clamp(1, 100);
+        self
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The URL viewers open, with the address of the default route for a
+    /// server on all addresses.
+    pub fn url(&self) -> String {
+        format!("http://{}/", rtp::reachable_address(self.address))
+    }
+
+    /// Viewers connected to the stream.
+    pub fn viewers(&self) -> usize {
+        self.sender.receiver_count()
+    }
+}
+
+impl Drop for MjpegServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl FrameSink for MjpegServer {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        if self.viewers() == 0 {
+            // a viewer joining later waits for a fresh picture
+            self.latest.lock().unwrap().take();
+            return Ok(());
+        }
+        if self.decimate.accept(frame.pts()).is_none() {
+            return Ok(());
+        }
+        let jpeg = Arc::new(image::encode(
+            &frame,
+            ImageFormat::Jpeg {
+                quality: self.quality,
+            },
+        )?);
+        *self.latest.lock().unwrap() = Some(jpeg.clone());
+        // with no one left to take it, that is fine too
+        let _ = self.sender.try_broadcast(jpeg);
+        Ok(())
+    }
+
+    /// Ends the streams of the viewers and stops listening.
+    async fn finish(&mut self) -> Result<()> {
+        self.sender.close();
+        self.task.abort();
+        Ok(())
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    receiver: InactiveReceiver<Arc<Vec<u8>>>,
+    latest: Arc<Mutex<Option<Arc<Vec<u8>>>>>,
+) {
+    loop {
+        match listener.accept().await {
+            Ok((tcp, _)) => {
+                let _ = tcp.set_nodelay(true);
+                runtime::handle().spawn(serve(tcp, receiver.clone(), latest.clone()));
+            }
+            Err(_) => tokio::time::sleep(ACCEPT_BACKOFF).await,
+        }
+    }
+}
+
+/// Answers the request of a connection with the stream, which only ends
+/// with the capture or the viewer.
+async fn serve(
+    mut tcp: TcpStream,
+    receiver: InactiveReceiver<Arc<Vec<u8>>>,
+    latest: Arc<Mutex<Option<Arc<Vec<u8>>>>>,
+) {
+    let mut buffer = Vec::new();
+    loop {
+        let request = tokio::time::timeout(IDLE_TIMEOUT, http::read_request(&mut tcp, &mut buffer));
+        let Ok(Some(request)) = request.await else {
+            return;
+        };
+        let Some(head) = refuse(&request) else {
+            break;
+        };
+        if tcp.write_all(&head).await.is_err() || !request.keep_alive() {
+            return;
+        }
+    }
+    // taking pictures before the head goes out, none are missed
+    let mut pictures = receiver.activate();
+    let content_type = format!("multipart/x-mixed-replace; boundary={}", BOUNDARY);
+    let headers = [
+        ("Content-Type", content_type.as_str()),
+        ("Cache-Control", "no-cache, no-store"),
+        ("Pragma", "no-cache"),
+        ("Access-Control-Allow-Origin", "*"),
+        ("Connection", "close"),
+    ];
+    let head = http::response("200 OK", &headers, None);
+    if tcp.write_all(&head).await.is_err() {
+        return;
+    }
+    let first = latest.lock().unwrap().clone();
+    if let Some(picture) = first
+        && send(&mut tcp, &picture).await.is_err()
+    {
+        return;
+    }
+    while let Some(picture) = next(&mut pictures).await {
+        if send(&mut tcp, &picture).await.is_err() {
+            return;
+        }
+    }
+    let end = format!("--{}--\r\n", BOUNDARY);
+    let _ = tcp.write_all(end.as_bytes()).await;
+}
+
+/// The response to a request that does not get the stream, `None` for
+/// one that does.
+fn refuse(request: &Request) -> Option<Vec<u8>> {
+    match request.method.as_str() {
+        "GET" => None,
+        "HEAD" => {
+            let content_type = format!("multipart/x-mixed-replace; boundary={}", BOUNDARY);
+            Some(http::response(
+                "200 OK",
+                &[("Content-Type", &content_type)],
+                None,
+            ))
+        }
+        _ => Some(http::response(
+            "405 Method Not Allowed",
+            &[("Allow", "GET, HEAD")],
+            Some(0),
+        )),
+    }
+}
+
+/// The next picture, skipping those a slow viewer missed, `None` once the
+/// capture ended.
+async fn next(pictures: &mut Receiver<Arc<Vec<u8>>>) -> Option<Arc<Vec<u8>>> {
+    loop {
+        match pictures.recv_direct().await {
+            Ok(picture) => return Some(picture),
+            Err(RecvError::Overflowed(_)) => continue,
+            Err(RecvError::Closed) => return None,
+        }
+    }
+}
+
+/// Writes a part of the stream, failing for a viewer gone or too slow.
+async fn send(tcp: &mut TcpStream, picture: &[u8]) -> std::io::Result<()> {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        picture.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(picture);
+    part.extend_from_slice(b"\r\n");
+    match tokio::time::timeout(WRITE_TIMEOUT, tcp.write_all(&part)).await {
+        Ok(written) => written,
+        Err(_) => Err(std::io::ErrorKind::TimedOut.into()),
+    }
+}
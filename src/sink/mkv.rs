@@ -0,0 +1,1012 @@
+use super::FrameSink;
+use super::codec::{self, BlockingEncoder};
+use crate::audio::{AudioCodec, AudioSource, AudioTrack};
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::recorder::{Markers, Metadata};
+use crate::runtime::compat;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+
+const EBML: u32 = 0x1a45_dfa3;
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114d_9b74;
+const SEEK: u32 = 0x4dbb;
+const SEEK_ID: u32 = 0x53ab;
+const SEEK_POSITION: u32 = 0x53ac;
+const INFO: u32 = 0x1549_a966;
+const TIMESTAMP_SCALE: u32 = 0x2ad7b1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4d80;
+const WRITING_APP: u32 = 0x5741;
+const TITLE: u32 = 0x7ba9;
+const TRACKS: u32 = 0x1654_ae6b;
+const TRACK_ENTRY: u32 = 0xae;
+const TRACK_NUMBER: u32 = 0xd7;
+const TRACK_UID: u32 = 0x73c5;
+const TRACK_TYPE: u32 = 0x83;
+const NAME: u32 = 0x536e;
+const FLAG_LACING: u32 = 0x9c;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63a2;
+const DEFAULT_DURATION: u32 = 0x23e383;
+const VIDEO: u32 = 0xe0;
+const PIXEL_WIDTH: u32 = 0xb0;
+const PIXEL_HEIGHT: u32 = 0xba;
+const CODEC_DELAY: u32 = 0x56aa;
+const SEEK_PRE_ROLL: u32 = 0x56bb;
+const AUDIO: u32 = 0xe1;
+const SAMPLING_FREQUENCY: u32 = 0xb5;
+const CHANNELS: u32 = 0x9f;
+const CLUSTER: u32 = 0x1f43_b675;
+const TIMESTAMP: u32 = 0xe7;
+const SIMPLE_BLOCK: u32 = 0xa3;
+const CUES: u32 = 0x1c53_bb6b;
+const CUE_POINT: u32 = 0xbb;
+const CUE_TIME: u32 = 0xb3;
+const CUE_TRACK_POSITIONS: u32 = 0xb7;
+const CUE_TRACK: u32 = 0xf7;
+const CUE_CLUSTER_POSITION: u32 = 0xf1;
+const CUE_RELATIVE_POSITION: u32 = 0xf0;
+const CHAPTERS: u32 = 0x1043_a770;
+const EDITION_ENTRY: u32 = 0x45b9;
+const CHAPTER_ATOM: u32 = 0xb6;
+const CHAPTER_UID: u32 = 0x73c4;
+const CHAPTER_TIME_START: u32 = 0x91;
+const CHAPTER_DISPLAY: u32 = 0x80;
+const CHAP_STRING: u32 = 0x85;
+const TAGS: u32 = 0x1254_c367;
+const TAG: u32 = 0x7373;
+const TARGETS: u32 = 0x63c0;
+const SIMPLE_TAG: u32 = 0x67c8;
+const TAG_NAME: u32 = 0x45a3;
+const TAG_STRING: u32 = 0x4487;
+const VOID: u32 = 0xec;
+
+/// Bytes kept free at the start of the segment for the seek head, enough
+/// for five entries with large positions.
+const SEEK_HEAD_SPACE: usize = 128;
+
+/// Records to a Matroska file, with any codec the encoders produce, or to
+/// WebM, see `create_webm`, or only the audio, see `create_audio`.
+///
+/// Clusters of a few seconds are written as they fill up and the segment
+/// is left open until `finish` adds the cues and the duration, so a
+/// recording that was cut short plays up to its last cluster.
+pub struct MkvFile {
+    file: BufWriter<File>,
+    // none for a file of audio alone
+    encoder: Option<BlockingEncoder>,
+    doc_type: &'static str,
+    codec: Option<Codec>,
+    size: Option<(u32, u32)>,
+    start: Option<Duration>,
+    // file offsets of the segment's data and of the duration, offsets of
+    // the info and the tracks in the segment
+    segment: u64,
+    duration: u64,
+    info: u64,
+    tracks: u64,
+    position: u64,
+    cluster: Cluster,
+    cues: Vec<Cue>,
+    // time of the last video block, or of the last frame of a file of
+    // audio alone, in milliseconds, and the time between those frames
+    last: u64,
+    interval: Duration,
+    markers: Option<Markers>,
+    metadata: Metadata,
+    audio: Vec<AudioTrack>,
+}
+
+impl std::fmt::Debug for MkvFile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MkvFile")
+            .field(
+                "config",
+                &self.encoder.as_ref().map(|encoder| encoder.config()),
+            )
+            .field("doc_type", &self.doc_type)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+/// The cluster being collected, its size is only known when it is full.
+#[derive(Debug, Default)]
+struct Cluster {
+    time: Option<u64>,
+    body: Vec<u8>,
+}
+
+impl Cluster {
+    /// A SimpleBlock of `track` at `relative` milliseconds from the cluster.
+    fn write_block(&mut self, track: u8, relative: i64, keyframe: bool, data: &[u8]) {
+        write_element(&mut self.body, SIMPLE_BLOCK, |block| {
+            block.push(0x80 | track);
+            block.extend((relative.clamp(i16::MIN as i64, i16::MAX as i64) as i16).to_be_bytes());
+            block.push(if keyframe { 0x80 } else { 0 });
+            block.extend_from_slice(data);
+        });
+    }
+}
+
+#[derive(Debug)]
+struct Cue {
+    time: u64,
+    track: u64,
+    // of the cluster in the segment and of the block in the cluster
+    cluster: u64,
+    block: u64,
+}
+
+impl MkvFile {
+    /// Creates or truncates `path`, encoding with `encode::default_encoder`.
+    pub async fn create(path: impl AsRef<Path>, config: EncoderConfig) -> Result<Self> {
+        MkvFile::create_with_encoder(path, encode::default_encoder(config)?).await
+    }
+
+    pub async fn create_with_encoder(
+        path: impl AsRef<Path>,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        MkvFile::open(path.as_ref(), Some(encoder), "matroska").await
+    }
+
+    /// A Matroska file of audio alone, `.mka`, e.g. the narration next to
+    /// the video to edit on its own. The frames written are not stored but
+    /// keep the time: the audio starts with the first one and ends with the
+    /// last, as in a file of the video recorded alongside. More tracks
+    /// follow with `with_audio`.
+    pub async fn create_audio(path: impl AsRef<Path>, audio: AudioTrack) -> Result<Self> {
+        let file = MkvFile::open(path.as_ref(), None, "matroska").await?;
+        file.with_audio(audio)
+    }
+
+    /// A WebM file, the Matroska subset browsers play. Only VP9 and AV1
+    /// are accepted, audio tracks would be Opus.
+    pub async fn create_webm(path: impl AsRef<Path>, config: EncoderConfig) -> Result<Self> {
+        webm_codec(config.codec)?;
+        MkvFile::create_webm_with_encoder(path, encode::default_encoder(config)?).await
+    }
+
+    pub async fn create_webm_with_encoder(
+        path: impl AsRef<Path>,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        webm_codec(encoder.config().codec)?;
+        MkvFile::open(path.as_ref(), Some(encoder), "webm").await
+    }
+
+    /// Adds a track with the audio packets up to the time of each frame,
+    /// those from before the first frame are left out. Each call adds one
+    /// more, e.g. for the tracks of `AudioTrack::spawn_all`. WebM only takes
+    /// Opus.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Result<Self> {
+        if self.doc_type == "webm" && audio.config().codec != AudioCodec::Opus {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} in WebM",
+                audio.config().codec
+            )));
+        }
+        self.audio.push(audio);
+        Ok(self)
+    }
+
+    /// Writes the markers as chapters when the recording is finished.
+    pub fn with_markers(mut self, markers: Markers) -> Self {
+        self.markers = Some(markers);
+        self
+    }
+
+    /// Sets the title and writing application of the segment, the author
+    /// and other tags follow in `finish`.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    async fn open(
+        path: &Path,
+        encoder: Option<Box<dyn Encoder>>,
+        doc_type: &'static str,
+    ) -> Result<Self> {
+        let file = compat(File::create(path)).await?;
+        Ok(MkvFile {
+            file: BufWriter::new(file),
+            codec: encoder.as_ref().map(|encoder| encoder.config().codec),
+            encoder: encoder.map(BlockingEncoder::new),
+            doc_type,
+            size: None,
+            start: None,
+            segment: 0,
+            duration: 0,
+            info: 0,
+            tracks: 0,
+            position: 0,
+            cluster: Cluster::default(),
+            cues: Vec::new(),
+            last: 0,
+            interval: Duration::from_millis(33),
+            markers: None,
+            metadata: Metadata::default(),
+            audio: Vec::new(),
+        })
+    }
+
+    /// The EBML header and the start of the segment: room for the seek
+    /// head, the segment info and the track.
+    async fn write_header(&mut self, codec_private: Option<&[u8]>) -> Result<()> {
+        let mut header = Vec::new();
+        write_element(&mut header, EBML, |ebml| {
+            write_uint(ebml, 0x4286, 1);
+            write_uint(ebml, 0x42f7, 1);
+            write_uint(ebml, 0x42f2, 4);
+            write_uint(ebml, 0x42f3, 8);
+            write_string(ebml, 0x4282, self.doc_type);
+            write_uint(ebml, 0x4287, 4);
+            write_uint(ebml, 0x4285, 2);
+        });
+        write_id(&mut header, SEGMENT);
+        // unknown size until `finish`
+        header.extend(0x01ff_ffff_ffff_ffffu64.to_be_bytes());
+        self.segment = header.len() as u64;
+        write_void(&mut header, SEEK_HEAD_SPACE);
+
+        let mut info = Vec::new();
+        // first, so the float follows its 3 byte header
+        write_float(&mut info, DURATION, 0.0);
+        write_uint(&mut info, TIMESTAMP_SCALE, 1_000_000);
+        write_string(&mut info, MUXING_APP, "xdp-screencast");
+        let application = self.metadata.application.as_deref();
+        write_string(
+            &mut info,
+            WRITING_APP,
+            application.unwrap_or("xdp-screencast"),
+        );
+        if let Some(title) = &self.metadata.title {
+            write_string(&mut info, TITLE, title);
+        }
+        self.info = header.len() as u64 - self.segment;
+        write_bytes(&mut header, INFO, &info);
+        self.duration = (header.len() - info.len() + 3) as u64;
+
+        let (width, height) = self.size.unwrap_or_default();
+        self.tracks = header.len() as u64 - self.segment;
+        write_element(&mut header, TRACKS, |tracks| {
+            if let Some(codec) = self.codec {
+                write_element(tracks, TRACK_ENTRY, |track| {
+                    write_uint(track, TRACK_NUMBER, 1);
+                    write_uint(track, TRACK_UID, 1);
+                    write_uint(track, TRACK_TYPE, 1);
+                    write_uint(track, FLAG_LACING, 0);
+                    write_string(track, CODEC_ID, codec_id(codec));
+                    if let Some(codec_private) = codec_private {
+                        write_bytes(track, CODEC_PRIVATE, codec_private);
+                    }
+                    write_uint(
+                        track,
+                        DEFAULT_DURATION,
+                        self.frame_interval().as_nanos() as u64,
+                    );
+                    write_element(track, VIDEO, |video| {
+                        write_uint(video, PIXEL_WIDTH, width as u64);
+                        write_uint(video, PIXEL_HEIGHT, height as u64);
+                    });
+                });
+            }
+            for (number, audio) in (self.first_audio_track() as u64..).zip(&self.audio) {
+                write_element(tracks, TRACK_ENTRY, |track| {
+                    write_uint(track, TRACK_NUMBER, number);
+                    write_uint(track, TRACK_UID, number);
+                    write_uint(track, TRACK_TYPE, 2);
+                    write_uint(track, FLAG_LACING, 0);
+                    if let Some(source) = audio.source() {
+                        write_string(track, NAME, source_name(source));
+                    }
+                    write_string(track, CODEC_ID, audio_codec_id(audio.config().codec));
+                    write_bytes(track, CODEC_PRIVATE, audio.codec_private());
+                    if audio.config().codec == AudioCodec::Opus {
+                        write_uint(track, CODEC_DELAY, audio.pre_skip().as_nanos() as u64);
+                        write_uint(track, SEEK_PRE_ROLL, 80_000_000);
+                    }
+                    write_element(track, AUDIO, |audio_element| {
+                        write_float(
+                            audio_element,
+                            SAMPLING_FREQUENCY,
+                            audio.output_rate() as f64,
+                        );
+                        write_uint(audio_element, CHANNELS, audio.config().channels as u64);
+                    });
+                });
+            }
+        });
+        self.file.write_all(&header).await?;
+        self.position = header.len() as u64;
+        Ok(())
+    }
+
+    /// Writes packets encoded elsewhere, of frames of `size`.
+    pub(super) async fn write_encoded(
+        &mut self,
+        size: (u32, u32),
+        packets: Vec<EncodedPacket>,
+    ) -> Result<()> {
+        self.size.get_or_insert(size);
+        compat(self.write_packets(packets)).await
+    }
+
+    async fn write_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        let Some(codec) = self.codec else {
+            return Ok(());
+        };
+        for packet in packets {
+            if self.position == 0 {
+                // nothing decodes before the first keyframe
+                if !packet.keyframe {
+                    continue;
+                }
+                // VP8 and VP9 describe themselves in every keyframe
+                let codec_private = match codec {
+                    Codec::Vp8 | Codec::Vp9 => None,
+                    codec => Some(codec::decoder_config(codec, &packet.data).ok_or_else(|| {
+                        ScreencastError::Encoder(format!(
+                            "the first {:?} keyframe lacks the stream headers",
+                            codec
+                        ))
+                    })?),
+                };
+                self.write_header(codec_private.as_deref()).await?;
+            }
+            let start = *self.start.get_or_insert(packet.pts);
+            let time = packet.pts.saturating_sub(start).as_millis() as u64;
+            // the audio up to the frame goes first, into the cluster before it
+            self.write_audio(packet.pts).await?;
+            if let Some(cluster_time) = self.cluster.time {
+                let length = time.saturating_sub(cluster_time);
+                // clusters start at keyframes where possible, the block
+                // timestamps are 16 bit
+                if length >= 5_000 || packet.keyframe && length >= 1_000 {
+                    self.write_cluster().await?;
+                }
+            }
+            let cluster_time = *self.cluster.time.get_or_insert_with(|| {
+                write_uint(&mut self.cluster.body, TIMESTAMP, time);
+                time
+            });
+            if packet.keyframe {
+                self.cues.push(Cue {
+                    time,
+                    track: 1,
+                    cluster: self.position - self.segment,
+                    block: self.cluster.body.len() as u64,
+                });
+            }
+            let data = codec::sample_data(codec, &packet.data);
+            self.cluster
+                .write_block(1, time as i64 - cluster_time as i64, packet.keyframe, &data);
+            self.last = time;
+        }
+        Ok(())
+    }
+
+    /// Writes the audio packets encoded so far that start by `until`.
+    async fn write_audio(&mut self, until: Duration) -> Result<()> {
+        let mut packets = Vec::new();
+        for (number, audio) in (self.first_audio_track()..).zip(&mut self.audio) {
+            packets.extend(
+                audio
+                    .ready(until)?
+                    .into_iter()
+                    .map(|packet| (number, packet)),
+            );
+        }
+        // the tracks interleaved in time
+        packets.sort_by_key(|(_, packet)| packet.pts);
+        let start = self.start.unwrap_or_default();
+        for (number, packet) in packets {
+            let Some(time) = packet.pts.checked_sub(start) else {
+                continue;
+            };
+            let time = time.as_millis() as u64;
+            if self
+                .cluster
+                .time
+                .is_some_and(|cluster_time| time.saturating_sub(cluster_time) >= 5_000)
+            {
+                self.write_cluster().await?;
+            }
+            let new_cluster = self.cluster.time.is_none();
+            let cluster_time = *self.cluster.time.get_or_insert_with(|| {
+                write_uint(&mut self.cluster.body, TIMESTAMP, time);
+                time
+            });
+            // without video the clusters start with audio to seek to
+            if new_cluster && self.encoder.is_none() {
+                self.cues.push(Cue {
+                    time,
+                    track: number as u64,
+                    cluster: self.position - self.segment,
+                    block: self.cluster.body.len() as u64,
+                });
+            }
+            self.cluster.write_block(
+                number,
+                time as i64 - cluster_time as i64,
+                true,
+                &packet.data,
+            );
+        }
+        Ok(())
+    }
+
+    /// Keeps the time in a file of audio alone: writes the audio up to the
+    /// frame at `pts`.
+    async fn write_clock(&mut self, pts: Option<Duration>) -> Result<()> {
+        let Some(pts) = pts else {
+            return Ok(());
+        };
+        if self.position == 0 {
+            self.write_header(None).await?;
+        }
+        let start = *self.start.get_or_insert(pts);
+        let time = pts.saturating_sub(start).as_millis() as u64;
+        if time > self.last {
+            self.interval = Duration::from_millis(time - self.last).min(Duration::from_secs(1));
+            self.last = time;
+        }
+        self.write_audio(pts).await
+    }
+
+    /// The time of a frame, the nominal one of the video or as measured
+    /// between the frames of a file of audio alone.
+    fn frame_interval(&self) -> Duration {
+        match &self.encoder {
+            Some(encoder) => {
+                let (num, denom) = encoder.config().framerate;
+                Duration::from_secs(denom.max(1) as u64) / num.max(1)
+            }
+            None => self.interval,
+        }
+    }
+
+    /// The number of the first audio track, after the video.
+    fn first_audio_track(&self) -> u8 {
+        1 + self.encoder.is_some() as u8
+    }
+
+    async fn write_cluster(&mut self) -> Result<()> {
+        if self.cluster.time.take().is_none() {
+            return Ok(());
+        }
+        let mut cluster = Vec::with_capacity(self.cluster.body.len() + 12);
+        write_id(&mut cluster, CLUSTER);
+        write_size(&mut cluster, self.cluster.body.len() as u64);
+        cluster.append(&mut self.cluster.body);
+        self.file.write_all(&cluster).await?;
+        self.file.flush().await?;
+        self.position += cluster.len() as u64;
+        Ok(())
+    }
+
+    /// Writes the cues and fills in the seek head, the duration and the
+    /// segment size.
+    async fn close(&mut self) -> Result<()> {
+        self.write_cluster().await?;
+        let mut cues = Vec::new();
+        write_element(&mut cues, CUES, |cues| {
+            for cue in &self.cues {
+                write_element(cues, CUE_POINT, |point| {
+                    write_uint(point, CUE_TIME, cue.time);
+                    write_element(point, CUE_TRACK_POSITIONS, |positions| {
+                        write_uint(positions, CUE_TRACK, cue.track);
+                        write_uint(positions, CUE_CLUSTER_POSITION, cue.cluster);
+                        write_uint(positions, CUE_RELATIVE_POSITION, cue.block);
+                    });
+                });
+            }
+        });
+        let cues_position = self.position - self.segment;
+        self.file.write_all(&cues).await?;
+        self.position += cues.len() as u64;
+
+        let markers = self.markers.as_ref().map(Markers::list).unwrap_or_default();
+        let chapters_position = self.position - self.segment;
+        if !markers.is_empty() {
+            let mut chapters = Vec::new();
+            write_element(&mut chapters, CHAPTERS, |chapters| {
+                write_element(chapters, EDITION_ENTRY, |edition| {
+                    for (uid, marker) in (1..).zip(&markers) {
+                        write_element(edition, CHAPTER_ATOM, |atom| {
+                            write_uint(atom, CHAPTER_UID, uid);
+                            write_uint(atom, CHAPTER_TIME_START, marker.time.as_nanos() as u64);
+                            write_element(atom, CHAPTER_DISPLAY, |display| {
+                                write_string(display, CHAP_STRING, &marker.label)
+                            });
+                        });
+                    }
+                });
+            });
+            self.file.write_all(&chapters).await?;
+            self.position += chapters.len() as u64;
+        }
+
+        let mut tags = Vec::new();
+        let author = self
+            .metadata
+            .author
+            .as_ref()
+            .map(|author| ("ARTIST", author));
+        let simple_tags: Vec<(&str, &String)> = author
+            .into_iter()
+            .chain(
+                self.metadata
+                    .tags
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value)),
+            )
+            .collect();
+        let tags_position = self.position - self.segment;
+        if !simple_tags.is_empty() {
+            write_element(&mut tags, TAGS, |tags| {
+                write_element(tags, TAG, |tag| {
+                    // no targets, of the whole segment
+                    write_element(tag, TARGETS, |_| {});
+                    for (name, value) in simple_tags {
+                        write_element(tag, SIMPLE_TAG, |simple| {
+                            write_string(simple, TAG_NAME, name);
+                            write_string(simple, TAG_STRING, value);
+                        });
+                    }
+                });
+            });
+            self.file.write_all(&tags).await?;
+            self.position += tags.len() as u64;
+        }
+
+        let mut seek_head = Vec::new();
+        write_element(&mut seek_head, SEEK_HEAD, |head| {
+            let chapters = (!markers.is_empty()).then_some((CHAPTERS, chapters_position));
+            let tags = (!tags.is_empty()).then_some((TAGS, tags_position));
+            for (id, position) in [
+                (INFO, self.info),
+                (TRACKS, self.tracks),
+                (CUES, cues_position),
+            ]
+            .into_iter()
+            .chain(chapters)
+            .chain(tags)
+            {
+                write_element(head, SEEK, |seek| {
+                    write_bytes(seek, SEEK_ID, &id.to_be_bytes());
+                    write_uint(seek, SEEK_POSITION, position);
+                });
+            }
+        });
+        let filler = SEEK_HEAD_SPACE - seek_head.len();
+        write_void(&mut seek_head, filler);
+        let duration = self.last as f64 + self.frame_interval().as_secs_f64() * 1000.0;
+        let segment_size = self.position - self.segment;
+        for (position, bytes) in [
+            (self.segment, seek_head),
+            (self.duration, duration.to_be_bytes().to_vec()),
+            (
+                self.segment - 8,
+                (segment_size | 1 << 56).to_be_bytes().to_vec(),
+            ),
+        ] {
+            self.file.seek(SeekFrom::Start(position)).await?;
+            self.file.write_all(&bytes).await?;
+        }
+        self.file.flush().await?;
+        Ok(())
+    }
+}
+
+impl FrameSink for MkvFile {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let Some(encoder) = &mut self.encoder else {
+            return compat(self.write_clock(frame.pts())).await;
+        };
+        self.size.get_or_insert((frame.width(), frame.height()));
+        let packets = encoder.encode(frame).await?;
+        compat(self.write_packets(packets)).await
+    }
+
+    /// Drains the encoder and closes the segment, also of a recording that
+    /// failed on the way.
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            let finished = match &mut self.encoder {
+                Some(encoder) => Some(encoder.finish().await),
+                None => None,
+            };
+            let written = match finished {
+                Some(Ok(packets)) => self.write_packets(packets).await,
+                Some(Err(err)) => Err(err),
+                None => Ok(()),
+            };
+            if self.position == 0 {
+                self.write_header(None).await?;
+            } else if let Some(start) = self.start {
+                // the audio until the last frame ends
+                let end = start + Duration::from_millis(self.last) + self.frame_interval();
+                self.write_audio(end).await?;
+            }
+            self.close().await?;
+            written
+        })
+        .await
+    }
+}
+
+fn webm_codec(codec: Codec) -> Result<()> {
+    match codec {
+        Codec::Vp9 | Codec::Av1 => Ok(()),
+        codec => Err(ScreencastError::Unsupported(format!("{:?} in WebM", codec))),
+    }
+}
+
+fn codec_id(codec: Codec) -> &'static str {
+    match codec {
+        Codec::H264 => "V_MPEG4/ISO/AVC",
+        Codec::Hevc => "V_MPEGH/ISO/HEVC",
+        Codec::Vp8 => "V_VP8",
+        Codec::Vp9 => "V_VP9",
+        Codec::Av1 => "V_AV1",
+    }
+}
+
+fn source_name(source: &AudioSource) -> &str {
+    match source {
+        AudioSource::Desktop => "Desktop",
+        AudioSource::Microphone => "Microphone",
+        AudioSource::Application(name) => name,
+    }
+}
+
+fn audio_codec_id(codec: AudioCodec) -> &'static str {
+    match codec {
+        AudioCodec::Opus => "A_OPUS",
+        AudioCodec::Aac => "A_AAC",
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+/// The shortest variable length size, all ones is reserved for unknown.
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    let length = (1..=8)
+        .find(|length| size < (1 << (7 * length)) - 1)
+        .unwrap_or(8);
+    let marked = size | 1 << (7 * length);
+    out.extend_from_slice(&marked.to_be_bytes()[8 - length..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, body: impl FnOnce(&mut Vec<u8>)) {
+    let mut content = Vec::new();
+    body(&mut content);
+    write_bytes(out, id, &content);
+}
+
+fn write_bytes(out: &mut Vec<u8>, id: u32, bytes: &[u8]) {
+    write_id(out, id);
+    write_size(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(7);
+    write_bytes(out, id, &bytes[skip..]);
+}
+
+fn write_string(out: &mut Vec<u8>, id: u32, value: &str) {
+    write_bytes(out, id, value.as_bytes());
+}
+
+fn write_float(out: &mut Vec<u8>, id: u32, value: f64) {
+    write_bytes(out, id, &value.to_be_bytes());
+}
+
+/// A Void element taking exactly `length` bytes, at least 2.
+fn write_void(out: &mut Vec<u8>, length: usize) {
+    write_id(out, VOID);
+    let body = match length - 2 {
+        body @ ..127 => {
+            out.push(0x80 | body as u8);
+            body
+        }
+        _ => {
+            out.extend(((length - 9) as u64 | 1 << 56).to_be_bytes());
+            length - 9
+        }
+    };
+    out.resize(out.len() + body, 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::audio::AudioConfig;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0x8c, 0x8d, 0x40];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+    /// Three frames 33 ms apart, a keyframe with its parameter sets first.
+    fn packets() -> Vec<EncodedPacket> {
+        let annex_b = |nals: &[&[u8]]| -> Vec<u8> {
+            nals.iter()
+                .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+                .collect()
+        };
+        let frames: [(&[&[u8]], bool); 3] = [
+            (&[SPS, PPS, &[0x65, 0x88, 0x84, 0x21, 0xa0]], true),
+            (&[&[0x41, 0x9a, 0x02, 0x04]], false),
+            (&[&[0x41, 0x9a, 0x04, 0x08, 0x10]], false),
+        ];
+        (0..)
+            .zip(frames)
+            .map(|(index, (nals, keyframe))| EncodedPacket {
+                codec: Codec::H264,
+                data: annex_b(nals),
+                pts: Duration::from_millis(1000 + 33 * index),
+                keyframe,
+            })
+            .collect()
+    }
+
+    struct Element<'a> {
+        depth: usize,
+        id: u32,
+        // offsets of the element and of its body in the file
+        start: usize,
+        data: usize,
+        body: &'a [u8],
+    }
+
+    /// A variable length integer and its length, with the marker bit kept
+    /// for IDs.
+    fn vint(data: &[u8], keep_marker: bool) -> (u64, usize) {
+        let length = data[0].leading_zeros() as usize + 1;
+        assert!(length <= 8, "invalid variable length integer");
+        let value = data[..length]
+            .iter()
+            .fold(0u64, |value, &byte| value << 8 | byte as u64);
+        match keep_marker {
+            true => (value, length),
+            false => (value & !(1 << (7 * length)), length),
+        }
+    }
+
+    fn uint(body: &[u8]) -> u64 {
+        body.iter().fold(0, |value, &byte| value << 8 | byte as u64)
+    }
+
+    /// Every element, parents before their children, each checked to fit
+    /// its parent.
+    fn elements(file: &[u8]) -> Vec<Element<'_>> {
+        fn walk<'a>(
+            file: &'a [u8],
+            range: (usize, usize),
+            depth: usize,
+            out: &mut Vec<Element<'a>>,
+        ) {
+            let (mut position, end) = range;
+            while position < end {
+                let (id, id_length) = vint(&file[position..], true);
+                let (size, size_length) = vint(&file[position + id_length..], false);
+                let data = position + id_length + size_length;
+                assert_ne!(
+                    size,
+                    (1 << (7 * size_length)) - 1,
+                    "size of {id:x} left unknown"
+                );
+                let next = data + size as usize;
+                assert!(next <= end, "element {id:x} overruns its parent");
+                let id = id as u32;
+                out.push(Element {
+                    depth,
+                    id,
+                    start: position,
+                    data,
+                    body: &file[data..next],
+                });
+                if matches!(
+                    id,
+                    EBML | SEGMENT
+                        | SEEK_HEAD
+                        | SEEK
+                        | INFO
+                        | TRACKS
+                        | TRACK_ENTRY
+                        | VIDEO
+                        | CLUSTER
+                        | CUES
+                        | CUE_POINT
+                        | CUE_TRACK_POSITIONS
+                ) {
+                    walk(file, (data, next), depth + 1, out);
+                }
+                position = next;
+            }
+        }
+        let mut out = Vec::new();
+        walk(file, (0, file.len()), 0, &mut out);
+        out
+    }
+
+    fn find<'a>(elements: &'a [Element<'a>], id: u32) -> &'a Element<'a> {
+        elements
+            .iter()
+            .find(|element| element.id == id)
+            .unwrap_or_else(|| panic!("no element {id:x}"))
+    }
+
+    #[tokio::test]
+    async fn matroska_layout() {
+        let path = std::env::temp_dir().join(format!("xdp-screencast-{}.mkv", std::process::id()));
+        let encoder = Box::new(codec::Encoded(EncoderConfig::new(Codec::H264)));
+        let mut mkv = MkvFile::create_with_encoder(&path, encoder).await.unwrap();
+        mkv.write_encoded((64, 48), packets()).await.unwrap();
+        mkv.finish().await.unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let elements = elements(&file);
+
+        let layout: Vec<(usize, u32)> = elements
+            .iter()
+            .map(|element| (element.depth, element.id))
+            .collect();
+        let seek = [(2, SEEK), (3, SEEK_ID), (3, SEEK_POSITION)];
+        let block = (2, SIMPLE_BLOCK);
+        let expected: Vec<(usize, u32)> = [
+            (0, EBML),
+            (1, 0x4286),
+            (1, 0x42f7),
+            (1, 0x42f2),
+            (1, 0x42f3),
+            (1, 0x4282),
+            (1, 0x4287),
+            (1, 0x4285),
+            (0, SEGMENT),
+            (1, SEEK_HEAD),
+        ]
+        .into_iter()
+        .chain(seek.repeat(3))
+        .chain([
+            (1, VOID),
+            (1, INFO),
+            (2, DURATION),
+            (2, TIMESTAMP_SCALE),
+            (2, MUXING_APP),
+            (2, WRITING_APP),
+            (1, TRACKS),
+            (2, TRACK_ENTRY),
+            (3, TRACK_NUMBER),
+            (3, TRACK_UID),
+            (3, TRACK_TYPE),
+            (3, FLAG_LACING),
+            (3, CODEC_ID),
+            (3, CODEC_PRIVATE),
+            (3, DEFAULT_DURATION),
+            (3, VIDEO),
+            (4, PIXEL_WIDTH),
+            (4, PIXEL_HEIGHT),
+            (1, CLUSTER),
+            (2, TIMESTAMP),
+            block,
+            block,
+            block,
+            (1, CUES),
+            (2, CUE_POINT),
+            (3, CUE_TIME),
+            (3, CUE_TRACK_POSITIONS),
+            (4, CUE_TRACK),
+            (4, CUE_CLUSTER_POSITION),
+            (4, CUE_RELATIVE_POSITION),
+        ])
+        .collect();
+        assert_eq!(layout, expected);
+        assert_eq!(find(&elements, 0x4282).body, b"matroska");
+
+        // the segment size is filled in, the seek head fills its space and
+        // points at its elements
+        let segment = find(&elements, SEGMENT).data;
+        assert_eq!(segment + find(&elements, SEGMENT).body.len(), file.len());
+        assert_eq!(find(&elements, INFO).start - segment, SEEK_HEAD_SPACE);
+        let ids = elements.iter().filter(|element| element.id == SEEK_ID);
+        let positions = elements
+            .iter()
+            .filter(|element| element.id == SEEK_POSITION);
+        for (id, position) in ids.zip(positions) {
+            let (target, _) = vint(&file[segment + uint(position.body) as usize..], true);
+            assert_eq!(target.to_be_bytes()[8 - id.body.len()..], *id.body);
+        }
+
+        // 66 ms to the last frame and its 60 fps duration
+        let duration = f64::from_be_bytes(find(&elements, DURATION).body.try_into().unwrap());
+        assert!((duration - (66.0 + 1000.0 / 60.0)).abs() < 1e-6);
+        assert_eq!(find(&elements, CODEC_ID).body, b"V_MPEG4/ISO/AVC");
+        let mut config = vec![1, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0, SPS.len() as u8];
+        config.extend_from_slice(SPS);
+        config.extend([1, 0, PPS.len() as u8]);
+        config.extend_from_slice(PPS);
+        assert_eq!(find(&elements, CODEC_PRIVATE).body, config);
+        assert_eq!(uint(find(&elements, DEFAULT_DURATION).body), 16_666_666);
+        assert_eq!(uint(find(&elements, PIXEL_WIDTH).body), 64);
+        assert_eq!(uint(find(&elements, PIXEL_HEIGHT).body), 48);
+
+        // track 1, the time from the cluster, the keyframe flag, then the
+        // length prefixed NAL units
+        assert_eq!(uint(find(&elements, TIMESTAMP).body), 0);
+        let blocks = elements.iter().filter(|element| element.id == SIMPLE_BLOCK);
+        for ((block, packet), time) in blocks.zip(packets()).zip([0u16, 33, 66]) {
+            assert_eq!(block.body[0], 0x81);
+            assert_eq!(block.body[1..3], time.to_be_bytes());
+            assert_eq!(block.body[3], if packet.keyframe { 0x80 } else { 0 });
+            assert_eq!(
+                block.body[4..],
+                codec::sample_data(Codec::H264, &packet.data)
+            );
+        }
+
+        // the cue points at the keyframe's block
+        let cluster = find(&elements, CLUSTER);
+        assert_eq!(uint(find(&elements, CUE_TIME).body), 0);
+        assert_eq!(uint(find(&elements, CUE_TRACK).body), 1);
+        let cluster_position = uint(find(&elements, CUE_CLUSTER_POSITION).body) as usize;
+        assert_eq!(segment + cluster_position, cluster.start);
+        let relative = uint(find(&elements, CUE_RELATIVE_POSITION).body) as usize;
+        assert_eq!(cluster.data + relative, find(&elements, SIMPLE_BLOCK).start);
+    }
+
+    #[tokio::test]
+    async fn webm_profile() {
+        let path = std::env::temp_dir().join(format!("xdp-screencast-{}.webm", std::process::id()));
+        let h264 = Box::new(codec::Encoded(EncoderConfig::new(Codec::H264)));
+        assert!(matches!(
+            MkvFile::create_webm_with_encoder(&path, h264).await,
+            Err(ScreencastError::Unsupported(_))
+        ));
+
+        let vp9 = Box::new(codec::Encoded(EncoderConfig::new(Codec::Vp9)));
+        let webm = MkvFile::create_webm_with_encoder(&path, vp9).await.unwrap();
+        let aac = AudioTrack::from_packets(AudioConfig::new(AudioCodec::Aac), Vec::new());
+        assert!(matches!(
+            webm.with_audio(aac),
+            Err(ScreencastError::Unsupported(_))
+        ));
+
+        let vp9 = Box::new(codec::Encoded(EncoderConfig::new(Codec::Vp9)));
+        let mut webm = MkvFile::create_webm_with_encoder(&path, vp9).await.unwrap();
+        let keyframe = EncodedPacket {
+            codec: Codec::Vp9,
+            data: vec![0x82, 0x49, 0x83, 0x42, 0x00, 0x03, 0xf0, 0x02, 0x76],
+            pts: Duration::from_millis(500),
+            keyframe: true,
+        };
+        webm.write_encoded((64, 48), vec![keyframe.clone()])
+            .await
+            .unwrap();
+        webm.finish().await.unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let elements = elements(&file);
+
+        assert_eq!(find(&elements, 0x4282).body, b"webm");
+        assert_eq!(find(&elements, CODEC_ID).body, b"V_VP9");
+        // VP9 describes itself in its keyframes
+        assert!(!elements.iter().any(|element| element.id == CODEC_PRIVATE));
+        let block = find(&elements, SIMPLE_BLOCK);
+        assert_eq!(block.body[..4], [0x81, 0, 0, 0x80]);
+        assert_eq!(block.body[4..], keyframe.data);
+    }
+}
@@ -0,0 +1,161 @@
+mod anim;
+mod apng;
+mod codec;
+mod command;
+mod gif;
+mod hls;
+mod http;
+mod mjpeg;
+mod mkv;
+mod mp4;
+#[cfg(feature = "ndi")]
+mod ndi;
+mod replay;
+mod rtmp;
+mod rtp;
+mod rtsp;
+mod segment;
+mod v4l2;
+mod webp;
+#[cfg(feature = "webrtc")]
+mod webrtc;
+mod y4m;
+
+pub use apng::Apng;
+pub use command::{Command, PipeFormat};
+pub use gif::Gif;
+pub use hls::{Hls, HlsFormat};
+pub use mjpeg::MjpegServer;
+pub use mkv::MkvFile;
+pub use mp4::Mp4File;
+#[cfg(feature = "ndi")]
+pub use ndi::Ndi;
+pub use replay::{Replay, ReplayHandle};
+pub use rtmp::Rtmp;
+pub use rtsp::RtspServer;
+pub use segment::{Segment, Segmented};
+pub use v4l2::V4l2Loopback;
+pub use webp::WebP;
+#[cfg(feature = "webrtc")]
+pub use webrtc::{WebRtc, WebRtcPeer, Whip};
+pub use y4m::Y4m;
+
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime;
+use futures_core::Stream;
+use futures_lite::StreamExt;
+use std::future::Future;
+use tokio::task::JoinHandle;
+
+/// The end of a pipeline: a file writer, a network streamer or any other
+/// consumer of frames. Sinks normally run on a task of their own, see
+/// `SinkHandle::spawn`.
+pub trait FrameSink: Send + 'static {
+    fn write_frame(&mut self, frame: Frame<'static>) -> impl Future<Output = Result<()>> + Send;
+
+    /// Flushes and closes the output, called once after the last frame, also
+    /// when writing failed.
+    fn finish(&mut self) -> impl Future<Output = Result<()>> + Send;
+}
+
+/// A sink running on its own task, fed through a bounded queue so a slow
+/// sink only holds up its own frames.
+#[derive(Debug)]
+pub struct SinkHandle {
+    sender: async_broadcast::Sender<Frame<'static>>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl SinkHandle {
+    /// Spawns `sink` with room for 8 queued frames.
+    pub fn spawn(sink: impl FrameSink) -> Self {
+        SinkHandle::with_capacity(sink, 8)
+    }
+
+    pub fn with_capacity(mut sink: impl FrameSink, capacity: usize) -> Self {
+        let (sender, mut receiver) = async_broadcast::broadcast(capacity.max(1));
+        let task = runtime::handle().spawn(async move {
+            let mut written = Ok(());
+            while let Ok(frame) = receiver.recv_direct().await {
+                written = sink.write_frame(frame).await;
+                if written.is_err() {
+                    break;
+                }
+            }
+            // closes the queue so `send` fails right away
+            drop(receiver);
+            let finished = sink.finish().await;
+            written.and(finished)
+        });
+        SinkHandle { sender, task }
+    }
+
+    /// Queues a frame, waiting while the queue is full. Fails once the sink
+    /// stopped, `finish` then returns why.
+    pub async fn send(&self, frame: Frame<'static>) -> Result<()> {
+        self.sender
+            .broadcast_direct(frame)
+            .await
+            .map(|_| ())
+            .map_err(|_| ScreencastError::SinkClosed)
+    }
+
+    /// Queues a frame unless the queue is full, returns false when the frame
+    /// was dropped.
+    pub fn try_send(&self, frame: Frame<'static>) -> Result<bool> {
+        match self.sender.try_broadcast(frame) {
+            Ok(_) => Ok(true),
+            Err(async_broadcast::TrySendError::Full(_)) => Ok(false),
+            Err(_) => Err(ScreencastError::SinkClosed),
+        }
+    }
+
+    /// Frames waiting in the queue.
+    pub fn queued(&self) -> usize {
+        self.sender.len()
+    }
+
+    /// Lets the sink write the queued frames, finishes it and returns the
+    /// first error it ran into.
+    pub async fn finish(self) -> Result<()> {
+        self.sender.close();
+        self.task.await.map_err(|_| ScreencastError::SinkClosed)?
+    }
+}
+
+/// Writes every frame of `frames` to all `sinks`, then finishes them. Stops
+/// early when a sink fails and returns the first error.
+pub async fn forward<S>(mut frames: S, sinks: Vec<SinkHandle>) -> Result<()>
+where
+    S: Stream<Item = Frame<'static>> + Unpin,
+{
+    while let Some(frame) = frames.next().await {
+        if send_all(&sinks, frame).await.is_err() {
+            break;
+        }
+    }
+    finish_all(sinks).await
+}
+
+/// Queues `frame` for every sink, fails once one of them stopped or there
+/// are none.
+pub(crate) async fn send_all(sinks: &[SinkHandle], frame: Frame<'static>) -> Result<()> {
+    let Some((last, rest)) = sinks.split_last() else {
+        return Err(ScreencastError::SinkClosed);
+    };
+    for sink in rest {
+        sink.send(frame.clone()).await?;
+    }
+    last.send(frame).await
+}
+
+/// Finishes every sink and returns the first error.
+pub(crate) async fn finish_all(sinks: Vec<SinkHandle>) -> Result<()> {
+    let mut result = Ok(());
+    for sink in sinks {
+        let finished = sink.finish().await;
+        result = result.and(finished);
+    }
+    result
+}
@@ -0,0 +1,1372 @@
+use super::FrameSink;
+use super::codec::{self, BlockingEncoder, Encoded};
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::recorder::{Markers, Metadata};
+use crate::runtime::compat;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+
+/// Ticks per second of the sample timestamps, the usual one for video.
+const TIMESCALE: u32 = 90_000;
+const MOVIE_TIMESCALE: u32 = 1000;
+
+/// Records to an MP4 file with H.264, HEVC, VP9 or AV1.
+///
+/// Frames are encoded as they come and their packets appended to the file,
+/// the index follows in `finish`. A recording that was never finished has
+/// no index and does not play, unless it is fragmented, see
+/// `with_fragment_duration`. The track takes the size of the first frame.
+pub struct Mp4File {
+    file: Output,
+    encoder: BlockingEncoder,
+    track: Track,
+    fragment_duration: Option<Duration>,
+    // sample data of the fragment being collected
+    fragment: Vec<u8>,
+    fragments: u32,
+    // offset of the mdat box and the end of the file
+    mdat: u64,
+    position: u64,
+    markers: Option<Markers>,
+    metadata: Metadata,
+    sounds: Vec<Sound>,
+}
+
+impl std::fmt::Debug for Mp4File {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Mp4File")
+            .field("config", self.encoder.config())
+            .field("samples", &self.track.samples.len())
+            .field("fragment_duration", &self.fragment_duration)
+            .field("position", &self.position)
+            .finish()
+    }
+}
+
+/// Where the file goes: to disk, or into memory for the segments of `Hls`.
+enum Output {
+    File(BufWriter<File>),
+    Memory(Vec<u8>),
+}
+
+#[derive(Debug)]
+struct Track {
+    codec: Codec,
+    size: Option<(u32, u32)>,
+    // the decoder configuration record, from the first keyframe
+    config: Option<Vec<u8>>,
+    // duration of the last sample, which has no successor to measure to
+    frame_duration: u64,
+    start: Option<Duration>,
+    // every sample, or those of the current fragment
+    samples: Vec<Sample>,
+}
+
+/// An audio track, timed in samples at its output rate from the start of
+/// the video.
+#[derive(Debug)]
+struct Sound {
+    track: AudioTrack,
+    // every sample, or those of the current fragment and their data
+    samples: Vec<Sample>,
+    fragment: Vec<u8>,
+    last_duration: u64,
+}
+
+#[derive(Debug)]
+struct Sample {
+    // in the file, or in the data of the current fragment
+    offset: u64,
+    size: u32,
+    time: u64,
+    keyframe: bool,
+}
+
+impl Mp4File {
+    /// Creates or truncates `path`, encoding with `encode::default_encoder`.
+    pub async fn create(path: impl AsRef<Path>, config: EncoderConfig) -> Result<Self> {
+        Mp4File::create_with_encoder(path, encode::default_encoder(config)?).await
+    }
+
+    pub async fn create_with_encoder(
+        path: impl AsRef<Path>,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        // refused before the file is truncated
+        supported(encoder.config().codec)?;
+        let file = compat(File::create(path.as_ref())).await?;
+        Mp4File::new(Output::File(BufWriter::new(file)), encoder)
+    }
+
+    /// A fragmented MP4 of packets encoded elsewhere, kept in memory: the
+    /// header and then each fragment are taken with `take_output`. Fragments
+    /// end only with `cut`, at the keyframes the owner picks.
+    pub(super) fn in_memory(config: EncoderConfig, fragment_duration: Duration) -> Result<Self> {
+        Ok(
+            Mp4File::new(Output::Memory(Vec::new()), Box::new(Encoded(config)))?
+                .with_fragment_duration(fragment_duration),
+        )
+    }
+
+    /// Every file is made here, so no track of a codec without a sample
+    /// entry gets written.
+    fn new(file: Output, encoder: Box<dyn Encoder>) -> Result<Self> {
+        let codec = encoder.config().codec;
+        supported(codec)?;
+        let (num, denom) = encoder.config().framerate;
+        Ok(Mp4File {
+            file,
+            encoder: BlockingEncoder::new(encoder),
+            track: Track {
+                codec,
+                size: None,
+                config: None,
+                frame_duration: TIMESCALE as u64 * denom.max(1) as u64 / num.max(1) as u64,
+                start: None,
+                samples: Vec::new(),
+            },
+            fragment_duration: None,
+            fragment: Vec::new(),
+            fragments: 0,
+            mdat: 0,
+            position: 0,
+            markers: None,
+            metadata: Metadata::default(),
+            sounds: Vec::new(),
+        })
+    }
+
+    /// Adds a track with the audio packets up to the time of each frame,
+    /// those from before the first frame are left out. Each call adds one
+    /// more, e.g. for the tracks of `AudioTrack::spawn_all`.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Self {
+        self.sounds.push(Sound {
+            track: audio,
+            samples: Vec::new(),
+            fragment: Vec::new(),
+            last_duration: 0,
+        });
+        self
+    }
+
+    /// Writes a fragmented MP4: the samples follow in a fragment of their
+    /// own every `duration`, each flushed to disk. A recording cut short by
+    /// a crash or power loss plays up to its last fragment. Players that
+    /// read the whole index up front seek slower in such files.
+    pub fn with_fragment_duration(mut self, duration: Duration) -> Self {
+        self.fragment_duration = Some(duration.max(Duration::from_millis(100)));
+        self
+    }
+
+    /// Writes the markers as a chapter track when the recording is
+    /// finished, not in fragmented files whose header comes first.
+    pub fn with_markers(mut self, markers: Markers) -> Self {
+        self.markers = Some(markers);
+        self
+    }
+
+    /// Stores the title, author, application and tags as iTunes style
+    /// metadata in the movie header.
+    pub fn with_metadata(mut self, metadata: Metadata) -> Self {
+        self.metadata = metadata;
+        self
+    }
+
+    /// The file type, then the movie header of a fragmented file or the
+    /// start of the one mdat box of a plain one.
+    async fn write_header(&mut self) -> Result<()> {
+        let mut header = ftyp(self.track.codec, self.fragment_duration.is_some());
+        match self.fragment_duration {
+            Some(_) => header.extend(self.moov(&[])),
+            None => {
+                self.mdat = header.len() as u64;
+                // a 64 bit size, filled in by `finish`
+                header.extend([0, 0, 0, 1]);
+                header.extend(*b"mdat");
+                header.extend([0; 8]);
+            }
+        }
+        self.file.write_all(&header).await?;
+        self.position = header.len() as u64;
+        Ok(())
+    }
+
+    /// Writes packets encoded elsewhere, of frames of `size`.
+    pub(super) async fn write_encoded(
+        &mut self,
+        size: (u32, u32),
+        packets: Vec<EncodedPacket>,
+    ) -> Result<()> {
+        self.track.size.get_or_insert(size);
+        compat(self.write_packets(packets)).await
+    }
+
+    /// Ends the fragment being collected, with the audio up to `next`, the
+    /// time of the packet that follows.
+    pub(super) async fn cut(&mut self, next: Duration) -> Result<()> {
+        let Some(start) = self.track.start else {
+            return Ok(());
+        };
+        compat(async {
+            self.write_audio(next).await?;
+            self.write_fragment(Some(ticks(next.saturating_sub(start))))
+                .await
+        })
+        .await
+    }
+
+    /// What went to memory since the last call.
+    pub(super) fn take_output(&mut self) -> Vec<u8> {
+        match &mut self.file {
+            Output::Memory(buffer) => std::mem::take(buffer),
+            Output::File(_) => Vec::new(),
+        }
+    }
+
+    async fn write_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        let codec = self.track.codec;
+        for packet in packets {
+            if self.track.config.is_none() {
+                // nothing decodes before the first keyframe
+                if !packet.keyframe {
+                    continue;
+                }
+                let config = codec::decoder_config(codec, &packet.data).ok_or_else(|| {
+                    ScreencastError::Encoder(format!(
+                        "the first {:?} keyframe lacks the stream headers",
+                        codec
+                    ))
+                })?;
+                self.track.config = Some(config);
+                self.write_header().await?;
+            }
+            let data = codec::sample_data(codec, &packet.data);
+            let start = *self.track.start.get_or_insert(packet.pts);
+            let time = ticks(packet.pts.saturating_sub(start));
+            // the audio up to the frame goes first, into the fragment before it
+            self.write_audio(packet.pts).await?;
+            let offset = match self.fragment_duration {
+                Some(duration) => {
+                    if let Some(first) = self.track.samples.first()
+                        && matches!(self.file, Output::File(_))
+                        && time.saturating_sub(first.time) >= ticks(duration)
+                    {
+                        self.write_fragment(Some(time)).await?;
+                    }
+                    let offset = self.fragment.len() as u64;
+                    self.fragment.extend_from_slice(&data);
+                    offset
+                }
+                None => {
+                    let offset = self.position;
+                    self.file.write_all(&data).await?;
+                    self.position += data.len() as u64;
+                    offset
+                }
+            };
+            self.track.samples.push(Sample {
+                offset,
+                size: data.len() as u32,
+                time,
+                keyframe: packet.keyframe,
+            });
+        }
+        Ok(())
+    }
+
+    /// Appends the audio packets encoded so far that start by `until`.
+    async fn write_audio(&mut self, until: Duration) -> Result<()> {
+        let Some(start) = self.track.start else {
+            return Ok(());
+        };
+        for sound in &mut self.sounds {
+            let rate = sound.track.output_rate() as u128;
+            // rounded, the packet times are whole nanoseconds
+            let ticks =
+                |time: Duration| ((time.as_nanos() * rate + 500_000_000) / 1_000_000_000) as u64;
+            for packet in sound.track.ready(until)? {
+                let Some(time) = packet.pts.checked_sub(start) else {
+                    continue;
+                };
+                let offset = match self.fragment_duration {
+                    Some(_) => {
+                        let offset = sound.fragment.len() as u64;
+                        sound.fragment.extend_from_slice(&packet.data);
+                        offset
+                    }
+                    None => {
+                        let offset = self.position;
+                        self.file.write_all(&packet.data).await?;
+                        self.position += packet.data.len() as u64;
+                        offset
+                    }
+                };
+                sound.samples.push(Sample {
+                    offset,
+                    size: packet.data.len() as u32,
+                    time: ticks(time),
+                    keyframe: true,
+                });
+                sound.last_duration = ticks(packet.duration);
+            }
+        }
+        Ok(())
+    }
+
+    /// The audio until the last frame ends.
+    async fn write_last_audio(&mut self) -> Result<()> {
+        let Some(start) = self.track.start else {
+            return Ok(());
+        };
+        let end = self.track.duration() as u128 * 1_000_000_000 / TIMESCALE as u128;
+        self.write_audio(start + Duration::from_nanos(end as u64))
+            .await
+    }
+
+    /// Writes the collected samples as a movie fragment and syncs the file.
+    /// `next` is the time of the sample that follows, which ends the last
+    /// one.
+    async fn write_fragment(&mut self, next: Option<u64>) -> Result<()> {
+        let Some(first) = self.track.samples.first() else {
+            return Ok(());
+        };
+        self.fragments += 1;
+        let base_time = first.time;
+        let next = next.unwrap_or_else(|| self.track.duration());
+        // the data offset counts from the moof, which has a fixed size
+        let size = self.moof(base_time, next, 0).len();
+        let mut fragment = self.moof(base_time, next, (size + 8) as u32);
+        let audio: Vec<u8> = self
+            .sounds
+            .iter_mut()
+            .flat_map(|sound| std::mem::take(&mut sound.fragment))
+            .collect();
+        fragment.extend(((self.fragment.len() + audio.len()) as u32 + 8).to_be_bytes());
+        fragment.extend(*b"mdat");
+        fragment.extend_from_slice(&self.fragment);
+        fragment.extend_from_slice(&audio);
+        self.file.write_all(&fragment).await?;
+        self.file.sync().await?;
+        self.position += fragment.len() as u64;
+        self.fragment.clear();
+        self.track.samples.clear();
+        for sound in &mut self.sounds {
+            sound.samples.clear();
+        }
+        Ok(())
+    }
+
+    /// Appends a text sample per marker to the media data, the chapters of
+    /// the chapter track in milliseconds.
+    async fn write_chapters(&mut self) -> Result<Vec<Sample>> {
+        let markers = self.markers.as_ref().map(Markers::list).unwrap_or_default();
+        let Some(first) = markers.first() else {
+            return Ok(Vec::new());
+        };
+        // the chapters cover the whole movie, from an untitled one up to
+        // the first marker
+        let untitled = (first.time > Duration::ZERO).then_some((Duration::ZERO, ""));
+        let titled = markers
+            .iter()
+            .map(|marker| (marker.time, marker.label.as_str()));
+        let mut chapters = Vec::new();
+        for (time, label) in untitled.into_iter().chain(titled) {
+            let label = &label[..label.floor_char_boundary(u16::MAX as usize)];
+            let mut data = Vec::new();
+            data.extend((label.len() as u16).to_be_bytes());
+            data.extend_from_slice(label.as_bytes());
+            // in UTF-8
+            write_box(&mut data, b"encd", |encd| {
+                encd.extend(0x100u32.to_be_bytes())
+            });
+            self.file.write_all(&data).await?;
+            chapters.push(Sample {
+                offset: self.position,
+                size: data.len() as u32,
+                time: time.as_millis() as u64,
+                keyframe: true,
+            });
+            self.position += data.len() as u64;
+        }
+        Ok(chapters)
+    }
+
+    fn moof(&self, base_time: u64, next: u64, data_offset: u32) -> Vec<u8> {
+        let samples = &self.track.samples;
+        let mut moof = Vec::new();
+        write_box(&mut moof, b"moof", |moof| {
+            write_full_box(moof, b"mfhd", 0, 0, |mfhd| {
+                mfhd.extend(self.fragments.to_be_bytes())
+            });
+            write_box(moof, b"traf", |traf| {
+                // offsets count from the moof
+                write_full_box(traf, b"tfhd", 0, 0x02_0000, |tfhd| {
+                    tfhd.extend(1u32.to_be_bytes())
+                });
+                write_full_box(traf, b"tfdt", 1, 0, |tfdt| {
+                    tfdt.extend(base_time.to_be_bytes())
+                });
+                // data offset, then duration, size and flags of each sample
+                write_full_box(traf, b"trun", 0, 0x701, |trun| {
+                    trun.extend((samples.len() as u32).to_be_bytes());
+                    trun.extend(data_offset.to_be_bytes());
+                    let ends = samples.iter().skip(1).map(|sample| sample.time);
+                    for (sample, end) in samples.iter().zip(ends.chain([next])) {
+                        let duration = end.saturating_sub(sample.time);
+                        trun.extend((duration.min(u32::MAX as u64) as u32).to_be_bytes());
+                        trun.extend(sample.size.to_be_bytes());
+                        // depends on no other sample, or is a non sync sample
+                        let flags: u32 = match sample.keyframe {
+                            true => 0x0200_0000,
+                            false => 0x0101_0000,
+                        };
+                        trun.extend(flags.to_be_bytes());
+                    }
+                });
+            });
+            // the audio follows the video in the mdat, track after track
+            let mut audio_offset = data_offset + self.fragment.len() as u32;
+            for (id, sound) in (2u32..).zip(&self.sounds) {
+                if sound.samples.is_empty() {
+                    continue;
+                }
+                write_box(moof, b"traf", |traf| {
+                    write_full_box(traf, b"tfhd", 0, 0x02_0000, |tfhd| {
+                        tfhd.extend(id.to_be_bytes())
+                    });
+                    write_full_box(traf, b"tfdt", 1, 0, |tfdt| {
+                        tfdt.extend(sound.samples[0].time.to_be_bytes())
+                    });
+                    // all sync samples
+                    write_full_box(traf, b"trun", 0, 0x301, |trun| {
+                        trun.extend((sound.samples.len() as u32).to_be_bytes());
+                        trun.extend(audio_offset.to_be_bytes());
+                        let ends = sound.samples.iter().skip(1).map(|sample| sample.time);
+                        for (sample, end) in sound.samples.iter().zip(ends.chain([sound.end()])) {
+                            let duration = end.saturating_sub(sample.time);
+                            trun.extend((duration.min(u32::MAX as u64) as u32).to_be_bytes());
+                            trun.extend(sample.size.to_be_bytes());
+                        }
+                    });
+                });
+                audio_offset += sound.fragment.len() as u32;
+            }
+        });
+        moof
+    }
+
+    /// The movie header, with a chapter track of the `chapters` given.
+    fn moov(&self, chapters: &[Sample]) -> Vec<u8> {
+        let track = &self.track;
+        let duration = track.duration();
+        let movie_duration = duration * MOVIE_TIMESCALE as u64 / TIMESCALE as u64;
+        let mut moov = Vec::new();
+        write_box(&mut moov, b"moov", |moov| {
+            let version = (movie_duration > u32::MAX as u64) as u8;
+            write_full_box(moov, b"mvhd", version, 0, |mvhd| {
+                write_times(mvhd, version);
+                mvhd.extend(MOVIE_TIMESCALE.to_be_bytes());
+                write_duration(mvhd, version, movie_duration);
+                // rate 1.0, volume 1.0
+                mvhd.extend(0x0001_0000u32.to_be_bytes());
+                mvhd.extend(0x0100u16.to_be_bytes());
+                mvhd.extend([0; 10]);
+                write_matrix(mvhd);
+                mvhd.extend([0; 24]);
+                // next track id
+                let tracks = 1 + self.sounds.len() as u32 + !chapters.is_empty() as u32;
+                mvhd.extend((tracks + 1).to_be_bytes());
+            });
+            let chapter_track = 2 + self.sounds.len() as u32;
+            if let (Some(config), Some((width, height))) = (&track.config, track.size) {
+                write_box(moov, b"trak", |trak| {
+                    // enabled and in the movie
+                    write_tkhd(trak, 1, 3, movie_duration, width, height, 0);
+                    if !chapters.is_empty() {
+                        write_box(trak, b"tref", |tref| {
+                            write_box(tref, b"chap", |chap| {
+                                chap.extend(chapter_track.to_be_bytes())
+                            })
+                        });
+                    }
+                    write_box(trak, b"mdia", |mdia| {
+                        let version = (duration > u32::MAX as u64) as u8;
+                        write_full_box(mdia, b"mdhd", version, 0, |mdhd| {
+                            write_times(mdhd, version);
+                            mdhd.extend(TIMESCALE.to_be_bytes());
+                            write_duration(mdhd, version, duration);
+                            // "und"
+                            mdhd.extend(0x55c4u16.to_be_bytes());
+                            mdhd.extend([0; 2]);
+                        });
+                        write_full_box(mdia, b"hdlr", 0, 0, |hdlr| {
+                            hdlr.extend([0; 4]);
+                            hdlr.extend(*b"vide");
+                            hdlr.extend([0; 12]);
+                            hdlr.extend(*b"VideoHandler\0");
+                        });
+                        write_box(mdia, b"minf", |minf| {
+                            write_full_box(minf, b"vmhd", 0, 1, |vmhd| vmhd.extend([0; 8]));
+                            write_dinf(minf);
+                            track.write_stbl(minf, config, width, height);
+                        });
+                    });
+                });
+                for (id, sound) in (2..).zip(&self.sounds) {
+                    sound.write_trak(moov, id, self.fragment_duration.is_some());
+                }
+                if !chapters.is_empty() {
+                    write_chapter_track(moov, chapter_track, chapters, movie_duration);
+                }
+                if self.metadata != Metadata::default() {
+                    write_udta(moov, &self.metadata);
+                }
+                if self.fragment_duration.is_some() {
+                    write_box(moov, b"mvex", |mvex| {
+                        // track 1 with the first sample description
+                        write_full_box(mvex, b"trex", 0, 0, |trex| {
+                            trex.extend([1u32, 1, 0, 0, 0].into_iter().flat_map(u32::to_be_bytes))
+                        });
+                        for id in (2..).take(self.sounds.len()) {
+                            write_full_box(mvex, b"trex", 0, 0, |trex| {
+                                trex.extend([id, 1, 0, 0, 0].into_iter().flat_map(u32::to_be_bytes))
+                            });
+                        }
+                    });
+                }
+            }
+        });
+        moov
+    }
+}
+
+impl FrameSink for Mp4File {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        self.track
+            .size
+            .get_or_insert((frame.width(), frame.height()));
+        let packets = self.encoder.encode(frame).await?;
+        compat(self.write_packets(packets)).await
+    }
+
+    /// Drains the encoder and writes the index, also of a recording that
+    /// failed on the way.
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            let written = match self.encoder.finish().await {
+                Ok(packets) => self.write_packets(packets).await,
+                Err(err) => Err(err),
+            };
+            if self.position == 0 {
+                self.write_header().await?;
+            }
+            self.write_last_audio().await?;
+            if self.fragment_duration.is_some() {
+                self.write_fragment(None).await?;
+                return written;
+            }
+            let chapters = self.write_chapters().await?;
+            let moov = self.moov(&chapters);
+            self.file.write_all(&moov).await?;
+            self.file
+                .write_at(self.mdat + 8, &(self.position - self.mdat).to_be_bytes())
+                .await?;
+            written
+        })
+        .await
+    }
+}
+
+impl Output {
+    async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Output::File(file) => file.write_all(data).await?,
+            Output::Memory(buffer) => buffer.extend_from_slice(data),
+        }
+        Ok(())
+    }
+
+    /// Overwrites what was written at `offset` and flushes.
+    async fn write_at(&mut self, offset: u64, data: &[u8]) -> Result<()> {
+        match self {
+            Output::File(file) => {
+                file.seek(SeekFrom::Start(offset)).await?;
+                file.write_all(data).await?;
+                file.flush().await?;
+            }
+            Output::Memory(buffer) => {
+                buffer[offset as usize..offset as usize + data.len()].copy_from_slice(data)
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets what was written to disk.
+    async fn sync(&mut self) -> Result<()> {
+        if let Output::File(file) = self {
+            file.flush().await?;
+            file.get_ref().sync_data().await?;
+        }
+        Ok(())
+    }
+}
+
+impl Track {
+    fn duration(&self) -> u64 {
+        self.samples
+            .last()
+            .map_or(0, |last| last.time + self.frame_duration)
+    }
+
+    fn write_stbl(&self, minf: &mut Vec<u8>, config: &[u8], width: u32, height: u32) {
+        write_box(minf, b"stbl", |stbl| {
+            write_full_box(stbl, b"stsd", 0, 0, |stsd| {
+                stsd.extend(1u32.to_be_bytes());
+                let (entry, record) = match self.codec {
+                    Codec::H264 => (b"avc1", b"avcC"),
+                    Codec::Hevc => (b"hvc1", b"hvcC"),
+                    Codec::Vp9 => (b"vp09", b"vpcC"),
+                    Codec::Av1 => (b"av01", b"av1C"),
+                    Codec::Vp8 => unreachable!("VP8 is refused by Mp4File::new"),
+                };
+                write_box(stsd, entry, |entry| {
+                    entry.extend([0; 6]);
+                    // data reference index
+                    entry.extend(1u16.to_be_bytes());
+                    entry.extend([0; 16]);
+                    entry.extend((width as u16).to_be_bytes());
+                    entry.extend((height as u16).to_be_bytes());
+                    // 72 dpi
+                    entry.extend(0x0048_0000u32.to_be_bytes());
+                    entry.extend(0x0048_0000u32.to_be_bytes());
+                    entry.extend([0; 4]);
+                    // frame count
+                    entry.extend(1u16.to_be_bytes());
+                    // compressor name
+                    entry.extend([0; 32]);
+                    entry.extend(0x0018u16.to_be_bytes());
+                    entry.extend((-1i16).to_be_bytes());
+                    match self.codec {
+                        Codec::Vp9 => write_full_box(entry, record, 1, 0, |vpcc| {
+                            vpcc.extend_from_slice(config)
+                        }),
+                        _ => write_box(entry, record, |record| record.extend_from_slice(config)),
+                    }
+                });
+            });
+
+            write_stts(stbl, &self.samples, self.duration());
+
+            // the samples of a fragmented file are flagged in its fragments
+            if !self.samples.is_empty() {
+                write_full_box(stbl, b"stss", 0, 0, |stss| {
+                    let keyframes: Vec<u32> = (1..)
+                        .zip(&self.samples)
+                        .filter(|(_, sample)| sample.keyframe)
+                        .map(|(number, _)| number)
+                        .collect();
+                    stss.extend((keyframes.len() as u32).to_be_bytes());
+                    for number in keyframes {
+                        stss.extend(number.to_be_bytes());
+                    }
+                });
+            }
+
+            write_chunks(stbl, &self.samples);
+        });
+    }
+}
+
+impl Sound {
+    /// The end of the last sample.
+    fn end(&self) -> u64 {
+        self.samples
+            .last()
+            .map_or(0, |last| last.time + self.last_duration)
+    }
+
+    /// The audio track, with an edit list that starts it at its time in the
+    /// movie and skips the samples the encoder put ahead.
+    fn write_trak(&self, moov: &mut Vec<u8>, id: u32, fragmented: bool) {
+        let rate = self.track.output_rate();
+        let priming = self.track.priming() as u64;
+        // the media of a plain file starts with its first sample, the
+        // fragments carry their times and leave the duration open
+        let (offset, duration) = match (fragmented, self.samples.first()) {
+            (false, Some(first)) => (first.time, self.end() - first.time),
+            _ => (0, 0),
+        };
+        let movie = |time: u64| time * MOVIE_TIMESCALE as u64 / rate as u64;
+        let edits: Vec<(u64, i64)> = (offset > 0)
+            .then_some((movie(offset), -1))
+            .into_iter()
+            .chain([(movie(duration.saturating_sub(priming)), priming as i64)])
+            .collect();
+        let track_duration = edits.iter().map(|(duration, _)| duration).sum();
+        write_box(moov, b"trak", |trak| {
+            write_tkhd(trak, id, 3, track_duration, 0, 0, 0x100);
+            write_box(trak, b"edts", |edts| {
+                write_full_box(edts, b"elst", 1, 0, |elst| {
+                    elst.extend((edits.len() as u32).to_be_bytes());
+                    for (duration, media_time) in edits {
+                        elst.extend(duration.to_be_bytes());
+                        elst.extend(media_time.to_be_bytes());
+                        // rate 1.0
+                        elst.extend(0x0001_0000u32.to_be_bytes());
+                    }
+                });
+            });
+            write_box(trak, b"mdia", |mdia| {
+                let version = (duration > u32::MAX as u64) as u8;
+                write_full_box(mdia, b"mdhd", version, 0, |mdhd| {
+                    write_times(mdhd, version);
+                    mdhd.extend(rate.to_be_bytes());
+                    write_duration(mdhd, version, duration);
+                    mdhd.extend(0x55c4u16.to_be_bytes());
+                    mdhd.extend([0; 2]);
+                });
+                write_full_box(mdia, b"hdlr", 0, 0, |hdlr| {
+                    hdlr.extend([0; 4]);
+                    hdlr.extend(*b"soun");
+                    hdlr.extend([0; 12]);
+                    hdlr.extend(*b"SoundHandler\0");
+                });
+                write_box(mdia, b"minf", |minf| {
+                    // balance
+                    write_full_box(minf, b"smhd", 0, 0, |smhd| smhd.extend([0; 4]));
+                    write_dinf(minf);
+                    write_box(minf, b"stbl", |stbl| {
+                        write_full_box(stbl, b"stsd", 0, 0, |stsd| {
+                            stsd.extend(1u32.to_be_bytes());
+                            self.write_sample_entry(stsd);
+                        });
+                        write_stts(stbl, &self.samples, self.end());
+                        write_chunks(stbl, &self.samples);
+                    });
+                });
+            });
+        });
+    }
+
+    /// `Opus` with its `dOps` box, or `mp4a` with the decoder config in an
+    /// elementary stream descriptor.
+    fn write_sample_entry(&self, stsd: &mut Vec<u8>) {
+        let config = self.track.config();
+        let rate = self.track.output_rate();
+        let kind = match config.codec {
+            AudioCodec::Opus => b"Opus",
+            AudioCodec::Aac => b"mp4a",
+        };
+        write_box(stsd, kind, |entry| {
+            entry.extend([0; 6]);
+            // data reference index
+            entry.extend(1u16.to_be_bytes());
+            entry.extend([0; 8]);
+            entry.extend((config.channels as u16).to_be_bytes());
+            // sample size, pre defined and reserved
+            entry.extend(16u16.to_be_bytes());
+            entry.extend([0; 4]);
+            // 16.16 fixed point, which 96 kHz and up overflow
+            entry.extend((rate.min(u16::MAX as u32) << 16).to_be_bytes());
+            match config.codec {
+                AudioCodec::Opus => write_box(entry, b"dOps", |dops| {
+                    // the OpusHead after its magic and version, big endian
+                    dops.extend([0, config.channels as u8]);
+                    dops.extend((self.track.priming() as u16).to_be_bytes());
+                    dops.extend(config.sample_rate.to_be_bytes());
+                    dops.extend([0; 3]);
+                }),
+                AudioCodec::Aac => write_full_box(entry, b"esds", 0, 0, |esds| {
+                    let specific = self.track.codec_private();
+                    let bitrate = config.bitrate.to_be_bytes();
+                    // ES, decoder config and decoder specific info, then
+                    // the sync layer config of MP4 files
+                    esds.extend([0x03, 23 + specific.len() as u8, 0, 2, 0]);
+                    esds.extend([0x04, 15 + specific.len() as u8, 0x40, 0x15, 0, 0, 0]);
+                    esds.extend(bitrate);
+                    esds.extend(bitrate);
+                    esds.extend([0x05, specific.len() as u8]);
+                    esds.extend_from_slice(specific);
+                    esds.extend([0x06, 1, 2]);
+                }),
+            }
+        });
+    }
+}
+
+/// The duration of every sample, the last one lasts until `end`.
+fn write_stts(stbl: &mut Vec<u8>, samples: &[Sample], end: u64) {
+    write_full_box(stbl, b"stts", 0, 0, |stts| {
+        let mut runs: Vec<(u32, u64)> = Vec::new();
+        let times = samples.iter().map(|sample| sample.time);
+        let ends = times.clone().skip(1).chain([end]);
+        for (time, end) in times.zip(ends) {
+            let delta = end.saturating_sub(time);
+            match runs.last_mut() {
+                Some((count, last)) if *last == delta => *count += 1,
+                _ => runs.push((1, delta)),
+            }
+        }
+        stts.extend((runs.len() as u32).to_be_bytes());
+        for (count, delta) in runs {
+            stts.extend(count.to_be_bytes());
+            stts.extend((delta.min(u32::MAX as u64) as u32).to_be_bytes());
+        }
+    });
+}
+
+/// Where the samples are: their chunks, sizes and offsets.
+fn write_chunks(stbl: &mut Vec<u8>, samples: &[Sample]) {
+    // a chunk per sample
+    write_full_box(stbl, b"stsc", 0, 0, |stsc| {
+        stsc.extend(1u32.to_be_bytes());
+        stsc.extend([1u32, 1, 1].into_iter().flat_map(u32::to_be_bytes));
+    });
+
+    write_full_box(stbl, b"stsz", 0, 0, |stsz| {
+        stsz.extend([0; 4]);
+        stsz.extend((samples.len() as u32).to_be_bytes());
+        for sample in samples {
+            stsz.extend(sample.size.to_be_bytes());
+        }
+    });
+
+    let large = samples
+        .last()
+        .is_some_and(|last| last.offset > u32::MAX as u64);
+    let kind = if large { b"co64" } else { b"stco" };
+    write_full_box(stbl, kind, 0, 0, |offsets| {
+        offsets.extend((samples.len() as u32).to_be_bytes());
+        for sample in samples {
+            match large {
+                true => offsets.extend(sample.offset.to_be_bytes()),
+                false => offsets.extend((sample.offset as u32).to_be_bytes()),
+            }
+        }
+    });
+}
+
+/// `volume` is 8.8 fixed point, 0x100 for audio tracks.
+fn write_tkhd(
+    trak: &mut Vec<u8>,
+    id: u32,
+    flags: u32,
+    duration: u64,
+    width: u32,
+    height: u32,
+    volume: u16,
+) {
+    let version = (duration > u32::MAX as u64) as u8;
+    write_full_box(trak, b"tkhd", version, flags, |tkhd| {
+        write_times(tkhd, version);
+        tkhd.extend(id.to_be_bytes());
+        tkhd.extend([0; 4]);
+        write_duration(tkhd, version, duration);
+        // reserved, layer, alternate group, volume, reserved
+        tkhd.extend([0; 12]);
+        tkhd.extend(volume.to_be_bytes());
+        tkhd.extend([0; 2]);
+        write_matrix(tkhd);
+        tkhd.extend((width << 16).to_be_bytes());
+        tkhd.extend((height << 16).to_be_bytes());
+    });
+}
+
+fn write_dinf(minf: &mut Vec<u8>) {
+    write_box(minf, b"dinf", |dinf| {
+        write_full_box(dinf, b"dref", 0, 0, |dref| {
+            dref.extend(1u32.to_be_bytes());
+            // the data is in this file
+            write_full_box(dref, b"url ", 0, 1, |_| {});
+        });
+    });
+}
+
+/// A QuickTime chapter track of text samples in milliseconds, referred to
+/// by the video track. It is disabled, players list the chapters instead
+/// of showing them.
+fn write_chapter_track(moov: &mut Vec<u8>, id: u32, chapters: &[Sample], duration: u64) {
+    write_box(moov, b"trak", |trak| {
+        write_tkhd(trak, id, 0, duration, 0, 0, 0);
+        write_box(trak, b"mdia", |mdia| {
+            let version = (duration > u32::MAX as u64) as u8;
+            write_full_box(mdia, b"mdhd", version, 0, |mdhd| {
+                write_times(mdhd, version);
+                mdhd.extend(MOVIE_TIMESCALE.to_be_bytes());
+                write_duration(mdhd, version, duration);
+                mdhd.extend(0x55c4u16.to_be_bytes());
+                mdhd.extend([0; 2]);
+            });
+            write_full_box(mdia, b"hdlr", 0, 0, |hdlr| {
+                hdlr.extend([0; 4]);
+                hdlr.extend(*b"text");
+                hdlr.extend([0; 12]);
+                hdlr.extend(*b"ChapterHandler\0");
+            });
+            write_box(mdia, b"minf", |minf| {
+                write_box(minf, b"gmhd", |gmhd| {
+                    // copy mode, gray opcolor, balance
+                    write_full_box(gmhd, b"gmin", 0, 0, |gmin| {
+                        gmin.extend(0x40u16.to_be_bytes());
+                        gmin.extend([0x8000u16; 3].into_iter().flat_map(u16::to_be_bytes));
+                        gmin.extend([0; 4]);
+                    });
+                    write_box(gmhd, b"text", write_matrix);
+                });
+                write_dinf(minf);
+                write_box(minf, b"stbl", |stbl| {
+                    write_full_box(stbl, b"stsd", 0, 0, |stsd| {
+                        stsd.extend(1u32.to_be_bytes());
+                        write_box(stsd, b"text", |entry| {
+                            entry.extend([0; 6]);
+                            // data reference index
+                            entry.extend(1u16.to_be_bytes());
+                            // display flags, justification, background
+                            // colour, text box, font, face and colour, an
+                            // empty font name
+                            entry.extend([0; 44]);
+                        });
+                    });
+                    let end = chapters
+                        .last()
+                        .map_or(0, |last| duration.max(last.time + 1));
+                    write_stts(stbl, chapters, end);
+                    write_chunks(stbl, chapters);
+                });
+            });
+        });
+    });
+}
+
+/// User data with an item list as iTunes writes it, understood by most
+/// players and by ffmpeg.
+fn write_udta(moov: &mut Vec<u8>, metadata: &Metadata) {
+    let data = |item: &mut Vec<u8>, value: &str| {
+        // UTF-8 text, no locale
+        write_box(item, b"data", |data| {
+            data.extend(1u32.to_be_bytes());
+            data.extend([0; 4]);
+            data.extend_from_slice(value.as_bytes());
+        })
+    };
+    write_box(moov, b"udta", |udta| {
+        write_full_box(udta, b"meta", 0, 0, |meta| {
+            write_full_box(meta, b"hdlr", 0, 0, |hdlr| {
+                hdlr.extend([0; 4]);
+                hdlr.extend(*b"mdirappl");
+                hdlr.extend([0; 9]);
+            });
+            write_box(meta, b"ilst", |ilst| {
+                for (kind, value) in [
+                    (b"\xa9nam", &metadata.title),
+                    (b"\xa9ART", &metadata.author),
+                    (b"\xa9too", &metadata.application),
+                ] {
+                    if let Some(value) = value {
+                        write_box(ilst, kind, |item| data(item, value));
+                    }
+                }
+                for (key, value) in &metadata.tags {
+                    write_box(ilst, b"----", |item| {
+                        write_full_box(item, b"mean", 0, 0, |mean| {
+                            mean.extend(*b"com.apple.iTunes")
+                        });
+                        write_full_box(item, b"name", 0, 0, |name| {
+                            name.extend_from_slice(key.as_bytes())
+                        });
+                        data(item, value);
+                    });
+                }
+            });
+        });
+    });
+}
+
+/// MP4 has no sample entry for VP8.
+fn supported(codec: Codec) -> Result<()> {
+    match codec {
+        Codec::Vp8 => Err(ScreencastError::Unsupported("VP8 in MP4".to_string())),
+        _ => Ok(()),
+    }
+}
+
+fn ftyp(codec: Codec, fragmented: bool) -> Vec<u8> {
+    let mut ftyp = Vec::new();
+    write_box(&mut ftyp, b"ftyp", |ftyp| {
+        ftyp.extend(*b"isom");
+        ftyp.extend(0x200u32.to_be_bytes());
+        ftyp.extend(*b"isomiso2mp41");
+        if fragmented {
+            ftyp.extend(*b"iso6");
+        }
+        match codec {
+            Codec::H264 => ftyp.extend(*b"avc1"),
+            Codec::Vp9 => ftyp.extend(*b"vp09"),
+            Codec::Av1 => ftyp.extend(*b"av01"),
+            Codec::Hevc | Codec::Vp8 => {}
+        }
+    });
+    ftyp
+}
+
+fn ticks(time: Duration) -> u64 {
+    (time.as_nanos() * TIMESCALE as u128 / 1_000_000_000) as u64
+}
+
+/// Appends a box, `body` writes its contents.
+fn write_box(out: &mut Vec<u8>, kind: &[u8; 4], body: impl FnOnce(&mut Vec<u8>)) {
+    let start = out.len();
+    out.extend([0; 4]);
+    out.extend(*kind);
+    body(out);
+    let size = (out.len() - start) as u32;
+    out[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    out: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    body: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(out, kind, |out| {
+        out.extend(((version as u32) << 24 | flags).to_be_bytes());
+        body(out);
+    });
+}
+
+/// Creation and modification time, left unset.
+fn write_times(out: &mut Vec<u8>, version: u8) {
+    match version {
+        1 => out.extend([0; 16]),
+        _ => out.extend([0; 8]),
+    }
+}
+
+fn write_duration(out: &mut Vec<u8>, version: u8, duration: u64) {
+    match version {
+        1 => out.extend(duration.to_be_bytes()),
+        _ => out.extend((duration as u32).to_be_bytes()),
+    }
+}
+
+fn write_matrix(out: &mut Vec<u8>) {
+    let matrix = [0x0001_0000u32, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+    out.extend(matrix.into_iter().flat_map(u32::to_be_bytes));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPS: &[u8] = &[0x67, 0x42, 0xc0, 0x1e, 0x8c, 0x8d, 0x40];
+    const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+    /// Three frames 33 ms apart, a keyframe with its parameter sets first.
+    fn packets() -> Vec<EncodedPacket> {
+        let annex_b = |nals: &[&[u8]]| -> Vec<u8> {
+            nals.iter()
+                .flat_map(|nal| [&[0, 0, 0, 1][..], nal].concat())
+                .collect()
+        };
+        let frames: [(&[&[u8]], bool); 3] = [
+            (&[SPS, PPS, &[0x65, 0x88, 0x84, 0x21, 0xa0]], true),
+            (&[&[0x41, 0x9a, 0x02, 0x04]], false),
+            (&[&[0x41, 0x9a, 0x04, 0x08, 0x10]], false),
+        ];
+        (0..)
+            .zip(frames)
+            .map(|(index, (nals, keyframe))| EncodedPacket {
+                codec: Codec::H264,
+                data: annex_b(nals),
+                pts: Duration::from_millis(1000 + 33 * index),
+                keyframe,
+            })
+            .collect()
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    /// The path and body of every box, parents before their children. The
+    /// size of each is checked to fit its parent.
+    fn boxes(data: &[u8]) -> Vec<(String, &[u8])> {
+        fn walk<'a>(data: &'a [u8], parent: &str, out: &mut Vec<(String, &'a [u8])>) {
+            let mut rest = data;
+            while !rest.is_empty() {
+                let (mut size, mut header) = (u32_at(rest, 0) as usize, 8);
+                if size == 1 {
+                    size = u64::from_be_bytes(rest[8..16].try_into().unwrap()) as usize;
+                    header = 16;
+                }
+                assert!(
+                    size >= header && size <= rest.len(),
+                    "box overruns {parent}"
+                );
+                let kind = std::str::from_utf8(&rest[4..8]).unwrap();
+                let path = match parent {
+                    "" => kind.to_string(),
+                    _ => format!("{parent}/{kind}"),
+                };
+                let body = &rest[header..size];
+                out.push((path.clone(), body));
+                // what comes ahead of the children
+                let skip = match kind {
+                    "moov" | "trak" | "mdia" | "minf" | "stbl" | "dinf" | "edts" | "mvex"
+                    | "moof" | "traf" => Some(0),
+                    "stsd" | "dref" => Some(8),
+                    "avc1" => Some(78),
+                    _ => None,
+                };
+                if let Some(skip) = skip {
+                    walk(&body[skip..], &path, out);
+                }
+                rest = &rest[size..];
+            }
+        }
+        let mut out = Vec::new();
+        walk(data, "", &mut out);
+        out
+    }
+
+    fn body<'a>(boxes: &[(String, &'a [u8])], path: &str) -> &'a [u8] {
+        boxes
+            .iter()
+            .find(|(other, _)| other == path)
+            .unwrap_or_else(|| panic!("no {path}"))
+            .1
+    }
+
+    /// The avcC record of the SPS and PPS, for the constrained baseline
+    /// profile without the High extension.
+    fn avc_config() -> Vec<u8> {
+        let mut config = vec![1, 0x42, 0xc0, 0x1e, 0xff, 0xe1, 0, SPS.len() as u8];
+        config.extend_from_slice(SPS);
+        config.extend([1, 0, PPS.len() as u8]);
+        config.extend_from_slice(PPS);
+        config
+    }
+
+    #[test]
+    fn refuses_vp8() {
+        let vp8 = EncoderConfig::new(Codec::Vp8);
+        assert!(matches!(
+            Mp4File::in_memory(vp8.clone(), Duration::from_secs(1)),
+            Err(ScreencastError::Unsupported(_))
+        ));
+        assert!(Mp4File::new(Output::Memory(Vec::new()), Box::new(Encoded(vp8))).is_err());
+    }
+
+    #[tokio::test]
+    async fn plain_file_layout() {
+        let encoder = Box::new(Encoded(EncoderConfig::new(Codec::H264)));
+        let mut mp4 = Mp4File::new(Output::Memory(Vec::new()), encoder).unwrap();
+        mp4.write_encoded((64, 48), packets()).await.unwrap();
+        mp4.finish().await.unwrap();
+        let file = mp4.take_output();
+        let boxes = boxes(&file);
+
+        let paths: Vec<&str> = boxes.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "ftyp",
+                "mdat",
+                "moov",
+                "moov/mvhd",
+                "moov/trak",
+                "moov/trak/tkhd",
+                "moov/trak/mdia",
+                "moov/trak/mdia/mdhd",
+                "moov/trak/mdia/hdlr",
+                "moov/trak/mdia/minf",
+                "moov/trak/mdia/minf/vmhd",
+                "moov/trak/mdia/minf/dinf",
+                "moov/trak/mdia/minf/dinf/dref",
+                "moov/trak/mdia/minf/dinf/dref/url ",
+                "moov/trak/mdia/minf/stbl",
+                "moov/trak/mdia/minf/stbl/stsd",
+                "moov/trak/mdia/minf/stbl/stsd/avc1",
+                "moov/trak/mdia/minf/stbl/stsd/avc1/avcC",
+                "moov/trak/mdia/minf/stbl/stts",
+                "moov/trak/mdia/minf/stbl/stss",
+                "moov/trak/mdia/minf/stbl/stsc",
+                "moov/trak/mdia/minf/stbl/stsz",
+                "moov/trak/mdia/minf/stbl/stco",
+            ]
+        );
+        assert_eq!(body(&boxes, "ftyp"), b"isom\0\0\x02\0isomiso2mp41avc1");
+        assert_eq!(
+            body(&boxes, "moov/trak/mdia/minf/stbl/stsd/avc1/avcC"),
+            avc_config()
+        );
+
+        // the samples as length prefixed NAL units, the parameter sets
+        // stay in the keyframe
+        let samples: Vec<Vec<u8>> = packets()
+            .iter()
+            .map(|packet| codec::sample_data(Codec::H264, &packet.data))
+            .collect();
+        assert_eq!(&samples[0][..4], (SPS.len() as u32).to_be_bytes());
+        let stsz = body(&boxes, "moov/trak/mdia/minf/stbl/stsz");
+        assert_eq!(u32_at(stsz, 8), 3);
+        let stco = body(&boxes, "moov/trak/mdia/minf/stbl/stco");
+        assert_eq!(u32_at(stco, 4), 3);
+        for (index, sample) in samples.iter().enumerate() {
+            assert_eq!(u32_at(stsz, 12 + 4 * index) as usize, sample.len());
+            let offset = u32_at(stco, 8 + 4 * index) as usize;
+            assert_eq!(&file[offset..offset + sample.len()], sample);
+        }
+        // the mdat ends where the moov starts, its 64 bit size patched
+        assert_eq!(body(&boxes, "mdat"), samples.concat());
+
+        // 33 ms twice, then the 60 fps frame duration for the last one
+        let stts = body(&boxes, "moov/trak/mdia/minf/stbl/stts");
+        assert_eq!(u32_at(stts, 4), 2);
+        assert_eq!(
+            &stts[8..],
+            [2u32, 2970, 1, 1500].map(u32::to_be_bytes).concat()
+        );
+        let stss = body(&boxes, "moov/trak/mdia/minf/stbl/stss");
+        assert_eq!(&stss[4..], [1u32, 1].map(u32::to_be_bytes).concat());
+        let mdhd = body(&boxes, "moov/trak/mdia/mdhd");
+        assert_eq!(u32_at(mdhd, 12), TIMESCALE);
+        assert_eq!(u32_at(mdhd, 16), 2 * 2970 + 1500);
+    }
+
+    #[tokio::test]
+    async fn fragmented_file_layout() {
+        let path = std::env::temp_dir().join(format!("xdp-screencast-{}.mp4", std::process::id()));
+        let encoder = Box::new(Encoded(EncoderConfig::new(Codec::H264)));
+        let mut mp4 = Mp4File::create_with_encoder(&path, encoder)
+            .await
+            .unwrap()
+            .with_fragment_duration(Duration::from_millis(100));
+        // the frame 100 ms after the first ends the first fragment
+        let mut packets = packets();
+        packets.extend([1100, 1133].map(|millis| EncodedPacket {
+            pts: Duration::from_millis(millis),
+            ..packets[1].clone()
+        }));
+        mp4.write_encoded((64, 48), packets.clone()).await.unwrap();
+        mp4.finish().await.unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let boxes = boxes(&file);
+        let top: Vec<&str> = boxes
+            .iter()
+            .map(|(path, _)| path.as_str())
+            .filter(|path| !path.contains('/'))
+            .collect();
+        assert_eq!(top, ["ftyp", "moov", "moof", "mdat", "moof", "mdat"]);
+        assert_eq!(body(&boxes, "ftyp"), b"isom\0\0\x02\0isomiso2mp41iso6avc1");
+        assert!(boxes.iter().any(|(path, _)| path == "moov/mvex/trex"));
+        // the samples are all in the fragments
+        let stsz = body(&boxes, "moov/trak/mdia/minf/stbl/stsz");
+        assert_eq!(u32_at(stsz, 8), 0);
+
+        // the start of each moof, where its data offsets count from
+        let mut moofs = Vec::new();
+        let mut position = 0;
+        while position < file.len() {
+            if &file[position + 4..position + 8] == b"moof" {
+                moofs.push(position);
+            }
+            position += u32_at(&file, position) as usize;
+        }
+        let fragments = [
+            (0..3, 0, [2970, 2970, 3060].as_slice()),
+            (3..5, 9000, &[2970, 1500]),
+        ];
+        for (sequence, (moof, (frames, base, durations))) in
+            (1..).zip(moofs.into_iter().zip(fragments))
+        {
+            let boxes = self::boxes(&file[moof..moof + u32_at(&file, moof) as usize]);
+            assert_eq!(u32_at(body(&boxes, "moof/mfhd"), 4), sequence);
+            let tfdt = body(&boxes, "moof/traf/tfdt");
+            assert_eq!(u64::from_be_bytes(tfdt[4..12].try_into().unwrap()), base);
+            let trun = body(&boxes, "moof/traf/trun");
+            assert_eq!(u32_at(trun, 4) as usize, frames.len());
+            let mut offset = moof + u32_at(trun, 8) as usize;
+            for ((entry, frame), &duration) in frames.enumerate().zip(durations) {
+                let sample = codec::sample_data(Codec::H264, &packets[frame].data);
+                let fields = &trun[12 + 12 * entry..];
+                assert_eq!(u32_at(fields, 0), duration);
+                assert_eq!(u32_at(fields, 4) as usize, sample.len());
+                assert_eq!(&file[offset..offset + sample.len()], sample);
+                offset += sample.len();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn in_memory_segments() {
+        let config = EncoderConfig::new(Codec::H264);
+        let mut mp4 = Mp4File::in_memory(config, Duration::from_secs(2)).unwrap();
+        let mut packets = packets();
+        let last = packets.pop().unwrap();
+        mp4.write_encoded((64, 48), packets).await.unwrap();
+        let init = mp4.take_output();
+        mp4.cut(last.pts).await.unwrap();
+        let segment = mp4.take_output();
+        mp4.write_encoded((64, 48), vec![last]).await.unwrap();
+        mp4.finish().await.unwrap();
+        let tail = mp4.take_output();
+
+        let init_boxes = boxes(&init);
+        let paths: Vec<&str> = init_boxes.iter().map(|(path, _)| path.as_str()).collect();
+        assert_eq!(paths[..3], ["ftyp", "moov", "moov/mvhd"]);
+        assert!(paths.contains(&"moov/mvex/trex"));
+        assert_eq!(
+            body(&init_boxes, "moov/trak/mdia/minf/stbl/stsd/avc1/avcC"),
+            avc_config()
+        );
+        // no samples in the header
+        let stsz = body(&init_boxes, "moov/trak/mdia/minf/stbl/stsz");
+        assert_eq!(u32_at(stsz, 8), 0);
+
+        for (sequence, fragment, frames) in [(1, &segment, 0..2), (2, &tail, 2..3)] {
+            let boxes = boxes(fragment);
+            let paths: Vec<&str> = boxes.iter().map(|(path, _)| path.as_str()).collect();
+            assert_eq!(
+                paths,
+                [
+                    "moof",
+                    "moof/mfhd",
+                    "moof/traf",
+                    "moof/traf/tfhd",
+                    "moof/traf/tfdt",
+                    "moof/traf/trun",
+                    "mdat",
+                ]
+            );
+            assert_eq!(u32_at(body(&boxes, "moof/mfhd"), 4), sequence);
+            let tfdt = body(&boxes, "moof/traf/tfdt");
+            let base = u64::from_be_bytes(tfdt[4..12].try_into().unwrap());
+            assert_eq!(base, 2970 * frames.start as u64);
+
+            // the data offset counts from the moof to the first sample
+            let trun = body(&boxes, "moof/traf/trun");
+            assert_eq!(u32_at(trun, 4) as usize, frames.len());
+            let mut offset = u32_at(trun, 8) as usize;
+            for (entry, frame) in frames.clone().enumerate() {
+                let sample = codec::sample_data(Codec::H264, &self::packets()[frame].data);
+                let fields = &trun[12 + 12 * entry..];
+                assert_eq!(u32_at(fields, 4) as usize, sample.len());
+                let flags = if frame == 0 { 0x0200_0000 } else { 0x0101_0000 };
+                assert_eq!(u32_at(fields, 8), flags);
+                assert_eq!(&fragment[offset..offset + sample.len()], sample);
+                offset += sample.len();
+            }
+            assert_eq!(offset, fragment.len());
+        }
+    }
+}
@@ -0,0 +1,83 @@
+#![allow(
+    non_camel_case_types,
+    non_snake_case,
+    non_upper_case_globals,
+    dead_code
+)]
+
+use std::os::raw::{c_char, c_float, c_int, c_void};
+
+pub type NDIlib_send_instance_t = *mut c_void;
+
+const fn fourcc(code: &[u8; 4]) -> c_int {
+    (code[0] as u32 | (code[1] as u32) << 8 | (code[2] as u32) << 16 | (code[3] as u32) << 24)
+        as c_int
+}
+
+pub const NDIlib_FourCC_video_type_BGRA: c_int = fourcc(b"BGRA");
+pub const NDIlib_FourCC_video_type_BGRX: c_int = fourcc(b"BGRX");
+pub const NDIlib_FourCC_video_type_RGBA: c_int = fourcc(b"RGBA");
+pub const NDIlib_FourCC_video_type_RGBX: c_int = fourcc(b"RGBX");
+pub const NDIlib_FourCC_video_type_NV12: c_int = fourcc(b"NV12");
+pub const NDIlib_FourCC_video_type_I420: c_int = fourcc(b"I420");
+
+pub const NDIlib_frame_format_type_progressive: c_int = 1;
+
+/// Timecodes and timestamps are in 100 ns.
+pub const NDIlib_send_timecode_synthesize: i64 = i64::MAX;
+
+#[repr(C)]
+pub struct NDIlib_send_create_t {
+    pub p_ndi_name: *const c_char,
+    pub p_groups: *const c_char,
+    pub clock_video: bool,
+    pub clock_audio: bool,
+}
+
+#[repr(C)]
+pub struct NDIlib_video_frame_v2_t {
+    pub xres: c_int,
+    pub yres: c_int,
+    pub FourCC: c_int,
+    pub frame_rate_N: c_int,
+    pub frame_rate_D: c_int,
+    pub picture_aspect_ratio: c_float,
+    pub frame_format_type: c_int,
+    pub timecode: i64,
+    pub p_data: *mut u8,
+    pub line_stride_in_bytes: c_int,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+/// Planar samples, one channel after the other.
+#[repr(C)]
+pub struct NDIlib_audio_frame_v2_t {
+    pub sample_rate: c_int,
+    pub no_channels: c_int,
+    pub no_samples: c_int,
+    pub timecode: i64,
+    pub p_data: *mut c_float,
+    pub channel_stride_in_bytes: c_int,
+    pub p_metadata: *const c_char,
+    pub timestamp: i64,
+}
+
+#[link(name = "ndi")]
+unsafe extern "C" {
+    pub fn NDIlib_initialize() -> bool;
+    pub fn NDIlib_send_create(settings: *const NDIlib_send_create_t) -> NDIlib_send_instance_t;
+    pub fn NDIlib_send_destroy(instance: NDIlib_send_instance_t);
+    pub fn NDIlib_send_send_video_v2(
+        instance: NDIlib_send_instance_t,
+        frame: *const NDIlib_video_frame_v2_t,
+    );
+    pub fn NDIlib_send_send_audio_v2(
+        instance: NDIlib_send_instance_t,
+        frame: *const NDIlib_audio_frame_v2_t,
+    );
+    pub fn NDIlib_send_get_no_connections(
+        instance: NDIlib_send_instance_t,
+        timeout_in_ms: u32,
+    ) -> c_int;
+}
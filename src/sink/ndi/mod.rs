@@ -0,0 +1,244 @@
+mod ffi;
+
+use super::FrameSink;
+use crate::audio::{AudioBuffer, AudioConfig, Mixer};
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::{Frame, Plane};
+use std::borrow::Cow;
+use std::ffi::CString;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+/// Publishes the capture as an NDI source on the local network, with the
+/// NDI SDK linked from the system, for OBS, vMix or any other NDI receiver
+/// on another machine, e.g. the streaming PC of a two PC setup.
+///
+/// Receivers find the source by name, as `HOSTNAME (name)`. Frames are only
+/// sent while a receiver is connected. BGRA, BGRX, RGBA, RGBX, NV12 and
+/// I420 frames go out as they are, others are converted to BGRA first.
+pub struct Ndi {
+    name: String,
+    sender: Arc<Sender>,
+    framerate: (u32, u32),
+}
+
+impl std::fmt::Debug for Ndi {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Ndi")
+            .field("name", &self.name)
+            .field("framerate", &self.framerate)
+            .finish()
+    }
+}
+
+/// The send instance, shared with the thread sending the audio.
+struct Sender {
+    instance: ffi::NDIlib_send_instance_t,
+    stopped: AtomicBool,
+    // what went wrong with the audio, reported with the next frame
+    error: Mutex<Option<ScreencastError>>,
+}
+
+// SAFETY: the send functions of the SDK may be called from any thread,
+// video and audio from different ones at the same time.
+unsafe impl Send for Sender {}
+unsafe impl Sync for Sender {}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        unsafe { ffi::NDIlib_send_destroy(self.instance) };
+    }
+}
+
+impl Ndi {
+    /// Announces a source called `name`.
+    pub fn new(name: &str) -> Result<Self> {
+        static INITIALIZED: OnceLock<bool> = OnceLock::new();
+        if !*INITIALIZED.get_or_init(|| unsafe { ffi::NDIlib_initialize() }) {
+            return Err(ScreencastError::Unsupported("NDI on this CPU".to_string()));
+        }
+        let ndi_name = CString::new(name)
+            .map_err(|_| ScreencastError::Unsupported(format!("NDI name {:?}", name)))?;
+        let settings = ffi::NDIlib_send_create_t {
+            p_ndi_name: ndi_name.as_ptr(),
+            p_groups: std::ptr::null(),
+            // frames are sent as the capture delivers them
+            clock_video: false,
+            clock_audio: false,
+        };
+        let instance = unsafe { ffi::NDIlib_send_create(&settings) };
+        if instance.is_null() {
+            return Err(ScreencastError::Streaming(format!(
+                "failed to create the NDI source {:?}",
+                name
+            )));
+        }
+        Ok(Ndi {
+            name: name.to_string(),
+            sender: Arc::new(Sender {
+                instance,
+                stopped: AtomicBool::new(false),
+                error: Mutex::new(None),
+            }),
+            framerate: (60, 1),
+        })
+    }
+
+    /// The frame rate receivers are told as `(numerator, denominator)`,
+    /// 60 by default.
+    pub fn with_framerate(mut self, framerate: (u32, u32)) -> Self {
+        self.framerate = (framerate.0.max(1), framerate.1.max(1));
+        self
+    }
+
+    /// Sends the audio of `buffers` along, e.g. those of an `AudioCapture`,
+    /// with the inputs of `config` mixed at its rate and channel count. The
+    /// samples go out as they are, the codec and bitrate do not matter.
+    pub fn with_audio<I>(self, buffers: I, config: &AudioConfig) -> Result<Self>
+    where
+        I: IntoIterator<Item = AudioBuffer>,
+        I::IntoIter: Send + 'static,
+    {
+        let mut mixer = Mixer::new(config)?;
+        let buffers = buffers.into_iter();
+        let sender = self.sender.clone();
+        thread::Builder::new()
+            .name("ndi-audio".to_string())
+            .spawn(move || {
+                for buffer in buffers {
+                    if sender.stopped.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    match mixer.push(&buffer) {
+                        Ok(mixed) => mixed.iter().for_each(|buffer| sender.send_audio(buffer)),
+                        Err(err) => {
+                            sender.error.lock().unwrap().get_or_insert(err);
+                            return;
+                        }
+                    }
+                }
+                for buffer in mixer.flush() {
+                    sender.send_audio(&buffer);
+                }
+            })?;
+        Ok(self)
+    }
+
+    /// Receivers connected to the source.
+    pub fn connections(&self) -> usize {
+        let connections = unsafe { ffi::NDIlib_send_get_no_connections(self.sender.instance, 0) };
+        connections.max(0) as usize
+    }
+}
+
+impl Sender {
+    fn send_audio(&self, buffer: &AudioBuffer) {
+        let (channels, frames) = (buffer.channels as usize, buffer.frames());
+        if channels == 0 || frames == 0 {
+            return;
+        }
+        // planar, one channel after the other
+        let mut planar = vec![0f32; channels * frames];
+        for (index, sample) in buffer.samples[..channels * frames].iter().enumerate() {
+            planar[index % channels * frames + index / channels] = *sample;
+        }
+        let audio = ffi::NDIlib_audio_frame_v2_t {
+            sample_rate: buffer.sample_rate as i32,
+            no_channels: channels as i32,
+            no_samples: frames as i32,
+            timecode: timecode(buffer.pts),
+            p_data: planar.as_mut_ptr(),
+            channel_stride_in_bytes: (frames * 4) as i32,
+            p_metadata: std::ptr::null(),
+            timestamp: 0,
+        };
+        unsafe { ffi::NDIlib_send_send_audio_v2(self.instance, &audio) };
+    }
+}
+
+impl Drop for Ndi {
+    fn drop(&mut self) {
+        self.sender.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+impl FrameSink for Ndi {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        if let Some(err) = self.sender.error.lock().unwrap().take() {
+            return Err(err);
+        }
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "NDI of DMA-BUF frames".to_string(),
+            ));
+        }
+        if self.connections() == 0 {
+            return Ok(());
+        }
+        let (frame, fourcc) = match fourcc(&frame) {
+            Some(fourcc) => (Cow::Borrowed(&frame), fourcc),
+            None => (
+                Cow::Owned(convert::convert(&frame, PixelFormat::Bgra)?),
+                ffi::NDIlib_FourCC_video_type_BGRA,
+            ),
+        };
+        let video = ffi::NDIlib_video_frame_v2_t {
+            xres: frame.width() as i32,
+            yres: frame.height() as i32,
+            FourCC: fourcc,
+            frame_rate_N: self.framerate.0 as i32,
+            frame_rate_D: self.framerate.1 as i32,
+            // square pixels
+            picture_aspect_ratio: 0.0,
+            frame_format_type: ffi::NDIlib_frame_format_type_progressive,
+            timecode: frame
+                .pts()
+                .map_or(ffi::NDIlib_send_timecode_synthesize, timecode),
+            // read only, the API just lacks const
+            p_data: frame.data().as_ptr().cast_mut(),
+            line_stride_in_bytes: frame.stride() as i32,
+            p_metadata: std::ptr::null(),
+            timestamp: 0,
+        };
+        // copies the frame before it returns
+        unsafe { ffi::NDIlib_send_send_video_v2(self.sender.instance, &video) };
+        Ok(())
+    }
+
+    /// Stops sending the audio, the source goes away with the sink.
+    async fn finish(&mut self) -> Result<()> {
+        self.sender.stopped.store(true, Ordering::Relaxed);
+        match self.sender.error.lock().unwrap().take() {
+            Some(err) => Err(err),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The FourCC of a frame the SDK takes as it is: packed RGB, or planes of
+/// an even size, with its planes back to back as it expects them.
+fn fourcc(frame: &Frame<'_>) -> Option<i32> {
+    let (width, height) = (frame.width(), frame.height());
+    if frame.planes() != Plane::contiguous(frame.pixel_format(), height, frame.stride()) {
+        return None;
+    }
+    let planar = width % 2 == 0 && height % 2 == 0;
+    match frame.pixel_format() {
+        PixelFormat::Bgra => Some(ffi::NDIlib_FourCC_video_type_BGRA),
+        PixelFormat::Bgrx => Some(ffi::NDIlib_FourCC_video_type_BGRX),
+        PixelFormat::Rgba => Some(ffi::NDIlib_FourCC_video_type_RGBA),
+        PixelFormat::Rgbx => Some(ffi::NDIlib_FourCC_video_type_RGBX),
+        PixelFormat::Nv12 if planar => Some(ffi::NDIlib_FourCC_video_type_NV12),
+        PixelFormat::I420 if planar => Some(ffi::NDIlib_FourCC_video_type_I420),
+        _ => None,
+    }
+}
+
+/// A time in the 100 ns units of NDI.
+fn timecode(time: Duration) -> i64 {
+    (time.as_nanos() / 100).min(i64::MAX as u128 - 1) as i64
+}
@@ -0,0 +1,302 @@
+use super::codec::{BlockingEncoder, Encoded};
+use super::{FrameSink, MkvFile, Mp4File};
+use crate::encode::{self, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Keeps the last seconds of the capture encoded in memory, to be saved
+/// after the fact with `ReplayHandle::save_replay`.
+///
+/// The buffer starts at a keyframe and is cut a group of pictures at a
+/// time, so it holds at least `length` once that much was captured and at
+/// most one keyframe interval more. Without an interval in the config a
+/// keyframe is made every two seconds. Nothing is written until saved.
+pub struct Replay {
+    encoder: BlockingEncoder,
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+impl std::fmt::Debug for Replay {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Replay")
+            .field("config", self.encoder.config())
+            .field("buffer", &self.buffer)
+            .finish()
+    }
+}
+
+/// Saves what a `Replay` holds, also while it records and after it
+/// finished.
+#[derive(Debug, Clone)]
+pub struct ReplayHandle {
+    buffer: Arc<Mutex<Buffer>>,
+}
+
+struct Buffer {
+    config: EncoderConfig,
+    length: Duration,
+    size: Option<(u32, u32)>,
+    packets: VecDeque<EncodedPacket>,
+}
+
+impl std::fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Buffer")
+            .field("length", &self.length)
+            .field("size", &self.size)
+            .field("packets", &self.packets.len())
+            .finish()
+    }
+}
+
+impl Replay {
+    /// Keeps `length` of video, encoding with `encode::default_encoder`.
+    pub fn new(config: EncoderConfig, length: Duration) -> Result<Self> {
+        let config = match config.keyframe_interval {
+            Some(_) => config,
+            None => {
+                let (num, denom) = config.framerate;
+                let interval = 2 * num.max(1) / denom.max(1);
+                config.with_keyframe_interval(interval)
+            }
+        };
+        Ok(Replay::with_encoder(
+            encode::default_encoder(config)?,
+            length,
+        ))
+    }
+
+    pub fn with_encoder(encoder: Box<dyn Encoder>, length: Duration) -> Self {
+        let buffer = Buffer {
+            config: encoder.config().clone(),
+            length: length.max(Duration::from_secs(1)),
+            size: None,
+            packets: VecDeque::new(),
+        };
+        Replay {
+            encoder: BlockingEncoder::new(encoder),
+            buffer: Arc::new(Mutex::new(buffer)),
+        }
+    }
+
+    pub fn handle(&self) -> ReplayHandle {
+        ReplayHandle {
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+impl ReplayHandle {
+    /// Writes the buffered video to `path`, as Matroska for a `.mkv` or
+    /// `.webm` file and MP4 otherwise. Fails without a keyframe yet.
+    pub async fn save_replay(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let (config, size, packets) = {
+            let buffer = self.buffer.lock().unwrap();
+            let packets: Vec<EncodedPacket> = buffer.packets.iter().cloned().collect();
+            (buffer.config.clone(), buffer.size, packets)
+        };
+        let Some(size) = size.filter(|_| !packets.is_empty()) else {
+            return Err(ScreencastError::Unsupported(
+                "a replay of nothing recorded".to_string(),
+            ));
+        };
+        let encoder = Box::new(Encoded(config));
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("mkv") => {
+                let mut mkv = MkvFile::create_with_encoder(path, encoder).await?;
+                let written = mkv.write_encoded(size, packets).await;
+                mkv.finish().await.and(written)
+            }
+            Some("webm") => {
+                let mut webm = MkvFile::create_webm_with_encoder(path, encoder).await?;
+                let written = webm.write_encoded(size, packets).await;
+                webm.finish().await.and(written)
+            }
+            _ => {
+                let mut mp4 = Mp4File::create_with_encoder(path, encoder).await?;
+                let written = mp4.write_encoded(size, packets).await;
+                mp4.finish().await.and(written)
+            }
+        }
+    }
+
+    /// The length of video held, from its first packet to its last.
+    pub fn buffered(&self) -> Duration {
+        let buffer = self.buffer.lock().unwrap();
+        match (buffer.packets.front(), buffer.packets.back()) {
+            (Some(first), Some(last)) => last.pts.saturating_sub(first.pts),
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+impl Buffer {
+    fn push(&mut self, packets: Vec<EncodedPacket>) {
+        for packet in packets {
+            // nothing decodes before the first keyframe
+            if self.packets.is_empty() && !packet.keyframe {
+                continue;
+            }
+            self.packets.push_back(packet);
+        }
+        let Some(newest) = self.packets.back().map(|packet| packet.pts) else {
+            return;
+        };
+        let start = newest.saturating_sub(self.length);
+        // drops the oldest group of pictures while the next one is enough
+        while let Some(next) = self
+            .packets
+            .iter()
+            .skip(1)
+            .position(|packet| packet.keyframe)
+            .map(|position| position + 1)
+            && self.packets[next].pts <= start
+        {
+            self.packets.drain(..next);
+        }
+    }
+}
+
+impl FrameSink for Replay {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let size = (frame.width(), frame.height());
+        let packets = self.encoder.encode(frame).await?;
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.size.get_or_insert(size);
+        buffer.push(packets);
+        Ok(())
+    }
+
+    /// Drains the encoder into the buffer, which stays to be saved.
+    async fn finish(&mut self) -> Result<()> {
+        let packets = self.encoder.finish().await?;
+        self.buffer.lock().unwrap().push(packets);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encode::Codec;
+    use crate::format::PixelFormat;
+
+    /// A packet per frame at the frame's pts, a keyframe every `interval`.
+    #[derive(Debug)]
+    struct Packets {
+        config: EncoderConfig,
+        interval: u32,
+        count: u32,
+    }
+
+    impl Encoder for Packets {
+        fn config(&self) -> &EncoderConfig {
+            &self.config
+        }
+
+        fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<EncodedPacket>> {
+            let keyframe = self.count.is_multiple_of(self.interval);
+            self.count += 1;
+            Ok(vec![packet(frame.pts().unwrap(), keyframe)])
+        }
+
+        fn finish(&mut self) -> Result<Vec<EncodedPacket>> {
+            Ok(Vec::new())
+        }
+
+        fn force_keyframe(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn packet(pts: Duration, keyframe: bool) -> EncodedPacket {
+        EncodedPacket {
+            codec: Codec::Vp9,
+            data: vec![if keyframe { 0x80 } else { 0x84 }],
+            pts,
+            keyframe,
+        }
+    }
+
+    fn buffer(length: Duration) -> Buffer {
+        Buffer {
+            config: EncoderConfig::new(Codec::Vp9),
+            length,
+            size: None,
+            packets: VecDeque::new(),
+        }
+    }
+
+    #[test]
+    fn evicts_whole_groups() {
+        let mut buffer = buffer(Duration::from_secs(1));
+        let step = Duration::from_millis(100);
+        for index in 0..=30u32 {
+            buffer.push(vec![packet(step * index, index.is_multiple_of(5))]);
+            let first = buffer.packets.front().unwrap();
+            let last = buffer.packets.back().unwrap();
+            assert!(first.keyframe);
+            let held = last.pts - first.pts;
+            assert!(
+                held >= last.pts.min(Duration::from_secs(1)),
+                "{index}: {held:?}"
+            );
+            assert!(held < Duration::from_millis(1500), "{index}: {held:?}");
+        }
+        assert_eq!(buffer.packets.front().unwrap().pts, Duration::from_secs(2));
+        assert_eq!(buffer.packets.len(), 11);
+    }
+
+    #[test]
+    fn starts_at_a_keyframe() {
+        let mut buffer = buffer(Duration::from_secs(1));
+        let millis = Duration::from_millis;
+        buffer.push(vec![packet(millis(0), false), packet(millis(100), false)]);
+        assert!(buffer.packets.is_empty());
+        buffer.push(vec![packet(millis(200), true), packet(millis(300), false)]);
+        let pts: Vec<_> = buffer.packets.iter().map(|packet| packet.pts).collect();
+        assert_eq!(pts, [millis(200), millis(300)]);
+        // no later keyframe, nothing to cut yet
+        buffer.push(
+            (4..40)
+                .map(|index| packet(millis(100) * index, false))
+                .collect(),
+        );
+        assert_eq!(buffer.packets.len(), 38);
+    }
+
+    #[tokio::test]
+    async fn saves_a_replay() {
+        let encoder = Packets {
+            config: EncoderConfig::new(Codec::Vp9),
+            interval: 10,
+            count: 0,
+        };
+        let mut replay = Replay::with_encoder(Box::new(encoder), Duration::from_secs(1));
+        let handle = replay.handle();
+        let path = std::env::temp_dir().join(format!("xdp-screencast-{}.webm", std::process::id()));
+        assert!(matches!(
+            handle.save_replay(&path).await,
+            Err(ScreencastError::Unsupported(_))
+        ));
+
+        for index in 0..25u32 {
+            let frame = Frame::packed(PixelFormat::Bgrx, 2, 2, 8, vec![0; 16])
+                .with_pts(Duration::from_millis(100) * index);
+            replay.write_frame(frame).await.unwrap();
+        }
+        replay.finish().await.unwrap();
+        assert_eq!(handle.buffered(), Duration::from_millis(1400));
+
+        handle.save_replay(&path).await.unwrap();
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&file[..4], [0x1a, 0x45, 0xdf, 0xa3]);
+        assert!(file.windows(4).any(|window| window == b"webm"));
+    }
+}
@@ -0,0 +1,234 @@
+/// An AMF0 value, as RTMP commands and FLV metadata carry them.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) enum Value {
+    Number(f64),
+    Boolean(bool),
+    String(String),
+    Object(Vec<(String, Value)>),
+    Null,
+    /// An object with a count ahead, how `onMetaData` is written.
+    EcmaArray(Vec<(String, Value)>),
+}
+
+const NUMBER: u8 = 0;
+const BOOLEAN: u8 = 1;
+const STRING: u8 = 2;
+const OBJECT: u8 = 3;
+const NULL: u8 = 5;
+const UNDEFINED: u8 = 6;
+const ECMA_ARRAY: u8 = 8;
+const OBJECT_END: u8 = 9;
+const STRICT_ARRAY: u8 = 10;
+const LONG_STRING: u8 = 12;
+
+impl Value {
+    pub(super) fn string(value: impl Into<String>) -> Self {
+        Value::String(value.into())
+    }
+
+    pub(super) fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub(super) fn as_number(&self) -> Option<f64> {
+        match self {
+            Value::Number(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// A property of an object.
+    pub(super) fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(properties) | Value::EcmaArray(properties) => properties
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value),
+            _ => None,
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Number(value) => {
+                out.push(NUMBER);
+                out.extend(value.to_be_bytes());
+            }
+            Value::Boolean(value) => out.extend([BOOLEAN, *value as u8]),
+            Value::String(value) if value.len() > u16::MAX as usize => {
+                out.push(LONG_STRING);
+                out.extend((value.len() as u32).to_be_bytes());
+                out.extend_from_slice(value.as_bytes());
+            }
+            Value::String(value) => {
+                out.push(STRING);
+                write_key(out, value);
+            }
+            Value::Object(properties) => {
+                out.push(OBJECT);
+                write_properties(out, properties);
+            }
+            Value::Null => out.push(NULL),
+            Value::EcmaArray(properties) => {
+                out.push(ECMA_ARRAY);
+                out.extend((properties.len() as u32).to_be_bytes());
+                write_properties(out, properties);
+            }
+        }
+    }
+}
+
+pub(super) fn encode(values: &[Value]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for value in values {
+        value.write(&mut out);
+    }
+    out
+}
+
+/// The values of a message up to the first one of a type not known here.
+pub(super) fn decode(mut data: &[u8]) -> Vec<Value> {
+    let mut values = Vec::new();
+    while let Some(value) = read(&mut data) {
+        values.push(value);
+    }
+    values
+}
+
+fn write_key(out: &mut Vec<u8>, key: &str) {
+    out.extend((key.len() as u16).to_be_bytes());
+    out.extend_from_slice(key.as_bytes());
+}
+
+fn write_properties(out: &mut Vec<u8>, properties: &[(String, Value)]) {
+    for (key, value) in properties {
+        write_key(out, key);
+        value.write(out);
+    }
+    out.extend([0, 0, OBJECT_END]);
+}
+
+fn take<'a>(data: &mut &'a [u8], length: usize) -> Option<&'a [u8]> {
+    let (taken, rest) = data.split_at_checked(length)?;
+    *data = rest;
+    Some(taken)
+}
+
+fn read_key(data: &mut &[u8]) -> Option<String> {
+    let length = u16::from_be_bytes(take(data, 2)?.try_into().ok()?) as usize;
+    Some(String::from_utf8_lossy(take(data, length)?).into_owned())
+}
+
+fn read_properties(data: &mut &[u8]) -> Option<Vec<(String, Value)>> {
+    let mut properties = Vec::new();
+    loop {
+        let key = read_key(data)?;
+        if key.is_empty() && data.first() == Some(&OBJECT_END) {
+            take(data, 1)?;
+            return Some(properties);
+        }
+        properties.push((key, read(data)?));
+    }
+}
+
+fn read(data: &mut &[u8]) -> Option<Value> {
+    let kind = take(data, 1)?[0];
+    Some(match kind {
+        NUMBER => Value::Number(f64::from_be_bytes(take(data, 8)?.try_into().ok()?)),
+        BOOLEAN => Value::Boolean(take(data, 1)?[0] != 0),
+        STRING => Value::String(read_key(data)?),
+        LONG_STRING => {
+            let length = u32::from_be_bytes(take(data, 4)?.try_into().ok()?) as usize;
+            Value::String(String::from_utf8_lossy(take(data, length)?).into_owned())
+        }
+        OBJECT => Value::Object(read_properties(data)?),
+        NULL | UNDEFINED => Value::Null,
+        ECMA_ARRAY => {
+            take(data, 4)?;
+            Value::EcmaArray(read_properties(data)?)
+        }
+        STRICT_ARRAY => {
+            let count = u32::from_be_bytes(take(data, 4)?.try_into().ok()?);
+            let mut items = Vec::new();
+            for index in 0..count {
+                items.push((index.to_string(), read(data)?));
+            }
+            Value::EcmaArray(items)
+        }
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let values = vec![
+            Value::string("connect"),
+            Value::Number(1.0),
+            Value::Object(vec![
+                ("app".into(), Value::string("live")),
+                (
+                    "nested".into(),
+                    Value::Object(vec![
+                        ("audio".into(), Value::Boolean(true)),
+                        (
+                            "inner".into(),
+                            Value::Object(vec![("none".into(), Value::Null)]),
+                        ),
+                    ]),
+                ),
+                ("empty".into(), Value::Object(Vec::new())),
+            ]),
+            Value::Null,
+            Value::EcmaArray(vec![
+                ("duration".into(), Value::Number(0.0)),
+                ("encoder".into(), Value::string("x".repeat(70_000))),
+            ]),
+        ];
+        let data = encode(&values);
+        assert_eq!(decode(&data), values);
+        assert_eq!(
+            values[2]
+                .get("nested")
+                .and_then(|nested| nested.get("inner")),
+            Some(&Value::Object(vec![("none".into(), Value::Null)]))
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let data = encode(&[Value::Object(vec![("a".into(), Value::Boolean(false))])]);
+        assert_eq!(data, [OBJECT, 0, 1, b'a', BOOLEAN, 0, 0, 0, OBJECT_END]);
+        let data = encode(&[Value::EcmaArray(Vec::new())]);
+        assert_eq!(data, [ECMA_ARRAY, 0, 0, 0, 0, 0, 0, OBJECT_END]);
+    }
+
+    #[test]
+    fn decodes_up_to_unknown_types() {
+        let mut data = vec![STRICT_ARRAY, 0, 0, 0, 2];
+        encode(&[Value::Number(2.0), Value::string("b")])
+            .iter()
+            .for_each(|byte| data.push(*byte));
+        data.push(UNDEFINED);
+        data.push(0x11);
+        data.extend(encode(&[Value::Null]));
+        assert_eq!(
+            decode(&data),
+            [
+                Value::EcmaArray(vec![
+                    ("0".into(), Value::Number(2.0)),
+                    ("1".into(), Value::string("b")),
+                ]),
+                Value::Null,
+            ]
+        );
+        // a truncated object is no value
+        assert_eq!(decode(&[OBJECT, 0, 1, b'a']), []);
+    }
+}
@@ -0,0 +1,288 @@
+use std::collections::HashMap;
+
+/// Chunks of a connection start at this size until a Set Chunk Size.
+const DEFAULT_CHUNK_SIZE: usize = 128;
+/// Larger timestamps go in 4 more bytes after the header.
+const EXTENDED_TIMESTAMP: u32 = 0xff_ffff;
+
+/// A message of the RTMP chunk stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Message {
+    pub kind: u8,
+    pub stream_id: u32,
+    pub timestamp: u32,
+    pub payload: Vec<u8>,
+}
+
+/// Splits messages into chunks, each with a full header as the first of
+/// its message so no state of earlier ones is needed.
+#[derive(Debug)]
+pub(super) struct ChunkWriter {
+    pub chunk_size: usize,
+}
+
+impl ChunkWriter {
+    pub(super) fn new() -> Self {
+        ChunkWriter {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    /// Appends the chunks of a message on chunk stream `csid`, 2 to 63.
+    pub(super) fn write(&self, out: &mut Vec<u8>, csid: u8, message: &Message) {
+        let extended = message.timestamp >= EXTENDED_TIMESTAMP;
+        let timestamp = message.timestamp.min(EXTENDED_TIMESTAMP);
+        out.push(csid & 0x3f);
+        out.extend(&timestamp.to_be_bytes()[1..]);
+        out.extend(&(message.payload.len() as u32).to_be_bytes()[1..]);
+        out.push(message.kind);
+        out.extend(message.stream_id.to_le_bytes());
+        if extended {
+            out.extend(message.timestamp.to_be_bytes());
+        }
+        for (index, chunk) in message.payload.chunks(self.chunk_size).enumerate() {
+            if index > 0 {
+                out.push(0xc0 | (csid & 0x3f));
+                if extended {
+                    out.extend(message.timestamp.to_be_bytes());
+                }
+            }
+            out.extend_from_slice(chunk);
+        }
+    }
+}
+
+/// Reassembles the messages of the server from its chunks, in whatever
+/// pieces the connection delivers them.
+#[derive(Debug)]
+pub(super) struct ChunkReader {
+    pub chunk_size: usize,
+    buffer: Vec<u8>,
+    streams: HashMap<u32, ChunkStream>,
+}
+
+/// What the headers of a chunk stream leave out is that of the one before.
+#[derive(Debug, Default)]
+struct ChunkStream {
+    timestamp: u32,
+    delta: u32,
+    length: usize,
+    kind: u8,
+    stream_id: u32,
+    extended: bool,
+    payload: Vec<u8>,
+}
+
+impl ChunkReader {
+    pub(super) fn new() -> Self {
+        ChunkReader {
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            buffer: Vec::new(),
+            streams: HashMap::new(),
+        }
+    }
+
+    /// Adds what was received, the messages it completed come out.
+    pub(super) fn feed(&mut self, data: &[u8]) -> Vec<Message> {
+        let mut buffer = std::mem::take(&mut self.buffer);
+        buffer.extend_from_slice(data);
+        let mut messages = Vec::new();
+        let mut offset = 0;
+        while let Some((length, message)) = self.chunk(&buffer[offset..]) {
+            offset += length;
+            if let Some(message) = message {
+                // takes effect with the next chunk
+                if message.kind == super::SET_CHUNK_SIZE && message.payload.len() >= 4 {
+                    let size = u32::from_be_bytes(message.payload[..4].try_into().unwrap());
+                    self.chunk_size = (size & 0x7fff_ffff).max(1) as usize;
+                }
+                messages.push(message);
+            }
+        }
+        buffer.drain(..offset);
+        self.buffer = buffer;
+        messages
+    }
+
+    /// Reads one chunk when all of it is there: its length, and the
+    /// message it completed.
+    fn chunk(&mut self, data: &[u8]) -> Option<(usize, Option<Message>)> {
+        let first = *data.first()?;
+        let format = first >> 6;
+        let (csid, mut offset) = match first & 0x3f {
+            0 => (64 + *data.get(1)? as u32, 2),
+            1 => (64 + *data.get(1)? as u32 + 256 * *data.get(2)? as u32, 3),
+            csid => (csid as u32, 1),
+        };
+        let header = match format {
+            0 => 11,
+            1 => 7,
+            2 => 3,
+            _ => 0,
+        };
+        let fields = data.get(offset..offset + header)?;
+        offset += header;
+        let u24 = |bytes: &[u8]| u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        let stream = self.streams.entry(csid).or_default();
+        let starts = stream.payload.is_empty();
+        let (mut timestamp, mut extended) = (stream.delta, stream.extended);
+        if format < 3 {
+            timestamp = u24(&fields[..3]);
+            extended = timestamp == EXTENDED_TIMESTAMP;
+        }
+        if extended {
+            timestamp = u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?);
+            offset += 4;
+        }
+        let length = match format {
+            0 | 1 => u24(&fields[3..6]) as usize,
+            _ => stream.length,
+        };
+        let remaining = length.saturating_sub(stream.payload.len());
+        let size = remaining.min(self.chunk_size);
+        let body = data.get(offset..offset + size)?;
+
+        // only a complete chunk changes the state
+        stream.extended = extended;
+        stream.length = length;
+        if format < 2 {
+            stream.kind = fields[6];
+        }
+        if format == 0 {
+            stream.stream_id = u32::from_le_bytes(fields[7..11].try_into().unwrap());
+        }
+        if starts {
+            match format {
+                0 => {
+                    stream.timestamp = timestamp;
+                    stream.delta = 0;
+                }
+                _ => {
+                    if format < 3 {
+                        stream.delta = timestamp;
+                    }
+                    stream.timestamp = stream.timestamp.wrapping_add(timestamp);
+                }
+            }
+        }
+        stream.payload.extend_from_slice(body);
+        if stream.payload.len() < stream.length {
+            return Some((offset + size, None));
+        }
+        let message = Message {
+            kind: stream.kind,
+            stream_id: stream.stream_id,
+            timestamp: stream.timestamp,
+            payload: std::mem::take(&mut stream.payload),
+        };
+        Some((offset + size, Some(message)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(timestamp: u32, size: usize) -> Message {
+        Message {
+            kind: 9,
+            stream_id: 1,
+            timestamp,
+            payload: (0..size).map(|index| index as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn round_trip_in_pieces() {
+        let writer = ChunkWriter::new();
+        let messages = [message(0, 300), message(33, 10), message(66, 128)];
+        let mut out = Vec::new();
+        for message in &messages {
+            writer.write(&mut out, 6, message);
+        }
+        // 300 bytes take a header and 2 more chunks of a byte each
+        assert_eq!(out.len(), 3 * 12 + 2 + 438);
+
+        let mut reader = ChunkReader::new();
+        let mut read = Vec::new();
+        for byte in &out {
+            read.extend(reader.feed(std::slice::from_ref(byte)));
+        }
+        assert_eq!(read, messages);
+    }
+
+    #[test]
+    fn extended_timestamps() {
+        let writer = ChunkWriter::new();
+        let messages = [message(0xff_ffff, 200), message(0x1234_5678, 300)];
+        let mut out = Vec::new();
+        for message in &messages {
+            writer.write(&mut out, 4, message);
+        }
+        assert_eq!(&out[1..4], [0xff; 3]);
+        assert_eq!(&out[12..16], 0xff_ffffu32.to_be_bytes());
+        // the continuation repeats it
+        assert_eq!(out[16 + 128], 0xc4);
+        assert_eq!(&out[17 + 128..21 + 128], 0xff_ffffu32.to_be_bytes());
+
+        let mut reader = ChunkReader::new();
+        assert_eq!(reader.feed(&out), messages);
+    }
+
+    #[test]
+    fn compressed_headers() {
+        let mut out = Vec::new();
+        // format 0: timestamp 1000, 3 bytes, kind 8, stream 1
+        out.extend([0x04, 0, 0x03, 0xe8, 0, 0, 3, 8, 1, 0, 0, 0]);
+        out.extend([1, 2, 3]);
+        // format 1: delta 40, 2 bytes, kind 9
+        out.extend([0x44, 0, 0, 40, 0, 0, 2, 9]);
+        out.extend([4, 5]);
+        // format 2: delta 20
+        out.extend([0x84, 0, 0, 20]);
+        out.extend([6, 7]);
+        // format 3: all as before
+        out.push(0xc4);
+        out.extend([8, 9]);
+        // a chunk stream id of two bytes
+        out.extend([0x00, 36, 0, 0, 5, 0, 0, 1, 20, 0, 0, 0, 0]);
+        out.push(10);
+
+        let mut reader = ChunkReader::new();
+        let messages = reader.feed(&out);
+        let timestamps: Vec<_> = messages.iter().map(|message| message.timestamp).collect();
+        assert_eq!(timestamps, [1000, 1040, 1060, 1080, 5]);
+        let kinds: Vec<_> = messages.iter().map(|message| message.kind).collect();
+        assert_eq!(kinds, [8, 9, 9, 9, 20]);
+        assert!(messages[..4].iter().all(|message| message.stream_id == 1));
+        assert_eq!(messages[2].payload, [6, 7]);
+        assert_eq!(messages[3].payload, [8, 9]);
+        assert_eq!(messages[4].stream_id, 0);
+    }
+
+    #[test]
+    fn set_chunk_size_mid_stream() {
+        let mut writer = ChunkWriter::new();
+        let mut out = Vec::new();
+        writer.write(&mut out, 6, &message(0, 200));
+        let set_chunk_size = Message {
+            kind: crate::sink::rtmp::SET_CHUNK_SIZE,
+            stream_id: 0,
+            timestamp: 0,
+            payload: 4096u32.to_be_bytes().to_vec(),
+        };
+        writer.write(&mut out, 2, &set_chunk_size);
+        writer.chunk_size = 4096;
+        writer.write(&mut out, 6, &message(33, 1000));
+        assert_eq!(out.len(), 12 + 1 + 200 + 12 + 4 + 12 + 1000);
+
+        let mut reader = ChunkReader::new();
+        let (first, rest) = out.split_at(220);
+        let mut messages = reader.feed(first);
+        assert_eq!(reader.chunk_size, 128);
+        messages.extend(reader.feed(rest));
+        assert_eq!(reader.chunk_size, 4096);
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2], message(33, 1000));
+    }
+}
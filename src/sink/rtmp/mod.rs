@@ -0,0 +1,608 @@
+mod amf;
+mod chunk;
+
+use super::FrameSink;
+use super::codec::{self, BlockingEncoder};
+#[cfg(feature = "webrtc")]
+use super::webrtc::tls::TlsStream;
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime::compat;
+use amf::Value;
+use chunk::{ChunkReader, ChunkWriter, Message};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+const SET_CHUNK_SIZE: u8 = 1;
+const ACKNOWLEDGEMENT: u8 = 3;
+const USER_CONTROL: u8 = 4;
+const WINDOW_ACK_SIZE: u8 = 5;
+const SET_PEER_BANDWIDTH: u8 = 6;
+const AUDIO: u8 = 8;
+const VIDEO: u8 = 9;
+const DATA: u8 = 18;
+const COMMAND: u8 = 20;
+
+const PING_REQUEST: u16 = 6;
+const PING_RESPONSE: u16 = 7;
+
+// chunk streams, as other encoders use them
+const CSID_CONTROL: u8 = 2;
+const CSID_COMMAND: u8 = 3;
+const CSID_AUDIO: u8 = 4;
+const CSID_DATA: u8 = 5;
+const CSID_VIDEO: u8 = 6;
+
+const CHUNK_SIZE: usize = 4096;
+/// The bytes the server may send before it hears of them.
+const WINDOW: u32 = 2_500_000;
+const HANDSHAKE: usize = 1536;
+/// For the server to answer a command.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// FLV codec ids of `onMetaData`.
+const AVC: f64 = 7.0;
+const AAC: f64 = 10.0;
+
+/// Publishes H.264 and AAC over RTMP, e.g. to the ingest of Twitch or
+/// YouTube: `rtmp://host[:port]/app/stream-key`, the last path segment the
+/// stream key. `rtmps://` needs the `webrtc` feature, which links OpenSSL.
+///
+/// The stream starts with the first keyframe, audio before it is left out.
+pub struct Rtmp {
+    connection: Connection,
+    writer: ChunkWriter,
+    reader: ChunkReader,
+    encoder: BlockingEncoder,
+    audio: Option<AudioTrack>,
+    stream_id: u32,
+    key: String,
+    transaction: f64,
+    // bytes received, those acknowledged and how many may go unacknowledged
+    received: u64,
+    acknowledged: u64,
+    window: u32,
+    size: Option<(u32, u32)>,
+    // the pts at timestamp 0, of the first keyframe
+    start: Option<Duration>,
+}
+
+impl std::fmt::Debug for Rtmp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Rtmp")
+            .field("connection", &self.connection)
+            .field("config", self.encoder.config())
+            .field("stream_id", &self.stream_id)
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+enum Connection {
+    Plain(TcpStream),
+    #[cfg(feature = "webrtc")]
+    Tls(Box<TlsStream>),
+}
+
+impl Connection {
+    async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Connection::Plain(tcp) => Ok(tcp.write_all(data).await?),
+            #[cfg(feature = "webrtc")]
+            Connection::Tls(tls) => tls.write_all(data).await,
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Connection::Plain(tcp) => Ok(tcp.read(buf).await?),
+            #[cfg(feature = "webrtc")]
+            Connection::Tls(tls) => tls.read(buf).await,
+        }
+    }
+
+    /// `None` when nothing came in, 0 at the end.
+    fn try_read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        match self {
+            Connection::Plain(tcp) => match tcp.try_read(buf) {
+                Ok(read) => Ok(Some(read)),
+                Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => Ok(None),
+                Err(err) => Err(err.into()),
+            },
+            #[cfg(feature = "webrtc")]
+            Connection::Tls(tls) => tls.try_read(buf),
+        }
+    }
+
+    async fn read_exact(&mut self, length: usize) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; length];
+        let mut filled = 0;
+        while filled < length {
+            match self.read(&mut data[filled..]).await? {
+                0 => return Err(closed()),
+                read => filled += read,
+            }
+        }
+        Ok(data)
+    }
+}
+
+/// The parts of an RTMP URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Target {
+    tls: bool,
+    host: String,
+    port: u16,
+    app: String,
+    key: String,
+}
+
+impl Target {
+    fn parse(url: &str) -> Result<Self> {
+        let invalid = || ScreencastError::Streaming(format!("invalid RTMP URL {:?}", url));
+        let (tls, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("rtmp") => (false, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("rtmps") => (true, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = rest.split_once('/').ok_or_else(invalid)?;
+        let (app, key) = path.rsplit_once('/').ok_or_else(invalid)?;
+        let default_port = if tls { 443 } else { 1935 };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+                match rest.strip_prefix(':') {
+                    Some(port) => (host, port.parse().map_err(|_| invalid())?),
+                    None => (host, default_port),
+                }
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+                None => (authority, default_port),
+            },
+        };
+        if host.is_empty() || app.is_empty() || key.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Target {
+            tls,
+            host: host.to_string(),
+            port,
+            app: app.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    /// The URL of the application, `tcUrl` of `connect`.
+    fn tc_url(&self) -> String {
+        let scheme = if self.tls { "rtmps" } else { "rtmp" };
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        format!("{}://{}:{}/{}", scheme, host, self.port, self.app)
+    }
+}
+
+impl Rtmp {
+    /// Connects and starts publishing, encoding with
+    /// `encode::default_encoder`.
+    pub async fn connect(url: &str, config: EncoderConfig) -> Result<Self> {
+        Rtmp::connect_with_encoder(url, encode::default_encoder(config)?).await
+    }
+
+    pub async fn connect_with_encoder(url: &str, encoder: Box<dyn Encoder>) -> Result<Self> {
+        let codec = encoder.config().codec;
+        if codec != Codec::H264 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} over RTMP",
+                codec
+            )));
+        }
+        let target = Target::parse(url)?;
+        if target.tls && !cfg!(feature = "webrtc") {
+            return Err(ScreencastError::Unsupported(
+                "rtmps without the webrtc feature".to_string(),
+            ));
+        }
+        compat(async {
+            let tcp = TcpStream::connect((target.host.as_str(), target.port)).await?;
+            let _ = tcp.set_nodelay(true);
+            let connection = match target.tls {
+                #[cfg(feature = "webrtc")]
+                true => Connection::Tls(Box::new(TlsStream::connect(tcp, &target.host).await?)),
+                _ => Connection::Plain(tcp),
+            };
+            let mut rtmp = Rtmp {
+                connection,
+                writer: ChunkWriter::new(),
+                reader: ChunkReader::new(),
+                encoder: BlockingEncoder::new(encoder),
+                audio: None,
+                stream_id: 0,
+                key: target.key.clone(),
+                transaction: 0.0,
+                received: 0,
+                acknowledged: 0,
+                window: WINDOW,
+                size: None,
+                start: None,
+            };
+            tokio::time::timeout(TIMEOUT * 3, rtmp.publish(&target))
+                .await
+                .map_err(|_| {
+                    ScreencastError::Streaming(format!("{} did not answer", target.host))
+                })??;
+            Ok(rtmp)
+        })
+        .await
+    }
+
+    /// Publishes an AAC track along, the audio up to each frame.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Result<Self> {
+        if audio.config().codec != AudioCodec::Aac {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} over RTMP",
+                audio.config().codec
+            )));
+        }
+        self.audio = Some(audio);
+        Ok(self)
+    }
+
+    /// The handshake, then `connect`, `createStream` and `publish`.
+    async fn publish(&mut self, target: &Target) -> Result<()> {
+        // the simple handshake, C2 echoes S1
+        let mut hello = vec![3];
+        hello.extend([0; 8]);
+        hello.extend((0..HANDSHAKE - 8).map(|_| fastrand::u8(..)));
+        self.connection.write_all(&hello).await?;
+        let reply = self.connection.read_exact(1 + HANDSHAKE).await?;
+        if reply[0] != 3 {
+            return Err(ScreencastError::Streaming(format!(
+                "RTMP version {} of the server",
+                reply[0]
+            )));
+        }
+        self.connection.write_all(&reply[1..]).await?;
+        self.connection.read_exact(HANDSHAKE).await?;
+
+        let mut out = Vec::new();
+        let chunk_size = (CHUNK_SIZE as u32).to_be_bytes().to_vec();
+        self.control(&mut out, SET_CHUNK_SIZE, chunk_size);
+        self.writer.chunk_size = CHUNK_SIZE;
+        let properties = vec![
+            ("app".to_string(), Value::string(&target.app)),
+            ("type".to_string(), Value::string("nonprivate")),
+            (
+                "flashVer".to_string(),
+                Value::string("FMLE/3.0 (compatible; xdp-screencast)"),
+            ),
+            ("tcUrl".to_string(), Value::string(target.tc_url())),
+        ];
+        let connect = self.command(&mut out, 0, "connect", vec![Value::Object(properties)]);
+        self.connection.write_all(&out).await?;
+        self.result(connect).await?;
+
+        let mut out = Vec::new();
+        let key = Value::string(&target.key);
+        self.command(&mut out, 0, "releaseStream", vec![Value::Null, key.clone()]);
+        self.command(&mut out, 0, "FCPublish", vec![Value::Null, key.clone()]);
+        let create = self.command(&mut out, 0, "createStream", vec![Value::Null]);
+        self.connection.write_all(&out).await?;
+        let result = self.result(create).await?;
+        self.stream_id = result.get(3).and_then(Value::as_number).ok_or_else(|| {
+            ScreencastError::Streaming("createStream without a stream".to_string())
+        })? as u32;
+
+        let mut out = Vec::new();
+        let values = vec![Value::Null, key, Value::string("live")];
+        self.command(&mut out, self.stream_id, "publish", values);
+        self.connection.write_all(&out).await?;
+        loop {
+            let values = self.next_command().await?;
+            if values.first().and_then(Value::as_str) == Some("onStatus") {
+                let info = values.get(3);
+                let code = info.and_then(|info| info.get("code")?.as_str());
+                if code == Some("NetStream.Publish.Start") {
+                    return Ok(());
+                }
+                check_status(&values)?;
+            }
+        }
+    }
+
+    /// Appends a protocol control message.
+    fn control(&self, out: &mut Vec<u8>, kind: u8, payload: Vec<u8>) {
+        let message = Message {
+            kind,
+            stream_id: 0,
+            timestamp: 0,
+            payload,
+        };
+        self.writer.write(out, CSID_CONTROL, &message);
+    }
+
+    /// Appends a command, its transaction id comes back.
+    fn command(
+        &mut self,
+        out: &mut Vec<u8>,
+        stream_id: u32,
+        name: &str,
+        values: Vec<Value>,
+    ) -> f64 {
+        self.transaction += 1.0;
+        let mut arguments = vec![Value::string(name), Value::Number(self.transaction)];
+        arguments.extend(values);
+        let message = Message {
+            kind: COMMAND,
+            stream_id,
+            timestamp: 0,
+            payload: amf::encode(&arguments),
+        };
+        self.writer.write(out, CSID_COMMAND, &message);
+        self.transaction
+    }
+
+    /// The `_result` of the command of `transaction`.
+    async fn result(&mut self, transaction: f64) -> Result<Vec<Value>> {
+        loop {
+            let values = self.next_command().await?;
+            if values.get(1).and_then(Value::as_number) != Some(transaction) {
+                continue;
+            }
+            match values.first().and_then(Value::as_str) {
+                Some("_result") => return Ok(values),
+                Some("_error") => {
+                    let info = values.get(3);
+                    let description = info
+                        .and_then(|info| info.get("description").or(info.get("code"))?.as_str())
+                        .unwrap_or("no reason given");
+                    return Err(ScreencastError::Streaming(format!(
+                        "the RTMP server refused: {}",
+                        description
+                    )));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Waits for the next command of the server, answering its control
+    /// messages on the way.
+    async fn next_command(&mut self) -> Result<Vec<Value>> {
+        let mut buf = vec![0u8; 16 * 1024];
+        loop {
+            let read = tokio::time::timeout(TIMEOUT, self.connection.read(&mut buf))
+                .await
+                .map_err(|_| {
+                    ScreencastError::Streaming("the RTMP server went silent".to_string())
+                })??;
+            if read == 0 {
+                return Err(closed());
+            }
+            let (commands, out) = self.receive(&buf[..read]);
+            if !out.is_empty() {
+                self.connection.write_all(&out).await?;
+            }
+            if let Some(values) = commands.into_iter().next() {
+                return Ok(values);
+            }
+        }
+    }
+
+    /// Takes what was received: the commands in it, and what to answer.
+    fn receive(&mut self, data: &[u8]) -> (Vec<Vec<Value>>, Vec<u8>) {
+        let mut out = Vec::new();
+        let mut commands = Vec::new();
+        self.received += data.len() as u64;
+        for message in self.reader.feed(data) {
+            let payload = &message.payload;
+            match message.kind {
+                WINDOW_ACK_SIZE if payload.len() >= 4 => {
+                    self.window = u32::from_be_bytes(payload[..4].try_into().unwrap());
+                }
+                SET_PEER_BANDWIDTH => {
+                    self.control(&mut out, WINDOW_ACK_SIZE, WINDOW.to_be_bytes().to_vec());
+                }
+                USER_CONTROL if payload.len() >= 6 => {
+                    let event = u16::from_be_bytes([payload[0], payload[1]]);
+                    if event == PING_REQUEST {
+                        let mut pong = PING_RESPONSE.to_be_bytes().to_vec();
+                        pong.extend_from_slice(&payload[2..6]);
+                        self.control(&mut out, USER_CONTROL, pong);
+                    }
+                }
+                COMMAND => commands.push(amf::decode(payload)),
+                _ => {}
+            }
+        }
+        if self.window > 0 && self.received - self.acknowledged >= self.window as u64 {
+            self.acknowledged = self.received;
+            let sequence = (self.received as u32).to_be_bytes().to_vec();
+            self.control(&mut out, ACKNOWLEDGEMENT, sequence);
+        }
+        (commands, out)
+    }
+
+    /// Handles what the server sent since, without waiting for more.
+    fn poll_server(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let mut buf = vec![0u8; 16 * 1024];
+        while let Some(read) = self.connection.try_read(&mut buf)? {
+            if read == 0 {
+                return Err(closed());
+            }
+            let (commands, answer) = self.receive(&buf[..read]);
+            out.extend(answer);
+            for values in commands {
+                check_status(&values)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn media(&self, out: &mut Vec<u8>, kind: u8, pts: Duration, payload: Vec<u8>) {
+        let timestamp = self
+            .start
+            .map_or(0, |start| pts.saturating_sub(start).as_millis() as u32);
+        let message = Message {
+            kind,
+            stream_id: self.stream_id,
+            timestamp,
+            payload,
+        };
+        let csid = match kind {
+            AUDIO => CSID_AUDIO,
+            VIDEO => CSID_VIDEO,
+            _ => CSID_DATA,
+        };
+        self.writer.write(out, csid, &message);
+    }
+
+    /// `onMetaData` and the sequence headers, ahead of the first keyframe.
+    fn write_headers(&self, out: &mut Vec<u8>, keyframe: &[u8], start: Duration) -> Result<()> {
+        let config = codec::decoder_config(Codec::H264, keyframe).ok_or_else(|| {
+            ScreencastError::Encoder("the first H.264 keyframe lacks the SPS and PPS".to_string())
+        })?;
+        let encoder = self.encoder.config();
+        let (width, height) = self.size.unwrap_or_default();
+        let (num, denom) = encoder.framerate;
+        let mut metadata = vec![
+            ("duration".to_string(), Value::Number(0.0)),
+            ("width".to_string(), Value::Number(width as f64)),
+            ("height".to_string(), Value::Number(height as f64)),
+            ("videocodecid".to_string(), Value::Number(AVC)),
+            (
+                "framerate".to_string(),
+                Value::Number(num as f64 / denom.max(1) as f64),
+            ),
+            (
+                "videodatarate".to_string(),
+                Value::Number(encoder.bitrate as f64 / 1000.0),
+            ),
+            ("encoder".to_string(), Value::string("xdp-screencast")),
+        ];
+        if let Some(audio) = &self.audio {
+            let config = audio.config();
+            metadata.extend([
+                ("audiocodecid".to_string(), Value::Number(AAC)),
+                (
+                    "audiosamplerate".to_string(),
+                    Value::Number(audio.output_rate() as f64),
+                ),
+                ("audiosamplesize".to_string(), Value::Number(16.0)),
+                ("stereo".to_string(), Value::Boolean(config.channels > 1)),
+                (
+                    "audiochannels".to_string(),
+                    Value::Number(config.channels as f64),
+                ),
+                (
+                    "audiodatarate".to_string(),
+                    Value::Number(config.bitrate as f64 / 1000.0),
+                ),
+            ]);
+        }
+        let data = amf::encode(&[
+            Value::string("@setDataFrame"),
+            Value::string("onMetaData"),
+            Value::EcmaArray(metadata),
+        ]);
+        self.media(out, DATA, start, data);
+        let mut header = vec![0x17, 0, 0, 0, 0];
+        header.extend(config);
+        self.media(out, VIDEO, start, header);
+        if let Some(audio) = &self.audio {
+            let mut header = vec![0xaf, 0];
+            header.extend_from_slice(audio.codec_private());
+            self.media(out, AUDIO, start, header);
+        }
+        Ok(())
+    }
+
+    async fn write_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        let mut out = Vec::new();
+        self.poll_server(&mut out)?;
+        for packet in packets {
+            let start = match self.start {
+                Some(start) => start,
+                // nothing decodes before the first keyframe
+                None if !packet.keyframe => continue,
+                None => {
+                    self.start = Some(packet.pts);
+                    self.write_headers(&mut out, &packet.data, packet.pts)?;
+                    packet.pts
+                }
+            };
+            if let Some(audio) = &mut self.audio {
+                let sounds = audio.ready(packet.pts)?;
+                for sound in sounds.into_iter().filter(|sound| sound.pts >= start) {
+                    let mut payload = vec![0xaf, 1];
+                    payload.extend(sound.data);
+                    self.media(&mut out, AUDIO, sound.pts, payload);
+                }
+            }
+            // no B-frames, the composition time stays 0
+            let mut payload = vec![if packet.keyframe { 0x17 } else { 0x27 }, 1, 0, 0, 0];
+            payload.extend(codec::sample_data(Codec::H264, &packet.data));
+            self.media(&mut out, VIDEO, packet.pts, payload);
+        }
+        if !out.is_empty() {
+            self.connection.write_all(&out).await?;
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for Rtmp {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        self.size.get_or_insert((frame.width(), frame.height()));
+        let packets = self.encoder.encode(frame).await?;
+        compat(self.write_packets(packets)).await
+    }
+
+    /// Sends what the encoder held back and ends the stream.
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            let written = match self.encoder.finish().await {
+                Ok(packets) => self.write_packets(packets).await,
+                Err(err) => Err(err),
+            };
+            let mut out = Vec::new();
+            let stream_id = self.stream_id as f64;
+            let key = Value::string(&self.key);
+            self.command(&mut out, 0, "FCUnpublish", vec![Value::Null, key]);
+            self.command(
+                &mut out,
+                0,
+                "deleteStream",
+                vec![Value::Null, Value::Number(stream_id)],
+            );
+            let closed = self.connection.write_all(&out).await;
+            written.and(closed)
+        })
+        .await
+    }
+}
+
+/// An error of an `onStatus` of the server, e.g. a stream key it refused.
+fn check_status(values: &[Value]) -> Result<()> {
+    let info = values.get(3);
+    let level = info.and_then(|info| info.get("level")?.as_str());
+    if level != Some("error") {
+        return Ok(());
+    }
+    let reason = info
+        .and_then(|info| info.get("description").or(info.get("code"))?.as_str())
+        .unwrap_or("no reason given");
+    Err(ScreencastError::Streaming(format!(
+        "the RTMP server stopped the stream: {}",
+        reason
+    )))
+}
+
+fn closed() -> ScreencastError {
+    ScreencastError::Streaming("the RTMP server closed the connection".to_string())
+}
@@ -0,0 +1,376 @@
+use crate::audio::AudioCodec;
+use crate::encode::Codec;
+use crate::encode::bitstream::{self, OBU_SEQUENCE_HEADER, OBU_TEMPORAL_DELIMITER};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Payload bytes of an RTP packet, which with the headers of SRTP, UDP and
+/// IPv6 stays below the MTU of tunnels and PPPoE links.
+pub(crate) const MAX_PAYLOAD: usize = 1100;
+
+const RTCP_SR: u8 = 200;
+const RTCP_SDES: u8 = 202;
+const RTCP_PSFB: u8 = 206;
+
+/// Seconds between 1900, where NTP counts from, and 1970.
+const NTP_EPOCH: u64 = 2_208_988_800;
+
+/// What an RTP stream carries, and how it is split into packets.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub(crate) enum Payload {
+    Video(Codec),
+    Audio(AudioCodec),
+}
+
+impl Payload {
+    /// The clock of the timestamps, the sample rate of the audio.
+    pub(crate) fn clock_rate(self, sample_rate: u32) -> u32 {
+        match self {
+            Payload::Video(_) => 90_000,
+            Payload::Audio(AudioCodec::Opus) => 48_000,
+            Payload::Audio(AudioCodec::Aac) => sample_rate,
+        }
+    }
+
+    /// The encoding name of `a=rtpmap`.
+    pub(crate) fn encoding_name(self) -> &'static str {
+        match self {
+            Payload::Video(Codec::H264) => "H264",
+            Payload::Video(Codec::Hevc) => "H265",
+            Payload::Video(Codec::Vp8) => "VP8",
+            Payload::Video(Codec::Vp9) => "VP9",
+            Payload::Video(Codec::Av1) => "AV1",
+            Payload::Audio(AudioCodec::Opus) => "opus",
+            Payload::Audio(AudioCodec::Aac) => "mpeg4-generic",
+        }
+    }
+}
+
+/// One RTP stream: the numbering and timestamps of its packets, and how
+/// many were sent for the sender reports.
+#[derive(Debug)]
+pub(crate) struct RtpStream {
+    pub ssrc: u32,
+    pub payload_type: u8,
+    pub clock_rate: u32,
+    payload: Payload,
+    sequence: u16,
+    // added to the timestamps, which start at a random value
+    offset: u32,
+    // of the VP9 payload descriptor
+    picture_id: u16,
+    packets: u32,
+    octets: u32,
+    last_timestamp: Option<u32>,
+}
+
+impl RtpStream {
+    pub(crate) fn new(payload: Payload, payload_type: u8, clock_rate: u32) -> Self {
+        RtpStream {
+            ssrc: fastrand::u32(..),
+            payload_type,
+            clock_rate,
+            payload,
+            sequence: fastrand::u16(..),
+            offset: fastrand::u32(..),
+            picture_id: fastrand::u16(..0x8000),
+            packets: 0,
+            octets: 0,
+            last_timestamp: None,
+        }
+    }
+
+    pub(crate) fn payload(&self) -> Payload {
+        self.payload
+    }
+
+    /// The sequence number of the next packet.
+    pub(crate) fn sequence(&self) -> u16 {
+        self.sequence
+    }
+
+    /// The timestamp of a pts, on the clock all streams of a session share.
+    pub(crate) fn timestamp(&self, pts: Duration) -> u32 {
+        let ticks = pts.as_nanos() * self.clock_rate as u128 / 1_000_000_000;
+        self.offset.wrapping_add(ticks as u32)
+    }
+
+    /// Splits an encoded frame or audio packet into RTP packets, the last
+    /// one of a frame marked.
+    pub(crate) fn packetize(&mut self, data: &[u8], pts: Duration, keyframe: bool) -> Vec<Vec<u8>> {
+        let payloads = match self.payload {
+            Payload::Video(Codec::H264) => nal_payloads(Codec::H264, data),
+            Payload::Video(Codec::Hevc) => nal_payloads(Codec::Hevc, data),
+            Payload::Video(Codec::Vp8) => vp8_payloads(data),
+            Payload::Video(Codec::Vp9) => {
+                let payloads = vp9_payloads(data, self.picture_id, keyframe);
+                self.picture_id = (self.picture_id + 1) & 0x7fff;
+                payloads
+            }
+            Payload::Video(Codec::Av1) => av1_payloads(data, keyframe),
+            Payload::Audio(AudioCodec::Opus) => vec![data.to_vec()],
+            Payload::Audio(AudioCodec::Aac) => vec![aac_payload(data)],
+        };
+        let timestamp = self.timestamp(pts);
+        let count = payloads.len();
+        payloads
+            .into_iter()
+            .enumerate()
+            .map(|(index, payload)| {
+                let marker = index + 1 == count && matches!(self.payload, Payload::Video(_));
+                self.packet(&payload, timestamp, marker)
+            })
+            .collect()
+    }
+
+    fn packet(&mut self, payload: &[u8], timestamp: u32, marker: bool) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(12 + payload.len());
+        packet.push(0x80);
+        packet.push((marker as u8) << 7 | self.payload_type);
+        packet.extend(self.sequence.to_be_bytes());
+        packet.extend(timestamp.to_be_bytes());
+        packet.extend(self.ssrc.to_be_bytes());
+        packet.extend_from_slice(payload);
+        self.sequence = self.sequence.wrapping_add(1);
+        self.packets = self.packets.wrapping_add(1);
+        self.octets = self.octets.wrapping_add(payload.len() as u32);
+        self.last_timestamp = Some(timestamp);
+        packet
+    }
+
+    /// A sender report and the CNAME of the stream, what a receiver lines
+    /// up streams with: the timestamp `pts` maps to and the wall clock time
+    /// it was taken at. `None` before the first packet.
+    pub(crate) fn sender_report(
+        &self,
+        pts: Duration,
+        wall: SystemTime,
+        cname: &str,
+    ) -> Option<Vec<u8>> {
+        self.last_timestamp?;
+        let since_epoch = wall.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let seconds = since_epoch.as_secs() + NTP_EPOCH;
+        let fraction = (since_epoch.subsec_nanos() as u64) * (1 << 32) / 1_000_000_000;
+        let mut report = vec![0x80, RTCP_SR, 0, 6];
+        report.extend(self.ssrc.to_be_bytes());
+        report.extend((seconds as u32).to_be_bytes());
+        report.extend((fraction as u32).to_be_bytes());
+        report.extend(self.timestamp(pts).to_be_bytes());
+        report.extend(self.packets.to_be_bytes());
+        report.extend(self.octets.to_be_bytes());
+
+        let mut chunk = self.ssrc.to_be_bytes().to_vec();
+        let cname = &cname.as_bytes()[..cname.len().min(255)];
+        chunk.extend([1, cname.len() as u8]);
+        chunk.extend_from_slice(cname);
+        // the item list ends with a zero byte, padded to 32 bits
+        chunk.resize((chunk.len() + 4) / 4 * 4, 0);
+        report.extend([0x81, RTCP_SDES]);
+        report.extend(((chunk.len() / 4) as u16).to_be_bytes());
+        report.extend(chunk);
+        Some(report)
+    }
+}
+
+/// The SSRCs of the media a receiver asked a keyframe of, with a picture
+/// loss indication or a full intra request, in a compound RTCP packet.
+pub(crate) fn keyframe_requests(packet: &[u8]) -> Vec<u32> {
+    let mut requests = Vec::new();
+    let mut rest = packet;
+    while rest.len() >= 4 {
+        let format = rest[0] & 0x1f;
+        let kind = rest[1];
+        let length = (u16::from_be_bytes([rest[2], rest[3]]) as usize + 1) * 4;
+        let Some(body) = rest.get(..length) else {
+            break;
+        };
+        match (kind, format) {
+            // the media source follows the sender
+            (RTCP_PSFB, 1) if body.len() >= 12 => {
+                requests.push(u32::from_be_bytes(body[8..12].try_into().unwrap()))
+            }
+            // entries of the SSRC and a sequence number
+            (RTCP_PSFB, 4) => requests.extend(
+                body.get(12..)
+                    .unwrap_or_default()
+                    .chunks_exact(8)
+                    .map(|entry| u32::from_be_bytes(entry[..4].try_into().unwrap())),
+            ),
+            _ => {}
+        }
+        rest = &rest[length..];
+    }
+    requests
+}
+
+/// Whether a datagram is RTCP rather than RTP, by the payload types
+/// RTCP reserves, RFC 5761.
+pub(crate) fn is_rtcp(packet: &[u8]) -> bool {
+    packet.get(1).is_some_and(|kind| (192..=223).contains(kind))
+}
+
+/// The address peers reach a socket bound to `bound` at, that of the
+/// default route for a socket on all addresses.
+pub(crate) fn reachable_address(bound: SocketAddr) -> SocketAddr {
+    if !bound.ip().is_unspecified() {
+        return bound;
+    }
+    // connecting a UDP socket sends nothing, it only picks the route
+    let routed = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))
+        .and_then(|socket| {
+            socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9))?;
+            socket.local_addr()
+        })
+        .map(|address| address.ip())
+        .ok()
+        .filter(|ip| !ip.is_unspecified());
+    let ip = routed.unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST));
+    SocketAddr::new(ip, bound.port())
+}
+
+/// H.264 and HEVC NAL units, alone or in fragmentation units, RFC 6184
+/// and RFC 7798. Access unit delimiters are left out.
+fn nal_payloads(codec: Codec, data: &[u8]) -> Vec<Vec<u8>> {
+    let (aud, header) = match codec {
+        Codec::Hevc => (35, 2),
+        _ => (9, 1),
+    };
+    let mut payloads = Vec::new();
+    for nal in bitstream::nal_units(data) {
+        let nal_type = bitstream::nal_type(codec, nal);
+        if nal_type == aud || nal.len() <= header {
+            continue;
+        }
+        if nal.len() <= MAX_PAYLOAD {
+            payloads.push(nal.to_vec());
+            continue;
+        }
+        let unit_header = match codec {
+            Codec::Hevc => vec![nal[0] & 0x81 | 49 << 1, nal[1]],
+            _ => vec![nal[0] & 0xe0 | 28],
+        };
+        let chunks: Vec<&[u8]> = nal[header..].chunks(MAX_PAYLOAD - header - 1).collect();
+        let last = chunks.len() - 1;
+        for (index, chunk) in chunks.into_iter().enumerate() {
+            let mut payload = unit_header.clone();
+            let start = if index == 0 { 0x80 } else { 0 };
+            let end = if index == last { 0x40 } else { 0 };
+            payload.push(start | end | nal_type);
+            payload.extend_from_slice(chunk);
+            payloads.push(payload);
+        }
+    }
+    payloads
+}
+
+/// VP8 with the smallest payload descriptor, the start of the frame
+/// flagged, RFC 7741.
+fn vp8_payloads(data: &[u8]) -> Vec<Vec<u8>> {
+    data.chunks(MAX_PAYLOAD - 1)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut payload = vec![if index == 0 { 0x10 } else { 0 }];
+            payload.extend_from_slice(chunk);
+            payload
+        })
+        .collect()
+}
+
+/// VP9 in flexible mode, RFC 9628: the picture id, and for inter frames
+/// the reference to the frame before.
+fn vp9_payloads(data: &[u8], picture_id: u16, keyframe: bool) -> Vec<Vec<u8>> {
+    let mut descriptor = vec![0x90, 0x80 | (picture_id >> 8) as u8, picture_id as u8];
+    if !keyframe {
+        descriptor[0] |= 0x40;
+        descriptor.push(1 << 1);
+    }
+    let chunks: Vec<&[u8]> = data.chunks(MAX_PAYLOAD - descriptor.len()).collect();
+    let last = chunks.len().saturating_sub(1);
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, chunk)| {
+            let mut payload = descriptor.clone();
+            if index == 0 {
+                payload[0] |= 0x08;
+            }
+            if index == last {
+                payload[0] |= 0x04;
+            }
+            payload.extend_from_slice(chunk);
+            payload
+        })
+        .collect()
+}
+
+/// AV1 OBUs without their size fields, each with a length ahead, split
+/// over packets where they have to be; temporal delimiters are left out.
+fn av1_payloads(data: &[u8], keyframe: bool) -> Vec<Vec<u8>> {
+    let elements: Vec<Vec<u8>> = bitstream::obus(data)
+        .filter(|&(obu_type, ..)| obu_type != OBU_TEMPORAL_DELIMITER)
+        .map(|(_, obu, payload)| {
+            // the header and its extension, without obu_has_size_field
+            let extension = obu[0] & 0x04 != 0;
+            let mut element = vec![obu[0] & !0x02];
+            if extension && obu.len() > 1 {
+                element.push(obu[1]);
+            }
+            element.extend_from_slice(payload);
+            element
+        })
+        .collect();
+    let starts_sequence =
+        keyframe && bitstream::obus(data).any(|(obu_type, ..)| obu_type == OBU_SEQUENCE_HEADER);
+    let mut payloads: Vec<Vec<u8>> = Vec::new();
+    // aggregation header: Z continues an element, Y is continued, N starts
+    // a coded video sequence
+    let mut payload = vec![if starts_sequence { 0x08 } else { 0 }];
+    for element in &elements {
+        let mut rest = element.as_slice();
+        loop {
+            let room = MAX_PAYLOAD.saturating_sub(payload.len() + leb128(rest.len()).len());
+            if room == 0 || (room < rest.len() && room < 16) {
+                payloads.push(std::mem::replace(&mut payload, vec![0]));
+                continue;
+            }
+            let (part, next) = rest.split_at(rest.len().min(room));
+            payload.extend(leb128(part.len()));
+            payload.extend_from_slice(part);
+            rest = next;
+            if rest.is_empty() {
+                break;
+            }
+            payload[0] |= 0x40;
+            payloads.push(std::mem::replace(&mut payload, vec![0x80]));
+        }
+    }
+    if payload.len() > 1 {
+        payloads.push(payload);
+    }
+    payloads
+}
+
+fn leb128(mut value: usize) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// An AAC frame as the one access unit of an `mpeg4-generic` packet in
+/// the AAC-hbr mode, RFC 3640.
+fn aac_payload(data: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(4 + data.len());
+    // the length of the AU headers in bits, then the one: 13 bits of size
+    // and 3 of index
+    payload.extend(16u16.to_be_bytes());
+    payload.extend(((data.len() as u16) << 3).to_be_bytes());
+    payload.extend_from_slice(data);
+    payload
+}
@@ -0,0 +1,1095 @@
+use super::FrameSink;
+use super::codec::BlockingEncoder;
+use super::rtp::{self, Payload, RtpStream};
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::bitstream;
+use crate::encode::{self, Codec, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::runtime::{self, compat};
+use async_broadcast::{Sender, TrySendError};
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream, ToSocketAddrs, UdpSocket};
+use tokio::task::{AbortHandle, JoinHandle};
+
+const VIDEO_PAYLOAD_TYPE: u8 = 96;
+const AUDIO_PAYLOAD_TYPE: u8 = 97;
+
+/// Sessions over UDP nobody asked about or sent a receiver report for this
+/// long are gone, as the `timeout` of the `Session` header tells clients.
+const SESSION_TIMEOUT: Duration = Duration::from_secs(60);
+const TICK: Duration = Duration::from_secs(1);
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// Frames queued for a connection receiving over TCP, more are dropped.
+const QUEUE: usize = 64;
+/// Requests larger than this close the connection.
+const MAX_REQUEST: usize = 64 * 1024;
+/// How long the server waits after a failed accept, e.g. out of file
+/// descriptors, instead of failing again right away.
+const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+const METHODS: &str =
+    "OPTIONS, DESCRIBE, SETUP, PLAY, PAUSE, TEARDOWN, GET_PARAMETER, SET_PARAMETER";
+
+/// Serves the capture at an RTSP URL, e.g. for an NVR or VLC: any number
+/// of clients can watch, with RTP over UDP or interleaved in the RTSP
+/// connection. Frames are only encoded while a client plays, each one
+/// joining with a keyframe, and the packets are shared by all of them.
+/// With an encoder that cannot force keyframes, e.g. the ffmpeg one, late
+/// clients wait for the next of the keyframe interval.
+///
+/// There is one stream, at any path, and no authentication: bind to a
+/// loopback address to keep it to this host.
+pub struct RtspServer {
+    encoder: BlockingEncoder,
+    audio: Option<AudioTrack>,
+    shared: Arc<Mutex<Shared>>,
+    address: SocketAddr,
+    rtp: Arc<UdpSocket>,
+    rtcp: Arc<UdpSocket>,
+    tasks: Vec<JoinHandle<()>>,
+    // the first pts sent and the wall clock time then, for sender reports
+    origin: Option<(Duration, SystemTime)>,
+    last_report: Option<Instant>,
+}
+
+impl std::fmt::Debug for RtspServer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let shared = self.shared.lock().unwrap();
+        f.debug_struct("RtspServer")
+            .field("config", self.encoder.config())
+            .field("address", &self.address)
+            .field("sessions", &shared.sessions.len())
+            .finish()
+    }
+}
+
+/// State of the server, shared by the sink and the tasks of the
+/// connections and the RTCP socket.
+struct Shared {
+    // video, then audio
+    tracks: Vec<Track>,
+    sessions: HashMap<String, Session>,
+    connections: HashMap<u64, AbortHandle>,
+    next_connection: u64,
+    server_ports: (u16, u16),
+    cname: String,
+    keyframe: bool,
+}
+
+struct Track {
+    stream: RtpStream,
+    // the `a=fmtp`, for video of the parameter sets of the last keyframe
+    parameters: Option<String>,
+    channels: u32,
+}
+
+struct Session {
+    // the connection that set it up, and its queue
+    connection: u64,
+    sender: Sender<Vec<u8>>,
+    // of each track, those not set up `None`
+    deliveries: Vec<Option<Delivery>>,
+    playing: bool,
+    last_seen: Instant,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Delivery {
+    /// In the RTSP connection, RTP on the channel and RTCP on the next.
+    Interleaved(u8),
+    Udp {
+        rtp: SocketAddr,
+        rtcp: SocketAddr,
+    },
+}
+
+/// What goes out after the state is unlocked.
+#[derive(Default)]
+struct Outgoing {
+    queued: HashMap<u64, (Sender<Vec<u8>>, Vec<u8>)>,
+    // RTCP or not, where to and the packet
+    datagrams: Vec<(bool, SocketAddr, Vec<u8>)>,
+}
+
+impl RtspServer {
+    /// Listens on `address`, e.g. `"0.0.0.0:8554"`, encoding with
+    /// `encode::default_encoder`. RTP over UDP goes out of a port pair of
+    /// its own on the same address.
+    pub async fn bind(address: impl ToSocketAddrs, config: EncoderConfig) -> Result<Self> {
+        RtspServer::bind_with_encoder(address, encode::default_encoder(config)?).await
+    }
+
+    pub async fn bind_with_encoder(
+        address: impl ToSocketAddrs,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        compat(async {
+            let listener = TcpListener::bind(address).await?;
+            let address = listener.local_addr()?;
+            let (rtp, rtcp) = bind_pair(address.ip()).await?;
+            let server_ports = (rtp.local_addr()?.port(), rtcp.local_addr()?.port());
+            let video = Payload::Video(encoder.config().codec);
+            let shared = Shared {
+                tracks: vec![Track {
+                    stream: RtpStream::new(video, VIDEO_PAYLOAD_TYPE, 90_000),
+                    parameters: match encoder.config().codec {
+                        Codec::H264 => Some("packetization-mode=1".to_string()),
+                        _ => None,
+                    },
+                    channels: 0,
+                }],
+                sessions: HashMap::new(),
+                connections: HashMap::new(),
+                next_connection: 0,
+                server_ports,
+                cname: format!("xdp-screencast-{:016x}", fastrand::u64(..)),
+                keyframe: true,
+            };
+            let shared = Arc::new(Mutex::new(shared));
+            let rtp = Arc::new(rtp);
+            let rtcp = Arc::new(rtcp);
+            let tasks = vec![
+                runtime::handle().spawn(accept(listener, shared.clone())),
+                runtime::handle().spawn(receive_reports(rtcp.clone(), shared.clone())),
+            ];
+            Ok(RtspServer {
+                encoder: BlockingEncoder::new(encoder),
+                audio: None,
+                shared,
+                address,
+                rtp,
+                rtcp,
+                tasks,
+                origin: None,
+                last_report: None,
+            })
+        })
+        .await
+    }
+
+    /// Serves an audio track along, AAC or Opus, the audio up to each
+    /// frame.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Self {
+        let config = audio.config();
+        let payload = Payload::Audio(config.codec);
+        let clock_rate = payload.clock_rate(audio.output_rate());
+        let parameters = match config.codec {
+            AudioCodec::Aac => Some(format!(
+                "streamtype=5;profile-level-id=1;mode=AAC-hbr;sizelength=13;indexlength=3;\
+                 indexdeltalength=3;config={}",
+                hex(audio.codec_private())
+            )),
+            AudioCodec::Opus if config.channels > 1 => Some("sprop-stereo=1".to_string()),
+            AudioCodec::Opus => None,
+        };
+        let track = Track {
+            stream: RtpStream::new(payload, AUDIO_PAYLOAD_TYPE, clock_rate),
+            parameters,
+            channels: config.channels,
+        };
+        {
+            let mut shared = self.shared.lock().unwrap();
+            shared.tracks.truncate(1);
+            shared.tracks.push(track);
+        }
+        self.audio = Some(audio);
+        self
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    /// The URL clients open, with the address of the default route for a
+    /// server on all addresses.
+    pub fn url(&self) -> String {
+        format!("rtsp://{}/", rtp::reachable_address(self.address))
+    }
+
+    /// Clients that are playing.
+    pub fn viewers(&self) -> usize {
+        let shared = self.shared.lock().unwrap();
+        shared
+            .sessions
+            .values()
+            .filter(|session| session.playing)
+            .count()
+    }
+
+    async fn send_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        for packet in packets {
+            let audio = match &mut self.audio {
+                Some(audio) => audio.ready(packet.pts)?,
+                None => Vec::new(),
+            };
+            let (origin_pts, origin_wall) =
+                *self.origin.get_or_insert((packet.pts, SystemTime::now()));
+            let report = self
+                .last_report
+                .is_none_or(|last| last.elapsed() >= REPORT_INTERVAL);
+            let outgoing = {
+                let mut shared = self.shared.lock().unwrap();
+                let mut outgoing = Outgoing::default();
+                if shared.tracks.len() > 1 {
+                    for audio in audio {
+                        let packets =
+                            shared.tracks[1]
+                                .stream
+                                .packetize(&audio.data, audio.pts, true);
+                        shared.deliver(1, &packets, false, &mut outgoing);
+                    }
+                }
+                if packet.keyframe
+                    && let Some(parameters) = video_parameters(packet.codec, &packet.data)
+                {
+                    shared.tracks[0].parameters = Some(parameters);
+                }
+                let packets =
+                    shared.tracks[0]
+                        .stream
+                        .packetize(&packet.data, packet.pts, packet.keyframe);
+                shared.deliver(0, &packets, false, &mut outgoing);
+                if report {
+                    let wall = origin_wall + packet.pts.saturating_sub(origin_pts);
+                    for track in 0..shared.tracks.len() {
+                        let cname = &shared.cname;
+                        if let Some(report) = shared.tracks[track]
+                            .stream
+                            .sender_report(packet.pts, wall, cname)
+                        {
+                            shared.deliver(track, &[report], true, &mut outgoing);
+                        }
+                    }
+                }
+                outgoing
+            };
+            if report {
+                self.last_report = Some(Instant::now());
+            }
+            self.send(outgoing).await;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, outgoing: Outgoing) {
+        let mut dropped = false;
+        for (_, (sender, data)) in outgoing.queued {
+            if let Err(TrySendError::Full(_)) = sender.try_broadcast(data) {
+                dropped = true;
+            }
+        }
+        // the viewer behind picks up again at a keyframe
+        if dropped {
+            self.shared.lock().unwrap().keyframe = true;
+        }
+        for (control, to, datagram) in outgoing.datagrams {
+            let socket = if control { &self.rtcp } else { &self.rtp };
+            // a lost packet is for the receiver to cover
+            let _ = socket.send_to(&datagram, to).await;
+        }
+    }
+
+    fn stop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let mut shared = self.shared.lock().unwrap();
+        for (_, connection) in shared.connections.drain() {
+            connection.abort();
+        }
+        shared.sessions.clear();
+    }
+}
+
+impl Drop for RtspServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl FrameSink for RtspServer {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let (playing, keyframe) = {
+            let mut shared = self.shared.lock().unwrap();
+            let playing = shared.sessions.values().any(|session| session.playing);
+            (playing, playing && std::mem::take(&mut shared.keyframe))
+        };
+        if !playing {
+            // the audio of frames not sent is not sent either
+            if let Some(audio) = &mut self.audio {
+                audio.ready(Duration::MAX)?;
+            }
+            return Ok(());
+        }
+        if keyframe {
+            self.encoder.request_keyframe().await?;
+        }
+        let packets = self.encoder.encode(frame).await?;
+        compat(self.send_packets(packets)).await
+    }
+
+    /// Sends what the encoder held back and closes the connections.
+    async fn finish(&mut self) -> Result<()> {
+        let written = match self.encoder.finish().await {
+            Ok(packets) => compat(self.send_packets(packets)).await,
+            Err(err) => Err(err),
+        };
+        self.stop();
+        written
+    }
+}
+
+impl Shared {
+    /// Hands packets of a track to the sessions playing it.
+    fn deliver(&self, track: usize, packets: &[Vec<u8>], control: bool, out: &mut Outgoing) {
+        for session in self.sessions.values().filter(|session| session.playing) {
+            match session.deliveries.get(track).copied().flatten() {
+                Some(Delivery::Interleaved(channel)) => {
+                    let channel = channel + control as u8;
+                    let (_, data) = out
+                        .queued
+                        .entry(session.connection)
+                        .or_insert_with(|| (session.sender.clone(), Vec::new()));
+                    for packet in packets {
+                        data.extend([b'$', channel]);
+                        data.extend((packet.len() as u16).to_be_bytes());
+                        data.extend_from_slice(packet);
+                    }
+                }
+                Some(Delivery::Udp { rtp, rtcp }) => {
+                    let to = if control { rtcp } else { rtp };
+                    out.datagrams
+                        .extend(packets.iter().map(|packet| (control, to, packet.clone())));
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Asks for a keyframe when a receiver report requests one of the video.
+    fn receive_rtcp(&mut self, packet: &[u8]) {
+        if rtp::is_rtcp(packet)
+            && rtp::keyframe_requests(packet).contains(&self.tracks[0].stream.ssrc)
+        {
+            self.keyframe = true;
+        }
+    }
+
+    /// The response to a request of a connection.
+    fn handle(
+        &mut self,
+        request: &Request,
+        connection: u64,
+        sender: &Sender<Vec<u8>>,
+        peer: SocketAddr,
+        local: SocketAddr,
+    ) -> Vec<u8> {
+        let cseq = request.header("CSeq").unwrap_or("0").to_string();
+        let respond = |status: &str, headers: &[(&str, String)], body: &str| {
+            let mut response = format!(
+                "RTSP/1.0 {}\r\nCSeq: {}\r\nServer: xdp-screencast\r\n",
+                status, cseq
+            );
+            for (name, value) in headers {
+                response.push_str(&format!("{}: {}\r\n", name, value));
+            }
+            if !body.is_empty() {
+                response.push_str(&format!("Content-Length: {}\r\n", body.len()));
+            }
+            response.push_str("\r\n");
+            response.push_str(body);
+            response.into_bytes()
+        };
+        if let Some(id) = request.session() {
+            match self.sessions.get_mut(id) {
+                Some(session) => session.last_seen = Instant::now(),
+                None => return respond("454 Session Not Found", &[], ""),
+            }
+        }
+        let base = match request.url.ends_with('/') {
+            true => request.url.clone(),
+            false => format!("{}/", request.url),
+        };
+        match request.method.as_str() {
+            "OPTIONS" => respond("200 OK", &[("Public", METHODS.to_string())], ""),
+            "DESCRIBE" => respond(
+                "200 OK",
+                &[
+                    ("Content-Base", base),
+                    ("Content-Type", "application/sdp".to_string()),
+                ],
+                &self.description(local.ip()),
+            ),
+            "SETUP" => {
+                // the track from the control URL, the one of a stream of one
+                let track = match request
+                    .url
+                    .rsplit('/')
+                    .next()
+                    .and_then(|last| last.strip_prefix("trackID="))
+                {
+                    Some(track) => track.parse::<usize>().ok(),
+                    None if self.tracks.len() == 1 => Some(0),
+                    None => None,
+                };
+                let Some(track) = track.filter(|&track| track < self.tracks.len()) else {
+                    return respond("404 Not Found", &[], "");
+                };
+                let transport = request.header("Transport").unwrap_or_default();
+                let Some(delivery) = delivery(transport, track, peer) else {
+                    return respond("461 Unsupported Transport", &[], "");
+                };
+                let id = match request.session() {
+                    Some(id) => id.to_string(),
+                    None => format!("{:016x}", fastrand::u64(..)),
+                };
+                let tracks = self.tracks.len();
+                let session = self.sessions.entry(id.clone()).or_insert_with(|| Session {
+                    connection,
+                    sender: sender.clone(),
+                    deliveries: vec![None; tracks],
+                    playing: false,
+                    last_seen: Instant::now(),
+                });
+                session.connection = connection;
+                session.sender = sender.clone();
+                session.deliveries[track] = Some(delivery);
+                let ssrc = self.tracks[track].stream.ssrc;
+                let transport = match delivery {
+                    Delivery::Interleaved(channel) => format!(
+                        "RTP/AVP/TCP;unicast;interleaved={}-{};ssrc={:08X}",
+                        channel,
+                        channel + 1,
+                        ssrc
+                    ),
+                    Delivery::Udp { rtp, rtcp } => format!(
+                        "RTP/AVP;unicast;client_port={}-{};server_port={}-{};ssrc={:08X}",
+                        rtp.port(),
+                        rtcp.port(),
+                        self.server_ports.0,
+                        self.server_ports.1,
+                        ssrc
+                    ),
+                };
+                respond(
+                    "200 OK",
+                    &[
+                        ("Transport", transport),
+                        (
+                            "Session",
+                            format!("{};timeout={}", id, SESSION_TIMEOUT.as_secs()),
+                        ),
+                    ],
+                    "",
+                )
+            }
+            "PLAY" => {
+                let Some(id) = request.session() else {
+                    return respond("454 Session Not Found", &[], "");
+                };
+                let session = self.sessions.get_mut(id).unwrap();
+                session.playing = true;
+                let deliveries = session.deliveries.clone();
+                let id = id.to_string();
+                self.keyframe = true;
+                let base = base
+                    .split("trackID=")
+                    .next()
+                    .unwrap_or_default()
+                    .to_string();
+                let info: Vec<String> = deliveries
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, delivery)| delivery.is_some())
+                    .map(|(track, _)| {
+                        format!(
+                            "url={}trackID={};seq={}",
+                            base,
+                            track,
+                            self.tracks[track].stream.sequence()
+                        )
+                    })
+                    .collect();
+                respond(
+                    "200 OK",
+                    &[
+                        ("Session", id),
+                        ("Range", "npt=now-".to_string()),
+                        ("RTP-Info", info.join(",")),
+                    ],
+                    "",
+                )
+            }
+            "PAUSE" => {
+                let Some(id) = request.session() else {
+                    return respond("454 Session Not Found", &[], "");
+                };
+                self.sessions.get_mut(id).unwrap().playing = false;
+                respond("200 OK", &[("Session", id.to_string())], "")
+            }
+            "TEARDOWN" => {
+                if let Some(id) = request.session() {
+                    self.sessions.remove(id);
+                }
+                respond("200 OK", &[], "")
+            }
+            // keepalives
+            "GET_PARAMETER" | "SET_PARAMETER" => respond("200 OK", &[], ""),
+            _ => respond(
+                "501 Not Implemented",
+                &[("Public", METHODS.to_string())],
+                "",
+            ),
+        }
+    }
+
+    fn description(&self, local: IpAddr) -> String {
+        let family = if local.is_ipv4() { "IP4" } else { "IP6" };
+        let mut sdp = format!(
+            "v=0\r\no=- {} 1 IN {} {}\r\ns=xdp-screencast\r\nc=IN {} {}\r\nt=0 0\r\n\
+             a=control:*\r\na=range:npt=now-\r\n",
+            fastrand::u32(..),
+            family,
+            local,
+            family,
+            if local.is_ipv4() { "0.0.0.0" } else { "::" },
+        );
+        for (index, track) in self.tracks.iter().enumerate() {
+            let stream = &track.stream;
+            let payload_type = stream.payload_type;
+            let (kind, channels) = match stream.payload() {
+                Payload::Video(_) => ("video", String::new()),
+                Payload::Audio(AudioCodec::Opus) => ("audio", "/2".to_string()),
+                Payload::Audio(AudioCodec::Aac) => ("audio", format!("/{}", track.channels)),
+            };
+            sdp.push_str(&format!(
+                "m={} 0 RTP/AVP {}\r\na=rtpmap:{} {}/{}{}\r\n",
+                kind,
+                payload_type,
+                payload_type,
+                stream.payload().encoding_name(),
+                stream.clock_rate,
+                channels
+            ));
+            if let Some(parameters) = &track.parameters {
+                sdp.push_str(&format!("a=fmtp:{} {}\r\n", payload_type, parameters));
+            }
+            sdp.push_str(&format!("a=control:trackID={}\r\n", index));
+        }
+        sdp
+    }
+}
+
+/// The RTP and RTCP sockets, on an even port and the one after when one
+/// is free, as clients expect.
+async fn bind_pair(ip: IpAddr) -> Result<(UdpSocket, UdpSocket)> {
+    for _ in 0..16 {
+        let rtp = UdpSocket::bind((ip, 0)).await?;
+        let port = rtp.local_addr()?.port();
+        if port % 2 == 0
+            && port < u16::MAX
+            && let Ok(rtcp) = UdpSocket::bind((ip, port + 1)).await
+        {
+            return Ok((rtp, rtcp));
+        }
+    }
+    Ok((
+        UdpSocket::bind((ip, 0)).await?,
+        UdpSocket::bind((ip, 0)).await?,
+    ))
+}
+
+async fn accept(listener: TcpListener, shared: Arc<Mutex<Shared>>) {
+    loop {
+        let Ok((tcp, peer)) = listener.accept().await else {
+            tokio::time::sleep(ACCEPT_BACKOFF).await;
+            continue;
+        };
+        let _ = tcp.set_nodelay(true);
+        let mut state = shared.lock().unwrap();
+        let id = state.next_connection;
+        state.next_connection += 1;
+        let task = runtime::handle().spawn(serve(tcp, peer, id, shared.clone()));
+        state.connections.insert(id, task.abort_handle());
+    }
+}
+
+/// Takes the reports of clients receiving over UDP, which keep their
+/// sessions alive, and ends the sessions of those gone.
+async fn receive_reports(socket: Arc<UdpSocket>, shared: Arc<Mutex<Shared>>) {
+    let mut buf = vec![0u8; 2048];
+    loop {
+        let received = tokio::time::timeout(TICK, socket.recv_from(&mut buf)).await;
+        let mut shared = shared.lock().unwrap();
+        if let Ok(Ok((length, from))) = received {
+            shared.receive_rtcp(&buf[..length]);
+            for session in shared.sessions.values_mut() {
+                let known = session
+                    .deliveries
+                    .iter()
+                    .any(|delivery| matches!(delivery, Some(Delivery::Udp { rtcp, .. }) if *rtcp == from));
+                if known {
+                    session.last_seen = Instant::now();
+                }
+            }
+        }
+        // those interleaved end with their connection
+        shared.sessions.retain(|_, session| {
+            session.last_seen.elapsed() < SESSION_TIMEOUT
+                || session
+                    .deliveries
+                    .iter()
+                    .any(|delivery| matches!(delivery, Some(Delivery::Interleaved(_))))
+        });
+    }
+}
+
+/// Ends the sessions of a connection received over it, and its writer,
+/// also when the connection task is aborted.
+struct Connection {
+    id: u64,
+    shared: Arc<Mutex<Shared>>,
+    writer: JoinHandle<()>,
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        self.writer.abort();
+        let mut shared = self.shared.lock().unwrap();
+        shared.connections.remove(&self.id);
+        let id = self.id;
+        shared.sessions.retain(|_, session| {
+            session.connection != id
+                || !session
+                    .deliveries
+                    .iter()
+                    .any(|delivery| matches!(delivery, Some(Delivery::Interleaved(_))))
+        });
+    }
+}
+
+/// Answers the requests of a connection, and writes them and the media
+/// interleaved for it from its queue.
+async fn serve(tcp: TcpStream, peer: SocketAddr, id: u64, shared: Arc<Mutex<Shared>>) {
+    let Ok(local) = tcp.local_addr() else {
+        return;
+    };
+    let (mut reader, writer) = tcp.into_split();
+    let (sender, receiver) = async_broadcast::broadcast(QUEUE);
+    let _connection = Connection {
+        id,
+        shared: shared.clone(),
+        writer: runtime::handle().spawn(write_queue(writer, receiver)),
+    };
+    let mut buffer = Vec::new();
+    let mut received = vec![0u8; 16 * 1024];
+    loop {
+        while let Some((length, item)) = parse(&buffer) {
+            buffer.drain(..length);
+            match item {
+                Item::Request(request) => {
+                    let response = {
+                        let mut shared = shared.lock().unwrap();
+                        shared.handle(&request, id, &sender, peer, local)
+                    };
+                    if sender.broadcast_direct(response).await.is_err() {
+                        return;
+                    }
+                }
+                Item::Interleaved(data) => shared.lock().unwrap().receive_rtcp(&data),
+            }
+        }
+        if buffer.len() > MAX_REQUEST {
+            return;
+        }
+        match reader.read(&mut received).await {
+            Ok(0) | Err(_) => return,
+            Ok(length) => buffer.extend_from_slice(&received[..length]),
+        }
+    }
+}
+
+async fn write_queue(mut writer: OwnedWriteHalf, mut receiver: async_broadcast::Receiver<Vec<u8>>) {
+    while let Ok(data) = receiver.recv_direct().await {
+        if writer.write_all(&data).await.is_err() {
+            break;
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    url: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The id of the `Session` header, without its parameters.
+    fn session(&self) -> Option<&str> {
+        self.header("Session")
+            .map(|session| session.split(';').next().unwrap_or_default().trim())
+    }
+}
+
+enum Item {
+    Request(Request),
+    /// RTCP of the client, on an odd channel.
+    Interleaved(Vec<u8>),
+}
+
+/// Reads a request or an interleaved packet when all of it is there.
+fn parse(data: &[u8]) -> Option<(usize, Item)> {
+    if data.first() == Some(&b'$') {
+        let length = u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize;
+        let packet = data.get(4..4 + length)?;
+        return Some((4 + length, Item::Interleaved(packet.to_vec())));
+    }
+    let end = data.windows(4).position(|window| window == b"\r\n\r\n")?;
+    let head = String::from_utf8_lossy(&data[..end]);
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split_whitespace();
+    let method = request_line.next().unwrap_or_default().to_string();
+    let url = request_line.next().unwrap_or_default().to_string();
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let body = headers
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case("Content-Length"))
+        .and_then(|(_, value)| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let length = end + 4 + body;
+    if data.len() < length {
+        return None;
+    }
+    Some((
+        length,
+        Item::Request(Request {
+            method,
+            url,
+            headers,
+        }),
+    ))
+}
+
+/// How a client asks a track to be sent: interleaved, on the channels it
+/// picked or after those of the tracks before, or to the ports of its
+/// address. Multicast and other destinations are not served.
+fn delivery(transport: &str, track: usize, peer: SocketAddr) -> Option<Delivery> {
+    // the first of the transports offered that is served
+    for spec in transport.split(',') {
+        let mut parameters = spec.split(';').map(str::trim);
+        let protocol = parameters.next().unwrap_or_default().to_ascii_uppercase();
+        let parameters: Vec<&str> = parameters.collect();
+        if parameters.contains(&"multicast") {
+            continue;
+        }
+        let range = |name: &str| {
+            parameters.iter().find_map(|parameter| {
+                let (key, value) = parameter.split_once('=')?;
+                if !key.eq_ignore_ascii_case(name) {
+                    return None;
+                }
+                let mut ports = value.split('-').map(|port| port.trim().parse::<u16>());
+                let first = ports.next()?.ok()?;
+                let second = match ports.next() {
+                    Some(port) => port.ok()?,
+                    None => first.checked_add(1)?,
+                };
+                Some((first, second))
+            })
+        };
+        match protocol.as_str() {
+            "RTP/AVP/TCP" => {
+                let channel = match range("interleaved") {
+                    Some((channel, _)) => u8::try_from(channel)
+                        .ok()
+                        .filter(|&channel| channel < 255)?,
+                    None => (track * 2) as u8,
+                };
+                return Some(Delivery::Interleaved(channel));
+            }
+            "RTP/AVP" | "RTP/AVP/UDP" => {
+                let Some((rtp, rtcp)) = range("client_port") else {
+                    continue;
+                };
+                return Some(Delivery::Udp {
+                    rtp: SocketAddr::new(peer.ip(), rtp),
+                    rtcp: SocketAddr::new(peer.ip(), rtcp),
+                });
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/// The `a=fmtp` of H.264 and HEVC with the parameter sets of a keyframe,
+/// RFC 6184 and RFC 7798, so clients can set up a decoder before the
+/// first one arrives.
+fn video_parameters(codec: Codec, keyframe: &[u8]) -> Option<String> {
+    let units = |nal_type: u8| -> Vec<String> {
+        bitstream::nal_units(keyframe)
+            .filter(|nal| bitstream::nal_type(codec, nal) == nal_type)
+            .map(base64)
+            .collect()
+    };
+    match codec {
+        Codec::H264 => {
+            let sps =
+                bitstream::nal_units(keyframe).find(|nal| bitstream::nal_type(codec, nal) == 7)?;
+            let mut sets = units(7);
+            sets.extend(units(8));
+            Some(format!(
+                "packetization-mode=1;profile-level-id={};sprop-parameter-sets={}",
+                hex(sps.get(1..4)?),
+                sets.join(",")
+            ))
+        }
+        Codec::Hevc => {
+            let (vps, sps, pps) = (units(32), units(33), units(34));
+            if sps.is_empty() {
+                return None;
+            }
+            Some(format!(
+                "sprop-vps={};sprop-sps={};sprop-pps={}",
+                vps.join(","),
+                sps.join(","),
+                pps.join(",")
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Standard base64 with padding, of the parameter sets.
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::codec::Encoded;
+
+    fn request(data: &[u8]) -> (usize, Request) {
+        match parse(data) {
+            Some((length, Item::Request(request))) => (length, request),
+            _ => panic!("no request in {:?}", String::from_utf8_lossy(data)),
+        }
+    }
+
+    #[test]
+    fn requests() {
+        let data = b"SETUP rtsp://host/stream/trackID=0 RTSP/1.0\r\nCSeq: 3\r\n\
+            transport: RTP/AVP;unicast;client_port=5000-5001\r\n\
+            Session: 0123abcd;timeout=60\r\n\r\nOPTIONS";
+        let (length, setup) = request(data);
+        assert_eq!(length, data.len() - 7);
+        assert_eq!(setup.method, "SETUP");
+        assert_eq!(setup.url, "rtsp://host/stream/trackID=0");
+        assert_eq!(setup.header("cseq"), Some("3"));
+        assert_eq!(
+            setup.header("Transport"),
+            Some("RTP/AVP;unicast;client_port=5000-5001")
+        );
+        assert_eq!(setup.session(), Some("0123abcd"));
+        assert!(parse(&data[length..]).is_none());
+
+        // the body is waited for and skipped
+        let data = b"SET_PARAMETER * RTSP/1.0\r\nContent-Length: 4\r\n\r\nab";
+        assert!(parse(data).is_none());
+        let data = [&data[..], b"cd"].concat();
+        assert_eq!(request(&data).0, data.len());
+
+        let data = [b'$', 1, 0, 3, 0x80, 0xc9, 0, 7];
+        match parse(&data) {
+            Some((7, Item::Interleaved(packet))) => assert_eq!(packet, [0x80, 0xc9, 0]),
+            _ => panic!("no interleaved packet"),
+        }
+        assert!(parse(&data[..6]).is_none());
+    }
+
+    #[test]
+    fn transports() {
+        let peer: SocketAddr = "192.0.2.7:40000".parse().unwrap();
+        let udp = |rtp, rtcp| {
+            Some(Delivery::Udp {
+                rtp: SocketAddr::new(peer.ip(), rtp),
+                rtcp: SocketAddr::new(peer.ip(), rtcp),
+            })
+        };
+        assert_eq!(
+            delivery("RTP/AVP;unicast;client_port=5000-5001", 0, peer),
+            udp(5000, 5001)
+        );
+        assert_eq!(
+            delivery("RTP/AVP/UDP;unicast;client_port=6000", 0, peer),
+            udp(6000, 6001)
+        );
+        assert_eq!(
+            delivery("rtp/avp/tcp;unicast;interleaved=4-5", 0, peer),
+            Some(Delivery::Interleaved(4))
+        );
+        assert_eq!(
+            delivery("RTP/AVP/TCP;unicast", 1, peer),
+            Some(Delivery::Interleaved(2))
+        );
+        // the first one served of those offered
+        assert_eq!(
+            delivery(
+                "RTP/AVP;multicast;port=7000-7001,RTP/AVP;unicast;client_port=8000-8001",
+                0,
+                peer
+            ),
+            udp(8000, 8001)
+        );
+        assert_eq!(delivery("RTP/AVP;unicast", 0, peer), None);
+        assert_eq!(delivery("RTP/AVP/TCP;interleaved=255", 0, peer), None);
+        assert_eq!(delivery("RTP/SAVP;unicast;client_port=5000", 0, peer), None);
+    }
+
+    #[test]
+    fn parameter_sets() {
+        let sps = [0x67, 0x42, 0xc0, 0x1e, 0x8c, 0x8d, 0x40];
+        let pps = [0x68, 0xce, 0x3c, 0x80];
+        let keyframe = [
+            &[0, 0, 0, 1][..],
+            &sps,
+            &[0, 0, 0, 1],
+            &pps,
+            &[0, 0, 1, 0x65, 0x88],
+        ]
+        .concat();
+        assert_eq!(
+            video_parameters(Codec::H264, &keyframe).unwrap(),
+            "packetization-mode=1;profile-level-id=42c01e;\
+             sprop-parameter-sets=Z0LAHoyNQA==,aM48gA=="
+        );
+        assert_eq!(video_parameters(Codec::H264, &[0, 0, 1, 0x65, 0x88]), None);
+        assert_eq!(video_parameters(Codec::Vp8, &keyframe), None);
+        assert_eq!(base64(b"xdp-s"), "eGRwLXM=");
+        assert_eq!(base64(b""), "");
+    }
+
+    /// Sends a request and reads its response, with the body.
+    async fn exchange(tcp: &mut TcpStream, request: &str) -> String {
+        tcp.write_all(request.as_bytes()).await.unwrap();
+        let mut response = Vec::new();
+        loop {
+            let mut buffer = [0; 4096];
+            let read = tcp.read(&mut buffer).await.unwrap();
+            assert!(
+                read > 0,
+                "closed after {:?}",
+                String::from_utf8_lossy(&response)
+            );
+            response.extend_from_slice(&buffer[..read]);
+            if parse(&response).is_some() {
+                return String::from_utf8(response).unwrap();
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn session() {
+        let encoder = Box::new(Encoded(EncoderConfig::new(Codec::H264)));
+        let server = RtspServer::bind_with_encoder("127.0.0.1:0", encoder)
+            .await
+            .unwrap();
+        let url = server.url();
+        let mut tcp = TcpStream::connect(server.local_addr()).await.unwrap();
+
+        let options = exchange(
+            &mut tcp,
+            &format!("OPTIONS {url} RTSP/1.0\r\nCSeq: 1\r\n\r\n"),
+        )
+        .await;
+        assert!(
+            options.starts_with("RTSP/1.0 200 OK\r\nCSeq: 1\r\n"),
+            "{options}"
+        );
+        assert!(options.contains(&format!("Public: {METHODS}\r\n")));
+
+        let describe = exchange(
+            &mut tcp,
+            &format!("DESCRIBE {url} RTSP/1.0\r\nCSeq: 2\r\n\r\n"),
+        )
+        .await;
+        assert!(
+            describe.contains("Content-Type: application/sdp\r\n"),
+            "{describe}"
+        );
+        assert!(describe.contains(&format!("Content-Base: {url}\r\n")));
+        let (_, sdp) = describe.split_once("\r\n\r\n").unwrap();
+        assert!(sdp.starts_with("v=0\r\n"));
+        assert!(sdp.contains("c=IN IP4 0.0.0.0\r\n"));
+        assert!(sdp.ends_with(
+            "m=video 0 RTP/AVP 96\r\na=rtpmap:96 H264/90000\r\n\
+             a=fmtp:96 packetization-mode=1\r\na=control:trackID=0\r\n"
+        ));
+
+        let setup = exchange(
+            &mut tcp,
+            &format!(
+                "SETUP {url}trackID=0 RTSP/1.0\r\nCSeq: 3\r\n\
+                 Transport: RTP/AVP/TCP;unicast;interleaved=0-1\r\n\r\n"
+            ),
+        )
+        .await;
+        assert!(setup.starts_with("RTSP/1.0 200 OK\r\n"), "{setup}");
+        assert!(setup.contains("Transport: RTP/AVP/TCP;unicast;interleaved=0-1;ssrc="));
+        assert!(setup.contains("Session: "));
+
+        let unknown = exchange(
+            &mut tcp,
+            &format!("PLAY {url} RTSP/1.0\r\nCSeq: 4\r\nSession: nope\r\n\r\n"),
+        )
+        .await;
+        assert!(unknown.starts_with("RTSP/1.0 454 Session Not Found\r\nCSeq: 4\r\n"));
+        let missing = exchange(
+            &mut tcp,
+            &format!("SETUP {url}trackID=3 RTSP/1.0\r\nCSeq: 5\r\n\r\n"),
+        )
+        .await;
+        assert!(
+            missing.starts_with("RTSP/1.0 404 Not Found\r\n"),
+            "{missing}"
+        );
+    }
+}
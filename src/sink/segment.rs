@@ -0,0 +1,276 @@
+use super::FrameSink;
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::runtime::compat;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// A file of a segmented recording, handed to the callback once complete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub path: PathBuf,
+    /// Counts from 0.
+    pub index: u32,
+    /// From its first frame to its last.
+    pub duration: Duration,
+    pub bytes: u64,
+}
+
+type SegmentCallback = dyn FnMut(&Segment) + Send;
+
+/// Splits a long recording into files of limited duration or size, each
+/// written by a sink of its own:
+/// `Segmented::new("capture-{index}.mkv", move |path| MkvFile::create(path, config.clone()))`.
+///
+/// The paths come from the template, with `{index}` replaced by the number
+/// of the segment in three digits or more and `{start}` by the time it
+/// started in seconds since the epoch. A new segment starts with the first
+/// frame past `segment_duration` or once the file reached `segment_bytes`,
+/// as far as written by the sink. Every segment begins with a fresh
+/// encoder, so a keyframe, and plays on its own. Without either limit
+/// everything goes into one file.
+pub struct Segmented<S, F> {
+    template: String,
+    open: F,
+    segment_duration: Option<Duration>,
+    segment_bytes: Option<u64>,
+    callback: Option<Box<SegmentCallback>>,
+    current: Option<Current<S>>,
+    index: u32,
+    // for frames without pts
+    clock: Option<Instant>,
+}
+
+struct Current<S> {
+    sink: S,
+    segment: Segment,
+    start: Duration,
+}
+
+impl<S, F> std::fmt::Debug for Segmented<S, F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Segmented")
+            .field("template", &self.template)
+            .field("segment_duration", &self.segment_duration)
+            .field("segment_bytes", &self.segment_bytes)
+            .field(
+                "current",
+                &self.current.as_ref().map(|current| &current.segment),
+            )
+            .finish()
+    }
+}
+
+impl<S, F, Fut> Segmented<S, F>
+where
+    S: FrameSink,
+    F: FnMut(PathBuf) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<S>> + Send,
+{
+    /// `open` creates the sink of each segment from its path.
+    pub fn new(template: impl Into<String>, open: F) -> Self {
+        Segmented {
+            template: template.into(),
+            open,
+            segment_duration: None,
+            segment_bytes: None,
+            callback: None,
+            current: None,
+            index: 0,
+            clock: None,
+        }
+    }
+
+    pub fn with_segment_duration(mut self, duration: Duration) -> Self {
+        self.segment_duration = Some(duration.max(Duration::from_secs(1)));
+        self
+    }
+
+    pub fn with_segment_bytes(mut self, bytes: u64) -> Self {
+        self.segment_bytes = Some(bytes.max(1));
+        self
+    }
+
+    /// Called with each segment once its sink finished, the last one in
+    /// `finish`.
+    pub fn with_segment_callback(
+        mut self,
+        callback: impl FnMut(&Segment) + Send + 'static,
+    ) -> Self {
+        self.callback = Some(Box::new(callback));
+        self
+    }
+
+    fn path(&self) -> PathBuf {
+        let start = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.template
+            .replace("{index}", &format!("{:03}", self.index))
+            .replace("{start}", &start.to_string())
+            .into()
+    }
+
+    fn is_full(&self, time: Duration) -> bool {
+        let Some(current) = &self.current else {
+            return false;
+        };
+        self.segment_duration
+            .is_some_and(|limit| time.saturating_sub(current.start) >= limit)
+            || self
+                .segment_bytes
+                .is_some_and(|limit| current.segment.bytes >= limit)
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        let Some(mut current) = self.current.take() else {
+            return Ok(());
+        };
+        current.sink.finish().await?;
+        if let Ok(metadata) = compat(tokio::fs::metadata(&current.segment.path)).await {
+            current.segment.bytes = metadata.len();
+        }
+        if let Some(callback) = &mut self.callback {
+            callback(&current.segment);
+        }
+        Ok(())
+    }
+}
+
+impl<S, F, Fut> FrameSink for Segmented<S, F>
+where
+    S: FrameSink,
+    F: FnMut(PathBuf) -> Fut + Send + 'static,
+    Fut: Future<Output = Result<S>> + Send,
+{
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let time = match frame.pts() {
+            Some(pts) => pts,
+            None => self.clock.get_or_insert_with(Instant::now).elapsed(),
+        };
+        if self.is_full(time) {
+            self.close().await?;
+        }
+        let current = match &mut self.current {
+            Some(current) => current,
+            None => {
+                let path = self.path();
+                let sink = (self.open)(path.clone()).await?;
+                let segment = Segment {
+                    path,
+                    index: self.index,
+                    duration: Duration::ZERO,
+                    bytes: 0,
+                };
+                self.index += 1;
+                self.current.insert(Current {
+                    sink,
+                    segment,
+                    start: time,
+                })
+            }
+        };
+        current.segment.duration = time.saturating_sub(current.start);
+        current.sink.write_frame(frame).await?;
+        if self.segment_bytes.is_some()
+            && let Ok(metadata) = compat(tokio::fs::metadata(&current.segment.path)).await
+        {
+            current.segment.bytes = metadata.len();
+        }
+        Ok(())
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        self.close().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::PixelFormat;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    /// Writes the bytes of each frame straight to the file.
+    #[derive(Debug)]
+    struct Raw(std::fs::File);
+
+    impl FrameSink for Raw {
+        async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+            self.0.write_all(frame.data())?;
+            Ok(())
+        }
+
+        async fn finish(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn record(
+        name: &str,
+        (duration, bytes): (Option<Duration>, Option<u64>),
+        frames: &[Option<u64>],
+    ) -> Vec<Segment> {
+        let template = std::env::temp_dir().join(format!(
+            "xdp-screencast-{}-{name}-{{index}}.raw",
+            std::process::id()
+        ));
+        let segments = Arc::new(Mutex::new(Vec::new()));
+        let done = segments.clone();
+        let mut sink = Segmented::new(template.to_string_lossy(), |path: PathBuf| async move {
+            Ok(Raw(std::fs::File::create(path)?))
+        })
+        .with_segment_callback(move |segment| done.lock().unwrap().push(segment.clone()));
+        if let Some(duration) = duration {
+            sink = sink.with_segment_duration(duration);
+        }
+        if let Some(bytes) = bytes {
+            sink = sink.with_segment_bytes(bytes);
+        }
+        for millis in frames {
+            let frame = Frame::packed(PixelFormat::Bgrx, 1, 1, 4, vec![0; 4]);
+            let frame = match millis {
+                Some(millis) => frame.with_pts(Duration::from_millis(*millis)),
+                None => frame,
+            };
+            sink.write_frame(frame).await.unwrap();
+        }
+        sink.finish().await.unwrap();
+        let segments = segments.lock().unwrap().clone();
+        for segment in &segments {
+            std::fs::remove_file(&segment.path).unwrap();
+        }
+        segments
+    }
+
+    #[tokio::test]
+    async fn rolls_over_by_duration() {
+        let frames = [0, 500, 1000, 1600, 2100].map(Some);
+        let limits = (Some(Duration::from_secs(1)), None);
+        let segments = record("duration", limits, &frames).await;
+        let summary: Vec<_> = segments
+            .iter()
+            .map(|segment| (segment.index, segment.duration.as_millis(), segment.bytes))
+            .collect();
+        assert_eq!(summary, [(0, 500, 8), (1, 600, 8), (2, 0, 4)]);
+        let name = segments[1].path.file_name().unwrap().to_string_lossy();
+        assert!(name.ends_with("-duration-001.raw"), "{name}");
+    }
+
+    #[tokio::test]
+    async fn rolls_over_by_size() {
+        let segments = record("size", (None, Some(10)), &[None; 7]).await;
+        let bytes: Vec<_> = segments.iter().map(|segment| segment.bytes).collect();
+        assert_eq!(bytes, [12, 12, 4]);
+    }
+
+    #[tokio::test]
+    async fn one_file_without_limits() {
+        let segments = record("single", (None, None), &[Some(0), Some(5000)]).await;
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].duration, Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,393 @@
+use super::FrameSink;
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::runtime::compat;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::AsRawFd;
+use std::os::raw::{c_int, c_ulong, c_void};
+use std::path::{Path, PathBuf};
+
+const V4L2_BUF_TYPE_VIDEO_OUTPUT: u32 = 2;
+const V4L2_CAP_VIDEO_OUTPUT: u32 = 0x0000_0002;
+const V4L2_CAP_DEVICE_CAPS: u32 = 0x8000_0000;
+const V4L2_FIELD_NONE: u32 = 1;
+const V4L2_COLORSPACE_SMPTE170M: u32 = 1;
+const V4L2_COLORSPACE_SRGB: u32 = 8;
+const V4L2_YCBCR_ENC_601: u32 = 1;
+const V4L2_QUANTIZATION_LIM_RANGE: u32 = 2;
+
+const VIDIOC_QUERYCAP: c_ulong = ioc(2, 0, size_of::<Capability>());
+const VIDIOC_S_FMT: c_ulong = ioc(3, 5, size_of::<Format>());
+const VIDIOC_S_PARM: c_ulong = ioc(3, 22, size_of::<StreamParm>());
+
+unsafe extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// `_IOC` of the kernel for the `'V'` ioctls, `direction` 2 for read and 3
+/// for read and write.
+const fn ioc(direction: c_ulong, number: c_ulong, size: usize) -> c_ulong {
+    direction << 30 | (size as c_ulong) << 16 | (b'V' as c_ulong) << 8 | number
+}
+
+#[repr(C)]
+struct Capability {
+    driver: [u8; 16],
+    card: [u8; 32],
+    bus_info: [u8; 32],
+    version: u32,
+    capabilities: u32,
+    device_caps: u32,
+    reserved: [u32; 3],
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PixFormat {
+    width: u32,
+    height: u32,
+    pixelformat: u32,
+    field: u32,
+    bytesperline: u32,
+    sizeimage: u32,
+    colorspace: u32,
+    private: u32,
+    flags: u32,
+    ycbcr_enc: u32,
+    quantization: u32,
+    xfer_func: u32,
+}
+
+#[repr(C)]
+struct Format {
+    kind: u32,
+    format: FormatUnion,
+}
+
+#[repr(C)]
+union FormatUnion {
+    pix: PixFormat,
+    raw: [u8; 200],
+    // other members hold pointers, which align the union
+    _align: *mut c_void,
+}
+
+#[repr(C)]
+struct StreamParm {
+    kind: u32,
+    output: OutputParm,
+    reserved: [u8; 200 - size_of::<OutputParm>()],
+}
+
+#[repr(C)]
+struct OutputParm {
+    capability: u32,
+    outputmode: u32,
+    // seconds per frame
+    numerator: u32,
+    denominator: u32,
+    extendedmode: u32,
+    writebuffers: u32,
+    reserved: [u32; 4],
+}
+
+/// Presents the capture as a webcam through a v4l2loopback device, e.g. for
+/// video calls in applications that cannot share a screen, or to share it
+/// with the filters of this crate applied.
+///
+/// The device takes the size of the first frame, a frame of another size
+/// fails. Frames are converted to YUY2, which every application reading
+/// webcams takes, unless `with_format`, and a trailing odd row or column of
+/// YUV formats is cut. YUV is BT.601 limited range, see `convert::convert`.
+/// The device has to be created first, e.g. with
+/// `modprobe v4l2loopback exclusive_caps=1`, which Chrome needs to list it.
+pub struct V4l2Loopback {
+    file: File,
+    path: PathBuf,
+    format: PixelFormat,
+    framerate: (u32, u32),
+    size: Option<(u32, u32)>,
+}
+
+impl std::fmt::Debug for V4l2Loopback {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("V4l2Loopback")
+            .field("path", &self.path)
+            .field("format", &self.format)
+            .field("framerate", &self.framerate)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl V4l2Loopback {
+    /// Opens a device, e.g. `/dev/video10`, failing for one that does not
+    /// take output.
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let file = compat(tokio::fs::OpenOptions::new().write(true).open(&path))
+            .await?
+            .into_std()
+            .await;
+        let mut capability: Capability = unsafe { std::mem::zeroed() };
+        if unsafe { ioctl(file.as_raw_fd(), VIDIOC_QUERYCAP, &mut capability) } < 0 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{} as a video device ({})",
+                path.display(),
+                io::Error::last_os_error()
+            )));
+        }
+        let capabilities = match capability.capabilities & V4L2_CAP_DEVICE_CAPS {
+            0 => capability.capabilities,
+            _ => capability.device_caps,
+        };
+        if capabilities & V4L2_CAP_VIDEO_OUTPUT == 0 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{} as a video output, it takes none",
+                path.display()
+            )));
+        }
+        Ok(V4l2Loopback {
+            file,
+            path,
+            format: PixelFormat::Yuy2,
+            framerate: (30, 1),
+            size: None,
+        })
+    }
+
+    /// Opens the first v4l2loopback device that takes output.
+    pub async fn find() -> Result<Self> {
+        let mut devices = Vec::new();
+        if let Ok(mut entries) =
+            compat(tokio::fs::read_dir("/sys/devices/virtual/video4linux")).await
+        {
+            while let Ok(Some(entry)) = compat(entries.next_entry()).await {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if let Some(number) = name.strip_prefix("video")
+                    && let Ok(number) = number.parse::<u32>()
+                {
+                    devices.push(number);
+                }
+            }
+        }
+        devices.sort_unstable();
+        for number in devices {
+            if let Ok(device) = V4l2Loopback::open(format!("/dev/video{}", number)).await {
+                return Ok(device);
+            }
+        }
+        Err(ScreencastError::Unsupported(
+            "virtual cameras without a v4l2loopback device".to_string(),
+        ))
+    }
+
+    /// The pixel format applications read: YUY2, I420, NV12, or BGR and
+    /// RGB with or without a fourth byte after them.
+    pub fn with_format(mut self, format: PixelFormat) -> Result<Self> {
+        if fourcc(format).is_none() {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} on a v4l2loopback device",
+                format
+            )));
+        }
+        self.format = format;
+        Ok(self)
+    }
+
+    /// The frame rate applications are told, as numerator and denominator.
+    pub fn with_framerate(mut self, framerate: (u32, u32)) -> Self {
+        self.framerate = (framerate.0.max(1), framerate.1.max(1));
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Sets the format of the device for frames of `width` by `height`.
+    fn configure(&mut self, width: u32, height: u32) -> Result<()> {
+        let yuv = matches!(
+            self.format,
+            PixelFormat::Yuy2 | PixelFormat::I420 | PixelFormat::Nv12
+        );
+        let pix = PixFormat {
+            width,
+            height,
+            pixelformat: fourcc(self.format).unwrap_or_default(),
+            field: V4L2_FIELD_NONE,
+            bytesperline: planes(self.format, width, height)[0].0 as u32,
+            sizeimage: image_size(self.format, width, height) as u32,
+            colorspace: match yuv {
+                true => V4L2_COLORSPACE_SMPTE170M,
+                false => V4L2_COLORSPACE_SRGB,
+            },
+            private: 0,
+            flags: 0,
+            ycbcr_enc: match yuv {
+                true => V4L2_YCBCR_ENC_601,
+                false => 0,
+            },
+            quantization: match yuv {
+                true => V4L2_QUANTIZATION_LIM_RANGE,
+                false => 0,
+            },
+            xfer_func: 0,
+        };
+        let mut format = Format {
+            kind: V4L2_BUF_TYPE_VIDEO_OUTPUT,
+            format: FormatUnion { raw: [0; 200] },
+        };
+        format.format.pix = pix;
+        let fd = self.file.as_raw_fd();
+        if unsafe { ioctl(fd, VIDIOC_S_FMT, &mut format) } < 0 {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} of {}x{} on {} ({})",
+                self.format,
+                width,
+                height,
+                self.path.display(),
+                io::Error::last_os_error()
+            )));
+        }
+        // SAFETY: the driver filled in the pixel format it takes
+        let set = unsafe { format.format.pix };
+        if (set.width, set.height, set.pixelformat, set.sizeimage)
+            != (pix.width, pix.height, pix.pixelformat, pix.sizeimage)
+        {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} of {}x{} on {}, it takes {}x{} of {} bytes",
+                self.format,
+                width,
+                height,
+                self.path.display(),
+                set.width,
+                set.height,
+                set.sizeimage
+            )));
+        }
+        let mut parm: StreamParm = unsafe { std::mem::zeroed() };
+        parm.kind = V4L2_BUF_TYPE_VIDEO_OUTPUT;
+        (parm.output.numerator, parm.output.denominator) = (self.framerate.1, self.framerate.0);
+        // applications read at their own pace without it
+        unsafe { ioctl(fd, VIDIOC_S_PARM, &mut parm) };
+        Ok(())
+    }
+
+    /// The frame in the format of the device, its rows tightly packed.
+    fn image(&mut self, frame: &Frame<'_>) -> Result<Vec<u8>> {
+        if frame.is_dmabuf() {
+            return Err(ScreencastError::Unsupported(
+                "v4l2loopback output of DMA-BUF frames".to_string(),
+            ));
+        }
+        let (width, height) = match self.format {
+            PixelFormat::I420 | PixelFormat::Nv12 => (frame.width() & !1, frame.height() & !1),
+            PixelFormat::Yuy2 => (frame.width() & !1, frame.height()),
+            _ => (frame.width(), frame.height()),
+        };
+        if width == 0 || height == 0 {
+            return Err(ScreencastError::Unsupported(format!(
+                "v4l2loopback output of {}x{} frames",
+                frame.width(),
+                frame.height()
+            )));
+        }
+        match self.size {
+            None => {
+                self.configure(width, height)?;
+                self.size = Some((width, height));
+            }
+            Some(size) if size != (width, height) => {
+                return Err(ScreencastError::Unsupported(format!(
+                    "v4l2loopback frames of {}x{} after {}x{}",
+                    width, height, size.0, size.1
+                )));
+            }
+            Some(_) => {}
+        }
+        let frame = match frame.pixel_format() == self.format {
+            true => Cow::Borrowed(frame),
+            false => Cow::Owned(convert::convert(frame, self.format)?),
+        };
+        let mut out = Vec::with_capacity(image_size(self.format, width, height));
+        for (index, (columns, rows)) in planes(self.format, width, height).into_iter().enumerate() {
+            let stride = frame.planes()[index].stride;
+            let data = frame.plane_data(index).unwrap_or_default();
+            for row in data.chunks(stride).take(rows) {
+                out.extend_from_slice(&row[..columns]);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl FrameSink for V4l2Loopback {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let image = self.image(&frame)?;
+        // each write is a frame, it must not be split
+        let written = self.file.write(&image)?;
+        if written != image.len() {
+            return Err(ScreencastError::Io(io::Error::new(
+                io::ErrorKind::WriteZero,
+                format!(
+                    "{} took {} of {} bytes",
+                    self.path.display(),
+                    written,
+                    image.len()
+                ),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Nothing to flush, applications keep showing the last frame until
+    /// the sink is dropped.
+    async fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The V4L2 pixel format, packed RGB formats are named by the order of
+/// their channels in a little endian word.
+fn fourcc(format: PixelFormat) -> Option<u32> {
+    let code = match format {
+        PixelFormat::Yuy2 => b"YUYV",
+        PixelFormat::I420 => b"YU12",
+        PixelFormat::Nv12 => b"NV12",
+        PixelFormat::Bgrx => b"XR24",
+        PixelFormat::Bgra => b"AR24",
+        PixelFormat::Rgbx => b"XB24",
+        PixelFormat::Rgba => b"AB24",
+        PixelFormat::Rgb => b"RGB3",
+        PixelFormat::Bgr => b"BGR3",
+        _ => return None,
+    };
+    Some(u32::from_le_bytes(*code))
+}
+
+/// Bytes per row and rows of each plane of a tightly packed image.
+fn planes(format: PixelFormat, width: u32, height: u32) -> Vec<(usize, usize)> {
+    let (width, height) = (width as usize, height as usize);
+    match format {
+        PixelFormat::I420 => vec![
+            (width, height),
+            (width / 2, height / 2),
+            (width / 2, height / 2),
+        ],
+        PixelFormat::Nv12 => vec![(width, height), (width, height / 2)],
+        PixelFormat::Yuy2 => vec![(width * 2, height)],
+        _ => vec![(width * format.bytes_per_pixel(), height)],
+    }
+}
+
+fn image_size(format: PixelFormat, width: u32, height: u32) -> usize {
+    planes(format, width, height)
+        .iter()
+        .map(|(columns, rows)| columns * rows)
+        .sum()
+}
@@ -0,0 +1,161 @@
+use super::FrameSink;
+use super::anim::{Canvas, Decimate, Pending, changed};
+use crate::error::Result;
+use crate::frame::Frame;
+use crate::image;
+use crate::runtime::compat;
+use std::path::Path;
+use std::time::Duration;
+use tokio::fs::File;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, BufWriter, SeekFrom};
+
+/// Records an animated WebP that loops forever, lossless and usually far
+/// smaller than the same clip as a GIF or APNG.
+///
+/// The capture is thinned out to 10 frames per second by default, and only
+/// the rectangle that changed since the last frame is stored. Frames
+/// without changes extend the previous one. The animation takes the size of
+/// the first frame, at most 16384 pixels wide and high.
+#[derive(Debug)]
+pub struct WebP {
+    file: BufWriter<File>,
+    decimate: Decimate,
+    canvas: Canvas,
+    pending: Option<Pending>,
+    position: u64,
+}
+
+impl WebP {
+    /// Creates or truncates `path`.
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = compat(File::create(path.as_ref())).await?;
+        Ok(WebP {
+            file: BufWriter::new(file),
+            decimate: Decimate::new(10),
+            canvas: Canvas::new("WebP", 1 << 14),
+            pending: None,
+            position: 0,
+        })
+    }
+
+    /// Frames per second kept of the capture.
+    pub fn with_framerate(mut self, fps: u32) -> Self {
+        self.decimate = self.decimate.with_framerate(fps.clamp(1, 1000));
+        self
+    }
+
+    /// Ends the animation after `duration`, later frames are dropped.
+    pub fn with_max_duration(mut self, duration: Duration) -> Self {
+        self.decimate = self.decimate.with_limit(duration);
+        self
+    }
+
+    /// Writes the pending frame, lasting until `until`.
+    async fn flush_pending(&mut self, until: Duration) -> Result<()> {
+        let Some(mut pending) = self.pending.take() else {
+            return Ok(());
+        };
+        let delay = until
+            .saturating_sub(pending.time)
+            .as_millis()
+            .clamp(1, 0xff_ffff) as u32;
+        pending.data[pending.delay..pending.delay + 3].copy_from_slice(&delay.to_le_bytes()[..3]);
+        self.file.write_all(&pending.data).await?;
+        self.position += pending.data.len() as u64;
+        Ok(())
+    }
+
+    /// The frame as an ANMF chunk of what changed and the offset of its
+    /// delay, `None` without changes.
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Option<(Vec<u8>, usize)>> {
+        let previous = self.canvas.draw(frame)?;
+        let (width, height) = (self.canvas.width(), self.canvas.height());
+        let current = self.canvas.pixels();
+        let first = previous.is_empty();
+        let unchanged =
+            |index: usize| !first && current[index * 4..][..4] == previous[index * 4..][..4];
+        let Some((left, top, right, bottom)) = changed(width, height, unchanged) else {
+            return Ok(None);
+        };
+        // frames are placed at even offsets
+        let bounds = (left & !1, top & !1, right, bottom);
+        let (left, top, right, bottom) = bounds;
+
+        let mut out = Vec::new();
+        if first {
+            // the RIFF size is filled in by `finish`
+            out.extend_from_slice(b"RIFF\0\0\0\0WEBP");
+            let mut header = vec![0x02, 0, 0, 0];
+            header.extend_from_slice(&(width as u32 - 1).to_le_bytes()[..3]);
+            header.extend_from_slice(&(height as u32 - 1).to_le_bytes()[..3]);
+            chunk(&mut out, b"VP8X", &header);
+            // black background, loops forever
+            chunk(&mut out, b"ANIM", &[0, 0, 0, 0xff, 0, 0]);
+        }
+        let (region_width, region_height) = ((right - left) as u32, (bottom - top) as u32);
+        let mut frame = Vec::new();
+        for value in [
+            left as u32 / 2,
+            top as u32 / 2,
+            region_width - 1,
+            region_height - 1,
+        ] {
+            frame.extend_from_slice(&value.to_le_bytes()[..3]);
+        }
+        let delay = out.len() + 8 + frame.len();
+        // the delay follows, then no blending and no disposal
+        frame.extend_from_slice(&[0, 0, 0, 0x02]);
+        let rgba = self.canvas.region(bounds, 4);
+        chunk(
+            &mut frame,
+            b"VP8L",
+            &image::encode_vp8l(&rgba, region_width, region_height),
+        );
+        chunk(&mut out, b"ANMF", &frame);
+        Ok(Some((out, delay)))
+    }
+}
+
+impl FrameSink for WebP {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        if frame.is_dmabuf() {
+            return Ok(());
+        }
+        let Some(time) = self.decimate.accept(frame.pts()) else {
+            return Ok(());
+        };
+        let Some((data, delay)) = self.encode(&frame)? else {
+            return Ok(());
+        };
+        compat(async {
+            self.flush_pending(time).await?;
+            self.pending = Some(Pending { time, data, delay });
+            Ok(())
+        })
+        .await
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            self.flush_pending(self.decimate.end()).await?;
+            if self.position > 0 {
+                let size = (self.position - 8).min(u32::MAX as u64) as u32;
+                self.file.seek(SeekFrom::Start(4)).await?;
+                self.file.write_all(&size.to_le_bytes()).await?;
+            }
+            self.file.flush().await?;
+            Ok(())
+        })
+        .await
+    }
+}
+
+/// A RIFF chunk, padded to an even size.
+fn chunk(out: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    out.extend_from_slice(kind);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+}
@@ -0,0 +1,115 @@
+use super::ffi;
+use crate::error::{Result, ScreencastError};
+use std::ffi::CStr;
+use std::os::raw::c_uint;
+use std::ptr;
+
+/// HMAC-SHA1 over the concatenated `parts`.
+pub(super) fn hmac_sha1(key: &[u8], parts: &[&[u8]]) -> [u8; 20] {
+    let data = parts.concat();
+    let mut out = [0; 20];
+    let mut len: c_uint = 0;
+    unsafe {
+        ffi::HMAC(
+            ffi::EVP_sha1(),
+            key.as_ptr().cast(),
+            key.len() as i32,
+            data.as_ptr(),
+            data.len(),
+            out.as_mut_ptr(),
+            &mut len,
+        );
+    }
+    out
+}
+
+/// Compares tags in a time that does not tell how much of them matched.
+pub(super) fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len()
+        && unsafe { ffi::CRYPTO_memcmp(a.as_ptr().cast(), b.as_ptr().cast(), a.len()) } == 0
+}
+
+/// Bytes from OpenSSL's generator, for keys and the ICE credentials.
+pub(super) fn random_bytes(len: usize) -> Result<Vec<u8>> {
+    let mut bytes = vec![0; len];
+    if unsafe { ffi::RAND_bytes(bytes.as_mut_ptr(), len as i32) } != 1 {
+        return Err(openssl_error("RAND_bytes"));
+    }
+    Ok(bytes)
+}
+
+/// Letters and digits, as ICE wants its credentials.
+pub(super) fn random_token(len: usize) -> Result<String> {
+    const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+    Ok(random_bytes(len)?
+        .into_iter()
+        .map(|byte| CHARS[byte as usize % CHARS.len()] as char)
+        .collect())
+}
+
+/// The oldest error OpenSSL queued, what failed last usually follows it.
+pub(super) fn openssl_error(context: &str) -> ScreencastError {
+    let code = unsafe { ffi::ERR_get_error() };
+    if code == 0 {
+        return ScreencastError::Streaming(context.to_string());
+    }
+    let mut buf = [0; 256];
+    unsafe { ffi::ERR_error_string_n(code, buf.as_mut_ptr(), buf.len()) };
+    let message = unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy();
+    ScreencastError::Streaming(format!("{}: {}", context, message))
+}
+
+/// AES-128 in counter mode with a fixed key, the IV given for each run.
+pub(super) struct AesCtr {
+    ctx: *mut ffi::EVP_CIPHER_CTX,
+}
+
+// SAFETY: the context is only used through `&mut self`.
+unsafe impl Send for AesCtr {}
+
+impl AesCtr {
+    pub(super) fn new(key: &[u8; 16]) -> Result<Self> {
+        let ctx = unsafe { ffi::EVP_CIPHER_CTX_new() };
+        if ctx.is_null() {
+            return Err(openssl_error("EVP_CIPHER_CTX_new"));
+        }
+        let aes = AesCtr { ctx };
+        let initialized = unsafe {
+            ffi::EVP_EncryptInit_ex(
+                ctx,
+                ffi::EVP_aes_128_ctr(),
+                ptr::null_mut(),
+                key.as_ptr(),
+                ptr::null(),
+            )
+        };
+        if initialized != 1 {
+            return Err(openssl_error("EVP_EncryptInit_ex"));
+        }
+        Ok(aes)
+    }
+
+    /// XORs `data` with the key stream starting at `iv`.
+    pub(super) fn apply(&mut self, iv: &[u8; 16], data: &mut [u8]) {
+        unsafe {
+            ffi::EVP_EncryptInit_ex(
+                self.ctx,
+                ptr::null(),
+                ptr::null_mut(),
+                ptr::null(),
+                iv.as_ptr(),
+            );
+            let mut len = 0;
+            for chunk in data.chunks_mut(i32::MAX as usize) {
+                let pointer = chunk.as_mut_ptr();
+                ffi::EVP_EncryptUpdate(self.ctx, pointer, &mut len, pointer, chunk.len() as i32);
+            }
+        }
+    }
+}
+
+impl Drop for AesCtr {
+    fn drop(&mut self) {
+        unsafe { ffi::EVP_CIPHER_CTX_free(self.ctx) };
+    }
+}
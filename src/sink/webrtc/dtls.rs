@@ -0,0 +1,443 @@
+use super::crypto::{self, openssl_error};
+use super::ffi;
+use crate::error::{Result, ScreencastError};
+use std::ffi::CStr;
+use std::os::raw::c_int;
+use std::ptr;
+
+/// Datagrams of DTLS records are kept below this, the size of RTP packets.
+pub(super) const MTU: usize = 1200;
+
+const SRTP_PROFILE: &CStr = c"SRTP_AES128_CM_SHA1_80";
+const SRTP_AES128_CM_SHA1_80: std::os::raw::c_ulong = 0x0001;
+const EXPORTER_LABEL: &str = "EXTRACTOR-dtls_srtp";
+
+const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+/// A self-signed certificate on a P-256 key, made for each peer, which the
+/// SDP vouches for by its fingerprint.
+pub(super) struct Certificate {
+    x509: *mut ffi::X509,
+    key: *mut ffi::EVP_PKEY,
+    fingerprint: String,
+}
+
+// SAFETY: never changed after it was made, OpenSSL counts references
+// atomically.
+unsafe impl Send for Certificate {}
+unsafe impl Sync for Certificate {}
+
+impl std::fmt::Debug for Certificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Certificate")
+            .field("fingerprint", &self.fingerprint)
+            .finish()
+    }
+}
+
+impl Certificate {
+    pub(super) fn generate() -> Result<Self> {
+        let serial = u32::from_be_bytes(crypto::random_bytes(4)?.try_into().unwrap()) >> 1;
+        unsafe {
+            let key = ffi::EVP_PKEY_Q_keygen(
+                ptr::null_mut(),
+                ptr::null(),
+                c"EC".as_ptr(),
+                c"P-256".as_ptr(),
+            );
+            if key.is_null() {
+                return Err(openssl_error("EVP_PKEY_Q_keygen"));
+            }
+            let x509 = ffi::X509_new();
+            if x509.is_null() {
+                ffi::EVP_PKEY_free(key);
+                return Err(openssl_error("X509_new"));
+            }
+            let mut certificate = Certificate {
+                x509,
+                key,
+                fingerprint: String::new(),
+            };
+            let name = ffi::X509_get_subject_name(x509);
+            let signed = ffi::X509_set_version(x509, 2) == 1
+                && ffi::ASN1_INTEGER_set(ffi::X509_get_serialNumber(x509), serial as _) == 1
+                && !ffi::X509_gmtime_adj(ffi::X509_getm_notBefore(x509), -86_400).is_null()
+                && !ffi::X509_gmtime_adj(ffi::X509_getm_notAfter(x509), 30 * 86_400).is_null()
+                && ffi::X509_set_pubkey(x509, key) == 1
+                && ffi::X509_NAME_add_entry_by_txt(
+                    name,
+                    c"CN".as_ptr(),
+                    ffi::MBSTRING_ASC,
+                    c"xdp-screencast".as_ptr().cast(),
+                    -1,
+                    -1,
+                    0,
+                ) == 1
+                && ffi::X509_set_issuer_name(x509, name) == 1
+                && ffi::X509_sign(x509, key, ffi::EVP_sha256()) > 0;
+            if !signed {
+                return Err(openssl_error("signing the certificate"));
+            }
+            certificate.fingerprint = fingerprint(x509)?;
+            Ok(certificate)
+        }
+    }
+
+    /// `sha-256` and the digest in hex, as `a=fingerprint` wants it.
+    pub(super) fn fingerprint(&self) -> &str {
+        &self.fingerprint
+    }
+}
+
+impl Drop for Certificate {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::X509_free(self.x509);
+            ffi::EVP_PKEY_free(self.key);
+        }
+    }
+}
+
+/// The master keys and salts of both directions, from the handshake.
+#[derive(Debug, Clone)]
+pub(super) struct SrtpKeys {
+    pub local_key: [u8; 16],
+    pub local_salt: [u8; 14],
+    pub remote_key: [u8; 16],
+    pub remote_salt: [u8; 14],
+}
+
+/// What came of a record fed to `Dtls::receive`.
+#[derive(Debug)]
+pub(super) enum Received {
+    Nothing,
+    /// The handshake finished, with the keys for SRTP.
+    Connected(SrtpKeys),
+    /// The peer closed the association.
+    Closed,
+}
+
+/// The DTLS association of a peer, over memory buffers: records received
+/// go in with `receive`, those to send come out of `outgoing`.
+pub(super) struct Dtls {
+    ctx: *mut ffi::SSL_CTX,
+    ssl: *mut ffi::SSL,
+    // owned by `ssl`
+    incoming: *mut ffi::BIO,
+    outgoing: *mut ffi::BIO,
+    // expected of the certificate of the peer, from its SDP
+    remote_fingerprint: String,
+    connected: bool,
+}
+
+// SAFETY: only used through `&mut self`.
+unsafe impl Send for Dtls {}
+
+impl std::fmt::Debug for Dtls {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Dtls")
+            .field("connected", &self.connected)
+            .finish()
+    }
+}
+
+impl Dtls {
+    /// The client sends the first flight with `start`, the server waits
+    /// for it.
+    pub(super) fn new(
+        certificate: &Certificate,
+        client: bool,
+        remote_fingerprint: &str,
+    ) -> Result<Self> {
+        unsafe {
+            let ctx = ffi::SSL_CTX_new(ffi::DTLS_method());
+            if ctx.is_null() {
+                return Err(openssl_error("SSL_CTX_new"));
+            }
+            // the peer is known by the fingerprint, checked once connected
+            ffi::SSL_CTX_set_verify(
+                ctx,
+                ffi::SSL_VERIFY_PEER | ffi::SSL_VERIFY_FAIL_IF_NO_PEER_CERT,
+                Some(accept_certificate),
+            );
+            let configured = ffi::SSL_CTX_use_certificate(ctx, certificate.x509) == 1
+                && ffi::SSL_CTX_use_PrivateKey(ctx, certificate.key) == 1
+                && ffi::SSL_CTX_set_tlsext_use_srtp(ctx, SRTP_PROFILE.as_ptr()) == 0;
+            let ssl = match configured {
+                true => ffi::SSL_new(ctx),
+                false => ptr::null_mut(),
+            };
+            if ssl.is_null() {
+                let err = openssl_error("setting up DTLS");
+                ffi::SSL_CTX_free(ctx);
+                return Err(err);
+            }
+            let incoming = ffi::BIO_new(ffi::BIO_s_mem());
+            let outgoing = ffi::BIO_new(ffi::BIO_s_mem());
+            ffi::SSL_set_bio(ssl, incoming, outgoing);
+            let dtls = Dtls {
+                ctx,
+                ssl,
+                incoming,
+                outgoing,
+                remote_fingerprint: remote_fingerprint.to_string(),
+                connected: false,
+            };
+            if incoming.is_null() || outgoing.is_null() {
+                return Err(openssl_error("BIO_new"));
+            }
+            // an empty buffer means waiting for more, not the end
+            ffi::BIO_ctrl(
+                incoming,
+                ffi::BIO_C_SET_BUF_MEM_EOF_RETURN,
+                -1,
+                ptr::null_mut(),
+            );
+            ffi::SSL_set_options(ssl, ffi::SSL_OP_NO_QUERY_MTU);
+            ffi::SSL_ctrl(ssl, ffi::SSL_CTRL_SET_MTU, MTU as _, ptr::null_mut());
+            match client {
+                true => ffi::SSL_set_connect_state(ssl),
+                false => ffi::SSL_set_accept_state(ssl),
+            }
+            Ok(dtls)
+        }
+    }
+
+    pub(super) fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Sends the first flight of a client.
+    pub(super) fn start(&mut self) -> Result<()> {
+        self.handshake().map(|_| ())
+    }
+
+    pub(super) fn receive(&mut self, record: &[u8]) -> Result<Received> {
+        unsafe { ffi::BIO_write(self.incoming, record.as_ptr().cast(), record.len() as i32) };
+        if !self.connected {
+            return self.handshake();
+        }
+        // alerts, a close_notify in particular, application data is not
+        // expected
+        let mut buf = [0u8; 2048];
+        loop {
+            let read =
+                unsafe { ffi::SSL_read(self.ssl, buf.as_mut_ptr().cast(), buf.len() as i32) };
+            if read > 0 {
+                continue;
+            }
+            return match unsafe { ffi::SSL_get_error(self.ssl, read) } {
+                SSL_ERROR_ZERO_RETURN => Ok(Received::Closed),
+                ffi::SSL_ERROR_WANT_READ | ffi::SSL_ERROR_WANT_WRITE => Ok(Received::Nothing),
+                _ => Ok(Received::Closed),
+            };
+        }
+    }
+
+    /// Resends a flight that went unanswered, call it every few hundred
+    /// milliseconds while connecting.
+    pub(super) fn handle_timeout(&mut self) {
+        if !self.connected {
+            unsafe { ffi::SSL_ctrl(self.ssl, ffi::DTLS_CTRL_HANDLE_TIMEOUT, 0, ptr::null_mut()) };
+        }
+    }
+
+    /// Sends a close_notify.
+    pub(super) fn close(&mut self) {
+        if self.connected {
+            unsafe { ffi::SSL_shutdown(self.ssl) };
+        }
+    }
+
+    /// The records to send, whole records in datagrams of at most `MTU`.
+    pub(super) fn outgoing(&mut self) -> Vec<Vec<u8>> {
+        let pending =
+            unsafe { ffi::BIO_ctrl(self.outgoing, ffi::BIO_CTRL_PENDING, 0, ptr::null_mut()) };
+        if pending <= 0 {
+            return Vec::new();
+        }
+        let mut bytes = vec![0u8; pending as usize];
+        let read =
+            unsafe { ffi::BIO_read(self.outgoing, bytes.as_mut_ptr().cast(), bytes.len() as i32) };
+        bytes.truncate(read.max(0) as usize);
+        let mut datagrams: Vec<Vec<u8>> = Vec::new();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= 13 {
+            let length = 13 + u16::from_be_bytes([rest[11], rest[12]]) as usize;
+            let (record, next) = rest.split_at(length.min(rest.len()));
+            match datagrams.last_mut() {
+                Some(datagram) if datagram.len() + record.len() <= MTU => {
+                    datagram.extend_from_slice(record)
+                }
+                _ => datagrams.push(record.to_vec()),
+            }
+            rest = next;
+        }
+        datagrams
+    }
+
+    fn handshake(&mut self) -> Result<Received> {
+        let result = unsafe { ffi::SSL_do_handshake(self.ssl) };
+        if result != 1 {
+            return match unsafe { ffi::SSL_get_error(self.ssl, result) } {
+                ffi::SSL_ERROR_WANT_READ | ffi::SSL_ERROR_WANT_WRITE => Ok(Received::Nothing),
+                _ => Err(openssl_error("DTLS handshake")),
+            };
+        }
+        let certificate = unsafe { ffi::SSL_get1_peer_certificate(self.ssl) };
+        if certificate.is_null() {
+            return Err(ScreencastError::Streaming(
+                "the peer sent no certificate".to_string(),
+            ));
+        }
+        let presented = unsafe { fingerprint(certificate) };
+        unsafe { ffi::X509_free(certificate) };
+        if !presented?.eq_ignore_ascii_case(&self.remote_fingerprint) {
+            return Err(ScreencastError::Streaming(
+                "the certificate of the peer does not match its fingerprint".to_string(),
+            ));
+        }
+        self.connected = true;
+        self.keys().map(Received::Connected)
+    }
+
+    fn keys(&mut self) -> Result<SrtpKeys> {
+        let profile = unsafe { ffi::SSL_get_selected_srtp_profile(self.ssl) };
+        if profile.is_null() || unsafe { (*profile).id } != SRTP_AES128_CM_SHA1_80 {
+            return Err(ScreencastError::Streaming(
+                "the peer agreed on no SRTP profile".to_string(),
+            ));
+        }
+        let mut material = [0u8; 60];
+        let exported = unsafe {
+            ffi::SSL_export_keying_material(
+                self.ssl,
+                material.as_mut_ptr(),
+                material.len(),
+                EXPORTER_LABEL.as_ptr().cast(),
+                EXPORTER_LABEL.len(),
+                ptr::null(),
+                0,
+                0,
+            )
+        };
+        if exported != 1 {
+            return Err(openssl_error("SSL_export_keying_material"));
+        }
+        // both keys, then both salts, the client's first
+        let (client_key, rest) = material.split_at(16);
+        let (server_key, rest) = rest.split_at(16);
+        let (client_salt, server_salt) = rest.split_at(14);
+        let (local, remote) = match self.is_client() {
+            true => ((client_key, client_salt), (server_key, server_salt)),
+            false => ((server_key, server_salt), (client_key, client_salt)),
+        };
+        Ok(SrtpKeys {
+            local_key: local.0.try_into().unwrap(),
+            local_salt: local.1.try_into().unwrap(),
+            remote_key: remote.0.try_into().unwrap(),
+            remote_salt: remote.1.try_into().unwrap(),
+        })
+    }
+
+    fn is_client(&self) -> bool {
+        unsafe { ffi::SSL_is_server(self.ssl) == 0 }
+    }
+}
+
+impl Drop for Dtls {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::SSL_free(self.ssl);
+            ffi::SSL_CTX_free(self.ctx);
+        }
+    }
+}
+
+unsafe extern "C" fn accept_certificate(_: c_int, _: *mut ffi::X509_STORE_CTX) -> c_int {
+    1
+}
+
+/// # Safety
+/// `x509` must be a live certificate.
+unsafe fn fingerprint(x509: *const ffi::X509) -> Result<String> {
+    let mut digest = [0u8; 32];
+    let mut len = 0;
+    if unsafe { ffi::X509_digest(x509, ffi::EVP_sha256(), digest.as_mut_ptr(), &mut len) } != 1 {
+        return Err(openssl_error("X509_digest"));
+    }
+    let hex: Vec<String> = digest[..len as usize]
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect();
+    Ok(format!("sha-256 {}", hex.join(":")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_handshake() {
+        let client_certificate = Certificate::generate().unwrap();
+        let server_certificate = Certificate::generate().unwrap();
+        let mut client =
+            Dtls::new(&client_certificate, true, server_certificate.fingerprint()).unwrap();
+        let mut server =
+            Dtls::new(&server_certificate, false, client_certificate.fingerprint()).unwrap();
+        client.start().unwrap();
+
+        let (mut client_keys, mut server_keys) = (None, None);
+        for _ in 0..10 {
+            for datagram in client.outgoing() {
+                assert!(datagram.len() <= MTU);
+                if let Received::Connected(keys) = server.receive(&datagram).unwrap() {
+                    server_keys = Some(keys);
+                }
+            }
+            for datagram in server.outgoing() {
+                assert!(datagram.len() <= MTU);
+                if let Received::Connected(keys) = client.receive(&datagram).unwrap() {
+                    client_keys = Some(keys);
+                }
+            }
+        }
+        assert!(client.is_connected() && server.is_connected());
+        let (client_keys, server_keys) = (client_keys.unwrap(), server_keys.unwrap());
+        assert_eq!(client_keys.local_key, server_keys.remote_key);
+        assert_eq!(client_keys.local_salt, server_keys.remote_salt);
+        assert_eq!(client_keys.remote_key, server_keys.local_key);
+        assert_eq!(client_keys.remote_salt, server_keys.local_salt);
+        assert_ne!(client_keys.local_key, client_keys.remote_key);
+
+        client.close();
+        let closed = client
+            .outgoing()
+            .iter()
+            .any(|datagram| matches!(server.receive(datagram), Ok(Received::Closed)));
+        assert!(closed);
+    }
+
+    #[test]
+    fn rejects_unexpected_certificate() {
+        let client_certificate = Certificate::generate().unwrap();
+        let server_certificate = Certificate::generate().unwrap();
+        let other = Certificate::generate().unwrap();
+        let mut client = Dtls::new(&client_certificate, true, other.fingerprint()).unwrap();
+        let mut server =
+            Dtls::new(&server_certificate, false, client_certificate.fingerprint()).unwrap();
+        client.start().unwrap();
+
+        let mut failed = false;
+        for _ in 0..10 {
+            for datagram in client.outgoing() {
+                let _ = server.receive(&datagram);
+            }
+            for datagram in server.outgoing() {
+                failed |= client.receive(&datagram).is_err();
+            }
+        }
+        assert!(failed);
+        assert!(!client.is_connected());
+    }
+}
@@ -0,0 +1,222 @@
+#![allow(non_camel_case_types, clippy::upper_case_acronyms, dead_code)]
+
+use std::os::raw::{c_char, c_int, c_long, c_uint, c_ulong, c_void};
+
+pub const SSL_VERIFY_PEER: c_int = 0x01;
+pub const SSL_VERIFY_FAIL_IF_NO_PEER_CERT: c_int = 0x02;
+
+pub const SSL_ERROR_WANT_READ: c_int = 2;
+pub const SSL_ERROR_WANT_WRITE: c_int = 3;
+pub const SSL_ERROR_ZERO_RETURN: c_int = 6;
+
+pub const SSL_OP_NO_QUERY_MTU: u64 = 0x0000_1000;
+
+pub const SSL_CTRL_SET_MTU: c_int = 17;
+pub const SSL_CTRL_SET_TLSEXT_HOSTNAME: c_int = 55;
+pub const TLSEXT_NAMETYPE_HOST_NAME: c_long = 0;
+pub const DTLS_CTRL_HANDLE_TIMEOUT: c_int = 74;
+
+pub const BIO_CTRL_PENDING: c_int = 10;
+pub const BIO_C_SET_BUF_MEM_EOF_RETURN: c_int = 130;
+
+pub const MBSTRING_ASC: c_int = 0x1001;
+
+#[repr(C)]
+pub struct SSL_CTX {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct SSL {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct SSL_METHOD {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct BIO {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct BIO_METHOD {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct X509 {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct X509_NAME {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct X509_STORE_CTX {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct ASN1_INTEGER {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct ASN1_TIME {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct EVP_PKEY {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct EVP_MD {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct EVP_CIPHER {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct EVP_CIPHER_CTX {
+    _private: [u8; 0],
+}
+
+#[repr(C)]
+pub struct SRTP_PROTECTION_PROFILE {
+    pub name: *const c_char,
+    pub id: c_ulong,
+}
+
+pub type VerifyCallback = unsafe extern "C" fn(c_int, *mut X509_STORE_CTX) -> c_int;
+
+#[link(name = "ssl")]
+unsafe extern "C" {
+    pub fn DTLS_method() -> *const SSL_METHOD;
+    pub fn TLS_client_method() -> *const SSL_METHOD;
+    pub fn SSL_CTX_new(method: *const SSL_METHOD) -> *mut SSL_CTX;
+    pub fn SSL_CTX_free(ctx: *mut SSL_CTX);
+    pub fn SSL_CTX_use_certificate(ctx: *mut SSL_CTX, x509: *mut X509) -> c_int;
+    pub fn SSL_CTX_use_PrivateKey(ctx: *mut SSL_CTX, pkey: *mut EVP_PKEY) -> c_int;
+    /// Returns 0 on success, unlike the others.
+    pub fn SSL_CTX_set_tlsext_use_srtp(ctx: *mut SSL_CTX, profiles: *const c_char) -> c_int;
+    pub fn SSL_CTX_set_default_verify_paths(ctx: *mut SSL_CTX) -> c_int;
+    pub fn SSL_CTX_set_verify(ctx: *mut SSL_CTX, mode: c_int, callback: Option<VerifyCallback>);
+
+    pub fn SSL_new(ctx: *mut SSL_CTX) -> *mut SSL;
+    pub fn SSL_free(ssl: *mut SSL);
+    pub fn SSL_set_bio(ssl: *mut SSL, rbio: *mut BIO, wbio: *mut BIO);
+    pub fn SSL_set_accept_state(ssl: *mut SSL);
+    pub fn SSL_set_connect_state(ssl: *mut SSL);
+    pub fn SSL_set1_host(ssl: *mut SSL, hostname: *const c_char) -> c_int;
+    pub fn SSL_set_options(ssl: *mut SSL, options: u64) -> u64;
+    pub fn SSL_ctrl(ssl: *mut SSL, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+    pub fn SSL_do_handshake(ssl: *mut SSL) -> c_int;
+    pub fn SSL_is_init_finished(ssl: *const SSL) -> c_int;
+    pub fn SSL_is_server(ssl: *const SSL) -> c_int;
+    pub fn SSL_get_error(ssl: *const SSL, ret: c_int) -> c_int;
+    pub fn SSL_read(ssl: *mut SSL, buf: *mut c_void, num: c_int) -> c_int;
+    pub fn SSL_write(ssl: *mut SSL, buf: *const c_void, num: c_int) -> c_int;
+    pub fn SSL_shutdown(ssl: *mut SSL) -> c_int;
+    pub fn SSL_export_keying_material(
+        ssl: *mut SSL,
+        out: *mut u8,
+        olen: usize,
+        label: *const c_char,
+        llen: usize,
+        context: *const u8,
+        contextlen: usize,
+        use_context: c_int,
+    ) -> c_int;
+    pub fn SSL_get_selected_srtp_profile(ssl: *mut SSL) -> *mut SRTP_PROTECTION_PROFILE;
+    pub fn SSL_get1_peer_certificate(ssl: *const SSL) -> *mut X509;
+}
+
+#[link(name = "crypto")]
+unsafe extern "C" {
+    pub fn BIO_s_mem() -> *const BIO_METHOD;
+    pub fn BIO_new(method: *const BIO_METHOD) -> *mut BIO;
+    pub fn BIO_write(bio: *mut BIO, data: *const c_void, len: c_int) -> c_int;
+    pub fn BIO_read(bio: *mut BIO, data: *mut c_void, len: c_int) -> c_int;
+    pub fn BIO_ctrl(bio: *mut BIO, cmd: c_int, larg: c_long, parg: *mut c_void) -> c_long;
+
+    pub fn EVP_PKEY_Q_keygen(
+        libctx: *mut c_void,
+        propq: *const c_char,
+        type_: *const c_char,
+        ...
+    ) -> *mut EVP_PKEY;
+    pub fn EVP_PKEY_free(pkey: *mut EVP_PKEY);
+
+    pub fn X509_new() -> *mut X509;
+    pub fn X509_free(x509: *mut X509);
+    pub fn X509_set_version(x509: *mut X509, version: c_long) -> c_int;
+    pub fn X509_get_serialNumber(x509: *mut X509) -> *mut ASN1_INTEGER;
+    pub fn ASN1_INTEGER_set(integer: *mut ASN1_INTEGER, value: c_long) -> c_int;
+    pub fn X509_getm_notBefore(x509: *const X509) -> *mut ASN1_TIME;
+    pub fn X509_getm_notAfter(x509: *const X509) -> *mut ASN1_TIME;
+    pub fn X509_gmtime_adj(time: *mut ASN1_TIME, adjust: c_long) -> *mut ASN1_TIME;
+    pub fn X509_set_pubkey(x509: *mut X509, pkey: *mut EVP_PKEY) -> c_int;
+    pub fn X509_get_subject_name(x509: *const X509) -> *mut X509_NAME;
+    pub fn X509_set_issuer_name(x509: *mut X509, name: *const X509_NAME) -> c_int;
+    pub fn X509_NAME_add_entry_by_txt(
+        name: *mut X509_NAME,
+        field: *const c_char,
+        type_: c_int,
+        bytes: *const u8,
+        len: c_int,
+        loc: c_int,
+        set: c_int,
+    ) -> c_int;
+    pub fn X509_sign(x509: *mut X509, pkey: *mut EVP_PKEY, md: *const EVP_MD) -> c_int;
+    pub fn X509_digest(
+        x509: *const X509,
+        md: *const EVP_MD,
+        out: *mut u8,
+        len: *mut c_uint,
+    ) -> c_int;
+
+    pub fn EVP_sha1() -> *const EVP_MD;
+    pub fn EVP_sha256() -> *const EVP_MD;
+    pub fn HMAC(
+        md: *const EVP_MD,
+        key: *const c_void,
+        key_len: c_int,
+        data: *const u8,
+        data_len: usize,
+        out: *mut u8,
+        out_len: *mut c_uint,
+    ) -> *mut u8;
+
+    pub fn EVP_aes_128_ctr() -> *const EVP_CIPHER;
+    pub fn EVP_CIPHER_CTX_new() -> *mut EVP_CIPHER_CTX;
+    pub fn EVP_CIPHER_CTX_free(ctx: *mut EVP_CIPHER_CTX);
+    pub fn EVP_EncryptInit_ex(
+        ctx: *mut EVP_CIPHER_CTX,
+        cipher: *const EVP_CIPHER,
+        engine: *mut c_void,
+        key: *const u8,
+        iv: *const u8,
+    ) -> c_int;
+    pub fn EVP_EncryptUpdate(
+        ctx: *mut EVP_CIPHER_CTX,
+        out: *mut u8,
+        out_len: *mut c_int,
+        input: *const u8,
+        input_len: c_int,
+    ) -> c_int;
+
+    pub fn RAND_bytes(buf: *mut u8, num: c_int) -> c_int;
+    pub fn CRYPTO_memcmp(a: *const c_void, b: *const c_void, len: usize) -> c_int;
+    pub fn ERR_get_error() -> c_ulong;
+    pub fn ERR_error_string_n(error: c_ulong, buf: *mut c_char, len: usize);
+}
@@ -0,0 +1,261 @@
+use super::tls::TlsStream;
+use crate::error::{Result, ScreencastError};
+use std::fmt::Write;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A request that takes longer failed.
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// An `http` or `https` URL, the parts a request needs.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Url {
+    pub tls: bool,
+    pub host: String,
+    pub port: u16,
+    /// With the query, from the first `/`.
+    pub path: String,
+}
+
+#[derive(Debug, Clone)]
+pub(super) struct Response {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Url {
+    pub(super) fn parse(url: &str) -> Result<Self> {
+        let invalid = || ScreencastError::Streaming(format!("invalid URL {:?}", url));
+        let (tls, rest) = match url.split_once("://") {
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("https") => (true, rest),
+            Some((scheme, rest)) if scheme.eq_ignore_ascii_case("http") => (false, rest),
+            _ => return Err(invalid()),
+        };
+        let (authority, path) = match rest.find(['/', '?']) {
+            Some(index) => rest.split_at(index),
+            None => (rest, "/"),
+        };
+        let path = match path.starts_with('?') {
+            true => format!("/{}", path),
+            false => path.to_string(),
+        };
+        // credentials are not sent, a token is
+        let authority = authority
+            .rsplit_once('@')
+            .map_or(authority, |(_, host)| host);
+        let default_port = if tls { 443 } else { 80 };
+        let (host, port) = match authority.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, rest) = bracketed.split_once(']').ok_or_else(invalid)?;
+                let port = match rest.strip_prefix(':') {
+                    Some(port) => port.parse().map_err(|_| invalid())?,
+                    None => default_port,
+                };
+                (host, port)
+            }
+            None => match authority.split_once(':') {
+                Some((host, port)) => (host, port.parse().map_err(|_| invalid())?),
+                None => (authority, default_port),
+            },
+        };
+        if host.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Url {
+            tls,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// Resolves `reference`, e.g. of a `Location` header, against this URL.
+    pub(super) fn join(&self, reference: &str) -> Result<Url> {
+        if reference.contains("://") {
+            return Url::parse(reference);
+        }
+        let path = if let Some(authority) = reference.strip_prefix("//") {
+            let scheme = if self.tls { "https" } else { "http" };
+            return Url::parse(&format!("{}://{}", scheme, authority));
+        } else if reference.starts_with('/') {
+            reference.to_string()
+        } else {
+            let base = self.path.split('?').next().unwrap_or("/");
+            let directory = &base[..base.rfind('/').map_or(0, |index| index + 1)];
+            format!("{}{}", directory, reference)
+        };
+        Ok(Url {
+            path,
+            ..self.clone()
+        })
+    }
+
+    fn authority(&self) -> String {
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.clone(),
+        };
+        match (self.tls, self.port) {
+            (true, 443) | (false, 80) => host,
+            _ => format!("{}:{}", host, self.port),
+        }
+    }
+}
+
+impl std::fmt::Display for Url {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let scheme = if self.tls { "https" } else { "http" };
+        write!(f, "{}://{}{}", scheme, self.authority(), self.path)
+    }
+}
+
+impl Response {
+    pub(super) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(super) fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+enum Connection {
+    Plain(TcpStream),
+    Tls(Box<TlsStream>),
+}
+
+impl Connection {
+    async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        match self {
+            Connection::Plain(tcp) => Ok(tcp.write_all(data).await?),
+            Connection::Tls(tls) => tls.write_all(data).await,
+        }
+    }
+
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Connection::Plain(tcp) => Ok(tcp.read(buf).await?),
+            Connection::Tls(tls) => tls.read(buf).await,
+        }
+    }
+}
+
+/// An HTTP/1.1 request on a connection of its own, closed after the
+/// response.
+pub(super) async fn request(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response> {
+    tokio::time::timeout(TIMEOUT, exchange(method, url, headers, body))
+        .await
+        .map_err(|_| ScreencastError::Streaming(format!("{} {} timed out", method, url)))?
+}
+
+async fn exchange(
+    method: &str,
+    url: &Url,
+    headers: &[(&str, &str)],
+    body: &[u8],
+) -> Result<Response> {
+    let tcp = TcpStream::connect((url.host.as_str(), url.port)).await?;
+    let _ = tcp.set_nodelay(true);
+    let mut connection = match url.tls {
+        true => Connection::Tls(Box::new(TlsStream::connect(tcp, &url.host).await?)),
+        false => Connection::Plain(tcp),
+    };
+    let mut head = format!(
+        "{} {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: xdp-screencast\r\nConnection: close\r\nContent-Length: {}\r\n",
+        method,
+        url.path,
+        url.authority(),
+        body.len()
+    );
+    for (name, value) in headers {
+        let _ = write!(head, "{}: {}\r\n", name, value);
+    }
+    head.push_str("\r\n");
+    let mut message = head.into_bytes();
+    message.extend_from_slice(body);
+    connection.write_all(&message).await?;
+
+    let mut received = Vec::new();
+    let mut buf = vec![0u8; 16 * 1024];
+    loop {
+        let read = connection.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        received.extend_from_slice(&buf[..read]);
+        if let Some(response) = parse_response(&received, false)? {
+            return Ok(response);
+        }
+    }
+    parse_response(&received, true)?
+        .ok_or_else(|| ScreencastError::Streaming(format!("{} closed the connection", url.host)))
+}
+
+/// The response once all of it came, `ended` when the server closed the
+/// connection, which ends a body of no given length.
+fn parse_response(received: &[u8], ended: bool) -> Result<Option<Response>> {
+    let Some(head_end) = received.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = String::from_utf8_lossy(&received[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| ScreencastError::Streaming("malformed HTTP response".to_string()))?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+    let mut response = Response {
+        status,
+        headers,
+        body: Vec::new(),
+    };
+    let rest = &received[head_end + 4..];
+    let chunked = response
+        .header("Transfer-Encoding")
+        .is_some_and(|encoding| encoding.eq_ignore_ascii_case("chunked"));
+    if chunked {
+        return Ok(dechunk(rest).map(|body| {
+            response.body = body;
+            response
+        }));
+    }
+    let length = response
+        .header("Content-Length")
+        .and_then(|length| length.parse::<usize>().ok());
+    match length {
+        Some(length) if rest.len() >= length => response.body = rest[..length].to_vec(),
+        None if ended || status == 204 || status == 304 => response.body = rest.to_vec(),
+        _ => return Ok(None),
+    }
+    Ok(Some(response))
+}
+
+/// A chunked body once its last chunk came.
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|window| window == b"\r\n")?;
+        let line = std::str::from_utf8(&data[..line_end]).ok()?;
+        let size = usize::from_str_radix(line.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
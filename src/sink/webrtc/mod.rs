@@ -0,0 +1,756 @@
+mod crypto;
+mod dtls;
+mod ffi;
+mod http;
+mod sdp;
+mod srtp;
+mod stun;
+pub(super) mod tls;
+mod whip;
+
+use super::FrameSink;
+use super::codec::BlockingEncoder;
+use super::rtp::{self, Payload, RtpStream};
+use crate::audio::{AudioCodec, AudioTrack};
+use crate::encode::{self, EncodedPacket, Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime::{self, compat};
+use dtls::{Certificate, Dtls, Received};
+use sdp::{LocalMedia, MediaDescription, Negotiated, SessionDescription, Transport};
+use srtp::Srtp;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::net::{ToSocketAddrs, UdpSocket};
+use tokio::task::JoinHandle;
+
+pub use whip::Whip;
+
+/// Payload types of an offer, the ones browsers pick too.
+const VIDEO_PAYLOAD_TYPE: u8 = 96;
+const AUDIO_PAYLOAD_TYPE: u8 = 111;
+
+/// How often the connection is looked after: DTLS resends and ICE checks.
+const TICK: Duration = Duration::from_millis(50);
+/// Checks go out this often until a candidate pair works, then as
+/// keepalives every `KEEPALIVE`.
+const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+const KEEPALIVE: Duration = Duration::from_secs(5);
+/// A connected peer that was not heard of for this long is gone, browsers
+/// check their consent every few seconds.
+const TIMEOUT: Duration = Duration::from_secs(30);
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Streams to a WebRTC peer, e.g. a browser, with sub-second latency: the
+/// frames are encoded and sent as SRTP over a UDP socket of its own once
+/// ICE found a path and the DTLS handshake finished, frames before that
+/// are dropped. Keyframes the viewer asks for are sent right away.
+///
+/// The session is negotiated through `WebRtcPeer`, from `peer`: answer an
+/// offer of the viewer with `accept_offer`, or offer with `create_offer`
+/// and take its answer with `accept_answer`, e.g. to publish to a server.
+/// H.264 goes in the non-interleaved mode, audio only as Opus.
+///
+/// Needs the `webrtc` feature, which links OpenSSL for DTLS and SRTP.
+pub struct WebRtc {
+    encoder: BlockingEncoder,
+    audio: Option<AudioTrack>,
+    peer: WebRtcPeer,
+    task: JoinHandle<()>,
+    // the first pts sent and the wall clock time then, for sender reports
+    origin: Option<(Duration, SystemTime)>,
+    last_report: Option<Instant>,
+}
+
+impl std::fmt::Debug for WebRtc {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebRtc")
+            .field("config", self.encoder.config())
+            .field("peer", &self.peer)
+            .finish()
+    }
+}
+
+/// The signaling side of a `WebRtc` sink, which stays usable after the
+/// sink was spawned, e.g. for candidates that trickle in.
+#[derive(Clone)]
+pub struct WebRtcPeer {
+    shared: Arc<Mutex<Peer>>,
+    socket: Arc<UdpSocket>,
+}
+
+impl std::fmt::Debug for WebRtcPeer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let peer = self.shared.lock().unwrap();
+        f.debug_struct("WebRtcPeer")
+            .field("selected", &peer.selected)
+            .field("connected", &peer.srtp.is_some())
+            .finish()
+    }
+}
+
+/// State of the connection, shared by the sink, its peer handles and the
+/// task reading the socket.
+struct Peer {
+    certificate: Certificate,
+    local: Transport,
+    video: Payload,
+    audio: bool,
+    cname: String,
+    // the ICE agent of the offer controls, the tie breaker of a conflict
+    controlling: bool,
+    tiebreaker: u64,
+    ipv4: bool,
+    remote: Option<Transport>,
+    // video, then audio, once negotiated
+    streams: Vec<RtpStream>,
+    dtls: Option<Dtls>,
+    // sending and the peer's reports
+    srtp: Option<Srtp>,
+    srtcp: Option<Srtp>,
+    // remote candidates, the checks on the way and the pair that works
+    candidates: Vec<SocketAddr>,
+    checks: HashMap<[u8; 12], (SocketAddr, Instant)>,
+    selected: Option<SocketAddr>,
+    next_check: Instant,
+    last_seen: Instant,
+    keyframe: bool,
+    closed: Option<String>,
+}
+
+type Datagrams = Vec<(Vec<u8>, SocketAddr)>;
+
+impl WebRtc {
+    /// Binds a UDP socket to `address`, e.g. `"0.0.0.0:0"` for any port,
+    /// encoding with `encode::default_encoder`.
+    pub async fn bind(address: impl ToSocketAddrs, config: EncoderConfig) -> Result<Self> {
+        WebRtc::bind_with_encoder(address, encode::default_encoder(config)?).await
+    }
+
+    pub async fn bind_with_encoder(
+        address: impl ToSocketAddrs,
+        encoder: Box<dyn Encoder>,
+    ) -> Result<Self> {
+        let socket = compat(UdpSocket::bind(address)).await?;
+        let bound = socket.local_addr()?;
+        let certificate = Certificate::generate()?;
+        let local = Transport {
+            ufrag: crypto::random_token(8)?,
+            pwd: crypto::random_token(24)?,
+            fingerprint: certificate.fingerprint().to_string(),
+            setup: "actpass".to_string(),
+            candidates: vec![rtp::reachable_address(bound)],
+        };
+        let now = Instant::now();
+        let peer = Peer {
+            certificate,
+            local,
+            video: Payload::Video(encoder.config().codec),
+            audio: false,
+            cname: format!("xdp-screencast-{}", crypto::random_token(8)?),
+            controlling: false,
+            tiebreaker: fastrand::u64(..),
+            ipv4: bound.is_ipv4(),
+            remote: None,
+            streams: Vec::new(),
+            dtls: None,
+            srtp: None,
+            srtcp: None,
+            candidates: Vec::new(),
+            checks: HashMap::new(),
+            selected: None,
+            next_check: now,
+            last_seen: now,
+            keyframe: true,
+            closed: None,
+        };
+        let peer = WebRtcPeer {
+            shared: Arc::new(Mutex::new(peer)),
+            socket: Arc::new(socket),
+        };
+        let task = runtime::handle().spawn(peer.clone().run());
+        Ok(WebRtc {
+            encoder: BlockingEncoder::new(encoder),
+            audio: None,
+            peer,
+            task,
+            origin: None,
+            last_report: None,
+        })
+    }
+
+    /// Sends an Opus track along, the audio up to each frame. Set it before
+    /// the session is negotiated.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Result<Self> {
+        if audio.config().codec != AudioCodec::Opus {
+            return Err(ScreencastError::Unsupported(format!(
+                "{:?} over WebRTC",
+                audio.config().codec
+            )));
+        }
+        self.peer.shared.lock().unwrap().audio = true;
+        self.audio = Some(audio);
+        Ok(self)
+    }
+
+    /// Offers `address` as a candidate too, e.g. the public address of a
+    /// router forwarding the port of the socket.
+    pub fn with_public_address(self, address: IpAddr) -> Self {
+        {
+            let mut peer = self.peer.shared.lock().unwrap();
+            let port = peer.local.candidates.first().map_or(9, SocketAddr::port);
+            let candidate = SocketAddr::new(address, port);
+            if !peer.local.candidates.contains(&candidate) {
+                peer.local.candidates.push(candidate);
+            }
+        }
+        self
+    }
+
+    pub fn peer(&self) -> WebRtcPeer {
+        self.peer.clone()
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.peer.socket.local_addr()?)
+    }
+
+    async fn send_packets(&mut self, packets: Vec<EncodedPacket>) -> Result<()> {
+        for packet in packets {
+            let audio = match &mut self.audio {
+                Some(audio) => audio.ready(packet.pts)?,
+                None => Vec::new(),
+            };
+            let (origin_pts, origin_wall) =
+                *self.origin.get_or_insert((packet.pts, SystemTime::now()));
+            let report = self
+                .last_report
+                .is_none_or(|last| last.elapsed() >= REPORT_INTERVAL);
+            let (datagrams, to) = {
+                let mut peer = self.peer.shared.lock().unwrap();
+                let mut datagrams = Vec::new();
+                for audio in audio {
+                    datagrams.extend(peer.media(1, &audio.data, audio.pts, true));
+                }
+                datagrams.extend(peer.media(0, &packet.data, packet.pts, packet.keyframe));
+                if report {
+                    let wall = origin_wall + packet.pts.saturating_sub(origin_pts);
+                    datagrams.extend(peer.reports(packet.pts, wall));
+                }
+                (datagrams, peer.selected)
+            };
+            if report {
+                self.last_report = Some(Instant::now());
+            }
+            let Some(to) = to else {
+                continue;
+            };
+            for datagram in datagrams {
+                // a lost packet is for the receiver to cover
+                let _ = self.peer.socket.send_to(&datagram, to).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for WebRtc {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl FrameSink for WebRtc {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let (connected, keyframe) = {
+            let mut peer = self.peer.shared.lock().unwrap();
+            if let Some(reason) = &peer.closed {
+                return Err(ScreencastError::Streaming(reason.clone()));
+            }
+            let connected = peer.srtp.is_some();
+            (connected, connected && std::mem::take(&mut peer.keyframe))
+        };
+        if !connected {
+            // the audio of frames not sent is not sent either
+            if let Some(audio) = &mut self.audio {
+                audio.ready(Duration::MAX)?;
+            }
+            return Ok(());
+        }
+        if keyframe {
+            self.encoder.request_keyframe().await?;
+        }
+        let packets = self.encoder.encode(frame).await?;
+        compat(self.send_packets(packets)).await
+    }
+
+    /// Sends what the encoder held back and closes the DTLS association.
+    async fn finish(&mut self) -> Result<()> {
+        compat(async {
+            let connected = self.peer.shared.lock().unwrap().srtp.is_some();
+            let written = match self.encoder.finish().await {
+                Ok(packets) if connected => self.send_packets(packets).await,
+                Ok(_) => Ok(()),
+                Err(err) => Err(err),
+            };
+            let datagrams = self.peer.shared.lock().unwrap().close();
+            self.peer.send(datagrams).await;
+            self.task.abort();
+            written
+        })
+        .await
+    }
+}
+
+impl WebRtcPeer {
+    /// Takes the offer of a viewer and returns the answer to send back.
+    /// The sections of the offer that take video of the codec of the
+    /// encoder, and Opus audio when there is a track, are answered, the
+    /// others rejected.
+    pub fn accept_offer(&self, offer: &str) -> Result<String> {
+        let offer = SessionDescription::parse(offer)?;
+        let mut peer = self.shared.lock().unwrap();
+        peer.check_unnegotiated()?;
+        let transport = offer.transport()?;
+        let mut sections = Vec::new();
+        let mut streams = Vec::new();
+        for media in &offer.media {
+            let payload = match media.kind.as_str() {
+                "video" => peer.video,
+                "audio" if peer.audio => Payload::Audio(AudioCodec::Opus),
+                _ => {
+                    sections.push((None, Some(media)));
+                    continue;
+                }
+            };
+            let taken = streams
+                .iter()
+                .any(|stream: &RtpStream| stream.payload() == payload);
+            match media.negotiate(payload) {
+                Some(negotiated) if media.receives() && !taken => {
+                    let stream = RtpStream::new(
+                        payload,
+                        negotiated.payload_type,
+                        payload.clock_rate(48_000),
+                    );
+                    sections.push((Some(local_media(&stream, negotiated)), Some(media)));
+                    streams.push(stream);
+                }
+                _ => sections.push((None, Some(media))),
+            }
+        }
+        if !streams.iter().any(|stream| stream.payload() == peer.video) {
+            return Err(ScreencastError::Unsupported(format!(
+                "an offer that takes no {}",
+                peer.video.encoding_name()
+            )));
+        }
+        // the side answering an offer to pick its role is the server
+        let client = transport.setup == "passive";
+        peer.local.setup = if client { "active" } else { "passive" }.to_string();
+        peer.controlling = false;
+        let answer = sdp::write(&peer.local, &sections, &peer.cname);
+        peer.start(transport, streams, client)?;
+        Ok(answer)
+    }
+
+    /// An offer of the video, and of the audio when there is a track, to
+    /// send to the peer, whose answer goes to `accept_answer`.
+    pub fn create_offer(&self) -> Result<String> {
+        let mut peer = self.shared.lock().unwrap();
+        peer.check_unnegotiated()?;
+        let mut streams = vec![RtpStream::new(peer.video, VIDEO_PAYLOAD_TYPE, 90_000)];
+        if peer.audio {
+            let opus = Payload::Audio(AudioCodec::Opus);
+            streams.push(RtpStream::new(opus, AUDIO_PAYLOAD_TYPE, 48_000));
+        }
+        let sections: Vec<_> = streams
+            .iter()
+            .enumerate()
+            .map(|(mid, stream)| {
+                let negotiated = Negotiated {
+                    mid: mid.to_string(),
+                    payload_type: stream.payload_type,
+                    parameters: offer_parameters(stream.payload()).map(str::to_string),
+                };
+                (Some(local_media(stream, negotiated)), None)
+            })
+            .collect();
+        peer.local.setup = "actpass".to_string();
+        peer.controlling = true;
+        let offer = sdp::write(&peer.local, &sections, &peer.cname);
+        peer.streams = streams;
+        Ok(offer)
+    }
+
+    pub fn accept_answer(&self, answer: &str) -> Result<()> {
+        let answer = SessionDescription::parse(answer)?;
+        let mut peer = self.shared.lock().unwrap();
+        if peer.streams.is_empty() || peer.dtls.is_some() {
+            return Err(ScreencastError::Streaming(
+                "an answer without an offer".to_string(),
+            ));
+        }
+        let transport = answer.transport()?;
+        let accepted = |payload: Payload, mid: usize| {
+            answer.media.iter().any(|media: &MediaDescription| {
+                media.mid() == mid.to_string()
+                    && media.port != 0
+                    && media.negotiate(payload).is_some()
+            })
+        };
+        if !accepted(peer.video, 0) {
+            return Err(ScreencastError::Streaming(format!(
+                "the answer rejects {}",
+                peer.video.encoding_name()
+            )));
+        }
+        let mut streams = std::mem::take(&mut peer.streams);
+        if streams.len() > 1 && !accepted(Payload::Audio(AudioCodec::Opus), 1) {
+            streams.truncate(1);
+        }
+        let client = transport.setup != "active";
+        peer.start(transport, streams, client)
+    }
+
+    /// A candidate of the peer that came after its description, the value
+    /// of `a=candidate` with or without the attribute name. Candidates of
+    /// mDNS names are skipped, the peer is found by its checks then.
+    pub fn add_ice_candidate(&self, candidate: &str) -> Result<()> {
+        let Some(address) = sdp::parse_candidate(candidate) else {
+            return Ok(());
+        };
+        let mut peer = self.shared.lock().unwrap();
+        peer.add_candidate(address);
+        Ok(())
+    }
+
+    /// Whether the DTLS handshake finished and media flows.
+    pub fn is_connected(&self) -> bool {
+        self.shared.lock().unwrap().srtp.is_some()
+    }
+
+    /// Reads the socket and keeps the connection alive until the sink
+    /// stops it.
+    async fn run(self) {
+        let mut buffer = vec![0u8; 2048];
+        loop {
+            let received = tokio::time::timeout(TICK, self.socket.recv_from(&mut buffer)).await;
+            let datagrams = {
+                let mut peer = self.shared.lock().unwrap();
+                let mut datagrams = match received {
+                    Ok(Ok((length, from))) => peer.receive(&buffer[..length], from),
+                    _ => Vec::new(),
+                };
+                datagrams.extend(peer.tick());
+                datagrams
+            };
+            self.send(datagrams).await;
+            if let Ok(Err(_)) = received {
+                // an ICMP error of an earlier datagram, not to spin on
+                tokio::time::sleep(TICK).await;
+            }
+        }
+    }
+
+    async fn send(&self, datagrams: Datagrams) {
+        for (datagram, to) in datagrams {
+            let _ = self.socket.send_to(&datagram, to).await;
+        }
+    }
+}
+
+impl Peer {
+    fn check_unnegotiated(&self) -> Result<()> {
+        match self.dtls {
+            Some(_) => Err(ScreencastError::Streaming(
+                "the session was negotiated already".to_string(),
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Starts ICE and DTLS with what was negotiated.
+    fn start(&mut self, transport: Transport, streams: Vec<RtpStream>, client: bool) -> Result<()> {
+        self.dtls = Some(Dtls::new(
+            &self.certificate,
+            client,
+            &transport.fingerprint,
+        )?);
+        for &candidate in &transport.candidates {
+            self.add_candidate(candidate);
+        }
+        self.remote = Some(transport);
+        self.streams = streams;
+        self.next_check = Instant::now();
+        Ok(())
+    }
+
+    fn add_candidate(&mut self, address: SocketAddr) {
+        if address.is_ipv4() == self.ipv4 && !self.candidates.contains(&address) {
+            self.candidates.push(address);
+            self.next_check = Instant::now();
+        }
+    }
+
+    fn receive(&mut self, packet: &[u8], from: SocketAddr) -> Datagrams {
+        match packet.first() {
+            Some(0..=3) if stun::is_stun(packet) => self.receive_stun(packet, from),
+            Some(20..=63) => self.receive_dtls(packet, from),
+            Some(128..=191) if rtp::is_rtcp(packet) => {
+                self.receive_rtcp(packet, from);
+                Vec::new()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    fn receive_stun(&mut self, packet: &[u8], from: SocketAddr) -> Datagrams {
+        let Some(message) = stun::Message::parse(packet) else {
+            return Vec::new();
+        };
+        let mut datagrams = Vec::new();
+        match message.kind {
+            stun::BINDING_REQUEST => {
+                let ours = message
+                    .attribute(stun::USERNAME)
+                    .and_then(|username| std::str::from_utf8(username).ok())
+                    .and_then(|username| username.split(':').next())
+                    .is_some_and(|ufrag| ufrag == self.local.ufrag);
+                if !ours || !stun::check_integrity(packet, self.local.pwd.as_bytes()) {
+                    return datagrams;
+                }
+                let response = stun::Message::new(stun::BINDING_SUCCESS, message.transaction)
+                    .with_address(from)
+                    .encode(Some(self.local.pwd.as_bytes()));
+                datagrams.push((response, from));
+                self.add_candidate(from);
+                if Some(from) == self.selected {
+                    self.last_seen = Instant::now();
+                }
+                if message.has(stun::USE_CANDIDATE) && !self.controlling {
+                    datagrams.extend(self.select(from));
+                } else if self.selected.is_none() && self.remote.is_some() {
+                    // the answer to a check of its own proves the path both ways
+                    match self.check(from) {
+                        Ok(request) => datagrams.push((request, from)),
+                        Err(err) => self.closed = Some(err.to_string()),
+                    }
+                }
+            }
+            stun::BINDING_SUCCESS => {
+                let Some(remote) = &self.remote else {
+                    return datagrams;
+                };
+                if !stun::check_integrity(packet, remote.pwd.as_bytes()) {
+                    return datagrams;
+                }
+                if let Some((address, _)) = self.checks.remove(&message.transaction) {
+                    self.last_seen = Instant::now();
+                    // nominated by the controlling side, taken for now by
+                    // the other until the peer nominates
+                    if self.selected.is_none()
+                        || self.controlling && self.selected != Some(address) && self.srtp.is_none()
+                    {
+                        datagrams.extend(self.select(address));
+                    }
+                }
+            }
+            _ => {}
+        }
+        datagrams
+    }
+
+    fn receive_dtls(&mut self, packet: &[u8], from: SocketAddr) -> Datagrams {
+        let Some(dtls) = &mut self.dtls else {
+            return Vec::new();
+        };
+        if !self.candidates.contains(&from) {
+            return Vec::new();
+        }
+        self.selected.get_or_insert(from);
+        match dtls.receive(packet) {
+            Ok(Received::Nothing) => {}
+            Ok(Received::Connected(keys)) => {
+                let srtp = Srtp::new(&keys.local_key, &keys.local_salt);
+                let srtcp = Srtp::new(&keys.remote_key, &keys.remote_salt);
+                match (srtp, srtcp) {
+                    (Ok(srtp), Ok(srtcp)) => {
+                        self.srtp = Some(srtp);
+                        self.srtcp = Some(srtcp);
+                        self.keyframe = true;
+                        self.last_seen = Instant::now();
+                    }
+                    (Err(err), _) | (_, Err(err)) => self.closed = Some(err.to_string()),
+                }
+            }
+            Ok(Received::Closed) => {
+                self.closed = Some("the peer closed the connection".to_string())
+            }
+            Err(err) => self.closed = Some(err.to_string()),
+        }
+        self.flush_dtls()
+    }
+
+    fn receive_rtcp(&mut self, packet: &[u8], from: SocketAddr) {
+        let Some(srtcp) = &mut self.srtcp else {
+            return;
+        };
+        let Some(packet) = srtcp.unprotect_rtcp(packet) else {
+            return;
+        };
+        if Some(from) == self.selected {
+            self.last_seen = Instant::now();
+        }
+        let video = self.streams.first().map(|stream| stream.ssrc);
+        if rtp::keyframe_requests(&packet)
+            .into_iter()
+            .any(|ssrc| Some(ssrc) == video)
+        {
+            self.keyframe = true;
+        }
+    }
+
+    /// Resends DTLS flights and checks candidates when it is time.
+    fn tick(&mut self) -> Datagrams {
+        let now = Instant::now();
+        let mut datagrams = Vec::new();
+        if let Some(dtls) = &mut self.dtls
+            && !dtls.is_connected()
+        {
+            dtls.handle_timeout();
+            datagrams.extend(self.flush_dtls());
+        }
+        self.checks
+            .retain(|_, (_, sent)| now.duration_since(*sent) < TIMEOUT);
+        if self.remote.is_some() && now >= self.next_check {
+            let targets = match self.selected {
+                Some(selected) => vec![selected],
+                None => self.candidates.clone(),
+            };
+            for target in targets {
+                match self.check(target) {
+                    Ok(request) => datagrams.push((request, target)),
+                    Err(err) => self.closed = Some(err.to_string()),
+                }
+            }
+            self.next_check = now
+                + if self.selected.is_some() {
+                    KEEPALIVE
+                } else {
+                    CHECK_INTERVAL
+                };
+        }
+        if self.selected.is_some()
+            && self.closed.is_none()
+            && now.duration_since(self.last_seen) > TIMEOUT
+        {
+            self.closed = Some("the peer stopped answering".to_string());
+        }
+        datagrams
+    }
+
+    /// A binding request to `target`, which nominates it when controlling.
+    fn check(&mut self, target: SocketAddr) -> Result<Vec<u8>> {
+        let Some(remote) = &self.remote else {
+            return Ok(Vec::new());
+        };
+        // the priority of a peer reflexive candidate of this host
+        let priority: u32 = (110 << 24) | (65_535 << 8) | 255;
+        let mut request = stun::Message::request()?
+            .with(
+                stun::USERNAME,
+                format!("{}:{}", remote.ufrag, self.local.ufrag),
+            )
+            .with(stun::PRIORITY, priority.to_be_bytes());
+        request = match self.controlling {
+            true => request
+                .with(stun::ICE_CONTROLLING, self.tiebreaker.to_be_bytes())
+                .with(stun::USE_CANDIDATE, []),
+            false => request.with(stun::ICE_CONTROLLED, self.tiebreaker.to_be_bytes()),
+        };
+        let packet = request.encode(Some(remote.pwd.as_bytes()));
+        self.checks
+            .insert(request.transaction, (target, Instant::now()));
+        Ok(packet)
+    }
+
+    /// Sends the media to `address` from now on, the DTLS client starts
+    /// the handshake with the first.
+    fn select(&mut self, address: SocketAddr) -> Datagrams {
+        let first = self.selected.is_none();
+        self.selected = Some(address);
+        self.last_seen = Instant::now();
+        if first
+            && let Some(dtls) = &mut self.dtls
+            && let Err(err) = dtls.start()
+        {
+            self.closed = Some(err.to_string());
+        }
+        self.flush_dtls()
+    }
+
+    fn flush_dtls(&mut self) -> Datagrams {
+        let (Some(dtls), Some(to)) = (&mut self.dtls, self.selected) else {
+            return Vec::new();
+        };
+        dtls.outgoing()
+            .into_iter()
+            .map(|datagram| (datagram, to))
+            .collect()
+    }
+
+    /// The SRTP packets of a frame or audio packet of the stream at `index`.
+    fn media(&mut self, index: usize, data: &[u8], pts: Duration, keyframe: bool) -> Vec<Vec<u8>> {
+        let (Some(stream), Some(srtp)) = (self.streams.get_mut(index), &mut self.srtp) else {
+            return Vec::new();
+        };
+        stream
+            .packetize(data, pts, keyframe)
+            .iter()
+            .filter_map(|packet| srtp.protect_rtp(packet))
+            .collect()
+    }
+
+    /// A sender report for each stream, `pts` taken at `wall`.
+    fn reports(&mut self, pts: Duration, wall: SystemTime) -> Vec<Vec<u8>> {
+        let Some(srtp) = &mut self.srtp else {
+            return Vec::new();
+        };
+        self.streams
+            .iter()
+            .filter_map(|stream| stream.sender_report(pts, wall, &self.cname))
+            .filter_map(|report| srtp.protect_rtcp(&report))
+            .collect()
+    }
+
+    /// A close_notify to the peer.
+    fn close(&mut self) -> Datagrams {
+        if let Some(dtls) = &mut self.dtls {
+            dtls.close();
+        }
+        self.flush_dtls()
+    }
+}
+
+fn local_media(stream: &RtpStream, negotiated: Negotiated) -> LocalMedia {
+    LocalMedia {
+        payload: stream.payload(),
+        negotiated,
+        ssrc: stream.ssrc,
+    }
+}
+
+/// The `a=fmtp` of an offer, those of the encodings browsers send.
+fn offer_parameters(payload: Payload) -> Option<&'static str> {
+    match payload {
+        Payload::Video(encode::Codec::H264) => {
+            Some("level-asymmetry-allowed=1;packetization-mode=1;profile-level-id=42e01f")
+        }
+        Payload::Video(encode::Codec::Vp9) => Some("profile-id=0"),
+        Payload::Audio(AudioCodec::Opus) => Some("minptime=10;useinbandfec=1"),
+        _ => None,
+    }
+}
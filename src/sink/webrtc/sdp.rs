@@ -0,0 +1,314 @@
+use crate::error::{Result, ScreencastError};
+use crate::sink::rtp::Payload;
+use std::fmt::Write;
+use std::net::{IpAddr, SocketAddr};
+
+/// A session description as far as a sending peer cares: the transport of
+/// the peer and its media sections.
+#[derive(Debug, Clone, Default)]
+pub(super) struct SessionDescription {
+    attributes: Vec<(String, String)>,
+    pub media: Vec<MediaDescription>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(super) struct MediaDescription {
+    pub kind: String,
+    pub port: u16,
+    pub protocol: String,
+    pub formats: Vec<String>,
+    attributes: Vec<(String, String)>,
+}
+
+/// What both sides agreed on for a media section.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Negotiated {
+    pub mid: String,
+    pub payload_type: u8,
+    /// The `a=fmtp` parameters to repeat in an answer.
+    pub parameters: Option<String>,
+}
+
+/// The ICE credentials and DTLS identity of a peer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Transport {
+    pub ufrag: String,
+    pub pwd: String,
+    pub fingerprint: String,
+    pub setup: String,
+    pub candidates: Vec<SocketAddr>,
+}
+
+/// A sending media section of a description this side writes.
+#[derive(Debug, Clone)]
+pub(super) struct LocalMedia {
+    pub payload: Payload,
+    pub negotiated: Negotiated,
+    pub ssrc: u32,
+}
+
+impl SessionDescription {
+    pub(super) fn parse(sdp: &str) -> Result<Self> {
+        let mut description = SessionDescription::default();
+        for line in sdp.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            let Some((kind, value)) = line.split_once('=') else {
+                continue;
+            };
+            match kind {
+                "m" => {
+                    let mut fields = value.split_whitespace();
+                    let kind = fields.next().unwrap_or_default().to_string();
+                    let port = fields
+                        .next()
+                        .and_then(|port| port.split('/').next()?.parse().ok())
+                        .unwrap_or(0);
+                    let protocol = fields.next().unwrap_or_default().to_string();
+                    description.media.push(MediaDescription {
+                        kind,
+                        port,
+                        protocol,
+                        formats: fields.map(str::to_string).collect(),
+                        attributes: Vec::new(),
+                    });
+                }
+                "a" => {
+                    let (name, value) = value.split_once(':').unwrap_or((value, ""));
+                    let attribute = (name.to_string(), value.to_string());
+                    match description.media.last_mut() {
+                        Some(media) => media.attributes.push(attribute),
+                        None => description.attributes.push(attribute),
+                    }
+                }
+                _ => {}
+            }
+        }
+        if description.media.is_empty() {
+            return Err(ScreencastError::Streaming(
+                "session description without media".to_string(),
+            ));
+        }
+        Ok(description)
+    }
+
+    /// An attribute of the first media section, or of the session.
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.media
+            .first()
+            .and_then(|media| media.attribute(name))
+            .or_else(|| find(&self.attributes, name))
+    }
+
+    pub(super) fn transport(&self) -> Result<Transport> {
+        let missing = |name: &str| {
+            ScreencastError::Streaming(format!("session description without {}", name))
+        };
+        let candidates = self
+            .media
+            .iter()
+            .flat_map(|media| media.attributes_named("candidate"))
+            .filter_map(parse_candidate)
+            .collect();
+        Ok(Transport {
+            ufrag: self
+                .attribute("ice-ufrag")
+                .ok_or_else(|| missing("ice-ufrag"))?
+                .to_string(),
+            pwd: self
+                .attribute("ice-pwd")
+                .ok_or_else(|| missing("ice-pwd"))?
+                .to_string(),
+            fingerprint: self
+                .attribute("fingerprint")
+                .ok_or_else(|| missing("a fingerprint"))?
+                .to_string(),
+            setup: self.attribute("setup").unwrap_or("actpass").to_string(),
+            candidates,
+        })
+    }
+}
+
+impl MediaDescription {
+    pub(super) fn attribute(&self, name: &str) -> Option<&str> {
+        find(&self.attributes, name)
+    }
+
+    fn attributes_named<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        self.attributes
+            .iter()
+            .filter(move |(attribute, _)| attribute == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(super) fn mid(&self) -> String {
+        self.attribute("mid").unwrap_or_default().to_string()
+    }
+
+    /// Whether the peer takes media in this section.
+    pub(super) fn receives(&self) -> bool {
+        self.port != 0
+            && !self
+                .attributes
+                .iter()
+                .any(|(name, _)| name == "sendonly" || name == "inactive")
+    }
+
+    /// The first payload type the section offers for `payload`, H.264 only
+    /// in the non-interleaved mode and VP9 in profile 0.
+    pub(super) fn negotiate(&self, payload: Payload) -> Option<Negotiated> {
+        let wanted = payload.encoding_name();
+        self.formats.iter().find_map(|format| {
+            let payload_type: u8 = format.parse().ok()?;
+            let rtpmap = self
+                .attributes_named("rtpmap")
+                .find_map(|value| value.strip_prefix(&format!("{} ", format)))?;
+            let name = rtpmap.split('/').next()?;
+            if !name.eq_ignore_ascii_case(wanted) {
+                return None;
+            }
+            let parameters = self
+                .attributes_named("fmtp")
+                .find_map(|value| value.strip_prefix(&format!("{} ", format)))
+                .map(str::to_string);
+            let parameter = |key: &str| {
+                parameters.as_deref().and_then(|parameters| {
+                    parameters.split(';').find_map(|pair| {
+                        let (name, value) = pair.trim().split_once('=')?;
+                        (name == key).then_some(value)
+                    })
+                })
+            };
+            let usable = match wanted {
+                "H264" => parameter("packetization-mode") == Some("1"),
+                "VP9" => parameter("profile-id").unwrap_or("0") == "0",
+                _ => true,
+            };
+            usable.then_some(Negotiated {
+                mid: self.mid(),
+                payload_type,
+                parameters,
+            })
+        })
+    }
+}
+
+fn find<'a>(attributes: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    attributes
+        .iter()
+        .find(|(attribute, _)| attribute == name)
+        .map(|(_, value)| value.as_str())
+}
+
+/// The address of a UDP candidate for the first component, those of mDNS
+/// names and TCP are of no use.
+pub(super) fn parse_candidate(candidate: &str) -> Option<SocketAddr> {
+    let candidate = candidate.trim();
+    let candidate = candidate.strip_prefix("a=").unwrap_or(candidate);
+    let candidate = candidate.strip_prefix("candidate:").unwrap_or(candidate);
+    let fields: Vec<&str> = candidate.split_whitespace().collect();
+    let [_, component, transport, _, address, port, ..] = fields.as_slice() else {
+        return None;
+    };
+    if *component != "1" || !transport.eq_ignore_ascii_case("udp") {
+        return None;
+    }
+    let ip: IpAddr = address.parse().ok()?;
+    Some(SocketAddr::new(ip, port.parse().ok()?))
+}
+
+/// The description of this side, an offer or an answer: one section for
+/// each of `sections`, those that are `None` rejected with their kind and
+/// protocol from `remote`.
+pub(super) fn write(
+    transport: &Transport,
+    sections: &[(Option<LocalMedia>, Option<&MediaDescription>)],
+    cname: &str,
+) -> String {
+    let mut sdp = String::new();
+    let session = fastrand::u64(..i64::MAX as u64);
+    let bundle: Vec<&str> = sections
+        .iter()
+        .filter_map(|(local, _)| local.as_ref().map(|local| local.negotiated.mid.as_str()))
+        .collect();
+    let _ = write!(
+        sdp,
+        "v=0\r\no=- {} 2 IN IP4 127.0.0.1\r\ns=-\r\nt=0 0\r\n",
+        session
+    );
+    let _ = write!(sdp, "a=group:BUNDLE {}\r\n", bundle.join(" "));
+    let _ = write!(sdp, "a=msid-semantic: WMS {}\r\n", cname);
+    for (local, remote) in sections {
+        let Some(local) = local else {
+            let (kind, protocol, format, mid) = match remote {
+                Some(remote) => (
+                    remote.kind.as_str(),
+                    remote.protocol.as_str(),
+                    remote.formats.first().map_or("0", String::as_str),
+                    remote.mid(),
+                ),
+                None => continue,
+            };
+            let _ = write!(
+                sdp,
+                "m={} 0 {} {}\r\nc=IN IP4 0.0.0.0\r\na=mid:{}\r\na=inactive\r\n",
+                kind, protocol, format, mid
+            );
+            continue;
+        };
+        let Negotiated {
+            mid,
+            payload_type,
+            parameters,
+        } = &local.negotiated;
+        let (kind, rate) = match local.payload {
+            Payload::Video(_) => ("video", "90000".to_string()),
+            Payload::Audio(_) => ("audio", "48000/2".to_string()),
+        };
+        let _ = write!(
+            sdp,
+            "m={} 9 UDP/TLS/RTP/SAVPF {}\r\nc=IN IP4 0.0.0.0\r\na=rtcp:9 IN IP4 0.0.0.0\r\n",
+            kind, payload_type
+        );
+        let _ = write!(
+            sdp,
+            "a=ice-ufrag:{}\r\na=ice-pwd:{}\r\na=fingerprint:{}\r\na=setup:{}\r\n",
+            transport.ufrag, transport.pwd, transport.fingerprint, transport.setup
+        );
+        let _ = write!(sdp, "a=mid:{}\r\na=sendonly\r\na=rtcp-mux\r\n", mid);
+        let _ = write!(
+            sdp,
+            "a=rtpmap:{} {}/{}\r\n",
+            payload_type,
+            local.payload.encoding_name(),
+            rate
+        );
+        if let Payload::Video(_) = local.payload {
+            let _ = write!(
+                sdp,
+                "a=rtcp-fb:{0} nack pli\r\na=rtcp-fb:{0} ccm fir\r\n",
+                payload_type
+            );
+        }
+        if let Some(parameters) = parameters {
+            let _ = write!(sdp, "a=fmtp:{} {}\r\n", payload_type, parameters);
+        }
+        let _ = write!(
+            sdp,
+            "a=msid:{0} {0}-{1}\r\na=ssrc:{2} cname:{0}\r\na=ssrc:{2} msid:{0} {0}-{1}\r\n",
+            cname, kind, local.ssrc
+        );
+        for (index, address) in transport.candidates.iter().enumerate() {
+            // host candidates, the first preferred
+            let priority = (126u32 << 24) | ((65_535 - index as u32) << 8) | 255;
+            let _ = write!(
+                sdp,
+                "a=candidate:{} 1 udp {} {} {} typ host\r\n",
+                index + 1,
+                priority,
+                address.ip(),
+                address.port()
+            );
+        }
+        sdp.push_str("a=end-of-candidates\r\n");
+    }
+    sdp
+}
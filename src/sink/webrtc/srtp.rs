@@ -0,0 +1,337 @@
+use super::crypto::{self, AesCtr};
+use crate::error::Result;
+use std::collections::HashMap;
+
+const TAG: usize = 10;
+/// SRTCP indexes accepted behind the highest one received.
+const REPLAY_WINDOW: u32 = 64;
+
+const LABEL_RTP_ENCRYPTION: u8 = 0;
+const LABEL_RTP_AUTH: u8 = 1;
+const LABEL_RTP_SALT: u8 = 2;
+const LABEL_RTCP_ENCRYPTION: u8 = 3;
+const LABEL_RTCP_AUTH: u8 = 4;
+const LABEL_RTCP_SALT: u8 = 5;
+
+/// SRTP and SRTCP of one direction in the AES_CM_128_HMAC_SHA1_80 profile,
+/// RFC 3711, with the session keys derived from a master key and salt.
+pub(super) struct Srtp {
+    rtp: SessionKeys,
+    rtcp: SessionKeys,
+    // rollover counter and highest sequence number by SSRC
+    rollover: HashMap<u32, (u32, u16)>,
+    rtcp_index: u32,
+    // the SRTCP indexes received by SSRC
+    replay: HashMap<u32, Replay>,
+}
+
+/// The highest index received and a bit for each of the window before it,
+/// set for those received, RFC 3711 section 3.3.2.
+#[derive(Debug, Clone, Copy)]
+struct Replay {
+    highest: u32,
+    seen: u64,
+}
+
+struct SessionKeys {
+    aes: AesCtr,
+    auth: [u8; 20],
+    salt: [u8; 14],
+}
+
+impl std::fmt::Debug for Srtp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Srtp")
+            .field("rtcp_index", &self.rtcp_index)
+            .finish()
+    }
+}
+
+impl Srtp {
+    pub(super) fn new(master_key: &[u8; 16], master_salt: &[u8; 14]) -> Result<Self> {
+        let mut master = AesCtr::new(master_key)?;
+        let mut derive = |label: u8, len: usize| derive(&mut master, master_salt, label, len);
+        let session = |encryption: Vec<u8>, auth: Vec<u8>, salt: Vec<u8>| -> Result<SessionKeys> {
+            Ok(SessionKeys {
+                aes: AesCtr::new(&encryption.try_into().unwrap())?,
+                auth: auth.try_into().unwrap(),
+                salt: salt.try_into().unwrap(),
+            })
+        };
+        let rtp = session(
+            derive(LABEL_RTP_ENCRYPTION, 16),
+            derive(LABEL_RTP_AUTH, 20),
+            derive(LABEL_RTP_SALT, 14),
+        )?;
+        let rtcp = session(
+            derive(LABEL_RTCP_ENCRYPTION, 16),
+            derive(LABEL_RTCP_AUTH, 20),
+            derive(LABEL_RTCP_SALT, 14),
+        )?;
+        Ok(Srtp {
+            rtp,
+            rtcp,
+            rollover: HashMap::new(),
+            rtcp_index: 0,
+            replay: HashMap::new(),
+        })
+    }
+
+    /// Encrypts the payload of an RTP packet and appends the tag, `None`
+    /// for a packet too short for its header, which is not sent at all.
+    pub(super) fn protect_rtp(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let header = rtp_header_len(packet)?;
+        let ssrc = u32::from_be_bytes(packet[8..12].try_into().unwrap());
+        let sequence = u16::from_be_bytes([packet[2], packet[3]]);
+        let (rollover, highest) = self.rollover.entry(ssrc).or_insert((0, sequence));
+        // the sequence numbers of a sender wrap in order
+        if sequence < *highest && *highest - sequence > 0x8000 {
+            *rollover += 1;
+        }
+        if sequence > *highest || *highest - sequence > 0x8000 {
+            *highest = sequence;
+        }
+        let index = (*rollover as u64) << 16 | sequence as u64;
+        let rollover = *rollover;
+
+        let mut protected = packet.to_vec();
+        let iv = iv(&self.rtp.salt, ssrc, index);
+        self.rtp.aes.apply(&iv, &mut protected[header..]);
+        let tag = crypto::hmac_sha1(&self.rtp.auth, &[&protected, &rollover.to_be_bytes()]);
+        protected.extend_from_slice(&tag[..TAG]);
+        Some(protected)
+    }
+
+    /// Encrypts a compound RTCP packet after the SSRC of its sender, and
+    /// appends the index and the tag, `None` for a packet too short.
+    pub(super) fn protect_rtcp(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        if packet.len() < 8 {
+            return None;
+        }
+        let ssrc = u32::from_be_bytes(packet[4..8].try_into().unwrap());
+        let index = self.rtcp_index;
+        self.rtcp_index = (self.rtcp_index + 1) & 0x7fff_ffff;
+        let mut protected = packet.to_vec();
+        let iv = iv(&self.rtcp.salt, ssrc, index as u64);
+        self.rtcp.aes.apply(&iv, &mut protected[8..]);
+        // E, the packet is encrypted
+        protected.extend((0x8000_0000 | index).to_be_bytes());
+        let tag = crypto::hmac_sha1(&self.rtcp.auth, &[&protected]);
+        protected.extend_from_slice(&tag[..TAG]);
+        Some(protected)
+    }
+
+    /// Checks the tag of an SRTCP packet of the peer and decrypts it,
+    /// `None` when it was forged, damaged or received before.
+    pub(super) fn unprotect_rtcp(&mut self, packet: &[u8]) -> Option<Vec<u8>> {
+        let end = packet.len().checked_sub(TAG + 4)?;
+        if end < 8 {
+            return None;
+        }
+        let (authenticated, tag) = packet.split_at(packet.len() - TAG);
+        let expected = crypto::hmac_sha1(&self.rtcp.auth, &[authenticated]);
+        if !crypto::equal(&expected[..TAG], tag) {
+            return None;
+        }
+        let ssrc = u32::from_be_bytes(packet[4..8].try_into().unwrap());
+        let index = u32::from_be_bytes(packet[end..end + 4].try_into().unwrap());
+        let replay = self.replay.get(&ssrc).copied();
+        let replay = Replay::accept(replay, index & 0x7fff_ffff)?;
+        let mut plain = packet[..end].to_vec();
+        if index & 0x8000_0000 != 0 {
+            let iv = iv(&self.rtcp.salt, ssrc, (index & 0x7fff_ffff) as u64);
+            self.rtcp.aes.apply(&iv, &mut plain[8..]);
+        }
+        self.replay.insert(ssrc, replay);
+        Some(plain)
+    }
+}
+
+impl Replay {
+    /// The window with `index` received, `None` for an index received
+    /// before or too far behind to tell.
+    fn accept(replay: Option<Replay>, index: u32) -> Option<Replay> {
+        let Some(Replay { highest, seen }) = replay else {
+            return Some(Replay {
+                highest: index,
+                seen: 1,
+            });
+        };
+        if index > highest {
+            let shift = index - highest;
+            let seen = if shift < REPLAY_WINDOW {
+                seen << shift | 1
+            } else {
+                1
+            };
+            return Some(Replay {
+                highest: index,
+                seen,
+            });
+        }
+        let behind = highest - index;
+        if behind >= REPLAY_WINDOW || seen & 1 << behind != 0 {
+            return None;
+        }
+        Some(Replay {
+            highest,
+            seen: seen | 1 << behind,
+        })
+    }
+}
+
+/// A session key of `len` bytes for `label`, with a key derivation rate
+/// of 0.
+fn derive(master: &mut AesCtr, master_salt: &[u8; 14], label: u8, len: usize) -> Vec<u8> {
+    let mut iv = [0u8; 16];
+    iv[..14].copy_from_slice(master_salt);
+    iv[7] ^= label;
+    let mut key = vec![0u8; len];
+    master.apply(&iv, &mut key);
+    key
+}
+
+/// The counter of a packet: the session salt, the SSRC and the index.
+fn iv(salt: &[u8; 14], ssrc: u32, index: u64) -> [u8; 16] {
+    let mut iv = [0u8; 16];
+    iv[..14].copy_from_slice(salt);
+    for (byte, ssrc) in iv[4..8].iter_mut().zip(ssrc.to_be_bytes()) {
+        *byte ^= ssrc;
+    }
+    for (byte, index) in iv[8..14].iter_mut().zip(&index.to_be_bytes()[2..]) {
+        *byte ^= index;
+    }
+    iv
+}
+
+/// The fixed header, the CSRCs and the extension of an RTP packet.
+fn rtp_header_len(packet: &[u8]) -> Option<usize> {
+    let csrcs = (*packet.first()? & 0x0f) as usize;
+    let mut length = 12 + 4 * csrcs;
+    if packet[0] & 0x10 != 0 {
+        let words = packet.get(length + 2..length + 4)?;
+        length += 4 + 4 * u16::from_be_bytes([words[0], words[1]]) as usize;
+    }
+    (length <= packet.len()).then_some(length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(text: &str) -> Vec<u8> {
+        (0..text.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+            .collect()
+    }
+
+    const MASTER_KEY: &str = "E1F97A0D3E018BE0D64FA32C06DE4139";
+    const MASTER_SALT: &str = "0EC675AD498AFEEBB6960B3AABE6";
+
+    fn pair() -> (Srtp, Srtp) {
+        let key = hex(MASTER_KEY).try_into().unwrap();
+        let salt = hex(MASTER_SALT).try_into().unwrap();
+        (
+            Srtp::new(&key, &salt).unwrap(),
+            Srtp::new(&key, &salt).unwrap(),
+        )
+    }
+
+    // RFC 3711, appendix B.2
+    #[test]
+    fn aes_counter_mode() {
+        let mut aes =
+            AesCtr::new(&hex("2B7E151628AED2A6ABF7158809CF4F3C").try_into().unwrap()).unwrap();
+        let iv = hex("F0F1F2F3F4F5F6F7F8F9FAFBFCFD0000").try_into().unwrap();
+        let mut stream = [0u8; 48];
+        aes.apply(&iv, &mut stream);
+        assert_eq!(
+            stream.to_vec(),
+            hex(concat!(
+                "E03EAD0935C95E80E166B16DD92B4EB4",
+                "D23513162B02D0F72A43A2FE4A5F97AB",
+                "41E95B3BB0A2E8DD477901E4FCA894C0"
+            ))
+        );
+    }
+
+    // RFC 3711, appendix B.3
+    #[test]
+    fn key_derivation() {
+        let mut master = AesCtr::new(&hex(MASTER_KEY).try_into().unwrap()).unwrap();
+        let salt = hex(MASTER_SALT).try_into().unwrap();
+        assert_eq!(
+            derive(&mut master, &salt, LABEL_RTP_ENCRYPTION, 16),
+            hex("C61E7A93744F39EE10734AFE3FF7A087")
+        );
+        assert_eq!(
+            derive(&mut master, &salt, LABEL_RTP_SALT, 14),
+            hex("30CBBC08863D8C85D49DB34A9AE1")
+        );
+        assert_eq!(
+            derive(&mut master, &salt, LABEL_RTP_AUTH, 20),
+            hex("CEBE321F6FF7716B6FD4AB49AF256A156D38BAA4")
+        );
+    }
+
+    #[test]
+    fn rtp_round_trip() {
+        let (mut sender, mut receiver) = pair();
+        let mut packet = vec![
+            0x80, 96, 0xff, 0xff, 0, 0, 0x0b, 0xb8, 0x12, 0x34, 0x56, 0x78,
+        ];
+        packet.extend(b"a frame of the stream");
+        let first = sender.protect_rtp(&packet).unwrap();
+        // the sequence number wraps, the rollover counter goes up
+        packet[2..4].copy_from_slice(&[0, 0]);
+        let second = sender.protect_rtp(&packet).unwrap();
+
+        for (protected, rollover) in [(first, 0u32), (second, 1)] {
+            assert_eq!(protected.len(), packet.len() + TAG);
+            let (encrypted, tag) = protected.split_at(protected.len() - TAG);
+            assert_ne!(encrypted[12..], packet[12..]);
+            let expected =
+                crypto::hmac_sha1(&receiver.rtp.auth, &[encrypted, &rollover.to_be_bytes()]);
+            assert_eq!(expected[..TAG], *tag);
+            let sequence = u16::from_be_bytes([encrypted[2], encrypted[3]]);
+            let index = (rollover as u64) << 16 | sequence as u64;
+            let mut plain = encrypted.to_vec();
+            let iv = iv(&receiver.rtp.salt, 0x1234_5678, index);
+            receiver.rtp.aes.apply(&iv, &mut plain[12..]);
+            assert_eq!(plain[12..], packet[12..]);
+        }
+        assert_eq!(sender.protect_rtp(&packet[..11]), None);
+    }
+
+    #[test]
+    fn rtcp_round_trip() {
+        let (mut sender, mut receiver) = pair();
+        let mut report = vec![0x80, 200, 0, 6, 0x12, 0x34, 0x56, 0x78];
+        report.extend([7u8; 20]);
+        let first = sender.protect_rtcp(&report).unwrap();
+        let second = sender.protect_rtcp(&report).unwrap();
+        assert_ne!(first, second);
+        assert_eq!(receiver.unprotect_rtcp(&second), Some(report.clone()));
+        // older ones within the window are still taken, once
+        assert_eq!(receiver.unprotect_rtcp(&first), Some(report.clone()));
+        assert_eq!(receiver.unprotect_rtcp(&first), None);
+        assert_eq!(receiver.unprotect_rtcp(&second), None);
+
+        let mut forged = sender.protect_rtcp(&report).unwrap();
+        forged[10] ^= 1;
+        assert_eq!(receiver.unprotect_rtcp(&forged), None);
+        assert_eq!(sender.protect_rtcp(&report[..7]), None);
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut replay = Replay::accept(None, 100).unwrap();
+        assert!(Replay::accept(Some(replay), 100).is_none());
+        replay = Replay::accept(Some(replay), 40).unwrap();
+        assert!(Replay::accept(Some(replay), 36).is_none());
+        replay = Replay::accept(Some(replay), 300).unwrap();
+        assert!(Replay::accept(Some(replay), 100).is_none());
+        assert!(Replay::accept(Some(replay), 299).is_some());
+    }
+}
@@ -0,0 +1,277 @@
+use super::crypto;
+use crate::error::Result;
+use crate::image;
+use std::net::{IpAddr, SocketAddr};
+
+pub(super) const BINDING_REQUEST: u16 = 0x0001;
+pub(super) const BINDING_SUCCESS: u16 = 0x0101;
+
+pub(super) const USERNAME: u16 = 0x0006;
+pub(super) const MESSAGE_INTEGRITY: u16 = 0x0008;
+pub(super) const XOR_MAPPED_ADDRESS: u16 = 0x0020;
+pub(super) const PRIORITY: u16 = 0x0024;
+pub(super) const USE_CANDIDATE: u16 = 0x0025;
+pub(super) const FINGERPRINT: u16 = 0x8028;
+pub(super) const ICE_CONTROLLED: u16 = 0x8029;
+pub(super) const ICE_CONTROLLING: u16 = 0x802a;
+
+const MAGIC_COOKIE: u32 = 0x2112_a442;
+const FINGERPRINT_XOR: u32 = 0x5354_554e;
+const HEADER: usize = 20;
+
+/// A STUN message as ICE exchanges them, RFC 5389.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Message {
+    pub kind: u16,
+    pub transaction: [u8; 12],
+    pub attributes: Vec<(u16, Vec<u8>)>,
+}
+
+/// Whether a datagram is STUN rather than DTLS or SRTP, RFC 7983.
+pub(super) fn is_stun(packet: &[u8]) -> bool {
+    packet.len() >= HEADER
+        && packet[0] < 4
+        && u32::from_be_bytes(packet[4..8].try_into().unwrap()) == MAGIC_COOKIE
+}
+
+impl Message {
+    pub(super) fn new(kind: u16, transaction: [u8; 12]) -> Self {
+        Message {
+            kind,
+            transaction,
+            attributes: Vec::new(),
+        }
+    }
+
+    /// A request with a new transaction id.
+    pub(super) fn request() -> Result<Self> {
+        let transaction = crypto::random_bytes(12)?.try_into().unwrap();
+        Ok(Message::new(BINDING_REQUEST, transaction))
+    }
+
+    pub(super) fn with(mut self, kind: u16, value: impl Into<Vec<u8>>) -> Self {
+        self.attributes.push((kind, value.into()));
+        self
+    }
+
+    pub(super) fn with_address(self, address: SocketAddr) -> Self {
+        let transaction = self.transaction;
+        self.with(XOR_MAPPED_ADDRESS, xor_address(address, &transaction))
+    }
+
+    pub(super) fn parse(packet: &[u8]) -> Option<Self> {
+        if !is_stun(packet) {
+            return None;
+        }
+        let length = u16::from_be_bytes([packet[2], packet[3]]) as usize;
+        let body = packet.get(HEADER..HEADER + length)?;
+        let mut attributes = Vec::new();
+        let mut rest = body;
+        while rest.len() >= 4 {
+            let kind = u16::from_be_bytes([rest[0], rest[1]]);
+            let length = u16::from_be_bytes([rest[2], rest[3]]) as usize;
+            let value = rest.get(4..4 + length)?;
+            attributes.push((kind, value.to_vec()));
+            rest = rest.get(4 + padded(length)..).unwrap_or_default();
+        }
+        Some(Message {
+            kind: u16::from_be_bytes([packet[0], packet[1]]),
+            transaction: packet[8..20].try_into().unwrap(),
+            attributes,
+        })
+    }
+
+    pub(super) fn attribute(&self, kind: u16) -> Option<&[u8]> {
+        self.attributes
+            .iter()
+            .find(|(attribute, _)| *attribute == kind)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    pub(super) fn has(&self, kind: u16) -> bool {
+        self.attribute(kind).is_some()
+    }
+
+    /// The message with MESSAGE-INTEGRITY keyed with `key`, if any, and
+    /// FINGERPRINT last.
+    pub(super) fn encode(&self, key: Option<&[u8]>) -> Vec<u8> {
+        let mut packet = Vec::with_capacity(128);
+        packet.extend(self.kind.to_be_bytes());
+        packet.extend([0, 0]);
+        packet.extend(MAGIC_COOKIE.to_be_bytes());
+        packet.extend(self.transaction);
+        for (kind, value) in &self.attributes {
+            write_attribute(&mut packet, *kind, value);
+        }
+        // both cover the message with its length up to and including them
+        if let Some(key) = key {
+            set_length(&mut packet, 24);
+            let integrity = crypto::hmac_sha1(key, &[&packet]);
+            write_attribute(&mut packet, MESSAGE_INTEGRITY, &integrity);
+        }
+        set_length(&mut packet, 8);
+        let crc = image::crc32(&packet) ^ FINGERPRINT_XOR;
+        write_attribute(&mut packet, FINGERPRINT, &crc.to_be_bytes());
+        packet
+    }
+}
+
+/// Whether the MESSAGE-INTEGRITY of `packet` was keyed with `key`.
+pub(super) fn check_integrity(packet: &[u8], key: &[u8]) -> bool {
+    let Some(length) = packet.get(2..4) else {
+        return false;
+    };
+    let end = (HEADER + u16::from_be_bytes([length[0], length[1]]) as usize).min(packet.len());
+    let mut offset = HEADER;
+    while offset + 4 <= end {
+        let kind = u16::from_be_bytes([packet[offset], packet[offset + 1]]);
+        let length = u16::from_be_bytes([packet[offset + 2], packet[offset + 3]]) as usize;
+        if kind == MESSAGE_INTEGRITY {
+            let Some(expected) = packet.get(offset + 4..offset + 24) else {
+                return false;
+            };
+            let mut covered = packet[..offset].to_vec();
+            covered[2..4].copy_from_slice(&((offset + 24 - HEADER) as u16).to_be_bytes());
+            return crypto::equal(&crypto::hmac_sha1(key, &[&covered]), expected);
+        }
+        offset += 4 + padded(length);
+    }
+    false
+}
+
+fn padded(length: usize) -> usize {
+    length.div_ceil(4) * 4
+}
+
+fn write_attribute(packet: &mut Vec<u8>, kind: u16, value: &[u8]) {
+    packet.extend(kind.to_be_bytes());
+    packet.extend((value.len() as u16).to_be_bytes());
+    packet.extend_from_slice(value);
+    packet.resize(packet.len() + padded(value.len()) - value.len(), 0);
+}
+
+/// Sets the length of the header to the attributes so far and `more`.
+fn set_length(packet: &mut [u8], more: usize) {
+    let length = (packet.len() - HEADER + more) as u16;
+    packet[2..4].copy_from_slice(&length.to_be_bytes());
+}
+
+fn xor_address(address: SocketAddr, transaction: &[u8; 12]) -> Vec<u8> {
+    let mut value = vec![0];
+    let port = address.port() ^ (MAGIC_COOKIE >> 16) as u16;
+    match address.ip() {
+        IpAddr::V4(ip) => {
+            value.push(1);
+            value.extend(port.to_be_bytes());
+            value.extend((u32::from(ip) ^ MAGIC_COOKIE).to_be_bytes());
+        }
+        IpAddr::V6(ip) => {
+            value.push(2);
+            value.extend(port.to_be_bytes());
+            let mut mask = MAGIC_COOKIE.to_be_bytes().to_vec();
+            mask.extend(transaction);
+            value.extend(ip.octets().iter().zip(mask).map(|(byte, mask)| byte ^ mask));
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRANSACTION: [u8; 12] = [
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+    ];
+
+    /// The sample IPv4 response of RFC 5769, its SOFTWARE padded with a
+    /// space.
+    #[rustfmt::skip]
+    const RESPONSE: [u8; 80] = [
+        0x01, 0x01, 0x00, 0x3c, 0x21, 0x12, 0xa4, 0x42,
+        0xb7, 0xe7, 0xa7, 0x01, 0xbc, 0x34, 0xd6, 0x86, 0xfa, 0x87, 0xdf, 0xae,
+        0x80, 0x22, 0x00, 0x0b, 0x74, 0x65, 0x73, 0x74, 0x20, 0x76, 0x65, 0x63,
+        0x74, 0x6f, 0x72, 0x20,
+        0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0xa1, 0x47, 0xe1, 0x12, 0xa6, 0x43,
+        0x00, 0x08, 0x00, 0x14, 0x2b, 0x91, 0xf5, 0x99, 0xfd, 0x9e, 0x90, 0xc3,
+        0x8c, 0x74, 0x89, 0xf9, 0x2a, 0xf9, 0xba, 0x53, 0xf0, 0x6b, 0xe7, 0xd7,
+        0x80, 0x28, 0x00, 0x04, 0xc0, 0x7d, 0x4c, 0x96,
+    ];
+
+    const PASSWORD: &[u8] = b"VOkJxbRl1RmTxUk/WvJxBt";
+
+    fn fingerprint(packet: &[u8]) -> [u8; 4] {
+        (image::crc32(&packet[..packet.len() - 8]) ^ FINGERPRINT_XOR).to_be_bytes()
+    }
+
+    #[test]
+    fn sample() {
+        let message = Message::parse(&RESPONSE).unwrap();
+        assert_eq!(message.kind, BINDING_SUCCESS);
+        assert_eq!(message.transaction, TRANSACTION);
+        assert_eq!(message.attribute(0x8022), Some(b"test vector".as_slice()));
+        let address = "192.0.2.1:32853".parse().unwrap();
+        let expected = Message::new(BINDING_SUCCESS, TRANSACTION).with_address(address);
+        assert_eq!(
+            message.attribute(XOR_MAPPED_ADDRESS),
+            expected.attribute(XOR_MAPPED_ADDRESS)
+        );
+        assert_eq!(
+            message.attribute(FINGERPRINT),
+            Some(fingerprint(&RESPONSE).as_slice())
+        );
+        assert!(check_integrity(&RESPONSE, PASSWORD));
+        assert!(!check_integrity(&RESPONSE, b"wrong"));
+    }
+
+    #[test]
+    fn round_trip() {
+        let address = "[2001:db8::1]:3478".parse().unwrap();
+        let message = Message::new(BINDING_SUCCESS, [7; 12])
+            .with(USERNAME, "remote:local")
+            .with(USE_CANDIDATE, Vec::new())
+            .with_address(address);
+        let packet = message.encode(Some(b"key"));
+        assert!(is_stun(&packet));
+        assert_eq!(packet.len() % 4, 0);
+        assert!(check_integrity(&packet, b"key"));
+
+        let parsed = Message::parse(&packet).unwrap();
+        let kinds: Vec<u16> = parsed.attributes.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                USERNAME,
+                USE_CANDIDATE,
+                XOR_MAPPED_ADDRESS,
+                MESSAGE_INTEGRITY,
+                FINGERPRINT
+            ]
+        );
+        assert_eq!(parsed.attributes[..3], message.attributes);
+        assert_eq!(
+            parsed.attribute(FINGERPRINT),
+            Some(fingerprint(&packet).as_slice())
+        );
+
+        let mut tampered = packet.clone();
+        tampered[25] ^= 1;
+        assert!(!check_integrity(&tampered, b"key"));
+        let unsigned = message.encode(None);
+        assert!(!check_integrity(&unsigned, b"key"));
+    }
+
+    #[test]
+    fn not_stun() {
+        // a DTLS record and an RTP packet
+        let mut dtls = RESPONSE;
+        dtls[0] = 22;
+        assert_eq!(Message::parse(&dtls), None);
+        let mut rtp = RESPONSE;
+        rtp[0] = 0x80;
+        assert!(!is_stun(&rtp));
+        assert_eq!(Message::parse(&RESPONSE[..19]), None);
+        // a length past the end of the datagram
+        assert_eq!(Message::parse(&RESPONSE[..72]), None);
+    }
+}
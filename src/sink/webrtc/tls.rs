@@ -0,0 +1,191 @@
+use super::crypto::openssl_error;
+use super::ffi;
+use crate::error::{Result, ScreencastError};
+use std::ffi::CString;
+use std::net::IpAddr;
+use std::ptr;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// A TLS client over TCP, the peer verified against the system trust
+/// store and `host`. OpenSSL works on memory buffers the stream fills and
+/// drains, like the DTLS of the media.
+pub(crate) struct TlsStream {
+    tcp: TcpStream,
+    ctx: *mut ffi::SSL_CTX,
+    ssl: *mut ffi::SSL,
+    incoming: *mut ffi::BIO,
+    outgoing: *mut ffi::BIO,
+}
+
+// SAFETY: the SSL objects are only used through `&mut self`.
+unsafe impl Send for TlsStream {}
+
+impl std::fmt::Debug for TlsStream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsStream").field("tcp", &self.tcp).finish()
+    }
+}
+
+impl TlsStream {
+    pub(crate) async fn connect(tcp: TcpStream, host: &str) -> Result<Self> {
+        let name = CString::new(host)
+            .map_err(|_| ScreencastError::Streaming(format!("invalid host {:?}", host)))?;
+        let mut stream = unsafe {
+            let ctx = ffi::SSL_CTX_new(ffi::TLS_client_method());
+            if ctx.is_null() {
+                return Err(openssl_error("SSL_CTX_new"));
+            }
+            ffi::SSL_CTX_set_verify(ctx, ffi::SSL_VERIFY_PEER, None);
+            let configured = ffi::SSL_CTX_set_default_verify_paths(ctx) == 1;
+            let ssl = match configured {
+                true => ffi::SSL_new(ctx),
+                false => ptr::null_mut(),
+            };
+            if ssl.is_null() {
+                let err = openssl_error("setting up TLS");
+                ffi::SSL_CTX_free(ctx);
+                return Err(err);
+            }
+            let incoming = ffi::BIO_new(ffi::BIO_s_mem());
+            let outgoing = ffi::BIO_new(ffi::BIO_s_mem());
+            ffi::SSL_set_bio(ssl, incoming, outgoing);
+            let stream = TlsStream {
+                tcp,
+                ctx,
+                ssl,
+                incoming,
+                outgoing,
+            };
+            if incoming.is_null() || outgoing.is_null() {
+                return Err(openssl_error("BIO_new"));
+            }
+            ffi::BIO_ctrl(
+                incoming,
+                ffi::BIO_C_SET_BUF_MEM_EOF_RETURN,
+                -1,
+                ptr::null_mut(),
+            );
+            if ffi::SSL_set1_host(ssl, name.as_ptr()) != 1 {
+                return Err(openssl_error("SSL_set1_host"));
+            }
+            // no server name for addresses
+            if host.parse::<IpAddr>().is_err() {
+                ffi::SSL_ctrl(
+                    ssl,
+                    ffi::SSL_CTRL_SET_TLSEXT_HOSTNAME,
+                    ffi::TLSEXT_NAMETYPE_HOST_NAME,
+                    name.as_ptr() as *mut _,
+                );
+            }
+            ffi::SSL_set_connect_state(ssl);
+            stream
+        };
+        loop {
+            let done = unsafe { ffi::SSL_do_handshake(stream.ssl) };
+            stream.flush().await?;
+            if done == 1 {
+                return Ok(stream);
+            }
+            match unsafe { ffi::SSL_get_error(stream.ssl, done) } {
+                ffi::SSL_ERROR_WANT_READ => {
+                    if !stream.fill().await? {
+                        return Err(ScreencastError::Streaming(format!(
+                            "{} closed the connection in the TLS handshake",
+                            host
+                        )));
+                    }
+                }
+                _ => return Err(openssl_error("TLS handshake")),
+            }
+        }
+    }
+
+    pub(crate) async fn write_all(&mut self, data: &[u8]) -> Result<()> {
+        // memory buffers take everything at once
+        let written = unsafe { ffi::SSL_write(self.ssl, data.as_ptr().cast(), data.len() as i32) };
+        if written <= 0 && !data.is_empty() {
+            return Err(openssl_error("SSL_write"));
+        }
+        self.flush().await
+    }
+
+    /// Reads what the peer sent, 0 at its end.
+    pub(crate) async fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        loop {
+            let read =
+                unsafe { ffi::SSL_read(self.ssl, buf.as_mut_ptr().cast(), buf.len() as i32) };
+            if read > 0 {
+                return Ok(read as usize);
+            }
+            match unsafe { ffi::SSL_get_error(self.ssl, read) } {
+                ffi::SSL_ERROR_ZERO_RETURN => return Ok(0),
+                ffi::SSL_ERROR_WANT_READ => {
+                    // post-handshake messages, e.g. session tickets, may want an answer
+                    self.flush().await?;
+                    if !self.fill().await? {
+                        return Ok(0);
+                    }
+                }
+                _ => return Err(openssl_error("SSL_read")),
+            }
+        }
+    }
+
+    /// Reads what came in already without waiting, `None` when nothing
+    /// did, 0 at the end.
+    pub(crate) fn try_read(&mut self, buf: &mut [u8]) -> Result<Option<usize>> {
+        let mut received = vec![0u8; 16 * 1024];
+        loop {
+            let read =
+                unsafe { ffi::SSL_read(self.ssl, buf.as_mut_ptr().cast(), buf.len() as i32) };
+            if read > 0 {
+                return Ok(Some(read as usize));
+            }
+            match unsafe { ffi::SSL_get_error(self.ssl, read) } {
+                ffi::SSL_ERROR_ZERO_RETURN => return Ok(Some(0)),
+                ffi::SSL_ERROR_WANT_READ => match self.tcp.try_read(&mut received) {
+                    Ok(0) => return Ok(Some(0)),
+                    Ok(length) => unsafe {
+                        ffi::BIO_write(self.incoming, received.as_ptr().cast(), length as i32);
+                    },
+                    Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => return Ok(None),
+                    Err(err) => return Err(err.into()),
+                },
+                _ => return Err(openssl_error("SSL_read")),
+            }
+        }
+    }
+
+    /// Sends what OpenSSL wrote.
+    async fn flush(&mut self) -> Result<()> {
+        let pending =
+            unsafe { ffi::BIO_ctrl(self.outgoing, ffi::BIO_CTRL_PENDING, 0, ptr::null_mut()) };
+        if pending <= 0 {
+            return Ok(());
+        }
+        let mut bytes = vec![0u8; pending as usize];
+        let read =
+            unsafe { ffi::BIO_read(self.outgoing, bytes.as_mut_ptr().cast(), bytes.len() as i32) };
+        bytes.truncate(read.max(0) as usize);
+        self.tcp.write_all(&bytes).await?;
+        Ok(())
+    }
+
+    /// Hands OpenSSL what came in, false at the end of the stream.
+    async fn fill(&mut self) -> Result<bool> {
+        let mut buf = vec![0u8; 16 * 1024];
+        let read = self.tcp.read(&mut buf).await?;
+        unsafe { ffi::BIO_write(self.incoming, buf.as_ptr().cast(), read as i32) };
+        Ok(read > 0)
+    }
+}
+
+impl Drop for TlsStream {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::SSL_free(self.ssl);
+            ffi::SSL_CTX_free(self.ctx);
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use super::WebRtc;
+use super::http::{self, Url};
+use crate::audio::AudioTrack;
+use crate::encode::{Encoder, EncoderConfig};
+use crate::error::{Result, ScreencastError};
+use crate::frame::Frame;
+use crate::runtime::compat;
+use crate::sink::FrameSink;
+
+/// Redirects followed for the offer.
+const MAX_REDIRECTS: usize = 3;
+
+/// Publishes to a WHIP endpoint (RFC 9725), e.g. of MediaMTX or Cloudflare
+/// Stream: the offer of a `WebRtc` sink is posted to the endpoint with the
+/// bearer token, if any, and its answer taken. Publishing happens with the
+/// first frame, or before with `publish`, and the session is deleted on
+/// the server by `finish`.
+///
+/// The candidates are the host addresses, all sent with the offer, so the
+/// server has to be reachable for UDP from this host.
+#[derive(Debug)]
+pub struct Whip {
+    webrtc: WebRtc,
+    endpoint: Url,
+    token: Option<String>,
+    // the session on the server, from the Location of the answer
+    resource: Option<Url>,
+}
+
+impl Whip {
+    pub async fn new(endpoint: &str, config: EncoderConfig) -> Result<Self> {
+        Whip::from_sink(endpoint, WebRtc::bind("0.0.0.0:0", config).await?)
+    }
+
+    pub async fn new_with_encoder(endpoint: &str, encoder: Box<dyn Encoder>) -> Result<Self> {
+        Whip::from_sink(
+            endpoint,
+            WebRtc::bind_with_encoder("0.0.0.0:0", encoder).await?,
+        )
+    }
+
+    /// Publishes through `webrtc`, e.g. one bound to an address of choice.
+    /// Its session must not be negotiated yet.
+    pub fn from_sink(endpoint: &str, webrtc: WebRtc) -> Result<Self> {
+        Ok(Whip {
+            webrtc,
+            endpoint: Url::parse(endpoint)?,
+            token: None,
+            resource: None,
+        })
+    }
+
+    /// Sent as `Authorization: Bearer`, the stream key of most services.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Publishes an Opus track along.
+    pub fn with_audio(mut self, audio: AudioTrack) -> Result<Self> {
+        self.webrtc = self.webrtc.with_audio(audio)?;
+        Ok(self)
+    }
+
+    /// Negotiates the session with the endpoint, frames are sent once the
+    /// connection is up. Does nothing when published already.
+    pub async fn publish(&mut self) -> Result<()> {
+        if self.resource.is_some() {
+            return Ok(());
+        }
+        compat(self.post_offer()).await
+    }
+
+    /// The URL of the session on the server, once published.
+    pub fn resource(&self) -> Option<String> {
+        self.resource.as_ref().map(Url::to_string)
+    }
+
+    async fn post_offer(&mut self) -> Result<()> {
+        let peer = self.webrtc.peer();
+        let offer = peer.create_offer()?;
+        let authorization = self.token.as_ref().map(|token| format!("Bearer {}", token));
+        let mut headers = vec![("Content-Type", "application/sdp")];
+        if let Some(authorization) = &authorization {
+            headers.push(("Authorization", authorization));
+        }
+        let mut url = self.endpoint.clone();
+        let mut redirects = 0;
+        let response = loop {
+            let response = http::request("POST", &url, &headers, offer.as_bytes()).await?;
+            let location = response.header("Location");
+            match (response.status, location) {
+                (307 | 308, Some(location)) if redirects < MAX_REDIRECTS => {
+                    url = url.join(location)?;
+                    redirects += 1;
+                }
+                _ => break response,
+            }
+        };
+        if !response.is_success() {
+            let reason = String::from_utf8_lossy(&response.body);
+            return Err(ScreencastError::Streaming(format!(
+                "{} refused the offer with {}: {}",
+                url,
+                response.status,
+                reason.trim()
+            )));
+        }
+        let answer = String::from_utf8_lossy(&response.body);
+        peer.accept_answer(&answer)?;
+        self.resource = Some(match response.header("Location") {
+            Some(location) => url.join(location)?,
+            None => url,
+        });
+        Ok(())
+    }
+
+    /// Ends the session on the server.
+    async fn delete(&mut self) -> Result<()> {
+        let Some(resource) = self.resource.take() else {
+            return Ok(());
+        };
+        let authorization = self.token.as_ref().map(|token| format!("Bearer {}", token));
+        let headers: Vec<(&str, &str)> = authorization
+            .iter()
+            .map(|authorization| ("Authorization", authorization.as_str()))
+            .collect();
+        let response = http::request("DELETE", &resource, &headers, &[]).await?;
+        // gone already is fine
+        if !response.is_success() && response.status != 404 {
+            return Err(ScreencastError::Streaming(format!(
+                "{} refused to end the session with {}",
+                resource, response.status
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl FrameSink for Whip {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        self.publish().await?;
+        self.webrtc.write_frame(frame).await
+    }
+
+    async fn finish(&mut self) -> Result<()> {
+        let finished = self.webrtc.finish().await;
+        let deleted = compat(self.delete()).await;
+        finished.and(deleted)
+    }
+}
@@ -0,0 +1,174 @@
+use super::FrameSink;
+use crate::convert;
+use crate::error::{Result, ScreencastError};
+use crate::format::PixelFormat;
+use crate::frame::Frame;
+use crate::runtime::compat;
+use std::borrow::Cow;
+use std::path::Path;
+use tokio::fs::File;
+use tokio::io::{AsyncWrite, AsyncWriteExt, BufWriter};
+
+/// Writes raw I420 video as yuv4mpeg2, which ffmpeg, x264 and the VMAF
+/// tools read without further options, to a file, a named pipe or the
+/// stdin of another process.
+///
+/// Every frame is written as it comes, the stream claims a constant frame
+/// rate of 30 per second unless `with_framerate`. YUV is BT.601 limited
+/// range, see `convert::convert`. The stream takes the size of the first
+/// frame, a frame of another size fails.
+pub struct Y4m {
+    output: BufWriter<Box<dyn AsyncWrite + Send + Unpin>>,
+    framerate: (u32, u32),
+    size: Option<(u32, u32)>,
+}
+
+impl std::fmt::Debug for Y4m {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Y4m")
+            .field("framerate", &self.framerate)
+            .field("size", &self.size)
+            .finish()
+    }
+}
+
+impl Y4m {
+    /// Creates or truncates `path`, a named pipe is opened for writing once
+    /// its reader is there.
+    pub async fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let file = compat(File::create(path.as_ref())).await?;
+        Ok(Y4m::from_writer(file))
+    }
+
+    /// Writes to any output, e.g. the stdin of an ffmpeg child process.
+    pub fn from_writer(writer: impl AsyncWrite + Send + Unpin + 'static) -> Self {
+        Y4m {
+            output: BufWriter::new(Box::new(writer)),
+            framerate: (30, 1),
+            size: None,
+        }
+    }
+
+    /// The frame rate in the header, as numerator and denominator.
+    pub fn with_framerate(mut self, framerate: (u32, u32)) -> Self {
+        self.framerate = (framerate.0.max(1), framerate.1.max(1));
+        self
+    }
+
+    fn encode(&mut self, frame: &Frame<'_>) -> Result<Vec<u8>> {
+        let (width, height) = (frame.width(), frame.height());
+        let mut out = Vec::new();
+        match self.size {
+            None => {
+                let (num, denom) = self.framerate;
+                out.extend_from_slice(
+                    format!(
+                        "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XYSCSS=420JPEG XCOLORRANGE=LIMITED\n",
+                        width, height, num, denom
+                    )
+                    .as_bytes(),
+                );
+                self.size = Some((width, height));
+            }
+            Some(size) if size != (width, height) => {
+                return Err(ScreencastError::Unsupported(format!(
+                    "Y4M frames of {}x{} after {}x{}",
+                    width, height, size.0, size.1
+                )));
+            }
+            Some(_) => {}
+        }
+        let frame = match frame.pixel_format() {
+            PixelFormat::I420 => Cow::Borrowed(frame),
+            _ => Cow::Owned(convert::convert(frame, PixelFormat::I420)?),
+        };
+        out.extend_from_slice(b"FRAME\n");
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        for (index, (columns, rows)) in [
+            (width, height),
+            (chroma_width, chroma_height),
+            (chroma_width, chroma_height),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let stride = frame.planes()[index].stride;
+            let data = frame.plane_data(index).unwrap_or_default();
+            for row in data.chunks(stride).take(rows as usize) {
+                out.extend_from_slice(&row[..columns as usize]);
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl FrameSink for Y4m {
+    async fn write_frame(&mut self, frame: Frame<'static>) -> Result<()> {
+        let data = self.encode(&frame)?;
+        compat(self.output.write_all(&data)).await?;
+        Ok(())
+    }
+
+    /// Flushes and shuts the output down, which ends the input of a reading
+    /// process.
+    async fn finish(&mut self) -> Result<()> {
+        compat(self.output.shutdown()).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Plane;
+
+    #[tokio::test]
+    async fn stream() {
+        let path = std::env::temp_dir().join(format!("xdp-screencast-{}.y4m", std::process::id()));
+        let mut y4m = Y4m::create(&path)
+            .await
+            .unwrap()
+            .with_framerate((60000, 1001));
+        // 3x3 with padded rows, the padding is not written
+        let i420 = [
+            (0..12).collect::<Vec<u8>>(),
+            vec![100, 101, 0, 0],
+            vec![200, 201, 0, 0],
+        ];
+        let planes = vec![
+            Plane {
+                offset: 0,
+                stride: 4,
+            },
+            Plane {
+                offset: 12,
+                stride: 2,
+            },
+            Plane {
+                offset: 16,
+                stride: 2,
+            },
+        ];
+        let frame = Frame::new(PixelFormat::I420, 3, 3, planes, i420.concat());
+        y4m.write_frame(frame).await.unwrap();
+        let black = Frame::packed(PixelFormat::Bgrx, 3, 3, 12, vec![0; 36]);
+        y4m.write_frame(black).await.unwrap();
+        let other = Frame::packed(PixelFormat::Bgrx, 2, 2, 8, vec![0; 16]);
+        assert!(matches!(
+            y4m.write_frame(other).await,
+            Err(ScreencastError::Unsupported(_))
+        ));
+        y4m.finish().await.unwrap();
+
+        let file = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let header =
+            b"YUV4MPEG2 W3 H3 F60000:1001 Ip A1:1 C420jpeg XYSCSS=420JPEG XCOLORRANGE=LIMITED\n";
+        let (start, frames) = file.split_at(header.len());
+        assert_eq!(start, header);
+        let frame = [b"FRAME\n".as_slice(), &[0, 1, 2, 4, 5, 6, 8, 9, 10]].concat();
+        let frame = [frame, vec![100, 101, 0, 0, 200, 201, 0, 0]].concat();
+        let black = [b"FRAME\n".as_slice(), &[16; 9], &[128; 8]].concat();
+        assert_eq!(frames, [frame, black].concat());
+    }
+}
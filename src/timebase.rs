@@ -0,0 +1,86 @@
+use crate::frame::Frame;
+use std::time::Duration;
+
+/// Maps frame timestamps onto the integer time base of a muxer, counting
+/// from the first frame.
+///
+/// Gaps from dropped frames or stalled compositors are kept, so the output
+/// stays in sync with wall time, and timestamps are strictly increasing even
+/// when two frames round to the same tick.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Timebase {
+    num: u32,
+    denom: u32,
+    origin: Option<Duration>,
+    last: Option<i64>,
+}
+
+impl Timebase {
+    /// Milliseconds, as used by FLV and WebM.
+    pub const MILLIS: (u32, u32) = (1, 1000);
+    pub const MICROS: (u32, u32) = (1, 1_000_000);
+    /// The 90 kHz clock of MPEG-TS and RTP video.
+    pub const MPEG: (u32, u32) = (1, 90_000);
+
+    /// A tick is `num / denom` seconds.
+    pub fn new((num, denom): (u32, u32)) -> Self {
+        Timebase {
+            num: num.max(1),
+            denom: denom.max(1),
+            origin: None,
+            last: None,
+        }
+    }
+
+    pub fn num(&self) -> u32 {
+        self.num
+    }
+
+    pub fn denom(&self) -> u32 {
+        self.denom
+    }
+
+    /// The time of the first frame, ticks are counted from here.
+    pub fn origin(&self) -> Option<Duration> {
+        self.origin
+    }
+
+    /// Starts counting from `origin` instead of the first frame, e.g. to
+    /// share one origin between the streams of a recording.
+    pub fn with_origin(mut self, origin: Duration) -> Self {
+        self.origin = Some(origin);
+        self
+    }
+
+    /// The frame's timestamp in ticks, frames without one are placed a tick
+    /// after the previous frame.
+    pub fn timestamp(&mut self, frame: &Frame<'_>) -> i64 {
+        let ticks = match frame.pts() {
+            Some(pts) => {
+                let origin = *self.origin.get_or_insert(pts);
+                match pts.checked_sub(origin) {
+                    Some(elapsed) => self.to_ticks(elapsed),
+                    None => -self.to_ticks(origin - pts),
+                }
+            }
+            None => self.last.map_or(0, |last| last + 1),
+        };
+        let ticks = match self.last {
+            Some(last) if ticks <= last => last + 1,
+            _ => ticks,
+        };
+        self.last = Some(ticks);
+        ticks
+    }
+
+    /// Rounds a duration to the nearest tick.
+    pub fn to_ticks(&self, duration: Duration) -> i64 {
+        let scale = self.num as u128 * 1_000_000_000;
+        ((duration.as_nanos() * self.denom as u128 + scale / 2) / scale) as i64
+    }
+
+    pub fn to_duration(&self, ticks: i64) -> Duration {
+        let nanos = ticks.max(0) as u128 * self.num as u128 * 1_000_000_000 / self.denom as u128;
+        Duration::from_nanos(nanos as u64)
+    }
+}
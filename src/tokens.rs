@@ -0,0 +1,176 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::{Path, PathBuf};
+use toml_edit::DocumentMut;
+
+const TOKENS_DIR: &str = "xdp-screencast";
+const TOKENS_FILE: &str = "tokens.toml";
+
+/// Restore tokens keyed by profile name, stored in
+/// `$XDG_STATE_HOME/xdp-screencast/tokens.toml`.
+#[derive(Debug, Clone)]
+pub struct TokenStore {
+    path: PathBuf,
+}
+
+impl TokenStore {
+    pub fn new() -> Result<Self> {
+        let state_home = std::env::var_os("XDG_STATE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state"))
+            })
+            .ok_or(Error::new(
+                ErrorKind::NotFound,
+                "token store: neither XDG_STATE_HOME nor HOME is set",
+            ))?;
+        Ok(TokenStore::with_path(
+            state_home.join(TOKENS_DIR).join(TOKENS_FILE),
+        ))
+    }
+
+    pub fn with_path(path: impl Into<PathBuf>) -> Self {
+        TokenStore { path: path.into() }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load(&self, profile: &str) -> Result<Option<String>> {
+        Ok(self.read_all()?.remove(profile))
+    }
+
+    pub fn save(&self, profile: &str, token: &str) -> Result<()> {
+        let mut tokens = self.read_all()?;
+        tokens.insert(profile.to_string(), token.to_string());
+        self.write_all(&tokens)
+    }
+
+    pub fn remove(&self, profile: &str) -> Result<()> {
+        let mut tokens = self.read_all()?;
+        if tokens.remove(profile).is_some() {
+            self.write_all(&tokens)?;
+        }
+        Ok(())
+    }
+
+    fn read_all(&self) -> Result<BTreeMap<String, String>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(err) => return Err(err),
+        };
+        let document = content
+            .parse::<DocumentMut>()
+            .map_err(|err| Error::new(ErrorKind::InvalidData, err))?;
+        let tokens = document
+            .iter()
+            .filter_map(|(profile, token)| {
+                token
+                    .as_str()
+                    .map(|token| (profile.to_string(), token.to_string()))
+            })
+            .collect();
+        Ok(tokens)
+    }
+
+    fn write_all(&self, tokens: &BTreeMap<String, String>) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = String::new();
+        for (profile, token) in tokens {
+            content.push_str(&quote(profile));
+            content.push_str(" = ");
+            content.push_str(&quote(token));
+            content.push('\n');
+        }
+        let temp_path = self.path.with_extension("toml.tmp");
+        fs::write(&temp_path, content)?;
+        fs::rename(&temp_path, &self.path)
+    }
+}
+
+fn quote(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if c.is_control() => result.push_str(&format!("\\u{:04X}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> TokenStore {
+        let dir =
+            std::env::temp_dir().join(format!("xdp-screencast-{}-{name}", std::process::id()));
+        TokenStore::with_path(dir.join(TOKENS_DIR).join(TOKENS_FILE))
+    }
+
+    fn clean(store: &TokenStore) {
+        let dir = store.path().parent().and_then(Path::parent).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn round_trip() {
+        let store = store("tokens");
+        assert_eq!(store.load("default").unwrap(), None);
+        store.save("default", "4b1d-token").unwrap();
+        store
+            .save("a \"quoted\" profile", "line\nbreak\\\t\u{1}")
+            .unwrap();
+        store.save("default", "replaced").unwrap();
+
+        let reopened = TokenStore::with_path(store.path());
+        assert_eq!(
+            reopened.load("default").unwrap().as_deref(),
+            Some("replaced")
+        );
+        assert_eq!(
+            reopened.load("a \"quoted\" profile").unwrap().as_deref(),
+            Some("line\nbreak\\\t\u{1}")
+        );
+        reopened.remove("default").unwrap();
+        reopened.remove("missing").unwrap();
+        assert_eq!(store.load("default").unwrap(), None);
+        assert!(!store.path().with_extension("toml.tmp").exists());
+        clean(&store);
+    }
+
+    #[test]
+    fn corrupt_file() {
+        let store = store("corrupt");
+        fs::create_dir_all(store.path().parent().unwrap()).unwrap();
+        fs::write(store.path(), "default = \"unterminated\n").unwrap();
+        let err = store.load("default").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        // a corrupt file is not overwritten
+        assert!(store.save("default", "token").is_err());
+        assert_eq!(
+            fs::read_to_string(store.path()).unwrap(),
+            "default = \"unterminated\n"
+        );
+
+        // values of other types are skipped
+        fs::write(store.path(), "default = \"token\"\ncount = 3\n").unwrap();
+        assert_eq!(store.load("count").unwrap(), None);
+        assert_eq!(store.load("default").unwrap().as_deref(), Some("token"));
+        clean(&store);
+    }
+}